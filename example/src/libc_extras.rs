@@ -19,10 +19,6 @@ pub mod libc {
         #[cfg(target_os = "macos")]
         pub fn truncate(path: *const c_char, size: off_t) -> c_int;
 
-        // On Linux, off_t is architecture-dependent, and this is provided for 32-bit systems:
-        #[cfg(target_os = "linux")]
-        pub fn truncate64(path: *const c_char, size: off64_t) -> c_int;
-
         // These XATTR functions are missing from the libc crate on Darwin for some reason.
         #[cfg(target_os = "macos")]
         pub fn listxattr(path: *const c_char, list: *mut c_char, size: size_t, options: c_int) -> ssize_t;
@@ -77,7 +73,7 @@ pub mod libc {
     // Mac OS X does not support futimens; map it to futimes with lower precision.
     #[cfg(target_os = "macos")]
     pub unsafe fn futimens(fd: c_int, times: *const timespec) -> c_int {
-        use super::super::libc_wrappers;
+        use fuse_mt::libc_wrappers;
         let mut times_osx = [timespec_to_timeval(&*times),
                              timespec_to_timeval(&*times)];
 
@@ -117,7 +113,7 @@ pub mod libc {
     #[cfg(target_os = "macos")]
     pub fn utimensat(dirfd: c_int, path: *const c_char, times: *const timespec,
                      _flag_ignored: c_int) -> c_int {
-        use super::super::libc_wrappers;
+        use fuse_mt::libc_wrappers;
         unsafe {
             if dirfd != AT_FDCWD {
                 assert_eq!(*path, b'/' as c_char, "relative paths are not supported here!");
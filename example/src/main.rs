@@ -6,13 +6,11 @@
 #![deny(rust_2018_idioms)]
 
 use std::env;
-use std::ffi::{OsStr, OsString};
 
 #[macro_use]
 extern crate log;
 
 mod libc_extras;
-mod libc_wrappers;
 mod passthrough;
 
 struct ConsoleLogger;
@@ -31,22 +29,44 @@ impl log::Log for ConsoleLogger {
 
 static LOGGER: ConsoleLogger = ConsoleLogger;
 
+fn usage() -> ! {
+    println!("usage: {} <target> <mountpoint> [-o <options>]", &env::args().next().unwrap());
+    std::process::exit(-1);
+}
+
 fn main() {
     log::set_logger(&LOGGER).unwrap();
     log::set_max_level(log::LevelFilter::Debug);
 
-    let args: Vec<OsString> = env::args_os().collect();
-
-    if args.len() != 3 {
-        println!("usage: {} <target> <mountpoint>", &env::args().next().unwrap());
-        std::process::exit(-1);
+    // Mount options are accepted the way mount.fuse passes them, so this can be used from fstab.
+    let (args, mut options) = match fuse_mt::check_options(env::args_os().skip(1)) {
+        fuse_mt::CheckOptions::Help => usage(),
+        fuse_mt::CheckOptions::Version => {
+            println!("passthrufs using {}", fuse_mt::version());
+            return;
+        }
+        fuse_mt::CheckOptions::Proceed(parsed) => (parsed.free_args, parsed.mount_options),
+        fuse_mt::CheckOptions::Error(e) => {
+            println!("{}", e);
+            usage()
+        }
+    };
+    if args.len() != 2 {
+        usage();
     }
+    options.insert(0, fuse_mt::MountOption::FSName("passthrufs".to_owned()));
 
     let filesystem = passthrough::PassthroughFS {
-        target: args[1].clone(),
+        target: args[0].clone(),
     };
 
-    let fuse_args = [OsStr::new("-o"), OsStr::new("fsname=passthrufs")];
+    let fuse = fuse_mt::FuseMT::new(filesystem, 1);
+
+    // Keep directory listings up to date when the target is changed directly.
+    let invalidator = fuse.invalidator();
+    let _watcher = fuse_mt::watch::Watcher::new(&args[0], move |path| invalidator.invalidate(path))
+        .map_err(|e| warn!("not watching {:?} for changes: {}", args[0], e))
+        .ok();
 
-    fuse_mt::mount(fuse_mt::FuseMT::new(filesystem, 1), &args[2], &fuse_args[..]).unwrap();
+    fuse_mt::mount2(fuse, &args[1], &options).unwrap();
 }
@@ -7,18 +7,34 @@
 
 use std::ffi::{CStr, CString, OsStr, OsString};
 use std::fs::{self, File};
-use std::io::{self, Read, Write, Seek, SeekFrom};
-use std::mem;
+use std::io::{self, Write};
 use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::FileExt;
 use std::os::unix::io::{FromRawFd, IntoRawFd};
 use std::path::{Path, PathBuf};
 use std::time::{Duration, SystemTime};
 
 use crate::libc_extras::libc;
-use crate::libc_wrappers;
+use fuse_mt::libc_wrappers;
 
 use fuse_mt::*;
 
+/// Translate `O_DIRECT` in the flags a file is being opened with into asking the kernel not to
+/// cache the file (`FOPEN_DIRECT_IO`), and return the flags to open the real file with, along with
+/// the flags to return from `open`.
+///
+/// The real file is opened without `O_DIRECT`, because that requires every read and write to use
+/// suitably aligned buffers and offsets, and the buffers FUSE gives us aren't.
+fn direct_io(flags: libc::c_int) -> (libc::c_int, u32) {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        if flags & libc::O_DIRECT != 0 {
+            return (flags & !libc::O_DIRECT, open_flags::FOPEN_DIRECT_IO);
+        }
+    }
+    (flags, 0)
+}
+
 pub struct PassthroughFS {
     pub target: OsString,
 }
@@ -52,6 +68,7 @@ fn stat_to_fuse(stat: libc::stat64) -> FileAttr {
     let nlink = stat.st_nlink as u32;
 
     FileAttr {
+        ino: stat.st_ino,
         size: stat.st_size as u64,
         blocks: stat.st_blocks as u64,
         atime: time(stat.st_atime, stat.st_atime_nsec),
@@ -68,32 +85,22 @@ fn stat_to_fuse(stat: libc::stat64) -> FileAttr {
     }
 }
 
-#[cfg(target_os = "macos")]
-fn statfs_to_fuse(statfs: libc::statfs) -> Statfs {
-    Statfs {
-        blocks: statfs.f_blocks,
-        bfree: statfs.f_bfree,
-        bavail: statfs.f_bavail,
-        files: statfs.f_files,
-        ffree: statfs.f_ffree,
-        bsize: statfs.f_bsize as u32,
-        namelen: 0, // TODO
-        frsize: 0, // TODO
-    }
-}
-
-#[cfg(target_os = "linux")]
-fn statfs_to_fuse(statfs: libc::statfs) -> Statfs {
-    Statfs {
-        blocks: statfs.f_blocks,
-        bfree: statfs.f_bfree,
-        bavail: statfs.f_bavail,
-        files: statfs.f_files,
-        ffree: statfs.f_ffree,
-        bsize: statfs.f_bsize as u32,
-        namelen: statfs.f_namelen as u32,
-        frsize: statfs.f_frsize as u32,
-    }
+// The fields of statvfs are different sizes on different platforms.
+#[allow(clippy::unnecessary_cast)]
+fn statvfs_to_fuse(statvfs: libc_wrappers::statvfs64) -> Statfs {
+    let mut statfs = Statfs::new(
+        statvfs.f_blocks as u64,
+        statvfs.f_bfree as u64,
+        statvfs.f_bavail as u64,
+        statvfs.f_files as u64,
+        statvfs.f_ffree as u64,
+        statvfs.f_bsize as u32,
+        statvfs.f_namemax as u32,
+        statvfs.f_frsize as u32,
+    );
+    statfs.fsid = statvfs.f_fsid as u64;
+    statfs.flags = statvfs.f_flag as u64;
+    statfs
 }
 
 impl PassthroughFS {
@@ -132,7 +139,7 @@ impl FilesystemMT for PassthroughFS {
         debug!("destroy");
     }
 
-    fn getattr(&self, _req: RequestInfo, path: &Path, fh: Option<u64>) -> ResultEntry {
+    fn getattr(&self, _req: RequestInfo, path: &FsPath, fh: Option<u64>) -> ResultEntry {
         debug!("getattr: {:?}", path);
 
         if let Some(fh) = fh {
@@ -141,18 +148,15 @@ impl FilesystemMT for PassthroughFS {
                 Err(e) => Err(e)
             }
         } else {
-            match self.stat_real(path) {
-                Ok(attr) => Ok((TTL, attr)),
-                Err(e) => Err(e.raw_os_error().unwrap())
-            }
+            Ok((TTL, self.stat_real(path).errno()?))
         }
     }
 
-    fn opendir(&self, _req: RequestInfo, path: &Path, _flags: u32) -> ResultOpen {
+    fn opendir(&self, _req: RequestInfo, path: &FsPath, _flags: u32) -> ResultOpen {
         let real = self.real_path(path);
         debug!("opendir: {:?} (flags = {:#o})", real, _flags);
         match libc_wrappers::opendir(real) {
-            Ok(fh) => Ok((fh, 0)),
+            Ok(fh) => Ok(OpenedFile::new(fh)),
             Err(e) => {
                 let ioerr = io::Error::from_raw_os_error(e);
                 error!("opendir({:?}): {}", path, ioerr);
@@ -161,12 +165,12 @@ impl FilesystemMT for PassthroughFS {
         }
     }
 
-    fn releasedir(&self, _req: RequestInfo, path: &Path, fh: u64, _flags: u32) -> ResultEmpty {
+    fn releasedir(&self, _req: RequestInfo, path: &FsPath, fh: u64, _flags: u32) -> ResultEmpty {
         debug!("releasedir: {:?}", path);
         libc_wrappers::closedir(fh)
     }
 
-    fn readdir(&self, _req: RequestInfo, path: &Path, fh: u64) -> ResultReaddir {
+    fn readdir(&self, _req: RequestInfo, path: &FsPath, fh: u64) -> ResultReaddir {
         debug!("readdir: {:?}", path);
         let mut entries: Vec<DirectoryEntry> = vec![];
 
@@ -199,7 +203,7 @@ impl FilesystemMT for PassthroughFS {
                                 Ok(stat64) => mode_to_filetype(stat64.st_mode),
                                 Err(errno) => {
                                     let ioerr = io::Error::from_raw_os_error(errno);
-                                    panic!("lstat failed after readdir_r gave no file type for {:?}: {}",
+                                    panic!("lstat failed after readdir gave no file type for {:?}: {}",
                                            entry_path, ioerr);
                                 }
                             }
@@ -209,6 +213,8 @@ impl FilesystemMT for PassthroughFS {
                     entries.push(DirectoryEntry {
                         name,
                         kind: filetype,
+                        offset: None,
+                        attr: None,
                     })
                 },
                 Ok(None) => { break; },
@@ -222,12 +228,13 @@ impl FilesystemMT for PassthroughFS {
         Ok(entries)
     }
 
-    fn open(&self, _req: RequestInfo, path: &Path, flags: u32) -> ResultOpen {
+    fn open(&self, _req: RequestInfo, path: &FsPath, flags: u32) -> ResultOpen {
         debug!("open: {:?} flags={:#x}", path, flags);
 
         let real = self.real_path(path);
-        match libc_wrappers::open(real, flags as libc::c_int) {
-            Ok(fh) => Ok((fh, flags)),
+        let (flags, open_flags) = direct_io(flags as libc::c_int);
+        match libc_wrappers::open(real, flags) {
+            Ok(fh) => Ok((fh, open_flags).into()),
             Err(e) => {
                 error!("open({:?}): {}", path, io::Error::from_raw_os_error(e));
                 Err(e)
@@ -235,64 +242,61 @@ impl FilesystemMT for PassthroughFS {
         }
     }
 
-    fn release(&self, _req: RequestInfo, path: &Path, fh: u64, _flags: u32, _lock_owner: u64, _flush: bool) -> ResultEmpty {
+    fn release(&self, _req: RequestInfo, path: &FsPath, fh: u64, _flags: u32, _lock_owner: Option<LockOwner>, _flush: bool) -> ResultEmpty {
         debug!("release: {:?}", path);
         libc_wrappers::close(fh)
     }
 
-    fn read(&self, _req: RequestInfo, path: &Path, fh: u64, offset: u64, size: u32, callback: impl FnOnce(ResultSlice<'_>) -> CallbackResult) -> CallbackResult {
+    fn read(&self, _req: RequestInfo, path: &FsPath, fh: u64, offset: u64, size: u32, callback: impl FnOnce(ResultSlice<'_>) -> CallbackResult) -> CallbackResult {
         debug!("read: {:?} {:#x} @ {:#x}", path, size, offset);
-        let mut file = unsafe { UnmanagedFile::new(fh) };
-
-        let mut data = Vec::<u8>::with_capacity(size as usize);
+        let file = unsafe { UnmanagedFile::new(fh) };
 
-        if let Err(e) = file.seek(SeekFrom::Start(offset)) {
-            error!("seek({:?}, {}): {}", path, offset, e);
-            return callback(Err(e.raw_os_error().unwrap()));
-        }
-        match file.read(unsafe { mem::transmute(data.spare_capacity_mut()) }) {
-            Ok(n) => { unsafe { data.set_len(n) }; },
-            Err(e) => {
-                error!("read {:?}, {:#x} @ {:#x}: {}", path, size, offset, e);
-                return callback(Err(e.raw_os_error().unwrap()));
+        let mut data = vec![0; size as usize];
+        let mut nread = 0;
+        while nread < data.len() {
+            match file.read_at(&mut data[nread ..], offset + nread as u64) {
+                Ok(0) => break,
+                Ok(n) => nread += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => {
+                    error!("read {:?}, {:#x} @ {:#x}: {}", path, size, offset, e);
+                    return callback(Err(io_errno(&e)));
+                }
             }
         }
+        data.truncate(nread);
 
         callback(Ok(&data))
     }
 
-    fn write(&self, _req: RequestInfo, path: &Path, fh: u64, offset: u64, data: Vec<u8>, _flags: u32) -> ResultWrite {
+    fn write(&self, _req: RequestInfo, path: &FsPath, fh: u64, offset: u64, data: Vec<u8>, _flags: u32) -> ResultWrite {
         debug!("write: {:?} {:#x} @ {:#x}", path, data.len(), offset);
-        let mut file = unsafe { UnmanagedFile::new(fh) };
+        let file = unsafe { UnmanagedFile::new(fh) };
 
-        if let Err(e) = file.seek(SeekFrom::Start(offset)) {
-            error!("seek({:?}, {}): {}", path, offset, e);
-            return Err(e.raw_os_error().unwrap());
-        }
-        let nwritten: u32 = match file.write(&data) {
+        let nwritten: u32 = match file.write_at(&data, offset) {
             Ok(n) => n as u32,
             Err(e) => {
                 error!("write {:?}, {:#x} @ {:#x}: {}", path, data.len(), offset, e);
-                return Err(e.raw_os_error().unwrap());
+                return Err(io_errno(&e));
             }
         };
 
         Ok(nwritten)
     }
 
-    fn flush(&self, _req: RequestInfo, path: &Path, fh: u64, _lock_owner: u64) -> ResultEmpty {
+    fn flush(&self, _req: RequestInfo, path: &FsPath, fh: u64, _lock_owner: LockOwner) -> ResultEmpty {
         debug!("flush: {:?}", path);
         let mut file = unsafe { UnmanagedFile::new(fh) };
 
         if let Err(e) = file.flush() {
             error!("flush({:?}): {}", path, e);
-            return Err(e.raw_os_error().unwrap());
+            return Err(io_errno(&e));
         }
 
         Ok(())
     }
 
-    fn fsync(&self, _req: RequestInfo, path: &Path, fh: u64, datasync: bool) -> ResultEmpty {
+    fn fsync(&self, _req: RequestInfo, path: &FsPath, fh: u64, datasync: bool) -> ResultEmpty {
         debug!("fsync: {:?}, data={:?}", path, datasync);
         let file = unsafe { UnmanagedFile::new(fh) };
 
@@ -302,13 +306,49 @@ impl FilesystemMT for PassthroughFS {
             file.sync_all()
         } {
             error!("fsync({:?}, {:?}): {}", path, datasync, e);
-            return Err(e.raw_os_error().unwrap());
+            return Err(io_errno(&e));
         }
 
         Ok(())
     }
 
-    fn chmod(&self, _req: RequestInfo, path: &Path, fh: Option<u64>, mode: u32) -> ResultEmpty {
+    fn fallocate(&self, _req: RequestInfo, path: &FsPath, fh: u64, offset: u64, length: u64, mode: i32) -> ResultEmpty {
+        debug!("fallocate: {:?} {:#x} @ {:#x} (mode={:#x})", path, length, offset, mode);
+        if let Err(e) = libc_wrappers::fallocate(fh, offset, length, mode) {
+            error!("fallocate({:?}): {}", path, io::Error::from_raw_os_error(e));
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn lseek(&self, _req: RequestInfo, path: &FsPath, fh: u64, offset: u64, whence: i32) -> ResultOffset {
+        debug!("lseek: {:?} {:#x} (whence={})", path, offset, whence);
+        match libc_wrappers::lseek(fh, offset, whence) {
+            Ok(offset) => Ok(offset),
+            // Seeking past the last data is expected, and not worth logging.
+            Err(libc::ENXIO) => Err(libc::ENXIO),
+            Err(e) => {
+                error!("lseek({:?}): {}", path, io::Error::from_raw_os_error(e));
+                Err(e)
+            }
+        }
+    }
+
+    fn copy_file_range(&self, _req: RequestInfo, path_in: &FsPath, fh_in: u64, offset_in: u64, path_out: &FsPath, fh_out: u64, offset_out: u64, len: u64, flags: u32) -> ResultWrite {
+        debug!("copy_file_range: {:?} @ {:#x} -> {:?} @ {:#x}, {:#x} bytes", path_in, offset_in,
+            path_out, offset_out, len);
+        // The kernel never asks for more than fits in the reply.
+        let len = len.min(u64::from(u32::MAX));
+        match libc_wrappers::copy_file_range(fh_in, offset_in, fh_out, offset_out, len, flags) {
+            Ok(n) => Ok(n as u32),
+            Err(e) => {
+                error!("copy_file_range({:?}, {:?}): {}", path_in, path_out, io::Error::from_raw_os_error(e));
+                Err(e)
+            }
+        }
+    }
+
+    fn chmod(&self, _req: RequestInfo, path: &FsPath, fh: Option<u64>, mode: u32) -> ResultEmpty {
         debug!("chmod: {:?} to {:#o}", path, mode);
 
         let result = if let Some(fh) = fh {
@@ -324,15 +364,15 @@ impl FilesystemMT for PassthroughFS {
         if -1 == result {
             let e = io::Error::last_os_error();
             error!("chmod({:?}, {:#o}): {}", path, mode, e);
-            Err(e.raw_os_error().unwrap())
+            Err(io_errno(&e))
         } else {
             Ok(())
         }
     }
 
-    fn chown(&self, _req: RequestInfo, path: &Path, fh: Option<u64>, uid: Option<u32>, gid: Option<u32>) -> ResultEmpty {
-        let uid = uid.unwrap_or(::std::u32::MAX);   // docs say "-1", but uid_t is unsigned
-        let gid = gid.unwrap_or(::std::u32::MAX);   // ditto for gid_t
+    fn chown(&self, _req: RequestInfo, path: &FsPath, fh: Option<u64>, uid: Option<u32>, gid: Option<u32>) -> ResultEmpty {
+        let uid = uid.unwrap_or(u32::MAX);   // docs say "-1", but uid_t is unsigned
+        let gid = gid.unwrap_or(u32::MAX);   // ditto for gid_t
         debug!("chown: {:?} to {}:{}", path, uid, gid);
 
         let result = if let Some(fd) = fh {
@@ -348,13 +388,13 @@ impl FilesystemMT for PassthroughFS {
         if -1 == result {
             let e = io::Error::last_os_error();
             error!("chown({:?}, {}, {}): {}", path, uid, gid, e);
-            Err(e.raw_os_error().unwrap())
+            Err(io_errno(&e))
         } else {
             Ok(())
         }
     }
 
-    fn truncate(&self, _req: RequestInfo, path: &Path, fh: Option<u64>, size: u64) -> ResultEmpty {
+    fn truncate(&self, _req: RequestInfo, path: &FsPath, fh: Option<u64>, size: u64) -> ResultEmpty {
         debug!("truncate: {:?} to {:#x}", path, size);
 
         let result = if let Some(fd) = fh {
@@ -370,13 +410,13 @@ impl FilesystemMT for PassthroughFS {
         if -1 == result {
             let e = io::Error::last_os_error();
             error!("truncate({:?}, {}): {}", path, size, e);
-            Err(e.raw_os_error().unwrap())
+            Err(io_errno(&e))
         } else {
             Ok(())
         }
     }
 
-    fn utimens(&self, _req: RequestInfo, path: &Path, fh: Option<u64>, atime: Option<SystemTime>, mtime: Option<SystemTime>) -> ResultEmpty {
+    fn utimens(&self, _req: RequestInfo, path: &FsPath, fh: Option<u64>, atime: Option<SystemTime>, mtime: Option<SystemTime>) -> ResultEmpty {
         debug!("utimens: {:?}: {:?}, {:?}", path, atime, mtime);
 
         let systemtime_to_libc = |time: Option<SystemTime>| -> libc::timespec {
@@ -416,42 +456,34 @@ impl FilesystemMT for PassthroughFS {
         if -1 == result {
             let e = io::Error::last_os_error();
             error!("utimens({:?}, {:?}, {:?}): {}", path, atime, mtime, e);
-            Err(e.raw_os_error().unwrap())
+            Err(io_errno(&e))
         } else {
             Ok(())
         }
     }
 
-    fn readlink(&self, _req: RequestInfo, path: &Path) -> ResultData {
+    fn readlink(&self, _req: RequestInfo, path: &FsPath) -> ResultData {
         debug!("readlink: {:?}", path);
 
         let real = self.real_path(path);
-        match ::std::fs::read_link(real) {
-            Ok(target) => Ok(target.into_os_string().into_vec()),
-            Err(e) => Err(e.raw_os_error().unwrap()),
-        }
+        Ok(::std::fs::read_link(real).errno()?.into_os_string().into_vec())
     }
 
-    fn statfs(&self, _req: RequestInfo, path: &Path) -> ResultStatfs {
+    fn statfs(&self, _req: RequestInfo, path: &FsPath) -> ResultStatfs {
         debug!("statfs: {:?}", path);
 
         let real = self.real_path(path);
-        let mut buf: libc::statfs = unsafe { ::std::mem::zeroed() };
-        let result = unsafe {
-            let path_c = CString::from_vec_unchecked(real.into_vec());
-            libc::statfs(path_c.as_ptr(), &mut buf)
-        };
-
-        if -1 == result {
-            let e = io::Error::last_os_error();
-            error!("statfs({:?}): {}", path, e);
-            Err(e.raw_os_error().unwrap())
-        } else {
-            Ok(statfs_to_fuse(buf))
+        match libc_wrappers::statvfs(real) {
+            Ok(statvfs) => Ok(statvfs_to_fuse(statvfs)),
+            Err(e) => {
+                let ioerr = io::Error::from_raw_os_error(e);
+                error!("statfs({:?}): {}", path, ioerr);
+                Err(e)
+            }
         }
     }
 
-    fn fsyncdir(&self, _req: RequestInfo, path: &Path, fh: u64, datasync: bool) -> ResultEmpty {
+    fn fsyncdir(&self, _req: RequestInfo, path: &FsPath, fh: u64, datasync: bool) -> ResultEmpty {
         debug!("fsyncdir: {:?} (datasync = {:?})", path, datasync);
 
         // TODO: what does datasync mean with regards to a directory handle?
@@ -459,13 +491,13 @@ impl FilesystemMT for PassthroughFS {
         if -1 == result {
             let e = io::Error::last_os_error();
             error!("fsyncdir({:?}): {}", path, e);
-            Err(e.raw_os_error().unwrap())
+            Err(io_errno(&e))
         } else {
             Ok(())
         }
     }
 
-    fn mknod(&self, _req: RequestInfo, parent_path: &Path, name: &OsStr, mode: u32, rdev: u32) -> ResultEntry {
+    fn mknod(&self, _req: RequestInfo, parent_path: &FsPath, name: &OsStr, mode: u32, rdev: u32) -> ResultEntry {
         debug!("mknod: {:?}/{:?} (mode={:#o}, rdev={})", parent_path, name, mode, rdev);
 
         let real = PathBuf::from(self.real_path(parent_path)).join(name);
@@ -477,7 +509,7 @@ impl FilesystemMT for PassthroughFS {
         if -1 == result {
             let e = io::Error::last_os_error();
             error!("mknod({:?}, {}, {}): {}", real, mode, rdev, e);
-            Err(e.raw_os_error().unwrap())
+            Err(io_errno(&e))
         } else {
             match libc_wrappers::lstat(real.into_os_string()) {
                 Ok(attr) => Ok((TTL, stat_to_fuse(attr))),
@@ -486,7 +518,7 @@ impl FilesystemMT for PassthroughFS {
         }
     }
 
-    fn mkdir(&self, _req: RequestInfo, parent_path: &Path, name: &OsStr, mode: u32) -> ResultEntry {
+    fn mkdir(&self, _req: RequestInfo, parent_path: &FsPath, name: &OsStr, mode: u32) -> ResultEntry {
         debug!("mkdir {:?}/{:?} (mode={:#o})", parent_path, name, mode);
 
         let real = PathBuf::from(self.real_path(parent_path)).join(name);
@@ -498,7 +530,7 @@ impl FilesystemMT for PassthroughFS {
         if -1 == result {
             let e = io::Error::last_os_error();
             error!("mkdir({:?}, {:#o}): {}", real, mode, e);
-            Err(e.raw_os_error().unwrap())
+            Err(io_errno(&e))
         } else {
             match libc_wrappers::lstat(real.clone().into_os_string()) {
                 Ok(attr) => Ok((TTL, stat_to_fuse(attr))),
@@ -510,29 +542,29 @@ impl FilesystemMT for PassthroughFS {
         }
     }
 
-    fn unlink(&self, _req: RequestInfo, parent_path: &Path, name: &OsStr) -> ResultEmpty {
+    fn unlink(&self, _req: RequestInfo, parent_path: &FsPath, name: &OsStr) -> ResultEmpty {
         debug!("unlink {:?}/{:?}", parent_path, name);
 
         let real = PathBuf::from(self.real_path(parent_path)).join(name);
         fs::remove_file(&real)
             .map_err(|ioerr| {
                 error!("unlink({:?}): {}", real, ioerr);
-                ioerr.raw_os_error().unwrap()
+                io_errno(&ioerr)
             })
     }
 
-    fn rmdir(&self, _req: RequestInfo, parent_path: &Path, name: &OsStr) -> ResultEmpty {
+    fn rmdir(&self, _req: RequestInfo, parent_path: &FsPath, name: &OsStr) -> ResultEmpty {
         debug!("rmdir: {:?}/{:?}", parent_path, name);
 
         let real = PathBuf::from(self.real_path(parent_path)).join(name);
         fs::remove_dir(&real)
             .map_err(|ioerr| {
                 error!("rmdir({:?}): {}", real, ioerr);
-                ioerr.raw_os_error().unwrap()
+                io_errno(&ioerr)
             })
     }
 
-    fn symlink(&self, _req: RequestInfo, parent_path: &Path, name: &OsStr, target: &Path) -> ResultEntry {
+    fn symlink(&self, _req: RequestInfo, parent_path: &FsPath, name: &OsStr, target: &Path) -> ResultEntry {
         debug!("symlink: {:?}/{:?} -> {:?}", parent_path, name, target);
 
         let real = PathBuf::from(self.real_path(parent_path)).join(name);
@@ -548,12 +580,12 @@ impl FilesystemMT for PassthroughFS {
             },
             Err(e) => {
                 error!("symlink({:?}, {:?}): {}", real, target, e);
-                Err(e.raw_os_error().unwrap())
+                Err(io_errno(&e))
             }
         }
     }
 
-    fn rename(&self, _req: RequestInfo, parent_path: &Path, name: &OsStr, newparent_path: &Path, newname: &OsStr) -> ResultEmpty {
+    fn rename(&self, _req: RequestInfo, parent_path: &FsPath, name: &OsStr, newparent_path: &FsPath, newname: &OsStr) -> ResultEmpty {
         debug!("rename: {:?}/{:?} -> {:?}/{:?}", parent_path, name, newparent_path, newname);
 
         let real = PathBuf::from(self.real_path(parent_path)).join(name);
@@ -561,11 +593,11 @@ impl FilesystemMT for PassthroughFS {
         fs::rename(&real, &newreal)
             .map_err(|ioerr| {
                 error!("rename({:?}, {:?}): {}", real, newreal, ioerr);
-                ioerr.raw_os_error().unwrap()
+                io_errno(&ioerr)
             })
     }
 
-    fn link(&self, _req: RequestInfo, path: &Path, newparent: &Path, newname: &OsStr) -> ResultEntry {
+    fn link(&self, _req: RequestInfo, path: &FsPath, newparent: &FsPath, newname: &OsStr) -> ResultEntry {
         debug!("link: {:?} -> {:?}/{:?}", path, newparent, newname);
 
         let real = self.real_path(path);
@@ -582,31 +614,32 @@ impl FilesystemMT for PassthroughFS {
             },
             Err(e) => {
                 error!("link({:?}, {:?}): {}", real, newreal, e);
-                Err(e.raw_os_error().unwrap())
+                Err(io_errno(&e))
             },
         }
     }
 
-    fn create(&self, _req: RequestInfo, parent: &Path, name: &OsStr, mode: u32, flags: u32) -> ResultCreate {
+    fn create(&self, _req: RequestInfo, parent: &FsPath, name: &OsStr, mode: u32, flags: u32) -> ResultCreate {
         debug!("create: {:?}/{:?} (mode={:#o}, flags={:#x})", parent, name, mode, flags);
 
         let real = PathBuf::from(self.real_path(parent)).join(name);
+        let (flags, open_flags) = direct_io(flags as libc::c_int);
         let fd = unsafe {
             let real_c = CString::from_vec_unchecked(real.clone().into_os_string().into_vec());
-            libc::open(real_c.as_ptr(), flags as i32 | libc::O_CREAT | libc::O_EXCL, mode)
+            libc::open(real_c.as_ptr(), flags | libc::O_CREAT | libc::O_EXCL, mode)
         };
 
         if -1 == fd {
             let ioerr = io::Error::last_os_error();
             error!("create({:?}): {}", real, ioerr);
-            Err(ioerr.raw_os_error().unwrap())
+            Err(io_errno(&ioerr))
         } else {
             match libc_wrappers::lstat(real.clone().into_os_string()) {
                 Ok(attr) => Ok(CreatedEntry {
                     ttl: TTL,
                     attr: stat_to_fuse(attr),
                     fh: fd as u64,
-                    flags,
+                    flags: open_flags,
                 }),
                 Err(e) => {
                     error!("lstat after create({:?}): {}", real, io::Error::from_raw_os_error(e));
@@ -616,16 +649,15 @@ impl FilesystemMT for PassthroughFS {
         }
     }
 
-    fn listxattr(&self, _req: RequestInfo, path: &Path, size: u32) -> ResultXattr {
+    fn listxattr(&self, _req: RequestInfo, path: &FsPath, size: u32) -> ResultXattr {
         debug!("listxattr: {:?}", path);
 
         let real = self.real_path(path);
 
         if size > 0 {
-            let mut data = Vec::<u8>::with_capacity(size as usize);
-            let nread = libc_wrappers::llistxattr(
-                real, unsafe { mem::transmute(data.spare_capacity_mut()) })?;
-            unsafe { data.set_len(nread) };
+            let mut data = vec![0; size as usize];
+            let nread = libc_wrappers::llistxattr(real, &mut data)?;
+            data.truncate(nread);
             Ok(Xattr::Data(data))
         } else {
             let nbytes = libc_wrappers::llistxattr(real, &mut[])?;
@@ -633,16 +665,15 @@ impl FilesystemMT for PassthroughFS {
         }
     }
 
-    fn getxattr(&self, _req: RequestInfo, path: &Path, name: &OsStr, size: u32) -> ResultXattr {
+    fn getxattr(&self, _req: RequestInfo, path: &FsPath, name: &OsStr, size: u32) -> ResultXattr {
         debug!("getxattr: {:?} {:?} {}", path, name, size);
 
         let real = self.real_path(path);
 
         if size > 0 {
-            let mut data = Vec::<u8>::with_capacity(size as usize);
-            let nread = libc_wrappers::lgetxattr(
-                real, name.to_owned(), unsafe { mem::transmute(data.spare_capacity_mut()) })?;
-            unsafe { data.set_len(nread) };
+            let mut data = vec![0; size as usize];
+            let nread = libc_wrappers::lgetxattr(real, name.to_owned(), &mut data)?;
+            data.truncate(nread);
             Ok(Xattr::Data(data))
         } else {
             let nbytes = libc_wrappers::lgetxattr(real, name.to_owned(), &mut [])?;
@@ -650,13 +681,13 @@ impl FilesystemMT for PassthroughFS {
         }
     }
 
-    fn setxattr(&self, _req: RequestInfo, path: &Path, name: &OsStr, value: &[u8], flags: u32, position: u32) -> ResultEmpty {
-        debug!("setxattr: {:?} {:?} {} bytes, flags = {:#x}, pos = {}", path, name, value.len(), flags, position);
+    fn setxattr(&self, _req: RequestInfo, path: &FsPath, name: &OsStr, value: &[u8], flags: u32, position: XattrPosition) -> ResultEmpty {
+        debug!("setxattr: {:?} {:?} {} bytes, flags = {:#x}, pos = {:?}", path, name, value.len(), flags, position);
         let real = self.real_path(path);
-        libc_wrappers::lsetxattr(real, name.to_owned(), value, flags, position)
+        libc_wrappers::lsetxattr(real, name.to_owned(), value, flags, position.offset())
     }
 
-    fn removexattr(&self, _req: RequestInfo, path: &Path, name: &OsStr) -> ResultEmpty {
+    fn removexattr(&self, _req: RequestInfo, path: &FsPath, name: &OsStr) -> ResultEmpty {
         debug!("removexattr: {:?} {:?}", path, name);
         let real = self.real_path(path);
         libc_wrappers::lremovexattr(real, name.to_owned())
@@ -669,7 +700,7 @@ impl FilesystemMT for PassthroughFS {
     }
 
     #[cfg(target_os = "macos")]
-    fn getxtimes(&self, _req: RequestInfo, path: &Path) -> ResultXTimes {
+    fn getxtimes(&self, _req: RequestInfo, path: &FsPath) -> ResultXTimes {
         debug!("getxtimes: {:?}", path);
         let xtimes = XTimes {
             bkuptime: SystemTime::UNIX_EPOCH,
@@ -702,16 +733,7 @@ impl Drop for UnmanagedFile {
     fn drop(&mut self) {
         // Release control of the file descriptor so it is not closed.
         let file = self.inner.take().unwrap();
-        file.into_raw_fd();
-    }
-}
-
-impl Read for UnmanagedFile {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.inner.as_ref().unwrap().read(buf)
-    }
-    fn read_to_end(&mut self, buf: &mut Vec<u8>) -> io::Result<usize> {
-        self.inner.as_ref().unwrap().read_to_end(buf)
+        let _ = file.into_raw_fd();
     }
 }
 
@@ -724,8 +746,11 @@ impl Write for UnmanagedFile {
     }
 }
 
-impl Seek for UnmanagedFile {
-    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
-        self.inner.as_ref().unwrap().seek(pos)
+impl FileExt for UnmanagedFile {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        self.inner.as_ref().unwrap().read_at(buf, offset)
+    }
+    fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<usize> {
+        self.inner.as_ref().unwrap().write_at(buf, offset)
     }
 }
@@ -0,0 +1,408 @@
+// Async I/O bridging :: adapters for serving FilesystemMT I/O calls from async backends.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+//! Utilities for implementing the blocking `FilesystemMT` I/O calls on top of async backends.
+//!
+//! FuseMT calls `read` and `write` on its worker threads, and expects them to block until the
+//! operation is done. If the data actually comes from an async source, these adapters take care
+//! of running the future to completion on the calling worker thread, while limiting how many
+//! workers can be blocked on the async runtime at once.
+//!
+//! The async runtime is abstracted by the `Executor` trait. With the `tokio` feature, a tokio
+//! runtime `Handle` is an `Executor`, and `TokioIo` and `TokioStream` adapt tokio's `AsyncRead`
+//! and `AsyncWrite` objects to the async source traits:
+//!
+//! ```ignore
+//! let bridge = Arc::new(AsyncBridge::new(runtime.handle().clone(), 16));
+//! let file = runtime.block_on(tokio::fs::File::open(path))?;
+//! let handle = AsyncFileHandle::new(bridge, Box::new(TokioIo::new(file)));
+//! ```
+
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+
+use crate::errno::*;
+use crate::types::*;
+
+/// A boxed future, as returned by the async source traits.
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Something that can run a future to completion, blocking the calling thread until it's done.
+pub trait Executor: Send + Sync {
+    fn block_on<F: Future>(&self, future: F) -> F::Output;
+}
+
+/// An executor which polls the future on the calling thread, parking the thread while it waits.
+///
+/// This is suitable for futures which don't depend on being run within any particular async
+/// runtime.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ThreadExecutor;
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+impl Executor for ThreadExecutor {
+    fn block_on<F: Future>(&self, future: F) -> F::Output {
+        let mut future = Box::pin(future);
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+}
+
+/// A source of data which can be read asynchronously at arbitrary offsets.
+pub trait AsyncReadAt: Send + Sync {
+    /// Read up to `size` bytes starting at `offset`. Returning fewer bytes than requested means
+    /// the end of the data was reached.
+    fn read_at(&self, offset: u64, size: u32) -> BoxFuture<'_, io::Result<Vec<u8>>>;
+}
+
+/// A sink for data which can be written asynchronously at arbitrary offsets.
+pub trait AsyncWriteAt: Send + Sync {
+    /// Write the data starting at `offset`, returning the number of bytes written.
+    fn write_at(&self, offset: u64, data: Vec<u8>) -> BoxFuture<'_, io::Result<u32>>;
+
+    /// Flush any buffered data.
+    fn flush(&self) -> BoxFuture<'_, io::Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Executor for tokio::runtime::Handle {
+    /// Runs the future on the runtime. This panics if called from within an async context, which
+    /// FuseMT's worker threads never are.
+    fn block_on<F: Future>(&self, future: F) -> F::Output {
+        tokio::runtime::Handle::block_on(self, future)
+    }
+}
+
+/// Adapts a tokio I/O object which can seek, such as a `tokio::fs::File`, to `AsyncReadAt` and
+/// `AsyncWriteAt`.
+///
+/// Each call seeks to its offset before reading or writing, so calls are serialized by a lock.
+#[cfg(feature = "tokio")]
+#[derive(Debug)]
+pub struct TokioIo<T> {
+    io: tokio::sync::Mutex<T>,
+}
+
+#[cfg(feature = "tokio")]
+impl<T> TokioIo<T> {
+    pub fn new(io: T) -> TokioIo<T> {
+        TokioIo { io: tokio::sync::Mutex::new(io) }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.io.into_inner()
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<T> AsyncReadAt for TokioIo<T>
+    where T: tokio::io::AsyncRead + tokio::io::AsyncSeek + Unpin + Send,
+{
+    fn read_at(&self, offset: u64, size: u32) -> BoxFuture<'_, io::Result<Vec<u8>>> {
+        use tokio::io::{AsyncReadExt, AsyncSeekExt};
+        Box::pin(async move {
+            let mut io = self.io.lock().await;
+            io.seek(io::SeekFrom::Start(offset)).await?;
+            let mut data = Vec::with_capacity(size as usize);
+            (&mut *io).take(u64::from(size)).read_to_end(&mut data).await?;
+            Ok(data)
+        })
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<T> AsyncWriteAt for TokioIo<T>
+    where T: tokio::io::AsyncWrite + tokio::io::AsyncSeek + Unpin + Send,
+{
+    fn write_at(&self, offset: u64, data: Vec<u8>) -> BoxFuture<'_, io::Result<u32>> {
+        use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+        Box::pin(async move {
+            let mut io = self.io.lock().await;
+            io.seek(io::SeekFrom::Start(offset)).await?;
+            io.write_all(&data).await?;
+            Ok(data.len() as u32)
+        })
+    }
+
+    fn flush(&self) -> BoxFuture<'_, io::Result<()>> {
+        use tokio::io::AsyncWriteExt;
+        Box::pin(async move {
+            self.io.lock().await.flush().await
+        })
+    }
+}
+
+/// Adapts a tokio I/O object which can't seek, such as a socket or pipe, to `AsyncReadAt` and
+/// `AsyncWriteAt`.
+///
+/// Reads and writes have to continue from where the last one left off; any other offset fails
+/// with `ESPIPE`. Reads and writes each have their own position, so a duplex stream can be used
+/// for both.
+#[cfg(feature = "tokio")]
+#[derive(Debug)]
+pub struct TokioStream<T> {
+    io: tokio::sync::Mutex<StreamState<T>>,
+}
+
+#[cfg(feature = "tokio")]
+#[derive(Debug)]
+struct StreamState<T> {
+    io: T,
+    read_pos: u64,
+    write_pos: u64,
+}
+
+#[cfg(feature = "tokio")]
+impl<T> TokioStream<T> {
+    pub fn new(io: T) -> TokioStream<T> {
+        TokioStream {
+            io: tokio::sync::Mutex::new(StreamState { io, read_pos: 0, write_pos: 0 }),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.io.into_inner().io
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<T> AsyncReadAt for TokioStream<T>
+    where T: tokio::io::AsyncRead + Unpin + Send,
+{
+    fn read_at(&self, offset: u64, size: u32) -> BoxFuture<'_, io::Result<Vec<u8>>> {
+        use tokio::io::AsyncReadExt;
+        Box::pin(async move {
+            let mut state = self.io.lock().await;
+            if offset != state.read_pos {
+                return Err(io::Error::from_raw_os_error(libc::ESPIPE));
+            }
+            let mut data = Vec::with_capacity(size as usize);
+            (&mut state.io).take(u64::from(size)).read_to_end(&mut data).await?;
+            state.read_pos += data.len() as u64;
+            Ok(data)
+        })
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<T> AsyncWriteAt for TokioStream<T>
+    where T: tokio::io::AsyncWrite + Unpin + Send,
+{
+    fn write_at(&self, offset: u64, data: Vec<u8>) -> BoxFuture<'_, io::Result<u32>> {
+        use tokio::io::AsyncWriteExt;
+        Box::pin(async move {
+            let mut state = self.io.lock().await;
+            if offset != state.write_pos {
+                return Err(io::Error::from_raw_os_error(libc::ESPIPE));
+            }
+            state.io.write_all(&data).await?;
+            state.write_pos += data.len() as u64;
+            Ok(data.len() as u32)
+        })
+    }
+
+    fn flush(&self) -> BoxFuture<'_, io::Result<()>> {
+        use tokio::io::AsyncWriteExt;
+        Box::pin(async move {
+            self.io.lock().await.io.flush().await
+        })
+    }
+}
+
+/// Runs async I/O on behalf of the blocking `FilesystemMT` calls.
+///
+/// At most `max_blocked` worker threads will be blocked waiting on the executor at once; any
+/// further callers wait their turn. This keeps a slow async backend from tying up every worker in
+/// the thread pool.
+#[derive(Debug)]
+pub struct AsyncBridge<E> {
+    executor: E,
+    permits: Mutex<usize>,
+    available: Condvar,
+}
+
+impl<E: Executor> AsyncBridge<E> {
+    pub fn new(executor: E, max_blocked: usize) -> AsyncBridge<E> {
+        AsyncBridge {
+            executor,
+            permits: Mutex::new(max_blocked.max(1)),
+            available: Condvar::new(),
+        }
+    }
+
+    /// Run a future to completion on the executor, blocking the calling thread.
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        let _permit = self.acquire();
+        self.executor.block_on(future)
+    }
+
+    fn acquire(&self) -> Permit<'_, E> {
+        let mut permits = self.permits.lock().unwrap();
+        while *permits == 0 {
+            permits = self.available.wait(permits).unwrap();
+        }
+        *permits -= 1;
+        Permit { bridge: self }
+    }
+
+    /// Serve a `FilesystemMT::read` call from an async source.
+    pub fn read(
+        &self,
+        source: &dyn AsyncReadAt,
+        offset: u64,
+        size: u32,
+        callback: impl FnOnce(ResultSlice<'_>) -> CallbackResult,
+    ) -> CallbackResult {
+        match self.block_on(source.read_at(offset, size)) {
+            Ok(data) => callback(Ok(&data)),
+            Err(e) => callback(Err(io_errno(&e))),
+        }
+    }
+
+    /// Serve a `FilesystemMT::write` call with an async sink.
+    pub fn write(&self, sink: &dyn AsyncWriteAt, offset: u64, data: Vec<u8>) -> ResultWrite {
+        self.block_on(sink.write_at(offset, data)).errno()
+    }
+
+    /// Serve a `FilesystemMT::flush` call with an async sink.
+    pub fn flush(&self, sink: &dyn AsyncWriteAt) -> ResultEmpty {
+        self.block_on(sink.flush()).errno()
+    }
+}
+
+/// Returns its permit to the bridge when dropped, even if the future panicked.
+struct Permit<'a, E> {
+    bridge: &'a AsyncBridge<E>,
+}
+
+impl<'a, E> Drop for Permit<'a, E> {
+    fn drop(&mut self) {
+        *self.bridge.permits.lock().unwrap() += 1;
+        self.bridge.available.notify_one();
+    }
+}
+
+/// A `FileHandle` that serves reads and writes from an async source, for use with
+/// `FilesystemMT::open_handle`.
+///
+/// Writes fail with `EBADF` unless a sink is given with `with_sink`.
+pub struct AsyncFileHandle<E> {
+    bridge: Arc<AsyncBridge<E>>,
+    source: Box<dyn AsyncReadAt>,
+    sink: Option<Box<dyn AsyncWriteAt>>,
+}
+
+impl<E: Executor> AsyncFileHandle<E> {
+    pub fn new(bridge: Arc<AsyncBridge<E>>, source: Box<dyn AsyncReadAt>) -> AsyncFileHandle<E> {
+        AsyncFileHandle {
+            bridge,
+            source,
+            sink: None,
+        }
+    }
+
+    /// Also accept writes, sending them to the given sink.
+    pub fn with_sink(mut self, sink: Box<dyn AsyncWriteAt>) -> AsyncFileHandle<E> {
+        self.sink = Some(sink);
+        self
+    }
+}
+
+impl<E: Executor> FileHandle for AsyncFileHandle<E> {
+    fn read(&self, _req: RequestInfo, offset: u64, size: u32) -> ResultData {
+        self.bridge.block_on(self.source.read_at(offset, size)).errno()
+    }
+
+    fn write(&self, _req: RequestInfo, offset: u64, data: Vec<u8>, _flags: u32) -> ResultWrite {
+        match self.sink {
+            Some(ref sink) => self.bridge.write(&**sink, offset, data),
+            None => Err(libc::EBADF),
+        }
+    }
+
+    fn flush(&self, _req: RequestInfo, _lock_owner: LockOwner) -> ResultEmpty {
+        match self.sink {
+            Some(ref sink) => self.bridge.flush(&**sink),
+            None => Ok(()),
+        }
+    }
+
+    fn fsync(&self, _req: RequestInfo, _datasync: bool) -> ResultEmpty {
+        match self.sink {
+            Some(ref sink) => self.bridge.flush(&**sink),
+            None => Ok(()),
+        }
+    }
+}
+
+#[test]
+fn test_bridge_read() {
+    struct Source(Vec<u8>);
+    impl AsyncReadAt for Source {
+        fn read_at(&self, offset: u64, size: u32) -> BoxFuture<'_, io::Result<Vec<u8>>> {
+            Box::pin(async move {
+                // Yield once to make sure the executor handles pending futures.
+                let mut yielded = false;
+                std::future::poll_fn(|cx| {
+                    if yielded {
+                        Poll::Ready(())
+                    } else {
+                        yielded = true;
+                        cx.waker().wake_by_ref();
+                        Poll::Pending
+                    }
+                }).await;
+                let start = (offset as usize).min(self.0.len());
+                let end = (start + size as usize).min(self.0.len());
+                Ok(self.0[start .. end].to_vec())
+            })
+        }
+    }
+
+    let bridge = AsyncBridge::new(ThreadExecutor, 1);
+    let source = Source(b"hello world".to_vec());
+    let mut result = None;
+    bridge.read(&source, 6, 100, |data| {
+        result = Some(data.map(<[u8]>::to_vec));
+        CallbackResult { _private: std::marker::PhantomData }
+    });
+    assert_eq!(Some(Ok(b"world".to_vec())), result);
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn test_tokio_io() {
+    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    let bridge = Arc::new(AsyncBridge::new(runtime.handle().clone(), 2));
+    let io = TokioIo::new(io::Cursor::new(b"hello world".to_vec()));
+    assert_eq!(5, bridge.block_on(io.write_at(6, b"tokio".to_vec())).unwrap());
+    assert_eq!(b"tokio".to_vec(), bridge.block_on(io.read_at(6, 100)).unwrap());
+    assert_eq!(b"hello tokio".to_vec(), io.into_inner().into_inner());
+
+    let stream = TokioStream::new(&b"abcdef"[..]);
+    assert_eq!(b"abc".to_vec(), bridge.block_on(stream.read_at(0, 3)).unwrap());
+    let e = bridge.block_on(stream.read_at(0, 3)).unwrap_err();
+    assert_eq!(Some(libc::ESPIPE), e.raw_os_error());
+    assert_eq!(b"def".to_vec(), bridge.block_on(stream.read_at(3, 3)).unwrap());
+}
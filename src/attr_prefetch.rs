@@ -0,0 +1,128 @@
+// AttrPrefetch :: fetching the attributes of listed entries ahead of the lookups for them.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::types::FileAttr;
+
+/// Most attributes to hold at once. Listings read while this many are waiting to be looked up
+/// aren't prefetched.
+const MAX_ENTRIES: usize = 65536;
+
+/// Attributes of directory entries, fetched on the worker threads when the directory is listed,
+/// since programs like `ls -l` look up every entry right after listing them, and FuseMT makes
+/// lookups one at a time.
+///
+/// Each is used for at most one lookup, and only within the TTL the filesystem gave for it. They
+/// are all dropped whenever anything is changed through the mount.
+#[derive(Debug)]
+pub struct AttrPrefetch {
+    /// Number of worker threads to spread the calls for one listing over.
+    pub concurrency: usize,
+    inner: Mutex<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// Bumped whenever the entries are dropped, so fetches which were started before then don't
+    /// add theirs.
+    generation: u64,
+    entries: HashMap<PathBuf, Entry>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    fetched: Instant,
+    ttl: Duration,
+    attr: FileAttr,
+}
+
+impl AttrPrefetch {
+    pub fn new(concurrency: usize) -> AttrPrefetch {
+        AttrPrefetch {
+            concurrency: concurrency.max(1),
+            inner: Mutex::new(Inner::default()),
+        }
+    }
+
+    /// If there's room for the attributes of `count` more entries, get the generation to add them
+    /// with.
+    pub fn start(&self, count: usize, now: Instant) -> Option<u64> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.entries.len() + count > MAX_ENTRIES {
+            inner.entries.retain(|_, entry| now.saturating_duration_since(entry.fetched) < entry.ttl);
+            if inner.entries.len() + count > MAX_ENTRIES {
+                return None;
+            }
+        }
+        Some(inner.generation)
+    }
+
+    /// Keep the attributes fetched for a path, unless they were dropped since the fetch started.
+    pub fn insert(&self, generation: u64, path: PathBuf, fetched: Instant, ttl: Duration,
+                  attr: FileAttr) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.generation == generation {
+            inner.entries.insert(path, Entry { fetched, ttl, attr });
+        }
+    }
+
+    /// Take the attributes fetched for a path, if there are any and they're still fresh, with the
+    /// rest of their TTL.
+    pub fn take(&self, path: &Path, now: Instant) -> Option<(Duration, FileAttr)> {
+        let entry = self.inner.lock().unwrap().entries.remove(path)?;
+        let age = now.saturating_duration_since(entry.fetched);
+        if age >= entry.ttl {
+            return None;
+        }
+        Some((entry.ttl - age, entry.attr))
+    }
+
+    /// Drop all the attributes kept.
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.generation += 1;
+        if !inner.entries.is_empty() {
+            inner.entries = HashMap::new();
+        }
+    }
+}
+
+#[test]
+fn test_attr_prefetch() {
+    let attr = FileAttr {
+        ino: 0, size: 1, blocks: 0, atime: std::time::UNIX_EPOCH, mtime: std::time::UNIX_EPOCH,
+        ctime: std::time::UNIX_EPOCH, crtime: std::time::UNIX_EPOCH,
+        kind: crate::FileType::RegularFile, perm: 0o644, nlink: 1, uid: 0, gid: 0, rdev: 0,
+        flags: 0,
+    };
+    let prefetch = AttrPrefetch::new(4);
+    let ttl = Duration::from_secs(60);
+
+    let now = Instant::now();
+
+    // Attributes are used once.
+    let generation = prefetch.start(3, now).unwrap();
+    for path in &["/a", "/b", "/c"] {
+        prefetch.insert(generation, PathBuf::from(path), now, ttl, attr);
+    }
+    assert_eq!(1, prefetch.take(Path::new("/a"), now).unwrap().1.size);
+    assert!(prefetch.take(Path::new("/a"), now).is_none());
+
+    // With the rest of their TTL, and not once they've expired.
+    let later = now + Duration::from_secs(50);
+    assert_eq!(Duration::from_secs(10), prefetch.take(Path::new("/b"), later).unwrap().0);
+    assert!(prefetch.take(Path::new("/c"), now + ttl).is_none());
+
+    // Fetches which were going on when they were dropped are ignored.
+    let generation = prefetch.start(1, now).unwrap();
+    prefetch.clear();
+    prefetch.insert(generation, PathBuf::from("/a"), now, ttl, attr);
+    assert!(prefetch.take(Path::new("/a"), now).is_none());
+    assert!(prefetch.start(MAX_ENTRIES + 1, now).is_none());
+}
@@ -0,0 +1,244 @@
+// Block Map :: byte-range file I/O on top of fixed-size block storage.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+//! Translating byte-range file I/O into operations on fixed-size blocks.
+//!
+//! Filesystems which encrypt, compress, or otherwise transform file data usually have to do it in
+//! fixed-size blocks, while FUSE reads and writes arbitrary byte ranges. `BlockMap` bridges the
+//! two: implement `BlockStore` to get and put whole blocks, and `BlockMap` turns reads, writes,
+//! and truncates into block operations, including reading, modifying, and writing back blocks
+//! which are only partly written.
+//!
+//! `BlockMap` doesn't keep track of file sizes; that's metadata the filesystem has to manage
+//! anyway, so each call takes the current size of the file, and writes return the new one.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+
+use crate::types::*;
+
+/// Default number of blocks to keep cached.
+const DEFAULT_CACHE_BLOCKS: usize = 64;
+
+/// Storage for the blocks of files.
+pub trait BlockStore {
+    /// Identifies a file. Anything works, as long as it's stable for the life of the file: a
+    /// path, an inode number, an object key, and so on.
+    type FileId: Clone + Eq + Hash;
+
+    /// Get the contents of a block. A block shorter than the block size, or one that doesn't exist
+    /// (`None`), reads as if it were padded out with zeroes.
+    fn get_block(&self, file: &Self::FileId, index: u64) -> Result<Option<Vec<u8>>, libc::c_int>;
+
+    /// Replace the contents of a block. `data` is never longer than the block size, but can be
+    /// shorter if it's the last block of the file.
+    fn put_block(&self, file: &Self::FileId, index: u64, data: &[u8]) -> ResultEmpty;
+
+    /// Remove the blocks of a file with index `from` and higher.
+    fn delete_blocks(&self, file: &Self::FileId, from: u64) -> ResultEmpty;
+}
+
+type Slot = Arc<Mutex<Option<Vec<u8>>>>;
+
+/// Does byte-range I/O on files stored as fixed-size blocks in a `BlockStore`.
+///
+/// Recently used blocks are cached in memory, which also serializes concurrent modifications of
+/// the same block, so two writes to different parts of one block don't overwrite each other.
+/// Writes go through to the store immediately.
+pub struct BlockMap<S: BlockStore> {
+    store: S,
+    block_size: u64,
+    cache_blocks: usize,
+    cache: Mutex<HashMap<(S::FileId, u64), Slot>>,
+}
+
+impl<S: BlockStore> BlockMap<S> {
+    /// Make a new block map using the given store, with the given block size in bytes.
+    pub fn new(store: S, block_size: u32) -> BlockMap<S> {
+        assert!(block_size > 0, "block size must be nonzero");
+        BlockMap {
+            store,
+            block_size: u64::from(block_size),
+            cache_blocks: DEFAULT_CACHE_BLOCKS,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Set how many blocks to keep cached in memory. The default is 64.
+    pub fn cache_blocks(mut self, count: usize) -> BlockMap<S> {
+        self.cache_blocks = count;
+        self
+    }
+
+    /// Get the underlying block store.
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// Get the block size, in bytes.
+    pub fn block_size(&self) -> u32 {
+        self.block_size as u32
+    }
+
+    /// Get the cache slot for a block, creating it if needed.
+    fn slot(&self, file: &S::FileId, index: u64) -> Slot {
+        let mut cache = self.cache.lock().unwrap();
+        if cache.len() >= self.cache_blocks {
+            // Drop everything not currently in use. Crude, but blocks are cheap to get again
+            // compared to the bookkeeping of a real LRU.
+            cache.retain(|_, slot| Arc::strong_count(slot) > 1);
+        }
+        cache.entry((file.clone(), index)).or_default().clone()
+    }
+
+    /// Fill a cache slot from the store, if it isn't already.
+    fn load<'a>(&self, file: &S::FileId, index: u64, slot: &'a mut Option<Vec<u8>>)
+        -> Result<&'a mut Vec<u8>, libc::c_int>
+    {
+        if slot.is_none() {
+            *slot = Some(self.store.get_block(file, index)?.unwrap_or_default());
+        }
+        Ok(slot.as_mut().unwrap())
+    }
+
+    /// Read up to `size` bytes at `offset` from a file which is `file_size` bytes long.
+    pub fn read(&self, file: &S::FileId, file_size: u64, offset: u64, size: u32) -> ResultData {
+        if offset >= file_size {
+            return Ok(vec![]);
+        }
+        let end = file_size.min(offset + u64::from(size));
+        let mut out = Vec::with_capacity((end - offset) as usize);
+        let mut pos = offset;
+        while pos < end {
+            let index = pos / self.block_size;
+            let start = (pos % self.block_size) as usize;
+            let len = (self.block_size - start as u64).min(end - pos) as usize;
+
+            let slot = self.slot(file, index);
+            let mut guard = slot.lock().unwrap();
+            let block = self.load(file, index, &mut guard)?;
+            let have = block.len().saturating_sub(start).min(len);
+            if have > 0 {
+                out.extend_from_slice(&block[start .. start + have]);
+            }
+            out.resize(out.len() + len - have, 0);
+            pos += len as u64;
+        }
+        Ok(out)
+    }
+
+    /// Write `data` at `offset` to a file which is `file_size` bytes long. Returns the new size of
+    /// the file.
+    pub fn write(&self, file: &S::FileId, file_size: u64, offset: u64, data: &[u8])
+        -> Result<u64, libc::c_int>
+    {
+        let mut pos = offset;
+        let mut data = data;
+        while !data.is_empty() {
+            let index = pos / self.block_size;
+            let start = (pos % self.block_size) as usize;
+            let len = ((self.block_size as usize) - start).min(data.len());
+
+            let slot = self.slot(file, index);
+            let mut guard = slot.lock().unwrap();
+            if start == 0 && len as u64 == self.block_size {
+                // Overwriting the whole block; no need to read it first.
+                *guard = Some(data[.. len].to_vec());
+            } else {
+                let block = self.load(file, index, &mut guard)?;
+                if block.len() < start + len {
+                    block.resize(start + len, 0);
+                }
+                block[start .. start + len].copy_from_slice(&data[.. len]);
+            }
+            if let Err(e) = self.store.put_block(file, index, guard.as_ref().unwrap()) {
+                // We don't know what state the block is in now.
+                *guard = None;
+                return Err(e);
+            }
+
+            data = &data[len ..];
+            pos += len as u64;
+        }
+        Ok(file_size.max(pos))
+    }
+
+    /// Change the size of a file which is `file_size` bytes long to `new_size` bytes. Growing a
+    /// file doesn't store anything: the new part reads as zeroes.
+    pub fn truncate(&self, file: &S::FileId, file_size: u64, new_size: u64) -> ResultEmpty {
+        if new_size >= file_size {
+            return Ok(());
+        }
+        let keep = new_size.div_ceil(self.block_size);
+        self.cache.lock().unwrap().retain(|(f, index), _| f != file || *index < keep);
+        self.store.delete_blocks(file, keep)?;
+
+        let tail = (new_size % self.block_size) as usize;
+        if tail != 0 {
+            // Cut off the end of the new last block, so it doesn't reappear if the file grows.
+            let index = keep - 1;
+            let slot = self.slot(file, index);
+            let mut guard = slot.lock().unwrap();
+            let block = self.load(file, index, &mut guard)?;
+            if block.len() > tail {
+                block.truncate(tail);
+                if let Err(e) = self.store.put_block(file, index, block) {
+                    *guard = None;
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Drop any cached blocks of a file, for example when it's deleted.
+    pub fn forget(&self, file: &S::FileId) {
+        self.cache.lock().unwrap().retain(|(f, _), _| f != file);
+    }
+}
+
+#[test]
+fn test_block_map() {
+    #[derive(Default)]
+    struct MemStore {
+        blocks: Mutex<HashMap<u64, Vec<u8>>>,
+    }
+
+    impl BlockStore for MemStore {
+        type FileId = ();
+        fn get_block(&self, _file: &(), index: u64) -> Result<Option<Vec<u8>>, libc::c_int> {
+            Ok(self.blocks.lock().unwrap().get(&index).cloned())
+        }
+        fn put_block(&self, _file: &(), index: u64, data: &[u8]) -> ResultEmpty {
+            assert!(data.len() <= 4);
+            self.blocks.lock().unwrap().insert(index, data.to_vec());
+            Ok(())
+        }
+        fn delete_blocks(&self, _file: &(), from: u64) -> ResultEmpty {
+            self.blocks.lock().unwrap().retain(|&index, _| index < from);
+            Ok(())
+        }
+    }
+
+    let map = BlockMap::new(MemStore::default(), 4).cache_blocks(1);
+    let size = map.write(&(), 0, 2, b"abcdefg").unwrap();
+    assert_eq!(9, size);
+    assert_eq!(b"\0\0abcdefg".to_vec(), map.read(&(), size, 0, 100).unwrap());
+    assert_eq!(3, map.store().blocks.lock().unwrap().len());
+
+    // Read-modify-write in the middle of a block.
+    let size = map.write(&(), size, 5, b"X").unwrap();
+    assert_eq!(b"bcXe".to_vec(), map.read(&(), size, 3, 4).unwrap());
+
+    // Writing past the end leaves a hole.
+    let size = map.write(&(), size, 14, b"Z").unwrap();
+    assert_eq!(b"g\0\0\0\0\0Z".to_vec(), map.read(&(), size, 8, 100).unwrap());
+
+    // Truncating drops blocks and trims the last one, so growing again reads zeroes.
+    map.truncate(&(), size, 6).unwrap();
+    assert_eq!(2, map.store().blocks.lock().unwrap().len());
+    assert_eq!(b"\0\0abcX\0\0".to_vec(), map.read(&(), 8, 0, 100).unwrap());
+}
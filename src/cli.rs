@@ -0,0 +1,458 @@
+// CLI :: the command line and lifecycle of a typical filesystem program.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+//! Everything a filesystem binary needs besides the filesystem itself.
+//!
+//! `Cli::run` takes care of parsing the usual `myfs [options] <backing> <mountpoint>` command line
+//! (including the `-o` options passed by mount.fuse, so the program can be used from fstab),
+//! setting up logging, going into the background unless asked not to, mounting, and unmounting
+//! cleanly on SIGINT, SIGTERM, or SIGHUP:
+//!
+//! ```ignore
+//! fn main() {
+//!     std::process::exit(Cli::new("myfs").run(|args| MyFilesystem::new(&args.backing)));
+//! }
+//! ```
+//!
+//! The options understood are below. The command line is parsed with clap, and programs can add
+//! options of their own with `Cli::command`, reading them from `Args::matches`.
+//!
+//! ```text
+//! -o <options>         mount options, comma-separated; can be repeated
+//! -f, --foreground     stay in the foreground, logging to stderr
+//! -d, --debug          stay in the foreground, with debug logging
+//! -v, --verbose...     log more; can be repeated
+//! -t, --threads <n>    number of worker threads
+//! -h, --help           show usage
+//! -V, --version        show version
+//! ```
+//!
+//! In the background, log messages go to syslog. The program doesn't exit (in the foreground)
+//! until the filesystem is mounted, or fails to mount, so scripts and mount.fuse see any errors.
+
+use std::error::Error;
+use std::ffi::{CString, OsString};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::mem::MaybeUninit;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use clap::{Arg, ArgAction, ArgMatches};
+use fuser::MountOption;
+
+use crate::options::{parse_mount_options, OptionError};
+use crate::types::FilesystemMT;
+use crate::FuseMT;
+
+/// A parsed command line.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Args {
+    /// The first free argument: what the filesystem is serving, such as a directory or URL.
+    pub backing: OsString,
+    /// Where to mount the filesystem.
+    pub mountpoint: PathBuf,
+    /// Options from all the `-o` arguments, in order.
+    pub mount_options: Vec<MountOption>,
+    /// Whether to stay in the foreground.
+    pub foreground: bool,
+    /// How much to log: 0 is warnings and errors, 1 adds info, 2 debug, and 3 or more everything.
+    pub verbosity: u32,
+    /// Number of worker threads, if given.
+    pub threads: Option<usize>,
+    /// Everything clap parsed, including any arguments added with `Cli::command`.
+    pub matches: ArgMatches,
+}
+
+/// What the command line asks for.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    Help,
+    Version,
+    Mount(Args),
+}
+
+/// An error in the command line.
+#[derive(Debug)]
+pub enum ArgsError {
+    /// A bad mount option.
+    Option(OptionError),
+    /// The command line didn't fit the arguments clap was told about.
+    Usage(clap::Error),
+}
+
+impl fmt::Display for ArgsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArgsError::Option(e) => e.fmt(f),
+            ArgsError::Usage(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for ArgsError {}
+
+impl From<OptionError> for ArgsError {
+    fn from(e: OptionError) -> ArgsError {
+        ArgsError::Option(e)
+    }
+}
+
+impl From<clap::Error> for ArgsError {
+    fn from(e: clap::Error) -> ArgsError {
+        ArgsError::Usage(e)
+    }
+}
+
+/// Whether log messages go to syslog instead of stderr.
+static USE_SYSLOG: AtomicBool = AtomicBool::new(false);
+
+struct Logger;
+
+impl log::Log for Logger {
+    fn enabled(&self, _metadata: &log::Metadata<'_>) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record<'_>) {
+        if !USE_SYSLOG.load(Ordering::Relaxed) {
+            eprintln!("{}: {}: {}", record.level(), record.target(), record.args());
+            return;
+        }
+        let priority = match record.level() {
+            log::Level::Error => libc::LOG_ERR,
+            log::Level::Warn => libc::LOG_WARNING,
+            log::Level::Info => libc::LOG_INFO,
+            log::Level::Debug | log::Level::Trace => libc::LOG_DEBUG,
+        };
+        let message = format!("{}: {}", record.target(), record.args()).replace('\0', "\\0");
+        let message = CString::new(message).unwrap();
+        unsafe {
+            libc::syslog(priority, b"%s\0".as_ptr() as *const libc::c_char, message.as_ptr());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: Logger = Logger;
+
+/// Fork into the background. Only the child returns, with its stdio pointed at /dev/null and a
+/// pipe to tell the parent how starting up went: the parent waits until it's closed, and exits
+/// successfully if the child wrote a zero byte to it, or prints anything else it wrote and fails.
+fn daemonize() -> io::Result<File> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let (mut read_end, write_end) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => {
+            drop(read_end);
+            if unsafe { libc::setsid() } == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            let null = OpenOptions::new().read(true).write(true).open("/dev/null")?;
+            for fd in 0 ..= 2 {
+                if unsafe { libc::dup2(null.as_raw_fd(), fd) } == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            Ok(write_end)
+        }
+        _ => {
+            drop(write_end);
+            let mut status = vec![];
+            let _ = read_end.read_to_end(&mut status);
+            if status == [0] {
+                std::process::exit(0);
+            }
+            if !status.is_empty() {
+                eprintln!("{}", String::from_utf8_lossy(&status));
+            }
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Block the signals that should unmount the filesystem, so they can be waited for with
+/// `wait_for_signal` instead. This has to happen before any threads are started, so that they
+/// all have them blocked too.
+fn block_signals() -> libc::sigset_t {
+    unsafe {
+        let mut set = MaybeUninit::<libc::sigset_t>::uninit();
+        libc::sigemptyset(set.as_mut_ptr());
+        let mut set = set.assume_init();
+        for &signal in &[libc::SIGINT, libc::SIGTERM, libc::SIGHUP] {
+            libc::sigaddset(&mut set, signal);
+        }
+        libc::pthread_sigmask(libc::SIG_BLOCK, &set, std::ptr::null_mut());
+        set
+    }
+}
+
+fn wait_for_signal(set: &libc::sigset_t) -> libc::c_int {
+    let mut signal = 0;
+    unsafe { libc::sigwait(set, &mut signal) };
+    signal
+}
+
+/// Runs a filesystem program. See the module docs.
+#[derive(Clone, Debug)]
+pub struct Cli {
+    name: String,
+    threads: usize,
+    command: clap::Command,
+}
+
+impl Cli {
+    /// `name` is used in the usage text, as the default `fsname` mount option, and as the syslog
+    /// identifier.
+    pub fn new(name: impl Into<String>) -> Cli {
+        let name = name.into();
+        Cli {
+            command: clap::Command::new(name.clone()),
+            name,
+            threads: 4,
+        }
+    }
+
+    /// Set the number of worker threads used unless `-t` is given. The default is 4.
+    pub fn threads(mut self, threads: usize) -> Cli {
+        self.threads = threads;
+        self
+    }
+
+    /// Customize the clap command the command line is parsed with, such as to add arguments or an
+    /// about text. The standard arguments are added to it when parsing.
+    pub fn command(mut self, f: impl FnOnce(clap::Command) -> clap::Command) -> Cli {
+        self.command = f(self.command);
+        self
+    }
+
+    fn clap_command(&self) -> clap::Command {
+        self.command.clone()
+            .override_usage(format!("{} [options] <backing> <mountpoint>", self.name))
+            .disable_help_flag(true)
+            .disable_version_flag(true)
+            .arg(Arg::new("backing").required(true).value_parser(clap::value_parser!(OsString))
+                .help("what the filesystem serves"))
+            .arg(Arg::new("mountpoint").required(true).value_parser(clap::value_parser!(PathBuf))
+                .help("where to mount the filesystem"))
+            .arg(Arg::new("options").short('o').value_name("options").action(ArgAction::Append)
+                .help("mount options, comma-separated"))
+            .arg(Arg::new("foreground").short('f').long("foreground").action(ArgAction::SetTrue)
+                .help("stay in the foreground, logging to stderr"))
+            .arg(Arg::new("debug").short('d').long("debug").action(ArgAction::SetTrue)
+                .help("stay in the foreground, with debug logging"))
+            .arg(Arg::new("verbose").short('v').long("verbose").action(ArgAction::Count)
+                .help("log more; can be repeated"))
+            .arg(Arg::new("threads").short('t').long("threads").value_name("n")
+                .value_parser(clap::value_parser!(usize))
+                .help(format!("number of worker threads (default {})", self.threads)))
+            .arg(Arg::new("version").short('V').long("version").action(ArgAction::SetTrue)
+                .exclusive(true).help("show version information"))
+            .arg(Arg::new("help").short('h').long("help").action(ArgAction::Help)
+                .help("show this help"))
+    }
+
+    /// Parse a command line in the form described in the module docs. `args` should not include
+    /// the program name.
+    pub fn parse_args<I, S>(&self, args: I) -> Result<Command, ArgsError>
+        where I: IntoIterator<Item = S>,
+              S: Into<OsString> + Clone,
+    {
+        let args = std::iter::once(OsString::from(&self.name))
+            .chain(args.into_iter().map(Into::into));
+        let mut matches = match self.clap_command().try_get_matches_from(args) {
+            Ok(matches) => matches,
+            Err(e) if e.kind() == clap::error::ErrorKind::DisplayHelp => return Ok(Command::Help),
+            Err(e) => return Err(e.into()),
+        };
+        if matches.get_flag("version") {
+            return Ok(Command::Version);
+        }
+
+        let mut mount_options = vec![];
+        for options in matches.get_many::<String>("options").into_iter().flatten() {
+            mount_options.extend(parse_mount_options(options)?);
+        }
+        let debug = matches.get_flag("debug");
+        let verbosity = u32::from(matches.get_count("verbose"));
+        Ok(Command::Mount(Args {
+            backing: matches.remove_one("backing").unwrap(),
+            mountpoint: matches.remove_one("mountpoint").unwrap(),
+            mount_options,
+            foreground: debug || matches.get_flag("foreground"),
+            verbosity: if debug { verbosity.max(2) } else { verbosity },
+            threads: matches.get_one("threads").copied(),
+            matches,
+        }))
+    }
+
+    fn usage(&self) -> String {
+        self.clap_command().render_help().to_string()
+    }
+
+    /// Parse the process's command line, make a filesystem with `make_fs`, and serve it until
+    /// it's unmounted or the process is signalled. Returns the exit code for the process.
+    pub fn run<FS, F>(self, make_fs: F) -> i32
+        where FS: FilesystemMT + Send + Sync + 'static,
+              F: FnOnce(&Args) -> io::Result<FS>,
+    {
+        let args = match self.parse_args(std::env::args_os().skip(1)) {
+            Ok(Command::Mount(args)) => args,
+            Ok(Command::Help) => {
+                println!("{}", self.usage());
+                return 0;
+            }
+            Ok(Command::Version) => {
+                println!("{} using {}", self.name, crate::version());
+                return 0;
+            }
+            Err(ArgsError::Usage(e)) => {
+                let _ = e.print();
+                return 2;
+            }
+            Err(e) => {
+                eprintln!("{}: {}", self.name, e);
+                return 2;
+            }
+        };
+        self.run_with(args, make_fs)
+    }
+
+    /// Like `run`, with an already-parsed command line.
+    pub fn run_with<FS, F>(self, mut args: Args, make_fs: F) -> i32
+        where FS: FilesystemMT + Send + Sync + 'static,
+              F: FnOnce(&Args) -> io::Result<FS>,
+    {
+        let _ = log::set_logger(&LOGGER);
+        log::set_max_level(match args.verbosity {
+            0 => log::LevelFilter::Warn,
+            1 => log::LevelFilter::Info,
+            2 => log::LevelFilter::Debug,
+            _ => log::LevelFilter::Trace,
+        });
+
+        // The daemon changes directory, so relative paths have to be resolved first.
+        args.mountpoint = match args.mountpoint.canonicalize() {
+            Ok(path) => path,
+            Err(e) => {
+                eprintln!("{}: mountpoint {:?}: {}", self.name, args.mountpoint, e);
+                return 1;
+            }
+        };
+        if let Ok(backing) = PathBuf::from(&args.backing).canonicalize() {
+            args.backing = backing.into();
+        }
+
+        let mut status_pipe = None;
+        if !args.foreground {
+            match daemonize() {
+                Ok(pipe) => status_pipe = Some(pipe),
+                Err(e) => {
+                    eprintln!("{}: failed to go into the background: {}", self.name, e);
+                    return 1;
+                }
+            }
+            let ident: &'static CString = Box::leak(Box::new(CString::new(self.name.clone())
+                .unwrap_or_default()));
+            unsafe { libc::openlog(ident.as_ptr(), libc::LOG_PID, libc::LOG_DAEMON) };
+            USE_SYSLOG.store(true, Ordering::Relaxed);
+            let _ = std::env::set_current_dir("/");
+        }
+
+        let signals = block_signals();
+        let result = self.mount(&args, make_fs);
+        let session = match result {
+            Ok(session) => session,
+            Err(e) => {
+                let message = format!("{}: {}", self.name, e);
+                error!("{}", message);
+                match status_pipe {
+                    Some(mut pipe) => { let _ = pipe.write_all(message.as_bytes()); }
+                    None => eprintln!("{}", message),
+                }
+                return 1;
+            }
+        };
+        if let Some(mut pipe) = status_pipe {
+            let _ = pipe.write_all(&[0]);
+        }
+        info!("mounted on {:?}", args.mountpoint);
+
+        let (signalled, signal) = mpsc::channel();
+        thread::spawn(move || {
+            let _ = signalled.send(wait_for_signal(&signals));
+        });
+        loop {
+            match signal.recv_timeout(Duration::from_millis(200)) {
+                Ok(signal) => {
+                    info!("got signal {}; unmounting", signal);
+                    break;
+                }
+                Err(_) if session.guard.is_finished() => {
+                    info!("unmounted");
+                    break;
+                }
+                Err(_) => (),
+            }
+        }
+        drop(session);
+        0
+    }
+
+    fn mount<FS, F>(&self, args: &Args, make_fs: F) -> io::Result<fuser::BackgroundSession>
+        where FS: FilesystemMT + Send + Sync + 'static,
+              F: FnOnce(&Args) -> io::Result<FS>,
+    {
+        let fs = make_fs(args)?;
+        let mut options = args.mount_options.clone();
+        if !options.iter().any(|option| matches!(option, MountOption::FSName(_))) {
+            options.insert(0, MountOption::FSName(self.name.clone()));
+        }
+        let threads = args.threads.unwrap_or(self.threads);
+        crate::spawn_mount2(FuseMT::new(fs, threads), &args.mountpoint, &options)
+    }
+}
+
+#[test]
+fn test_parse_args() {
+    let cli = Cli::new("testfs")
+        .command(|command| command.arg(Arg::new("cache").long("cache").value_name("dir")));
+    let args = match cli.parse_args(
+        ["-v", "src", "-o", "ro", "-d", "/mnt", "-oallow_other", "--threads", "8", "--cache", "c"])
+    {
+        Ok(Command::Mount(args)) => args,
+        other => panic!("{:?}", other),
+    };
+    assert_eq!("src", args.backing);
+    assert_eq!(PathBuf::from("/mnt"), args.mountpoint);
+    assert_eq!(vec![MountOption::RO, MountOption::AllowOther], args.mount_options);
+    assert_eq!((true, 2, Some(8)), (args.foreground, args.verbosity, args.threads));
+    assert_eq!(Some("c"), args.matches.get_one::<String>("cache").map(String::as_str));
+
+    let args = match cli.parse_args(["--", "-src", "/mnt"]) {
+        Ok(Command::Mount(args)) => args,
+        other => panic!("{:?}", other),
+    };
+    assert_eq!("-src", args.backing);
+    assert_eq!((false, 0, None), (args.foreground, args.verbosity, args.threads));
+
+    assert_eq!(Command::Help, cli.parse_args(["src", "-h", "-x"]).unwrap());
+    assert_eq!(Command::Version, cli.parse_args(["--version"]).unwrap());
+    assert!(matches!(cli.parse_args(["src"]), Err(ArgsError::Usage(_))));
+    assert!(matches!(cli.parse_args(["a", "b", "c"]), Err(ArgsError::Usage(_))));
+    assert!(matches!(cli.parse_args(["a", "b", "-t", "x"]), Err(ArgsError::Usage(_))));
+    assert!(matches!(cli.parse_args(["a", "b", "-o", "ro,bogus"]),
+        Err(ArgsError::Option(OptionError::Unknown(ref o))) if o == "bogus"));
+}
@@ -0,0 +1,89 @@
+// Clock :: where FuseMT gets the time from.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+/// A source of the current time, for FuseMT's caches to expire entries by, and for setting
+/// timestamps to "now".
+///
+/// The default is `SystemClock`. Tests can use a `ManualClock` instead, with `FuseMT::clock`, to
+/// make time pass on demand rather than sleeping until cached entries expire.
+pub trait Clock: Debug + Send + Sync {
+    /// The current time, for measuring how long things have been kept.
+    fn now(&self) -> Instant;
+
+    /// The current time of day, for timestamps.
+    fn system_time(&self) -> SystemTime;
+}
+
+/// The real time.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A clock which only moves when it's told to.
+///
+/// It starts at the real time it's made at. Clones share the same time, so one can be given to
+/// FuseMT and another kept to advance it.
+#[derive(Clone, Debug)]
+pub struct ManualClock {
+    start: Instant,
+    start_time: SystemTime,
+    elapsed: Arc<Mutex<Duration>>,
+}
+
+impl ManualClock {
+    pub fn new() -> ManualClock {
+        ManualClock {
+            start: Instant::now(),
+            start_time: SystemTime::now(),
+            elapsed: Arc::new(Mutex::new(Duration::ZERO)),
+        }
+    }
+
+    /// Move the clock forward.
+    pub fn advance(&self, by: Duration) {
+        *self.elapsed.lock().unwrap() += by;
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> ManualClock {
+        ManualClock::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.start + *self.elapsed.lock().unwrap()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        self.start_time + *self.elapsed.lock().unwrap()
+    }
+}
+
+#[test]
+fn test_manual_clock() {
+    let clock = ManualClock::new();
+    let (start, start_time) = (clock.now(), clock.system_time());
+    std::thread::sleep(Duration::from_millis(5));
+    assert_eq!(start, clock.now());
+
+    clock.clone().advance(Duration::from_secs(60));
+    assert_eq!(Duration::from_secs(60), clock.now() - start);
+    assert_eq!(Duration::from_secs(60), clock.system_time().duration_since(start_time).unwrap());
+}
@@ -3,10 +3,14 @@
 // Copyright (c) 2017-2019 by William R. Fraser
 //
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::mem;
 use std::num::Wrapping;
+use std::sync::Arc;
 
-use super::DirectoryEntry;
+use super::{DirHandle, DirectoryEntry};
+use crate::state_dump::DirectoryState;
 
 /// Directory entry cache.
 ///
@@ -20,7 +24,8 @@ use super::DirectoryEntry;
 ///
 /// To do this, we need to cache the response from the filesystem, and we need to give FUSE our own
 /// file handle (the cache entry key) instead of the one the filesystem returned from opendir(), so
-/// we have to store that file handle as well.
+/// we have to store that file handle as well. If the filesystem returned a `DirHandle` object from
+/// opendir_handle() instead, we store that in the entry.
 #[derive(Debug)]
 pub struct DirectoryCache {
     next_key: Wrapping<u64>,
@@ -35,21 +40,41 @@ impl DirectoryCache {
         }
     }
 
-    /// Add a new entry with the given file handle and an un-populated directory entry list.
+    /// Get the number of open directories in the cache.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Add a new entry for the directory with the given inode, with the given file handle and an
+    /// un-populated directory entry list.
     /// This is intended to be called on opendir().
-    pub fn new_entry(&mut self, fh: u64) -> u64 {
+    pub fn new_entry(&mut self, ino: u64, fh: u64) -> u64 {
+        self.insert(DirectoryCacheEntry::new(ino, fh))
+    }
+
+    /// Add a new entry with the given directory handle object and an un-populated directory entry
+    /// list.
+    /// This is intended to be called on opendir() when the filesystem implements
+    /// opendir_handle().
+    pub fn new_handle_entry(&mut self, ino: u64, handle: Box<dyn DirHandle>) -> u64 {
+        let mut entry = DirectoryCacheEntry::new(ino, 0);
+        entry.handle = Some(handle);
+        self.insert(entry)
+    }
+
+    fn insert(&mut self, entry: DirectoryCacheEntry) -> u64 {
         let key = self.next_key.0;
-        self.entries.insert(key, DirectoryCacheEntry::new(fh));
+        self.entries.insert(key, entry);
         self.next_key += Wrapping(1);
         key
     }
 
-    /// Get the real file handle (the one set by the filesystem) for a given cache entry key.
+    /// Get a reference to the cache entry (file handle and entries) for the given key.
     /// Panics if there is no such key.
-    pub fn real_fh(&self, key: u64) -> u64 {
+    pub fn get(&self, key: u64) -> &DirectoryCacheEntry {
         self.entries.get(&key).unwrap_or_else(|| {
             panic!("no such directory cache key {}", key);
-        }).fh
+        })
     }
 
     /// Get a mutable reference to the cache entry (file handle and entries) for the given key.
@@ -60,25 +85,185 @@ impl DirectoryCache {
         })
     }
 
+    /// Store a newly fetched listing in the entry with the given key. If another handle on the
+    /// same directory has an identical listing cached, they share it, so that many readers of a
+    /// large directory don't each hold a copy of it.
+    /// Panics if there is no such key.
+    pub fn set_entries(&mut self, key: u64, entries: Vec<DirectoryEntry>)
+        -> Arc<Vec<DirectoryEntry>>
+    {
+        let ino = self.get(key).ino;
+        let shared = self.entries.values()
+            .filter(|entry| entry.ino == ino)
+            .filter_map(|entry| entry.entries.as_ref())
+            .find(|other| ***other == entries)
+            .cloned();
+        let entries = shared.unwrap_or_else(|| Arc::new(entries));
+        let entry = self.get_mut(key);
+        entry.entries = Some(entries.clone());
+        entry.finished = false;
+        entries
+    }
+
+    /// Mark the cached listings of the directory with the given inode (or of all directories, if
+    /// None) as out of date, so they get fetched again the next time they're read from the start.
+    pub fn mark_stale(&mut self, ino: Option<u64>) {
+        for entry in self.entries.values_mut() {
+            if ino.is_none() || ino == Some(entry.ino) {
+                entry.stale = true;
+            }
+        }
+    }
+
+    /// Get the approximate number of bytes of memory used by the cache.
+    pub fn memory_usage(&self) -> usize {
+        // Count listings shared between entries only once.
+        let mut seen = HashSet::new();
+        let listings: usize = self.entries.values()
+            .filter_map(|entry| entry.entries.as_ref())
+            .filter(|entries| seen.insert(Arc::as_ptr(entries)))
+            .map(|entries| entries.capacity() * mem::size_of::<DirectoryEntry>()
+                + entries.iter().map(|entry| entry.name.len()).sum::<usize>())
+            .sum();
+        self.entries.capacity() * mem::size_of::<(u64, DirectoryCacheEntry)>() + listings
+    }
+
+    /// Drop the cached listings of directories which have been read to the end. They're fetched
+    /// again if they're read again.
+    pub fn evict(&mut self) {
+        for entry in self.entries.values_mut().filter(|entry| entry.finished) {
+            entry.entries = None;
+            entry.finished = false;
+        }
+    }
+
+    /// List the open directories, in the order they were opened.
+    pub fn dump(&self) -> Vec<DirectoryState> {
+        let mut dirs: Vec<_> = self.entries.iter()
+            .map(|(&fh, entry)| DirectoryState {
+                fh,
+                ino: entry.ino,
+                entries: entry.entries.as_ref().map(|entries| entries.len()),
+                handle: entry.handle.is_some(),
+                stale: entry.stale,
+                finished: entry.finished,
+            })
+            .collect();
+        dirs.sort_by_key(|dir| dir.fh);
+        dirs
+    }
+
     /// Delete the cache entry with the given key.
     /// This is intended to be called on releasedir().
-    /// Panics if there is no such key.
+    /// Does nothing if there is no such key.
     pub fn delete(&mut self, key: u64) {
         self.entries.remove(&key);
     }
 }
 
-#[derive(Debug)]
+/// Whether every entry of a listing has an offset of its own, for listing it to resume at,
+/// instead of using their positions.
+pub fn has_offsets(entries: &[DirectoryEntry]) -> bool {
+    entries.iter().all(|entry| entry.offset.is_some())
+}
+
+/// Find the index to resume a listing whose entries have offsets at, given the offset asked for:
+/// right after the entry with that offset or, if there isn't one any more, at the first entry
+/// with a greater one.
+pub fn resume_index(entries: &[DirectoryEntry], offset: u64) -> usize {
+    match entries.iter().position(|entry| entry.offset == Some(offset)) {
+        Some(index) => index + 1,
+        None => entries.iter()
+            .position(|entry| entry.offset > Some(offset))
+            .unwrap_or(entries.len()),
+    }
+}
+
 pub struct DirectoryCacheEntry {
+    pub ino: u64,
     pub fh: u64,
-    pub entries: Option<Vec<DirectoryEntry>>,
+    pub handle: Option<Box<dyn DirHandle>>,
+    pub entries: Option<Arc<Vec<DirectoryEntry>>>,
+    pub stale: bool,
+    /// Whether the listing has been read to the end.
+    pub finished: bool,
 }
 
 impl DirectoryCacheEntry {
-    pub fn new(fh: u64) -> DirectoryCacheEntry {
+    pub fn new(ino: u64, fh: u64) -> DirectoryCacheEntry {
         DirectoryCacheEntry {
+            ino,
             fh,
+            handle: None,
             entries: None,
+            stale: false,
+            finished: false,
         }
     }
 }
+
+impl fmt::Debug for DirectoryCacheEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DirectoryCacheEntry")
+            .field("ino", &self.ino)
+            .field("fh", &self.fh)
+            .field("handle", &self.handle.as_ref().map(|_| "DirHandle"))
+            .field("entries", &self.entries)
+            .field("stale", &self.stale)
+            .field("finished", &self.finished)
+            .finish()
+    }
+}
+
+#[test]
+fn test_shared_listings() {
+    let listing = |names: &[&str]| names.iter()
+        .map(|name| DirectoryEntry {
+            name: (*name).into(),
+            kind: crate::FileType::RegularFile,
+            offset: None,
+            attr: None,
+        })
+        .collect::<Vec<_>>();
+    let mut cache = DirectoryCache::new();
+    let a = cache.new_entry(5, 0);
+    let b = cache.new_entry(5, 0);
+    let c = cache.new_entry(5, 0);
+    let other_dir = cache.new_entry(6, 0);
+
+    let first = cache.set_entries(a, listing(&["x", "y"]));
+    let single = cache.memory_usage();
+
+    // The same listing of the same directory is shared...
+    assert!(Arc::ptr_eq(&first, &cache.set_entries(b, listing(&["x", "y"]))));
+    assert_eq!(single, cache.memory_usage());
+
+    // ...but not a different listing, or the same one of a different directory.
+    assert!(!Arc::ptr_eq(&first, &cache.set_entries(c, listing(&["x"]))));
+    assert!(!Arc::ptr_eq(&first, &cache.set_entries(other_dir, listing(&["x", "y"]))));
+}
+
+#[test]
+fn test_resume_index() {
+    let listing = |offsets: &[u64]| offsets.iter()
+        .map(|&offset| DirectoryEntry {
+            name: offset.to_string().into(),
+            kind: crate::FileType::RegularFile,
+            offset: Some(offset),
+            attr: None,
+        })
+        .collect::<Vec<_>>();
+    let entries = listing(&[10, 20, 30]);
+    assert!(has_offsets(&entries));
+    assert_eq!(2, resume_index(&entries, 20));
+    assert_eq!(3, resume_index(&entries, 30));
+
+    // If the entry left off at was removed, pick up at the one after it.
+    let entries = listing(&[10, 30]);
+    assert_eq!(1, resume_index(&entries, 20));
+    assert_eq!(2, resume_index(&entries, 40));
+
+    let mut entries = listing(&[10]);
+    entries[0].offset = None;
+    assert!(!has_offsets(&entries));
+}
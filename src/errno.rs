@@ -0,0 +1,67 @@
+// Errno :: turning I/O errors into the error numbers FilesystemMT calls return.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+use std::io;
+
+/// Get the error number for an I/O error: the OS error number it was made from, if it was, or
+/// else one for its kind.
+///
+/// Unlike `e.raw_os_error().unwrap()`, this doesn't panic on errors which didn't come from a
+/// system call, like those made with `io::Error::new` or returned by `read_exact` at the end of a
+/// file. Those without a matching error number are `EIO`.
+pub fn io_errno(e: &io::Error) -> libc::c_int {
+    if let Some(errno) = e.raw_os_error() {
+        return errno;
+    }
+    match e.kind() {
+        io::ErrorKind::NotFound => libc::ENOENT,
+        io::ErrorKind::PermissionDenied => libc::EACCES,
+        io::ErrorKind::AlreadyExists => libc::EEXIST,
+        io::ErrorKind::InvalidInput => libc::EINVAL,
+        io::ErrorKind::WouldBlock => libc::EAGAIN,
+        io::ErrorKind::TimedOut => libc::ETIMEDOUT,
+        io::ErrorKind::Interrupted => libc::EINTR,
+        io::ErrorKind::ConnectionRefused => libc::ECONNREFUSED,
+        io::ErrorKind::ConnectionReset => libc::ECONNRESET,
+        io::ErrorKind::ConnectionAborted => libc::ECONNABORTED,
+        io::ErrorKind::NotConnected => libc::ENOTCONN,
+        io::ErrorKind::BrokenPipe => libc::EPIPE,
+        io::ErrorKind::Unsupported => libc::ENOTSUP,
+        io::ErrorKind::OutOfMemory => libc::ENOMEM,
+        _ => libc::EIO,
+    }
+}
+
+/// Lets `FilesystemMT` methods use `?` on I/O results, by turning their errors into error numbers
+/// with `io_errno`:
+///
+/// ```ignore
+/// fn fsync(&self, _req: RequestInfo, _path: &FsPath, fh: u64, _datasync: bool) -> ResultEmpty {
+///     self.file(fh)?.sync_all().errno()?;
+///     Ok(())
+/// }
+/// ```
+pub trait IoResultExt<T> {
+    /// Replace the error with its error number.
+    fn errno(self) -> Result<T, libc::c_int>;
+}
+
+impl<T> IoResultExt<T> for io::Result<T> {
+    fn errno(self) -> Result<T, libc::c_int> {
+        self.map_err(|e| io_errno(&e))
+    }
+}
+
+#[test]
+fn test_io_errno() {
+    assert_eq!(libc::ENOSPC, io_errno(&io::Error::from_raw_os_error(libc::ENOSPC)));
+    assert_eq!(libc::ENOENT, io_errno(&io::ErrorKind::NotFound.into()));
+    assert_eq!(libc::EIO, io_errno(&io::Error::other("made up")));
+    assert_eq!(libc::EIO, io_errno(&io::ErrorKind::UnexpectedEof.into()));
+
+    let read: io::Result<u32> = Err(io::ErrorKind::PermissionDenied.into());
+    assert_eq!(Err(libc::EACCES), read.errno());
+    assert_eq!(Ok(1), io::Result::Ok(1).errno());
+}
@@ -0,0 +1,149 @@
+// File Limit :: capping how many calls run at once on each file.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use crate::worker_pool::WorkerPool;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Limits the number of calls running at once on each file (by inode, so across all of its open
+/// handles), so that a single busy file can't take up every worker thread while calls on other
+/// files wait behind it.
+///
+/// Calls over the limit aren't handed to the thread pool at all, and the thread which dispatches
+/// requests doesn't wait for them. They're queued here, and each is started when one of the calls
+/// running on its file finishes, in the order they were received.
+pub struct FileLimit {
+    max: usize,
+    files: Mutex<HashMap<u64, File>>,
+}
+
+#[derive(Default)]
+struct File {
+    running: usize,
+    queued: VecDeque<(Option<WorkerPool>, Job)>,
+}
+
+impl fmt::Debug for FileLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FileLimit")
+            .field("max", &self.max)
+            .field("queued", &self.queued())
+            .finish()
+    }
+}
+
+impl FileLimit {
+    pub fn new(max: usize) -> FileLimit {
+        FileLimit {
+            max: max.max(1),
+            files: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Number of calls waiting for others on their files to finish.
+    pub fn queued(&self) -> usize {
+        self.files.lock().unwrap().values().map(|file| file.queued.len()).sum()
+    }
+
+    /// Run a call on the given file: on the given threads, or right here if there are none, once
+    /// there are fewer than the limit already running on it.
+    pub fn run(self: &Arc<Self>, ino: u64, threads: Option<WorkerPool>, job: Job) {
+        {
+            let mut files = self.files.lock().unwrap();
+            let file = files.entry(ino).or_default();
+            if file.running >= self.max {
+                file.queued.push_back((threads, job));
+                return;
+            }
+            file.running += 1;
+        }
+        self.start(ino, threads, job);
+    }
+
+    fn start(self: &Arc<Self>, ino: u64, threads: Option<WorkerPool>, job: Job) {
+        // Finish even if the call panics, so its file isn't stuck at the limit.
+        let running = Running { limit: self.clone(), ino };
+        let f = move || {
+            job();
+            drop(running);
+        };
+        match threads {
+            Some(threads) => threads.execute(f),
+            None => f(),
+        }
+    }
+
+    /// Start the next call queued on a file, now that one running on it finished.
+    fn finish(self: &Arc<Self>, ino: u64) {
+        let next = {
+            let mut files = self.files.lock().unwrap();
+            let file = files.get_mut(&ino).expect("finished a call on a file with none running");
+            let next = file.queued.pop_front();
+            if next.is_none() {
+                file.running -= 1;
+                if file.running == 0 {
+                    files.remove(&ino);
+                }
+            }
+            next
+        };
+        if let Some((threads, job)) = next {
+            self.start(ino, threads, job);
+        }
+    }
+}
+
+struct Running {
+    limit: Arc<FileLimit>,
+    ino: u64,
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.limit.finish(self.ino);
+    }
+}
+
+#[test]
+fn test_file_limit() {
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    let limit = Arc::new(FileLimit::new(2));
+    let threads = WorkerPool::new(4);
+    let (started_tx, started) = mpsc::channel();
+    let mut releases = vec![];
+    for (i, ino) in [1, 1, 1, 2].iter().enumerate() {
+        let (release_tx, release) = mpsc::channel::<()>();
+        releases.push(release_tx);
+        let started_tx = started_tx.clone();
+        limit.run(*ino, Some(threads.clone()), Box::new(move || {
+            started_tx.send(i).unwrap();
+            release.recv().unwrap();
+        }));
+    }
+    assert_eq!(1, limit.queued());
+
+    // The third call on file 1 waits, but the one on file 2 doesn't wait behind it.
+    let mut first = (0 .. 3).map(|_| started.recv().unwrap()).collect::<Vec<_>>();
+    first.sort();
+    assert_eq!(vec![0, 1, 3], first);
+    assert!(started.recv_timeout(Duration::from_millis(50)).is_err());
+
+    // Finishing a call on file 2 doesn't start it; finishing one on file 1 does.
+    releases[3].send(()).unwrap();
+    assert!(started.recv_timeout(Duration::from_millis(50)).is_err());
+    releases[0].send(()).unwrap();
+    assert_eq!(2, started.recv().unwrap());
+
+    releases[1].send(()).unwrap();
+    releases[2].send(()).unwrap();
+    threads.join();
+    assert!(limit.files.lock().unwrap().is_empty());
+}
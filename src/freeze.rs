@@ -0,0 +1,129 @@
+// Freeze :: pausing a mounted filesystem while its backend is worked on.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+/// A handle for pausing FuseMT while it's mounted, for example to take a consistent snapshot of
+/// the filesystem's backend, or do maintenance on it.
+///
+/// Get one from `FuseMT::freezer` before mounting; it can be cloned and used from any thread.
+/// While frozen, FuseMT doesn't start any new calls to the filesystem: requests from the kernel
+/// wait until it's thawed, as do periodic syncs (see `FuseMT::periodic_sync`). Programs using the
+/// filesystem just see their calls take longer.
+///
+/// `freeze` must not be called from inside a call to the filesystem, because it would wait for
+/// that call to finish.
+#[derive(Clone, Debug, Default)]
+pub struct Freezer {
+    inner: Arc<Shared>,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    frozen: bool,
+    in_flight: usize,
+}
+
+impl Freezer {
+    pub(crate) fn new() -> Freezer {
+        Freezer::default()
+    }
+
+    /// Stop starting new calls, and wait for the ones already started, including any on the
+    /// worker threads, to finish.
+    pub fn freeze(&self) {
+        let mut state = self.lock();
+        state.frozen = true;
+        while state.in_flight > 0 {
+            state = self.inner.changed.wait(state).unwrap();
+        }
+        debug!("frozen");
+    }
+
+    /// Let calls start again.
+    pub fn thaw(&self) {
+        self.lock().frozen = false;
+        self.inner.changed.notify_all();
+        debug!("thawed");
+    }
+
+    /// Whether the filesystem is frozen.
+    pub fn is_frozen(&self) -> bool {
+        self.lock().frozen
+    }
+
+    /// Wait until the filesystem isn't frozen, then start a call, which lasts until the returned
+    /// guard is dropped.
+    pub(crate) fn enter(&self) -> InFlight {
+        let mut state = self.lock();
+        while state.frozen {
+            state = self.inner.changed.wait(state).unwrap();
+        }
+        state.in_flight += 1;
+        InFlight(self.inner.clone())
+    }
+
+    /// Start a call which is part of one that's already started, so it goes ahead even if the
+    /// filesystem was frozen in the meantime.
+    pub(crate) fn enter_admitted(&self) -> InFlight {
+        self.lock().in_flight += 1;
+        InFlight(self.inner.clone())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.inner.state.lock().unwrap()
+    }
+}
+
+/// A call in progress, which `Freezer::freeze` waits for.
+#[derive(Debug)]
+pub(crate) struct InFlight(Arc<Shared>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.state.lock().unwrap().in_flight -= 1;
+        self.0.changed.notify_all();
+    }
+}
+
+#[test]
+fn test_freezer() {
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    let freezer = Freezer::new();
+    let call = freezer.enter();
+
+    // Freezing waits for the call in progress, and the part of it on another thread.
+    let (tx, rx) = mpsc::channel();
+    let worker = freezer.enter_admitted();
+    let f = freezer.clone();
+    let freezing = thread::spawn(move || {
+        f.freeze();
+        tx.send(()).unwrap();
+    });
+    drop(call);
+    assert!(rx.recv_timeout(Duration::from_millis(50)).is_err());
+    drop(worker);
+    rx.recv().unwrap();
+    freezing.join().unwrap();
+    assert!(freezer.is_frozen());
+
+    // New calls wait for it to thaw.
+    let f = freezer.clone();
+    let entering = thread::spawn(move || drop(f.enter()));
+    thread::sleep(Duration::from_millis(20));
+    assert!(!entering.is_finished());
+    freezer.thaw();
+    entering.join().unwrap();
+    assert!(!freezer.is_frozen());
+}
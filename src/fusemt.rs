@@ -4,17 +4,42 @@
 // Copyright (c) 2016-2022 by William R. Fraser
 //
 
-use std::ffi::OsStr;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::ffi::{OsStr, OsString};
+use std::mem;
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
-use std::time::SystemTime;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
 
-use fuser::TimeOrNow;
-use threadpool::ThreadPool;
+use fuser::{MountOption, TimeOrNow};
 
+use crate::attr_prefetch::*;
+use crate::clock::*;
 use crate::directory_cache::*;
+use crate::file_limit::*;
+use crate::freeze::*;
+use crate::handle_table::*;
+use crate::hotspots::*;
 use crate::inode_table::*;
+use crate::invalidation::*;
+use crate::memory::*;
+use crate::periodic_sync::*;
+use crate::open_files::*;
+use crate::pool_stats::*;
+use crate::prefetch::*;
+use crate::readiness::*;
+use crate::reentrancy::*;
+use crate::responder::*;
+use crate::revalidation::*;
+use crate::silly_rename::*;
+use crate::state_dump::*;
+use crate::stats::*;
+use crate::tunables::*;
 use crate::types::*;
+use crate::worker_pool::*;
+use crate::write_barrier::*;
+use crate::xattr_cache::*;
 
 trait IntoRequestInfo {
     fn info(&self) -> RequestInfo;
@@ -27,6 +52,9 @@ impl<'a> IntoRequestInfo for fuser::Request<'a> {
             uid: self.uid(),
             gid: self.gid(),
             pid: self.pid(),
+            node: None,
+            umask: None,
+            lock_owner: None,
         }
     }
 }
@@ -52,54 +80,1098 @@ fn fuse_fileattr(attr: FileAttr, ino: u64) -> fuser::FileAttr {
 }
 
 trait TimeOrNowExt {
-    fn time(self) -> SystemTime;
+    fn time(self, clock: &dyn Clock) -> SystemTime;
 }
 
 impl TimeOrNowExt for TimeOrNow {
-    fn time(self) -> SystemTime {
+    fn time(self, clock: &dyn Clock) -> SystemTime {
         match self {
             TimeOrNow::SpecificTime(t) => t,
-            TimeOrNow::Now => SystemTime::now(),
+            TimeOrNow::Now => clock.system_time(),
         }
     }
 }
 
+/// Listings sent through `readdir_deferred` after it returned, with the keys of the directory cache
+/// entries to keep them in.
+type DeferredListings = Arc<Mutex<Vec<(u64, Vec<DirectoryEntry>)>>>;
+
+/// The version of the FUSE protocol spoken by `fuser` with the ABI feature FuseMT enables.
+const FUSE_PROTOCOL_VERSION: (u32, u32) = (7, 28);
+
+/// Get the capabilities the kernel offered. `fuser` doesn't say what they are, but when asked for
+/// some the kernel doesn't offer, it says which those are. No kernel offers every flag (the top
+/// one is reserved), so asking for all of them tells all.
+///
+/// The asking is done on a copy of the config, so that the one replied to the kernel is left as
+/// it was whatever the answer. That the copy is sound relies on `KernelConfig` being plain data,
+/// which is checked below; check the rest still holds before moving to a newer fuser.
+fn kernel_capabilities(config: &fuser::KernelConfig) -> u32 {
+    const _: () = assert!(!mem::needs_drop::<fuser::KernelConfig>());
+    // `KernelConfig` only holds numbers, and owns nothing which could be freed twice.
+    let mut probe = unsafe { std::ptr::read(config) };
+    match probe.add_capabilities(!0) {
+        Err(missing) => !missing,
+        Ok(()) => !0,
+    }
+}
+
+/// The `flock` operation the kernel is asking for with a lock of the given type.
+fn flock_operation(typ: i32, sleep: bool) -> i32 {
+    let operation = match typ {
+        libc::F_RDLCK => libc::LOCK_SH,
+        libc::F_WRLCK => libc::LOCK_EX,
+        _ => libc::LOCK_UN,
+    };
+    if sleep {
+        operation
+    } else {
+        operation | libc::LOCK_NB
+    }
+}
+
+/// Names of the entries added to the start of listings with `synthesize_dot_entries`.
+const DOT_ENTRIES: [&str; 2] = [".", ".."];
+
+fn dot_entry(name: &str) -> DirectoryEntry {
+    DirectoryEntry {
+        name: OsString::from(name),
+        kind: crate::FileType::Directory,
+        offset: None,
+        attr: None,
+    }
+}
+
+/// Attributes to send for the dot entries of a readdirplus listing. The kernel doesn't cache
+/// these, and only uses their type.
+fn dot_attr() -> FileAttr {
+    FileAttr {
+        ino: 0,
+        size: 0,
+        blocks: 0,
+        atime: SystemTime::UNIX_EPOCH,
+        mtime: SystemTime::UNIX_EPOCH,
+        ctime: SystemTime::UNIX_EPOCH,
+        crtime: SystemTime::UNIX_EPOCH,
+        kind: crate::FileType::Directory,
+        perm: 0,
+        nlink: 0,
+        uid: 0,
+        gid: 0,
+        rdev: 0,
+        flags: 0,
+    }
+}
+
+/// A directory listing, as found for a readdir call by `FuseMT::listing`.
+enum Listing {
+    /// The listing was cached already.
+    Cached(Arc<Vec<DirectoryEntry>>),
+    /// The listing was just fetched from the filesystem, and is cached now.
+    Fetched(Arc<Vec<DirectoryEntry>>),
+    /// The filesystem will send the listing later.
+    Later(Pending<Vec<DirectoryEntry>>),
+}
+
+/// Put FuseMT's own dot entries, if it adds any, at the start of a listing, in place of the
+/// filesystem's.
+fn add_dot_entries(entries: &mut Vec<DirectoryEntry>, dots: u64) {
+    if dots > 0 {
+        entries.retain(|entry| entry.name != "." && entry.name != "..");
+        entries.splice(0 .. 0, DOT_ENTRIES.iter().map(|name| dot_entry(name)));
+    }
+}
+
+/// Make sure the filesystem's answer to a getxattr or listxattr call is one the kernel will take:
+/// data given for a size probe is turned into its size, and data too big for the buffer asked for
+/// into `ERANGE`.
+fn fit_xattr(result: ResultXattr, size: u32) -> ResultXattr {
+    match result {
+        Ok(Xattr::Data(data)) => Xattr::sized(data, size),
+        other => other,
+    }
+}
+
+/// Add the entries of a listing to a readdir reply, from the given offset on, with `add`, which
+/// is given each entry and the offset of the next one, until it says the reply is full. Gives
+/// whether the listing had already been read to the end.
+fn add_entries(entries: &[DirectoryEntry], offset: u64, dots: u64,
+               mut add: impl FnMut(&DirectoryEntry, u64) -> bool) -> bool {
+    op_debug!("directory has {} entries", entries.len());
+    let with_offsets = has_offsets(&entries[dots as usize ..]);
+    let start = start_index(entries, offset, dots);
+    let finished = start >= entries.len();
+
+    for (index, entry) in entries.iter().enumerate().skip(start) {
+        op_debug!("readdir: adding entry #{}, {:?}", index, entry.name);
+
+        let next = match entry.offset {
+            Some(next) if with_offsets && index >= dots as usize => next + dots,
+            _ => index as u64 + 1,
+        };
+        if add(entry, next) {
+            op_debug!("readdir: reply buffer is full");
+            break;
+        }
+    }
+    finished
+}
+
+/// Get the index of the entry a readdir call from the given offset starts at, which is past the
+/// end if the listing has been read to the end.
+fn start_index(entries: &[DirectoryEntry], offset: u64, dots: u64) -> usize {
+    // As with `readdir_at`, the filesystem's offsets come after those of the dot entries.
+    if offset > dots && has_offsets(&entries[dots as usize ..]) {
+        dots as usize + resume_index(&entries[dots as usize ..], offset - dots)
+    } else {
+        usize::try_from(offset).unwrap_or(usize::MAX)
+    }
+}
+
+/// Add entries from the given offset on to a readdirplus reply, getting the attributes of those
+/// listed without them from the filesystem.
+#[allow(clippy::too_many_arguments)]
+fn add_entries_plus<T: FilesystemMT>(target: &T, inodes: &Mutex<InodeTable>, req: RequestInfo,
+                                     path: &FsPath, ino: u64, parent: u64,
+                                     entries: &[DirectoryEntry], offset: u64, dots: u64,
+                                     reply: &mut fuser::ReplyDirectoryPlus) {
+    add_entries(entries, offset, dots, |entry, next| {
+        add_entry_plus(target, inodes, req, path, ino, parent, entry, next, reply)
+    });
+}
+
+/// Add an entry to a readdirplus reply, getting its attributes from the filesystem (with
+/// `getattr_deferred`, as for a lookup) if it was listed without them. Entries are only kept in the inode table once the reply has taken them,
+/// since the kernel counts each one it's sent as a lookup. Returns true if the reply is full.
+#[allow(clippy::too_many_arguments)]
+fn add_entry_plus<T: FilesystemMT>(target: &T, inodes: &Mutex<InodeTable>, req: RequestInfo,
+                                   path: &FsPath, ino: u64, parent: u64, entry: &DirectoryEntry,
+                                   next: u64, reply: &mut fuser::ReplyDirectoryPlus) -> bool {
+    if entry.name == "." || entry.name == ".." {
+        let dot_ino = if entry.name == "." { ino } else { parent };
+        return reply.add(dot_ino, next as i64, &entry.name, &Duration::ZERO,
+            &fuse_fileattr(dot_attr(), dot_ino), 0);
+    }
+    let (ttl, attr) = match entry.attr {
+        Some(attr) => attr,
+        None => {
+            let path = FsPath::from(path.join(&entry.name));
+            match Responder::wait(|attr| target.getattr_deferred(req, &path, None, attr)) {
+                Ok(attr) => attr,
+                Err(e) => {
+                    // It's likely gone since it was listed; leave it out.
+                    op_debug!("readdirplus: getattr on {:?} failed: {}", entry.name, e);
+                    return false;
+                }
+            }
+        }
+    };
+    let mut inodes = inodes.lock().unwrap();
+    let (child, generation) = inodes.add_or_get(ino, &entry.name);
+    let full = reply.add(child, next as i64, &entry.name, &ttl, &fuse_fileattr(attr, child),
+        generation);
+    if full {
+        // Drop it again if it was only added for this.
+        inodes.forget(child, 0);
+    } else {
+        inodes.lookup(child);
+    }
+    full
+}
+
+/// How many bytes of entries to list for each readdirplus call on a filesystem which lists a
+/// page at a time. This is the smallest buffer the kernel asks to have filled; entries which
+/// don't fit in it are left out, and listed again from there on the next call.
+const READDIRPLUS_PAGE: usize = 4096;
+
+/// The space an entry takes in a readdirplus reply: a `fuse_entry_out` and a `fuse_dirent`,
+/// followed by the name, padded to 8 bytes.
+fn direntplus_size(name: &OsStr) -> usize {
+    (128 + 24 + name.len() + 7) & !7
+}
+
+/// Read from a file into a new buffer.
+fn read_to_vec<T: FilesystemMT>(target: &T, req: RequestInfo, path: &FsPath,
+                                handle: &FileHandleEntry, offset: u64, size: u32) -> ResultData {
+    match handle {
+        FileHandleEntry::Raw(fh) => {
+            let mut result = Err(libc::EIO);
+            target.read(req, path, *fh, offset, size, |data| {
+                result = data.map(<[u8]>::to_vec);
+                CallbackResult {
+                    _private: std::marker::PhantomData {},
+                }
+            });
+            result
+        }
+        FileHandleEntry::Managed(handle) => handle.read(req, offset, size),
+    }
+}
+
+/// The adapter between a `FilesystemMT` and FUSE: this implements `fuser::Filesystem`, keeping
+/// track of inodes and handing the calls off to the `FilesystemMT` on its worker threads.
+///
+/// It is usually mounted with `mount` or one of its siblings, but since it's an ordinary
+/// `fuser::Filesystem`, it can just as well be mounted with `fuser::Session` directly, or wrapped
+/// in another `fuser::Filesystem` which handles some calls itself and passes the rest on. The
+/// `fuser` crate is re-exported as `fuse_mt::fuser` so that such code uses the same version.
 #[derive(Debug)]
 pub struct FuseMT<T> {
     target: Arc<T>,
-    inodes: InodeTable,
-    threads: Option<ThreadPool>,
+    inodes: Arc<Mutex<InodeTable>>,
+    threads: Option<WorkerPool>,
     num_threads: usize,
     directory_cache: DirectoryCache,
+    handles: HandleTable,
+    open_files: OpenFiles,
+    open_handle_supported: bool,
+    create_handle_supported: bool,
+    opendir_handle_supported: bool,
+    readdir_at_supported: bool,
+    list_at_supported: bool,
+    invalidator: Invalidator,
+    prefetcher: Option<Arc<Prefetcher>>,
+    stats: Stats,
+    pool_stats: PoolStats,
+    tunables: Tunables,
+    silly_rename: Option<SillyRename>,
+    write_barrier: Option<Arc<WriteBarrier>>,
+    file_limit: Option<Arc<FileLimit>>,
+    hotspots: Option<Arc<Hotspots>>,
+    deferred_listings: DeferredListings,
+    lanes: HashMap<Lane, LanePool>,
+    write_buffers: WriteBuffers,
+    memory_limit: Option<MemoryLimit>,
+    path_log: Option<Arc<PathLog>>,
+    xattr_cache: Option<XattrCache>,
+    periodic_sync: Option<PeriodicSync>,
+    freezer: Freezer,
+    dump_on_signal: Option<(libc::c_int, PathBuf)>,
+    reentrancy: Reentrancy,
+    attr_prefetch: Option<Arc<AttrPrefetch>>,
+    dot_entries: bool,
+    max_write: Option<u32>,
+    lock_mode: LockMode,
+    readdirplus: bool,
+    mount_options: Vec<MountOption>,
+    ready: ReadyNotifier,
+    clock: Arc<dyn Clock>,
+    /// Buffer the paths of entries being looked up are built in, kept so that lookups don't
+    /// need to allocate one.
+    lookup_path: PathBuf,
+}
+
+/// The worker threads of a lane other than the default one, started when first needed.
+#[derive(Debug)]
+struct LanePool {
+    num_threads: usize,
+    threads: Option<WorkerPool>,
+}
+
+impl LanePool {
+    /// The lane's threads, or `None` if it has none and its calls run on FuseMT's own thread.
+    fn threads(&mut self) -> Option<WorkerPool> {
+        if self.num_threads == 0 {
+            return None;
+        }
+        let num_threads = self.num_threads;
+        Some(self.threads.get_or_insert_with(|| WorkerPool::new(num_threads)).clone())
+    }
+}
+
+/// A way to queue more work on a lane's threads from a worker thread, for calls which lead to
+/// other calls on a different lane, or from wherever a deferred reply is sent.
+struct Spawner {
+    threads: WorkerPool,
+    operation: Option<Operation>,
+    freezer: Freezer,
+    reentrancy: Reentrancy,
+    pool_stats: PoolStats,
+}
+
+impl Spawner {
+    fn run<F: FnOnce() + Send + 'static>(self, f: F) {
+        let op = self.freezer.enter_admitted();
+        let reentrancy = self.reentrancy;
+        let queued = self.pool_stats.queue(self.operation);
+        self.threads.execute(move || {
+            let running = queued.start();
+            reentrancy.register_thread();
+            f();
+            drop(running);
+            drop(op);
+        });
+    }
 }
 
 impl<T: FilesystemMT + Sync + Send + 'static> FuseMT<T> {
+    /// Make a FUSE filesystem out of the given `FilesystemMT`, using `num_threads` worker threads
+    /// for I/O calls. If `num_threads` is zero, all calls are made on the FUSE session's thread.
     pub fn new(target_fs: T, num_threads: usize) -> FuseMT<T> {
+        FuseMT::from_arc(Arc::new(target_fs), num_threads)
+    }
+
+    /// Like `new`, but for a `FilesystemMT` which is shared with other code, for example to be
+    /// mounted more than once, or to be inspected while it's mounted.
+    pub fn from_arc(target_fs: Arc<T>, num_threads: usize) -> FuseMT<T> {
         FuseMT {
-            target: Arc::new(target_fs),
-            inodes: InodeTable::new(),
+            target: target_fs,
+            inodes: Arc::new(Mutex::new(InodeTable::new())),
             threads: None,
             num_threads,
             directory_cache: DirectoryCache::new(),
+            handles: HandleTable::new(),
+            open_handle_supported: true,
+            create_handle_supported: true,
+            opendir_handle_supported: true,
+            readdir_at_supported: true,
+            list_at_supported: true,
+            invalidator: Invalidator::new(),
+            prefetcher: None,
+            stats: Stats::new(),
+            pool_stats: PoolStats::new(),
+            tunables: Tunables::new(),
+            open_files: OpenFiles::new(),
+            silly_rename: None,
+            write_barrier: None,
+            file_limit: None,
+            hotspots: None,
+            deferred_listings: Arc::new(Mutex::new(vec![])),
+            lanes: HashMap::new(),
+            write_buffers: WriteBuffers::default(),
+            memory_limit: None,
+            path_log: None,
+            xattr_cache: None,
+            periodic_sync: None,
+            freezer: Freezer::new(),
+            dump_on_signal: None,
+            reentrancy: Reentrancy::new(),
+            attr_prefetch: None,
+            dot_entries: false,
+            max_write: None,
+            lock_mode: LockMode::Local,
+            readdirplus: false,
+            mount_options: vec![],
+            ready: ReadyNotifier::default(),
+            clock: Arc::new(SystemClock),
+            lookup_path: PathBuf::new(),
         }
     }
 
-    fn threadpool_run<F: FnOnce() + Send + 'static>(&mut self, f: F) {
-        if self.num_threads == 0 {
-            f()
+    /// Enable prefetching. When a file is read sequentially, FuseMT will read up to `window`
+    /// bytes ahead of the kernel's requests, on the worker thread that served the last read, and
+    /// serve the following reads from memory. This can hide latency in filesystems with slow
+    /// backends, at the cost of some wasted reads for readers which stop early.
+    ///
+    /// Prefetched data for a file is discarded whenever it's written to or truncated through the
+    /// mount, but changes made by other means are not noticed.
+    pub fn prefetch(mut self, window: u32) -> FuseMT<T> {
+        self.prefetcher = Some(Arc::new(Prefetcher::new(window)));
+        self
+    }
+
+    /// Enable silly renaming. Files which are unlinked, or replaced by a rename, while they're still
+    /// open get renamed to a hidden name (`.fuse_hidden` followed by some digits) in the same
+    /// directory instead, and are only deleted once the last handle to them is released. This is
+    /// for filesystems whose backends can't keep deleted files readable while they're open.
+    ///
+    /// The hidden files show up in directory listings until they're deleted.
+    pub fn silly_rename(mut self) -> FuseMT<T> {
+        self.silly_rename = Some(SillyRename::new());
+        self
+    }
+
+    /// Make flush, fsync, and release calls on an open file wait until all writes to it which were
+    /// received before them have finished. Without this, writes and syncs on the same file can run
+    /// at the same time on different threads, and a sync can finish before an earlier write has
+    /// even reached the filesystem.
+    pub fn sync_barriers(mut self) -> FuseMT<T> {
+        self.write_barrier = Some(Arc::new(WriteBarrier::new()));
+        self
+    }
+
+    /// Run at most `max` reads, writes, and other calls on the data of any one file at once, so
+    /// that a busy file can't take up every worker thread while calls on other files wait behind
+    /// it. Calls on the same file over the limit are queued, without holding up the dispatch of
+    /// other requests, and started in order as earlier ones finish.
+    ///
+    /// The limit is per file, across all the handles it's open with. Metadata calls aren't limited.
+    pub fn max_calls_per_file(mut self, max: usize) -> FuseMT<T> {
+        self.file_limit = Some(Arc::new(FileLimit::new(max)));
+        self
+    }
+
+    /// The `FilesystemMT` this is serving.
+    pub fn target(&self) -> &Arc<T> {
+        &self.target
+    }
+
+    /// Give calls which `FilesystemMT::classify` puts in `lane` their own `num_threads` worker
+    /// threads, so they don't queue up behind calls in other lanes, or hold them up. Giving
+    /// `Lane::DEFAULT` threads this way is the same as passing them to `new`.
+    pub fn lane(mut self, lane: Lane, num_threads: usize) -> FuseMT<T> {
+        if lane == Lane::DEFAULT {
+            self.num_threads = num_threads;
         } else {
-            if self.threads.is_none() {
-                debug!("initializing threadpool with {} threads", self.num_threads);
-                self.threads = Some(ThreadPool::new(self.num_threads));
+            self.lanes.insert(lane, LanePool { num_threads, threads: None });
+        }
+        self
+    }
+
+    /// Limit how much memory FuseMT's caches may use. When the memory used by the inode table,
+    /// directory listings, prefetched data, and data waiting to be written adds up to more than
+    /// `bytes`, FuseMT discards prefetched data, the listings of directories which have been read
+    /// to the end, and the full paths kept in the inode table. The rest can't be freed on FuseMT's
+    /// own; if the limit is still exceeded, a warning is logged.
+    ///
+    /// Memory use is checked at most once a second, so it can go over the limit in between. The
+    /// amount used by each part is reported in the stats (see `STATS_XATTR`) either way.
+    pub fn memory_limit(mut self, bytes: usize) -> FuseMT<T> {
+        self.memory_limit = Some(MemoryLimit::new(bytes));
+        self
+    }
+
+    /// Re-check the paths of calls on the worker threads just before making them. A file can be
+    /// renamed or deleted after a call on it was queued but before it runs; without this, the
+    /// call is made with the file's old path, which may by then name a different file. With it,
+    /// the call is made with the file's new path, or fails with `ESTALE` if the file was deleted
+    /// or replaced.
+    ///
+    /// Filesystems which find files by their handles rather than their paths don't need this, and
+    /// shouldn't use it: it makes calls on files deleted while they're open fail. Calls on
+    /// `FileHandle` objects are not affected either way.
+    pub fn revalidate_paths(mut self) -> FuseMT<T> {
+        self.path_log = Some(Arc::new(PathLog::new()));
+        self
+    }
+
+    /// Fetch whole extended attributes (and lists of them) when the kernel only asks for their
+    /// size. Programs reading them ask for the size first and then for the value, and filesystems
+    /// usually have to compute the value to answer either; with this, the value is kept for a
+    /// moment and the second call is answered from it. The filesystem sees the first call with
+    /// a size of 64 KiB instead of zero, and doesn't see the second one at all.
+    pub fn cache_xattr_probes(mut self) -> FuseMT<T> {
+        self.xattr_cache = Some(XattrCache::new());
+        self
+    }
+
+    /// Sync buffered data every `interval` while mounted, for files whose paths `filter` returns
+    /// true for, so that a crash loses at most that much. This calls `FilesystemMT::sync` from a
+    /// thread of its own, or, if it isn't implemented, `fsync` on each `FileHandle` object which
+    /// has been written to since it was last synced. A final sync is done on unmount.
+    pub fn periodic_sync<F>(mut self, interval: Duration, filter: F) -> FuseMT<T>
+        where F: Fn(&Path) -> bool + Send + Sync + 'static
+    {
+        self.periodic_sync = Some(PeriodicSync::new(interval, Arc::new(filter)));
+        self
+    }
+
+    /// Write a `StateDump` to `path`, as JSON, whenever the process receives `signal` while
+    /// mounted, such as `libc::SIGUSR1`. The dump is written when the next request arrives after
+    /// the signal, so a mount which is completely idle or stuck in the request loop won't write
+    /// one. Only one FuseMT in a process should use this.
+    pub fn dump_state_on_signal(mut self, signal: libc::c_int, path: impl Into<PathBuf>)
+        -> FuseMT<T>
+    {
+        self.dump_on_signal = Some((signal, path.into()));
+        self
+    }
+
+    /// Catch the filesystem calling into its own mount, which is at `mountpoint`, and fail those
+    /// calls with `EDEADLK` instead of hanging. See `Reentrancy` for details.
+    pub fn detect_reentrancy(self, mountpoint: impl Into<PathBuf>) -> FuseMT<T> {
+        self.reentrancy.enable(mountpoint.into());
+        self
+    }
+
+    /// Fetch the attributes of the entries of a directory when it's listed, spread over up to
+    /// `concurrency` worker threads, and answer the lookups which usually follow from them.
+    /// Without this, the first `ls -l` of a large directory makes one `getattr` call per entry,
+    /// one after another, since FuseMT handles lookups on the thread reading requests.
+    ///
+    /// Prefetched attributes are only used once, and within the TTL the filesystem gave for them.
+    /// They're dropped when anything is changed through the mount, but changes made by other means
+    /// aren't noticed. This needs worker threads, and does nothing without them.
+    pub fn prefetch_attrs(mut self, concurrency: usize) -> FuseMT<T> {
+        self.attr_prefetch = Some(Arc::new(AttrPrefetch::new(concurrency)));
+        self
+    }
+
+    /// Add `.` and `..` entries to the start of every directory listing, instead of leaving that
+    /// to the filesystem. Any the filesystem returns itself are left out, so it doesn't matter
+    /// whether it does.
+    ///
+    /// For filesystems which implement `readdir_at` or `DirHandle::list_at`, the offsets they give
+    /// are shifted up by two in the offsets FuseMT gives the kernel, to make room.
+    pub fn synthesize_dot_entries(mut self) -> FuseMT<T> {
+        self.dot_entries = true;
+        self
+    }
+
+    /// Limit the size of each write the kernel sends. By default, FuseMT asks for writes of up to
+    /// 16 MiB, and the kernel sends them as large as its own limit allows (1 MiB on most Linux
+    /// systems), so that large sequential writes take few calls. Each write's data is copied once
+    /// and held until the filesystem has written it, so filesystems which take many writes at
+    /// once may want them smaller.
+    pub fn max_write(mut self, bytes: u32) -> FuseMT<T> {
+        self.max_write = Some(bytes);
+        self
+    }
+
+    /// Have the kernel ask for the attributes of directories' entries along with their listings,
+    /// when it expects to need them, as when running `ls -l`, instead of looking up each entry
+    /// afterwards. FuseMT uses the attributes given in each `DirectoryEntry`, and calls `getattr`
+    /// for entries which don't have them, so this helps most with filesystems which get them
+    /// along with their listings anyway.
+    ///
+    /// Directories are listed the same way as for readdir, with `readdir_at` or `DirHandle::list_at`
+    /// if the filesystem implements them, since the kernel can mix the two calls on the same
+    /// handle. The `getattr` calls run on the worker threads, and listings sent later by
+    /// `readdir_deferred` are answered once they're sent, as with `readdir`.
+    pub fn readdirplus(mut self) -> FuseMT<T> {
+        self.readdirplus = true;
+        self
+    }
+
+    /// Have the kernel pass locks of the given kind on to the filesystem, instead of keeping
+    /// track of them itself, for filesystems whose files are shared with other machines. See
+    /// `LockMode`. The default is `LockMode::Local`.
+    pub fn locks(mut self, mode: LockMode) -> FuseMT<T> {
+        self.lock_mode = mode;
+        self
+    }
+
+    /// Keep totals of the calls made on each part of the filesystem, for finding out which files
+    /// and directories take up most of its time. Calls are totalled by the first `depth`
+    /// components of their paths, and the `top` busiest are read out through the `HOTSPOTS_XATTR`
+    /// attribute on the root of the mount.
+    pub fn profile_hotspots(mut self, depth: usize, top: usize) -> FuseMT<T> {
+        self.hotspots = Some(Arc::new(Hotspots::new(depth, top)));
+        self
+    }
+
+    /// Tell FuseMT the options the filesystem is mounted with, to pass on to it in
+    /// `ConnectionInfo`.
+    pub fn mount_options(mut self, options: &[MountOption]) -> FuseMT<T> {
+        self.mount_options = options.to_vec();
+        self
+    }
+
+    /// Get a handle for notifying FuseMT of changes made to the filesystem without going through
+    /// FUSE. See `Invalidator` for details.
+    pub fn invalidator(&self) -> Invalidator {
+        self.invalidator.clone()
+    }
+
+    /// Use the given clock, instead of the system's, to expire cached data by, and for setting
+    /// timestamps to the current time. This is meant for tests, with a `ManualClock`.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> FuseMT<T> {
+        self.clock = clock;
+        self
+    }
+
+    /// Get a handle for finding out when the filesystem is ready to serve requests, once mounted.
+    /// See `Readiness` for details.
+    pub fn readiness(&self) -> Readiness {
+        self.ready.0.clone()
+    }
+
+    /// Get a handle for changing settings while mounted. See `Tunables` for details.
+    pub fn tunables(&self) -> Tunables {
+        self.tunables.clone()
+    }
+
+    /// Get a handle for pausing calls to the filesystem while mounted. See `Freezer` for details.
+    pub fn freezer(&self) -> Freezer {
+        self.freezer.clone()
+    }
+
+    /// Get a handle for checking how busy the worker threads are while mounted. See `PoolStats`
+    /// for details.
+    pub fn pool_stats(&self) -> PoolStats {
+        self.pool_stats.clone()
+    }
+
+    /// Get a handle for checking paths against the mountpoint given to `detect_reentrancy`. See
+    /// `Reentrancy` for details.
+    pub fn reentrancy(&self) -> Reentrancy {
+        self.reentrancy.clone()
+    }
+
+    fn apply_tunables(&mut self) {
+        let changes = match self.tunables.take() {
+            Some(changes) => changes,
+            None => return,
+        };
+        if let Some(num_threads) = changes.threads {
+            op_debug!("changing number of threads from {} to {}", self.num_threads, num_threads);
+            self.num_threads = num_threads;
+            if num_threads == 0 {
+                self.threads = None;
+            } else if let Some(ref mut threads) = self.threads {
+                threads.set_num_threads(num_threads);
             }
-            self.threads.as_ref().unwrap().execute(f);
         }
+        if let Some(window) = changes.prefetch {
+            op_debug!("changing prefetch window to {:?}", window);
+            self.prefetcher = window.map(|window| Arc::new(Prefetcher::new(window)));
+        }
+    }
+
+    /// Keep the listings which were sent through `readdir_deferred` after it returned.
+    fn cache_deferred_listings(&mut self) {
+        let listings = std::mem::take(&mut *self.deferred_listings.lock().unwrap());
+        for (key, entries) in listings {
+            self.directory_cache.set_entries(key, entries);
+        }
+    }
+
+    /// Find the listing for a readdir call from the given offset on, from the cache, or else
+    /// from the filesystem.
+    fn listing(&mut self, req_info: RequestInfo, fh: u64, path: &FsPath, offset: u64, dots: u64)
+        -> Result<Listing, libc::c_int>
+    {
+        let dcache_entry = self.directory_cache.get_mut(fh);
+        // Listings with offsets of their own can be fetched again anywhere in them, since they
+        // don't depend on entries staying where they were.
+        let resumable = dcache_entry.entries.as_ref()
+            .is_some_and(|entries| has_offsets(&entries[dots as usize ..]));
+        if (offset == 0 || resumable) && dcache_entry.stale {
+            op_debug!("cached entries are out of date; re-fetching");
+            dcache_entry.entries = None;
+            dcache_entry.stale = false;
+        }
+        if let Some(ref entries) = dcache_entry.entries {
+            return Ok(Listing::Cached(entries.clone()));
+        }
+        let result = if let Some(ref handle) = dcache_entry.handle {
+            op_debug!("entries not yet fetched; requesting from directory handle");
+            handle.list(req_info)
+        } else {
+            op_debug!("entries not yet fetched; requesting with fh {}", dcache_entry.fh);
+            let (responder, pending) = Responder::pending();
+            self.target.readdir_deferred(req_info, path, dcache_entry.fh, responder);
+            match pending.take() {
+                Some(result) => result,
+                None => return Ok(Listing::Later(pending)),
+            }
+        };
+        let mut entries = result?;
+        add_dot_entries(&mut entries, dots);
+        Ok(Listing::Fetched(self.directory_cache.set_entries(fh, entries)))
+    }
+
+    /// Whether a directory is to be listed a page at a time with `readdir_at` or
+    /// `DirHandle::list_at`, rather than from `listing`: that is, if the filesystem implements
+    /// them, as far as is known, and the directory hasn't been listed whole already.
+    fn pages(&self, fh: u64) -> bool {
+        let dcache_entry = self.directory_cache.get(fh);
+        dcache_entry.entries.is_none() && match dcache_entry.handle {
+            Some(_) => self.list_at_supported,
+            None => self.readdir_at_supported,
+        }
+    }
+
+    /// List a page of a directory, from the given offset on, with `readdir_at` or
+    /// `DirHandle::list_at`, passing `add` each entry and the offset of the next one, as the
+    /// kernel is given them. The dot entries FuseMT adds itself aren't included. If this fails
+    /// with `ENOSYS`, it isn't tried again, and the listing should come from `listing` instead.
+    fn list_page(&mut self, req_info: RequestInfo, fh: u64, path: &FsPath, offset: u64, dots: u64,
+                 add: &mut dyn FnMut(&DirectoryEntry, u64) -> bool) -> ResultEmpty
+    {
+        let mut add = |entry: &DirectoryEntry, next: u64| {
+            if dots > 0 && (entry.name == "." || entry.name == "..") {
+                return false;
+            }
+            add(entry, next + dots)
+        };
+        let dcache_entry = self.directory_cache.get(fh);
+        let result = match dcache_entry.handle {
+            Some(ref handle) => handle.list_at(req_info, offset.saturating_sub(dots), &mut add),
+            None => self.target.readdir_at(req_info, path, dcache_entry.fh,
+                offset.saturating_sub(dots), &mut add),
+        };
+        if result == Err(libc::ENOSYS) {
+            if dcache_entry.handle.is_some() {
+                op_debug!("list_at not implemented; using list instead");
+                self.list_at_supported = false;
+            } else {
+                op_debug!("readdir_at not implemented; using readdir instead");
+                self.readdir_at_supported = false;
+            }
+        }
+        result
+    }
+
+    /// List a page of a directory for a readdirplus call with `list_page`, along with the offset
+    /// of the next entry after each one, starting with the dot entries FuseMT adds, if the offset
+    /// is before them. This stops at about as many entries as fit in the smallest reply.
+    fn plus_page(&mut self, req_info: RequestInfo, fh: u64, path: &FsPath, offset: u64, dots: u64)
+        -> Result<Vec<(DirectoryEntry, u64)>, libc::c_int>
+    {
+        let mut page = (offset .. dots)
+            .map(|index| (dot_entry(DOT_ENTRIES[index as usize]), index + 1))
+            .collect::<Vec<_>>();
+        let mut room = READDIRPLUS_PAGE;
+        for (entry, _) in &page {
+            room -= direntplus_size(entry.name.as_os_str());
+        }
+        self.list_page(req_info, fh, path, offset, dots, &mut |entry, next| {
+            room = room.saturating_sub(direntplus_size(entry.name.as_os_str()));
+            page.push((entry.clone(), next));
+            room == 0
+        })?;
+        Ok(page)
+    }
+
+    fn apply_invalidations(&mut self) {
+        match self.invalidator.take() {
+            None => (),
+            Some(Invalidations::Everything) => {
+                op_debug!("invalidating all directory listings");
+                self.directory_cache.mark_stale(None);
+            }
+            Some(Invalidations::Paths(paths)) => {
+                for path in paths {
+                    op_debug!("invalidating {:?}", path);
+                    let dirs = [Some(path.as_path()), path.parent()];
+                    for dir in dirs.iter().flatten() {
+                        if let Some(ino) = self.inodes.lock().unwrap().get_inode(dir) {
+                            self.directory_cache.mark_stale(Some(ino));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Take a snapshot of the inode table, the open directories, and the open files, for working
+    /// out why a mount is stuck or leaking memory. This is also served as the value of the
+    /// `STATE_XATTR` attribute.
+    pub fn dump_state(&self) -> StateDump {
+        let (open_files, open_file_handles) = self.handles.counts();
+        StateDump {
+            inodes: self.inodes.lock().unwrap().dump(),
+            directories: self.directory_cache.dump(),
+            open_files,
+            open_file_handles,
+        }
+    }
+
+    /// Start handling a request: write a state dump if one was asked for by signal, then wait
+    /// until the filesystem isn't frozen.
+    fn enter(&self) -> InFlight {
+        if let Some((_, ref path)) = self.dump_on_signal {
+            if take_signal() {
+                match self.dump_state().write_to(path) {
+                    Ok(()) => info!("wrote state dump to {:?}", path),
+                    Err(e) => error!("unable to write state dump to {:?}: {}", path, e),
+                }
+            }
+        }
+        self.freezer.enter()
+    }
+
+    /// Start timing a call on the given path, or on the entry with the given name in it, if
+    /// hotspots are being profiled.
+    fn profile(&self, path: &Path, name: Option<&OsStr>) -> Option<Profile> {
+        self.hotspots.as_ref().map(|hotspots| hotspots.start(path, name))
+    }
+
+    /// Format the text served as the value of the `STATS_XATTR` attribute.
+    fn format_stats(&self) -> String {
+        let mut gauges = vec![
+            ("inodes", self.inodes.lock().unwrap().len()),
+            ("open_files", self.handles.len()),
+            ("open_dirs", self.directory_cache.len()),
+            ("prefetch_streams", self.prefetcher.as_ref().map_or(0, |p| p.len())),
+            ("threads", self.num_threads),
+            ("file_limit_queued", self.file_limit.as_ref().map_or(0, |l| l.queued())),
+            ("pool_queued", self.pool_stats.queued()),
+            ("pool_busy", self.pool_stats.busy()),
+            ("pool_completed", self.pool_stats.completed() as usize),
+        ];
+        gauges.extend_from_slice(&self.memory_usage().gauges());
+        let mut waits = self.pool_stats.wait_times().into_iter()
+            .map(|(op, wait)| (format!("{:?}", op).to_lowercase(), wait))
+            .collect::<Vec<_>>();
+        waits.sort_by(|a, b| a.0.cmp(&b.0));
+        let wait_gauges = waits.iter()
+            .flat_map(|(op, wait)| vec![
+                (format!("pool_waits.{}", op), wait.calls as usize),
+                (format!("pool_wait_us.{}", op), wait.total.as_micros() as usize),
+                (format!("pool_wait_max_us.{}", op), wait.max.as_micros() as usize),
+            ])
+            .collect::<Vec<_>>();
+        gauges.extend(wait_gauges.iter().map(|(name, value)| (name.as_str(), *value)));
+        self.stats.format(&gauges)
+    }
+
+    fn memory_usage(&self) -> MemoryUsage {
+        MemoryUsage {
+            inodes: self.inodes.lock().unwrap().memory_usage(),
+            directories: self.directory_cache.memory_usage(),
+            prefetch: self.prefetcher.as_ref().map_or(0, |p| p.memory_usage()),
+            writes: self.write_buffers.get(),
+        }
+    }
+
+    /// If there's a memory limit and it's time to check it, evict what can be evicted if memory
+    /// use is over it.
+    fn check_memory(&mut self) {
+        let limit = match self.memory_limit {
+            Some(ref mut limit) => {
+                if !limit.due(self.clock.now()) {
+                    return;
+                }
+                limit.bytes
+            }
+            None => return,
+        };
+        let usage = self.memory_usage();
+        if usage.total() <= limit {
+            self.memory_limit.as_mut().unwrap().over = false;
+            return;
+        }
+
+        op_debug!("using {} bytes of memory, over the limit of {}; evicting", usage.total(), limit);
+        if let Some(ref prefetcher) = self.prefetcher {
+            prefetcher.evict();
+        }
+        self.directory_cache.evict();
+        self.attrs_changed();
+        self.inodes.lock().unwrap().shrink();
+
+        let usage = self.memory_usage();
+        let limit = self.memory_limit.as_mut().unwrap();
+        if usage.total() > limit.bytes && !limit.over {
+            warn!("still using {} bytes of memory after evicting caches, over the limit of {}: {:?}",
+                usage.total(), limit.bytes, usage);
+        }
+        limit.over = usage.total() > limit.bytes;
+    }
+
+    /// Prepare to check the given path again when a call with the given handle runs.
+    fn path_ticket(&self, path: FsPath, handle: &FileHandleEntry) -> PathTicket {
+        match (&self.path_log, handle) {
+            (Some(log), FileHandleEntry::Raw(_)) => log.ticket(path),
+            _ => PathTicket::unchecked(path),
+        }
+    }
+
+    /// Note that the given path was renamed to `to`, or removed, for calls still queued on it.
+    fn path_changed(&self, from: &Path, to: Option<&Path>) {
+        if let Some(ref log) = self.path_log {
+            log.changed(from, to);
+        }
+    }
+
+    /// Answer a getxattr or listxattr call through the xattr cache, if it's enabled: a probe for
+    /// the size gets the whole value with `fetch`, and keeps it for the call that follows. Returns
+    /// the reply back if the call wasn't answered and should go to the filesystem as usual.
+    ///
+    /// The key is only made if the cache is enabled, since it has a copy of the attribute name.
+    fn cached_xattr(
+        &mut self,
+        key: impl FnOnce() -> XattrKey,
+        size: u32,
+        reply: fuser::ReplyXattr,
+        fetch: impl FnOnce(u32) -> ResultXattr,
+    ) -> Option<fuser::ReplyXattr> {
+        let cache = match self.xattr_cache {
+            Some(ref mut cache) => cache,
+            None => return Some(reply),
+        };
+        let key = key();
+        let now = self.clock.now();
+        if size != 0 {
+            match cache.take(&key, now) {
+                Some(data) if data.len() > size as usize => reply.error(libc::ERANGE),
+                Some(data) => {
+                    op_debug!("sending {} bytes fetched for the size probe", data.len());
+                    reply.data(&data)
+                }
+                None => return Some(reply),
+            }
+            return None;
+        }
+        match fetch(XATTR_SIZE_MAX) {
+            Ok(Xattr::Data(data)) => {
+                reply.size(data.len() as u32);
+                cache.insert(key, data, now);
+            }
+            Ok(Xattr::Size(size)) => reply.size(size),
+            // Too big to fetch; just ask for the size.
+            Err(libc::ERANGE) => return Some(reply),
+            Err(e) => reply.error(e),
+        }
+        None
+    }
+
+    /// Note that something may have been changed through the mount, so prefetched attributes
+    /// can't be trusted any more.
+    fn attrs_changed(&self) {
+        if let Some(ref prefetch) = self.attr_prefetch {
+            prefetch.clear();
+        }
+    }
+
+    /// Fetch the attributes of entries just listed in a directory on the worker threads, if
+    /// attribute prefetching is on.
+    fn start_attr_prefetch(&mut self, req: RequestInfo, dir: &FsPath, names: Vec<OsString>) {
+        let prefetch = match self.attr_prefetch {
+            Some(ref prefetch) if self.num_threads > 0 && !names.is_empty() => prefetch.clone(),
+            _ => return,
+        };
+        let generation = match prefetch.start(names.len(), self.clock.now()) {
+            Some(generation) => generation,
+            None => return,
+        };
+        op_debug!("prefetching attributes of {} entries in {:?}", names.len(), dir);
+        let tasks = prefetch.concurrency.min(names.len());
+        let names = Arc::new(names);
+        for task in 0 .. tasks {
+            let (target, prefetch) = (self.target.clone(), prefetch.clone());
+            let clock = self.clock.clone();
+            let (dir, names) = (dir.clone(), names.clone());
+            self.threadpool_run(Lane::DEFAULT, move || {
+                for name in names.iter().skip(task).step_by(tasks) {
+                    let path = FsPath::from(dir.join(name));
+                    let fetched = clock.now();
+                    if let Ok((ttl, attr)) = target.getattr(req, &path, None) {
+                        prefetch.insert(generation, path.into(), fetched, ttl, attr);
+                    }
+                }
+            });
+        }
+    }
+
+    /// Get the info to give the filesystem about a request on the given inode.
+    fn request_info(&self, req: &fuser::Request<'_>, ino: u64) -> RequestInfo {
+        RequestInfo {
+            node: self.inodes.lock().unwrap().get_generation(ino)
+                .map(|generation| NodeId::new(ino, generation)),
+            ..req.info()
+        }
+    }
+
+    /// Note that a handle to the given inode was opened.
+    fn opened(&mut self, ino: u64) {
+        self.open_files.opened(ino);
+    }
+
+    /// If silly renaming is enabled and the given entry is open, rename it to a hidden name.
+    /// Returns whether it was renamed, in which case it must not be deleted.
+    fn hide_if_open(&mut self, req: RequestInfo, parent: u64, parent_path: &FsPath, name: &OsStr)
+        -> Result<bool, libc::c_int>
+    {
+        let silly = match self.silly_rename {
+            Some(ref mut silly) => silly,
+            None => return Ok(false),
+        };
+        let ino = match self.inodes.lock().unwrap().get_child(parent, name) {
+            Some(ino) if self.open_files.is_open(ino) => ino,
+            _ => return Ok(false),
+        };
+        let hidden_name = silly.hide(ino);
+        op_debug!("{:?}/{:?} is open; renaming it to {:?} instead of deleting it",
+            parent_path, name, hidden_name);
+        self.target.rename(req, parent_path, name, parent_path, &hidden_name)?;
+        if let Some(ref log) = self.path_log {
+            log.changed(&parent_path.join(name), Some(&parent_path.join(&hidden_name)));
+        }
+        self.inodes.lock().unwrap().rename(parent, name, parent, &hidden_name);
+        silly.hidden(ino);
+        Ok(true)
+    }
+
+    /// Wait until the worker threads of the given lane have started on every call queued for them.
+    fn wait_for_queue(&self, lane: Lane) {
+        let threads = match self.lanes.get(&lane) {
+            Some(lane) => lane.threads.as_ref(),
+            None => self.threads.as_ref(),
+        };
+        if let Some(threads) = threads {
+            threads.wait_for_queue();
+        }
+    }
+
+    fn threadpool_run<F: FnOnce() + Send + 'static>(&mut self, lane: Lane, f: F) {
+        self.dispatch(lane, None, None, f)
+    }
+
+    /// Like `threadpool_run`, for the given operation on the data of the given file, which waits
+    /// its turn if there's a limit on the calls running on each file.
+    fn threadpool_run_on<F: FnOnce() + Send + 'static>(&mut self, lane: Lane, op: Operation,
+                                                       ino: u64, f: F) {
+        self.dispatch(lane, Some(op), Some(ino), f)
+    }
+
+    fn dispatch<F: FnOnce() + Send + 'static>(&mut self, lane: Lane, operation: Option<Operation>,
+                                              ino: Option<u64>, f: F) {
+        self.apply_tunables();
+        // The call isn't over until it's finished on the worker thread.
+        let op = self.freezer.enter_admitted();
+        let reentrancy = self.reentrancy.clone();
+        let queued = self.pool_stats.queue(operation);
+        let f = move || {
+            let running = queued.start();
+            reentrancy.register_thread();
+            f();
+            drop(running);
+            drop(op);
+        };
+        let threads = self.lane_threads(lane);
+        match (ino, &self.file_limit) {
+            (Some(ino), Some(limit)) => limit.run(ino, threads, Box::new(f)),
+            _ => match threads {
+                Some(threads) => threads.execute(f),
+                None => f(),
+            },
+        }
+    }
+
+    /// Get the threads calls on a lane run on, or `None` if they run on FuseMT's own thread.
+    fn lane_threads(&mut self, lane: Lane) -> Option<WorkerPool> {
+        if let Some(lane) = self.lanes.get_mut(&lane) {
+            return lane.threads();
+        }
+        if self.num_threads == 0 {
+            return None;
+        }
+        if self.threads.is_none() {
+            op_debug!("initializing threadpool with {} threads", self.num_threads);
+            self.threads = Some(WorkerPool::new(self.num_threads));
+        }
+        self.threads.clone()
+    }
+
+    /// Get a way to run prefetches of a file from the worker thread reading it, which is queued
+    /// on another lane, or `None` if they should run on that thread, right after the read.
+    fn prefetch_spawner(&mut self, path: &FsPath, read_lane: Lane) -> Option<Spawner> {
+        let lane = self.target.classify(Operation::Prefetch, path);
+        if lane == read_lane {
+            return None;
+        }
+        self.spawner(lane, Some(Operation::Prefetch))
+    }
+
+    /// Get a way to queue calls on a lane's threads from another thread, or `None` if they run
+    /// on FuseMT's own thread.
+    fn spawner(&mut self, lane: Lane, operation: Option<Operation>) -> Option<Spawner> {
+        Some(Spawner {
+            threads: self.lane_threads(lane)?,
+            operation,
+            freezer: self.freezer.clone(),
+            reentrancy: self.reentrancy.clone(),
+            pool_stats: self.pool_stats.clone(),
+        })
     }
 }
 
 macro_rules! get_path {
     ($s:expr, $ino:expr, $reply:expr) => {
-        if let Some(path) = $s.inodes.get_path($ino) {
+        if let Some(path) = $s.inodes.lock().unwrap().get_path($ino) {
             path
         } else {
             $reply.error(libc::EINVAL);
@@ -108,18 +1180,117 @@ macro_rules! get_path {
     }
 }
 
+macro_rules! get_handle {
+    ($s:expr, $fh:expr, $reply:expr) => {
+        if let Some(entry) = $s.handles.get($fh) {
+            entry
+        } else {
+            error!("no such file handle {}", $fh);
+            $reply.error(libc::EBADF);
+            return;
+        }
+    }
+}
+
+/// Start handling a request, unless it came from one of FuseMT's own threads, in which case it
+/// fails with `EDEADLK`. Gives a guard which keeps the filesystem from being frozen until dropped.
+macro_rules! enter {
+    ($s:expr, $req:expr, $reply:expr) => {
+        match $s.reentrancy.check_caller($req.pid()) {
+            Ok(()) => $s.enter(),
+            Err(e) => {
+                $reply.error(e);
+                return;
+            }
+        }
+    }
+}
+
+/// Ask the filesystem which lane a call goes in and whether it can take it on, waiting or
+/// replying `EAGAIN` as it says. Gives the lane, and whether the filesystem isn't busy.
+macro_rules! admit {
+    ($s:expr, $op:expr, $path:expr, $reply:expr) => {{
+        let lane = $s.target.classify($op, $path);
+        match $s.target.load($op, $path) {
+            Load::Normal => (lane, true),
+            Load::Busy => {
+                op_debug!("filesystem is busy; waiting for queued calls to start");
+                $s.wait_for_queue(lane);
+                (lane, false)
+            }
+            Load::Overloaded => {
+                op_debug!("filesystem is overloaded; rejecting {:?}", $op);
+                $s.stats.count("overloaded");
+                $reply.error(libc::EAGAIN);
+                return;
+            }
+        }
+    }}
+}
+
 impl<T: FilesystemMT + Sync + Send + 'static> fuser::Filesystem for FuseMT<T> {
     fn init(
         &mut self,
         req: &fuser::Request<'_>,
-        _config: &mut fuser::KernelConfig, // TODO
+        config: &mut fuser::KernelConfig,
     ) -> Result<(), libc::c_int> {
-        debug!("init");
-        self.target.init(req.info())
+        self.stats.count("init");
+        op_debug!("init");
+        self.reentrancy.register_thread();
+        if let Some(max_write) = self.max_write {
+            if let Err(nearest) = config.set_max_write(max_write) {
+                warn!("max_write of {} bytes isn't possible; using {}", max_write, nearest);
+                let _ = config.set_max_write(nearest);
+            }
+        }
+        let (lock_flag, lock_kind) = match self.lock_mode {
+            LockMode::Local => (0, ""),
+            LockMode::Posix => (fuser::consts::FUSE_POSIX_LOCKS, "POSIX"),
+            LockMode::Flock => (fuser::consts::FUSE_FLOCK_LOCKS, "flock"),
+        };
+        if config.add_capabilities(lock_flag).is_err() {
+            warn!("the kernel can't pass {} locks on; it will keep track of them itself", lock_kind);
+        }
+        if self.readdirplus {
+            let flags = fuser::consts::FUSE_DO_READDIRPLUS | fuser::consts::FUSE_READDIRPLUS_AUTO;
+            if config.add_capabilities(flags).is_err() {
+                warn!("the kernel can't list directories with their entries' attributes");
+            }
+        }
+        let conn = ConnectionInfo {
+            protocol_version: FUSE_PROTOCOL_VERSION,
+            capabilities: kernel_capabilities(config),
+            mount_options: self.mount_options.clone(),
+        };
+        op_debug!("init: {:?}", conn);
+        if let Err(e) = self.target.init_connection(req.info(), &conn, config) {
+            self.ready.0.set(Err(e));
+            return Err(e);
+        }
+        if let Some(ref mut periodic_sync) = self.periodic_sync {
+            periodic_sync.start(self.target.clone(), self.freezer.clone());
+        }
+        if let Some((signal, _)) = self.dump_on_signal {
+            if let Err(e) = install_signal_handler(signal) {
+                error!("unable to handle signal {} for state dumps: {}", signal, e);
+            }
+        }
+        #[cfg(all(feature = "systemd", target_os = "linux"))]
+        crate::systemd::notify_or_log("READY=1");
+        self.ready.0.set(Ok(()));
+        Ok(())
     }
 
     fn destroy(&mut self) {
-        debug!("destroy");
+        op_debug!("destroy");
+        #[cfg(all(feature = "systemd", target_os = "linux"))]
+        crate::systemd::notify_or_log("STOPPING=1");
+        self.ready.0.set(Err(libc::ENOTCONN));
+        // Nothing can be waiting for a thaw once the filesystem is gone.
+        self.freezer.thaw();
+        if let Some(ref mut periodic_sync) = self.periodic_sync {
+            periodic_sync.stop(&*self.target);
+        }
         self.target.destroy();
     }
 
@@ -130,13 +1301,36 @@ impl<T: FilesystemMT + Sync + Send + 'static> fuser::Filesystem for FuseMT<T> {
         name: &OsStr,
         reply: fuser::ReplyEntry,
     ) {
+        self.stats.count("lookup");
+        let _op = enter!(self, req, reply);
+        self.check_memory();
         let parent_path = get_path!(self, parent, reply);
-        debug!("lookup: {:?}, {:?}", parent_path, name);
-        let path = Arc::new((*parent_path).clone().join(name));
-        match self.target.getattr(req.info(), &path, None) {
+        let _profile = self.profile(&parent_path, Some(name));
+        op_debug!("lookup: {:?}, {:?}", parent_path, name);
+        let mut path = mem::take(&mut self.lookup_path).into_os_string();
+        path.clear();
+        let mut path = PathBuf::from(path);
+        path.push(&*parent_path);
+        path.push(name);
+        let prefetched = self.attr_prefetch.as_ref()
+            .and_then(|prefetch| prefetch.take(&path, self.clock.now()));
+        let result = match prefetched {
+            Some(entry) => {
+                op_debug!("lookup: using prefetched attributes");
+                Ok(entry)
+            }
+            None => {
+                let path = FsPath::from(path.as_path());
+                self.target.getattr(self.request_info(req, parent), &path, None)
+            }
+        };
+        self.lookup_path = path;
+        match result {
             Ok((ttl, attr)) => {
-                let (ino, generation) = self.inodes.add_or_get(path.clone());
-                self.inodes.lookup(ino);
+                let mut inodes = self.inodes.lock().unwrap();
+                let (ino, generation) = inodes.add_or_get(parent, name);
+                inodes.lookup(ino);
+                drop(inodes);
                 reply.entry(&ttl, &fuse_fileattr(attr, ino), generation);
             },
             Err(e) => reply.error(e),
@@ -149,11 +1343,17 @@ impl<T: FilesystemMT + Sync + Send + 'static> fuser::Filesystem for FuseMT<T> {
         ino: u64,
         nlookup: u64,
     ) {
-        let path = self.inodes.get_path(ino).unwrap_or_else(|| {
-            Arc::new(PathBuf::from("[unknown]"))
-        });
-        let lookups = self.inodes.forget(ino, nlookup);
-        debug!("forget: inode {} ({:?}) now at {} lookups", ino, path, lookups);
+        self.stats.count("forget");
+        let _op = self.enter();
+        // Building the path just to log it would allocate for inodes whose path isn't kept.
+        let path = if cfg!(not(feature = "quiet")) && log_enabled!(log::Level::Debug) {
+            self.inodes.lock().unwrap().get_path(ino)
+        } else {
+            None
+        };
+        let lookups = self.inodes.lock().unwrap().forget(ino, nlookup);
+        op_debug!("forget: inode {} ({:?}) now at {} lookups", ino,
+            path.as_deref().unwrap_or_else(|| Path::new("[unknown]")), lookups);
     }
 
     fn getattr(
@@ -162,14 +1362,21 @@ impl<T: FilesystemMT + Sync + Send + 'static> fuser::Filesystem for FuseMT<T> {
         ino: u64,
         reply: fuser::ReplyAttr,
     ) {
+        self.stats.count("getattr");
+        let op = enter!(self, req, reply);
         let path = get_path!(self, ino, reply);
-        debug!("getattr: {:?}", path);
-        match self.target.getattr(req.info(), &path, None) {
-            Ok((ttl, attr)) => {
-                reply.attr(&ttl, &fuse_fileattr(attr, ino))
-            },
-            Err(e) => reply.error(e),
-        }
+        let profile = self.profile(&path, None);
+        op_debug!("getattr: {:?}", path);
+        self.target.getattr_deferred(self.request_info(req, ino), &path, None, Responder::new(move |result| {
+            match result {
+                Ok((ttl, attr)) => {
+                    reply.attr(&ttl, &fuse_fileattr(attr, ino))
+                },
+                Err(e) => reply.error(e),
+            }
+            drop(profile);
+            drop(op);
+        }));
     }
 
     fn setattr(
@@ -184,64 +1391,93 @@ impl<T: FilesystemMT + Sync + Send + 'static> fuser::Filesystem for FuseMT<T> {
         mtime: Option<TimeOrNow>,       // utimens
         _ctime: Option<SystemTime>,     // ? TODO
         fh: Option<u64>,                // passed to all
-        crtime: Option<SystemTime>,     // utimens_osx  (OS X only)
+        crtime: Option<SystemTime>,     // setxtimes    (OS X only)
         chgtime: Option<SystemTime>,    // utimens_osx  (OS X only)
-        bkuptime: Option<SystemTime>,   // utimens_osx  (OS X only)
+        bkuptime: Option<SystemTime>,   // setxtimes    (OS X only)
         flags: Option<u32>,             // utimens_osx  (OS X only)
         reply: fuser::ReplyAttr,
     ) {
+        self.stats.count("setattr");
+        let _op = enter!(self, req, reply);
+        self.attrs_changed();
         let path = get_path!(self, ino, reply);
-        debug!("setattr: {:?}", path);
+        let _profile = self.profile(&path, None);
+        op_debug!("setattr: {:?}", path);
+
+        // Managed file handles have no numeric value to give to the filesystem.
+        let managed = match fh.and_then(|fh| self.handles.get(fh)) {
+            Some(FileHandleEntry::Managed(handle)) => Some(handle),
+            _ => None,
+        };
+        let fh = fh.and_then(|fh| self.handles.real_fh(fh));
 
-        debug!("\tino:\t{:?}", ino);
-        debug!("\tmode:\t{:?}", mode);
-        debug!("\tuid:\t{:?}", uid);
-        debug!("\tgid:\t{:?}", gid);
-        debug!("\tsize:\t{:?}", size);
-        debug!("\tatime:\t{:?}", atime);
-        debug!("\tmtime:\t{:?}", mtime);
-        debug!("\tfh:\t{:?}", fh);
+        op_debug!("\tino:\t{:?}", ino);
+        op_debug!("\tmode:\t{:?}", mode);
+        op_debug!("\tuid:\t{:?}", uid);
+        op_debug!("\tgid:\t{:?}", gid);
+        op_debug!("\tsize:\t{:?}", size);
+        op_debug!("\tatime:\t{:?}", atime);
+        op_debug!("\tmtime:\t{:?}", mtime);
+        op_debug!("\tfh:\t{:?}", fh);
 
         // TODO: figure out what C FUSE does when only some of these are implemented.
 
         if let Some(mode) = mode {
-            if let Err(e) = self.target.chmod(req.info(), &path, fh, mode) {
+            if let Err(e) = self.target.chmod(self.request_info(req, ino), &path, fh, mode) {
                 reply.error(e);
                 return;
             }
         }
 
         if uid.is_some() || gid.is_some() {
-            if let Err(e) = self.target.chown(req.info(), &path, fh, uid, gid) {
+            if let Err(e) = self.target.chown(self.request_info(req, ino), &path, fh, uid, gid) {
                 reply.error(e);
                 return;
             }
         }
 
         if let Some(size) = size {
-            if let Err(e) = self.target.truncate(req.info(), &path, fh, size) {
+            if let Some(ref prefetcher) = self.prefetcher {
+                prefetcher.invalidate_inode(ino);
+            }
+            let result = match managed.map(|handle| handle.truncate(self.request_info(req, ino), size)) {
+                Some(Err(libc::ENOSYS)) | None => self.target.truncate(self.request_info(req, ino), &path, fh, size),
+                Some(result) => result,
+            };
+            if let Err(e) = result {
                 reply.error(e);
                 return;
             }
         }
 
         if atime.is_some() || mtime.is_some() {
-            let atime = atime.map(TimeOrNowExt::time);
-            let mtime = mtime.map(TimeOrNowExt::time);
-            if let Err(e) = self.target.utimens(req.info(), &path, fh, atime, mtime) {
+            let atime = atime.map(|t| t.time(&*self.clock));
+            let mtime = mtime.map(|t| t.time(&*self.clock));
+            if let Err(e) = self.target.utimens(self.request_info(req, ino), &path, fh, atime, mtime) {
                 reply.error(e);
                 return;
             }
         }
 
+        #[cfg(target_os = "macos")]
+        let (crtime, bkuptime) = {
+            if crtime.is_some() || bkuptime.is_some() {
+                if let Err(e) = self.target.setxtimes(self.request_info(req, ino), &path, fh, bkuptime, crtime) {
+                    reply.error(e);
+                    return;
+                }
+            }
+            (None, None)
+        };
+
         if crtime.is_some() || chgtime.is_some() || bkuptime.is_some() || flags.is_some() {
-            if let Err(e) = self.target.utimens_macos(req.info(), &path, fh, crtime, chgtime, bkuptime, flags) {
+            if let Err(e) = self.target.utimens_macos(self.request_info(req, ino), &path, fh, crtime, chgtime, bkuptime, flags) {
                 reply.error(e);
                 return
             }
         }
 
-        match self.target.getattr(req.info(), &path, fh) {
+        match self.target.getattr(self.request_info(req, ino), &path, fh) {
             Ok((ttl, attr)) => reply.attr(&ttl, &fuse_fileattr(attr, ino)),
             Err(e) => reply.error(e),
         }
@@ -253,9 +1489,12 @@ impl<T: FilesystemMT + Sync + Send + 'static> fuser::Filesystem for FuseMT<T> {
         ino: u64,
         reply: fuser::ReplyData,
     ) {
+        self.stats.count("readlink");
+        let _op = enter!(self, req, reply);
         let path = get_path!(self, ino, reply);
-        debug!("readlink: {:?}", path);
-        match self.target.readlink(req.info(), &path) {
+        let _profile = self.profile(&path, None);
+        op_debug!("readlink: {:?}", path);
+        match self.target.readlink(self.request_info(req, ino), &path) {
             Ok(data) => reply.data(&data),
             Err(e) => reply.error(e),
         }
@@ -267,15 +1506,20 @@ impl<T: FilesystemMT + Sync + Send + 'static> fuser::Filesystem for FuseMT<T> {
         parent: u64,
         name: &OsStr,
         mode: u32,
-        _umask: u32, // TODO
+        umask: u32,
         rdev: u32,
         reply: fuser::ReplyEntry,
     ) {
+        self.stats.count("mknod");
+        let _op = enter!(self, req, reply);
+        self.attrs_changed();
         let parent_path = get_path!(self, parent, reply);
-        debug!("mknod: {:?}/{:?}", parent_path, name);
-        match self.target.mknod(req.info(), &parent_path, name, mode, rdev) {
+        let _profile = self.profile(&parent_path, Some(name));
+        op_debug!("mknod: {:?}/{:?}", parent_path, name);
+        let req = RequestInfo { umask: Some(umask), ..self.request_info(req, parent) };
+        match self.target.mknod(req, &parent_path, name, mode, rdev) {
             Ok((ttl, attr)) => {
-                let (ino, generation) = self.inodes.add(Arc::new(parent_path.join(name)));
+                let (ino, generation) = self.inodes.lock().unwrap().add(parent, name);
                 reply.entry(&ttl, &fuse_fileattr(attr, ino), generation)
             },
             Err(e) => reply.error(e),
@@ -288,14 +1532,19 @@ impl<T: FilesystemMT + Sync + Send + 'static> fuser::Filesystem for FuseMT<T> {
         parent: u64,
         name: &OsStr,
         mode: u32,
-        _umask: u32, // TODO
+        umask: u32,
         reply: fuser::ReplyEntry,
     ) {
+        self.stats.count("mkdir");
+        let _op = enter!(self, req, reply);
+        self.attrs_changed();
         let parent_path = get_path!(self, parent, reply);
-        debug!("mkdir: {:?}/{:?}", parent_path, name);
-        match self.target.mkdir(req.info(), &parent_path, name, mode) {
+        let _profile = self.profile(&parent_path, Some(name));
+        op_debug!("mkdir: {:?}/{:?}", parent_path, name);
+        let req = RequestInfo { umask: Some(umask), ..self.request_info(req, parent) };
+        match self.target.mkdir(req, &parent_path, name, mode) {
             Ok((ttl, attr)) => {
-                let (ino, generation) = self.inodes.add(Arc::new(parent_path.join(name)));
+                let (ino, generation) = self.inodes.lock().unwrap().add(parent, name);
                 reply.entry(&ttl, &fuse_fileattr(attr, ino), generation)
             },
             Err(e) => reply.error(e),
@@ -309,11 +1558,31 @@ impl<T: FilesystemMT + Sync + Send + 'static> fuser::Filesystem for FuseMT<T> {
         name: &OsStr,
         reply: fuser::ReplyEmpty,
     ) {
+        self.stats.count("unlink");
+        let _op = enter!(self, req, reply);
+        self.attrs_changed();
         let parent_path = get_path!(self, parent, reply);
-        debug!("unlink: {:?}/{:?}", parent_path, name);
-        match self.target.unlink(req.info(), &parent_path, name) {
+        let _profile = self.profile(&parent_path, Some(name));
+        op_debug!("unlink: {:?}/{:?}", parent_path, name);
+        match self.hide_if_open(self.request_info(req, parent), parent, &parent_path, name) {
+            Ok(true) => {
+                reply.ok();
+                return;
+            }
+            Ok(false) => (),
+            Err(e) => {
+                reply.error(e);
+                return;
+            }
+        }
+        let ino = self.inodes.lock().unwrap().get_child(parent, name);
+        match self.target.unlink(self.request_info(req, parent), &parent_path, name) {
             Ok(()) => {
-                self.inodes.unlink(&parent_path.join(name));
+                self.path_changed(&parent_path.join(name), None);
+                self.inodes.lock().unwrap().unlink(parent, name);
+                if let Some(ino) = ino {
+                    self.open_files.unlinked(ino);
+                }
                 reply.ok()
             },
             Err(e) => reply.error(e),
@@ -327,10 +1596,17 @@ impl<T: FilesystemMT + Sync + Send + 'static> fuser::Filesystem for FuseMT<T> {
         name: &OsStr,
         reply: fuser::ReplyEmpty,
     ) {
+        self.stats.count("rmdir");
+        let _op = enter!(self, req, reply);
+        self.attrs_changed();
         let parent_path = get_path!(self, parent, reply);
-        debug!("rmdir: {:?}/{:?}", parent_path, name);
-        match self.target.rmdir(req.info(), &parent_path, name) {
-            Ok(()) => reply.ok(),
+        let _profile = self.profile(&parent_path, Some(name));
+        op_debug!("rmdir: {:?}/{:?}", parent_path, name);
+        match self.target.rmdir(self.request_info(req, parent), &parent_path, name) {
+            Ok(()) => {
+                self.path_changed(&parent_path.join(name), None);
+                reply.ok()
+            },
             Err(e) => reply.error(e),
         }
     }
@@ -343,11 +1619,15 @@ impl<T: FilesystemMT + Sync + Send + 'static> fuser::Filesystem for FuseMT<T> {
         link: &Path,
         reply: fuser::ReplyEntry,
     ) {
+        self.stats.count("symlink");
+        let _op = enter!(self, req, reply);
+        self.attrs_changed();
         let parent_path = get_path!(self, parent, reply);
-        debug!("symlink: {:?}/{:?} -> {:?}", parent_path, name, link);
-        match self.target.symlink(req.info(), &parent_path, name, link) {
+        let _profile = self.profile(&parent_path, Some(name));
+        op_debug!("symlink: {:?}/{:?} -> {:?}", parent_path, name, link);
+        match self.target.symlink(self.request_info(req, parent), &parent_path, name, link) {
             Ok((ttl, attr)) => {
-                let (ino, generation) = self.inodes.add(Arc::new(parent_path.join(name)));
+                let (ino, generation) = self.inodes.lock().unwrap().add(parent, name);
                 reply.entry(&ttl, &fuse_fileattr(attr, ino), generation)
             },
             Err(e) => reply.error(e),
@@ -364,12 +1644,32 @@ impl<T: FilesystemMT + Sync + Send + 'static> fuser::Filesystem for FuseMT<T> {
         _flags: u32, // TODO
         reply: fuser::ReplyEmpty,
     ) {
+        self.stats.count("rename");
+        let _op = enter!(self, req, reply);
+        self.attrs_changed();
         let parent_path = get_path!(self, parent, reply);
+        let _profile = self.profile(&parent_path, Some(name));
         let newparent_path = get_path!(self, newparent, reply);
-        debug!("rename: {:?}/{:?} -> {:?}/{:?}", parent_path, name, newparent_path, newname);
-        match self.target.rename(req.info(), &parent_path, name, &newparent_path, newname) {
+        op_debug!("rename: {:?}/{:?} -> {:?}/{:?}", parent_path, name, newparent_path, newname);
+        if let Err(e) = self.hide_if_open(self.request_info(req, parent), newparent, &newparent_path, newname) {
+            reply.error(e);
+            return;
+        }
+        let replaced = {
+            let inodes = self.inodes.lock().unwrap();
+            inodes.get_child(newparent, newname)
+                .filter(|&ino| Some(ino) != inodes.get_child(parent, name))
+        };
+        match self.target.rename(self.request_info(req, parent), &parent_path, name, &newparent_path, newname) {
             Ok(()) => {
-                self.inodes.rename(&parent_path.join(name), Arc::new(newparent_path.join(newname)));
+                if let Some(ino) = replaced {
+                    self.open_files.unlinked(ino);
+                }
+                let (path, new_path) = (parent_path.join(name), newparent_path.join(newname));
+                // Whatever was at the new path before is gone now.
+                self.path_changed(&new_path, None);
+                self.path_changed(&path, Some(&new_path));
+                self.inodes.lock().unwrap().rename(parent, name, newparent, newname);
                 reply.ok()
             },
             Err(e) => reply.error(e),
@@ -384,14 +1684,18 @@ impl<T: FilesystemMT + Sync + Send + 'static> fuser::Filesystem for FuseMT<T> {
         newname: &OsStr,
         reply: fuser::ReplyEntry,
     ) {
+        self.stats.count("link");
+        let _op = enter!(self, req, reply);
+        self.attrs_changed();
         let path = get_path!(self, ino, reply);
+        let _profile = self.profile(&path, None);
         let newparent_path = get_path!(self, newparent, reply);
-        debug!("link: {:?} -> {:?}/{:?}", path, newparent_path, newname);
-        match self.target.link(req.info(), &path, &newparent_path, newname) {
+        op_debug!("link: {:?} -> {:?}/{:?}", path, newparent_path, newname);
+        match self.target.link(self.request_info(req, ino), &path, &newparent_path, newname) {
             Ok((ttl, attr)) => {
                 // NOTE: this results in the new link having a different inode from the original.
                 // This is needed because our inode table is a 1:1 map between paths and inodes.
-                let (new_ino, generation) = self.inodes.add(Arc::new(newparent_path.join(newname)));
+                let (new_ino, generation) = self.inodes.lock().unwrap().add(newparent, newname);
                 reply.entry(&ttl, &fuse_fileattr(attr, new_ino), generation);
             },
             Err(e) => reply.error(e),
@@ -405,10 +1709,35 @@ impl<T: FilesystemMT + Sync + Send + 'static> fuser::Filesystem for FuseMT<T> {
         flags: i32,
         reply: fuser::ReplyOpen,
     ) {
+        self.stats.count("open");
+        let _op = enter!(self, req, reply);
         let path = get_path!(self, ino, reply);
-        debug!("open: {:?}", path);
-        match self.target.open(req.info(), &path, flags as u32) { // TODO: change flags to i32
-            Ok((fh, flags)) => reply.opened(fh, flags),
+        let _profile = self.profile(&path, None);
+        op_debug!("open: {:?}", path);
+        if self.open_handle_supported {
+            match self.target.open_handle(self.request_info(req, ino), &path, flags as u32) {
+                Ok((handle, flags)) => {
+                    let key = self.handles.insert(FileHandleEntry::Managed(handle.into()));
+                    self.opened(ino);
+                    reply.opened(key, flags);
+                    return;
+                },
+                Err(libc::ENOSYS) => {
+                    op_debug!("open_handle not implemented; using open instead");
+                    self.open_handle_supported = false;
+                },
+                Err(e) => {
+                    reply.error(e);
+                    return;
+                }
+            }
+        }
+        match self.target.open(self.request_info(req, ino), &path, flags as u32) { // TODO: change flags to i32
+            Ok(opened) => {
+                let key = self.handles.insert(FileHandleEntry::Raw(opened.fh));
+                self.opened(ino);
+                reply.opened(key, opened.flags())
+            },
             Err(e) => reply.error(e),
         }
     }
@@ -421,28 +1750,119 @@ impl<T: FilesystemMT + Sync + Send + 'static> fuser::Filesystem for FuseMT<T> {
         offset: i64,
         size: u32,
         _flags: i32,                // TODO
-        _lock_owner: Option<u64>,   // TODO
+        lock_owner: Option<u64>,
         reply: fuser::ReplyData,
     ) {
+        self.stats.count("read");
+        let _op = enter!(self, req, reply);
+        self.check_memory();
         let path = get_path!(self, ino, reply);
-        debug!("read: {:?} {:#x} @ {:#x}", path, size, offset);
+        let mut profile = self.profile(&path, None);
+        op_debug!("read: {:?} {:#x} @ {:#x}", path, size, offset);
         if offset < 0 {
             error!("read called with a negative offset");
             reply.error(libc::EINVAL);
             return;
         }
+        let handle = get_handle!(self, fh, reply);
         let target = self.target.clone();
-        let req_info = req.info();
-        self.threadpool_run(move || {
-            target.read(req_info, &path, fh, offset as u64, size, |result| {
-                match result {
-                    Ok(data) => reply.data(data),
-                    Err(e) => reply.error(e),
+        let req_info = RequestInfo {
+            lock_owner: lock_owner.map(LockOwner::from),
+            ..self.request_info(req, ino)
+        };
+        let prefetcher = self.prefetcher.clone();
+        let (lane, normal_load) = admit!(self, Operation::Read, &path, reply);
+        // Keep following the stream while the filesystem is busy, but don't add to its load.
+        let (prefetch_allowed, spawner) = match prefetcher {
+            Some(_) => (
+                normal_load && self.target.load(Operation::Prefetch, &path) == Load::Normal,
+                self.prefetch_spawner(&path, lane),
+            ),
+            None => (false, None),
+        };
+        let path = self.path_ticket(path, &handle);
+        self.threadpool_run_on(lane, Operation::Read, ino, move || {
+            let path = match path.revalidate() {
+                Ok(path) => path,
+                Err(e) => {
+                    reply.error(e);
+                    return;
                 }
-                CallbackResult {
-                    _private: std::marker::PhantomData {},
+            };
+            let offset = offset as u64;
+            let mut served = None;
+            if let Some(data) = prefetcher.as_ref().and_then(|p| p.get(fh, offset, size)) {
+                op_debug!("read: served from prefetch buffer");
+                reply.data(&data);
+                served = Some(data.len());
+            } else {
+                match handle {
+                    FileHandleEntry::Raw(real_fh) => {
+                        target.read(req_info, &path, real_fh, offset, size, |result| {
+                            match result {
+                                Ok(data) => {
+                                    served = Some(data.len());
+                                    reply.data(data);
+                                }
+                                Err(e) => reply.error(e),
+                            }
+                            CallbackResult {
+                                _private: std::marker::PhantomData {},
+                            }
+                        });
+                    },
+                    FileHandleEntry::Managed(ref handle) => {
+                        let (responder, pending) = Responder::pending();
+                        handle.read_deferred(req_info, offset, size, responder);
+                        match pending.take() {
+                            Some(Ok(data)) => {
+                                served = Some(data.len());
+                                reply.data(&data);
+                            }
+                            Some(Err(e)) => reply.error(e),
+                            None => {
+                                // The handle is waiting for data. Answer whenever it's sent, and
+                                // don't prefetch on a stream which can't be read ahead of time.
+                                pending.later(move |result| {
+                                    match result {
+                                        Ok(data) => {
+                                            if let Some(ref mut profile) = profile {
+                                                profile.add_bytes(data.len());
+                                            }
+                                            reply.data(&data)
+                                        }
+                                        Err(e) => reply.error(e),
+                                    }
+                                    drop(profile);
+                                });
+                                return;
+                            }
+                        }
+                    }
                 }
-            });
+            }
+            // Prefetching isn't part of the call.
+            if let (Some(profile), Some(served)) = (profile.as_mut(), served) {
+                profile.add_bytes(served);
+            }
+            drop(profile);
+
+            if let (Some(prefetcher), Some(served)) = (prefetcher, served) {
+                let request = prefetcher.record(fh, ino, offset, size, served)
+                    .filter(|_| prefetch_allowed);
+                if let Some(request) = request {
+                    op_debug!("prefetching {:#x} @ {:#x}", request.size, request.offset);
+                    let prefetch = move || {
+                        let result = read_to_vec(&*target, req_info, &path, &handle,
+                            request.offset, request.size);
+                        prefetcher.complete(fh, request, result);
+                    };
+                    match spawner {
+                        Some(spawner) => spawner.run(prefetch),
+                        None => prefetch(),
+                    }
+                }
+            }
         });
     }
 
@@ -455,27 +1875,82 @@ impl<T: FilesystemMT + Sync + Send + 'static> fuser::Filesystem for FuseMT<T> {
         data: &[u8],
         _write_flags: u32,          // TODO
         flags: i32,
-        _lock_owner: Option<u64>,   // TODO
+        lock_owner: Option<u64>,
         reply: fuser::ReplyWrite,
     ) {
+        self.stats.count("write");
+        let _op = enter!(self, req, reply);
+        self.attrs_changed();
         let path = get_path!(self, ino, reply);
-        debug!("write: {:?} {:#x} @ {:#x}", path, data.len(), offset);
+        let mut profile = self.profile(&path, None);
+        op_debug!("write: {:?} {:#x} @ {:#x}", path, data.len(), offset);
         if offset < 0 {
             error!("write called with a negative offset");
             reply.error(libc::EINVAL);
             return;
         }
+        let (lane, _) = admit!(self, Operation::Write, &path, reply);
         let target = self.target.clone();
-        let req_info = req.info();
+        let req_info = RequestInfo {
+            lock_owner: lock_owner.map(LockOwner::from),
+            ..self.request_info(req, ino)
+        };
+
+        if let Some(ref prefetcher) = self.prefetcher {
+            prefetcher.invalidate_inode(ino);
+        }
+
+        let handle = get_handle!(self, fh, reply);
 
         // The data needs to be copied here before dispatching to the threadpool because it's a
-        // slice of a single buffer that `fuser` re-uses for the entire session.
+        // slice of a single buffer that `fuser` re-uses for the entire session. This is the only
+        // allocation a write needs, other than for queueing it on the threadpool.
         let data_buf = Vec::from(data);
-
-        self.threadpool_run(move|| {
-            match target.write(req_info, &path, fh, offset as u64, data_buf, flags as u32) {
-                Ok(written) => reply.written(written),
-                Err(e) => reply.error(e),
+        let write_buffers = self.write_buffers.clone();
+        write_buffers.add(data_buf.len());
+        let barrier = self.write_barrier.clone();
+        if let Some(ref barrier) = barrier {
+            barrier.begin(fh);
+        }
+        let key = fh;
+        let dirty = self.periodic_sync.as_ref().map(|periodic_sync| periodic_sync.dirty.clone());
+        let written_path = path.clone();
+        let path = self.path_ticket(path, &handle);
+        // The write isn't over until it's answered, which may be after the worker is done with it.
+        let op = self.freezer.enter_admitted();
+        self.threadpool_run_on(lane, Operation::Write, ino, move|| {
+            let len = data_buf.len();
+            let reply = Responder::new(move |result| {
+                write_buffers.remove(len);
+                if let Some(barrier) = barrier {
+                    barrier.end(key);
+                }
+                match result {
+                    Ok(written) => {
+                        if let Some(ref mut profile) = profile {
+                            profile.add_bytes(written as usize);
+                        }
+                        reply.written(written)
+                    }
+                    Err(e) => reply.error(e),
+                }
+                drop(profile);
+                drop(op);
+            });
+            let path = match path.revalidate() {
+                Ok(path) => path,
+                Err(e) => return reply.send(Err(e)),
+            };
+            match handle {
+                FileHandleEntry::Raw(fh) => target.write_deferred(
+                    req_info, &path, fh, offset as u64, data_buf, flags as u32, reply),
+                FileHandleEntry::Managed(handle) => {
+                    let result = handle.write(req_info, offset as u64, data_buf, flags as u32);
+                    if let (Some(dirty), Ok(_)) = (dirty, &result) {
+                        dirty.written(key, &written_path, &handle);
+                    }
+                    reply.send(result)
+                }
             }
         });
     }
@@ -488,14 +1963,40 @@ impl<T: FilesystemMT + Sync + Send + 'static> fuser::Filesystem for FuseMT<T> {
         lock_owner: u64,
         reply: fuser::ReplyEmpty,
     ) {
+        self.stats.count("flush");
+        let _op = enter!(self, req, reply);
         let path = get_path!(self, ino, reply);
-        debug!("flush: {:?}", path);
+        let profile = self.profile(&path, None);
+        op_debug!("flush: {:?}", path);
+        let handle = get_handle!(self, fh, reply);
         let target = self.target.clone();
-        let req_info = req.info();
-        self.threadpool_run(move|| {
-            match target.flush(req_info, &path, fh, lock_owner) {
-                Ok(()) => reply.ok(),
-                Err(e) => reply.error(e),
+        let req_info = self.request_info(req, ino);
+        let barrier = self.write_barrier.clone();
+        let key = fh;
+        let (lane, _) = admit!(self, Operation::Flush, &path, reply);
+        let path = self.path_ticket(path, &handle);
+        let op = self.freezer.enter_admitted();
+        self.threadpool_run_on(lane, Operation::Flush, ino, move|| {
+            if let Some(barrier) = barrier {
+                barrier.wait(key);
+            }
+            let reply = Responder::new(move |result| {
+                match result {
+                    Ok(()) => reply.ok(),
+                    Err(e) => reply.error(e),
+                }
+                drop(profile);
+                drop(op);
+            });
+            let path = match path.revalidate() {
+                Ok(path) => path,
+                Err(e) => return reply.send(Err(e)),
+            };
+            match handle {
+                FileHandleEntry::Raw(fh) =>
+                    target.flush_deferred(req_info, &path, fh, LockOwner(lock_owner), reply),
+                FileHandleEntry::Managed(handle) =>
+                    reply.send(handle.flush(req_info, LockOwner(lock_owner))),
             }
         });
     }
@@ -510,14 +2011,66 @@ impl<T: FilesystemMT + Sync + Send + 'static> fuser::Filesystem for FuseMT<T> {
         flush: bool,
         reply: fuser::ReplyEmpty,
     ) {
+        self.stats.count("release");
+        let _op = enter!(self, req, reply);
         let path = get_path!(self, ino, reply);
-        debug!("release: {:?}", path);
-        match self.target.release(
-            req.info(), &path, fh, flags as u32, lock_owner.unwrap_or(0) /* TODO */, flush)
-        {
-            Ok(()) => reply.ok(),
-            Err(e) => reply.error(e),
+        let _profile = self.profile(&path, None);
+        op_debug!("release: {:?}", path);
+        let handle = get_handle!(self, fh, reply);
+        if let Some(ref periodic_sync) = self.periodic_sync {
+            periodic_sync.dirty.released(fh);
         }
+        if let Some(ref prefetcher) = self.prefetcher {
+            prefetcher.remove(fh);
+        }
+        self.handles.remove(fh);
+        let last = self.open_files.closed(ino);
+        let hidden = last.is_some()
+            && self.silly_rename.as_mut().is_some_and(|silly| silly.closed(ino));
+
+        // Like flush, this waits for the writes in flight on the handle, so it's done on a worker
+        // thread. Releases aren't turned away when the filesystem is busy, since the kernel
+        // doesn't retry them.
+        let target = self.target.clone();
+        let inodes = self.inodes.clone();
+        let req_info = self.request_info(req, ino);
+        let barrier = self.write_barrier.clone();
+        let lane = self.target.classify(Operation::Flush, &path);
+        let ticket = self.path_ticket(path.clone(), &handle);
+        self.threadpool_run(lane, move || {
+            if let Some(barrier) = barrier {
+                barrier.wait(fh);
+            }
+            // The handle has to be released even if its file was removed since.
+            let path = ticket.revalidate().unwrap_or(path);
+            let lock_owner = lock_owner.map(LockOwner);
+            let (result, target_fh) = match handle {
+                FileHandleEntry::Raw(real_fh) => (target.release(
+                    req_info, &path, real_fh, flags as u32, lock_owner, flush), real_fh),
+                FileHandleEntry::Managed(handle) => (handle.release(
+                    req_info, flags as u32, lock_owner, flush), fh),
+            };
+            if last == Some(true) {
+                op_debug!("release: closed the last handle to unlinked file {:?}", path);
+                target.last_release_after_unlink(req_info, &path, target_fh);
+            }
+            if hidden {
+                op_debug!("release: deleting hidden file {:?}", path);
+                if let (Some(parent), Some(name)) = (path.parent(), path.file_name()) {
+                    if let Err(e) = target.unlink(req_info, &FsPath::from(parent), name) {
+                        error!("unable to delete hidden file {:?}: {}", path, e);
+                    }
+                    let mut inodes = inodes.lock().unwrap();
+                    if let Some(parent) = inodes.get_parent(ino) {
+                        inodes.unlink(parent, name);
+                    }
+                }
+            }
+            match result {
+                Ok(()) => reply.ok(),
+                Err(e) => reply.error(e),
+            }
+        });
     }
 
     fn fsync(
@@ -528,14 +2081,38 @@ impl<T: FilesystemMT + Sync + Send + 'static> fuser::Filesystem for FuseMT<T> {
         datasync: bool,
         reply: fuser::ReplyEmpty,
     ) {
+        self.stats.count("fsync");
+        let _op = enter!(self, req, reply);
         let path = get_path!(self, ino, reply);
-        debug!("fsync: {:?}", path);
+        let profile = self.profile(&path, None);
+        op_debug!("fsync: {:?}", path);
+        let handle = get_handle!(self, fh, reply);
         let target = self.target.clone();
-        let req_info = req.info();
-        self.threadpool_run(move|| {
-            match target.fsync(req_info, &path, fh, datasync) {
-                Ok(()) => reply.ok(),
-                Err(e) => reply.error(e),
+        let req_info = self.request_info(req, ino);
+        let barrier = self.write_barrier.clone();
+        let key = fh;
+        let (lane, _) = admit!(self, Operation::Fsync, &path, reply);
+        let path = self.path_ticket(path, &handle);
+        let op = self.freezer.enter_admitted();
+        self.threadpool_run_on(lane, Operation::Fsync, ino, move|| {
+            if let Some(barrier) = barrier {
+                barrier.wait(key);
+            }
+            let reply = Responder::new(move |result| {
+                match result {
+                    Ok(()) => reply.ok(),
+                    Err(e) => reply.error(e),
+                }
+                drop(profile);
+                drop(op);
+            });
+            let path = match path.revalidate() {
+                Ok(path) => path,
+                Err(e) => return reply.send(Err(e)),
+            };
+            match handle {
+                FileHandleEntry::Raw(fh) => target.fsync_deferred(req_info, &path, fh, datasync, reply),
+                FileHandleEntry::Managed(handle) => reply.send(handle.fsync(req_info, datasync)),
             }
         });
     }
@@ -547,12 +2124,32 @@ impl<T: FilesystemMT + Sync + Send + 'static> fuser::Filesystem for FuseMT<T> {
         flags: i32,
         reply: fuser::ReplyOpen,
     ) {
+        self.stats.count("opendir");
+        let _op = enter!(self, req, reply);
         let path = get_path!(self, ino, reply);
-        debug!("opendir: {:?}", path);
-        match self.target.opendir(req.info(), &path, flags as u32) {
-            Ok((fh, flags)) => {
-                let dcache_key = self.directory_cache.new_entry(fh);
-                reply.opened(dcache_key, flags);
+        let _profile = self.profile(&path, None);
+        op_debug!("opendir: {:?}", path);
+        if self.opendir_handle_supported {
+            match self.target.opendir_handle(self.request_info(req, ino), &path, flags as u32) {
+                Ok((handle, flags)) => {
+                    let dcache_key = self.directory_cache.new_handle_entry(ino, handle);
+                    reply.opened(dcache_key, flags);
+                    return;
+                },
+                Err(libc::ENOSYS) => {
+                    op_debug!("opendir_handle not implemented; using opendir instead");
+                    self.opendir_handle_supported = false;
+                },
+                Err(e) => {
+                    reply.error(e);
+                    return;
+                }
+            }
+        }
+        match self.target.opendir(self.request_info(req, ino), &path, flags as u32) {
+            Ok(opened) => {
+                let dcache_key = self.directory_cache.new_entry(ino, opened.fh);
+                reply.opened(dcache_key, opened.flags());
             },
             Err(e) => reply.error(e),
         }
@@ -566,8 +2163,12 @@ impl<T: FilesystemMT + Sync + Send + 'static> fuser::Filesystem for FuseMT<T> {
         offset: i64,
         mut reply: fuser::ReplyDirectory,
     ) {
+        self.stats.count("readdir");
+        let _op = enter!(self, req, reply);
+        self.check_memory();
         let path = get_path!(self, ino, reply);
-        debug!("readdir: {:?} @ {}", path, offset);
+        let _profile = self.profile(&path, None);
+        op_debug!("readdir: {:?} @ {}", path, offset);
 
         if offset < 0 {
             error!("readdir called with a negative offset");
@@ -575,43 +2176,21 @@ impl<T: FilesystemMT + Sync + Send + 'static> fuser::Filesystem for FuseMT<T> {
             return;
         }
 
-        let entries: &[DirectoryEntry] = {
-            let dcache_entry = self.directory_cache.get_mut(fh);
-            if let Some(ref entries) = dcache_entry.entries {
-                entries
-            } else {
-                debug!("entries not yet fetched; requesting with fh {}", dcache_entry.fh);
-                match self.target.readdir(req.info(), &path, dcache_entry.fh) {
-                    Ok(entries) => {
-                        dcache_entry.entries = Some(entries);
-                        dcache_entry.entries.as_ref().unwrap()
-                    },
-                    Err(e) => {
-                        reply.error(e);
-                        return;
-                    }
-                }
-            }
-        };
+        // Before invalidating, in case any of these are already out of date.
+        self.cache_deferred_listings();
+        self.apply_invalidations();
 
-        let parent_inode = if ino == 1 {
-            ino
-        } else {
-            let parent_path: &Path = path.parent().unwrap();
-            match self.inodes.get_inode(parent_path) {
-                Some(inode) => inode,
-                None => {
-                    error!("readdir: unable to get inode for parent of {:?}", path);
-                    reply.error(libc::EIO);
-                    return;
-                }
+        let parent_inode = match self.inodes.lock().unwrap().get_parent(ino) {
+            Some(inode) => inode,
+            None => {
+                error!("readdir: unable to get inode for parent of {:?}", path);
+                reply.error(libc::EIO);
+                return;
             }
         };
 
-        debug!("directory has {} entries", entries.len());
-
-        for (index, entry) in entries.iter().skip(offset as usize).enumerate() {
-            let entry_inode = if entry.name == Path::new(".") {
+        let entry_inode = move |entry: &DirectoryEntry| {
+            if entry.name == Path::new(".") {
                 ino
             } else if entry.name == Path::new("..") {
                 parent_inode
@@ -620,23 +2199,216 @@ impl<T: FilesystemMT + Sync + Send + 'static> fuser::Filesystem for FuseMT<T> {
                 // populate its inode cache with this value, so subsequent access to these
                 // files is going to involve it issuing a LOOKUP operation anyway.
                 !1
+            }
+        };
+
+        // The `.` and `..` entries FuseMT adds come first, at offsets 0 and 1.
+        let mut offset = offset as u64;
+        let dots = if self.dot_entries { DOT_ENTRIES.len() as u64 } else { 0 };
+        let mut dots_added = 0;
+
+        // If the filesystem can list the directory a page at a time, skip the cache entirely.
+        let req_info = self.request_info(req, ino);
+        if self.pages(fh) {
+            for index in offset .. dots {
+                let dot = dot_entry(DOT_ENTRIES[index as usize]);
+                if reply.add(entry_inode(&dot), index as i64 + 1, dot.kind, &dot.name) {
+                    reply.ok();
+                    return;
+                }
+                dots_added += 1;
+            }
+            let prefetching = self.attr_prefetch.is_some();
+            let mut listed = vec![];
+            let mut add = |entry: &DirectoryEntry, next: u64| {
+                op_debug!("readdir: adding entry {:?}, next offset {}", entry.name, next);
+                let full = reply.add(entry_inode(entry), next as i64, entry.kind,
+                    entry.name.as_os_str());
+                if prefetching && !full && entry.name != "." && entry.name != ".." {
+                    listed.push(entry.name.clone());
+                }
+                full
             };
+            match self.list_page(req_info, fh, &path, offset, dots, &mut add) {
+                Ok(()) => {
+                    reply.ok();
+                    self.start_attr_prefetch(req_info, &path, listed);
+                    return;
+                }
+                Err(libc::ENOSYS) => {
+                    // Don't list again the dot entries already added.
+                    offset += dots_added;
+                }
+                Err(e) => {
+                    reply.error(e);
+                    return;
+                }
+            }
+        }
+
+        let entries = match self.listing(req_info, fh, &path, offset, dots) {
+            Ok(Listing::Cached(entries)) => entries,
+            Ok(Listing::Fetched(entries)) => {
+                let names = if self.attr_prefetch.is_some() {
+                    entries.iter()
+                        .filter(|entry| entry.name != "." && entry.name != "..")
+                        .map(|entry| entry.name.clone())
+                        .collect()
+                } else {
+                    vec![]
+                };
+                self.start_attr_prefetch(req_info, &path, names);
+                entries
+            }
+            Ok(Listing::Later(pending)) => {
+                // Listings sent later are answered from wherever they're sent, and kept for the
+                // next readdir call to cache.
+                let listings = self.deferred_listings.clone();
+                let op = self.freezer.enter_admitted();
+                pending.later(move |result| {
+                    match result {
+                        Ok(mut entries) => {
+                            add_dot_entries(&mut entries, dots);
+                            add_entries(&entries, offset, dots, |entry, next| {
+                                reply.add(entry_inode(entry), next as i64, entry.kind, &entry.name)
+                            });
+                            listings.lock().unwrap().push((fh, entries));
+                            reply.ok();
+                        }
+                        Err(e) => reply.error(e),
+                    }
+                    drop(op);
+                });
+                return;
+            }
+            Err(e) => {
+                reply.error(e);
+                return;
+            }
+        };
+
+        let finished = add_entries(&entries, offset, dots, |entry, next| {
+            reply.add(entry_inode(entry), next as i64, entry.kind, &entry.name)
+        });
+        if finished {
+            self.directory_cache.get_mut(fh).finished = true;
+        }
+        reply.ok();
+    }
+
+    fn readdirplus(
+        &mut self,
+        req: &fuser::Request<'_>,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        mut reply: fuser::ReplyDirectoryPlus,
+    ) {
+        self.stats.count("readdirplus");
+        let _op = enter!(self, req, reply);
+        self.check_memory();
+        let path = get_path!(self, ino, reply);
+        let _profile = self.profile(&path, None);
+        op_debug!("readdirplus: {:?} @ {}", path, offset);
+
+        if offset < 0 {
+            error!("readdirplus called with a negative offset");
+            reply.error(libc::EINVAL);
+            return;
+        }
 
-            debug!("readdir: adding entry #{}, {:?}", offset + index as i64, entry.name);
+        self.cache_deferred_listings();
+        self.apply_invalidations();
 
-            let buffer_full: bool = reply.add(
-                entry_inode,
-                offset + index as i64 + 1,
-                entry.kind,
-                entry.name.as_os_str());
+        let parent_inode = match self.inodes.lock().unwrap().get_parent(ino) {
+            Some(inode) => inode,
+            None => {
+                error!("readdirplus: unable to get inode for parent of {:?}", path);
+                reply.error(libc::EIO);
+                return;
+            }
+        };
 
-            if buffer_full {
-                debug!("readdir: reply buffer is full");
-                break;
+        // The attributes of entries listed without them are fetched on the worker threads,
+        // which add each entry to the inode table as the reply takes it.
+        let offset = offset as u64;
+        let dots = if self.dot_entries { DOT_ENTRIES.len() as u64 } else { 0 };
+        let req_info = self.request_info(req, ino);
+        let (target, inodes) = (self.target.clone(), self.inodes.clone());
+
+        // As with readdir, filesystems which list a page at a time skip the cache, and the offsets
+        // the kernel is given are theirs, so the two can be mixed on the same handle.
+        if self.pages(fh) {
+            match self.plus_page(req_info, fh, &path, offset, dots) {
+                Ok(page) => {
+                    self.threadpool_run(Lane::DEFAULT, move || {
+                        for (entry, next) in &page {
+                            if add_entry_plus(&*target, &inodes, req_info, &path, ino, parent_inode,
+                                entry, *next, &mut reply)
+                            {
+                                break;
+                            }
+                        }
+                        reply.ok();
+                    });
+                    return;
+                }
+                Err(libc::ENOSYS) => (),
+                Err(e) => {
+                    reply.error(e);
+                    return;
+                }
             }
         }
 
-        reply.ok();
+        let entries = match self.listing(req_info, fh, &path, offset, dots) {
+            Ok(Listing::Cached(entries)) | Ok(Listing::Fetched(entries)) => entries,
+            Ok(Listing::Later(pending)) => {
+                // As in readdir, listings sent later are answered from wherever they're sent, and
+                // kept for the next call to cache.
+                let listings = self.deferred_listings.clone();
+                let spawner = self.spawner(Lane::DEFAULT, None);
+                let op = self.freezer.enter_admitted();
+                pending.later(move |result| {
+                    let mut entries = match result {
+                        Ok(entries) => entries,
+                        Err(e) => {
+                            reply.error(e);
+                            drop(op);
+                            return;
+                        }
+                    };
+                    add_dot_entries(&mut entries, dots);
+                    let add = move || {
+                        add_entries_plus(&*target, &inodes, req_info, &path, ino, parent_inode,
+                            &entries, offset, dots, &mut reply);
+                        listings.lock().unwrap().push((fh, entries));
+                        reply.ok();
+                        drop(op);
+                    };
+                    match spawner {
+                        Some(spawner) => spawner.run(add),
+                        None => add(),
+                    }
+                });
+                return;
+            }
+            Err(e) => {
+                reply.error(e);
+                return;
+            }
+        };
+
+        if start_index(&entries, offset, dots) >= entries.len() {
+            self.directory_cache.get_mut(fh).finished = true;
+            reply.ok();
+            return;
+        }
+        self.threadpool_run(Lane::DEFAULT, move || {
+            add_entries_plus(&*target, &inodes, req_info, &path, ino, parent_inode, &entries,
+                offset, dots, &mut reply);
+            reply.ok();
+        });
     }
 
     fn releasedir(
@@ -647,13 +2419,22 @@ impl<T: FilesystemMT + Sync + Send + 'static> fuser::Filesystem for FuseMT<T> {
         flags: i32,
         reply: fuser::ReplyEmpty,
     ) {
+        self.stats.count("releasedir");
+        let _op = enter!(self, req, reply);
         let path = get_path!(self, ino, reply);
-        debug!("releasedir: {:?}", path);
-        let real_fh = self.directory_cache.real_fh(fh);
-        match self.target.releasedir(req.info(), &path, real_fh, flags as u32) {
+        let _profile = self.profile(&path, None);
+        op_debug!("releasedir: {:?}", path);
+        let dcache_entry = self.directory_cache.get(fh);
+        let result = if let Some(ref handle) = dcache_entry.handle {
+            handle.release(self.request_info(req, ino), flags as u32)
+        } else {
+            self.target.releasedir(self.request_info(req, ino), &path, dcache_entry.fh, flags as u32)
+        };
+        match result {
             Ok(()) => reply.ok(),
             Err(e) => reply.error(e),
         }
+        self.cache_deferred_listings();
         self.directory_cache.delete(fh);
     }
 
@@ -665,10 +2446,18 @@ impl<T: FilesystemMT + Sync + Send + 'static> fuser::Filesystem for FuseMT<T> {
         datasync: bool,
         reply: fuser::ReplyEmpty,
     ) {
+        self.stats.count("fsyncdir");
+        let _op = enter!(self, req, reply);
         let path = get_path!(self, ino, reply);
-        debug!("fsyncdir: {:?} (datasync: {:?})", path, datasync);
-        let real_fh = self.directory_cache.real_fh(fh);
-        match self.target.fsyncdir(req.info(), &path, real_fh, datasync) {
+        let _profile = self.profile(&path, None);
+        op_debug!("fsyncdir: {:?} (datasync: {:?})", path, datasync);
+        let dcache_entry = self.directory_cache.get(fh);
+        let result = if let Some(ref handle) = dcache_entry.handle {
+            handle.fsync(self.request_info(req, ino), datasync)
+        } else {
+            self.target.fsyncdir(self.request_info(req, ino), &path, dcache_entry.fh, datasync)
+        };
+        match result {
             Ok(()) => reply.ok(),
             Err(e) => reply.error(e),
         }
@@ -680,14 +2469,23 @@ impl<T: FilesystemMT + Sync + Send + 'static> fuser::Filesystem for FuseMT<T> {
         ino: u64,
         reply: fuser::ReplyStatfs,
     ) {
+        self.stats.count("statfs");
+        let _op = enter!(self, req, reply);
         let path = if ino == 1 {
-            Arc::new(PathBuf::from("/"))
+            FsPath::from("/")
         } else {
             get_path!(self, ino, reply)
         };
 
-        debug!("statfs: {:?}", path);
-        match self.target.statfs(req.info(), &path) {
+        op_debug!("statfs: {:?}", path);
+        let result = self.target.statfs(self.request_info(req, ino), &path).and_then(|statfs| {
+            Ok(match self.target.quota(self.request_info(req, ino), &path)? {
+                Some(quota) => statfs.with_quota(&quota),
+                None => statfs,
+            })
+        });
+        match result {
+            // FUSE has no way to send `fsid` or `flags`; the kernel fills in its own.
             Ok(statfs) => reply.statfs(
                 statfs.blocks,
                 statfs.bfree,
@@ -711,10 +2509,18 @@ impl<T: FilesystemMT + Sync + Send + 'static> fuser::Filesystem for FuseMT<T> {
         position: u32,
         reply: fuser::ReplyEmpty,
     ) {
+        self.stats.count("setxattr");
+        let _op = enter!(self, req, reply);
+        self.attrs_changed();
         let path = get_path!(self, ino, reply);
-        debug!("setxattr: {:?} {:?} ({} bytes, flags={:#x}, pos={:#x}",
+        let _profile = self.profile(&path, None);
+        op_debug!("setxattr: {:?} {:?} ({} bytes, flags={:#x}, pos={:#x}",
             path, name, value.len(), flags, position);
-        match self.target.setxattr(req.info(), &path, name, value, flags as u32, position) {
+        if let Some(ref mut cache) = self.xattr_cache {
+            cache.invalidate(ino);
+        }
+        let position = XattrPosition::new(name, position);
+        match self.target.setxattr(self.request_info(req, ino), &path, name, value, flags as u32, position) {
             Ok(()) => reply.ok(),
             Err(e) => reply.error(e),
         }
@@ -728,19 +2534,50 @@ impl<T: FilesystemMT + Sync + Send + 'static> fuser::Filesystem for FuseMT<T> {
         size: u32,
         reply: fuser::ReplyXattr,
     ) {
+        self.stats.count("getxattr");
+        let _op = enter!(self, req, reply);
+        let hotspots = self.hotspots.as_ref().filter(|_| name == HOTSPOTS_XATTR);
+        if ino == fuser::FUSE_ROOT_ID
+            && (name == STATS_XATTR || name == STATE_XATTR || hotspots.is_some())
+        {
+            let value = if name == STATS_XATTR {
+                self.format_stats()
+            } else if let Some(hotspots) = hotspots {
+                hotspots.report()
+            } else {
+                self.dump_state().to_json()
+            };
+            if size == 0 {
+                reply.size(value.len() as u32);
+            } else if (size as usize) < value.len() {
+                reply.error(libc::ERANGE);
+            } else {
+                reply.data(value.as_bytes());
+            }
+            return;
+        }
         let path = get_path!(self, ino, reply);
-        debug!("getxattr: {:?} {:?}", path, name);
-        match self.target.getxattr(req.info(), &path, name, size) {
+        let _profile = self.profile(&path, None);
+        op_debug!("getxattr: {:?} {:?}", path, name);
+        let key = || XattrKey { ino, name: Some(name.to_owned()), pid: req.pid() };
+        let target = self.target.clone();
+        let req_info = self.request_info(req, ino);
+        let fetch = |size| target.getxattr(req_info, &path, name, size);
+        let reply = match self.cached_xattr(key, size, reply, fetch) {
+            Some(reply) => reply,
+            None => return,
+        };
+        match fit_xattr(self.target.getxattr(self.request_info(req, ino), &path, name, size), size) {
             Ok(Xattr::Size(size)) => {
-                debug!("getxattr: sending size {}", size);
+                op_debug!("getxattr: sending size {}", size);
                 reply.size(size)
             },
             Ok(Xattr::Data(vec)) => {
-                debug!("getxattr: sending {} bytes", vec.len());
+                op_debug!("getxattr: sending {} bytes", vec.len());
                 reply.data(&vec)
             },
             Err(e) => {
-                debug!("getxattr: error {}", e);
+                op_debug!("getxattr: error {}", e);
                 reply.error(e)
             },
         }
@@ -753,15 +2590,26 @@ impl<T: FilesystemMT + Sync + Send + 'static> fuser::Filesystem for FuseMT<T> {
         size: u32,
         reply: fuser::ReplyXattr,
     ) {
+        self.stats.count("listxattr");
+        let _op = enter!(self, req, reply);
         let path = get_path!(self, ino, reply);
-        debug!("listxattr: {:?}", path);
-        match self.target.listxattr(req.info(), &path, size) {
+        let _profile = self.profile(&path, None);
+        op_debug!("listxattr: {:?}", path);
+        let key = || XattrKey { ino, name: None, pid: req.pid() };
+        let target = self.target.clone();
+        let req_info = self.request_info(req, ino);
+        let fetch = |size| target.listxattr(req_info, &path, size);
+        let reply = match self.cached_xattr(key, size, reply, fetch) {
+            Some(reply) => reply,
+            None => return,
+        };
+        match fit_xattr(self.target.listxattr(self.request_info(req, ino), &path, size), size) {
             Ok(Xattr::Size(size)) => {
-                debug!("listxattr: sending size {}", size);
+                op_debug!("listxattr: sending size {}", size);
                 reply.size(size)
             },
             Ok(Xattr::Data(vec)) => {
-                debug!("listxattr: sending {} bytes", vec.len());
+                op_debug!("listxattr: sending {} bytes", vec.len());
                 reply.data(&vec)
             }
             Err(e) => reply.error(e),
@@ -775,9 +2623,16 @@ impl<T: FilesystemMT + Sync + Send + 'static> fuser::Filesystem for FuseMT<T> {
         name: &OsStr,
         reply: fuser::ReplyEmpty,
     ) {
+        self.stats.count("removexattr");
+        let _op = enter!(self, req, reply);
+        self.attrs_changed();
         let path = get_path!(self, ino, reply);
-        debug!("removexattr: {:?}, {:?}", path, name);
-        match self.target.removexattr(req.info(), &path, name) {
+        let _profile = self.profile(&path, None);
+        op_debug!("removexattr: {:?}, {:?}", path, name);
+        if let Some(ref mut cache) = self.xattr_cache {
+            cache.invalidate(ino);
+        }
+        match self.target.removexattr(self.request_info(req, ino), &path, name) {
             Ok(()) => reply.ok(),
             Err(e) => reply.error(e),
         }
@@ -790,9 +2645,12 @@ impl<T: FilesystemMT + Sync + Send + 'static> fuser::Filesystem for FuseMT<T> {
         mask: i32,
         reply: fuser::ReplyEmpty,
     ) {
+        self.stats.count("access");
+        let _op = enter!(self, req, reply);
         let path = get_path!(self, ino, reply);
-        debug!("access: {:?}, mask={:#o}", path, mask);
-        match self.target.access(req.info(), &path, mask as u32) {
+        let _profile = self.profile(&path, None);
+        op_debug!("access: {:?}, mask={:#o}", path, mask);
+        match self.target.access(self.request_info(req, ino), &path, mask as u32) {
             Ok(()) => reply.ok(),
             Err(e) => reply.error(e),
         }
@@ -804,28 +2662,284 @@ impl<T: FilesystemMT + Sync + Send + 'static> fuser::Filesystem for FuseMT<T> {
         parent: u64,
         name: &OsStr,
         mode: u32,
-        _umask: u32, // TODO
+        umask: u32,
         flags: i32,
         reply: fuser::ReplyCreate,
     ) {
+        self.stats.count("create");
+        let _op = enter!(self, req, reply);
+        self.attrs_changed();
         let parent_path = get_path!(self, parent, reply);
-        debug!("create: {:?}/{:?} (mode={:#o}, flags={:#x})", parent_path, name, mode, flags);
-        match self.target.create(req.info(), &parent_path, name, mode, flags as u32) {
+        let _profile = self.profile(&parent_path, Some(name));
+        op_debug!("create: {:?}/{:?} (mode={:#o}, flags={:#x})", parent_path, name, mode, flags);
+        let req = RequestInfo { umask: Some(umask), ..self.request_info(req, parent) };
+        if self.create_handle_supported {
+            match self.target.create_handle(req, &parent_path, name, mode, flags as u32) {
+                Ok(create) => {
+                    let (ino, generation) = self.inodes.lock().unwrap().add(parent, name);
+                    let attr = fuse_fileattr(create.attr, ino);
+                    let key = self.handles.insert(FileHandleEntry::Managed(create.handle.into()));
+                    self.opened(ino);
+                    reply.created(&create.ttl, &attr, generation, key, create.flags);
+                    return;
+                },
+                Err(libc::ENOSYS) => {
+                    op_debug!("create_handle not implemented; using create instead");
+                    self.create_handle_supported = false;
+                },
+                Err(e) => {
+                    reply.error(e);
+                    return;
+                }
+            }
+        }
+        match self.target.create(req, &parent_path, name, mode, flags as u32) {
             Ok(create) => {
-                let (ino, generation) = self.inodes.add(Arc::new(parent_path.join(name)));
+                let (ino, generation) = self.inodes.lock().unwrap().add(parent, name);
                 let attr = fuse_fileattr(create.attr, ino);
-                reply.created(&create.ttl, &attr, generation, create.fh, create.flags);
+                let key = self.handles.insert(FileHandleEntry::Raw(create.fh));
+                self.opened(ino);
+                reply.created(&create.ttl, &attr, generation, key, create.flags);
             },
             Err(e) => reply.error(e),
         }
     }
 
-    // getlk
+    fn getlk(
+        &mut self,
+        req: &fuser::Request<'_>,
+        ino: u64,
+        fh: u64,
+        lock_owner: u64,
+        start: u64,
+        end: u64,
+        typ: i32,
+        pid: u32,
+        reply: fuser::ReplyLock,
+    ) {
+        self.stats.count("getlk");
+        let _op = enter!(self, req, reply);
+        let path = get_path!(self, ino, reply);
+        let _profile = self.profile(&path, None);
+        op_debug!("getlk: {:?} {:#x}-{:#x} (type={}, owner={:#x})", path, start, end, typ, lock_owner);
+        let lock = FileLock { start, end, typ, pid };
+        let result = match get_handle!(self, fh, reply) {
+            FileHandleEntry::Raw(real_fh) => self.target.getlk(
+                self.request_info(req, ino), &path, real_fh, lock_owner.into(), lock),
+            FileHandleEntry::Managed(handle) => handle.getlk(
+                self.request_info(req, ino), lock_owner.into(), lock),
+        };
+        match result {
+            Ok(lock) => reply.locked(lock.start, lock.end, lock.typ, lock.pid),
+            Err(e) => reply.error(e),
+        }
+    }
 
-    // setlk
+    fn setlk(
+        &mut self,
+        req: &fuser::Request<'_>,
+        ino: u64,
+        fh: u64,
+        lock_owner: u64,
+        start: u64,
+        end: u64,
+        typ: i32,
+        pid: u32,
+        sleep: bool,
+        reply: fuser::ReplyEmpty,
+    ) {
+        // With only flock locks passed on, these are all flock calls in disguise, of whole files.
+        let flock = if self.lock_mode == LockMode::Flock {
+            self.stats.count("flock");
+            Some(flock_operation(typ, sleep))
+        } else {
+            self.stats.count("setlk");
+            None
+        };
+        let _op = enter!(self, req, reply);
+        let path = get_path!(self, ino, reply);
+        let profile = self.profile(&path, None);
+        match flock {
+            Some(operation) => op_debug!("flock: {:?} (operation={:#x}, owner={:#x})", path,
+                operation, lock_owner),
+            None => op_debug!("setlk: {:?} {:#x}-{:#x} (type={}, owner={:#x}, sleep={})", path,
+                start, end, typ, lock_owner, sleep),
+        }
+        let handle = get_handle!(self, fh, reply);
+        let target = self.target.clone();
+        let req_info = self.request_info(req, ino);
+        let lock = FileLock { start, end, typ, pid };
+        let call = move || {
+            let owner = LockOwner(lock_owner);
+            let result = match (handle, flock) {
+                (FileHandleEntry::Raw(real_fh), None) => target.setlk(
+                    req_info, &path, real_fh, owner, lock, sleep),
+                (FileHandleEntry::Raw(real_fh), Some(operation)) => target.flock(
+                    req_info, &path, real_fh, owner, operation),
+                (FileHandleEntry::Managed(handle), None) => handle.setlk(
+                    req_info, owner, lock, sleep),
+                (FileHandleEntry::Managed(handle), Some(operation)) => handle.flock(
+                    req_info, owner, operation),
+            };
+            match result {
+                Ok(()) => reply.ok(),
+                Err(e) => reply.error(e),
+            }
+            drop(profile);
+        };
+        if sleep {
+            // Waiting for a lock can take as long as whoever holds it likes, and the call which
+            // releases it has to be able to come in meanwhile.
+            self.threadpool_run(Lane::DEFAULT, call);
+        } else {
+            call();
+        }
+    }
 
     // bmap
 
+    fn fallocate(
+        &mut self,
+        req: &fuser::Request<'_>,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        length: i64,
+        mode: i32,
+        reply: fuser::ReplyEmpty,
+    ) {
+        self.stats.count("fallocate");
+        let _op = enter!(self, req, reply);
+        self.attrs_changed();
+        let path = get_path!(self, ino, reply);
+        let profile = self.profile(&path, None);
+        op_debug!("fallocate: {:?} {:#x} @ {:#x} (mode={:#x})", path, length, offset, mode);
+        if offset < 0 || length <= 0 {
+            reply.error(libc::EINVAL);
+            return;
+        }
+        let handle = get_handle!(self, fh, reply);
+        if let Some(ref prefetcher) = self.prefetcher {
+            prefetcher.invalidate_inode(ino);
+        }
+        let target = self.target.clone();
+        let req_info = self.request_info(req, ino);
+        let (lane, _) = admit!(self, Operation::Fallocate, &path, reply);
+        let path = self.path_ticket(path, &handle);
+        self.threadpool_run_on(lane, Operation::Fallocate, ino, move|| {
+            let result = match handle {
+                FileHandleEntry::Raw(real_fh) => path.revalidate().and_then(|path|
+                    target.fallocate(req_info, &path, real_fh, offset as u64, length as u64, mode)),
+                FileHandleEntry::Managed(handle) => {
+                    match handle.fallocate(req_info, offset as u64, length as u64, mode) {
+                        // ENOSYS would turn it off for the whole mount.
+                        Err(libc::ENOSYS) => Err(libc::EOPNOTSUPP),
+                        other => other,
+                    }
+                }
+            };
+            drop(profile);
+            match result {
+                Ok(()) => reply.ok(),
+                Err(e) => reply.error(e),
+            }
+        });
+    }
+
+    fn lseek(
+        &mut self,
+        req: &fuser::Request<'_>,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        whence: i32,
+        reply: fuser::ReplyLseek,
+    ) {
+        self.stats.count("lseek");
+        let _op = enter!(self, req, reply);
+        let path = get_path!(self, ino, reply);
+        let _profile = self.profile(&path, None);
+        op_debug!("lseek: {:?} {:#x} (whence={})", path, offset, whence);
+        if offset < 0 {
+            reply.error(libc::ENXIO);
+            return;
+        }
+        let result = match get_handle!(self, fh, reply) {
+            FileHandleEntry::Raw(real_fh) => self.target.lseek(
+                self.request_info(req, ino), &path, real_fh, offset as u64, whence),
+            FileHandleEntry::Managed(handle) => {
+                match handle.lseek(self.request_info(req, ino), offset as u64, whence) {
+                    // ENOSYS would turn it off for the whole mount, not just this handle.
+                    Err(libc::ENOSYS) => Err(libc::EOPNOTSUPP),
+                    other => other,
+                }
+            }
+        };
+        match result {
+            Ok(offset) => reply.offset(offset as i64),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn copy_file_range(
+        &mut self,
+        req: &fuser::Request<'_>,
+        ino_in: u64,
+        fh_in: u64,
+        offset_in: i64,
+        ino_out: u64,
+        fh_out: u64,
+        offset_out: i64,
+        len: u64,
+        flags: u32,
+        reply: fuser::ReplyWrite,
+    ) {
+        self.stats.count("copy_file_range");
+        let _op = enter!(self, req, reply);
+        self.attrs_changed();
+        let path_in = get_path!(self, ino_in, reply);
+        let path_out = get_path!(self, ino_out, reply);
+        // Counted as a write to the file copied to.
+        let mut profile = self.profile(&path_out, None);
+        op_debug!("copy_file_range: {:?} @ {:#x} -> {:?} @ {:#x}, {:#x} bytes", path_in, offset_in,
+            path_out, offset_out, len);
+        if offset_in < 0 || offset_out < 0 {
+            reply.error(libc::EINVAL);
+            return;
+        }
+        let handles = (get_handle!(self, fh_in, reply), get_handle!(self, fh_out, reply));
+        let (real_fh_in, real_fh_out) = match handles {
+            (FileHandleEntry::Raw(fh_in), FileHandleEntry::Raw(fh_out)) => (fh_in, fh_out),
+            // The kernel falls back to reading and writing for this error.
+            _ => {
+                reply.error(libc::EOPNOTSUPP);
+                return;
+            }
+        };
+        if let Some(ref prefetcher) = self.prefetcher {
+            prefetcher.invalidate_inode(ino_out);
+        }
+        let target = self.target.clone();
+        let req_info = self.request_info(req, ino_in);
+        let (lane, _) = admit!(self, Operation::CopyFileRange, &path_in, reply);
+        let path_in = self.path_ticket(path_in, &FileHandleEntry::Raw(real_fh_in));
+        let path_out = self.path_ticket(path_out, &FileHandleEntry::Raw(real_fh_out));
+        self.threadpool_run_on(lane, Operation::CopyFileRange, ino_out, move|| {
+            let paths = path_in.revalidate().and_then(|p| Ok((p, path_out.revalidate()?)));
+            let result = paths.and_then(|(path_in, path_out)|
+                target.copy_file_range(req_info, &path_in, real_fh_in, offset_in as u64,
+                    &path_out, real_fh_out, offset_out as u64, len, flags));
+            if let (Some(profile), Ok(copied)) = (profile.as_mut(), &result) {
+                profile.add_bytes(*copied as usize);
+            }
+            drop(profile);
+            match result {
+                Ok(copied) => reply.written(copied),
+                Err(e) => reply.error(e),
+            }
+        });
+    }
+
     #[cfg(target_os = "macos")]
     fn setvolname(
         &mut self,
@@ -833,14 +2947,45 @@ impl<T: FilesystemMT + Sync + Send + 'static> fuser::Filesystem for FuseMT<T> {
         name: &OsStr,
         reply: fuser::ReplyEmpty,
     ) {
-        debug!("setvolname: {:?}", name);
+        self.stats.count("setvolname");
+        let _op = enter!(self, req, reply);
+        op_debug!("setvolname: {:?}", name);
         match self.target.setvolname(req.info(), name) {
             Ok(()) => reply.ok(),
             Err(e) => reply.error(e),
         }
     }
 
-    // exchange (macOS only, undocumented)
+    #[cfg(target_os = "macos")]
+    fn exchange(
+        &mut self,
+        req: &fuser::Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        newparent: u64,
+        newname: &OsStr,
+        options: u64,
+        reply: fuser::ReplyEmpty,
+    ) {
+        self.stats.count("exchange");
+        let _op = enter!(self, req, reply);
+        self.attrs_changed();
+        let parent_path = get_path!(self, parent, reply);
+        let _profile = self.profile(&parent_path, Some(name));
+        let newparent_path = get_path!(self, newparent, reply);
+        op_debug!("exchange: {:?}/{:?} <-> {:?}/{:?}", parent_path, name, newparent_path, newname);
+        match self.target.exchange(self.request_info(req, parent), &parent_path, name, &newparent_path, newname, options) {
+            Ok(()) => {
+                // Queued calls on either file can't follow a swap, so they fail as if the files
+                // were replaced, rather than reach the wrong one.
+                self.path_changed(&parent_path.join(name), None);
+                self.path_changed(&newparent_path.join(newname), None);
+                self.inodes.lock().unwrap().exchange(parent, name, newparent, newname);
+                reply.ok()
+            },
+            Err(e) => reply.error(e),
+        }
+    }
 
     #[cfg(target_os = "macos")]
     fn getxtimes(
@@ -849,9 +2994,12 @@ impl<T: FilesystemMT + Sync + Send + 'static> fuser::Filesystem for FuseMT<T> {
         ino: u64,
         reply: fuser::ReplyXTimes,
     ) {
+        self.stats.count("getxtimes");
+        let _op = enter!(self, req, reply);
         let path = get_path!(self, ino, reply);
-        debug!("getxtimes: {:?}", path);
-        match self.target.getxtimes(req.info(), &path) {
+        let _profile = self.profile(&path, None);
+        op_debug!("getxtimes: {:?}", path);
+        match self.target.getxtimes(self.request_info(req, ino), &path) {
             Ok(xtimes) => {
                 reply.xtimes(xtimes.bkuptime, xtimes.crtime);
             }
@@ -859,3 +3007,57 @@ impl<T: FilesystemMT + Sync + Send + 'static> fuser::Filesystem for FuseMT<T> {
         }
     }
 }
+
+#[test]
+fn test_readdir_mixed_with_readdirplus() {
+    struct Fs;
+    impl FilesystemMT for Fs {
+        fn readdir(&self, _req: RequestInfo, _path: &FsPath, _fh: u64) -> ResultReaddir {
+            panic!("listed whole");
+        }
+        fn readdir_at(&self, _req: RequestInfo, _path: &FsPath, _fh: u64, offset: u64, add: &mut dyn FnMut(&DirectoryEntry, u64) -> bool) -> ResultEmpty {
+            // Entry n is followed by the one with cookie 100 + 10n.
+            let start = offset.saturating_sub(100) / 10;
+            for n in start .. 50 {
+                let entry = DirectoryEntry { name: format!("e{:02}", n).into(),
+                    kind: crate::FileType::RegularFile, offset: None, attr: None };
+                if add(&entry, 100 + 10 * (n + 1)) {
+                    break;
+                }
+            }
+            Ok(())
+        }
+    }
+
+    let mut fuse = FuseMT::new(Fs, 0).synthesize_dot_entries();
+    let fh = fuse.directory_cache.new_entry(1, 0);
+    let (req, path, dots) = (RequestInfo::new(0, 0, 0, 0), &FsPath::from("/"), 2);
+
+    // Take turns with readdirplus calls and readdir calls of a few entries each, resuming each
+    // at the offset of the last entry the one before gave.
+    let mut names = vec![];
+    let mut offset = 0;
+    for call in 0 .. {
+        let page = if call % 2 == 0 {
+            fuse.plus_page(req, fh, path, offset, dots).unwrap()
+        } else {
+            let mut page = vec![];
+            fuse.list_page(req, fh, path, offset, dots, &mut |entry, next| {
+                page.push((entry.clone(), next));
+                page.len() == 7
+            }).unwrap();
+            page
+        };
+        offset = match page.last() {
+            Some(&(_, next)) => next,
+            None => break,
+        };
+        names.extend(page.into_iter().map(|(entry, _)| entry.name.into_string().unwrap()));
+        // Nothing was cached along the way.
+        assert!(fuse.pages(fh));
+    }
+
+    let mut expected = vec![".".to_owned(), "..".to_owned()];
+    expected.extend((0 .. 50).map(|n| format!("e{:02}", n)));
+    assert_eq!(expected, names);
+}
@@ -0,0 +1,187 @@
+// GroupCache :: cached lookups of callers' supplementary groups.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::clock::{Clock, SystemClock};
+use crate::types::RequestInfo;
+
+/// Most callers to keep groups for before expired ones are cleared out.
+const MAX_ENTRIES: usize = 1024;
+
+/// `getgrouplist` takes signed group IDs on macOS.
+#[cfg(target_os = "macos")]
+type GrouplistGid = libc::c_int;
+#[cfg(not(target_os = "macos"))]
+type GrouplistGid = libc::gid_t;
+
+/// Looks up which groups callers are in, for checking permissions in userspace.
+///
+/// FUSE only gives the caller's primary group in a request, so checking a file's group permission
+/// bits against that alone rejects callers who are in the file's group as one of their
+/// supplementary groups. This finds out the rest:
+///
+/// * On Linux, from `/proc/<pid>/status`, which gives the groups the calling process actually has.
+/// * Otherwise, or if the process already exited, from the group database with `getgrouplist`,
+///   which gives the groups the caller's user is configured to be in.
+///
+/// Looking these up takes a few system calls, so the result is kept for `ttl`, per calling
+/// process. Processes rarely change their groups, but a longer `ttl` makes it more likely that a
+/// process ID is reused by a different process, so it should be short, like a second.
+///
+/// It can be shared between threads, and between filesystems.
+#[derive(Debug)]
+pub struct GroupCache {
+    ttl: Duration,
+    clock: Arc<dyn Clock>,
+    /// Keyed by the caller's process, user, and group IDs.
+    entries: Mutex<HashMap<(u32, u32, u32), Entry>>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    time: Instant,
+    groups: Arc<[libc::gid_t]>,
+}
+
+impl GroupCache {
+    pub fn new(ttl: Duration) -> GroupCache {
+        GroupCache {
+            ttl,
+            clock: Arc::new(SystemClock),
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Use the given clock, instead of the system's, to expire entries by.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> GroupCache {
+        self.clock = clock;
+        self
+    }
+
+    /// Get all the groups the caller making the given request is in, including their primary
+    /// group.
+    pub fn groups(&self, req: &RequestInfo) -> Arc<[libc::gid_t]> {
+        let key = (req.pid, req.uid, req.gid);
+        let now = self.clock.now();
+        if let Some(entry) = self.entries.lock().unwrap().get(&key) {
+            if now.saturating_duration_since(entry.time) < self.ttl {
+                return entry.groups.clone();
+            }
+        }
+
+        let mut groups = resolve(req);
+        if !groups.contains(&req.gid) {
+            groups.insert(0, req.gid);
+        }
+        let groups: Arc<[libc::gid_t]> = groups.into();
+
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= MAX_ENTRIES {
+            let ttl = self.ttl;
+            entries.retain(|_, entry| now.saturating_duration_since(entry.time) < ttl);
+        }
+        entries.insert(key, Entry { time: now, groups: groups.clone() });
+        groups
+    }
+
+    /// Whether the caller making the given request is in the given group.
+    pub fn in_group(&self, req: &RequestInfo, gid: libc::gid_t) -> bool {
+        req.gid == gid || self.groups(req).contains(&gid)
+    }
+}
+
+fn resolve(req: &RequestInfo) -> Vec<libc::gid_t> {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        if let Some(groups) = proc_groups(req.pid) {
+            return groups;
+        }
+    }
+    match user_groups(req.uid, req.gid) {
+        Ok(groups) => groups,
+        Err(e) => {
+            warn!("unable to look up the groups of uid {}: {}", req.uid, e);
+            vec![]
+        }
+    }
+}
+
+/// Read the groups of a process from procfs, if it's still running.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn proc_groups(pid: u32) -> Option<Vec<libc::gid_t>> {
+    if pid == 0 {
+        return None;
+    }
+    let status = std::fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+    let line = status.lines().find_map(|line| line.strip_prefix("Groups:"))?;
+    line.split_whitespace().map(|gid| gid.parse().ok()).collect()
+}
+
+/// Look up the groups a user is in, from the group database.
+fn user_groups(uid: libc::uid_t, gid: libc::gid_t) -> Result<Vec<libc::gid_t>, libc::c_int> {
+    let mut buf = vec![0 as libc::c_char; 1024];
+    let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut found = std::ptr::null_mut();
+    loop {
+        let e = unsafe {
+            libc::getpwuid_r(uid, &mut pwd, buf.as_mut_ptr(), buf.len(), &mut found)
+        };
+        match e {
+            0 => break,
+            libc::ERANGE => buf.resize(buf.len() * 2, 0),
+            e => return Err(e),
+        }
+    }
+    if found.is_null() {
+        return Err(libc::ENOENT);
+    }
+    let name = unsafe { CStr::from_ptr(pwd.pw_name) };
+
+    let mut groups: Vec<GrouplistGid> = vec![0; 32];
+    loop {
+        let mut n = groups.len() as libc::c_int;
+        let ret = unsafe {
+            libc::getgrouplist(name.as_ptr(), gid as GrouplistGid, groups.as_mut_ptr(), &mut n)
+        };
+        if ret != -1 {
+            groups.truncate(n as usize);
+            return Ok(groups.into_iter().map(|gid| gid as libc::gid_t).collect());
+        }
+        // It gives the number needed on Linux, but not everywhere.
+        let needed = (n as usize).max(groups.len() * 2);
+        groups.resize(needed, 0);
+    }
+}
+
+#[test]
+fn test_group_cache() {
+    let uid = unsafe { libc::getuid() };
+    let gid = unsafe { libc::getgid() };
+    let req = RequestInfo { unique: 0, uid, gid, pid: std::process::id(), node: None, umask: None, lock_owner: None };
+    let clock = crate::ManualClock::new();
+    let cache = GroupCache::new(Duration::from_secs(60)).with_clock(Arc::new(clock.clone()));
+
+    let groups = cache.groups(&req);
+    assert!(groups.contains(&gid));
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        let mut own = vec![0; 256];
+        let n = unsafe { libc::getgroups(own.len() as libc::c_int, own.as_mut_ptr()) };
+        own.truncate(n as usize);
+        assert!(own.iter().all(|gid| groups.contains(gid)));
+    }
+    assert!(Arc::ptr_eq(&groups, &cache.groups(&req)));
+    clock.advance(Duration::from_secs(60));
+    assert!(!Arc::ptr_eq(&groups, &cache.groups(&req)));
+
+    // A different caller isn't given the same groups.
+    let other = RequestInfo { gid: 54321, ..req };
+    assert!(cache.in_group(&other, 54321));
+    assert!(!cache.in_group(&req, 54321));
+}
@@ -0,0 +1,96 @@
+// HandleTable :: a registry of open file handles.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+use std::collections::HashMap;
+use std::fmt;
+use std::num::Wrapping;
+use std::sync::Arc;
+
+use super::FileHandle;
+
+/// Open file handle registry.
+///
+/// Filesystems can either return a plain `u64` file handle from `open` and `create`, or they can
+/// return a `FileHandle` object which FuseMT keeps ownership of and routes I/O calls to directly.
+///
+/// Because the two kinds of handles can't be told apart by their value alone, FuseMT gives FUSE
+/// its own file handle (the table key) for every open file, and translates it back to whichever
+/// kind of handle the filesystem gave us when calls come in. This is the same approach as the
+/// directory cache.
+#[derive(Debug)]
+pub struct HandleTable {
+    next_key: Wrapping<u64>,
+    entries: HashMap<u64, FileHandleEntry>,
+}
+
+/// What a file handle key given to FUSE refers to.
+#[derive(Clone)]
+pub enum FileHandleEntry {
+    /// A file handle value returned by the filesystem's `open` or `create` call.
+    Raw(u64),
+    /// A handle object returned by the filesystem's `open_handle` or `create_handle` call.
+    Managed(Arc<dyn FileHandle>),
+}
+
+impl fmt::Debug for FileHandleEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FileHandleEntry::Raw(fh) => f.debug_tuple("Raw").field(fh).finish(),
+            FileHandleEntry::Managed(_) => f.write_str("Managed"),
+        }
+    }
+}
+
+impl HandleTable {
+    pub fn new() -> HandleTable {
+        HandleTable {
+            next_key: Wrapping(1),
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Add a new open file to the table, and return the key that should be given to FUSE as its
+    /// file handle.
+    /// This is intended to be called on open() and create().
+    pub fn insert(&mut self, entry: FileHandleEntry) -> u64 {
+        let key = self.next_key.0;
+        self.entries.insert(key, entry);
+        self.next_key += Wrapping(1);
+        key
+    }
+
+    /// Get the entry for the given key, or None if there is no such key.
+    pub fn get(&self, key: u64) -> Option<FileHandleEntry> {
+        self.entries.get(&key).cloned()
+    }
+
+    /// Get the file handle value that the filesystem returned for the given key, if it was a raw
+    /// file handle. Managed handles and unknown keys return None.
+    pub fn real_fh(&self, key: u64) -> Option<u64> {
+        match self.entries.get(&key) {
+            Some(FileHandleEntry::Raw(fh)) => Some(*fh),
+            _ => None,
+        }
+    }
+
+    /// Get the number of open files in the table.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Get the number of open files with raw file handles, and with `FileHandle` objects.
+    pub fn counts(&self) -> (usize, usize) {
+        let raw = self.entries.values()
+            .filter(|entry| matches!(entry, FileHandleEntry::Raw(_)))
+            .count();
+        (raw, self.entries.len() - raw)
+    }
+
+    /// Remove the entry for the given key.
+    /// This is intended to be called on release().
+    pub fn remove(&mut self, key: u64) -> Option<FileHandleEntry> {
+        self.entries.remove(&key)
+    }
+}
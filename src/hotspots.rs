@@ -0,0 +1,149 @@
+// Hotspots :: finding which parts of the filesystem take the most calls.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fmt::Write;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Name of the extended attribute on the root of the mount which reads out the hotspots report,
+/// when `FuseMT::profile_hotspots` is on.
+///
+/// Like `STATS_XATTR`, FuseMT answers reads of it itself, and leaves it out of `listxattr`
+/// results: `getfattr --only-values -n fusemt.hotspots /mnt`. Each line is one path prefix, as
+/// `calls bytes microseconds path`, busiest first, where `bytes` counts the data read and
+/// written, and `microseconds` is the total time the calls took. The path comes last, so it may
+/// contain spaces.
+pub const HOTSPOTS_XATTR: &str = "fusemt.hotspots";
+
+/// Most prefixes to keep totals for. Calls on others are counted under `OTHER`.
+const MAX_PREFIXES: usize = 65536;
+
+/// Where calls on prefixes past `MAX_PREFIXES` are counted.
+const OTHER: &str = "[other]";
+
+/// Totals of the calls made on each path prefix: the first few components of the paths they
+/// were made on, so that calls on everything under a directory deep enough in the tree add up.
+#[derive(Debug)]
+pub struct Hotspots {
+    depth: usize,
+    top: usize,
+    prefixes: Mutex<HashMap<PathBuf, Usage>>,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Usage {
+    calls: u64,
+    bytes: u64,
+    time: Duration,
+}
+
+impl Hotspots {
+    /// Total calls by the first `depth` components of their paths, and report the `top` busiest.
+    pub fn new(depth: usize, top: usize) -> Hotspots {
+        Hotspots {
+            depth,
+            top,
+            prefixes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Start timing a call on the given path, or on the entry with the given name in it. It's
+    /// counted when the returned `Profile` is dropped.
+    pub fn start(self: &Arc<Self>, path: &Path, name: Option<&OsStr>) -> Profile {
+        let mut prefix = PathBuf::from("/");
+        let names = path.components()
+            .filter_map(|c| match c {
+                Component::Normal(name) => Some(name),
+                _ => None,
+            })
+            .chain(name);
+        for name in names.take(self.depth) {
+            prefix.push(name);
+        }
+        Profile {
+            hotspots: self.clone(),
+            prefix,
+            start: Instant::now(),
+            bytes: 0,
+        }
+    }
+
+    fn add(&self, prefix: PathBuf, bytes: u64, time: Duration) {
+        let mut prefixes = self.prefixes.lock().unwrap();
+        let prefix = if prefixes.len() < MAX_PREFIXES || prefixes.contains_key(&prefix) {
+            prefix
+        } else {
+            PathBuf::from(OTHER)
+        };
+        let usage = prefixes.entry(prefix).or_default();
+        usage.calls += 1;
+        usage.bytes += bytes;
+        usage.time += time;
+    }
+
+    /// Format the busiest prefixes, by the total time their calls took, as described for
+    /// `HOTSPOTS_XATTR`.
+    pub fn report(&self) -> String {
+        let mut busiest = self.prefixes.lock().unwrap().iter()
+            .map(|(prefix, usage)| (prefix.clone(), *usage))
+            .collect::<Vec<_>>();
+        busiest.sort_by(|a, b| b.1.time.cmp(&a.1.time).then_with(|| a.0.cmp(&b.0)));
+        let mut out = String::new();
+        for (prefix, usage) in busiest.iter().take(self.top) {
+            writeln!(out, "{} {} {} {}", usage.calls, usage.bytes, usage.time.as_micros(),
+                prefix.to_string_lossy()).unwrap();
+        }
+        out
+    }
+}
+
+/// A call being timed for `Hotspots`.
+#[derive(Debug)]
+pub struct Profile {
+    hotspots: Arc<Hotspots>,
+    prefix: PathBuf,
+    start: Instant,
+    bytes: u64,
+}
+
+impl Profile {
+    /// Count data read or written by the call.
+    pub fn add_bytes(&mut self, bytes: usize) {
+        self.bytes += bytes as u64;
+    }
+}
+
+impl Drop for Profile {
+    fn drop(&mut self) {
+        let prefix = std::mem::take(&mut self.prefix);
+        self.hotspots.add(prefix, self.bytes, self.start.elapsed());
+    }
+}
+
+#[test]
+fn test_hotspots() {
+    let hotspots = Arc::new(Hotspots::new(2, 2));
+
+    // Calls are counted under the first two components of their paths.
+    drop(hotspots.start(Path::new("/a"), Some(OsStr::new("b"))));
+    let mut profile = hotspots.start(Path::new("/a/b/c"), None);
+    profile.add_bytes(4096);
+    drop(profile);
+    drop(hotspots.start(Path::new("/"), None));
+    {
+        let prefixes = hotspots.prefixes.lock().unwrap();
+        assert_eq!(2, prefixes.len());
+        assert_eq!((2, 4096), (prefixes[Path::new("/a/b")].calls, prefixes[Path::new("/a/b")].bytes));
+        assert_eq!(1, prefixes[Path::new("/")].calls);
+    }
+
+    // The report has the busiest ones by time.
+    hotspots.add(PathBuf::from("/x y"), 0, Duration::from_secs(2));
+    hotspots.add(PathBuf::from("/z"), 10, Duration::from_secs(1));
+    assert_eq!("1 0 2000000 /x y\n1 10 1000000 /z\n", hotspots.report());
+}
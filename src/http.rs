@@ -0,0 +1,622 @@
+// HTTP :: the HTTP client and helpers for the network-backed adapters.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+// Which of these helpers get used depends on which network features are enabled.
+#![allow(dead_code)]
+
+use std::convert::TryFrom;
+use std::io::{self, Read};
+use std::time::{Duration, SystemTime};
+
+/// The parts of an `http://` or `https://` URL that the client needs.
+#[derive(Clone, Debug)]
+pub struct Url {
+    /// Whether the URL is `https://`.
+    pub tls: bool,
+    pub host: String,
+    pub port: u16,
+    /// Path component, without any trailing slash. Empty if the URL has no path; requests for an
+    /// empty path are sent for `/`.
+    pub path: String,
+}
+
+impl Url {
+    pub fn parse(url: &str) -> io::Result<Url> {
+        let (tls, rest) = match (url.strip_prefix("https://"), url.strip_prefix("http://")) {
+            (Some(rest), _) => (true, rest),
+            (None, Some(rest)) => (false, rest),
+            (None, None) => return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                format!("only http:// and https:// URLs are supported: {:?}", url))),
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(idx) => (&rest[.. idx], &rest[idx ..]),
+            None => (rest, ""),
+        };
+        // An IPv6 address in brackets has colons of its own.
+        let port_sep = authority.rfind(':').filter(|&idx| !authority[idx ..].contains(']'));
+        let (host, port) = match port_sep {
+            Some(idx) => {
+                let port = authority[idx + 1 ..].parse().map_err(|_| {
+                    io::Error::new(io::ErrorKind::InvalidInput, format!("bad port in {:?}", url))
+                })?;
+                (&authority[.. idx], port)
+            }
+            None => (authority, if tls { 443 } else { 80 }),
+        };
+        if host.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("no host in {:?}", url)));
+        }
+        Ok(Url {
+            tls,
+            host: host.to_owned(),
+            port,
+            path: path.trim_end_matches('/').to_owned(),
+        })
+    }
+
+    /// The scheme, host, and port, which paths are appended to for requests.
+    pub fn origin(&self) -> String {
+        format!("{}://{}:{}", if self.tls { "https" } else { "http" }, self.host, self.port)
+    }
+}
+
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    /// Set if the body was longer than the limit it was read with, and was cut short.
+    pub truncated: bool,
+}
+
+impl Response {
+    /// Get the value of a header (case-insensitively), if present.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Turn non-success status codes into errors.
+    pub fn error_for_status(self) -> io::Result<Response> {
+        let kind = match self.status {
+            200 ..= 299 => return Ok(self),
+            401 | 403 => io::ErrorKind::PermissionDenied,
+            404 | 410 => io::ErrorKind::NotFound,
+            _ => io::ErrorKind::Other,
+        };
+        Err(io::Error::new(kind, format!("HTTP status {}", self.status)))
+    }
+}
+
+/// The default limit on the size of a response body. The server decides how much it sends, so
+/// without a limit a bad one could make us use up all our memory.
+pub const DEFAULT_MAX_BODY: usize = 256 * 1024 * 1024;
+
+/// An HTTP client for a single host, over `ureq`, which keeps idle connections around for re-use.
+/// `https://` URLs are served over TLS with rustls, trusting the Mozilla root certificates.
+///
+/// Redirects aren't followed, and error statuses are returned as responses like any other, so
+/// callers see exactly what the server said.
+#[derive(Debug)]
+pub struct HttpClient {
+    url: Url,
+    origin: String,
+    max_body: usize,
+    agent: ureq::Agent,
+}
+
+impl HttpClient {
+    pub fn new(url: Url, timeout: Duration) -> HttpClient {
+        HttpClient {
+            origin: url.origin(),
+            url,
+            max_body: DEFAULT_MAX_BODY,
+            agent: ureq::AgentBuilder::new()
+                .timeout(timeout)
+                .redirects(0)
+                .user_agent(&format!("fuse_mt/{}", crate::VERSION))
+                .build(),
+        }
+    }
+
+    /// Set the largest response body to accept. Requests with longer responses fail.
+    pub fn max_body(mut self, max_body: usize) -> HttpClient {
+        self.max_body = max_body;
+        self
+    }
+
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    /// Send a request and read the response.
+    ///
+    /// `path` is sent as-is, so it must already be percent-encoded, and include any query string.
+    pub fn request(&self, method: &str, path: &str, headers: &[(&str, &str)], body: &[u8])
+        -> io::Result<Response>
+    {
+        let response = self.request_limited(method, path, headers, body, self.max_body)?;
+        if response.truncated {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                format!("HTTP response body is larger than {} bytes", self.max_body)));
+        }
+        Ok(response)
+    }
+
+    /// Send a request and read the response, keeping at most `limit` bytes of the body.
+    fn request_limited(&self, method: &str, path: &str, headers: &[(&str, &str)], body: &[u8],
+                       limit: usize) -> io::Result<Response>
+    {
+        let path = if path.is_empty() { "/" } else { path };
+        let mut request = self.agent.request(method, &format!("{}{}", self.origin, path));
+        for (name, value) in headers {
+            request = request.set(name, value);
+        }
+        let result = if !body.is_empty() || method == "PUT" || method == "POST" {
+            request.send_bytes(body)
+        } else {
+            request.call()
+        };
+        let response = match result {
+            Ok(response) | Err(ureq::Error::Status(_, response)) => response,
+            Err(ureq::Error::Transport(e)) => return Err(transport_error(e)),
+        };
+
+        let status = response.status();
+        let mut headers = vec![];
+        for name in response.headers_names() {
+            for value in response.all(&name) {
+                headers.push((name.clone(), value.to_owned()));
+            }
+        }
+        // Read one byte past the limit to tell whether there's more. If there is, the rest is
+        // left unread, and ureq closes the connection instead of re-using it.
+        let mut body = vec![];
+        let room = u64::try_from(limit).unwrap_or(u64::MAX).saturating_add(1);
+        response.into_reader().take(room).read_to_end(&mut body)?;
+        let truncated = body.len() > limit;
+        body.truncate(limit);
+        Ok(Response { status, headers, body, truncated })
+    }
+
+    /// Get the size and modification time of a resource with a HEAD request, or None if it
+    /// doesn't exist. `headers` are sent along with the request.
+    pub fn head(&self, path: &str, headers: &[(&str, &str)])
+        -> io::Result<Option<(u64, SystemTime)>>
+    {
+        let response = self.request("HEAD", path, headers, &[])?;
+        if response.status == 404 || response.status == 410 {
+            return Ok(None);
+        }
+        let response = response.error_for_status()?;
+        let size = response.header("Content-Length")
+            .and_then(|len| len.parse().ok())
+            .unwrap_or(0);
+        let mtime = response.header("Last-Modified")
+            .and_then(parse_http_date)
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+        Ok(Some((size, mtime)))
+    }
+
+    /// Read up to `size` bytes of a resource, starting at `offset`, with a Range request.
+    /// `headers` are sent along with the request.
+    pub fn get_range(&self, path: &str, headers: &[(&str, &str)], offset: u64, size: u64)
+        -> io::Result<Vec<u8>>
+    {
+        if size == 0 {
+            return Ok(vec![]);
+        }
+        let end = offset.checked_add(size).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "range end overflows")
+        })?;
+        let range = format!("bytes={}-{}", offset, end - 1);
+        // If the server ignores the range and sends the whole thing, only the part up to the end
+        // of the range is needed, so it doesn't matter if the rest is cut off.
+        let mut headers = headers.to_vec();
+        headers.push(("Range", &range));
+        let response = self.request_limited("GET", path, &headers, &[], self.max_body)?;
+        if response.status == 416 {
+            // Range not satisfiable: reading past the end.
+            return Ok(vec![]);
+        }
+        let mut response = response.error_for_status()?;
+        let needed = if response.status == 200 { end } else { size };
+        if response.truncated && (response.body.len() as u64) < needed {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                format!("HTTP response body is larger than {} bytes", self.max_body)));
+        }
+        if response.status == 200 {
+            // The server ignored the range and sent the whole thing.
+            let len = response.body.len();
+            let start = usize::try_from(offset).unwrap_or(usize::MAX).min(len);
+            let end = start.saturating_add(usize::try_from(size).unwrap_or(usize::MAX)).min(len);
+            response.body.truncate(end);
+            response.body.drain(.. start);
+        } else {
+            // Don't pass on more than was asked for, if the server sent extra.
+            response.body.truncate(usize::try_from(size).unwrap_or(usize::MAX));
+        }
+        Ok(response.body)
+    }
+}
+
+/// Whether a URL's host is on the loopback interface, so that what's sent to it in the clear
+/// doesn't leave the machine.
+pub fn is_loopback(host: &str) -> bool {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    host.eq_ignore_ascii_case("localhost")
+        || host.parse::<std::net::IpAddr>().is_ok_and(|ip| ip.is_loopback())
+}
+
+/// Turn a ureq error into an `io::Error` of the same kind as what caused it, so that timeouts in
+/// particular can be told apart.
+fn transport_error(e: ureq::Transport) -> io::Error {
+    use std::error::Error;
+    let kind = match e.source().and_then(|source| source.downcast_ref::<io::Error>()) {
+        Some(source) => source.kind(),
+        None => match e.kind() {
+            ureq::ErrorKind::InvalidUrl | ureq::ErrorKind::UnknownScheme => {
+                io::ErrorKind::InvalidInput
+            }
+            ureq::ErrorKind::ConnectionFailed => io::ErrorKind::ConnectionRefused,
+            ureq::ErrorKind::BadStatus | ureq::ErrorKind::BadHeader => io::ErrorKind::InvalidData,
+            _ => io::ErrorKind::Other,
+        },
+    };
+    io::Error::new(kind, e.to_string())
+}
+
+/// Percent-encode a string for use in a URL path or query. Forward slashes are left alone if
+/// `keep_slash` is set.
+pub fn encode(s: &str, keep_slash: bool) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A' ..= b'Z' | b'a' ..= b'z' | b'0' ..= b'9' | b'-' | b'_' | b'.' | b'~' => {
+                out.push(b as char)
+            }
+            b'/' if keep_slash => out.push('/'),
+            _ => out += &format!("%{:02X}", b),
+        }
+    }
+    out
+}
+
+/// Decode a percent-encoded string. Invalid escapes are passed through as-is.
+pub fn decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1 .. i + 3]).unwrap_or("");
+            if let Ok(b) = u8::from_str_radix(hex, 16) {
+                out.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Standard base64 encoding, with padding.
+pub fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] =
+        b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let n = (u32::from(chunk[0]) << 16)
+            | (u32::from(*chunk.get(1).unwrap_or(&0)) << 8)
+            | u32::from(*chunk.get(2).unwrap_or(&0));
+        for i in 0 .. 4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Number of days since 1970-01-01 for the given proleptic Gregorian date.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = if y >= 0 { y } else { y - 399 } / 400;
+    let yoe = y - era * 400;
+    let m = i64::from(month);
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+fn to_system_time(year: i64, month: u32, day: u32, hour: u64, min: u64, sec: u64)
+    -> Option<SystemTime>
+{
+    if !(1 ..= 12).contains(&month) || !(1 ..= 31).contains(&day) || hour > 23 || min > 59
+        || sec > 60
+    {
+        return None;
+    }
+    let days = days_from_civil(year, month, day);
+    if days < 0 {
+        return None;
+    }
+    let secs = days as u64 * 86400 + hour * 3600 + min * 60 + sec;
+    Some(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+/// The proleptic Gregorian date for a number of days since 1970-01-01; the inverse of
+/// `days_from_civil`.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = if z >= 0 { z } else { z - 146_096 } / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Format a time as a basic ISO 8601 UTC timestamp, like "20091012T175030Z", the form AWS
+/// request signing uses. Times before the epoch are formatted as the epoch.
+pub fn format_iso8601_basic(t: SystemTime) -> String {
+    let secs = t.duration_since(SystemTime::UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let secs = secs % 86400;
+    format!("{:04}{:02}{:02}T{:02}{:02}{:02}Z", year, month, day, secs / 3600, secs / 60 % 60,
+        secs % 60)
+}
+
+/// Parse an HTTP date, like "Sun, 06 Nov 1994 08:49:37 GMT".
+pub fn parse_http_date(s: &str) -> Option<SystemTime> {
+    const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep",
+        "Oct", "Nov", "Dec"];
+    let mut parts = s.split_whitespace().skip(1);
+    let day = parts.next()?.parse().ok()?;
+    let month_name = parts.next()?;
+    let month = MONTHS.iter().position(|m| *m == month_name)? as u32 + 1;
+    let year = parts.next()?.parse().ok()?;
+    let mut time = parts.next()?.split(':');
+    let hour = time.next()?.parse().ok()?;
+    let min = time.next()?.parse().ok()?;
+    let sec = time.next()?.parse().ok()?;
+    to_system_time(year, month, day, hour, min, sec)
+}
+
+/// Parse an ISO 8601 UTC timestamp, like "2009-10-12T17:50:30.000Z".
+pub fn parse_iso8601(s: &str) -> Option<SystemTime> {
+    let (date, time) = s.split_once('T')?;
+    let mut date = date.split('-');
+    let year = date.next()?.parse().ok()?;
+    let month = date.next()?.parse().ok()?;
+    let day = date.next()?.parse().ok()?;
+    let time = time.trim_end_matches('Z');
+    let time = time.split('.').next()?;
+    let mut time = time.split(':');
+    let hour = time.next()?.parse().ok()?;
+    let min = time.next()?.parse().ok()?;
+    let sec = time.next()?.parse().ok()?;
+    to_system_time(year, month, day, hour, min, sec)
+}
+
+/// Find the text of each `<tag>...</tag>` element in an XML document, in order.
+///
+/// This is nowhere near a real XML parser; it's just enough for picking values out of the simple
+/// responses returned by the services we talk to. Namespace prefixes on the tag are ignored.
+pub fn xml_elements<'a>(doc: &'a str, tag: &str) -> Vec<&'a str> {
+    let mut found = vec![];
+    let mut rest = doc;
+    while let Some(start) = find_open_tag(rest, tag) {
+        let (content_start, self_closing) = start;
+        if self_closing {
+            found.push("");
+            rest = &rest[content_start ..];
+            continue;
+        }
+        let content = &rest[content_start ..];
+        match find_close_tag(content, tag) {
+            Some((end, after)) => {
+                found.push(&content[.. end]);
+                rest = &content[after ..];
+            }
+            None => break,
+        }
+    }
+    found
+}
+
+fn tag_name_matches(name: &str, tag: &str) -> bool {
+    name == tag || name.rsplit(':').next() == Some(tag)
+}
+
+/// Find an opening tag; returns the offset just past it, and whether it was self-closing.
+fn find_open_tag(doc: &str, tag: &str) -> Option<(usize, bool)> {
+    let mut offset = 0;
+    while let Some(idx) = doc[offset ..].find('<') {
+        let start = offset + idx + 1;
+        let end = start + doc[start ..].find('>')?;
+        let inner = &doc[start .. end];
+        let self_closing = inner.ends_with('/');
+        let name = inner.trim_end_matches('/').split_whitespace().next().unwrap_or("");
+        if tag_name_matches(name, tag) {
+            return Some((end + 1, self_closing));
+        }
+        offset = end + 1;
+    }
+    None
+}
+
+/// Find the matching closing tag; returns its start offset, and the offset just past it.
+fn find_close_tag(doc: &str, tag: &str) -> Option<(usize, usize)> {
+    let mut offset = 0;
+    while let Some(idx) = doc[offset ..].find("</") {
+        let start = offset + idx;
+        let end = start + doc[start ..].find('>')?;
+        if tag_name_matches(doc[start + 2 .. end].trim(), tag) {
+            return Some((start, end + 1));
+        }
+        offset = end + 1;
+    }
+    None
+}
+
+/// Decode the predefined XML entities and numeric character references.
+pub fn xml_unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(idx) = rest.find('&') {
+        out += &rest[.. idx];
+        rest = &rest[idx ..];
+        let end = match rest.find(';') {
+            Some(end) => end,
+            None => break,
+        };
+        let entity = &rest[1 .. end];
+        let decoded = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ if entity.starts_with("#x") => u32::from_str_radix(&entity[2 ..], 16).ok()
+                .and_then(char::from_u32),
+            _ if entity.starts_with('#') => entity[1 ..].parse().ok().and_then(char::from_u32),
+            _ => None,
+        };
+        match decoded {
+            Some(c) => {
+                out.push(c);
+                rest = &rest[end + 1 ..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1 ..];
+            }
+        }
+    }
+    out += rest;
+    out
+}
+
+#[test]
+fn test_dates() {
+    let t = parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT").unwrap();
+    assert_eq!(784111777, t.duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs());
+    let t = parse_iso8601("1994-11-06T08:49:37.000Z").unwrap();
+    assert_eq!(784111777, t.duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs());
+    assert_eq!("19941106T084937Z", format_iso8601_basic(t));
+    let t = parse_iso8601("2000-02-29T23:59:59Z").unwrap();
+    assert_eq!("20000229T235959Z", format_iso8601_basic(t));
+}
+
+#[test]
+fn test_encoding() {
+    assert_eq!("a%20b/c%25", encode("a b/c%", true));
+    assert_eq!("a b/c%", decode("a%20b/c%25"));
+    assert_eq!("dXNlcjpwYXNz", base64(b"user:pass"));
+    assert_eq!("YQ==", base64(b"a"));
+}
+
+#[test]
+fn test_xml_elements() {
+    let doc = "<r><a:Key>x &amp; y</a:Key><Key/><Key>z</Key></r>";
+    let keys: Vec<String> = xml_elements(doc, "Key").into_iter().map(xml_unescape).collect();
+    assert_eq!(vec!["x & y", "", "z"], keys);
+}
+
+/// Read a line of a request, for the test servers.
+#[cfg(test)]
+fn read_line(conn: &mut impl io::BufRead) -> io::Result<String> {
+    let mut line = String::new();
+    conn.read_line(&mut line)?;
+    Ok(line.trim_end_matches(&['\r', '\n'][..]).to_owned())
+}
+
+#[test]
+fn test_url() {
+    let url = Url::parse("https://[::1]/dav/").unwrap();
+    assert_eq!((true, "[::1]", 443, "/dav"), (url.tls, url.host.as_str(), url.port, url.path.as_str()));
+    assert_eq!("https://[::1]:443", url.origin());
+    let url = Url::parse("http://example.com:8080").unwrap();
+    assert_eq!("http://example.com:8080", url.origin());
+    assert_eq!(io::ErrorKind::InvalidInput, Url::parse("ftp://example.com").unwrap_err().kind());
+}
+
+#[test]
+fn test_get_range() {
+    use std::io::{BufReader, Write};
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut conn = BufReader::new(stream);
+        // Serve requests on the same connection: one honoring the range, and others not.
+        for body in &["lo w", "hello world", "hello world"] {
+            while !read_line(&mut conn).unwrap().is_empty() {}
+            let status = if body.len() < 11 { "206 Partial Content" } else { "200 OK" };
+            write!(conn.get_mut(), "HTTP/1.1 {}\r\nContent-Length: {}\r\n\r\n{}",
+                status, body.len(), body).unwrap();
+        }
+    });
+
+    let client = HttpClient::new(Url::parse(&format!("http://127.0.0.1:{}/", port)).unwrap(),
+        Duration::from_secs(5));
+    assert_eq!(b"lo w".to_vec(), client.get_range("/x", &[], 3, 4).unwrap());
+    assert_eq!(b"lo w".to_vec(), client.get_range("/x", &[], 3, 4).unwrap());
+    // Past the end, even when the offset doesn't fit in 32 bits.
+    assert_eq!(Vec::<u8>::new(), client.get_range("/x", &[], (1 << 32) + 3, 4).unwrap());
+    server.join().unwrap();
+}
+
+#[test]
+fn test_response_limits() {
+    use std::io::{BufReader, Write};
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = std::thread::spawn(move || {
+        let mut request_lines = vec![];
+        let filler = "x".repeat(2048);
+        let responses = [
+            // Claims a huge body, which mustn't be allocated up front.
+            format!("HTTP/1.1 200 OK\r\nContent-Length: 1000000000000\r\n\r\n{}", filler),
+            // A chunk size which would overflow when added to the body so far.
+            format!("HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+                5\r\nhello\r\nffffffffffffffff\r\n{}", filler),
+            "HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok".to_owned(),
+        ];
+        for response in &responses {
+            let (stream, _) = listener.accept().unwrap();
+            let mut conn = BufReader::new(stream);
+            request_lines.push(read_line(&mut conn).unwrap());
+            while !read_line(&mut conn).unwrap().is_empty() {}
+            conn.get_mut().write_all(response.as_bytes()).unwrap();
+        }
+        request_lines
+    });
+
+    let url = Url::parse(&format!("http://127.0.0.1:{}", port)).unwrap();
+    assert_eq!("", url.path);
+    assert!(!url.tls);
+    let client = HttpClient::new(url, Duration::from_secs(5)).max_body(1024);
+    let e = client.request("GET", "/big", &[], &[]).unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidData, e.kind());
+    let e = client.request("GET", "/chunked", &[], &[]).unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidData, e.kind());
+    let response = client.request("GET", "", &[], &[]).unwrap();
+    assert_eq!(b"ok".to_vec(), response.body);
+    assert_eq!("GET / HTTP/1.1", server.join().unwrap()[2]);
+}
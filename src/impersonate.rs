@@ -0,0 +1,151 @@
+// Impersonate :: make system calls with the credentials of the caller.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+//! Making system calls on behalf of the caller, so the kernel checks their permissions.
+//!
+//! A passthrough-style filesystem which runs as root and is mounted with `allow_other` can access
+//! any file in its backing directory, no matter who asks. Rather than re-implementing the kernel's
+//! permission checks, it can assume the caller's user and group IDs around the system calls it
+//! makes for them, and let the kernel check them:
+//!
+//! ```ignore
+//! fn unlink(&self, req: RequestInfo, parent: &FsPath, name: &OsStr) -> ResultEmpty {
+//!     let _caller = impersonate(&req).errno()?;
+//!     fs::remove_file(self.real_path(parent).join(name)).errno()
+//! }
+//! ```
+//!
+//! This uses `setfsuid` and `setfsgid`, which only affect the current thread, and only the checks
+//! made on file accesses: the process can still switch back, and other threads keep running as
+//! root. Files created while impersonating are owned by the caller.
+
+use std::io;
+use std::marker::PhantomData;
+
+use crate::types::RequestInfo;
+
+/// The caller's credentials, assumed by the current thread until this is dropped.
+///
+/// While it's in effect, the thread doesn't have the supplementary groups of the filesystem's
+/// process, only the caller's ones it was given, if any: see `impersonate_with_groups`.
+#[derive(Debug)]
+#[must_use = "the caller's credentials are only assumed until this is dropped"]
+pub struct Impersonation {
+    uid: libc::uid_t,
+    gid: libc::gid_t,
+    groups: Vec<libc::gid_t>,
+    /// Credentials belong to a thread, so this has to be dropped on the one that made it.
+    _thread: PhantomData<*const ()>,
+}
+
+/// Assume the file system user and group IDs of the caller making the given request, on the
+/// current thread, until the returned guard is dropped.
+///
+/// The process needs the `CAP_SETUID` and `CAP_SETGID` capabilities, which root has. Fails with
+/// `EPERM` without them.
+///
+/// The thread is given no supplementary groups, so files the caller could only reach through one
+/// of theirs are out of reach.
+pub fn impersonate(req: &RequestInfo) -> io::Result<Impersonation> {
+    impersonate_with_groups(req, &[])
+}
+
+/// Like `impersonate`, but also assume the given supplementary groups, such as the caller's from
+/// `GroupCache::groups`.
+pub fn impersonate_with_groups(req: &RequestInfo, groups: &[libc::gid_t])
+    -> io::Result<Impersonation>
+{
+    // Passing an invalid ID changes nothing, and gives the current one.
+    let guard = Impersonation {
+        uid: unsafe { libc::setfsuid(libc::uid_t::MAX) } as libc::uid_t,
+        gid: unsafe { libc::setfsgid(libc::gid_t::MAX) } as libc::gid_t,
+        groups: get_groups()?,
+        _thread: PhantomData,
+    };
+    set_groups(groups)?;
+    // If these fail, dropping the guard puts back what was changed.
+    unsafe {
+        libc::setfsgid(req.gid);
+        if libc::setfsgid(libc::gid_t::MAX) as libc::gid_t != req.gid {
+            return Err(io::Error::from_raw_os_error(libc::EPERM));
+        }
+        libc::setfsuid(req.uid);
+        if libc::setfsuid(libc::uid_t::MAX) as libc::uid_t != req.uid {
+            return Err(io::Error::from_raw_os_error(libc::EPERM));
+        }
+    }
+    Ok(guard)
+}
+
+impl Drop for Impersonation {
+    fn drop(&mut self) {
+        unsafe {
+            libc::setfsuid(self.uid);
+            libc::setfsgid(self.gid);
+        }
+        if let Err(e) = set_groups(&self.groups) {
+            error!("unable to restore supplementary groups: {}", e);
+        }
+    }
+}
+
+fn get_groups() -> io::Result<Vec<libc::gid_t>> {
+    let n = unsafe { libc::getgroups(0, std::ptr::null_mut()) };
+    if n < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut groups = vec![0; n as usize];
+    let n = unsafe { libc::getgroups(n, groups.as_mut_ptr()) };
+    if n < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    groups.truncate(n as usize);
+    Ok(groups)
+}
+
+/// Set the supplementary groups of the current thread only. The C library's `setgroups` sets them
+/// for every thread in the process.
+fn set_groups(groups: &[libc::gid_t]) -> io::Result<()> {
+    #[cfg(any(target_arch = "x86", target_arch = "arm"))]
+    let syscall = libc::SYS_setgroups32;
+    #[cfg(not(any(target_arch = "x86", target_arch = "arm")))]
+    let syscall = libc::SYS_setgroups;
+    if unsafe { libc::syscall(syscall, groups.len(), groups.as_ptr()) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[test]
+fn test_impersonate() {
+    use std::fs;
+    use std::os::unix::fs::MetadataExt;
+
+    let req = RequestInfo { unique: 0, uid: 12345, gid: 23456, pid: 0, node: None, umask: None, lock_owner: None };
+    if unsafe { libc::geteuid() } != 0 {
+        // Without root, all it can do is fail.
+        assert_eq!(Some(libc::EPERM), impersonate(&req).unwrap_err().raw_os_error());
+        return;
+    }
+
+    let dir = std::env::temp_dir().join(format!("fuse_mt_impersonate_{}", std::process::id()));
+    fs::create_dir(&dir).unwrap();
+    fs::set_permissions(&dir, std::os::unix::fs::PermissionsExt::from_mode(0o777)).unwrap();
+    let groups = get_groups().unwrap();
+    {
+        let _caller = impersonate(&req).unwrap();
+        fs::write(dir.join("file"), b"").unwrap();
+        assert!(get_groups().unwrap().is_empty());
+    }
+    {
+        let _caller = impersonate_with_groups(&req, &[23456, 34567]).unwrap();
+        assert_eq!(vec![23456, 34567], get_groups().unwrap());
+    }
+    let meta = fs::metadata(dir.join("file")).unwrap();
+    assert_eq!((12345, 23456), (meta.uid(), meta.gid()));
+    assert_eq!(groups, get_groups().unwrap());
+    assert_eq!(0, unsafe { libc::setfsuid(libc::uid_t::MAX) });
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -3,31 +3,161 @@
 // Copyright (c) 2016-2022 by William R. Fraser
 //
 
-use std::borrow::Borrow;
-use std::cmp::{Eq, PartialEq};
 use std::collections::{HashMap, VecDeque};
 use std::collections::hash_map::Entry::*;
-use std::hash::{Hash, Hasher};
-use std::path::{Path, PathBuf};
+use std::ffi::OsStr;
+use std::hash::{BuildHasherDefault, Hasher};
+use std::mem;
+use std::path::{Component, Path, PathBuf};
 use std::sync::Arc;
 
+use crate::state_dump::InodeState;
+use crate::types::FsPath;
+
 pub type Inode = u64;
 pub type Generation = u64;
 pub type LookupCount = u64;
 
+/// The number of an interned file name.
+type NameId = u32;
+
+/// File names, each stored once no matter how many directories have an entry by that name.
+#[derive(Debug, Default)]
+struct Names {
+    ids: HashMap<Arc<OsStr>, NameId>,
+    /// Each name, and the number of table entries with it, by number. Numbers are re-used once
+    /// their name is no longer used.
+    names: Vec<(Option<Arc<OsStr>>, usize)>,
+    free: Vec<NameId>,
+    /// Total length of the names, for `memory_usage`.
+    bytes: usize,
+}
+
+impl Names {
+    fn find(&self, name: &OsStr) -> Option<NameId> {
+        self.ids.get(name).copied()
+    }
+
+    fn get(&self, id: NameId) -> &OsStr {
+        self.names[id as usize].0.as_ref().unwrap()
+    }
+
+    /// Get the number for a name, adding it if it's new, and count one more use of it.
+    fn intern(&mut self, name: &OsStr) -> NameId {
+        let id = match self.ids.get(name) {
+            Some(&id) => id,
+            None => {
+                let name: Arc<OsStr> = Arc::from(name);
+                let id = match self.free.pop() {
+                    Some(id) => {
+                        self.names[id as usize] = (Some(name.clone()), 0);
+                        id
+                    }
+                    None => {
+                        self.names.push((Some(name.clone()), 0));
+                        (self.names.len() - 1) as NameId
+                    }
+                };
+                self.bytes += name.len();
+                self.ids.insert(name, id);
+                id
+            }
+        };
+        self.names[id as usize].1 += 1;
+        id
+    }
+
+    /// Count one less use of a name, removing it if that was the last.
+    fn release(&mut self, id: NameId) {
+        let entry = &mut self.names[id as usize];
+        entry.1 -= 1;
+        if entry.1 == 0 {
+            let name = entry.0.take().unwrap();
+            self.bytes -= name.len();
+            self.ids.remove(&name);
+            self.free.push(id);
+        }
+    }
+
+    fn memory_usage(&self) -> usize {
+        // Each name is in an `Arc`, with two counts ahead of it. Plus one control byte per bucket.
+        self.ids.capacity() * (mem::size_of::<(Arc<OsStr>, NameId)>() + 1)
+            + self.names.capacity() * mem::size_of::<(Option<Arc<OsStr>>, usize)>()
+            + self.free.capacity() * mem::size_of::<NameId>()
+            + self.ids.len() * 2 * mem::size_of::<usize>()
+            + self.bytes
+    }
+}
+
+/// A hasher for keys made of the table's own indexes.
+///
+/// The default hasher resists collisions engineered from its input, at several times the cost of
+/// this one, but nobody outside gets to choose the indexes. Names, which they do choose, are only
+/// hashed once per lookup, with the default hasher, to find their index.
+#[derive(Debug, Default)]
+struct IndexHasher(u64);
+
+impl Hasher for IndexHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.write_u64(u64::from(byte));
+        }
+    }
+
+    fn write_u32(&mut self, n: u32) {
+        self.write_u64(u64::from(n));
+    }
+
+    fn write_u64(&mut self, n: u64) {
+        // The same mixing as the Firefox and rustc hasher.
+        self.0 = (self.0.rotate_left(5) ^ n).wrapping_mul(0x517c_c1b7_2722_0a95);
+    }
+
+    fn write_usize(&mut self, n: usize) {
+        self.write_u64(n as u64);
+    }
+}
+
+type ByName = HashMap<(usize, NameId), usize, BuildHasherDefault<IndexHasher>>;
+
 #[derive(Debug)]
 struct InodeTableEntry {
-    path: Option<Arc<PathBuf>>,
+    /// The index of the entry of the directory this is in, and its name there, or `None` if this
+    /// slot is free. The root's is itself.
+    link: Option<(usize, NameId)>,
     lookups: LookupCount,
     generation: Generation,
+    /// The number of entries in the table which are in this directory. It's kept until there are
+    /// none, so that their paths can still be found.
+    children: usize,
+    /// The full path, if it's been asked for, and the epoch it was built in.
+    path: Option<(u64, FsPath)>,
 }
 
 /// A data structure for mapping paths to inodes and vice versa.
+///
+/// Paths are stored as a name and the inode of the directory they're in, and the names are
+/// interned, so a deep tree takes far less memory than it would with full paths. It also means
+/// renaming a directory doesn't need to touch the entries under it.
+///
+/// Full paths are built when they're asked for, and kept until a directory is renamed or memory
+/// runs short (see `shrink`).
 #[derive(Debug)]
 pub struct InodeTable {
     table: Vec<InodeTableEntry>,
     free_list: VecDeque<usize>,
-    by_path: HashMap<Arc<PathBuf>, usize>,
+    /// Maps a directory's index and a name in it to the index of its entry.
+    by_name: ByName,
+    names: Names,
+    /// Bumped whenever a directory is renamed, which changes the paths of everything under it.
+    /// Paths built before then are built again the next time they're asked for.
+    epoch: u64,
+    /// Total size of the full paths kept, for `memory_usage`.
+    path_bytes: usize,
 }
 
 impl InodeTable {
@@ -42,86 +172,164 @@ impl InodeTable {
         let mut inode_table = InodeTable {
             table: Vec::new(),
             free_list: VecDeque::new(),
-            by_path: HashMap::new()
+            by_name: ByName::default(),
+            names: Names::default(),
+            epoch: 0,
+            path_bytes: 0,
         };
-        let root = Arc::new(PathBuf::from("/"));
+        let root_name = inode_table.names.intern(OsStr::new(""));
         inode_table.table.push(InodeTableEntry {
-            path: Some(root.clone()),
+            link: Some((0, root_name)),
             lookups: 0, // not used for this entry; root is always present.
             generation: 0,
+            children: 0,
+            path: None,
         });
-        inode_table.by_path.insert(root, 0);
         inode_table
     }
 
-    /// Add a path to the inode table.
+    /// Get the number of inodes currently in the table, including the root.
+    pub fn len(&self) -> usize {
+        self.table.len() - self.free_list.len()
+    }
+
+    /// Get the approximate number of bytes of memory used by the table.
+    pub fn memory_usage(&self) -> usize {
+        self.table.capacity() * mem::size_of::<InodeTableEntry>()
+            + self.free_list.capacity() * mem::size_of::<usize>()
+            // Plus one control byte per bucket.
+            + self.by_name.capacity() * (mem::size_of::<((usize, NameId), usize)>() + 1)
+            + self.names.memory_usage()
+            + self.path_bytes
+    }
+
+    /// Give back memory reserved for entries which have since been removed, and drop the full
+    /// paths kept. The table itself can't shrink, because inode numbers are indexes into it.
+    pub fn shrink(&mut self) {
+        for entry in &mut self.table {
+            entry.path = None;
+        }
+        self.path_bytes = 0;
+        self.free_list.shrink_to_fit();
+        self.by_name.shrink_to_fit();
+        self.names.ids.shrink_to_fit();
+        self.names.free.shrink_to_fit();
+    }
+
+    /// Add an entry in the directory with the given inode to the inode table.
     ///
-    /// Returns the inode number the path is now mapped to.
+    /// Returns the inode number the entry is now mapped to.
     /// The returned inode number may be a re-used number formerly assigned to a now-forgotten
     /// path.
     ///
-    /// The path is added with an initial lookup count of 1.
+    /// The entry is added with an initial lookup count of 1.
     ///
     /// This operation runs in O(log n) time.
-    pub fn add(&mut self, path: Arc<PathBuf>) -> (Inode, Generation) {
-        let (inode, generation) = {
-            let (inode, entry) = Self::get_inode_entry(&mut self.free_list, &mut self.table);
-            entry.path = Some(path.clone());
-            entry.lookups = 1;
-            (inode, entry.generation)
-        };
-        debug!("explicitly adding {} -> {:?} with 1 lookups", inode, path);
-        let previous = self.by_path.insert(path, inode as usize - 1);
-        if let Some(previous) = previous {
-            error!("inode table buggered: {:?}", self);
-            panic!("attempted to insert duplicate path into inode table: {:?}", previous);
-        }
+    pub fn add(&mut self, parent: Inode, name: &OsStr) -> (Inode, Generation) {
+        let (inode, generation) = self.insert(parent, name, 1);
+        op_debug!("explicitly adding {} -> {:?} in {} with 1 lookups", inode, name, parent);
         (inode, generation)
     }
 
-    /// Add a path to the inode table if it does not yet exist.
+    /// Add an entry in the directory with the given inode to the inode table if it does not yet
+    /// exist.
     ///
-    /// Returns the inode number the path is now mapped to.
+    /// Returns the inode number the entry is now mapped to.
     ///
-    /// If the path was not in the table, it is added with an initial lookup count of 0.
+    /// If the entry was not in the table, it is added with an initial lookup count of 0.
     ///
     /// This operation runs in O(log n) time.
-    pub fn add_or_get(&mut self, path: Arc<PathBuf>) -> (Inode, Generation) {
-        match self.by_path.entry(path.clone()) {
-            Vacant(path_entry) => {
-                let (inode, entry) = Self::get_inode_entry(&mut self.free_list, &mut self.table);
-                debug!("adding {} -> {:?} with 0 lookups", inode, path);
-                entry.path = Some(path);
-                path_entry.insert(inode as usize - 1);
-                (inode, entry.generation)
-            },
-            Occupied(path_entry) => {
-                let idx = path_entry.get();
-                ((idx + 1) as Inode, self.table[*idx].generation)
+    pub fn add_or_get(&mut self, parent: Inode, name: &OsStr) -> (Inode, Generation) {
+        match self.get_child(parent, name) {
+            Some(inode) => (inode, self.table[inode as usize - 1].generation),
+            None => {
+                let (inode, generation) = self.insert(parent, name, 0);
+                op_debug!("adding {} -> {:?} in {} with 0 lookups", inode, name, parent);
+                (inode, generation)
             }
         }
     }
 
+    fn insert(&mut self, parent: Inode, name: &OsStr, lookups: LookupCount)
+        -> (Inode, Generation)
+    {
+        let parent = parent as usize - 1;
+        let name = self.names.intern(name);
+        let (inode, entry) = Self::get_inode_entry(&mut self.free_list, &mut self.table);
+        entry.link = Some((parent, name));
+        entry.lookups = lookups;
+        let generation = entry.generation;
+        self.table[parent].children += 1;
+        if let Some(previous) = self.by_name.insert((parent, name), inode as usize - 1) {
+            error!("inode table buggered: {:?}", self);
+            panic!("attempted to insert duplicate path into inode table: {:?}", previous);
+        }
+        (inode, generation)
+    }
+
     /// Get the path that corresponds to an inode, if there is one, or None, if it is not in the
     /// table.
     /// Note that the file could be unlinked but still open, in which case it's not actually
     /// reachable from the path returned.
     ///
-    /// This operation runs in O(1) time.
-    pub fn get_path(&self, inode: Inode) -> Option<Arc<PathBuf>> {
-        self.table[inode as usize - 1].path.clone()
+    /// This operation runs in O(1) time, unless the path has to be built, which takes time in
+    /// proportion to its depth.
+    pub fn get_path(&mut self, inode: Inode) -> Option<FsPath> {
+        let idx = inode as usize - 1;
+        let entry = &self.table[idx];
+        entry.link?;
+        match entry.path {
+            Some((epoch, ref path)) if epoch == self.epoch => return Some(path.clone()),
+            _ => (),
+        }
+        let path = FsPath::from(self.build_path(idx));
+        // Each path is in an `Arc`, with two counts ahead of it.
+        self.path_bytes += path.as_os_str().len() + 2 * mem::size_of::<usize>();
+        if let Some((_, old)) = self.table[idx].path.replace((self.epoch, path.clone())) {
+            self.path_bytes -= old.as_os_str().len() + 2 * mem::size_of::<usize>();
+        }
+        Some(path)
     }
 
-    /// Get the inode that corresponds to a path, if there is one, or None, if it is not in the
-    /// table.
+    /// Get the generation of the given inode, if it's in the table.
+    pub fn get_generation(&self, inode: Inode) -> Option<Generation> {
+        let entry = self.table.get(inode as usize - 1)?;
+        entry.link.map(|_| entry.generation)
+    }
+
+    /// Get the inode of the directory the given inode is in, if it's in the table. The root is in
+    /// itself.
+    pub fn get_parent(&self, inode: Inode) -> Option<Inode> {
+        self.table[inode as usize - 1].link.map(|(parent, _)| (parent + 1) as Inode)
+    }
+
+    /// Get the inode of the entry with the given name in the directory with the given inode, if
+    /// there is one.
     ///
     /// This operation runs in O(log n) time.
-    pub fn get_inode(&mut self, path: &Path) -> Option<Inode> {
-        self.by_path
-            .get(Pathish::new(path))
+    pub fn get_child(&self, parent: Inode, name: &OsStr) -> Option<Inode> {
+        let name = self.names.find(name)?;
+        self.by_name
+            .get(&(parent as usize - 1, name))
             .map(|idx| (idx + 1) as Inode)
     }
 
+    /// Get the inode that corresponds to a path, if there is one, or None, if it is not in the
+    /// table.
+    ///
+    /// This operation runs in O(log n) time for each component of the path.
+    pub fn get_inode(&self, path: &Path) -> Option<Inode> {
+        let mut inode = 1;
+        for component in path.components() {
+            match component {
+                Component::RootDir => (),
+                Component::Normal(name) => inode = self.get_child(inode, name)?,
+                _ => return None,
+            }
+        }
+        Some(inode)
+    }
+
     /// Increment the lookup count on a given inode.
     ///
     /// Calling this on an invalid inode will result in a panic.
@@ -134,16 +342,16 @@ impl InodeTable {
 
         let entry = &mut self.table[inode as usize - 1];
         entry.lookups += 1;
-        debug!("lookups on {} -> {:?} now {}", inode, entry.path, entry.lookups);
+        op_debug!("lookups on {} -> {:?} now {}", inode, entry.link, entry.lookups);
     }
 
     /// Decrement the lookup count on a given inode by the given number.
     ///
-    /// If the lookup count reaches 0, the path is removed from the table, and the inode number
-    /// is eligible to be re-used.
+    /// If the lookup count reaches 0, and no entries in the table are in it, the entry is removed
+    /// from the table, and the inode number is eligible to be re-used.
     ///
     /// Returns the new lookup count of the inode. (If it returns 0, that means the inode was
-    /// deleted.)
+    /// deleted, or will be once it's no longer needed for the paths of others.)
     ///
     /// Calling this on an invalid inode will result in a panic.
     ///
@@ -153,42 +361,148 @@ impl InodeTable {
             return 1;
         }
 
-        let mut delete = false;
-        let lookups: LookupCount;
         let idx = inode as usize - 1;
+        let entry = &mut self.table[idx];
+        op_debug!("forget entry {:?}", entry);
+        assert!(n <= entry.lookups);
+        entry.lookups -= n;
+        let lookups = entry.lookups;
+        self.remove_if_unused(idx);
+        lookups
+    }
 
-        {
-            let entry = &mut self.table[idx];
-            debug!("forget entry {:?}", entry);
-            assert!(n <= entry.lookups);
-            entry.lookups -= n;
-            lookups = entry.lookups;
-            if lookups == 0 {
-                delete = true;
-                self.by_path.remove(entry.path.as_ref().unwrap());
-            }
+    /// Change the name of an entry, or move it to a different directory, without changing its
+    /// inode number. Everything under it moves with it.
+    /// Lookup counts remain unchanged, even if this is replacing another file.
+    pub fn rename(&mut self, parent: Inode, name: &OsStr, newparent: Inode, newname: &OsStr) {
+        let idx = match self.get_child(parent, name) {
+            Some(inode) => inode as usize - 1,
+            None => return,
+        };
+        let (old_parent, old_name) = self.table[idx].link.unwrap();
+        self.by_name.remove(&(old_parent, old_name));
+
+        let newparent = newparent as usize - 1;
+        let newname = self.names.intern(newname);
+        // This can replace a path with a new inode.
+        self.by_name.insert((newparent, newname), idx);
+        self.table[idx].link = Some((newparent, newname));
+        self.names.release(old_name);
+        self.table[newparent].children += 1;
+        self.table[old_parent].children -= 1;
+
+        self.moved(idx);
+        self.remove_if_unused(old_parent);
+    }
+
+    /// Swap two entries, which may be in different directories, so that each has the other's
+    /// name, without changing their inode numbers. If only one of them is in the table, it's just
+    /// renamed.
+    #[cfg_attr(not(target_os = "macos"), allow(dead_code))] // Only macOS has `exchange`.
+    pub fn exchange(&mut self, parent: Inode, name: &OsStr, newparent: Inode, newname: &OsStr) {
+        let (a, b) = match (self.get_child(parent, name), self.get_child(newparent, newname)) {
+            (Some(a), Some(b)) => (a as usize - 1, b as usize - 1),
+            (Some(_), None) => return self.rename(parent, name, newparent, newname),
+            (None, Some(_)) => return self.rename(newparent, newname, parent, name),
+            (None, None) => return,
+        };
+        if a == b {
+            return;
         }
+        // Each directory loses one entry and gains another, and each name is still used once, so
+        // only the links need to change.
+        let (link_a, link_b) = (self.table[a].link.unwrap(), self.table[b].link.unwrap());
+        self.table[a].link = Some(link_b);
+        self.table[b].link = Some(link_a);
+        self.by_name.insert(link_b, a);
+        self.by_name.insert(link_a, b);
+        self.moved(a);
+        self.moved(b);
+    }
 
-        if delete {
-            self.table[idx].path = None;
-            self.free_list.push_back(idx);
+    /// Drop the full paths which changed when the entry at the given index moved: its own, or, if
+    /// it's a directory with entries in the table, all of them.
+    fn moved(&mut self, idx: usize) {
+        if self.table[idx].children > 0 {
+            self.epoch += 1;
+        } else if let Some((_, path)) = self.table[idx].path.take() {
+            self.path_bytes -= path.as_os_str().len() + 2 * mem::size_of::<usize>();
         }
+    }
 
-        lookups
+    /// Remove the mapping to the entry with the given name in the directory with the given inode,
+    /// but keep the inode around.
+    pub fn unlink(&mut self, parent: Inode, name: &OsStr) {
+        if let Some(name) = self.names.find(name) {
+            self.by_name.remove(&(parent as usize - 1, name));
+        }
+        // Note that the inode->path mapping remains.
     }
 
-    /// Change an inode's path to a different one, without changing the inode number.
-    /// Lookup counts remain unchanged, even if this is replacing another file.
-    pub fn rename(&mut self, oldpath: &Path, newpath: Arc<PathBuf>) {
-        let idx = self.by_path.remove(Pathish::new(oldpath)).unwrap();
-        self.table[idx].path = Some(newpath.clone());
-        self.by_path.insert(newpath, idx); // this can replace a path with a new inode
+    /// List the inodes in the table, in order.
+    pub fn dump(&self) -> Vec<InodeState> {
+        self.table.iter().enumerate()
+            .filter_map(|(idx, entry)| {
+                let link = entry.link?;
+                Some(InodeState {
+                    ino: (idx + 1) as Inode,
+                    path: self.build_path(idx),
+                    lookups: entry.lookups,
+                    generation: entry.generation,
+                    unlinked: idx != 0 && self.by_name.get(&link) != Some(&idx),
+                })
+            })
+            .collect()
     }
 
-    /// Remove the path->inode mapping for a given path, but keep the inode around.
-    pub fn unlink(&mut self, path: &Path) {
-        self.by_path.remove(Pathish::new(path));
-        // Note that the inode->path mapping remains.
+    /// Put together the path of an entry from the names of it and the directories it's in,
+    /// starting from the nearest one whose path is already known.
+    fn build_path(&self, mut idx: usize) -> PathBuf {
+        let mut names = vec![];
+        let base = loop {
+            let entry = &self.table[idx];
+            match entry.path {
+                Some((epoch, ref path)) if epoch == self.epoch => break &**path,
+                _ => (),
+            }
+            if idx == 0 {
+                break Path::new("/");
+            }
+            let (parent, name) = entry.link.unwrap();
+            names.push(self.names.get(name));
+            idx = parent;
+        };
+        let len = base.as_os_str().len() + names.iter().map(|name| name.len() + 1).sum::<usize>();
+        let mut path = PathBuf::with_capacity(len);
+        path.push(base);
+        for name in names.iter().rev() {
+            path.push(name);
+        }
+        path
+    }
+
+    /// Remove an entry from the table if the kernel has forgotten it and there are no entries in
+    /// it, and then the directory it's in, if that was the last thing keeping it.
+    fn remove_if_unused(&mut self, mut idx: usize) {
+        loop {
+            let entry = &mut self.table[idx];
+            if idx == 0 || entry.lookups > 0 || entry.children > 0 {
+                return;
+            }
+            let (parent, name) = entry.link.take().unwrap();
+            if let Some((_, path)) = entry.path.take() {
+                self.path_bytes -= path.as_os_str().len() + 2 * mem::size_of::<usize>();
+            }
+            if let Occupied(mapping) = self.by_name.entry((parent, name)) {
+                if *mapping.get() == idx {
+                    mapping.remove();
+                }
+            }
+            self.names.release(name);
+            self.free_list.push_back(idx);
+            self.table[parent].children -= 1;
+            idx = parent;
+        }
     }
 
     /// Get a free indode table entry and its number, either by allocating a new one, or re-using
@@ -201,15 +515,17 @@ impl InodeTable {
             -> (Inode, &'a mut InodeTableEntry) {
         let idx = match free_list.pop_front() {
             Some(idx) => {
-                debug!("re-using inode {}", idx + 1);
+                op_debug!("re-using inode {}", idx + 1);
                 table[idx].generation += 1;
                 idx
             },
             None => {
                 table.push(InodeTableEntry {
-                    path: None,
+                    link: None,
                     lookups: 0,
                     generation: 0,
+                    children: 0,
+                    path: None,
                 });
                 table.len() - 1
             }
@@ -218,84 +534,57 @@ impl InodeTable {
     }
 }
 
-/// Facilitates comparing Rc<PathBuf> and &Path
-#[derive(Debug)]
-struct Pathish {
-    inner: Path,
-}
-
-impl Pathish {
-    pub fn new(p: &Path) -> &Pathish {
-        unsafe { &*(p as *const _ as *const Pathish) }
-    }
-}
-
-impl Borrow<Pathish> for Arc<PathBuf> {
-    fn borrow(&self) -> &Pathish {
-        Pathish::new(self.as_path())
-    }
-}
-
-impl Hash for Pathish {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        self.inner.hash(state);
-    }
-}
-
-impl Eq for Pathish {
-}
-
-impl PartialEq for Pathish {
-    fn eq(&self, other: &Pathish) -> bool {
-        self.inner.eq(&other.inner)
-    }
+#[cfg(test)]
+fn name(s: &str) -> &OsStr {
+    OsStr::new(s)
 }
 
 #[test]
 fn test_inode_reuse() {
     let mut table = InodeTable::new();
-    let path1 = Arc::new(PathBuf::from("/foo/a"));
-    let path2 = Arc::new(PathBuf::from("/foo/b"));
+    let foo = table.add(1, name("foo")).0;
 
     // Add a path.
-    let inode1 = table.add(path1.clone()).0;
+    let inode1 = table.add(foo, name("a")).0;
     assert!(inode1 != 1);
-    assert_eq!(*path1, *table.get_path(inode1).unwrap());
+    assert_eq!(Path::new("/foo/a"), &*table.get_path(inode1).unwrap());
 
     // Add a second path; verify that the inode number is different.
-    let inode2 = table.add(path2.clone()).0;
+    let inode2 = table.add(foo, name("b")).0;
     assert!(inode2 != inode1);
     assert!(inode2 != 1);
-    assert_eq!(*path2, *table.get_path(inode2).unwrap());
+    assert_eq!(Path::new("/foo/b"), &*table.get_path(inode2).unwrap());
 
     // Forget the first inode; verify that lookups on it fail.
+    assert_eq!(Some(0), table.get_generation(inode1));
     assert_eq!(0, table.forget(inode1, 1));
     assert!(table.get_path(inode1).is_none());
+    assert!(table.get_generation(inode1).is_none());
 
     // Add a third path; verify that the inode is reused.
-    let (inode3, generation3) = table.add(Arc::new(PathBuf::from("/foo/c")));
+    let (inode3, generation3) = table.add(foo, name("c"));
     assert_eq!(inode1, inode3);
     assert_eq!(1, generation3);
+    assert_eq!(Some(1), table.get_generation(inode3));
 
     // Check that lookups on the third path succeed.
-    assert_eq!(Path::new("/foo/c"), *table.get_path(inode3).unwrap());
+    assert_eq!(Path::new("/foo/c"), &*table.get_path(inode3).unwrap());
 }
 
 #[test]
 fn test_add_or_get() {
     let mut table = InodeTable::new();
-    let path1 = Arc::new(PathBuf::from("/foo/a"));
-    let path2 = Arc::new(PathBuf::from("/foo/b"));
+    let foo = table.add(1, name("foo")).0;
 
     // add_or_get() a path and verify that get by inode works before lookup() is done.
-    let inode1 = table.add_or_get(path1.clone()).0;
-    assert_eq!(*path1, *table.get_path(inode1).unwrap());
+    let inode1 = table.add_or_get(foo, name("a")).0;
+    assert_eq!(Path::new("/foo/a"), &*table.get_path(inode1).unwrap());
     table.lookup(inode1);
 
     // add() a second path and verify that get by path and inode work.
-    let inode2 = table.add(path2.clone()).0;
-    assert_eq!(*path2, *table.get_path(inode2).unwrap());
-    assert_eq!(inode2, table.add_or_get(path2).0);
+    let inode2 = table.add(foo, name("b")).0;
+    assert_eq!(Path::new("/foo/b"), &*table.get_path(inode2).unwrap());
+    assert_eq!(inode2, table.add_or_get(foo, name("b")).0);
     table.lookup(inode2);
 
     // Check the ref counts by doing a single forget.
@@ -303,41 +592,213 @@ fn test_add_or_get() {
     assert_eq!(1, table.forget(inode2, 1));
 }
 
+#[test]
+fn test_forget_unused() {
+    let mut table = InodeTable::new();
+    let foo = table.add(1, name("foo")).0;
+
+    // An entry added without a lookup is dropped by forgetting nothing.
+    let inode = table.add_or_get(foo, name("a")).0;
+    assert_eq!(0, table.forget(inode, 0));
+    assert!(table.get_inode(Path::new("/foo/a")).is_none());
+
+    // One the kernel has looked up stays.
+    let inode = table.add_or_get(foo, name("b")).0;
+    table.lookup(inode);
+    assert_eq!(1, table.forget(inode, 0));
+    assert_eq!(inode, table.get_inode(Path::new("/foo/b")).unwrap());
+}
+
 #[test]
 fn test_inode_rename() {
     let mut table = InodeTable::new();
-    let path1 = Arc::new(PathBuf::from("/foo/a"));
-    let path2 = Arc::new(PathBuf::from("/foo/b"));
+    let foo = table.add(1, name("foo")).0;
+    let (path1, path2) = (Path::new("/foo/a"), Path::new("/foo/b"));
 
     // Add a path; verify that get by path and inode work.
-    let inode = table.add(path1.clone()).0;
-    assert_eq!(*path1, *table.get_path(inode).unwrap());
-    assert_eq!(inode, table.get_inode(&path1).unwrap());
+    let inode = table.add(foo, name("a")).0;
+    assert_eq!(path1, &*table.get_path(inode).unwrap());
+    assert_eq!(inode, table.get_inode(path1).unwrap());
 
     // Rename the inode; verify that get by the new path works and old path doesn't, and get by
     // inode still works.
-    table.rename(&path1, path2.clone());
-    assert!(table.get_inode(&path1).is_none());
-    assert_eq!(inode, table.get_inode(&path2).unwrap());
-    assert_eq!(*path2, *table.get_path(inode).unwrap());
+    table.rename(foo, name("a"), foo, name("b"));
+    assert!(table.get_inode(path1).is_none());
+    assert_eq!(inode, table.get_inode(path2).unwrap());
+    assert_eq!(path2, &*table.get_path(inode).unwrap());
+}
+
+#[test]
+fn test_directory_rename() {
+    let mut table = InodeTable::new();
+    let foo = table.add(1, name("foo")).0;
+    let bar = table.add(foo, name("bar")).0;
+    let file = table.add(bar, name("file")).0;
+    assert_eq!(Path::new("/foo/bar/file"), &*table.get_path(file).unwrap());
+
+    // Everything under a renamed directory moves with it.
+    let baz = table.add(1, name("baz")).0;
+    table.rename(foo, name("bar"), baz, name("qux"));
+    assert_eq!(Path::new("/baz/qux/file"), &*table.get_path(file).unwrap());
+    assert_eq!(Some(file), table.get_inode(Path::new("/baz/qux/file")));
+    assert_eq!(None, table.get_inode(Path::new("/foo/bar/file")));
+    assert_eq!(Some(baz), table.get_parent(bar));
+
+    // A directory stays as long as something under it does, even once it's forgotten.
+    assert_eq!(0, table.forget(bar, 1));
+    assert_eq!(Path::new("/baz/qux"), &*table.get_path(bar).unwrap());
+    table.forget(file, 1);
+    assert!(table.get_path(bar).is_none());
+    assert_eq!(3, table.len());
+}
+
+#[test]
+fn test_exchange() {
+    let mut table = InodeTable::new();
+    let foo = table.add(1, name("foo")).0;
+    let bar = table.add(1, name("bar")).0;
+    let a = table.add(foo, name("a")).0;
+    let b = table.add(bar, name("b")).0;
+    assert_eq!(Path::new("/foo/a"), &*table.get_path(a).unwrap());
+
+    // Each keeps its inode number under the other's name.
+    table.exchange(foo, name("a"), bar, name("b"));
+    assert_eq!(Some(b), table.get_inode(Path::new("/foo/a")));
+    assert_eq!(Some(a), table.get_inode(Path::new("/bar/b")));
+    assert_eq!(Path::new("/bar/b"), &*table.get_path(a).unwrap());
+    assert_eq!(Some(foo), table.get_parent(b));
+
+    // With only one of them in the table, it's renamed.
+    table.exchange(foo, name("c"), foo, name("a"));
+    assert_eq!(None, table.get_inode(Path::new("/foo/a")));
+    assert_eq!(Path::new("/foo/c"), &*table.get_path(b).unwrap());
 }
 
 #[test]
 fn test_unlink() {
     let mut table = InodeTable::new();
-    let path = Arc::new(PathBuf::from("/foo/bar"));
+    let foo = table.add(1, name("foo")).0;
+    let path = Path::new("/foo/bar");
 
     // Add a path.
-    let inode = table.add(path.clone()).0;
+    let inode = table.add(foo, name("bar")).0;
 
     // Unlink it and verify that get by path fails.
-    table.unlink(&path);
-    assert!(table.get_inode(&path).is_none());
+    table.unlink(foo, name("bar"));
+    assert!(table.get_inode(path).is_none());
 
     // Getting the path for the inode should still return the path.
-    assert_eq!(*path, *table.get_path(inode).unwrap());
+    assert_eq!(path, &*table.get_path(inode).unwrap());
 
     // Verify that forgetting it once drops the refcount to zero and then lookups by inode fail.
     assert_eq!(0, table.forget(inode, 1));
     assert!(table.get_path(inode).is_none());
 }
+
+#[test]
+fn test_memory_usage() {
+    let mut table = InodeTable::new();
+    let foo = table.add(1, name("foo")).0;
+    assert_eq!(3, table.names.bytes);
+
+    // Names are only stored once.
+    let a = table.add(foo, name("a")).0;
+    let foo_a = table.add(a, name("foo")).0;
+    assert_eq!(4, table.names.bytes);
+    let with_names = table.memory_usage();
+
+    // Full paths are kept once they're built, until the table is shrunk.
+    table.get_path(foo_a);
+    assert!(table.memory_usage() > with_names);
+    table.shrink();
+    assert_eq!(0, table.path_bytes);
+
+    // Forgetting an inode frees its name once nothing else uses it, though not its slot in the
+    // table.
+    table.forget(foo_a, 1);
+    assert_eq!(4, table.names.bytes);
+    table.forget(a, 1);
+    assert_eq!(3, table.names.bytes);
+    assert_eq!(2, table.names.ids.len());
+}
+
+#[test]
+fn test_dump() {
+    let mut table = InodeTable::new();
+    let a = table.add(1, name("a")).0;
+    let b = table.add(1, name("b")).0;
+    table.lookup(b);
+    let c = table.add(1, name("c")).0;
+    table.forget(a, 1);
+    table.unlink(1, name("c"));
+
+    let dump = table.dump();
+    assert_eq!(vec![1, b, c], dump.iter().map(|inode| inode.ino).collect::<Vec<_>>());
+    assert_eq!(Path::new("/"), dump[0].path);
+    assert_eq!(2, dump[1].lookups);
+    assert!(!dump[1].unlinked);
+    assert_eq!(Path::new("/c"), dump[2].path);
+    assert!(dump[2].unlinked);
+}
+
+/// Throughput of the operations every request goes through, on a table of 100,000 entries. Run it
+/// with `cargo test --release bench_inode_table -- --ignored --nocapture`.
+#[test]
+#[ignore]
+fn bench_inode_table() {
+    use std::time::Instant;
+
+    const DIRS: usize = 1000;
+    const FILES: usize = 100;
+    let names: Vec<String> = (0..DIRS).map(|i| format!("entry{}", i)).collect();
+    let report = |op: &str, start: Instant| {
+        let elapsed = start.elapsed();
+        println!("{:>10}: {:>6} ns/op, {:>10.0} ops/s", op,
+            elapsed.as_nanos() / (DIRS * FILES) as u128,
+            (DIRS * FILES) as f64 / elapsed.as_secs_f64());
+    };
+
+    let mut table = InodeTable::new();
+    let start = Instant::now();
+    let mut inodes = Vec::with_capacity(DIRS * FILES);
+    for dir in &names {
+        let dir = table.add_or_get(1, name(dir)).0;
+        for file in &names[..FILES] {
+            inodes.push(table.add(dir, name(file)).0);
+        }
+    }
+    report("add", start);
+
+    let start = Instant::now();
+    for (i, &inode) in inodes.iter().enumerate() {
+        let dir = table.get_parent(inode).unwrap();
+        assert_eq!(Some(inode), table.get_child(dir, name(&names[i % FILES])));
+    }
+    report("get_child", start);
+
+    let paths: Vec<_> = inodes.iter().map(|&inode| table.get_path(inode).unwrap()).collect();
+    let start = Instant::now();
+    for (path, &inode) in paths.iter().zip(&inodes) {
+        assert_eq!(Some(inode), table.get_inode(path));
+    }
+    report("get_inode", start);
+
+    let start = Instant::now();
+    for &inode in &inodes {
+        table.get_path(inode).unwrap();
+    }
+    report("get_path", start);
+
+    let start = Instant::now();
+    for &inode in &inodes {
+        table.lookup(inode);
+    }
+    report("lookup", start);
+
+    let start = Instant::now();
+    for &inode in &inodes {
+        table.forget(inode, 2);
+    }
+    report("forget", start);
+    assert_eq!(1, table.len());
+}
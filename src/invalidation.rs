@@ -0,0 +1,89 @@
+// Invalidation :: notifying FuseMT of changes made behind its back.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// How many paths to queue up before giving up and invalidating everything.
+const MAX_PENDING: usize = 4096;
+
+/// A handle for telling FuseMT that something in the filesystem changed without going through
+/// FUSE, for example when a passthrough filesystem's backing directory is modified directly.
+///
+/// Get one from `FuseMT::invalidator` before mounting; it can be cloned and used from any
+/// thread. Invalidations are applied the next time FuseMT handles a directory read: any open
+/// directory whose listing was cached before the change gets re-listed from the filesystem when it
+/// is next read from the beginning, instead of returning the old listing.
+///
+/// Note that the kernel keeps its own caches of attributes and directory entries, which are only
+/// refreshed when the TTL returned by the filesystem expires. Filesystems whose contents change
+/// behind FUSE's back should use short TTLs.
+#[derive(Clone, Debug, Default)]
+pub struct Invalidator {
+    inner: Arc<Pending>,
+}
+
+#[derive(Debug, Default)]
+struct Pending {
+    any: AtomicBool,
+    queue: Mutex<PendingQueue>,
+}
+
+#[derive(Debug, Default)]
+struct PendingQueue {
+    paths: Vec<PathBuf>,
+    overflowed: bool,
+}
+
+/// Invalidations taken from the queue.
+pub(crate) enum Invalidations {
+    Paths(Vec<PathBuf>),
+    Everything,
+}
+
+impl Invalidator {
+    pub(crate) fn new() -> Invalidator {
+        Invalidator::default()
+    }
+
+    /// Note that the filesystem entry at the given path was created, removed, or changed.
+    ///
+    /// Both the entry itself (if it's a directory) and its parent directory are invalidated.
+    pub fn invalidate(&self, path: impl AsRef<Path>) {
+        let path = path.as_ref();
+        let mut queue = self.inner.queue.lock().unwrap();
+        if queue.paths.len() >= MAX_PENDING {
+            queue.paths.clear();
+            queue.overflowed = true;
+        } else if !queue.overflowed && queue.paths.last().map(PathBuf::as_path) != Some(path) {
+            queue.paths.push(path.to_owned());
+        }
+        self.inner.any.store(true, Ordering::Release);
+    }
+
+    /// Note that anything in the filesystem may have changed.
+    pub fn invalidate_all(&self) {
+        let mut queue = self.inner.queue.lock().unwrap();
+        queue.paths.clear();
+        queue.overflowed = true;
+        self.inner.any.store(true, Ordering::Release);
+    }
+
+    /// Take all the pending invalidations, if there are any.
+    pub(crate) fn take(&self) -> Option<Invalidations> {
+        if !self.inner.any.load(Ordering::Acquire) {
+            return None;
+        }
+        let mut queue = self.inner.queue.lock().unwrap();
+        self.inner.any.store(false, Ordering::Release);
+        let queue = std::mem::take(&mut *queue);
+        Some(if queue.overflowed {
+            Invalidations::Everything
+        } else {
+            Invalidations::Paths(queue.paths)
+        })
+    }
+}
@@ -0,0 +1,30 @@
+// JSON :: the little bit of JSON encoding needed for machine-readable output.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+use std::fmt::Write;
+
+/// Append a string to `out` as a quoted JSON string.
+pub fn write_str(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[test]
+fn test_write_str() {
+    let mut out = String::new();
+    write_str(&mut out, "a\"b\\c\nd\u{1}é");
+    assert_eq!("\"a\\\"b\\\\c\\nd\\u0001é\"", out);
+}
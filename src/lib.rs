@@ -19,16 +19,109 @@
 #[macro_use]
 extern crate log;
 
+/// `debug!`, for messages logged on every call, which the `quiet` feature compiles out.
+#[cfg(not(feature = "quiet"))]
+macro_rules! op_debug {
+    ($($arg:tt)*) => { debug!($($arg)*) };
+}
+
+/// The arguments are still type-checked, but never evaluated.
+#[cfg(feature = "quiet")]
+macro_rules! op_debug {
+    ($($arg:tt)*) => {
+        if false {
+            debug!($($arg)*)
+        }
+    };
+}
+
+mod attr_prefetch;
+mod clock;
 mod directory_cache;
+mod errno;
+mod file_limit;
+mod freeze;
 mod fusemt;
+mod groups;
+mod handle_table;
+mod hotspots;
 mod inode_table;
+mod invalidation;
+mod json;
+mod memory;
+mod open_files;
+mod periodic_sync;
+mod pool_stats;
+mod prefetch;
+mod readiness;
+mod reentrancy;
+mod responder;
+mod revalidation;
+mod silly_rename;
+mod state_dump;
+mod stats;
+mod tunables;
 mod types;
+mod version;
+mod worker_pool;
+mod write_barrier;
+mod xattr_cache;
+
+#[cfg(any(feature = "http", feature = "s3", feature = "webdav"))]
+mod http;
+
+pub mod async_io;
+pub mod block_map;
+#[cfg(feature = "cli")]
+pub mod cli;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub mod impersonate;
+pub mod libc_wrappers;
+#[cfg(feature = "ninep")]
+pub mod ninep;
+pub mod object_store;
+pub mod op_log;
+pub mod options;
+#[cfg(feature = "otel")]
+pub mod otel;
+pub mod session;
+pub mod sparse;
+
+#[cfg(feature = "remote")]
+pub mod remote;
+#[cfg(all(feature = "systemd", target_os = "linux"))]
+pub mod systemd;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub mod watch;
+#[cfg(feature = "webdav")]
+pub mod webdav;
+pub mod wrappers;
 
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 
-pub use fuser::FileType;
+pub use fuser::{FileType, MountOption};
+/// The version of `fuser` FuseMT is built on, for mounting `FuseMT` with `fuser::Session` or
+/// wrapping it in other `fuser::Filesystem` implementations.
+pub use fuser;
+pub use crate::clock::{Clock, ManualClock, SystemClock};
+pub use crate::errno::{io_errno, IoResultExt};
+pub use crate::freeze::Freezer;
 pub use crate::fusemt::*;
+pub use crate::groups::GroupCache;
+pub use crate::hotspots::HOTSPOTS_XATTR;
+pub use crate::invalidation::Invalidator;
+pub use crate::options::{
+    check_options, parse_mount_options, validate_mount_options, CheckOptions, MacOption, OptionError,
+    ParsedOptions,
+};
+pub use crate::pool_stats::{PoolStats, WaitTime};
+pub use crate::readiness::Readiness;
+pub use crate::reentrancy::Reentrancy;
+pub use crate::state_dump::{DirectoryState, InodeState, StateDump, STATE_XATTR};
+pub use crate::stats::STATS_XATTR;
+pub use crate::tunables::Tunables;
 pub use crate::types::*;
+pub use crate::version::{version, VersionInfo};
 
 // Forward to similarly-named fuser functions to work around deprecation for now.
 // When these are removed, we'll have to either reimplement or break reverse compat.
@@ -36,7 +129,8 @@ pub use crate::types::*;
 
 use std::ffi::OsStr;
 use std::io;
-use std::path::Path;
+use std::os::unix::io::RawFd;
+use std::path::{Path, PathBuf};
 
 /// Mount the given filesystem to the given mountpoint. This function will not return until the
 /// filesystem is unmounted.
@@ -63,3 +157,54 @@ pub fn spawn_mount<FS: fuser::Filesystem + Send + 'static, P: AsRef<Path>>(
     #[allow(deprecated)]
     fuser::spawn_mount(fs, mountpoint, options)
 }
+
+/// Mount the given filesystem to the given mountpoint. This function will not return until the
+/// filesystem is unmounted.
+///
+/// Like `mount`, but takes typed options, such as those from `parse_mount_options`. Fails with
+/// `InvalidInput` if any of them aren't supported on this platform.
+#[inline(always)]
+pub fn mount2<FS: fuser::Filesystem, P: AsRef<Path>>(
+    fs: FS,
+    mountpoint: P,
+    options: &[MountOption],
+) -> io::Result<()> {
+    validate_mount_options(options).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    fuser::mount2(fs, mountpoint, options)
+}
+
+/// Mount the given filesystem to the given mountpoint. This function spawns a background thread to
+/// handle filesystem operations while being mounted and therefore returns immediately. The
+/// returned handle should be stored to reference the mounted filesystem. If it's dropped, the
+/// filesystem will be unmounted.
+///
+/// Like `spawn_mount`, but takes typed options, such as those from `parse_mount_options`. Fails
+/// with `InvalidInput` if any of them aren't supported on this platform.
+///
+/// To be able to leave the filesystem mounted when the handle is dropped, use
+/// `session::MountedSession` instead.
+#[inline(always)]
+pub fn spawn_mount2<FS: fuser::Filesystem + Send + 'static, P: AsRef<Path>>(
+    fs: FS,
+    mountpoint: P,
+    options: &[MountOption],
+) -> io::Result<fuser::BackgroundSession> {
+    validate_mount_options(options).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    fuser::spawn_mount2(fs, mountpoint, options)
+}
+
+/// Get a mountpoint which serves a FUSE connection that's already open as the given file
+/// descriptor, instead of mounting one. This is for systems where the filesystem's process can't
+/// mount it, like Android, where a privileged helper opens `/dev/fuse`, mounts it, and hands the
+/// file descriptor on, by inheritance or over a Unix socket.
+///
+/// Give the result to any of the mount functions, or `MountedSession::spawn`, in place of a
+/// path. This needs `fuser` to be built against libfuse 3.3 or later, which takes over the file
+/// descriptor when given such a mountpoint. Options for the kernel have no effect, since the
+/// helper has already mounted it, and unmounting is left to the helper too.
+pub fn preopened_mountpoint(fd: RawFd) -> io::Result<PathBuf> {
+    if unsafe { libc::fcntl(fd, libc::F_GETFD) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(PathBuf::from(format!("/dev/fd/{}", fd)))
+}
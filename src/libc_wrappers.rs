@@ -0,0 +1,360 @@
+// Libc Wrappers :: Safe wrappers around system calls.
+//
+// Copyright (c) 2016-2026 by William R. Fraser
+//
+
+//! Safe wrappers around the system calls needed by filesystems which pass calls through to files
+//! on a local filesystem, like the `passthrufs` example. Errors are returned as error numbers,
+//! ready to be returned from `FilesystemMT` calls.
+//!
+//! Calls which can be interrupted by a signal before they've done anything (failing with `EINTR`)
+//! are retried, so that signals sent to the process running the filesystem don't show up as
+//! errors through the mount.
+
+use std::convert::TryFrom;
+use std::ffi::{CStr, CString, OsString};
+use std::io;
+use std::mem;
+use std::os::unix::ffi::OsStringExt;
+
+/// The platform differences the wrappers need papered over.
+mod sys {
+    pub use libc::*;
+
+    #[cfg(target_os = "macos")]
+    pub type stat64 = stat;
+
+    #[cfg(target_os = "macos")]
+    pub unsafe fn lstat64(path: *const c_char, buf: *mut stat64) -> c_int {
+        lstat(path, buf)
+    }
+
+    #[cfg(target_os = "macos")]
+    pub unsafe fn fstat64(fd: c_int, buf: *mut stat64) -> c_int {
+        fstat(fd, buf)
+    }
+
+    #[cfg(target_os = "macos")]
+    pub type statvfs64 = statvfs;
+
+    #[cfg(target_os = "macos")]
+    pub unsafe fn statvfs64(path: *const c_char, buf: *mut statvfs64) -> c_int {
+        statvfs(path, buf)
+    }
+
+    #[cfg(target_os = "macos")]
+    pub unsafe fn llistxattr(path: *const c_char, list: *mut c_char, size: size_t) -> ssize_t {
+        listxattr(path, list, size, XATTR_NOFOLLOW)
+    }
+
+    #[cfg(target_os = "macos")]
+    pub unsafe fn lgetxattr(path: *const c_char, name: *const c_char, value: *mut c_void,
+                            size: size_t) -> ssize_t {
+        getxattr(path, name, value, size, 0, XATTR_NOFOLLOW)
+    }
+
+    #[cfg(target_os = "macos")]
+    pub unsafe fn lremovexattr(path: *const c_char, name: *const c_char) -> c_int {
+        removexattr(path, name, XATTR_NOFOLLOW)
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "emscripten"))]
+    pub unsafe fn errno_location() -> *mut c_int {
+        __errno_location()
+    }
+
+    #[cfg(target_os = "android")]
+    pub unsafe fn errno_location() -> *mut c_int {
+        __errno()
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "emscripten", target_os = "android")))]
+    pub unsafe fn errno_location() -> *mut c_int {
+        __error()
+    }
+}
+
+pub use self::sys::{stat64, statvfs64};
+
+macro_rules! into_cstring {
+    ($path:expr, $syscall:expr) => {
+        match CString::new($path.into_vec()) {
+            Ok(s) => s,
+            Err(e) => {
+                error!(concat!($syscall, ": path {:?} contains interior NUL byte"),
+                       OsString::from_vec(e.into_vec()));
+                return Err(libc::EINVAL);
+            }
+        }
+    }
+}
+
+fn last_errno() -> libc::c_int {
+    io::Error::last_os_error().raw_os_error().unwrap()
+}
+
+/// Make a system call which returns -1 on failure, retrying it as long as it fails with `EINTR`.
+fn retry<T: From<i8> + PartialEq>(mut call: impl FnMut() -> T) -> Result<T, libc::c_int> {
+    loop {
+        let result = call();
+        if result != T::from(-1) {
+            return Ok(result);
+        }
+        match last_errno() {
+            libc::EINTR => continue,
+            e => return Err(e),
+        }
+    }
+}
+
+pub fn opendir(path: OsString) -> Result<u64, libc::c_int> {
+    let path_c = into_cstring!(path, "opendir");
+
+    loop {
+        let dir: *mut libc::DIR = unsafe { libc::opendir(path_c.as_ptr()) };
+        if !dir.is_null() {
+            return Ok(dir as u64);
+        }
+        match last_errno() {
+            libc::EINTR => continue,
+            e => return Err(e),
+        }
+    }
+}
+
+/// Read the next entry from a directory opened with `opendir`, or `None` at the end of it.
+///
+/// Only `d_ino`, `d_type`, and `d_name` are filled in in the entry returned.
+pub fn readdir(fh: u64) -> Result<Option<libc::dirent>, libc::c_int> {
+    let dir = fh as usize as *mut libc::DIR;
+    loop {
+        // `readdir` returns null both at the end of the directory and on failure, and only sets
+        // `errno` on failure.
+        unsafe { *sys::errno_location() = 0 };
+        let result = unsafe { libc::readdir(dir) };
+        if result.is_null() {
+            match last_errno() {
+                0 => return Ok(None),
+                libc::EINTR => continue,
+                e => return Err(e),
+            }
+        }
+
+        // The entry is in a buffer belonging to the DIR, and may be shorter than a whole `dirent`,
+        // so copy it field by field.
+        let mut entry: libc::dirent = unsafe { mem::zeroed() };
+        let name = unsafe {
+            entry.d_ino = (*result).d_ino;
+            entry.d_type = (*result).d_type;
+            CStr::from_ptr((*result).d_name.as_ptr())
+        };
+        for (dst, &src) in entry.d_name.iter_mut().zip(name.to_bytes()) {
+            *dst = src as libc::c_char;
+        }
+        return Ok(Some(entry));
+    }
+}
+
+pub fn closedir(fh: u64) -> Result<(), libc::c_int> {
+    let dir = fh as usize as *mut libc::DIR;
+    if -1 == unsafe { libc::closedir(dir) } {
+        Err(last_errno())
+    } else {
+        Ok(())
+    }
+}
+
+pub fn open(path: OsString, flags: libc::c_int) -> Result<u64, libc::c_int> {
+    let path_c = into_cstring!(path, "open");
+
+    let fd = retry(|| unsafe { libc::open(path_c.as_ptr(), flags) })?;
+    Ok(fd as u64)
+}
+
+/// Close a file descriptor.
+///
+/// This isn't retried on `EINTR`: the descriptor is closed anyway, and could already have been
+/// reused by another thread, so the interruption is ignored instead.
+pub fn close(fh: u64) -> Result<(), libc::c_int> {
+    let fd = fh as libc::c_int;
+    if -1 == unsafe { libc::close(fd) } {
+        match last_errno() {
+            libc::EINTR => Ok(()),
+            e => Err(e),
+        }
+    } else {
+        Ok(())
+    }
+}
+
+pub fn lstat(path: OsString) -> Result<stat64, libc::c_int> {
+    let path_c = into_cstring!(path, "lstat");
+
+    let mut buf: stat64 = unsafe { mem::zeroed() };
+    retry(|| unsafe { sys::lstat64(path_c.as_ptr(), &mut buf) })?;
+    Ok(buf)
+}
+
+pub fn fstat(fd: u64) -> Result<stat64, libc::c_int> {
+    let mut buf: stat64 = unsafe { mem::zeroed() };
+    retry(|| unsafe { sys::fstat64(fd as libc::c_int, &mut buf) })?;
+    Ok(buf)
+}
+
+/// Get statistics about the filesystem a path is on.
+pub fn statvfs(path: OsString) -> Result<statvfs64, libc::c_int> {
+    let path_c = into_cstring!(path, "statvfs");
+
+    let mut buf: statvfs64 = unsafe { mem::zeroed() };
+    retry(|| unsafe { sys::statvfs64(path_c.as_ptr(), &mut buf) })?;
+    Ok(buf)
+}
+
+pub fn llistxattr(path: OsString, buf: &mut [u8]) -> Result<usize, libc::c_int> {
+    let path_c = into_cstring!(path, "llistxattr");
+
+    let nbytes = retry(|| unsafe {
+        sys::llistxattr(path_c.as_ptr(), buf.as_mut_ptr() as *mut libc::c_char, buf.len())
+    })?;
+    Ok(nbytes as usize)
+}
+
+pub fn lgetxattr(path: OsString, name: OsString, buf: &mut [u8]) -> Result<usize, libc::c_int> {
+    let path_c = into_cstring!(path, "lgetxattr");
+    let name_c = into_cstring!(name, "lgetxattr");
+
+    let nbytes = retry(|| unsafe {
+        sys::lgetxattr(path_c.as_ptr(), name_c.as_ptr(), buf.as_mut_ptr() as *mut libc::c_void,
+            buf.len())
+    })?;
+    Ok(nbytes as usize)
+}
+
+pub fn lsetxattr(path: OsString, name: OsString, value: &[u8], flags: u32, position: u32) -> Result<(), libc::c_int> {
+    let path_c = into_cstring!(path, "lsetxattr");
+    let name_c = into_cstring!(name, "lsetxattr");
+
+    // MacOS obnoxiously has an non-standard parameter at the end of their lsetxattr...
+    #[cfg(target_os = "macos")]
+    unsafe fn real(path: *const libc::c_char, name: *const libc::c_char,
+                   value: *const libc::c_void, size: libc::size_t, flags: libc::c_int,
+                   position: u32) -> libc::c_int {
+        libc::setxattr(path, name, value, size, position, flags | libc::XATTR_NOFOLLOW)
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    unsafe fn real(path: *const libc::c_char, name: *const libc::c_char,
+                   value: *const libc::c_void, size: libc::size_t, flags: libc::c_int,
+                   _position: u32) -> libc::c_int {
+        libc::lsetxattr(path, name, value, size, flags)
+    }
+
+    if cfg!(not(target_os = "macos")) && position != 0 {
+        error!("lsetxattr: position != 0 is only supported on MacOS");
+        return Err(libc::EINVAL);
+    }
+
+    retry(|| unsafe {
+        real(path_c.as_ptr(), name_c.as_ptr(), value.as_ptr() as *const libc::c_void,
+             value.len(), flags as libc::c_int, position)
+    })?;
+    Ok(())
+}
+
+pub fn lremovexattr(path: OsString, name: OsString) -> Result<(), libc::c_int> {
+    let path_c = into_cstring!(path, "lremovexattr");
+    let name_c = into_cstring!(name, "lremovexattr");
+
+    retry(|| unsafe { sys::lremovexattr(path_c.as_ptr(), name_c.as_ptr()) })?;
+    Ok(())
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn fallocate(fd: u64, offset: u64, length: u64, mode: libc::c_int) -> Result<(), libc::c_int> {
+    retry(|| unsafe {
+        libc::fallocate64(fd as libc::c_int, mode, offset as libc::off64_t, length as libc::off64_t)
+    })?;
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub fn fallocate(_fd: u64, _offset: u64, _length: u64, _mode: libc::c_int) -> Result<(), libc::c_int> {
+    Err(libc::EOPNOTSUPP)
+}
+
+/// Find the next data or hole (`SEEK_DATA` or `SEEK_HOLE`) in an open file, at or after an offset.
+pub fn lseek(fd: u64, offset: u64, whence: libc::c_int) -> Result<u64, libc::c_int> {
+    let offset = libc::off_t::try_from(offset).map_err(|_| libc::EINVAL)?;
+    let result = unsafe { libc::lseek(fd as libc::c_int, offset, whence) };
+    if result == -1 {
+        return Err(last_errno());
+    }
+    Ok(result as u64)
+}
+
+// This makes the system call directly, since neither musl nor older versions of bionic have a
+// wrapper for it.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn copy_file_range(fd_in: u64, offset_in: u64, fd_out: u64, offset_out: u64, len: u64, flags: u32) -> Result<usize, libc::c_int> {
+    let mut off_in = offset_in as libc::off64_t;
+    let mut off_out = offset_out as libc::off64_t;
+    let n = retry(|| unsafe {
+        libc::syscall(libc::SYS_copy_file_range, fd_in as libc::c_int, &mut off_in as *mut libc::off64_t,
+            fd_out as libc::c_int, &mut off_out as *mut libc::off64_t,
+            usize::try_from(len).unwrap_or(usize::MAX), flags as libc::c_uint)
+    })?;
+    Ok(n as usize)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub fn copy_file_range(_fd_in: u64, _offset_in: u64, _fd_out: u64, _offset_out: u64, _len: u64, _flags: u32) -> Result<usize, libc::c_int> {
+    Err(libc::EOPNOTSUPP)
+}
+
+#[test]
+fn test_libc_wrappers() {
+    let dir = std::env::temp_dir().join(format!("fuse_mt_libc_wrappers_{}", std::process::id()));
+    std::fs::create_dir(&dir).unwrap();
+    std::fs::write(dir.join("file"), b"data").unwrap();
+
+    let fh = opendir(dir.clone().into_os_string()).unwrap();
+    let mut names = vec![];
+    while let Some(entry) = readdir(fh).unwrap() {
+        let name = unsafe { CStr::from_ptr(entry.d_name.as_ptr()) };
+        names.push(name.to_str().unwrap().to_owned());
+    }
+    closedir(fh).unwrap();
+    names.sort();
+    assert_eq!(vec![".", "..", "file"], names);
+
+    let fd = open(dir.join("file").into_os_string(), libc::O_RDONLY).unwrap();
+    assert_eq!(4, fstat(fd).unwrap().st_size);
+    assert_eq!(Ok(4), lseek(fd, 0, libc::SEEK_HOLE));
+    assert_eq!(Err(libc::ENXIO), lseek(fd, 4, libc::SEEK_DATA));
+    close(fd).unwrap();
+    assert_eq!(4, lstat(dir.join("file").into_os_string()).unwrap().st_size);
+    assert_eq!(Err(libc::ENOENT), lstat(dir.join("missing").into_os_string()).map(drop));
+    assert_eq!(Err(libc::EINVAL), open(OsString::from("a\0b"), libc::O_RDONLY));
+    let (a, b) = (statvfs(dir.clone().into_os_string()).unwrap(),
+        statvfs(dir.join("file").into_os_string()).unwrap());
+    assert!(a.f_bsize > 0);
+    assert_eq!(a.f_fsid, b.f_fsid);
+
+    // Offsets past 4 GiB, which need 64-bit types on 32-bit platforms.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        use std::os::unix::io::AsRawFd;
+        let big = std::fs::File::create(dir.join("big")).unwrap();
+        let fd_in = open(dir.join("file").into_os_string(), libc::O_RDONLY).unwrap();
+        let fd_out = big.as_raw_fd() as u64;
+        fallocate(fd_out, 5 << 30, 4, libc::FALLOC_FL_KEEP_SIZE).unwrap();
+        assert_eq!(0, fstat(fd_out).unwrap().st_size);
+        if let Ok(n) = copy_file_range(fd_in, 0, fd_out, 5 << 30, 4, 0) {
+            assert_eq!(4, n);
+            assert_eq!((5 << 30) + 4, fstat(fd_out).unwrap().st_size);
+        }
+        close(fd_in).unwrap();
+    }
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
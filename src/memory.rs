@@ -0,0 +1,105 @@
+// Memory :: approximate accounting of the memory held by FuseMT's tables and caches.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// Least time between checks of memory use against the limit. Adding up the sizes of the caches
+/// takes time proportional to how much is in them, so it isn't done on every request.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Counts the bytes of write data copied out of `fuser`'s buffer and not yet written to the
+/// filesystem.
+#[derive(Clone, Debug, Default)]
+pub struct WriteBuffers(Arc<AtomicUsize>);
+
+impl WriteBuffers {
+    pub fn add(&self, bytes: usize) {
+        self.0.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn remove(&self, bytes: usize) {
+        self.0.fetch_sub(bytes, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Approximate bytes of memory used by each of FuseMT's tables and caches.
+#[derive(Debug, Default)]
+pub struct MemoryUsage {
+    pub inodes: usize,
+    pub directories: usize,
+    pub prefetch: usize,
+    pub writes: usize,
+}
+
+impl MemoryUsage {
+    pub fn total(&self) -> usize {
+        self.inodes + self.directories + self.prefetch + self.writes
+    }
+
+    /// The usage as gauges for `Stats::format`.
+    pub fn gauges(&self) -> [(&'static str, usize); 5] {
+        [
+            ("memory.inodes", self.inodes),
+            ("memory.directories", self.directories),
+            ("memory.prefetch", self.prefetch),
+            ("memory.writes", self.writes),
+            ("memory.total", self.total()),
+        ]
+    }
+}
+
+/// A cap on memory use, and when it was last checked.
+#[derive(Debug)]
+pub struct MemoryLimit {
+    pub bytes: usize,
+    last_check: Option<Instant>,
+    /// Whether memory use was still over the limit after the last eviction.
+    pub over: bool,
+}
+
+impl MemoryLimit {
+    pub fn new(bytes: usize) -> MemoryLimit {
+        MemoryLimit {
+            bytes,
+            last_check: None,
+            over: false,
+        }
+    }
+
+    /// Whether it's time to check memory use again. Returns true at most once per
+    /// `CHECK_INTERVAL`.
+    pub fn due(&mut self, now: Instant) -> bool {
+        match self.last_check {
+            Some(last) if now.duration_since(last) < CHECK_INTERVAL => false,
+            _ => {
+                self.last_check = Some(now);
+                true
+            }
+        }
+    }
+}
+
+#[test]
+fn test_memory_limit() {
+    let mut limit = MemoryLimit::new(100);
+    let now = Instant::now();
+    assert!(limit.due(now));
+    assert!(!limit.due(now + CHECK_INTERVAL / 2));
+    assert!(limit.due(now + CHECK_INTERVAL));
+
+    let writes = WriteBuffers::default();
+    writes.add(10);
+    writes.clone().add(5);
+    writes.remove(10);
+    let usage = MemoryUsage { inodes: 1, directories: 2, prefetch: 3, writes: writes.get() };
+    assert_eq!(11, usage.total());
+    assert_eq!(("memory.total", 11), usage.gauges()[4]);
+}
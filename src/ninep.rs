@@ -0,0 +1,1026 @@
+// 9P :: serve a FilesystemMT over the 9P2000.L protocol.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+//! A 9P2000.L server for `FilesystemMT` implementations.
+//!
+//! This exposes the same filesystem that would be mounted with FUSE to 9P clients instead, such
+//! as the Linux kernel's v9fs (`mount -t 9p -o trans=tcp,port=5640,version=9p2000.L`), or virtual
+//! machines that use 9P for sharing folders with the host.
+//!
+//! ```ignore
+//! let listener = TcpListener::bind("127.0.0.1:5640")?;
+//! NinePServer::new(MyFilesystem::new()).serve_tcp(listener)?;
+//! ```
+//!
+//! Each connection is served on its own thread, and handles requests in order. Qids are the inode
+//! numbers the filesystem gives in `FileAttr::ino`; for filesystems which leave that 0, they're
+//! derived from a hash of the path, so a file's qid changes if it's renamed.
+//!
+//! Byte-range lock requests are passed on to `setlk` and `getlk`. For filesystems which don't
+//! implement those, locks always succeed, the same as QEMU's 9P server.
+//!
+//! There is no authentication: clients say which uid they are when they attach, and that's
+//! trusted, so only serve to clients you trust with every user's files. Clients that attach
+//! without giving a uid are treated as the anonymous user, which is `nobody` (65534) unless set
+//! otherwise with `NinePServer::anonymous_user`.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::convert::{TryFrom, TryInto};
+use std::ffi::OsStr;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read, Write};
+use std::net::TcpListener;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::directory_cache::{has_offsets, resume_index};
+use crate::types::*;
+use crate::FileType;
+
+/// Largest message size we'll agree to.
+const MAX_MSIZE: u32 = 1 << 20;
+
+/// Size of a message header: size[4] type[1] tag[2].
+const HEADER: u32 = 7;
+
+const NOFID: u32 = !0;
+
+mod msg {
+    pub const RLERROR: u8 = 7;
+    pub const TSTATFS: u8 = 8;
+    pub const TLOPEN: u8 = 12;
+    pub const TLCREATE: u8 = 14;
+    pub const TSYMLINK: u8 = 16;
+    pub const TMKNOD: u8 = 18;
+    pub const TRENAME: u8 = 20;
+    pub const TREADLINK: u8 = 22;
+    pub const TGETATTR: u8 = 24;
+    pub const TSETATTR: u8 = 26;
+    pub const TXATTRWALK: u8 = 30;
+    pub const TXATTRCREATE: u8 = 32;
+    pub const TREADDIR: u8 = 40;
+    pub const TFSYNC: u8 = 50;
+    pub const TLOCK: u8 = 52;
+    pub const TGETLOCK: u8 = 54;
+    pub const TLINK: u8 = 70;
+    pub const TMKDIR: u8 = 72;
+    pub const TRENAMEAT: u8 = 74;
+    pub const TUNLINKAT: u8 = 76;
+    pub const TVERSION: u8 = 100;
+    pub const TAUTH: u8 = 102;
+    pub const TATTACH: u8 = 104;
+    pub const TFLUSH: u8 = 108;
+    pub const TWALK: u8 = 110;
+    pub const TREAD: u8 = 116;
+    pub const TWRITE: u8 = 118;
+    pub const TCLUNK: u8 = 120;
+    pub const TREMOVE: u8 = 122;
+}
+
+mod setattr {
+    pub const MODE: u32 = 0x1;
+    pub const UID: u32 = 0x2;
+    pub const GID: u32 = 0x4;
+    pub const SIZE: u32 = 0x8;
+    pub const ATIME: u32 = 0x10;
+    pub const MTIME: u32 = 0x20;
+    pub const ATIME_SET: u32 = 0x80;
+    pub const MTIME_SET: u32 = 0x100;
+}
+
+mod lock {
+    pub const RDLCK: u8 = 0;
+    pub const WRLCK: u8 = 1;
+    pub const UNLCK: u8 = 2;
+    pub const FLAGS_BLOCK: u32 = 0x1;
+    pub const SUCCESS: u8 = 0;
+    pub const BLOCKED: u8 = 1;
+}
+
+/// The getattr fields we fill in: everything in the basic set.
+const GETATTR_BASIC: u64 = 0x7ff;
+
+/// Translate the fields a getattr asks for into an `AttrMask`. Above these, the bits are the same
+/// as `statx`'s.
+fn attr_mask(request_mask: u64) -> AttrMask {
+    const MODE: u64 = 0x1;
+    const NLINK: u64 = 0x2;
+    const UID: u64 = 0x4;
+    const GID: u64 = 0x8;
+    const RDEV: u64 = 0x10;
+    let mut mask = AttrMask((request_mask & 0xfe0) as u32);
+    for (bit, attrs) in [
+        (MODE, AttrMask::TYPE | AttrMask::MODE),
+        (NLINK, AttrMask::NLINK),
+        (UID, AttrMask::UID),
+        (GID, AttrMask::GID),
+        (RDEV, AttrMask::TYPE),
+    ] {
+        if request_mask & bit != 0 {
+            mask = mask | attrs;
+        }
+    }
+    mask
+}
+
+/// Translate a 9P lock request into a `FileLock`. A length of 0 means to the end of the file.
+fn file_lock(typ: u8, start: u64, length: u64, pid: u32) -> Result<FileLock, libc::c_int> {
+    let typ = match typ {
+        lock::RDLCK => libc::F_RDLCK,
+        lock::WRLCK => libc::F_WRLCK,
+        lock::UNLCK => libc::F_UNLCK,
+        _ => return Err(libc::EINVAL),
+    };
+    let end = match length {
+        0 => i64::MAX as u64,
+        _ => start.checked_add(length - 1).ok_or(libc::EINVAL)?,
+    };
+    Ok(FileLock { start, end, typ, pid })
+}
+
+/// The 9P lock type, start, and length of a `FileLock`.
+fn lock_range(lock: &FileLock) -> (u8, u64, u64) {
+    let typ = match lock.typ {
+        libc::F_RDLCK => lock::RDLCK,
+        libc::F_WRLCK => lock::WRLCK,
+        _ => lock::UNLCK,
+    };
+    let length = match lock.end {
+        end if end == i64::MAX as u64 => 0,
+        end => end.saturating_sub(lock.start) + 1,
+    };
+    (typ, lock.start, length)
+}
+
+/// The owner of the locks a client's process takes, which is the same for all its fids.
+fn lock_owner(client_id: &OsStr, proc_id: u32) -> LockOwner {
+    let mut hasher = DefaultHasher::new();
+    (client_id, proc_id).hash(&mut hasher);
+    LockOwner(hasher.finish())
+}
+
+const AT_REMOVEDIR: u32 = 0x200;
+
+/// Magic number reported for the filesystem type, same as v9fs.
+const V9FS_MAGIC: u32 = 0x0102_1997;
+
+#[derive(Default)]
+struct Enc(Vec<u8>);
+
+impl Enc {
+    fn u8(&mut self, v: u8) -> &mut Self {
+        self.0.push(v);
+        self
+    }
+
+    fn u16(&mut self, v: u16) -> &mut Self {
+        self.0.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    fn u32(&mut self, v: u32) -> &mut Self {
+        self.0.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    fn u64(&mut self, v: u64) -> &mut Self {
+        self.0.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    fn str(&mut self, v: &[u8]) -> &mut Self {
+        self.u16(v.len() as u16);
+        self.0.extend_from_slice(v);
+        self
+    }
+
+    fn qid(&mut self, qid: Qid) -> &mut Self {
+        self.u8(qid.kind).u32(0).u64(qid.path)
+    }
+
+    fn time(&mut self, t: SystemTime) -> &mut Self {
+        let (secs, nanos) = match t.duration_since(SystemTime::UNIX_EPOCH) {
+            Ok(d) => (d.as_secs(), d.subsec_nanos()),
+            Err(_) => (0, 0),
+        };
+        self.u64(secs).u64(nanos.into())
+    }
+}
+
+struct Dec<'a>(&'a [u8]);
+
+fn malformed() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "malformed 9P message")
+}
+
+impl<'a> Dec<'a> {
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.0.len() < n {
+            return Err(malformed());
+        }
+        let (taken, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn str(&mut self) -> io::Result<&'a OsStr> {
+        let len = self.u16()?;
+        Ok(OsStr::from_bytes(self.take(len.into())?))
+    }
+
+    /// A single path component; anything that could escape the directory is rejected.
+    fn name(&mut self) -> Result<&'a OsStr, libc::c_int> {
+        let name = self.str().map_err(|_| libc::EINVAL)?;
+        if name.is_empty() || name == "." || name == ".." || name.as_bytes().contains(&b'/') {
+            return Err(libc::EINVAL);
+        }
+        Ok(name)
+    }
+
+    fn time(&mut self) -> io::Result<SystemTime> {
+        let secs = self.u64()?;
+        let nanos = self.u64()?;
+        Ok(SystemTime::UNIX_EPOCH + Duration::new(secs, (nanos % 1_000_000_000) as u32))
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Qid {
+    kind: u8,
+    path: u64,
+}
+
+impl Qid {
+    /// The qid of the file with the given path and attributes. Clients tell files apart by these,
+    /// so they're made from the filesystem's inode numbers, which stay the same when a file is
+    /// renamed, and are shared by its hard links. For filesystems without them, a hash of the path
+    /// has to do.
+    fn new(path: &Path, attr: &FileAttr) -> Qid {
+        let ino = if attr.ino != 0 {
+            attr.ino
+        } else {
+            let mut hasher = DefaultHasher::new();
+            path.hash(&mut hasher);
+            hasher.finish()
+        };
+        Qid {
+            kind: match attr.kind {
+                FileType::Directory => 0x80,
+                FileType::Symlink => 0x02,
+                _ => 0,
+            },
+            path: ino,
+        }
+    }
+}
+
+/// The attributes qids are made from.
+const QID_ATTRS: AttrMask = AttrMask(AttrMask::TYPE.0 | AttrMask::INO.0);
+
+fn mode_bits(kind: FileType) -> u32 {
+    match kind {
+        FileType::NamedPipe => libc::S_IFIFO,
+        FileType::CharDevice => libc::S_IFCHR,
+        FileType::BlockDevice => libc::S_IFBLK,
+        FileType::Directory => libc::S_IFDIR,
+        FileType::RegularFile => libc::S_IFREG,
+        FileType::Symlink => libc::S_IFLNK,
+        FileType::Socket => libc::S_IFSOCK,
+    }
+}
+
+fn dirent_type(kind: FileType) -> u8 {
+    match kind {
+        FileType::NamedPipe => libc::DT_FIFO,
+        FileType::CharDevice => libc::DT_CHR,
+        FileType::BlockDevice => libc::DT_BLK,
+        FileType::Directory => libc::DT_DIR,
+        FileType::RegularFile => libc::DT_REG,
+        FileType::Symlink => libc::DT_LNK,
+        FileType::Socket => libc::DT_SOCK,
+    }
+}
+
+enum Open {
+    File(u64),
+    Dir { fh: u64, entries: Option<Vec<DirectoryEntry>> },
+    Xattr(Vec<u8>),
+}
+
+struct Fid {
+    path: FsPath,
+    uid: u32,
+    gid: u32,
+    open: Option<Open>,
+}
+
+/// Per-connection state.
+struct Connection {
+    msize: u32,
+    fids: HashMap<u32, Fid>,
+}
+
+/// The uid and gid of `nobody` on most systems.
+const NOBODY: u32 = 65534;
+
+/// Serves a filesystem to 9P2000.L clients.
+pub struct NinePServer<FS> {
+    fs: Arc<FS>,
+    unique: Arc<AtomicU64>,
+    anonymous: (u32, u32),
+}
+
+impl<FS: FilesystemMT + Send + Sync + 'static> NinePServer<FS> {
+    pub fn new(fs: FS) -> NinePServer<FS> {
+        NinePServer {
+            fs: Arc::new(fs),
+            unique: Arc::new(AtomicU64::new(1)),
+            anonymous: (NOBODY, NOBODY),
+        }
+    }
+
+    /// Set the uid and gid used for clients that attach without giving a uid. The default is
+    /// 65534 for both, the usual `nobody` user.
+    pub fn anonymous_user(mut self, uid: u32, gid: u32) -> NinePServer<FS> {
+        self.anonymous = (uid, gid);
+        self
+    }
+
+    /// Accept connections on a TCP socket forever, serving each on its own thread.
+    pub fn serve_tcp(&self, listener: TcpListener) -> io::Result<()> {
+        for stream in listener.incoming() {
+            let stream = stream?;
+            stream.set_nodelay(true)?;
+            self.spawn(stream);
+        }
+        Ok(())
+    }
+
+    /// Accept connections on a Unix socket forever, serving each on its own thread.
+    pub fn serve_unix(&self, listener: UnixListener) -> io::Result<()> {
+        for stream in listener.incoming() {
+            self.spawn(stream?);
+        }
+        Ok(())
+    }
+
+    fn spawn(&self, stream: impl Read + Write + Send + 'static) {
+        let server = NinePServer {
+            fs: Arc::clone(&self.fs),
+            unique: Arc::clone(&self.unique),
+            anonymous: self.anonymous,
+        };
+        thread::spawn(move || {
+            if let Err(e) = server.serve_connection(stream) {
+                debug!("9P connection ended: {}", e);
+            }
+        });
+    }
+
+    /// Serve requests on a single connection until it's closed by the client.
+    pub fn serve_connection(&self, mut stream: impl Read + Write) -> io::Result<()> {
+        let mut conn = Connection { msize: MAX_MSIZE, fids: HashMap::new() };
+        let result = self.serve_loop(&mut stream, &mut conn);
+        self.clunk_all(&mut conn);
+        result
+    }
+
+    fn serve_loop(&self, stream: &mut impl ReadWrite, conn: &mut Connection) -> io::Result<()> {
+        loop {
+            let mut size = [0; 4];
+            match stream.read_exact(&mut size) {
+                Ok(()) => (),
+                Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(e) => return Err(e),
+            }
+            let size = u32::from_le_bytes(size);
+            if !(HEADER ..= MAX_MSIZE).contains(&size) {
+                return Err(malformed());
+            }
+            let mut body = vec![0; size as usize - 4];
+            stream.read_exact(&mut body)?;
+            let ty = body[0];
+            let tag = u16::from_le_bytes([body[1], body[2]]);
+
+            let (rtype, reply) = match self.handle(conn, ty, &mut Dec(&body[3 ..])) {
+                Ok(reply) => (ty + 1, reply),
+                Err(errno) => {
+                    let mut reply = Enc::default();
+                    reply.u32(errno as u32);
+                    (msg::RLERROR, reply)
+                }
+            };
+
+            let mut out = Enc::default();
+            out.u32(HEADER + reply.0.len() as u32).u8(rtype).u16(tag);
+            out.0.extend_from_slice(&reply.0);
+            stream.write_all(&out.0)?;
+            stream.flush()?;
+        }
+    }
+
+    fn req(&self, fid: &Fid) -> RequestInfo {
+        RequestInfo {
+            unique: self.unique.fetch_add(1, Ordering::Relaxed),
+            uid: fid.uid,
+            gid: fid.gid,
+            pid: 0,
+            node: None,
+            umask: None,
+            lock_owner: None,
+        }
+    }
+
+    fn qid(&self, req: RequestInfo, path: &FsPath) -> Result<Qid, libc::c_int> {
+        let (_ttl, attr) = self.fs.getattr_mask(req, path, None, QID_ATTRS)?;
+        Ok(Qid::new(path, &attr))
+    }
+
+    /// Close whatever the fid has open.
+    fn clunk(&self, fid: Fid) -> ResultEmpty {
+        let req = self.req(&fid);
+        match fid.open {
+            Some(Open::File(fh)) => self.fs.release(req, &fid.path, fh, 0, None, true),
+            Some(Open::Dir { fh, .. }) => self.fs.releasedir(req, &fid.path, fh, 0),
+            Some(Open::Xattr(_)) | None => Ok(()),
+        }
+    }
+
+    fn clunk_all(&self, conn: &mut Connection) {
+        for (_, fid) in conn.fids.drain() {
+            let _ = self.clunk(fid);
+        }
+    }
+
+    fn handle(&self, conn: &mut Connection, ty: u8, args: &mut Dec<'_>)
+        -> Result<Enc, libc::c_int>
+    {
+        let mut out = Enc::default();
+        let fids = &mut conn.fids;
+        macro_rules! arg {
+            ($e:expr) => { $e.map_err(|_| libc::EINVAL)? }
+        }
+        macro_rules! fid {
+            ($fid:expr) => { fids.get(&$fid).ok_or(libc::EBADF)? }
+        }
+
+        match ty {
+            msg::TVERSION => {
+                let msize = arg!(args.u32());
+                let version = arg!(args.str());
+                self.clunk_all(conn);
+                conn.msize = msize.clamp(4096, MAX_MSIZE);
+                let version: &[u8] = if version.as_bytes().starts_with(b"9P2000.L") {
+                    b"9P2000.L"
+                } else {
+                    b"unknown"
+                };
+                out.u32(conn.msize).str(version);
+            }
+            msg::TAUTH => return Err(libc::EOPNOTSUPP),
+            msg::TATTACH => {
+                let (fid, _afid) = (arg!(args.u32()), arg!(args.u32()));
+                let (_uname, _aname) = (arg!(args.str()), arg!(args.str()));
+                let uid = arg!(args.u32());
+                if fids.contains_key(&fid) {
+                    return Err(libc::EBADF);
+                }
+                // 9P doesn't tell us the user's group; assume a user private group until a
+                // request gives us one explicitly.
+                let (uid, gid) = if uid == NOFID { self.anonymous } else { (uid, uid) };
+                let new = Fid { path: FsPath::from("/"), uid, gid, open: None };
+                out.qid(self.qid(self.req(&new), &new.path)?);
+                fids.insert(fid, new);
+            }
+            msg::TFLUSH => {
+                // Requests are handled in order, so there's never anything to cancel.
+            }
+            msg::TWALK => {
+                let (fid, newfid, count) = (arg!(args.u32()), arg!(args.u32()), arg!(args.u16()));
+                if newfid != fid && fids.contains_key(&newfid) {
+                    return Err(libc::EBADF);
+                }
+                let base = fid!(fid);
+                if base.open.is_some() {
+                    return Err(libc::EBADF);
+                }
+                let req = self.req(base);
+                let mut path = PathBuf::from(base.path.clone());
+                let mut qids = vec![];
+                for i in 0 .. count {
+                    let name = arg!(args.str());
+                    if name == ".." {
+                        if path != Path::new("/") {
+                            path.pop();
+                        }
+                    } else if name != "." {
+                        if name.as_bytes().contains(&b'/') {
+                            return Err(libc::EINVAL);
+                        }
+                        path.push(name);
+                    }
+                    match self.qid(req, &FsPath::from(path.as_path())) {
+                        Ok(qid) => qids.push(qid),
+                        Err(e) if i == 0 => return Err(e),
+                        Err(_) => break,
+                    }
+                }
+                if qids.len() == count as usize {
+                    let (uid, gid) = (base.uid, base.gid);
+                    fids.insert(newfid, Fid { path: path.into(), uid, gid, open: None });
+                }
+                out.u16(qids.len() as u16);
+                for qid in qids {
+                    out.qid(qid);
+                }
+            }
+            msg::TLOPEN => {
+                let (fid, flags) = (arg!(args.u32()), arg!(args.u32()));
+                let f = fid!(fid);
+                let req = self.req(f);
+                let (_, attr) = self.fs.getattr_mask(req, &f.path, None, QID_ATTRS)?;
+                let open = if attr.kind == FileType::Directory {
+                    let fh = self.fs.opendir(req, &f.path, flags)?.fh;
+                    Open::Dir { fh, entries: None }
+                } else {
+                    Open::File(self.fs.open(req, &f.path, flags)?.fh)
+                };
+                let qid = Qid::new(&f.path, &attr);
+                fids.get_mut(&fid).unwrap().open = Some(open);
+                out.qid(qid).u32(conn.msize - HEADER - 4);
+            }
+            msg::TLCREATE => {
+                let fid = arg!(args.u32());
+                let name = args.name()?;
+                let (flags, mode, gid) = (arg!(args.u32()), arg!(args.u32()), arg!(args.u32()));
+                let f = fids.get_mut(&fid).ok_or(libc::EBADF)?;
+                f.gid = gid;
+                let req = self.req(f);
+                let created = self.fs.create(req, &f.path, name, mode, flags)?;
+                f.path = FsPath::from(f.path.join(name));
+                f.open = Some(Open::File(created.fh));
+                out.qid(Qid::new(&f.path, &created.attr)).u32(conn.msize - HEADER - 4);
+            }
+            msg::TSYMLINK => {
+                let fid = arg!(args.u32());
+                let name = args.name()?;
+                let (target, gid) = (arg!(args.str()), arg!(args.u32()));
+                let f = fid!(fid);
+                let req = RequestInfo { gid, ..self.req(f) };
+                let (_, attr) = self.fs.symlink(req, &f.path, name, Path::new(target))?;
+                out.qid(Qid::new(&f.path.join(name), &attr));
+            }
+            msg::TMKNOD => {
+                let fid = arg!(args.u32());
+                let name = args.name()?;
+                let (mode, major, minor) = (arg!(args.u32()), arg!(args.u32()), arg!(args.u32()));
+                let gid = arg!(args.u32());
+                let f = fid!(fid);
+                let req = RequestInfo { gid, ..self.req(f) };
+                let rdev = libc::makedev(major, minor) as u32;
+                let (_, attr) = self.fs.mknod(req, &f.path, name, mode, rdev)?;
+                out.qid(Qid::new(&f.path.join(name), &attr));
+            }
+            msg::TMKDIR => {
+                let fid = arg!(args.u32());
+                let name = args.name()?;
+                let (mode, gid) = (arg!(args.u32()), arg!(args.u32()));
+                let f = fid!(fid);
+                let req = RequestInfo { gid, ..self.req(f) };
+                let (_, attr) = self.fs.mkdir(req, &f.path, name, mode)?;
+                out.qid(Qid::new(&f.path.join(name), &attr));
+            }
+            msg::TRENAME => {
+                let (fid, dfid) = (arg!(args.u32()), arg!(args.u32()));
+                let newname = args.name()?;
+                let newparent = fid!(dfid).path.clone();
+                let f = fid!(fid);
+                let (parent, name) = split_path(&f.path)?;
+                self.fs.rename(self.req(f), &parent, name, &newparent, newname)?;
+                fids.get_mut(&fid).unwrap().path = FsPath::from(newparent.join(newname));
+            }
+            msg::TRENAMEAT => {
+                let olddir = arg!(args.u32());
+                let oldname = args.name()?;
+                let newdir = arg!(args.u32());
+                let newname = args.name()?;
+                let newparent = fid!(newdir).path.clone();
+                let f = fid!(olddir);
+                self.fs.rename(self.req(f), &f.path, oldname, &newparent, newname)?;
+            }
+            msg::TUNLINKAT => {
+                let dir = arg!(args.u32());
+                let name = args.name()?;
+                let flags = arg!(args.u32());
+                let f = fid!(dir);
+                if flags & AT_REMOVEDIR != 0 {
+                    self.fs.rmdir(self.req(f), &f.path, name)?;
+                } else {
+                    self.fs.unlink(self.req(f), &f.path, name)?;
+                }
+            }
+            msg::TREMOVE => {
+                let fid = arg!(args.u32());
+                let f = fids.remove(&fid).ok_or(libc::EBADF)?;
+                let req = self.req(&f);
+                let is_dir = self.fs.getattr_mask(req, &f.path, None, AttrMask::TYPE)
+                    .map(|(_, attr)| attr.kind == FileType::Directory);
+                let path = f.path.clone();
+                let _ = self.clunk(f);
+                let (parent, name) = split_path(&path)?;
+                if is_dir? {
+                    self.fs.rmdir(req, &parent, name)?;
+                } else {
+                    self.fs.unlink(req, &parent, name)?;
+                }
+            }
+            msg::TLINK => {
+                let (dfid, fid) = (arg!(args.u32()), arg!(args.u32()));
+                let name = args.name()?;
+                let target = fid!(fid).path.clone();
+                let dir = fid!(dfid);
+                self.fs.link(self.req(dir), &target, &dir.path, name)?;
+            }
+            msg::TREADLINK => {
+                let f = fid!(arg!(args.u32()));
+                let target = self.fs.readlink(self.req(f), &f.path)?;
+                out.str(&target);
+            }
+            msg::TGETATTR => {
+                let (fid, request_mask) = (arg!(args.u32()), arg!(args.u64()));
+                let f = fid!(fid);
+                let fh = match f.open {
+                    Some(Open::File(fh)) => Some(fh),
+                    _ => None,
+                };
+                let mask = attr_mask(request_mask) | QID_ATTRS;
+                let (_, attr) = self.fs.getattr_mask(self.req(f), &f.path, fh, mask)?;
+                out.u64(request_mask & GETATTR_BASIC)
+                    .qid(Qid::new(&f.path, &attr))
+                    .u32(mode_bits(attr.kind) | u32::from(attr.perm))
+                    .u32(attr.uid)
+                    .u32(attr.gid)
+                    .u64(attr.nlink.into())
+                    .u64(attr.rdev.into())
+                    .u64(attr.size)
+                    .u64(4096)
+                    .u64(attr.blocks)
+                    .time(attr.atime)
+                    .time(attr.mtime)
+                    .time(attr.ctime)
+                    .time(attr.crtime)
+                    .u64(0)
+                    .u64(0);
+            }
+            msg::TSETATTR => {
+                let (fid, valid) = (arg!(args.u32()), arg!(args.u32()));
+                let (mode, uid, gid) = (arg!(args.u32()), arg!(args.u32()), arg!(args.u32()));
+                let size = arg!(args.u64());
+                let (atime, mtime) = (arg!(args.time()), arg!(args.time()));
+                let f = fid!(fid);
+                let req = self.req(f);
+                let fh = match f.open {
+                    Some(Open::File(fh)) => Some(fh),
+                    _ => None,
+                };
+                if valid & setattr::MODE != 0 {
+                    self.fs.chmod(req, &f.path, fh, mode)?;
+                }
+                if valid & (setattr::UID | setattr::GID) != 0 {
+                    let uid = if valid & setattr::UID != 0 { Some(uid) } else { None };
+                    let gid = if valid & setattr::GID != 0 { Some(gid) } else { None };
+                    self.fs.chown(req, &f.path, fh, uid, gid)?;
+                }
+                if valid & setattr::SIZE != 0 {
+                    self.fs.truncate(req, &f.path, fh, size)?;
+                }
+                if valid & (setattr::ATIME | setattr::MTIME) != 0 {
+                    let now = SystemTime::now();
+                    let time = |bit, set, t| {
+                        if valid & bit == 0 {
+                            None
+                        } else if valid & set != 0 {
+                            Some(t)
+                        } else {
+                            Some(now)
+                        }
+                    };
+                    let atime = time(setattr::ATIME, setattr::ATIME_SET, atime);
+                    let mtime = time(setattr::MTIME, setattr::MTIME_SET, mtime);
+                    self.fs.utimens(req, &f.path, fh, atime, mtime)?;
+                }
+            }
+            msg::TXATTRWALK => {
+                let (fid, newfid) = (arg!(args.u32()), arg!(args.u32()));
+                let name = arg!(args.str());
+                if fids.contains_key(&newfid) {
+                    return Err(libc::EBADF);
+                }
+                let f = fid!(fid);
+                let req = self.req(f);
+                let data = if name.is_empty() {
+                    match self.fs.listxattr(req, &f.path, u32::MAX)? {
+                        Xattr::Data(data) => data,
+                        Xattr::Size(_) => return Err(libc::EIO),
+                    }
+                } else {
+                    match self.fs.getxattr(req, &f.path, name, u32::MAX)? {
+                        Xattr::Data(data) => data,
+                        Xattr::Size(_) => return Err(libc::EIO),
+                    }
+                };
+                out.u64(data.len() as u64);
+                let new = Fid { path: f.path.clone(), uid: f.uid, gid: f.gid, open: Some(Open::Xattr(data)) };
+                fids.insert(newfid, new);
+            }
+            msg::TXATTRCREATE => return Err(libc::EOPNOTSUPP),
+            msg::TREAD => {
+                let (fid, offset, count) = (arg!(args.u32()), arg!(args.u64()), arg!(args.u32()));
+                let count = count.min(conn.msize - HEADER - 4);
+                let f = fid!(fid);
+                match f.open {
+                    Some(Open::File(fh)) => {
+                        let mut result = Err(libc::EIO);
+                        self.fs.read(self.req(f), &f.path, fh, offset, count, |data| {
+                            result = data.map(|data| {
+                                out.u32(data.len() as u32);
+                                out.0.extend_from_slice(data);
+                            });
+                            CallbackResult { _private: std::marker::PhantomData }
+                        });
+                        result?;
+                    }
+                    Some(Open::Xattr(ref data)) => {
+                        let start = usize::try_from(offset).unwrap_or(usize::MAX).min(data.len());
+                        let end = start.saturating_add(count as usize).min(data.len());
+                        out.u32((end - start) as u32);
+                        out.0.extend_from_slice(&data[start .. end]);
+                    }
+                    _ => return Err(libc::EBADF),
+                }
+            }
+            msg::TWRITE => {
+                let (fid, offset, count) = (arg!(args.u32()), arg!(args.u64()), arg!(args.u32()));
+                let data = arg!(args.take(count as usize)).to_vec();
+                let f = fid!(fid);
+                match f.open {
+                    Some(Open::File(fh)) => {
+                        let written = self.fs.write(self.req(f), &f.path, fh, offset, data, 0)?;
+                        out.u32(written);
+                    }
+                    _ => return Err(libc::EBADF),
+                }
+            }
+            msg::TREADDIR => {
+                let (fid, offset, count) = (arg!(args.u32()), arg!(args.u64()), arg!(args.u32()));
+                let count = count.min(conn.msize - HEADER - 4) as usize;
+                let f = fids.get_mut(&fid).ok_or(libc::EBADF)?;
+                let req = self.req(f);
+                let (fh, entries) = match f.open {
+                    Some(Open::Dir { fh, ref mut entries }) => (fh, entries),
+                    _ => return Err(libc::EBADF),
+                };
+                // List the directory when reading from the start, and page through that listing
+                // on subsequent calls.
+                if offset == 0 || entries.is_none() {
+                    *entries = Some(self.fs.readdir(req, &f.path, fh)?);
+                }
+                let entries = entries.as_ref().unwrap();
+                let with_offsets = has_offsets(entries);
+                let start = if with_offsets && offset > 0 {
+                    resume_index(entries, offset)
+                } else {
+                    offset as usize
+                };
+                let mut data = Enc::default();
+                for (i, entry) in entries.iter().enumerate().skip(start) {
+                    let next = match entry.offset {
+                        Some(next) if with_offsets => next,
+                        _ => i as u64 + 1,
+                    };
+                    // Entries listed without their attributes have to be looked at for their
+                    // inode numbers; those gone since they were listed are left out.
+                    let path = FsPath::from(f.path.join(&entry.name));
+                    let qid = match entry.attr {
+                        Some((_, attr)) => Qid::new(&path, &attr),
+                        None => match self.fs.getattr_mask(req, &path, None, QID_ATTRS) {
+                            Ok((_, attr)) => Qid::new(&path, &attr),
+                            Err(_) => continue,
+                        },
+                    };
+                    let mut one = Enc::default();
+                    one.qid(qid)
+                        .u64(next)
+                        .u8(dirent_type(entry.kind))
+                        .str(entry.name.as_bytes());
+                    if data.0.len() + one.0.len() > count {
+                        break;
+                    }
+                    data.0.extend_from_slice(&one.0);
+                }
+                out.u32(data.0.len() as u32);
+                out.0.extend_from_slice(&data.0);
+            }
+            msg::TFSYNC => {
+                let fid = arg!(args.u32());
+                // Older clients don't send the datasync flag.
+                let datasync = args.u32().unwrap_or(0) != 0;
+                let f = fid!(fid);
+                match f.open {
+                    Some(Open::File(fh)) => self.fs.fsync(self.req(f), &f.path, fh, datasync)?,
+                    Some(Open::Dir { fh, .. }) => {
+                        self.fs.fsyncdir(self.req(f), &f.path, fh, datasync)?
+                    }
+                    _ => return Err(libc::EBADF),
+                }
+            }
+            msg::TLOCK => {
+                let f = fid!(arg!(args.u32()));
+                let (typ, flags) = (arg!(args.take(1))[0], arg!(args.u32()));
+                let (start, length) = (arg!(args.u64()), arg!(args.u64()));
+                let (proc_id, client_id) = (arg!(args.u32()), arg!(args.str()));
+                let fh = match f.open {
+                    Some(Open::File(fh)) => fh,
+                    _ => return Err(libc::EBADF),
+                };
+                let lock = file_lock(typ, start, length, proc_id)?;
+                // Clients wait for a lock by asking again for as long as it's blocked, which they
+                // do for blocking requests too, so the filesystem is never asked to wait for it.
+                let blocking = flags & lock::FLAGS_BLOCK != 0;
+                op_debug!("9P lock {:?} on {:?} (blocking: {})", lock, f.path, blocking);
+                let owner = lock_owner(client_id, proc_id);
+                match self.fs.setlk(self.req(f), &f.path, fh, owner, lock, false) {
+                    // Filesystems without locks are treated as if every lock is free, as before.
+                    Ok(()) | Err(libc::ENOSYS) => out.u8(lock::SUCCESS),
+                    Err(libc::EAGAIN) => out.u8(lock::BLOCKED),
+                    Err(e) => return Err(e),
+                };
+            }
+            msg::TGETLOCK => {
+                let f = fid!(arg!(args.u32()));
+                let typ = arg!(args.take(1))[0];
+                let (start, length) = (arg!(args.u64()), arg!(args.u64()));
+                let (proc_id, client_id) = (arg!(args.u32()), arg!(args.str()));
+                let fh = match f.open {
+                    Some(Open::File(fh)) => fh,
+                    _ => return Err(libc::EBADF),
+                };
+                let lock = file_lock(typ, start, length, proc_id)?;
+                let owner = lock_owner(client_id, proc_id);
+                let found = match self.fs.getlk(self.req(f), &f.path, fh, owner, lock) {
+                    Ok(found) => found,
+                    Err(libc::ENOSYS) => FileLock { typ: libc::F_UNLCK, ..lock },
+                    Err(e) => return Err(e),
+                };
+                let (typ, start, length) = lock_range(&found);
+                out.u8(typ).u64(start).u64(length).u32(found.pid).str(client_id.as_bytes());
+            }
+            msg::TCLUNK => {
+                let f = fids.remove(&arg!(args.u32())).ok_or(libc::EBADF)?;
+                self.clunk(f)?;
+            }
+            msg::TSTATFS => {
+                let f = fid!(arg!(args.u32()));
+                let st = self.fs.statfs(self.req(f), &f.path)?;
+                out.u32(V9FS_MAGIC).u32(st.bsize).u64(st.blocks).u64(st.bfree).u64(st.bavail)
+                    .u64(st.files).u64(st.ffree).u64(st.fsid).u32(st.namelen);
+            }
+            _ => {
+                debug!("unsupported 9P message type {}", ty);
+                return Err(libc::EOPNOTSUPP);
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// Both halves of a connection.
+trait ReadWrite: Read + Write {}
+
+impl<T: Read + Write> ReadWrite for T {}
+
+fn split_path(path: &Path) -> Result<(FsPath, &OsStr), libc::c_int> {
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => Ok((FsPath::from(parent), name)),
+        _ => Err(libc::EBUSY),
+    }
+}
+
+#[test]
+fn test_ninep_walk_and_read() {
+    use std::os::unix::net::UnixStream;
+
+    struct Fs;
+    impl FilesystemMT for Fs {
+        fn getattr(&self, req: RequestInfo, path: &FsPath, _fh: Option<u64>) -> ResultEntry {
+            // The client attaches without a uid, so it should be treated as nobody.
+            if (req.uid, req.gid) != (NOBODY, NOBODY) {
+                return Err(libc::EACCES);
+            }
+            let kind = match path.to_str() {
+                Some("/") => FileType::Directory,
+                Some("/hello") => FileType::RegularFile,
+                _ => return Err(libc::ENOENT),
+            };
+            let t = SystemTime::UNIX_EPOCH;
+            Ok((Duration::from_secs(1), FileAttr {
+                ino: if kind == FileType::Directory { 1 } else { 2 },
+                size: 5, blocks: 1, atime: t, mtime: t, ctime: t, crtime: t, kind, perm: 0o644,
+                nlink: 1, uid: 0, gid: 0, rdev: 0, flags: 0,
+            }))
+        }
+        fn open(&self, _req: RequestInfo, _path: &FsPath, _flags: u32) -> ResultOpen {
+            Ok(OpenedFile::new(7))
+        }
+        fn read(&self, _req: RequestInfo, _path: &FsPath, fh: u64, offset: u64, _size: u32, callback: impl FnOnce(ResultSlice<'_>) -> CallbackResult) -> CallbackResult {
+            assert_eq!(7, fh);
+            callback(Ok(&b"hello"[offset as usize ..]))
+        }
+        fn getlk(&self, _req: RequestInfo, _path: &FsPath, _fh: u64, _lock_owner: LockOwner, lock: FileLock) -> ResultLock {
+            Ok(FileLock { typ: libc::F_WRLCK, pid: 9, ..lock })
+        }
+        fn setlk(&self, _req: RequestInfo, _path: &FsPath, fh: u64, _lock_owner: LockOwner, lock: FileLock, sleep: bool) -> ResultEmpty {
+            assert_eq!((7, false, i64::MAX as u64), (fh, sleep, lock.end));
+            match lock.typ {
+                libc::F_WRLCK => Err(libc::EAGAIN),
+                _ => Ok(()),
+            }
+        }
+    }
+
+    let (mut client, server_end) = UnixStream::pair().unwrap();
+    let server = NinePServer::new(Fs);
+    thread::spawn(move || server.serve_connection(server_end));
+
+    let mut call = |ty: u8, args: Enc| -> (u8, Vec<u8>) {
+        let mut msg = Enc::default();
+        msg.u32(HEADER + args.0.len() as u32).u8(ty).u16(1);
+        msg.0.extend_from_slice(&args.0);
+        client.write_all(&msg.0).unwrap();
+        let mut size = [0; 4];
+        client.read_exact(&mut size).unwrap();
+        let mut reply = vec![0; u32::from_le_bytes(size) as usize - 4];
+        client.read_exact(&mut reply).unwrap();
+        (reply[0], reply[3 ..].to_vec())
+    };
+    let mut args = Enc::default();
+
+    args.u32(8192).str(b"9P2000.L");
+    assert_eq!(msg::TVERSION + 1, call(msg::TVERSION, std::mem::take(&mut args)).0);
+    args.u32(1).u32(NOFID).str(b"").str(b"").u32(NOFID);
+    assert_eq!(msg::TATTACH + 1, call(msg::TATTACH, std::mem::take(&mut args)).0);
+
+    args.u32(1).u32(2).u16(1).str(b"nope");
+    let (ty, reply) = call(msg::TWALK, std::mem::take(&mut args));
+    assert_eq!((msg::RLERROR, libc::ENOENT as u32), (ty, Dec(&reply).u32().unwrap()));
+
+    // The qid is the file's inode number.
+    args.u32(1).u32(2).u16(1).str(b"hello");
+    let (ty, reply) = call(msg::TWALK, std::mem::take(&mut args));
+    let mut reply = Dec(&reply);
+    assert_eq!((msg::TWALK + 1, 1), (ty, reply.u16().unwrap()));
+    assert_eq!((0, 0, 2), (reply.take(1).unwrap()[0], reply.u32().unwrap(), reply.u64().unwrap()));
+    args.u32(2).u32(libc::O_RDONLY as u32);
+    assert_eq!(msg::TLOPEN + 1, call(msg::TLOPEN, std::mem::take(&mut args)).0);
+    args.u32(2).u64(1).u32(100);
+    let (ty, reply) = call(msg::TREAD, std::mem::take(&mut args));
+    assert_eq!(msg::TREAD + 1, ty);
+    assert_eq!(b"\x04\0\0\0ello", &reply[..]);
+
+    // Only the fields asked for are reported.
+    assert_eq!(AttrMask::TYPE | AttrMask::MODE | AttrMask::SIZE, attr_mask(0x201));
+    args.u32(2).u64(0x201);
+    let (ty, reply) = call(msg::TGETATTR, std::mem::take(&mut args));
+    assert_eq!((msg::TGETATTR + 1, 0x201), (ty, Dec(&reply).u64().unwrap()));
+
+    // Locks go to the filesystem, and ones held by others are reported as blocked.
+    args.u32(2).u8(lock::RDLCK).u32(lock::FLAGS_BLOCK).u64(0).u64(0).u32(5).str(b"client");
+    assert_eq!((msg::TLOCK + 1, vec![lock::SUCCESS]), call(msg::TLOCK, std::mem::take(&mut args)));
+    args.u32(2).u8(lock::WRLCK).u32(0).u64(0).u64(0).u32(5).str(b"client");
+    assert_eq!((msg::TLOCK + 1, vec![lock::BLOCKED]), call(msg::TLOCK, std::mem::take(&mut args)));
+    args.u32(2).u8(lock::RDLCK).u64(10).u64(5).u32(5).str(b"client");
+    let (ty, reply) = call(msg::TGETLOCK, std::mem::take(&mut args));
+    let mut reply = Dec(&reply);
+    assert_eq!((msg::TGETLOCK + 1, lock::WRLCK), (ty, reply.take(1).unwrap()[0]));
+    assert_eq!((10, 5, 9), (reply.u64().unwrap(), reply.u64().unwrap(), reply.u32().unwrap()));
+}
@@ -0,0 +1,596 @@
+// Op Log :: machine-readable logging of filesystem calls.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+//! A log of every filesystem call, as JSON lines.
+//!
+//! Wrap a filesystem in `OpLogFs` to write one line to an `OpLog` for each call made to it, once
+//! the call is done:
+//!
+//! ```text
+//! {"time":1760608800.123456,"op":"read","path":"/a/b","offset":0,"size":4096,"duration_us":87,"errno":0}
+//! ```
+//!
+//! `offset` and `size` are only present for reads and writes, where `size` is the number of bytes
+//! actually read or written. `errno` is 0 for calls that succeeded. Paths which aren't valid UTF-8
+//! have the invalid parts replaced with U+FFFD.
+//!
+//! This is meant as a replacement for the `debug!` logging FuseMT does of each call, for when
+//! something other than a person needs to read the log:
+//!
+//! ```ignore
+//! let log = OpLog::file("/var/log/myfs/ops.json")?
+//!     .rotate(64 << 20, 4)
+//!     .rate_limit(1000);
+//! let fs = OpLogFs::new(MyFilesystem::new(), log);
+//! ```
+
+use std::ffi::OsStr;
+use std::fmt::Write as _;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::json;
+use crate::types::*;
+
+enum Sink {
+    Writer(Box<dyn Write + Send>),
+    File {
+        path: PathBuf,
+        file: File,
+        size: u64,
+    },
+}
+
+/// Limits lines to a number per second, allowing bursts of up to a second's worth.
+struct RateLimit {
+    per_second: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl RateLimit {
+    fn allow(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.last = now;
+        self.tokens = (self.tokens + elapsed * self.per_second).min(self.per_second);
+        if self.tokens >= 1. {
+            self.tokens -= 1.;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+struct OpLogState {
+    sink: Sink,
+    /// Maximum size of the file and number of old ones to keep.
+    rotate: Option<(u64, u32)>,
+    rate_limit: Option<RateLimit>,
+    /// Number of lines dropped by the rate limit since the last one written.
+    dropped: u64,
+}
+
+/// Where the lines of an operation log go.
+pub struct OpLog {
+    state: Mutex<OpLogState>,
+}
+
+impl OpLog {
+    /// Write the log to the given writer. Each line is written with a single `write_all` call.
+    pub fn new(writer: impl Write + Send + 'static) -> OpLog {
+        OpLog::with_sink(Sink::Writer(Box::new(writer)))
+    }
+
+    /// Write the log to a file, appending if it already exists.
+    pub fn file(path: impl Into<PathBuf>) -> io::Result<OpLog> {
+        let path = path.into();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(OpLog::with_sink(Sink::File { path, file, size }))
+    }
+
+    fn with_sink(sink: Sink) -> OpLog {
+        OpLog {
+            state: Mutex::new(OpLogState {
+                sink,
+                rotate: None,
+                rate_limit: None,
+                dropped: 0,
+            }),
+        }
+    }
+
+    /// For logs written to a file: once the file reaches `max_size` bytes, rename it to have a
+    /// `.1` suffix (and any older `.1` file to `.2`, and so on) and start a new one. Only `keep`
+    /// old files are kept.
+    pub fn rotate(self, max_size: u64, keep: u32) -> OpLog {
+        self.state.lock().unwrap().rotate = Some((max_size, keep));
+        self
+    }
+
+    /// Write at most this many lines per second, with bursts of up to a second's worth. Lines
+    /// over the limit are dropped, and the number dropped is logged (as an `"op":"dropped"` line
+    /// with a `count`) before the next line that isn't.
+    pub fn rate_limit(self, lines_per_second: u32) -> OpLog {
+        let per_second = f64::from(lines_per_second);
+        self.state.lock().unwrap().rate_limit = Some(RateLimit {
+            per_second,
+            tokens: per_second,
+            last: Instant::now(),
+        });
+        self
+    }
+
+    fn write(&self, line: &str) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(ref mut limit) = state.rate_limit {
+            if !limit.allow() {
+                state.dropped += 1;
+                return;
+            }
+        }
+        if state.dropped > 0 {
+            let mut note = String::new();
+            write_time(&mut note, SystemTime::now());
+            writeln!(note, ",\"op\":\"dropped\",\"count\":{}}}", state.dropped).unwrap();
+            state.dropped = 0;
+            if let Err(e) = state.write_line(&note) {
+                error!("failed to write to the op log: {}", e);
+            }
+        }
+        if let Err(e) = state.write_line(line) {
+            error!("failed to write to the op log: {}", e);
+        }
+    }
+}
+
+impl OpLogState {
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        match self.sink {
+            Sink::Writer(ref mut writer) => writer.write_all(line.as_bytes()),
+            Sink::File { ref path, ref mut file, ref mut size } => {
+                if let Some((max_size, keep)) = self.rotate {
+                    if *size > 0 && *size + line.len() as u64 > max_size {
+                        *file = rotate(path, keep)?;
+                        *size = 0;
+                    }
+                }
+                file.write_all(line.as_bytes())?;
+                *size += line.len() as u64;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Shift `path.N` to `path.N+1` for each old file, drop the oldest, move `path` to `path.1`, and
+/// open a new, empty `path`.
+fn rotate(path: &Path, keep: u32) -> io::Result<File> {
+    let numbered = |n: u32| {
+        let mut name = path.as_os_str().to_owned();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    };
+    if keep == 0 {
+        fs::remove_file(path)?;
+    } else {
+        for n in (1 .. keep).rev() {
+            match fs::rename(numbered(n), numbered(n + 1)) {
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+                other => other?,
+            }
+        }
+        fs::rename(path, numbered(1))?;
+    }
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn write_time(out: &mut String, time: SystemTime) {
+    let since_epoch = time.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+    write!(out, "{{\"time\":{}.{:06}", since_epoch.as_secs(), since_epoch.subsec_micros()).unwrap();
+}
+
+/// What's known about a finished call.
+struct Record<'a> {
+    op: &'a str,
+    path: &'a Path,
+    offset: Option<u64>,
+    size: Option<u64>,
+    duration: Duration,
+    errno: libc::c_int,
+}
+
+impl Record<'_> {
+    fn to_json(&self, time: SystemTime) -> String {
+        let mut line = String::new();
+        write_time(&mut line, time);
+        line.push_str(",\"op\":");
+        json::write_str(&mut line, self.op);
+        line.push_str(",\"path\":");
+        json::write_str(&mut line, &self.path.to_string_lossy());
+        if let Some(offset) = self.offset {
+            write!(line, ",\"offset\":{}", offset).unwrap();
+        }
+        if let Some(size) = self.size {
+            write!(line, ",\"size\":{}", size).unwrap();
+        }
+        writeln!(line, ",\"duration_us\":{},\"errno\":{}}}", self.duration.as_micros(), self.errno)
+            .unwrap();
+        line
+    }
+}
+
+/// A filesystem which writes a line to an `OpLog` for every call made to an inner filesystem.
+///
+/// Calls on `FileHandle` and `DirHandle` objects returned by the inner filesystem go straight to
+/// them, so only the calls which open them are logged.
+pub struct OpLogFs<FS> {
+    inner: FS,
+    log: OpLog,
+}
+
+impl<FS: FilesystemMT> OpLogFs<FS> {
+    pub fn new(inner: FS, log: OpLog) -> OpLogFs<FS> {
+        OpLogFs { inner, log }
+    }
+
+    fn record(&self, record: Record<'_>) {
+        self.log.write(&record.to_json(SystemTime::now()));
+    }
+
+    /// Make a call to the inner filesystem and log it.
+    fn logged<T>(&self, op: &str, path: &Path, call: impl FnOnce() -> Result<T, libc::c_int>)
+        -> Result<T, libc::c_int>
+    {
+        let start = Instant::now();
+        let result = call();
+        self.record(Record {
+            op,
+            path,
+            offset: None,
+            size: None,
+            duration: start.elapsed(),
+            errno: result.as_ref().err().copied().unwrap_or(0),
+        });
+        result
+    }
+}
+
+impl<FS: FilesystemMT> FilesystemMT for OpLogFs<FS> {
+    fn init(&self, req: RequestInfo) -> ResultEmpty {
+        self.logged("init", Path::new("/"), || self.inner.init(req))
+    }
+
+    fn init_connection(&self, req: RequestInfo, conn: &ConnectionInfo, config: &mut KernelConfig) -> ResultEmpty {
+        self.logged("init", Path::new("/"), || self.inner.init_connection(req, conn, config))
+    }
+
+    fn destroy(&self) {
+        self.inner.destroy();
+    }
+
+    fn getattr(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>) -> ResultEntry {
+        self.logged("getattr", path, || self.inner.getattr(req, path, fh))
+    }
+
+    fn chmod(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, mode: u32) -> ResultEmpty {
+        self.logged("chmod", path, || self.inner.chmod(req, path, fh, mode))
+    }
+
+    fn chown(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, uid: Option<u32>, gid: Option<u32>) -> ResultEmpty {
+        self.logged("chown", path, || self.inner.chown(req, path, fh, uid, gid))
+    }
+
+    fn truncate(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, size: u64) -> ResultEmpty {
+        self.logged("truncate", path, || self.inner.truncate(req, path, fh, size))
+    }
+
+    fn utimens(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, atime: Option<SystemTime>, mtime: Option<SystemTime>) -> ResultEmpty {
+        self.logged("utimens", path, || self.inner.utimens(req, path, fh, atime, mtime))
+    }
+
+    fn utimens_macos(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, crtime: Option<SystemTime>, chgtime: Option<SystemTime>, bkuptime: Option<SystemTime>, flags: Option<u32>) -> ResultEmpty {
+        self.logged("utimens_macos", path,
+            || self.inner.utimens_macos(req, path, fh, crtime, chgtime, bkuptime, flags))
+    }
+
+    fn readlink(&self, req: RequestInfo, path: &FsPath) -> ResultData {
+        self.logged("readlink", path, || self.inner.readlink(req, path))
+    }
+
+    fn mknod(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, mode: u32, rdev: u32) -> ResultEntry {
+        self.logged("mknod", &parent.join(name), || self.inner.mknod(req, parent, name, mode, rdev))
+    }
+
+    fn mkdir(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, mode: u32) -> ResultEntry {
+        self.logged("mkdir", &parent.join(name), || self.inner.mkdir(req, parent, name, mode))
+    }
+
+    fn unlink(&self, req: RequestInfo, parent: &FsPath, name: &OsStr) -> ResultEmpty {
+        self.logged("unlink", &parent.join(name), || self.inner.unlink(req, parent, name))
+    }
+
+    fn rmdir(&self, req: RequestInfo, parent: &FsPath, name: &OsStr) -> ResultEmpty {
+        self.logged("rmdir", &parent.join(name), || self.inner.rmdir(req, parent, name))
+    }
+
+    fn symlink(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, target: &Path) -> ResultEntry {
+        self.logged("symlink", &parent.join(name), || self.inner.symlink(req, parent, name, target))
+    }
+
+    fn rename(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, newparent: &FsPath, newname: &OsStr) -> ResultEmpty {
+        self.logged("rename", &parent.join(name), || self.inner.rename(req, parent, name, newparent, newname))
+    }
+
+    fn link(&self, req: RequestInfo, path: &FsPath, newparent: &FsPath, newname: &OsStr) -> ResultEntry {
+        self.logged("link", path, || self.inner.link(req, path, newparent, newname))
+    }
+
+    fn open(&self, req: RequestInfo, path: &FsPath, flags: u32) -> ResultOpen {
+        self.logged("open", path, || self.inner.open(req, path, flags))
+    }
+
+    fn open_handle(&self, req: RequestInfo, path: &FsPath, flags: u32) -> ResultOpenHandle {
+        self.logged("open", path, || self.inner.open_handle(req, path, flags))
+    }
+
+    fn read(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, size: u32, callback: impl FnOnce(ResultSlice<'_>) -> CallbackResult) -> CallbackResult {
+        let start = Instant::now();
+        let mut outcome = (None, libc::EIO);
+        let result = self.inner.read(req, path, fh, offset, size, |result| {
+            outcome = match result {
+                Ok(data) => (Some(data.len() as u64), 0),
+                Err(errno) => (None, errno),
+            };
+            callback(result)
+        });
+        self.record(Record {
+            op: "read",
+            path,
+            offset: Some(offset),
+            size: outcome.0,
+            duration: start.elapsed(),
+            errno: outcome.1,
+        });
+        result
+    }
+
+    fn write(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, data: Vec<u8>, flags: u32) -> ResultWrite {
+        let start = Instant::now();
+        let result = self.inner.write(req, path, fh, offset, data, flags);
+        self.record(Record {
+            op: "write",
+            path,
+            offset: Some(offset),
+            size: result.as_ref().ok().map(|&written| u64::from(written)),
+            duration: start.elapsed(),
+            errno: result.as_ref().err().copied().unwrap_or(0),
+        });
+        result
+    }
+
+    fn getlk(&self, req: RequestInfo, path: &FsPath, fh: u64, lock_owner: LockOwner, lock: FileLock) -> ResultLock {
+        self.logged("getlk", path, || self.inner.getlk(req, path, fh, lock_owner, lock))
+    }
+
+    fn setlk(&self, req: RequestInfo, path: &FsPath, fh: u64, lock_owner: LockOwner, lock: FileLock, sleep: bool) -> ResultEmpty {
+        self.logged("setlk", path, || self.inner.setlk(req, path, fh, lock_owner, lock, sleep))
+    }
+
+    fn flock(&self, req: RequestInfo, path: &FsPath, fh: u64, lock_owner: LockOwner, operation: i32) -> ResultEmpty {
+        self.logged("flock", path, || self.inner.flock(req, path, fh, lock_owner, operation))
+    }
+
+    fn fallocate(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, length: u64, mode: i32) -> ResultEmpty {
+        self.logged("fallocate", path, || self.inner.fallocate(req, path, fh, offset, length, mode))
+    }
+
+    fn lseek(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, whence: i32) -> ResultOffset {
+        self.logged("lseek", path, || self.inner.lseek(req, path, fh, offset, whence))
+    }
+
+    fn copy_file_range(&self, req: RequestInfo, path_in: &FsPath, fh_in: u64, offset_in: u64, path_out: &FsPath, fh_out: u64, offset_out: u64, len: u64, flags: u32) -> ResultWrite {
+        let start = Instant::now();
+        let result = self.inner.copy_file_range(req, path_in, fh_in, offset_in, path_out, fh_out, offset_out, len, flags);
+        self.record(Record {
+            op: "copy_file_range",
+            path: path_in,
+            offset: Some(offset_in),
+            size: result.as_ref().ok().map(|&copied| u64::from(copied)),
+            duration: start.elapsed(),
+            errno: result.as_ref().err().copied().unwrap_or(0),
+        });
+        result
+    }
+
+    fn flush(&self, req: RequestInfo, path: &FsPath, fh: u64, lock_owner: LockOwner) -> ResultEmpty {
+        self.logged("flush", path, || self.inner.flush(req, path, fh, lock_owner))
+    }
+
+    fn release(&self, req: RequestInfo, path: &FsPath, fh: u64, flags: u32, lock_owner: Option<LockOwner>, flush: bool) -> ResultEmpty {
+        self.logged("release", path, || self.inner.release(req, path, fh, flags, lock_owner, flush))
+    }
+
+    fn last_release_after_unlink(&self, req: RequestInfo, path: &FsPath, fh: u64) {
+        self.inner.last_release_after_unlink(req, path, fh);
+    }
+
+    fn fsync(&self, req: RequestInfo, path: &FsPath, fh: u64, datasync: bool) -> ResultEmpty {
+        self.logged("fsync", path, || self.inner.fsync(req, path, fh, datasync))
+    }
+
+    fn sync(&self, filter: &dyn Fn(&Path) -> bool) -> ResultEmpty {
+        self.logged("sync", Path::new("/"), || self.inner.sync(filter))
+    }
+
+    fn opendir(&self, req: RequestInfo, path: &FsPath, flags: u32) -> ResultOpen {
+        self.logged("opendir", path, || self.inner.opendir(req, path, flags))
+    }
+
+    fn opendir_handle(&self, req: RequestInfo, path: &FsPath, flags: u32) -> ResultOpenDirHandle {
+        self.logged("opendir", path, || self.inner.opendir_handle(req, path, flags))
+    }
+
+    fn readdir(&self, req: RequestInfo, path: &FsPath, fh: u64) -> ResultReaddir {
+        self.logged("readdir", path, || self.inner.readdir(req, path, fh))
+    }
+
+    fn readdir_at(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, add: &mut dyn FnMut(&DirectoryEntry, u64) -> bool) -> ResultEmpty {
+        self.logged("readdir", path, || self.inner.readdir_at(req, path, fh, offset, add))
+    }
+
+    fn releasedir(&self, req: RequestInfo, path: &FsPath, fh: u64, flags: u32) -> ResultEmpty {
+        self.logged("releasedir", path, || self.inner.releasedir(req, path, fh, flags))
+    }
+
+    fn fsyncdir(&self, req: RequestInfo, path: &FsPath, fh: u64, datasync: bool) -> ResultEmpty {
+        self.logged("fsyncdir", path, || self.inner.fsyncdir(req, path, fh, datasync))
+    }
+
+    fn statfs(&self, req: RequestInfo, path: &FsPath) -> ResultStatfs {
+        self.logged("statfs", path, || self.inner.statfs(req, path))
+    }
+
+    fn quota(&self, req: RequestInfo, path: &FsPath) -> ResultQuota {
+        self.logged("quota", path, || self.inner.quota(req, path))
+    }
+
+    fn setxattr(&self, req: RequestInfo, path: &FsPath, name: &OsStr, value: &[u8], flags: u32, position: XattrPosition) -> ResultEmpty {
+        self.logged("setxattr", path, || self.inner.setxattr(req, path, name, value, flags, position))
+    }
+
+    fn getxattr(&self, req: RequestInfo, path: &FsPath, name: &OsStr, size: u32) -> ResultXattr {
+        self.logged("getxattr", path, || self.inner.getxattr(req, path, name, size))
+    }
+
+    fn listxattr(&self, req: RequestInfo, path: &FsPath, size: u32) -> ResultXattr {
+        self.logged("listxattr", path, || self.inner.listxattr(req, path, size))
+    }
+
+    fn removexattr(&self, req: RequestInfo, path: &FsPath, name: &OsStr) -> ResultEmpty {
+        self.logged("removexattr", path, || self.inner.removexattr(req, path, name))
+    }
+
+    fn access(&self, req: RequestInfo, path: &FsPath, mask: u32) -> ResultEmpty {
+        self.logged("access", path, || self.inner.access(req, path, mask))
+    }
+
+    fn create(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, mode: u32, flags: u32) -> ResultCreate {
+        self.logged("create", &parent.join(name), || self.inner.create(req, parent, name, mode, flags))
+    }
+
+    fn create_handle(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, mode: u32, flags: u32) -> ResultCreateHandle {
+        self.logged("create", &parent.join(name), || self.inner.create_handle(req, parent, name, mode, flags))
+    }
+
+    #[cfg(target_os = "macos")]
+    fn setvolname(&self, req: RequestInfo, name: &OsStr) -> ResultEmpty {
+        self.logged("setvolname", Path::new("/"), || self.inner.setvolname(req, name))
+    }
+
+    #[cfg(target_os = "macos")]
+    fn exchange(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, newparent: &FsPath, newname: &OsStr, options: u64) -> ResultEmpty {
+        self.logged("exchange", &parent.join(name), || self.inner.exchange(req, parent, name, newparent, newname, options))
+    }
+
+    #[cfg(target_os = "macos")]
+    fn getxtimes(&self, req: RequestInfo, path: &FsPath) -> ResultXTimes {
+        self.logged("getxtimes", path, || self.inner.getxtimes(req, path))
+    }
+
+    #[cfg(target_os = "macos")]
+    fn setxtimes(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, bkuptime: Option<SystemTime>, crtime: Option<SystemTime>) -> ResultEmpty {
+        self.logged("setxtimes", path, || self.inner.setxtimes(req, path, fh, bkuptime, crtime))
+    }
+
+    fn classify(&self, op: Operation, path: &FsPath) -> Lane {
+        self.inner.classify(op, path)
+    }
+
+    fn load(&self, op: Operation, path: &FsPath) -> Load {
+        self.inner.load(op, path)
+    }
+}
+
+#[test]
+fn test_op_log() {
+    use std::sync::Arc;
+
+    #[derive(Clone)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    struct Inner;
+    impl FilesystemMT for Inner {
+        fn read(&self, _req: RequestInfo, _path: &FsPath, _fh: u64, _offset: u64, _size: u32, callback: impl FnOnce(ResultSlice<'_>) -> CallbackResult) -> CallbackResult {
+            callback(Ok(b"hello"))
+        }
+    }
+
+    let record = Record {
+        op: "write",
+        path: Path::new("/a \"b\""),
+        offset: Some(10),
+        size: Some(3),
+        duration: Duration::from_micros(1500),
+        errno: 0,
+    };
+    assert_eq!(
+        "{\"time\":1.000002,\"op\":\"write\",\"path\":\"/a \\\"b\\\"\",\"offset\":10,\"size\":3,\"duration_us\":1500,\"errno\":0}\n",
+        record.to_json(SystemTime::UNIX_EPOCH + Duration::from_micros(1_000_002)));
+
+    // Calls are logged as they finish, with their results.
+    let out = Shared(Arc::new(Mutex::new(vec![])));
+    let fs = OpLogFs::new(Inner, OpLog::new(out.clone()).rate_limit(2));
+    let req = RequestInfo { unique: 0, uid: 0, gid: 0, pid: 0, node: None, umask: None, lock_owner: None };
+    fs.read(req, &FsPath::from("/f"), 0, 4, 100, |_| CallbackResult { _private: std::marker::PhantomData });
+    assert!(fs.getattr(req, &FsPath::from("/f"), None).is_err());
+    // Over the rate limit:
+    assert!(fs.getattr(req, &FsPath::from("/g"), None).is_err());
+    fs.log.state.lock().unwrap().rate_limit.as_mut().unwrap().last -= Duration::from_secs(1);
+    assert!(fs.getattr(req, &FsPath::from("/h"), None).is_err());
+
+    let text = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+    // Leave out the times and durations, which vary.
+    let lines = text.lines()
+        .map(|line| line.split(',')
+            .filter(|field| !field.starts_with("{\"time\"") && !field.starts_with("\"duration_us\""))
+            .collect::<Vec<_>>()
+            .join(","))
+        .collect::<Vec<_>>();
+    assert_eq!(vec![
+        "\"op\":\"read\",\"path\":\"/f\",\"offset\":4,\"size\":5,\"errno\":0}".to_owned(),
+        format!("\"op\":\"getattr\",\"path\":\"/f\",\"errno\":{}}}", libc::ENOSYS),
+        "\"op\":\"dropped\",\"count\":1}".to_owned(),
+        format!("\"op\":\"getattr\",\"path\":\"/h\",\"errno\":{}}}", libc::ENOSYS),
+    ], lines);
+
+    // Rotation.
+    let dir = std::env::temp_dir().join(format!("fuse_mt_op_log_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir(&dir).unwrap();
+    let path = dir.join("ops.json");
+    let log = OpLog::file(&path).unwrap().rotate(10, 2);
+    for line in &["first\n", "second\n", "third\n", "fourth\n"] {
+        log.write(line);
+    }
+    assert_eq!("fourth\n", fs::read_to_string(&path).unwrap());
+    assert_eq!("third\n", fs::read_to_string(dir.join("ops.json.1")).unwrap());
+    assert_eq!("second\n", fs::read_to_string(dir.join("ops.json.2")).unwrap());
+    assert!(!dir.join("ops.json.3").exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
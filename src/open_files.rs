@@ -0,0 +1,73 @@
+// Open Files :: keeping track of which files are open, and which were unlinked while open.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+use std::collections::{HashMap, HashSet};
+
+/// Counts the open handles of each inode, and notes those which are unlinked while they have
+/// any, so that the filesystem can be told when the last handle to one of them is closed.
+#[derive(Debug, Default)]
+pub struct OpenFiles {
+    /// Number of open handles for each inode that has any.
+    open: HashMap<u64, usize>,
+    /// Open inodes which have been unlinked.
+    unlinked: HashSet<u64>,
+}
+
+impl OpenFiles {
+    pub fn new() -> OpenFiles {
+        OpenFiles::default()
+    }
+
+    /// Note that a handle to the given inode was opened.
+    pub fn opened(&mut self, ino: u64) {
+        *self.open.entry(ino).or_insert(0) += 1;
+    }
+
+    /// Whether the given inode has any open handles.
+    pub fn is_open(&self, ino: u64) -> bool {
+        self.open.contains_key(&ino)
+    }
+
+    /// Note that the given inode was unlinked. Nothing is kept for it unless it's open.
+    pub fn unlinked(&mut self, ino: u64) {
+        if self.is_open(ino) {
+            self.unlinked.insert(ino);
+        }
+    }
+
+    /// Note that a handle to the given inode was closed. Returns `None` if others are still open,
+    /// or else whether the inode had been unlinked.
+    pub fn closed(&mut self, ino: u64) -> Option<bool> {
+        match self.open.get_mut(&ino) {
+            Some(count) if *count > 1 => {
+                *count -= 1;
+                None
+            }
+            Some(_) => {
+                self.open.remove(&ino);
+                Some(self.unlinked.remove(&ino))
+            }
+            None => None,
+        }
+    }
+}
+
+#[test]
+fn test_open_files() {
+    let mut files = OpenFiles::new();
+    files.opened(5);
+    files.opened(5);
+    files.opened(6);
+    files.unlinked(5);
+    files.unlinked(7);
+    assert!(files.is_open(5));
+    assert!(!files.is_open(7));
+    assert_eq!(None, files.closed(5));
+    assert_eq!(Some(true), files.closed(5));
+    assert!(!files.is_open(5));
+    assert_eq!(Some(false), files.closed(6));
+    assert_eq!(None, files.closed(7));
+    assert!(files.unlinked.is_empty());
+}
@@ -0,0 +1,400 @@
+// Options :: parsing mount(8)-style option strings.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+//! Parsing of mount options in the classic `-o key=value,flag` form.
+//!
+//! When a filesystem is listed in /etc/fstab with type `fuse.<name>`, mount(8) runs the
+//! `mount.fuse` helper, which in turn runs the filesystem's binary as
+//! `<name> <source> <mountpoint> -o <options>`. `parse_mount_options` turns that option string into
+//! `MountOption`s for `mount2` or `spawn_mount2`, rejecting anything it doesn't recognize instead
+//! of silently passing it through to the kernel. `check_options` handles a whole command line in
+//! that form, including the conventional `-h` and `-V` flags.
+//!
+//! macFUSE takes a number of options of its own, like `volname` and `noappledouble`, which `fuser`
+//! has no variants for. `MacOption` builds them, and `parse_mount_options` accepts them, on macOS;
+//! everywhere else they're rejected, as is passing one to `mount2` and friends, rather than being
+//! handed to a kernel which doesn't know them.
+
+use std::error::Error;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::path::PathBuf;
+
+use fuser::MountOption;
+
+/// Options which are only meaningful to mount(8) or fstab tooling, and are dropped.
+const IGNORED: &[&str] = &[
+    "defaults", "auto", "noauto", "user", "nouser", "users", "owner", "group", "_netdev",
+    "nofail", "nobootwait",
+];
+
+/// Options which are given to the kernel as-is, and take a numeric value.
+const NUMERIC: &[&str] = &["max_read", "blksize"];
+
+/// Options which are given to the kernel as-is, and take any value.
+const PASSTHROUGH: &[&str] = &["context", "fscontext", "defcontext", "rootcontext"];
+
+/// macFUSE options which don't take a value.
+const MAC_FLAGS: &[&str] = &[
+    "local", "noappledouble", "noapplexattr", "nobrowse", "nolocalcaches", "defer_permissions",
+];
+
+/// macFUSE options which take a numeric value.
+const MAC_NUMERIC: &[&str] = &["daemon_timeout", "iosize"];
+
+/// macFUSE options which take any value.
+const MAC_VALUED: &[&str] = &["volname", "volicon"];
+
+/// Options specific to macFUSE. These convert into `MountOption`s, for use with `mount2` and
+/// friends, but mounting with them fails on other platforms.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MacOption {
+    /// The name the volume is shown with in Finder.
+    VolName(String),
+    /// Path to an `.icns` file to use as the volume's icon.
+    VolIcon(PathBuf),
+    /// Mark the volume as local rather than a network volume, so Finder shows it on the desktop and
+    /// in the sidebar.
+    Local,
+    /// Don't create or allow `._` AppleDouble files.
+    NoAppleDouble,
+    /// Don't allow any `com.apple.*` extended attributes.
+    NoAppleXattr,
+    /// Don't show the volume in Finder.
+    NoBrowse,
+    /// Don't cache file data or attributes in the kernel.
+    NoLocalCaches,
+    /// Have the kernel allow everything, and leave permission checks to the filesystem.
+    DeferPermissions,
+    /// Seconds the kernel waits for the filesystem to answer a request before giving up on it.
+    DaemonTimeout(u32),
+    /// Size in bytes of the kernel's I/O requests.
+    IoSize(u32),
+}
+
+impl From<MacOption> for MountOption {
+    fn from(option: MacOption) -> MountOption {
+        MountOption::CUSTOM(match option {
+            MacOption::VolName(name) => format!("volname={}", name),
+            MacOption::VolIcon(path) => format!("volicon={}", path.display()),
+            MacOption::Local => "local".to_owned(),
+            MacOption::NoAppleDouble => "noappledouble".to_owned(),
+            MacOption::NoAppleXattr => "noapplexattr".to_owned(),
+            MacOption::NoBrowse => "nobrowse".to_owned(),
+            MacOption::NoLocalCaches => "nolocalcaches".to_owned(),
+            MacOption::DeferPermissions => "defer_permissions".to_owned(),
+            MacOption::DaemonTimeout(secs) => format!("daemon_timeout={}", secs),
+            MacOption::IoSize(bytes) => format!("iosize={}", bytes),
+        })
+    }
+}
+
+/// An error in a mount option string.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OptionError {
+    /// An option which isn't recognized.
+    Unknown(String),
+    /// An option which needs a value was given without one.
+    MissingValue(String),
+    /// An option which doesn't take a value was given one.
+    UnexpectedValue(String),
+    /// An option's value isn't valid for it.
+    InvalidValue { option: String, value: String },
+    /// A command line argument which isn't recognized.
+    UnknownArgument(OsString),
+    /// An option which only exists on another platform.
+    Unsupported(String),
+}
+
+impl fmt::Display for OptionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OptionError::Unknown(option) => write!(f, "unknown mount option {:?}", option),
+            OptionError::MissingValue(option) => {
+                write!(f, "mount option {:?} requires a value, as in \"{}=...\"", option, option)
+            }
+            OptionError::UnexpectedValue(option) => {
+                write!(f, "mount option {:?} does not take a value", option)
+            }
+            OptionError::InvalidValue { option, value } => {
+                write!(f, "invalid value {:?} for mount option {:?}", value, option)
+            }
+            OptionError::UnknownArgument(arg) => write!(f, "unknown argument {:?}", arg),
+            OptionError::Unsupported(option) => {
+                write!(f, "mount option {:?} is not supported on this platform", option)
+            }
+        }
+    }
+}
+
+impl Error for OptionError {}
+
+/// Parse a comma-separated mount option string, as given to `-o`, into `MountOption`s.
+///
+/// Commas can be included in values by escaping them with a backslash. Options which only matter
+/// to mount(8), like `defaults`, `noauto`, `_netdev`, and `x-systemd.*`, are accepted and dropped.
+pub fn parse_mount_options(options: &str) -> Result<Vec<MountOption>, OptionError> {
+    let mut result = vec![];
+    for option in split(options) {
+        let (key, value) = match option.find('=') {
+            Some(idx) => (&option[.. idx], Some(&option[idx + 1 ..])),
+            None => (option.as_str(), None),
+        };
+
+        if key.is_empty() || IGNORED.contains(&key) || key.starts_with("x-")
+            || key == "comment"
+        {
+            continue;
+        }
+
+        let parsed = match (key, value) {
+            ("fsname", Some(v)) => MountOption::FSName(v.to_owned()),
+            ("subtype", Some(v)) => MountOption::Subtype(v.to_owned()),
+            ("fsname", None) | ("subtype", None) => {
+                return Err(OptionError::MissingValue(key.to_owned()));
+            }
+            (key, Some(v)) if NUMERIC.contains(&key) => {
+                if v.parse::<u32>().is_err() {
+                    return Err(OptionError::InvalidValue {
+                        option: key.to_owned(),
+                        value: v.to_owned(),
+                    });
+                }
+                MountOption::CUSTOM(option.clone())
+            }
+            (key, Some(_)) if PASSTHROUGH.contains(&key) => MountOption::CUSTOM(option.clone()),
+            (key, None) if NUMERIC.contains(&key) || PASSTHROUGH.contains(&key) => {
+                return Err(OptionError::MissingValue(key.to_owned()));
+            }
+            (key, value) if is_mac_option(key) => {
+                check_mac_option(key, value)?;
+                MountOption::CUSTOM(option.clone())
+            }
+            (key, value) => {
+                let flag = flag(key).ok_or_else(|| OptionError::Unknown(key.to_owned()))?;
+                if value.is_some() {
+                    return Err(OptionError::UnexpectedValue(key.to_owned()));
+                }
+                flag
+            }
+        };
+        result.push(parsed);
+    }
+    Ok(result)
+}
+
+/// Check that the given options can be used on this platform: that is, that there are no macFUSE
+/// options, unless this is macOS. `mount2`, `spawn_mount2`, and `MountedSession::spawn` do this
+/// before mounting.
+pub fn validate_mount_options(options: &[MountOption]) -> Result<(), OptionError> {
+    for option in options {
+        if let MountOption::CUSTOM(option) = option {
+            let (key, value) = match option.find('=') {
+                Some(idx) => (&option[.. idx], Some(&option[idx + 1 ..])),
+                None => (option.as_str(), None),
+            };
+            if is_mac_option(key) {
+                check_mac_option(key, value)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn is_mac_option(key: &str) -> bool {
+    MAC_FLAGS.contains(&key) || MAC_NUMERIC.contains(&key) || MAC_VALUED.contains(&key)
+}
+
+/// Check a macFUSE option's value, and that this is macOS.
+fn check_mac_option(key: &str, value: Option<&str>) -> Result<(), OptionError> {
+    if cfg!(not(target_os = "macos")) {
+        return Err(OptionError::Unsupported(key.to_owned()));
+    }
+    match value {
+        Some(_) if MAC_FLAGS.contains(&key) => Err(OptionError::UnexpectedValue(key.to_owned())),
+        None if !MAC_FLAGS.contains(&key) => Err(OptionError::MissingValue(key.to_owned())),
+        Some(v) if MAC_NUMERIC.contains(&key) && v.parse::<u32>().is_err() => {
+            Err(OptionError::InvalidValue { option: key.to_owned(), value: v.to_owned() })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The result of checking a command line with `check_options`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CheckOptions {
+    /// `-h` or `--help` was given: the program should print its usage and exit.
+    Help,
+    /// `-V` or `--version` was given: the program should print its version and exit.
+    Version,
+    /// The command line was valid, and the program should mount the filesystem.
+    Proceed(ParsedOptions),
+    /// The command line was not valid.
+    Error(OptionError),
+}
+
+/// A successfully checked command line.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ParsedOptions {
+    /// Options from all the `-o` arguments, in order.
+    pub mount_options: Vec<MountOption>,
+    /// Arguments which aren't options, such as the mountpoint, in order.
+    pub free_args: Vec<OsString>,
+}
+
+/// Check a command line in the form used by FUSE filesystems (and by mount.fuse when running them
+/// from fstab): free arguments mixed with `-o <options>` (or `-o<options>`), `-h`/`--help`, and
+/// `-V`/`--version`. Anything after `--` is taken as a free argument.
+///
+/// `args` should not include the program name. Unknown flags and mount options are reported as
+/// errors rather than ignored, so typos don't go unnoticed; it's up to the caller to decide how to
+/// present them, and the help and version text.
+pub fn check_options<I, S>(args: I) -> CheckOptions
+    where I: IntoIterator<Item = S>,
+          S: AsRef<OsStr>,
+{
+    let mut parsed = ParsedOptions::default();
+    let mut args = args.into_iter();
+    let mut only_free = false;
+    while let Some(arg) = args.next() {
+        let arg = arg.as_ref();
+        if only_free {
+            parsed.free_args.push(arg.to_owned());
+            continue;
+        }
+        let options = match arg.to_str() {
+            Some("-h") | Some("--help") => return CheckOptions::Help,
+            Some("-V") | Some("--version") => return CheckOptions::Version,
+            Some("--") => {
+                only_free = true;
+                continue;
+            }
+            Some("-o") => match args.next() {
+                Some(value) => value.as_ref().to_owned(),
+                None => return CheckOptions::Error(OptionError::MissingValue("-o".to_owned())),
+            },
+            Some(s) if s.starts_with("-o") => OsString::from(&s[2 ..]),
+            _ if arg.to_string_lossy().starts_with('-') => {
+                return CheckOptions::Error(OptionError::UnknownArgument(arg.to_owned()));
+            }
+            _ => {
+                parsed.free_args.push(arg.to_owned());
+                continue;
+            }
+        };
+        let options = match options.to_str() {
+            Some(options) => options,
+            None => {
+                return CheckOptions::Error(OptionError::InvalidValue {
+                    option: "-o".to_owned(),
+                    value: options.to_string_lossy().into_owned(),
+                });
+            }
+        };
+        match parse_mount_options(options) {
+            Ok(options) => parsed.mount_options.extend(options),
+            Err(e) => return CheckOptions::Error(e),
+        }
+    }
+    CheckOptions::Proceed(parsed)
+}
+
+fn flag(name: &str) -> Option<MountOption> {
+    Some(match name {
+        "allow_other" => MountOption::AllowOther,
+        "allow_root" => MountOption::AllowRoot,
+        "auto_unmount" => MountOption::AutoUnmount,
+        "default_permissions" => MountOption::DefaultPermissions,
+        "dev" => MountOption::Dev,
+        "nodev" => MountOption::NoDev,
+        "suid" => MountOption::Suid,
+        "nosuid" => MountOption::NoSuid,
+        "ro" => MountOption::RO,
+        "rw" => MountOption::RW,
+        "exec" => MountOption::Exec,
+        "noexec" => MountOption::NoExec,
+        "atime" => MountOption::Atime,
+        "noatime" => MountOption::NoAtime,
+        "dirsync" => MountOption::DirSync,
+        "sync" => MountOption::Sync,
+        "async" => MountOption::Async,
+        _ => return None,
+    })
+}
+
+/// Split an option string on commas which aren't escaped with a backslash.
+fn split(options: &str) -> Vec<String> {
+    let mut parts = vec![];
+    let mut current = String::new();
+    let mut chars = options.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => current.extend(chars.next()),
+            ',' => parts.push(std::mem::take(&mut current)),
+            c => current.push(c),
+        }
+    }
+    parts.push(current);
+    parts
+}
+
+#[test]
+fn test_parse_mount_options() {
+    assert_eq!(
+        Ok(vec![
+            MountOption::RW,
+            MountOption::AllowOther,
+            MountOption::FSName("a,b".to_owned()),
+            MountOption::CUSTOM("max_read=4096".to_owned()),
+        ]),
+        parse_mount_options("rw,noauto,allow_other,,fsname=a\\,b,x-systemd.automount,max_read=4096"));
+
+    assert_eq!(Err(OptionError::Unknown("alow_other".to_owned())),
+        parse_mount_options("ro,alow_other"));
+    assert_eq!(Err(OptionError::MissingValue("fsname".to_owned())),
+        parse_mount_options("fsname"));
+    assert_eq!(Err(OptionError::UnexpectedValue("ro".to_owned())),
+        parse_mount_options("ro=1"));
+    assert_eq!(Err(OptionError::InvalidValue { option: "blksize".to_owned(), value: "big".to_owned() }),
+        parse_mount_options("blksize=big"));
+}
+
+#[test]
+fn test_mac_options() {
+    let volname = MountOption::from(MacOption::VolName("My Files".to_owned()));
+    assert_eq!(MountOption::CUSTOM("volname=My Files".to_owned()), volname);
+
+    if cfg!(target_os = "macos") {
+        assert_eq!(Ok(vec![volname.clone(), MountOption::CUSTOM("local".to_owned())]),
+            parse_mount_options("volname=My Files,local"));
+        assert_eq!(Ok(()), validate_mount_options(&[volname, MacOption::IoSize(4096).into()]));
+        assert_eq!(Err(OptionError::InvalidValue { option: "iosize".to_owned(), value: "x".to_owned() }),
+            parse_mount_options("iosize=x"));
+        assert_eq!(Err(OptionError::UnexpectedValue("local".to_owned())),
+            parse_mount_options("local=1"));
+    } else {
+        assert_eq!(Err(OptionError::Unsupported("volname".to_owned())),
+            parse_mount_options("volname=My Files"));
+        assert_eq!(Err(OptionError::Unsupported("noappledouble".to_owned())),
+            validate_mount_options(&[MountOption::RO, MacOption::NoAppleDouble.into()]));
+    }
+    assert_eq!(Ok(()), validate_mount_options(&[MountOption::CUSTOM("max_read=4096".to_owned())]));
+}
+
+#[test]
+fn test_check_options() {
+    assert_eq!(
+        CheckOptions::Proceed(ParsedOptions {
+            mount_options: vec![MountOption::RO, MountOption::AllowOther],
+            free_args: vec!["src".into(), "/mnt".into(), "-x".into()],
+        }),
+        check_options(["src", "-o", "ro", "/mnt", "-oallow_other", "--", "-x"]));
+    assert_eq!(CheckOptions::Help, check_options(["/mnt", "--help", "-o", "bogus"]));
+    assert_eq!(CheckOptions::Version, check_options(["-V"]));
+    assert_eq!(CheckOptions::Error(OptionError::UnknownArgument("-x".into())),
+        check_options(["-x"]));
+    assert_eq!(CheckOptions::Error(OptionError::MissingValue("-o".to_owned())),
+        check_options(["/mnt", "-o"]));
+}
@@ -0,0 +1,387 @@
+// OTel :: OpenTelemetry tracing of filesystem calls.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+//! Record a trace span for every filesystem call, with OpenTelemetry.
+//!
+//! Wrap a filesystem in `TracedFs` to get a span for each call made to it, with the operation,
+//! path, and caller in its attributes, and the error code if it failed. Spans are made with the
+//! global tracer provider from the `opentelemetry` crate, so they go wherever the program has set
+//! that up to send them, such as to a collector with `opentelemetry-otlp`:
+//!
+//! ```ignore
+//! let exporter = opentelemetry_otlp::SpanExporter::builder().with_http().build()?;
+//! let provider = opentelemetry_sdk::trace::SdkTracerProvider::builder()
+//!     .with_batch_exporter(exporter)
+//!     .build();
+//! opentelemetry::global::set_tracer_provider(provider.clone());
+//!
+//! let fs = TracedFs::new(MyFilesystem::new());
+//! fuse_mt::mount(FuseMT::new(fs, 4), &mountpoint, &[])?;
+//! provider.shutdown()?;
+//! ```
+//!
+//! Until a provider is set, spans are made with the global no-op provider, which costs little.
+//!
+//! While a call is in progress, its span is in the current `opentelemetry::Context` on the thread
+//! making it, so spans the filesystem starts underneath it (with any tracer) are its children,
+//! and the trace context can be passed along to other services with the usual propagators, and
+//! they'll all show up as part of the same trace.
+
+use std::ffi::OsStr;
+use std::io;
+use std::path::Path;
+use std::time::SystemTime;
+
+use opentelemetry::trace::{SpanKind, Status, TraceContextExt, Tracer};
+use opentelemetry::{global, Context, InstrumentationScope, KeyValue};
+
+use crate::types::*;
+
+/// A filesystem which records a span for every call made to an inner filesystem.
+///
+/// Each span is named after the operation (like `fuse.getattr`), and has the path, the caller's
+/// uid, gid, and pid, and the FUSE request ID as attributes. Failed calls have their error code
+/// in a `fuse.errno` attribute and are marked as errors.
+///
+/// Calls on `FileHandle` and `DirHandle` objects returned by the inner filesystem go straight to
+/// them, so only the calls which open them are traced.
+#[derive(Debug)]
+pub struct TracedFs<FS> {
+    inner: FS,
+    scope: InstrumentationScope,
+}
+
+impl<FS: FilesystemMT> TracedFs<FS> {
+    pub fn new(inner: FS) -> TracedFs<FS> {
+        let scope = InstrumentationScope::builder("fuse_mt").with_version(crate::VERSION).build();
+        TracedFs { inner, scope }
+    }
+
+    /// Start a span for a call, and return a context with it in, to be made current while the
+    /// call is made.
+    ///
+    /// The tracer is got from the global provider each time, so that spans go to whichever
+    /// provider is set, even if it's set after the filesystem is made.
+    fn span(&self, req: RequestInfo, op: &'static str, path: &Path) -> Context {
+        let tracer = global::tracer_with_scope(self.scope.clone());
+        let span = tracer.span_builder(format!("fuse.{}", op))
+            .with_kind(SpanKind::Server)
+            .with_attributes([
+                KeyValue::new("fuse.op", op),
+                KeyValue::new("fuse.path", path.to_string_lossy().into_owned()),
+                KeyValue::new("fuse.unique", req.unique as i64),
+                KeyValue::new("fuse.uid", i64::from(req.uid)),
+                KeyValue::new("fuse.gid", i64::from(req.gid)),
+                KeyValue::new("fuse.pid", i64::from(req.pid)),
+            ])
+            .start(&tracer);
+        Context::current_with_span(span)
+    }
+
+    /// Make a call to the inner filesystem inside a new span.
+    fn traced<T>(&self, req: RequestInfo, op: &'static str, path: &Path,
+                 call: impl FnOnce() -> Result<T, libc::c_int>) -> Result<T, libc::c_int>
+    {
+        in_span(self.span(req, op, path), call)
+    }
+}
+
+/// Make a call with the span in `cx` as the current span, and end the span once it returns.
+fn in_span<T>(cx: Context, call: impl FnOnce() -> Result<T, libc::c_int>)
+    -> Result<T, libc::c_int>
+{
+    let result = {
+        let _guard = cx.clone().attach();
+        call()
+    };
+    if let Err(errno) = result {
+        set_errno(&cx, errno);
+    }
+    cx.span().end();
+    result
+}
+
+fn set_errno(cx: &Context, errno: libc::c_int) {
+    let span = cx.span();
+    span.set_attribute(KeyValue::new("fuse.errno", i64::from(errno)));
+    span.set_status(Status::error(io::Error::from_raw_os_error(errno).to_string()));
+}
+
+impl<FS: FilesystemMT> FilesystemMT for TracedFs<FS> {
+    fn init(&self, req: RequestInfo) -> ResultEmpty {
+        self.traced(req, "init", Path::new("/"), || self.inner.init(req))
+    }
+
+    fn init_connection(&self, req: RequestInfo, conn: &ConnectionInfo, config: &mut KernelConfig) -> ResultEmpty {
+        self.traced(req, "init", Path::new("/"), || self.inner.init_connection(req, conn, config))
+    }
+
+    fn destroy(&self) {
+        self.inner.destroy();
+    }
+
+    fn getattr(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>) -> ResultEntry {
+        self.traced(req, "getattr", path, || self.inner.getattr(req, path, fh))
+    }
+
+    fn chmod(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, mode: u32) -> ResultEmpty {
+        self.traced(req, "chmod", path, || self.inner.chmod(req, path, fh, mode))
+    }
+
+    fn chown(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, uid: Option<u32>, gid: Option<u32>) -> ResultEmpty {
+        self.traced(req, "chown", path, || self.inner.chown(req, path, fh, uid, gid))
+    }
+
+    fn truncate(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, size: u64) -> ResultEmpty {
+        self.traced(req, "truncate", path, || self.inner.truncate(req, path, fh, size))
+    }
+
+    fn utimens(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, atime: Option<SystemTime>, mtime: Option<SystemTime>) -> ResultEmpty {
+        self.traced(req, "utimens", path, || self.inner.utimens(req, path, fh, atime, mtime))
+    }
+
+    fn utimens_macos(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, crtime: Option<SystemTime>, chgtime: Option<SystemTime>, bkuptime: Option<SystemTime>, flags: Option<u32>) -> ResultEmpty {
+        self.traced(req, "utimens_macos", path,
+            || self.inner.utimens_macos(req, path, fh, crtime, chgtime, bkuptime, flags))
+    }
+
+    fn readlink(&self, req: RequestInfo, path: &FsPath) -> ResultData {
+        self.traced(req, "readlink", path, || self.inner.readlink(req, path))
+    }
+
+    fn mknod(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, mode: u32, rdev: u32) -> ResultEntry {
+        self.traced(req, "mknod", &parent.join(name), || self.inner.mknod(req, parent, name, mode, rdev))
+    }
+
+    fn mkdir(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, mode: u32) -> ResultEntry {
+        self.traced(req, "mkdir", &parent.join(name), || self.inner.mkdir(req, parent, name, mode))
+    }
+
+    fn unlink(&self, req: RequestInfo, parent: &FsPath, name: &OsStr) -> ResultEmpty {
+        self.traced(req, "unlink", &parent.join(name), || self.inner.unlink(req, parent, name))
+    }
+
+    fn rmdir(&self, req: RequestInfo, parent: &FsPath, name: &OsStr) -> ResultEmpty {
+        self.traced(req, "rmdir", &parent.join(name), || self.inner.rmdir(req, parent, name))
+    }
+
+    fn symlink(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, target: &Path) -> ResultEntry {
+        self.traced(req, "symlink", &parent.join(name), || self.inner.symlink(req, parent, name, target))
+    }
+
+    fn rename(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, newparent: &FsPath, newname: &OsStr) -> ResultEmpty {
+        let cx = self.span(req, "rename", &parent.join(name));
+        cx.span().set_attribute(KeyValue::new("fuse.new_path",
+            newparent.join(newname).to_string_lossy().into_owned()));
+        in_span(cx, || self.inner.rename(req, parent, name, newparent, newname))
+    }
+
+    fn link(&self, req: RequestInfo, path: &FsPath, newparent: &FsPath, newname: &OsStr) -> ResultEntry {
+        self.traced(req, "link", path, || self.inner.link(req, path, newparent, newname))
+    }
+
+    fn open(&self, req: RequestInfo, path: &FsPath, flags: u32) -> ResultOpen {
+        self.traced(req, "open", path, || self.inner.open(req, path, flags))
+    }
+
+    fn open_handle(&self, req: RequestInfo, path: &FsPath, flags: u32) -> ResultOpenHandle {
+        self.traced(req, "open", path, || self.inner.open_handle(req, path, flags))
+    }
+
+    fn read(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, size: u32, callback: impl FnOnce(ResultSlice<'_>) -> CallbackResult) -> CallbackResult {
+        let cx = self.span(req, "read", path);
+        let result = {
+            let _guard = cx.clone().attach();
+            self.inner.read(req, path, fh, offset, size, |result| {
+                if let Err(errno) = result {
+                    set_errno(&cx, errno);
+                }
+                callback(result)
+            })
+        };
+        cx.span().end();
+        result
+    }
+
+    fn write(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, data: Vec<u8>, flags: u32) -> ResultWrite {
+        self.traced(req, "write", path, || self.inner.write(req, path, fh, offset, data, flags))
+    }
+
+    fn getlk(&self, req: RequestInfo, path: &FsPath, fh: u64, lock_owner: LockOwner, lock: FileLock) -> ResultLock {
+        self.traced(req, "getlk", path, || self.inner.getlk(req, path, fh, lock_owner, lock))
+    }
+
+    fn setlk(&self, req: RequestInfo, path: &FsPath, fh: u64, lock_owner: LockOwner, lock: FileLock, sleep: bool) -> ResultEmpty {
+        self.traced(req, "setlk", path, || self.inner.setlk(req, path, fh, lock_owner, lock, sleep))
+    }
+
+    fn flock(&self, req: RequestInfo, path: &FsPath, fh: u64, lock_owner: LockOwner, operation: i32) -> ResultEmpty {
+        self.traced(req, "flock", path, || self.inner.flock(req, path, fh, lock_owner, operation))
+    }
+
+    fn fallocate(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, length: u64, mode: i32) -> ResultEmpty {
+        self.traced(req, "fallocate", path, || self.inner.fallocate(req, path, fh, offset, length, mode))
+    }
+
+    fn lseek(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, whence: i32) -> ResultOffset {
+        self.traced(req, "lseek", path, || self.inner.lseek(req, path, fh, offset, whence))
+    }
+
+    fn copy_file_range(&self, req: RequestInfo, path_in: &FsPath, fh_in: u64, offset_in: u64, path_out: &FsPath, fh_out: u64, offset_out: u64, len: u64, flags: u32) -> ResultWrite {
+        let cx = self.span(req, "copy_file_range", path_in);
+        cx.span().set_attribute(KeyValue::new("fuse.new_path",
+            path_out.to_string_lossy().into_owned()));
+        in_span(cx, || self.inner.copy_file_range(req, path_in, fh_in, offset_in, path_out, fh_out, offset_out, len, flags))
+    }
+
+    fn flush(&self, req: RequestInfo, path: &FsPath, fh: u64, lock_owner: LockOwner) -> ResultEmpty {
+        self.traced(req, "flush", path, || self.inner.flush(req, path, fh, lock_owner))
+    }
+
+    fn release(&self, req: RequestInfo, path: &FsPath, fh: u64, flags: u32, lock_owner: Option<LockOwner>, flush: bool) -> ResultEmpty {
+        self.traced(req, "release", path, || self.inner.release(req, path, fh, flags, lock_owner, flush))
+    }
+
+    fn last_release_after_unlink(&self, req: RequestInfo, path: &FsPath, fh: u64) {
+        self.inner.last_release_after_unlink(req, path, fh);
+    }
+
+    fn fsync(&self, req: RequestInfo, path: &FsPath, fh: u64, datasync: bool) -> ResultEmpty {
+        self.traced(req, "fsync", path, || self.inner.fsync(req, path, fh, datasync))
+    }
+
+    fn sync(&self, filter: &dyn Fn(&Path) -> bool) -> ResultEmpty {
+        self.inner.sync(filter)
+    }
+
+    fn opendir(&self, req: RequestInfo, path: &FsPath, flags: u32) -> ResultOpen {
+        self.traced(req, "opendir", path, || self.inner.opendir(req, path, flags))
+    }
+
+    fn opendir_handle(&self, req: RequestInfo, path: &FsPath, flags: u32) -> ResultOpenDirHandle {
+        self.traced(req, "opendir", path, || self.inner.opendir_handle(req, path, flags))
+    }
+
+    fn readdir(&self, req: RequestInfo, path: &FsPath, fh: u64) -> ResultReaddir {
+        self.traced(req, "readdir", path, || self.inner.readdir(req, path, fh))
+    }
+
+    fn readdir_at(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, add: &mut dyn FnMut(&DirectoryEntry, u64) -> bool) -> ResultEmpty {
+        self.traced(req, "readdir", path, || self.inner.readdir_at(req, path, fh, offset, add))
+    }
+
+    fn releasedir(&self, req: RequestInfo, path: &FsPath, fh: u64, flags: u32) -> ResultEmpty {
+        self.traced(req, "releasedir", path, || self.inner.releasedir(req, path, fh, flags))
+    }
+
+    fn fsyncdir(&self, req: RequestInfo, path: &FsPath, fh: u64, datasync: bool) -> ResultEmpty {
+        self.traced(req, "fsyncdir", path, || self.inner.fsyncdir(req, path, fh, datasync))
+    }
+
+    fn statfs(&self, req: RequestInfo, path: &FsPath) -> ResultStatfs {
+        self.traced(req, "statfs", path, || self.inner.statfs(req, path))
+    }
+
+    fn quota(&self, req: RequestInfo, path: &FsPath) -> ResultQuota {
+        self.traced(req, "quota", path, || self.inner.quota(req, path))
+    }
+
+    fn setxattr(&self, req: RequestInfo, path: &FsPath, name: &OsStr, value: &[u8], flags: u32, position: XattrPosition) -> ResultEmpty {
+        self.traced(req, "setxattr", path, || self.inner.setxattr(req, path, name, value, flags, position))
+    }
+
+    fn getxattr(&self, req: RequestInfo, path: &FsPath, name: &OsStr, size: u32) -> ResultXattr {
+        self.traced(req, "getxattr", path, || self.inner.getxattr(req, path, name, size))
+    }
+
+    fn listxattr(&self, req: RequestInfo, path: &FsPath, size: u32) -> ResultXattr {
+        self.traced(req, "listxattr", path, || self.inner.listxattr(req, path, size))
+    }
+
+    fn removexattr(&self, req: RequestInfo, path: &FsPath, name: &OsStr) -> ResultEmpty {
+        self.traced(req, "removexattr", path, || self.inner.removexattr(req, path, name))
+    }
+
+    fn access(&self, req: RequestInfo, path: &FsPath, mask: u32) -> ResultEmpty {
+        self.traced(req, "access", path, || self.inner.access(req, path, mask))
+    }
+
+    fn create(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, mode: u32, flags: u32) -> ResultCreate {
+        self.traced(req, "create", &parent.join(name), || self.inner.create(req, parent, name, mode, flags))
+    }
+
+    fn create_handle(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, mode: u32, flags: u32) -> ResultCreateHandle {
+        self.traced(req, "create", &parent.join(name), || self.inner.create_handle(req, parent, name, mode, flags))
+    }
+
+    #[cfg(target_os = "macos")]
+    fn setvolname(&self, req: RequestInfo, name: &OsStr) -> ResultEmpty {
+        self.traced(req, "setvolname", Path::new("/"), || self.inner.setvolname(req, name))
+    }
+
+    #[cfg(target_os = "macos")]
+    fn exchange(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, newparent: &FsPath, newname: &OsStr, options: u64) -> ResultEmpty {
+        self.traced(req, "exchange", &parent.join(name), || self.inner.exchange(req, parent, name, newparent, newname, options))
+    }
+
+    #[cfg(target_os = "macos")]
+    fn getxtimes(&self, req: RequestInfo, path: &FsPath) -> ResultXTimes {
+        self.traced(req, "getxtimes", path, || self.inner.getxtimes(req, path))
+    }
+
+    #[cfg(target_os = "macos")]
+    fn setxtimes(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, bkuptime: Option<SystemTime>, crtime: Option<SystemTime>) -> ResultEmpty {
+        self.traced(req, "setxtimes", path, || self.inner.setxtimes(req, path, fh, bkuptime, crtime))
+    }
+
+    fn classify(&self, op: Operation, path: &FsPath) -> Lane {
+        self.inner.classify(op, path)
+    }
+
+    fn load(&self, op: Operation, path: &FsPath) -> Load {
+        self.inner.load(op, path)
+    }
+}
+
+#[test]
+fn test_otel() {
+    use opentelemetry::trace::{Span as _, SpanKind, Status};
+    use opentelemetry_sdk::trace::{InMemorySpanExporter, SdkTracerProvider};
+
+    struct Inner;
+    impl FilesystemMT for Inner {
+        fn getattr(&self, _req: RequestInfo, path: &FsPath, _fh: Option<u64>) -> ResultEntry {
+            let mut span = global::tracer("backend").start("lookup in backend");
+            span.set_attribute(KeyValue::new("backend.key", path.to_string_lossy().into_owned()));
+            Err(libc::ENOENT)
+        }
+    }
+
+    let exporter = InMemorySpanExporter::default();
+    let provider = SdkTracerProvider::builder().with_simple_exporter(exporter.clone()).build();
+    global::set_tracer_provider(provider.clone());
+
+    let fs = TracedFs::new(Inner);
+    let req = RequestInfo { unique: 9, uid: 1000, gid: 100, pid: 42, node: None, umask: None, lock_owner: None };
+    assert_eq!(Err(libc::ENOENT), fs.getattr(req, &FsPath::from("/a\"b"), None).map(|_| ()));
+    assert!(!Context::current().has_active_span());
+    provider.force_flush().unwrap();
+
+    let spans = exporter.get_finished_spans().unwrap();
+    assert_eq!(2, spans.len());
+    let (child, parent) = (&spans[0], &spans[1]);
+    assert_eq!("lookup in backend", child.name);
+    assert_eq!(SpanKind::Internal, child.span_kind);
+    assert_eq!("fuse.getattr", parent.name);
+    assert_eq!(SpanKind::Server, parent.span_kind);
+    assert_eq!("fuse_mt", parent.instrumentation_scope.name());
+    assert_eq!(parent.span_context.span_id(), child.parent_span_id);
+    assert_eq!(parent.span_context.trace_id(), child.span_context.trace_id());
+    assert!(parent.attributes.contains(&KeyValue::new("fuse.pid", 42)));
+    assert!(parent.attributes.contains(&KeyValue::new("fuse.path", "/a\"b")));
+    assert!(parent.attributes.contains(&KeyValue::new("fuse.errno", i64::from(libc::ENOENT))));
+    assert!(matches!(parent.status, Status::Error { .. }));
+    assert_eq!(Status::Unset, child.status);
+}
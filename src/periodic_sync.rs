@@ -0,0 +1,209 @@
+// PeriodicSync :: syncing buffered data to the filesystem at a regular interval.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::freeze::Freezer;
+use crate::types::{FileHandle, FilesystemMT, FsPath, RequestInfo};
+
+/// Chooses which files a periodic sync applies to, by path.
+pub type SyncFilter = Arc<dyn Fn(&Path) -> bool + Send + Sync>;
+
+/// The `FileHandle` objects which have been written to, for filesystems which don't implement
+/// `FilesystemMT::sync`.
+#[derive(Default)]
+pub struct DirtyHandles {
+    entries: Mutex<HashMap<u64, Arc<DirtyHandle>>>,
+}
+
+struct DirtyHandle {
+    path: FsPath,
+    handle: Arc<dyn FileHandle>,
+    dirty: AtomicBool,
+    /// Set once the handle is released; held while it's being synced, so that it can't be
+    /// released in the middle.
+    released: Mutex<bool>,
+}
+
+impl DirtyHandles {
+    /// Record that the open file with the given key was written to.
+    pub fn written(&self, key: u64, path: &FsPath, handle: &Arc<dyn FileHandle>) {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.entry(key).or_insert_with(|| Arc::new(DirtyHandle {
+            path: path.clone(),
+            handle: handle.clone(),
+            dirty: AtomicBool::new(false),
+            released: Mutex::new(false),
+        }));
+        entry.dirty.store(true, Ordering::Release);
+    }
+
+    /// Stop tracking the open file with the given key, because it's being released. If it's being
+    /// synced, this waits for that to finish.
+    pub fn released(&self, key: u64) {
+        let entry = self.entries.lock().unwrap().remove(&key);
+        if let Some(entry) = entry {
+            *entry.released.lock().unwrap() = true;
+        }
+    }
+
+    /// Call `fsync` on each handle which has been written to since it was last synced, and whose
+    /// path the filter accepts.
+    fn sync(&self, req: RequestInfo, filter: &dyn Fn(&Path) -> bool) {
+        let entries: Vec<_> = self.entries.lock().unwrap().values()
+            .filter(|entry| filter(&entry.path))
+            .cloned()
+            .collect();
+        for entry in entries {
+            let released = entry.released.lock().unwrap();
+            if *released || !entry.dirty.swap(false, Ordering::AcqRel) {
+                continue;
+            }
+            if let Err(e) = entry.handle.fsync(req, false) {
+                warn!("periodic sync of {:?} failed: {}", entry.path, e);
+                entry.dirty.store(true, Ordering::Release);
+            }
+        }
+    }
+
+    fn clear(&self, filter: &dyn Fn(&Path) -> bool) {
+        for entry in self.entries.lock().unwrap().values() {
+            if filter(&entry.path) {
+                entry.dirty.store(false, Ordering::Release);
+            }
+        }
+    }
+
+    #[cfg(test)]
+    fn dirty_count(&self) -> usize {
+        self.entries.lock().unwrap().values()
+            .filter(|entry| entry.dirty.load(Ordering::Acquire))
+            .count()
+    }
+}
+
+/// The settings for periodic syncing, and the thread doing it while mounted.
+pub struct PeriodicSync {
+    interval: Duration,
+    filter: SyncFilter,
+    pub dirty: Arc<DirtyHandles>,
+    thread: Option<(Sender<()>, JoinHandle<()>)>,
+}
+
+impl PeriodicSync {
+    pub fn new(interval: Duration, filter: SyncFilter) -> PeriodicSync {
+        PeriodicSync {
+            interval,
+            filter,
+            dirty: Arc::new(DirtyHandles::default()),
+            thread: None,
+        }
+    }
+
+    /// Start syncing the given filesystem in the background, except while it's frozen.
+    pub fn start<T: FilesystemMT + Send + Sync + 'static>(&mut self, target: Arc<T>,
+                                                          freezer: Freezer) {
+        if self.thread.is_some() {
+            return;
+        }
+        let (stop, stopped) = mpsc::channel();
+        let interval = self.interval;
+        let filter = self.filter.clone();
+        let dirty = self.dirty.clone();
+        let spawned = thread::Builder::new()
+            .name("fuse_mt sync".to_owned())
+            .spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    let _op = freezer.enter();
+                    sync_once(&*target, &dirty, &*filter);
+                }
+            });
+        match spawned {
+            Ok(handle) => self.thread = Some((stop, handle)),
+            Err(e) => error!("unable to start periodic sync thread: {}", e),
+        }
+    }
+
+    /// Stop the background thread, after a last sync.
+    pub fn stop<T: FilesystemMT>(&mut self, target: &T) {
+        if let Some((stop, handle)) = self.thread.take() {
+            drop(stop);
+            if handle.join().is_err() {
+                error!("periodic sync thread panicked");
+            }
+            sync_once(target, &self.dirty, &*self.filter);
+        }
+    }
+}
+
+impl fmt::Debug for PeriodicSync {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PeriodicSync")
+            .field("interval", &self.interval)
+            .field("running", &self.thread.is_some())
+            .finish()
+    }
+}
+
+/// Sync through `FilesystemMT::sync`, or if it isn't implemented, the dirty handles.
+fn sync_once<T: FilesystemMT + ?Sized>(target: &T, dirty: &DirtyHandles,
+                                       filter: &dyn Fn(&Path) -> bool) {
+    match target.sync(filter) {
+        Ok(()) => dirty.clear(filter),
+        Err(libc::ENOSYS) => {
+            // Calls the kernel didn't make have no request of their own.
+            let req = RequestInfo { unique: 0, uid: 0, gid: 0, pid: 0, node: None, umask: None, lock_owner: None };
+            dirty.sync(req, filter);
+        }
+        Err(e) => warn!("periodic sync failed: {}", e),
+    }
+}
+
+#[test]
+fn test_dirty_handles() {
+    use std::sync::atomic::AtomicUsize;
+    use crate::types::ResultEmpty;
+
+    #[derive(Default)]
+    struct Counter(AtomicUsize);
+    impl FileHandle for Counter {
+        fn fsync(&self, _req: RequestInfo, _datasync: bool) -> ResultEmpty {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+    }
+
+    struct NoSync;
+    impl FilesystemMT for NoSync {}
+
+    let dirty = DirtyHandles::default();
+    let a = Arc::new(Counter::default());
+    let b = Arc::new(Counter::default());
+    let (a_dyn, b_dyn): (Arc<dyn FileHandle>, Arc<dyn FileHandle>) = (a.clone(), b.clone());
+    dirty.written(1, &FsPath::from("/keep/a"), &a_dyn);
+    dirty.written(2, &FsPath::from("/skip/b"), &b_dyn);
+    dirty.written(1, &FsPath::from("/keep/a"), &a_dyn);
+
+    let filter = |path: &Path| path.starts_with("/keep");
+    sync_once(&NoSync, &dirty, &filter);
+    assert_eq!(1, a.0.load(Ordering::Relaxed));
+    assert_eq!(0, b.0.load(Ordering::Relaxed));
+    assert_eq!(1, dirty.dirty_count());
+
+    // Clean and released handles aren't synced.
+    sync_once(&NoSync, &dirty, &filter);
+    dirty.released(2);
+    sync_once(&NoSync, &dirty, &|_: &Path| true);
+    assert_eq!(1, a.0.load(Ordering::Relaxed));
+    assert_eq!(0, b.0.load(Ordering::Relaxed));
+    assert_eq!(0, dirty.dirty_count());
+}
@@ -0,0 +1,153 @@
+// Pool Stats :: how busy the worker threads are.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::types::Operation;
+
+/// A handle for reading statistics of the calls FuseMT hands to its worker threads, while it's
+/// mounted. Get one with `FuseMT::pool_stats`; it can be cloned and kept anywhere.
+///
+/// These tell whether more threads would help: if calls wait long to start, and all the threads
+/// are busy, there aren't enough of them for the load. If calls don't wait, but take a long time
+/// anyway, the filesystem is the bottleneck, and more threads would only add to its load. The same
+/// numbers are included in the `STATS_XATTR` attribute.
+///
+/// The calls counted are the I/O calls run on the worker threads (see `Operation`), including
+/// prefetches, across all lanes. Calls made on FuseMT's own thread, when it has no worker threads,
+/// are counted too, and never wait.
+#[derive(Clone, Debug, Default)]
+pub struct PoolStats(Arc<Shared>);
+
+#[derive(Debug, Default)]
+struct Shared {
+    queued: AtomicUsize,
+    busy: AtomicUsize,
+    completed: AtomicU64,
+    waits: Mutex<HashMap<Operation, WaitTime>>,
+}
+
+/// How long calls of one kind waited for a worker thread to start on them.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct WaitTime {
+    /// Number of calls which have started.
+    pub calls: u64,
+    /// Total time they waited.
+    pub total: Duration,
+    /// Longest time any of them waited.
+    pub max: Duration,
+}
+
+impl WaitTime {
+    /// Average time the calls waited.
+    pub fn average(&self) -> Duration {
+        match self.calls {
+            0 => Duration::ZERO,
+            calls => self.total / calls as u32,
+        }
+    }
+}
+
+impl PoolStats {
+    pub(crate) fn new() -> PoolStats {
+        PoolStats::default()
+    }
+
+    /// Number of calls waiting for a worker thread, including those held back by
+    /// `FuseMT::max_calls_per_file`.
+    pub fn queued(&self) -> usize {
+        self.0.queued.load(Ordering::Relaxed)
+    }
+
+    /// Number of worker threads running a call right now.
+    pub fn busy(&self) -> usize {
+        self.0.busy.load(Ordering::Relaxed)
+    }
+
+    /// Number of calls which have finished.
+    pub fn completed(&self) -> u64 {
+        self.0.completed.load(Ordering::Relaxed)
+    }
+
+    /// How long calls of the given kind have waited to start.
+    pub fn wait_time(&self, op: Operation) -> WaitTime {
+        self.0.waits.lock().unwrap().get(&op).copied().unwrap_or_default()
+    }
+
+    /// The wait times of each kind of call which has had any.
+    pub fn wait_times(&self) -> Vec<(Operation, WaitTime)> {
+        self.0.waits.lock().unwrap().iter().map(|(op, wait)| (*op, *wait)).collect()
+    }
+
+    /// Note that a call was handed to the worker threads. Its wait is counted under the given
+    /// operation, if it's one of them.
+    pub(crate) fn queue(&self, op: Option<Operation>) -> Queued {
+        self.0.queued.fetch_add(1, Ordering::Relaxed);
+        Queued { stats: self.clone(), op, since: Instant::now() }
+    }
+}
+
+/// A call waiting for a worker thread.
+#[derive(Debug)]
+pub(crate) struct Queued {
+    stats: PoolStats,
+    op: Option<Operation>,
+    since: Instant,
+}
+
+impl Queued {
+    /// Note that a worker thread started on the call. It's counted as finished when the returned
+    /// guard is dropped.
+    pub fn start(self) -> Running {
+        let shared = &self.stats.0;
+        shared.queued.fetch_sub(1, Ordering::Relaxed);
+        shared.busy.fetch_add(1, Ordering::Relaxed);
+        if let Some(op) = self.op {
+            let waited = self.since.elapsed();
+            let mut waits = shared.waits.lock().unwrap();
+            let wait = waits.entry(op).or_default();
+            wait.calls += 1;
+            wait.total += waited;
+            wait.max = wait.max.max(waited);
+        }
+        Running(self.stats)
+    }
+}
+
+/// A call running on a worker thread.
+#[derive(Debug)]
+pub(crate) struct Running(PoolStats);
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.0.0.busy.fetch_sub(1, Ordering::Relaxed);
+        self.0.0.completed.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[test]
+fn test_pool_stats() {
+    let stats = PoolStats::new();
+    let read = stats.queue(Some(Operation::Read));
+    let other = stats.queue(None);
+    assert_eq!((2, 0, 0), (stats.queued(), stats.busy(), stats.completed()));
+
+    let running = read.start();
+    let _other = other.start();
+    assert_eq!((0, 2, 0), (stats.queued(), stats.busy(), stats.completed()));
+    drop(running);
+    assert_eq!((0, 1, 1), (stats.queued(), stats.busy(), stats.completed()));
+
+    // Only the calls with an operation have their waits counted.
+    let wait = stats.wait_time(Operation::Read);
+    assert_eq!(1, wait.calls);
+    assert_eq!(wait.total, wait.max);
+    assert_eq!(wait.total, wait.average());
+    assert_eq!(WaitTime::default(), stats.wait_time(Operation::Write));
+    assert_eq!(1, stats.wait_times().len());
+}
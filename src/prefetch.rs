@@ -0,0 +1,219 @@
+// Prefetch :: speculative reads ahead of sequential readers.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+use std::collections::HashMap;
+use std::mem;
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+/// Most file handles to track read patterns for at once.
+const MAX_STREAMS: usize = 256;
+
+/// How many sequential reads in a row it takes before we start prefetching.
+const SEQUENTIAL_THRESHOLD: u32 = 2;
+
+/// Longest a read will wait for a prefetch which covers it to finish.
+const MAX_WAIT: Duration = Duration::from_secs(5);
+
+/// Tracks the read pattern of each open file and holds data read ahead of sequential readers.
+///
+/// The read path asks `get` for prefetched data before reading from the filesystem, then tells
+/// `record` what it served. When `record` sees a sequential pattern, it returns a range to read
+/// ahead, which the caller reads from the filesystem and hands to `complete`.
+#[derive(Debug)]
+pub struct Prefetcher {
+    window: u32,
+    streams: Mutex<HashMap<u64, Stream>>,
+    done: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct Stream {
+    ino: u64,
+    /// Where the next read will be if the reader is sequential.
+    next: u64,
+    /// Number of sequential reads in a row.
+    run: u32,
+    buf_offset: u64,
+    buf: Vec<u8>,
+    /// Whether `buf` reaches the end of the file.
+    eof: bool,
+    /// Offset of the prefetch in progress, if any.
+    in_flight: Option<u64>,
+    /// Bumped whenever prefetched data is invalidated, so prefetches started before then are
+    /// discarded when they finish.
+    generation: u64,
+}
+
+/// A read to issue ahead of the reader.
+#[derive(Debug, PartialEq, Eq)]
+pub struct PrefetchRequest {
+    pub offset: u64,
+    pub size: u32,
+    generation: u64,
+}
+
+impl Prefetcher {
+    pub fn new(window: u32) -> Prefetcher {
+        Prefetcher {
+            window,
+            streams: Mutex::new(HashMap::new()),
+            done: Condvar::new(),
+        }
+    }
+
+    /// Get data for a read from the prefetch buffer, if it's there. If a prefetch which covers
+    /// the read is in progress, wait for it.
+    pub fn get(&self, fh: u64, offset: u64, size: u32) -> Option<Vec<u8>> {
+        let mut streams = self.streams.lock().unwrap();
+        loop {
+            let stream = streams.get(&fh)?;
+            match stream.in_flight {
+                Some(start) if offset >= start && offset < start.saturating_add(self.window.into()) => {
+                    let (guard, timeout) = self.done.wait_timeout(streams, MAX_WAIT).unwrap();
+                    streams = guard;
+                    if timeout.timed_out() {
+                        return None;
+                    }
+                }
+                _ => break,
+            }
+        }
+
+        let stream = streams.get(&fh)?;
+        if offset < stream.buf_offset {
+            return None;
+        }
+        let start = offset - stream.buf_offset;
+        let len = stream.buf.len() as u64;
+        let end = start + u64::from(size);
+        if start > len || (end > len && !stream.eof) || (start == len && !stream.eof) {
+            return None;
+        }
+        Some(stream.buf[start as usize .. end.min(len) as usize].to_vec())
+    }
+
+    /// Record a read that was served, and return a prefetch to issue if the reader looks
+    /// sequential.
+    pub fn record(&self, fh: u64, ino: u64, offset: u64, size: u32, served: usize)
+        -> Option<PrefetchRequest>
+    {
+        let mut streams = self.streams.lock().unwrap();
+        if !streams.contains_key(&fh) && streams.len() >= MAX_STREAMS {
+            return None;
+        }
+        let stream = streams.entry(fh).or_insert_with(|| Stream {
+            ino,
+            next: u64::MAX,
+            ..Stream::default()
+        });
+        if offset == stream.next {
+            stream.run = stream.run.saturating_add(1);
+        } else {
+            stream.run = 0;
+        }
+        stream.next = offset + served as u64;
+
+        if stream.run < SEQUENTIAL_THRESHOLD || served < size as usize || stream.in_flight.is_some() {
+            return None;
+        }
+
+        // Don't prefetch again until the reader has used up at least half of what we have.
+        let buffered_end = stream.buf_offset + stream.buf.len() as u64;
+        if stream.eof || buffered_end.saturating_sub(stream.next) > u64::from(self.window / 2) {
+            return None;
+        }
+
+        stream.in_flight = Some(stream.next);
+        Some(PrefetchRequest {
+            offset: stream.next,
+            size: self.window,
+            generation: stream.generation,
+        })
+    }
+
+    /// Store the result of a prefetch.
+    pub fn complete(&self, fh: u64, request: PrefetchRequest, result: Result<Vec<u8>, libc::c_int>) {
+        let mut streams = self.streams.lock().unwrap();
+        if let Some(stream) = streams.get_mut(&fh) {
+            if stream.generation == request.generation {
+                stream.in_flight = None;
+                if let Ok(data) = result {
+                    stream.eof = data.len() < request.size as usize;
+                    stream.buf_offset = request.offset;
+                    stream.buf = data;
+                }
+            }
+        }
+        self.done.notify_all();
+    }
+
+    /// Discard prefetched data for the given inode, because it was written to.
+    pub fn invalidate_inode(&self, ino: u64) {
+        let mut streams = self.streams.lock().unwrap();
+        for stream in streams.values_mut().filter(|s| s.ino == ino) {
+            stream.buf = vec![];
+            stream.eof = false;
+            stream.in_flight = None;
+            stream.generation += 1;
+        }
+        self.done.notify_all();
+    }
+
+    /// Get the number of file handles whose read patterns are being tracked.
+    pub fn len(&self) -> usize {
+        self.streams.lock().unwrap().len()
+    }
+
+    /// Get the approximate number of bytes of memory used by prefetched data.
+    pub fn memory_usage(&self) -> usize {
+        let streams = self.streams.lock().unwrap();
+        streams.capacity() * mem::size_of::<(u64, Stream)>()
+            + streams.values().map(|stream| stream.buf.capacity()).sum::<usize>()
+    }
+
+    /// Discard all prefetched data, to free up memory. Read patterns are still tracked.
+    pub fn evict(&self) {
+        let mut streams = self.streams.lock().unwrap();
+        for stream in streams.values_mut() {
+            stream.buf = vec![];
+            stream.eof = false;
+        }
+    }
+
+    /// Stop tracking the given file handle, because it was closed.
+    pub fn remove(&self, fh: u64) {
+        self.streams.lock().unwrap().remove(&fh);
+        self.done.notify_all();
+    }
+}
+
+#[test]
+fn test_prefetch() {
+    let p = Prefetcher::new(100);
+    assert_eq!(None, p.get(1, 0, 10));
+    assert_eq!(None, p.record(1, 5, 0, 10, 10));
+    assert_eq!(None, p.record(1, 5, 10, 10, 10));
+    let req = p.record(1, 5, 20, 10, 10).unwrap();
+    assert_eq!((30, 100), (req.offset, req.size));
+    p.complete(1, req, Ok((30 .. 80).collect()));
+
+    // Served from the buffer, including a short read at EOF.
+    assert_eq!(Some((30 .. 40).collect()), p.get(1, 30, 10));
+    assert_eq!(Some((75 .. 80).collect()), p.get(1, 75, 10));
+    assert_eq!(Some(vec![]), p.get(1, 80, 10));
+
+    // Writes discard the buffer.
+    p.invalidate_inode(5);
+    assert_eq!(None, p.get(1, 30, 10));
+
+    // So does running short on memory.
+    let req = p.record(1, 5, 30, 10, 10).unwrap();
+    p.complete(1, req, Ok(vec![0; 100]));
+    let full = p.memory_usage();
+    p.evict();
+    assert_eq!(full - 100, p.memory_usage());
+    assert_eq!(None, p.get(1, 40, 10));
+}
@@ -0,0 +1,168 @@
+// Readiness :: finding out when a mounted filesystem can serve requests.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+use std::fmt;
+use std::io;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+type Callback = Box<dyn FnOnce(io::Result<()>) + Send>;
+
+/// A handle for finding out when FuseMT is ready to serve requests: when the kernel has sent its
+/// `INIT` request and the filesystem's `init` has succeeded. Mounting returns before then, so
+/// programs which start other work on the filesystem once it's mounted should wait on this
+/// instead of polling the mountpoint.
+///
+/// Get one from `FuseMT::readiness` before mounting; it can be cloned and used from any thread.
+/// Requests made right after it's signalled may still wait briefly for the kernel to finish
+/// processing FuseMT's reply to `INIT`, but they won't fail.
+///
+/// If `init` fails, waiters get its error. If the filesystem is unmounted, or `FuseMT` is dropped
+/// without having been mounted, before it became ready, they get `ENOTCONN`.
+#[derive(Clone, Debug, Default)]
+pub struct Readiness {
+    inner: Arc<Shared>,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+    /// `None` until `init` has been called, then its result.
+    result: Option<Result<(), libc::c_int>>,
+    callbacks: Vec<Callback>,
+}
+
+impl fmt::Debug for State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("State")
+            .field("result", &self.result)
+            .field("callbacks", &self.callbacks.len())
+            .finish()
+    }
+}
+
+impl Readiness {
+    /// Whether the filesystem has become ready. This stays true once it has, even after it's
+    /// unmounted.
+    pub fn is_ready(&self) -> bool {
+        self.lock().result == Some(Ok(()))
+    }
+
+    /// Wait until the filesystem is ready, or fails to become ready.
+    pub fn wait(&self) -> io::Result<()> {
+        let mut state = self.lock();
+        loop {
+            if let Some(result) = state.result {
+                return to_io(result);
+            }
+            state = self.inner.changed.wait(state).unwrap();
+        }
+    }
+
+    /// Like `wait`, but fail with `TimedOut` if the filesystem isn't ready within the timeout.
+    pub fn wait_timeout(&self, timeout: Duration) -> io::Result<()> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.lock();
+        loop {
+            if let Some(result) = state.result {
+                return to_io(result);
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining == Duration::ZERO {
+                return Err(io::Error::new(io::ErrorKind::TimedOut,
+                    "filesystem wasn't ready in time"));
+            }
+            state = self.inner.changed.wait_timeout(state, remaining).unwrap().0;
+        }
+    }
+
+    /// Call the given function once the filesystem is ready, or fails to become ready, with the
+    /// same result `wait` would return. If that's already happened, it's called right away.
+    ///
+    /// Otherwise it's called from the FUSE session's thread while it handles `INIT`, so it
+    /// should only start work elsewhere, not do anything slow or use the filesystem itself.
+    pub fn on_ready(&self, callback: impl FnOnce(io::Result<()>) + Send + 'static) {
+        let mut state = self.lock();
+        match state.result {
+            Some(result) => {
+                drop(state);
+                callback(to_io(result));
+            }
+            None => state.callbacks.push(Box::new(callback)),
+        }
+    }
+
+    /// Record the result of `init`, if it hasn't been recorded yet, and tell everyone waiting.
+    pub(crate) fn set(&self, result: Result<(), libc::c_int>) {
+        let callbacks = {
+            let mut state = self.lock();
+            if state.result.is_some() {
+                return;
+            }
+            state.result = Some(result);
+            std::mem::take(&mut state.callbacks)
+        };
+        self.inner.changed.notify_all();
+        for callback in callbacks {
+            callback(to_io(result));
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.inner.state.lock().unwrap()
+    }
+}
+
+fn to_io(result: Result<(), libc::c_int>) -> io::Result<()> {
+    result.map_err(io::Error::from_raw_os_error)
+}
+
+/// FuseMT's side of a `Readiness`, which tells waiters the filesystem is gone if it's dropped
+/// without having become ready.
+#[derive(Debug, Default)]
+pub(crate) struct ReadyNotifier(pub Readiness);
+
+impl Drop for ReadyNotifier {
+    fn drop(&mut self) {
+        self.0.set(Err(libc::ENOTCONN));
+    }
+}
+
+#[test]
+fn test_readiness() {
+    use std::sync::mpsc;
+    use std::thread;
+
+    let notifier = ReadyNotifier::default();
+    let readiness = notifier.0.clone();
+    assert_eq!(io::ErrorKind::TimedOut,
+        readiness.wait_timeout(Duration::from_millis(10)).unwrap_err().kind());
+
+    let (tx, rx) = mpsc::channel();
+    readiness.on_ready(move |result| tx.send(result.is_ok()).unwrap());
+    let r = readiness.clone();
+    let waiting = thread::spawn(move || r.wait());
+    notifier.0.set(Ok(()));
+    waiting.join().unwrap().unwrap();
+    assert!(rx.recv().unwrap());
+    assert!(readiness.is_ready());
+
+    // Later results don't change it.
+    drop(notifier);
+    assert!(readiness.is_ready());
+    readiness.on_ready(|result| result.unwrap());
+
+    // Failures are passed on.
+    let notifier = ReadyNotifier::default();
+    let readiness = notifier.0.clone();
+    drop(notifier);
+    assert_eq!(Some(libc::ENOTCONN), readiness.wait().unwrap_err().raw_os_error());
+    assert!(!readiness.is_ready());
+}
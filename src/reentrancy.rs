@@ -0,0 +1,166 @@
+// Reentrancy :: catching calls a filesystem makes into its own mount.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::types::ResultEmpty;
+
+/// A handle for catching a filesystem touching paths under its own mountpoint, which would
+/// otherwise deadlock the mount: the call can't finish until FuseMT answers it, and FuseMT is busy
+/// with the call that made it.
+///
+/// Get one from `FuseMT::reentrancy`. Detection is off unless it was turned on with
+/// `FuseMT::detect_reentrancy`, in which case:
+///
+/// * Requests the kernel sends on behalf of FuseMT's own threads fail with `EDEADLK`, instead of
+///   waiting behind the call that made them. This needs the kernel to tell FuseMT which thread
+///   made a request, which only Linux does.
+/// * `check` fails with `EDEADLK` for paths under the mountpoint, for filesystems to call before
+///   touching paths they were given by someone else, like symlink targets or paths in
+///   configuration. This catches calls from the thread reading requests from the kernel too,
+///   which would otherwise hang before FuseMT ever saw the request.
+///
+/// Either way, an error is logged, since this is a bug in the filesystem.
+#[derive(Clone, Debug, Default)]
+pub struct Reentrancy {
+    inner: Arc<Shared>,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    mountpoint: Mutex<Option<PathBuf>>,
+    threads: Mutex<HashSet<u32>>,
+}
+
+/// The FuseMT thread the current thread is registered as, which is unregistered when the thread
+/// exits so its ID can't be mistaken for another thread which is given the same one later.
+struct Registration {
+    shared: Arc<Shared>,
+    tid: u32,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        self.shared.threads.lock().unwrap().remove(&self.tid);
+    }
+}
+
+thread_local! {
+    static REGISTRATION: RefCell<Option<Registration>> = const { RefCell::new(None) };
+}
+
+impl Reentrancy {
+    pub(crate) fn new() -> Reentrancy {
+        Reentrancy::default()
+    }
+
+    /// Turn detection on, for a filesystem mounted at the given path.
+    pub(crate) fn enable(&self, mountpoint: PathBuf) {
+        *self.inner.mountpoint.lock().unwrap() = Some(absolute(&mountpoint));
+    }
+
+    /// Whether detection is on.
+    pub fn is_enabled(&self) -> bool {
+        self.inner.mountpoint.lock().unwrap().is_some()
+    }
+
+    /// Check that the given path isn't under the filesystem's own mountpoint. A relative path is
+    /// taken relative to the current directory.
+    ///
+    /// Returns `EDEADLK` if it is, and detection is on.
+    pub fn check(&self, path: &Path) -> ResultEmpty {
+        let mountpoint = self.inner.mountpoint.lock().unwrap();
+        match *mountpoint {
+            Some(ref mountpoint) if absolute(path).starts_with(mountpoint) => {
+                error!("filesystem tried to access {:?}, in its own mount at {:?}; failing it with \
+                    EDEADLK instead of deadlocking", path, mountpoint);
+                Err(libc::EDEADLK)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Note that the current thread is one of FuseMT's, if detection is on.
+    pub(crate) fn register_thread(&self) {
+        let tid = match current_tid() {
+            Some(tid) if self.is_enabled() => tid,
+            _ => return,
+        };
+        REGISTRATION.with(|registration| {
+            let mut registration = registration.borrow_mut();
+            if registration.is_none() {
+                self.inner.threads.lock().unwrap().insert(tid);
+                *registration = Some(Registration { shared: self.inner.clone(), tid });
+            }
+        });
+    }
+
+    /// Check that a request didn't come from one of FuseMT's own threads, given the ID of the
+    /// thread the kernel says made it.
+    ///
+    /// Returns `EDEADLK` if it did.
+    pub(crate) fn check_caller(&self, pid: u32) -> ResultEmpty {
+        if self.inner.threads.lock().unwrap().contains(&pid) {
+            error!("filesystem made a call into its own mount from thread {}; failing it with \
+                EDEADLK instead of deadlocking", pid);
+            return Err(libc::EDEADLK);
+        }
+        Ok(())
+    }
+}
+
+/// Make a path absolute without touching the filesystem, which could be the one being checked
+/// for.
+fn absolute(path: &Path) -> PathBuf {
+    if path.is_absolute() {
+        return path.to_owned();
+    }
+    match env::current_dir() {
+        Ok(dir) => dir.join(path),
+        Err(_) => path.to_owned(),
+    }
+}
+
+/// Get the ID the kernel uses for the current thread in the requests it makes, on platforms where
+/// it gives the thread and not just the process.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn current_tid() -> Option<u32> {
+    Some(unsafe { libc::syscall(libc::SYS_gettid) } as u32)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn current_tid() -> Option<u32> {
+    None
+}
+
+#[test]
+fn test_reentrancy() {
+    let reentrancy = Reentrancy::new();
+    assert_eq!(Ok(()), reentrancy.check(Path::new("/mnt/fs/a")));
+    reentrancy.register_thread();
+    assert!(reentrancy.inner.threads.lock().unwrap().is_empty());
+
+    reentrancy.enable(PathBuf::from("/mnt/fs"));
+    assert_eq!(Err(libc::EDEADLK), reentrancy.check(Path::new("/mnt/fs")));
+    assert_eq!(Err(libc::EDEADLK), reentrancy.check(Path::new("/mnt/fs/a/b")));
+    assert_eq!(Ok(()), reentrancy.check(Path::new("/mnt/fsx")));
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        let r = reentrancy.clone();
+        let tid = std::thread::spawn(move || {
+            r.register_thread();
+            let tid = current_tid().unwrap();
+            assert_eq!(Err(libc::EDEADLK), r.check_caller(tid));
+            tid
+        }).join().unwrap();
+        // The thread is forgotten once it exits.
+        assert_eq!(Ok(()), reentrancy.check_caller(tid));
+    }
+}
@@ -0,0 +1,171 @@
+// Responder :: answering calls later, from any thread.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+use std::fmt;
+use std::mem;
+use std::sync::{mpsc, Arc, Mutex};
+
+type Callback<T> = Box<dyn FnOnce(Result<T, libc::c_int>) + Send>;
+
+/// A handle for sending the result of a call after the `FilesystemMT` method has returned.
+///
+/// The `_deferred` methods of `FilesystemMT` take one of these instead of returning their result,
+/// so that filesystems driven by an event loop of their own can start the work, return, and send
+/// the result from the event loop when it's done, without holding up a FuseMT thread meanwhile.
+/// It can be moved to any thread.
+///
+/// If a responder is dropped without a result being sent, the call fails with `EIO`.
+pub struct Responder<T> {
+    slot: Option<Arc<Mutex<Slot<T>>>>,
+}
+
+enum Slot<T> {
+    /// Nothing has been sent, and nobody is waiting for it yet.
+    Empty,
+    /// A result was sent before anybody was waiting for it.
+    Sent(Result<T, libc::c_int>),
+    /// Waiting for a result, to be passed to this.
+    Waiting(Callback<T>),
+    /// The result has been passed on.
+    Done,
+}
+
+impl<T: Send + 'static> Responder<T> {
+    /// Make a responder which passes the result it's sent to the given function, on whichever
+    /// thread sends it. This is for wrappers which need to do something with their inner
+    /// filesystem's result before sending on their own.
+    pub fn new(f: impl FnOnce(Result<T, libc::c_int>) + Send + 'static) -> Responder<T> {
+        Responder {
+            slot: Some(Arc::new(Mutex::new(Slot::Waiting(Box::new(f))))),
+        }
+    }
+
+    /// Make a responder, and the means of getting its result.
+    pub(crate) fn pending() -> (Responder<T>, Pending<T>) {
+        let slot = Arc::new(Mutex::new(Slot::Empty));
+        (Responder { slot: Some(slot.clone()) }, Pending(slot))
+    }
+
+    /// Make a call which sends its result through a responder, and wait for the result. This is
+    /// for wrappers which have to look at their inner filesystem's result before they can go on.
+    pub(crate) fn wait(call: impl FnOnce(Responder<T>)) -> Result<T, libc::c_int> {
+        let (tx, rx) = mpsc::channel();
+        call(Responder::new(move |result| tx.send(result).unwrap()));
+        rx.recv().unwrap()
+    }
+}
+
+impl<T> Responder<T> {
+    /// Send the result of the call.
+    pub fn send(mut self, result: Result<T, libc::c_int>) {
+        self.complete(result);
+    }
+
+    fn complete(&mut self, result: Result<T, libc::c_int>) {
+        let slot = match self.slot.take() {
+            Some(slot) => slot,
+            None => return,
+        };
+        let mut slot = slot.lock().unwrap();
+        match mem::replace(&mut *slot, Slot::Done) {
+            Slot::Empty => *slot = Slot::Sent(result),
+            Slot::Waiting(callback) => {
+                drop(slot);
+                callback(result);
+            }
+            Slot::Sent(_) | Slot::Done => unreachable!("result sent twice"),
+        }
+    }
+}
+
+impl<T> fmt::Debug for Responder<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Responder")
+            .field("sent", &self.slot.is_none())
+            .finish()
+    }
+}
+
+impl<T> Drop for Responder<T> {
+    fn drop(&mut self) {
+        self.complete(Err(libc::EIO));
+    }
+}
+
+/// FuseMT's side of a responder made by `Responder::pending`.
+pub(crate) struct Pending<T>(Arc<Mutex<Slot<T>>>);
+
+impl<T> Pending<T> {
+    /// Take the result, if it was sent already.
+    ///
+    /// This lets FuseMT carry on with results sent right away, as they are by the default
+    /// `_deferred` methods, the same way it would with those of the plain methods.
+    pub fn take(&self) -> Option<Result<T, libc::c_int>> {
+        let mut slot = self.0.lock().unwrap();
+        match mem::replace(&mut *slot, Slot::Empty) {
+            Slot::Sent(result) => {
+                *slot = Slot::Done;
+                Some(result)
+            }
+            Slot::Empty => None,
+            Slot::Waiting(_) | Slot::Done => unreachable!("pending result taken twice"),
+        }
+    }
+
+    /// Have the result passed to the given function when it's sent, or right away if it was sent
+    /// since it was last checked for.
+    pub fn later(self, f: impl FnOnce(Result<T, libc::c_int>) + Send + 'static) {
+        let mut slot = self.0.lock().unwrap();
+        match mem::replace(&mut *slot, Slot::Done) {
+            Slot::Sent(result) => {
+                drop(slot);
+                f(result);
+            }
+            Slot::Empty => *slot = Slot::Waiting(Box::new(f)),
+            Slot::Waiting(_) | Slot::Done => unreachable!("pending result taken twice"),
+        }
+    }
+}
+
+#[test]
+fn test_responder() {
+    use std::thread;
+
+    // Results sent right away are taken right away.
+    let (responder, pending) = Responder::pending();
+    assert_eq!(None, pending.take());
+    responder.send(Ok(1));
+    assert_eq!(Some(Ok(1)), pending.take());
+
+    // Results sent later, from another thread, are passed on then.
+    let (responder, pending) = Responder::<u32>::pending();
+    assert_eq!(None, pending.take());
+    let (tx, rx) = mpsc::channel();
+    pending.later(move |result| tx.send(result).unwrap());
+    thread::spawn(move || responder.send(Err(libc::ENOENT))).join().unwrap();
+    assert_eq!(Err(libc::ENOENT), rx.recv().unwrap());
+
+    // Including ones sent between checking for them and waiting for them.
+    let (responder, pending) = Responder::pending();
+    assert_eq!(None, pending.take());
+    responder.send(Ok(2));
+    let (tx, rx) = mpsc::channel();
+    pending.later(move |result| tx.send(result).unwrap());
+    assert_eq!(Ok(2), rx.recv().unwrap());
+
+    // Dropping a responder without sending anything fails the call.
+    let (tx, rx) = mpsc::channel();
+    drop(Responder::<()>::new(move |result| tx.send(result).unwrap()));
+    assert_eq!(Err(libc::EIO), rx.recv().unwrap());
+    let (responder, pending) = Responder::<()>::pending();
+    drop(responder);
+    assert_eq!(Some(Err(libc::EIO)), pending.take());
+
+    // Waiting for a result sent from another thread.
+    let result = Responder::wait(|responder| {
+        thread::spawn(move || responder.send(Ok(3)));
+    });
+    assert_eq!(Ok(3), result);
+}
@@ -0,0 +1,159 @@
+// Revalidation :: re-checking the paths of queued calls against renames and deletions.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+use std::collections::{BTreeMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::types::FsPath;
+
+/// A log of the renames and deletions made since the oldest call still waiting on a worker thread
+/// was queued.
+///
+/// Calls on the worker threads carry the path their file had when they were queued. If the file is
+/// renamed or deleted before the call runs, that path goes stale: it may name nothing, or a
+/// different file. Each queued call holds a `PathTicket`, which replays the changes made since it
+/// was issued to find the file's path now.
+#[derive(Debug, Default)]
+pub struct PathLog {
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Number of changes made so far.
+    epoch: u64,
+    /// Changes that tickets may still need, oldest first: the epoch after each one, the path
+    /// changed, and where it went, if it was renamed.
+    changes: VecDeque<(u64, PathBuf, Option<PathBuf>)>,
+    /// Number of outstanding tickets issued at each epoch.
+    tickets: BTreeMap<u64, usize>,
+}
+
+impl State {
+    /// Forget the changes which every outstanding ticket was issued after.
+    fn prune(&mut self) {
+        let oldest = self.tickets.keys().next().copied().unwrap_or(self.epoch);
+        while self.changes.front().is_some_and(|(epoch, _, _)| *epoch <= oldest) {
+            self.changes.pop_front();
+        }
+    }
+}
+
+impl PathLog {
+    pub fn new() -> PathLog {
+        PathLog::default()
+    }
+
+    /// Record that the file or directory at `from` was renamed to `to`, or removed if `to` is
+    /// `None`. This applies to everything under it too.
+    pub fn changed(&self, from: &Path, to: Option<&Path>) {
+        let mut state = self.state.lock().unwrap();
+        state.epoch += 1;
+        // If no calls are queued, there's nothing that needs to check against this change.
+        if !state.tickets.is_empty() {
+            let epoch = state.epoch;
+            state.changes.push_back((epoch, from.to_owned(), to.map(Path::to_owned)));
+        }
+    }
+
+    /// Issue a ticket for a call on the given path, to be revalidated when the call runs.
+    pub fn ticket(self: &Arc<Self>, path: FsPath) -> PathTicket {
+        let mut state = self.state.lock().unwrap();
+        let epoch = state.epoch;
+        *state.tickets.entry(epoch).or_insert(0) += 1;
+        PathTicket {
+            path,
+            log: Some((Arc::clone(self), epoch)),
+        }
+    }
+}
+
+/// The path of a queued call, and when it was queued.
+#[derive(Debug)]
+pub struct PathTicket {
+    path: FsPath,
+    log: Option<(Arc<PathLog>, u64)>,
+}
+
+impl PathTicket {
+    /// A ticket which always revalidates to the path it was given.
+    pub fn unchecked(path: FsPath) -> PathTicket {
+        PathTicket { path, log: None }
+    }
+
+    /// Get the path the file has now, following any renames since the ticket was issued. Fails
+    /// with `ESTALE` if the file was removed, or replaced by another one.
+    pub fn revalidate(&self) -> Result<FsPath, libc::c_int> {
+        let (log, issued) = match self.log {
+            Some((ref log, issued)) => (log, issued),
+            None => return Ok(self.path.clone()),
+        };
+        let state = log.state.lock().unwrap();
+        let mut path = self.path.clone();
+        for (_, from, to) in state.changes.iter().filter(|(epoch, _, _)| *epoch > issued) {
+            if let Ok(rest) = path.strip_prefix(from) {
+                match to {
+                    // (Joining an empty path would add a trailing slash.)
+                    Some(to) if rest.as_os_str().is_empty() => path = FsPath::from(to.as_path()),
+                    Some(to) => path = FsPath::from(to.join(rest)),
+                    None => return Err(libc::ESTALE),
+                }
+            }
+        }
+        if path != self.path {
+            debug!("{:?} was renamed to {:?} while the call was queued", self.path, path);
+        }
+        Ok(path)
+    }
+}
+
+impl Drop for PathTicket {
+    fn drop(&mut self) {
+        if let Some((ref log, issued)) = self.log {
+            let mut state = log.state.lock().unwrap();
+            if let Some(count) = state.tickets.get_mut(&issued) {
+                *count -= 1;
+                if *count == 0 {
+                    state.tickets.remove(&issued);
+                }
+            }
+            state.prune();
+        }
+    }
+}
+
+#[test]
+fn test_path_log() {
+    let log = Arc::new(PathLog::new());
+    let path = |p: &str| FsPath::from(p);
+
+    // Changes made while there are no tickets out aren't kept.
+    log.changed(Path::new("/a"), None);
+    assert!(log.state.lock().unwrap().changes.is_empty());
+
+    let file = log.ticket(path("/dir/file"));
+    let other = log.ticket(path("/other"));
+    let replaced = log.ticket(path("/new/file"));
+
+    // Replacing /new with /dir moves the file and makes the old /new/file stale.
+    log.changed(Path::new("/new"), None);
+    log.changed(Path::new("/dir"), Some(Path::new("/new")));
+    assert_eq!(path("/new/file"), file.revalidate().unwrap());
+    assert_eq!(path("/other"), other.revalidate().unwrap());
+    assert_eq!(Err(libc::ESTALE), replaced.revalidate());
+
+    // A ticket issued after the changes doesn't see them.
+    let later = log.ticket(path("/new/file"));
+    assert_eq!(path("/new/file"), later.revalidate().unwrap());
+
+    // The log is emptied once the tickets which need it are gone.
+    drop((file, other, replaced));
+    assert!(log.state.lock().unwrap().changes.is_empty());
+    drop(later);
+    assert!(log.state.lock().unwrap().tickets.is_empty());
+
+    assert_eq!(path("/x"), PathTicket::unchecked(path("/x")).revalidate().unwrap());
+}
@@ -0,0 +1,205 @@
+// Session :: a mounted filesystem that can be driven from an event loop.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+//! Integrating a mounted filesystem into an existing event loop.
+//!
+//! `mount` blocks until the filesystem is unmounted, and `spawn_mount` hands back a
+//! `BackgroundSession` which can only be joined, blocking. A program built around its own event
+//! loop (epoll, mio, tokio, ...) instead wants to find out that the filesystem went away the same
+//! way it finds out about everything else: by a file descriptor becoming readable.
+//!
+//! `MountedSession` provides that. Its file descriptor (from `AsRawFd`) becomes readable once the
+//! session loop has finished, whether because the filesystem was unmounted from outside, or by
+//! `unmount`, or because of an error; `try_wait` then returns how it ended without blocking. With
+//! tokio, for example:
+//!
+//! ```ignore
+//! let session = MountedSession::spawn(FuseMT::new(fs, 4), &mountpoint, &options)?;
+//! let session = tokio::io::unix::AsyncFd::new(session)?;
+//! loop {
+//!     tokio::select! {
+//!         _ = session.readable() => break,
+//!         // ... other events ...
+//!     }
+//! }
+//! session.into_inner().join()?;
+//! ```
+//!
+//! Requests from the kernel are still read on a thread of the session's own: `fuser` doesn't
+//! expose the FUSE device, so the read-dispatch loop can't be run a step at a time. This costs
+//! little, because FuseMT only decodes each request on that thread before handing it off to its
+//! worker threads.
+
+use std::fs::File;
+use std::io;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::thread::{self, JoinHandle};
+
+use fuser::{MountOption, SessionUnmounter};
+
+/// A filesystem mounted with its session loop on a background thread, which signals through a
+/// file descriptor when the loop finishes.
+///
+/// By default, dropping it unmounts the filesystem and waits for the session loop to finish. With
+/// `set_unmount_on_drop(false)`, or by calling `detach`, the filesystem stays mounted instead, and
+/// the session loop keeps serving it until it's unmounted from outside, or the process exits.
+#[derive(Debug)]
+pub struct MountedSession {
+    mountpoint: PathBuf,
+    unmounter: SessionUnmounter,
+    unmount_on_drop: bool,
+    /// Read end of a pipe whose write end is closed when the session loop finishes.
+    done: File,
+    thread: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl MountedSession {
+    /// Mount the given filesystem to the given mountpoint and start handling requests for it on a
+    /// background thread. Fails with `InvalidInput` if any of the options aren't supported on this
+    /// platform.
+    pub fn spawn<FS: fuser::Filesystem + Send + 'static, P: AsRef<Path>>(
+        fs: FS,
+        mountpoint: P,
+        options: &[MountOption],
+    ) -> io::Result<MountedSession> {
+        crate::validate_mount_options(options)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let mountpoint = mountpoint.as_ref().to_owned();
+        let (done, done_write) = done_pipe()?;
+        let mut session = fuser::Session::new(fs, &mountpoint, options)?;
+        let unmounter = session.unmount_callable();
+        let thread = thread::Builder::new()
+            .name("fuse_mt session".to_owned())
+            .spawn(move || {
+                let result = session.run();
+                // Dropping the session unmounts it (if that hasn't happened already) and calls
+                // `destroy`, which should be done by the time anyone is told the loop is over.
+                drop(session);
+                drop(done_write);
+                result
+            })?;
+        Ok(MountedSession {
+            mountpoint,
+            unmounter,
+            unmount_on_drop: true,
+            done,
+            thread: Some(thread),
+        })
+    }
+
+    /// The path the filesystem is mounted on.
+    pub fn mountpoint(&self) -> &Path {
+        &self.mountpoint
+    }
+
+    /// Check, without blocking, whether the session loop has finished.
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_none() || readable(self.done.as_raw_fd())
+    }
+
+    /// If the session loop has finished, return how it ended, without blocking. Returns `None`
+    /// while the filesystem is still mounted. Once the result has been returned, later calls
+    /// return `Some(Ok(()))`.
+    pub fn try_wait(&mut self) -> Option<io::Result<()>> {
+        if !self.is_finished() {
+            return None;
+        }
+        Some(match self.thread.take() {
+            Some(thread) => join(thread),
+            None => Ok(()),
+        })
+    }
+
+    /// Unmount the filesystem. This doesn't wait for the session loop to finish; the file
+    /// descriptor becomes readable when it has.
+    pub fn unmount(&mut self) -> io::Result<()> {
+        self.unmounter.unmount()
+    }
+
+    /// Choose whether dropping the session unmounts the filesystem (the default), or leaves it
+    /// mounted, like `detach`.
+    pub fn set_unmount_on_drop(&mut self, unmount: bool) {
+        self.unmount_on_drop = unmount;
+    }
+
+    /// Let go of the session, leaving the filesystem mounted and the session loop running on its
+    /// thread. There's no way to find out how it ends after this.
+    ///
+    /// The filesystem is only served while this process lives: if it exits while still mounted,
+    /// the mount is left behind, and calls on it fail with `ENOTCONN` until it's unmounted.
+    pub fn detach(mut self) {
+        debug!("detaching from session for {:?}", self.mountpoint);
+        self.thread = None;
+    }
+
+    /// Wait for the session loop to finish, and return how it ended. This doesn't unmount the
+    /// filesystem.
+    pub fn join(mut self) -> io::Result<()> {
+        match self.thread.take() {
+            Some(thread) => join(thread),
+            None => Ok(()),
+        }
+    }
+}
+
+impl AsRawFd for MountedSession {
+    /// A file descriptor which becomes readable when the session loop finishes. Don't read from
+    /// it; just wait for it to become readable.
+    fn as_raw_fd(&self) -> RawFd {
+        self.done.as_raw_fd()
+    }
+}
+
+impl Drop for MountedSession {
+    fn drop(&mut self) {
+        if !self.unmount_on_drop {
+            return;
+        }
+        if let Some(thread) = self.thread.take() {
+            if let Err(e) = self.unmounter.unmount() {
+                error!("failed to unmount {:?}: {}", self.mountpoint, e);
+            }
+            if let Err(e) = join(thread) {
+                error!("session loop for {:?} failed: {}", self.mountpoint, e);
+            }
+        }
+    }
+}
+
+fn join(thread: JoinHandle<io::Result<()>>) -> io::Result<()> {
+    thread.join().unwrap_or_else(|_| Err(io::Error::other("session loop panicked")))
+}
+
+/// Make a pipe with both ends close-on-exec, and the read end non-blocking.
+fn done_pipe() -> io::Result<(File, File)> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let (read_end, write_end) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+    for fd in &fds {
+        if unsafe { libc::fcntl(*fd, libc::F_SETFD, libc::FD_CLOEXEC) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    if unsafe { libc::fcntl(fds[0], libc::F_SETFL, libc::O_NONBLOCK) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok((read_end, write_end))
+}
+
+fn readable(fd: RawFd) -> bool {
+    let mut pollfd = libc::pollfd { fd, events: libc::POLLIN, revents: 0 };
+    unsafe { libc::poll(&mut pollfd, 1, 0) > 0 }
+}
+
+#[test]
+fn test_done_pipe() {
+    let (read_end, write_end) = done_pipe().unwrap();
+    assert!(!readable(read_end.as_raw_fd()));
+    drop(write_end);
+    assert!(readable(read_end.as_raw_fd()));
+}
@@ -0,0 +1,51 @@
+// Silly Rename :: keeping unlinked files around until they're closed.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+use std::collections::HashSet;
+use std::ffi::OsString;
+
+/// Names inodes which are unlinked while open (as tracked by `OpenFiles`) so that they can be
+/// renamed to a hidden name instead, and only deleted when the last handle to them is closed.
+/// This is how NFS clients deal with the same problem.
+#[derive(Debug, Default)]
+pub struct SillyRename {
+    /// Inodes which have been renamed to a hidden name.
+    hidden: HashSet<u64>,
+    counter: u32,
+}
+
+impl SillyRename {
+    pub fn new() -> SillyRename {
+        SillyRename::default()
+    }
+
+    /// Note that the last handle to the given inode was closed. Returns true if the inode had
+    /// been hidden, meaning it should be deleted now.
+    pub fn closed(&mut self, ino: u64) -> bool {
+        self.hidden.remove(&ino)
+    }
+
+    /// Get a new hidden name for the given inode, which should be renamed to it instead of being
+    /// deleted.
+    pub fn hide(&mut self, ino: u64) -> OsString {
+        self.counter = self.counter.wrapping_add(1);
+        OsString::from(format!(".fuse_hidden{:08x}{:08x}", ino, self.counter))
+    }
+
+    /// Note that the rename to the hidden name succeeded.
+    pub fn hidden(&mut self, ino: u64) {
+        self.hidden.insert(ino);
+    }
+}
+
+#[test]
+fn test_silly_rename() {
+    let mut s = SillyRename::new();
+    assert_ne!(s.hide(5), s.hide(5));
+    s.hidden(5);
+    assert!(s.closed(5));
+    assert!(!s.closed(5));
+    assert!(!s.closed(6));
+}
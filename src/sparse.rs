@@ -0,0 +1,228 @@
+// Sparse :: finding and keeping the holes in sparse files.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+//! Helpers for sparse files: files with holes, ranges which read as zeros but take up no space.
+//!
+//! Copying a file by reading it and writing what was read fills in its holes, so a copy of a
+//! mostly-empty disk image can take up many times the space of the original. These find the parts
+//! of a file which have data, so that only those get copied, for filesystems which copy files
+//! around themselves, such as when copying a file up from a read-only layer before changing it.
+//!
+//! Wrappers in `fuse_mt::wrappers` which copy files leave blocks of zeros out of the copies, so
+//! they end up as holes when the filesystem underneath supports them.
+
+use std::fs::File;
+use std::io;
+use std::ops::Range;
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::AsRawFd;
+
+/// Size of the pieces files are copied in.
+const COPY_SIZE: usize = 128 * 1024;
+
+/// Whether the data is all zeros, and so can be left as a hole.
+pub fn is_zeros(data: &[u8]) -> bool {
+    data.iter().all(|&b| b == 0)
+}
+
+/// Find the ranges of a file which have data in them, with `SEEK_DATA` and `SEEK_HOLE`, in order.
+/// Everything else in the file is a hole.
+///
+/// Filesystems which don't support finding holes report the whole file as data. Holes can also be
+/// reported as data by filesystems which only track them in large blocks, so the ranges can still
+/// contain zeros. This moves the file's offset.
+pub fn data_ranges(file: &File) -> io::Result<Vec<Range<u64>>> {
+    let len = file.metadata()?.len();
+    let mut ranges = vec![];
+    let mut offset = 0;
+    while offset < len {
+        let start = match seek(file, offset, libc::SEEK_DATA) {
+            Ok(start) => start,
+            // No more data after the offset.
+            Err(ref e) if e.raw_os_error() == Some(libc::ENXIO) => break,
+            Err(ref e) if e.raw_os_error() == Some(libc::EINVAL) && offset == 0 => {
+                ranges.push(0 .. len);
+                break;
+            }
+            Err(e) => return Err(e),
+        };
+        let end = seek(file, start, libc::SEEK_HOLE)?.min(len);
+        ranges.push(start .. end);
+        offset = end;
+    }
+    Ok(ranges)
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn seek(file: &File, offset: u64, whence: libc::c_int) -> io::Result<u64> {
+    match unsafe { libc::lseek64(file.as_raw_fd(), offset as libc::off64_t, whence) } {
+        -1 => Err(io::Error::last_os_error()),
+        n => Ok(n as u64),
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn seek(file: &File, offset: u64, whence: libc::c_int) -> io::Result<u64> {
+    match unsafe { libc::lseek(file.as_raw_fd(), offset as libc::off_t, whence) } {
+        -1 => Err(io::Error::last_os_error()),
+        n => Ok(n as u64),
+    }
+}
+
+/// Copy the contents of one file to another, leaving the holes in it, and any blocks of zeros, as
+/// holes in the copy. The destination is truncated or extended to the length of the source.
+///
+/// Returns the number of bytes written.
+pub fn copy_sparse(from: &File, to: &File) -> io::Result<u64> {
+    let len = from.metadata()?.len();
+    to.set_len(0)?;
+    let mut buf = vec![0; COPY_SIZE];
+    let mut written = 0;
+    for range in data_ranges(from)? {
+        let mut offset = range.start;
+        while offset < range.end {
+            let n = (range.end - offset).min(COPY_SIZE as u64) as usize;
+            from.read_exact_at(&mut buf[.. n], offset)?;
+            if !is_zeros(&buf[.. n]) {
+                to.write_all_at(&buf[.. n], offset)?;
+                written += n as u64;
+            }
+            offset += n as u64;
+        }
+    }
+    to.set_len(len)?;
+    Ok(written)
+}
+
+/// A range of a file and where it's stored on disk, from `extents`.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Extent {
+    /// Offset of the range in the file.
+    pub offset: u64,
+    /// Offset of the range on the disk, if the filesystem says.
+    pub physical: u64,
+    pub len: u64,
+    /// Whether space is allocated for the range, but it hasn't been written to, so reads as
+    /// zeros. Such ranges are reported as data by `data_ranges`.
+    pub unwritten: bool,
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod fiemap {
+    /// `FS_IOC_FIEMAP`: `_IOWR('f', 11, struct fiemap)`.
+    pub const FS_IOC_FIEMAP: libc::c_ulong = 0xc020_660b;
+    pub const FIEMAP_FLAG_SYNC: u32 = 0x1;
+    pub const FIEMAP_EXTENT_LAST: u32 = 0x1;
+    pub const FIEMAP_EXTENT_UNWRITTEN: u32 = 0x800;
+    /// Number of extents asked for at a time.
+    pub const BATCH: usize = 32;
+
+    #[repr(C)]
+    pub struct Fiemap {
+        pub start: u64,
+        pub length: u64,
+        pub flags: u32,
+        pub mapped_extents: u32,
+        pub extent_count: u32,
+        pub reserved: u32,
+        pub extents: [FiemapExtent; BATCH],
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    pub struct FiemapExtent {
+        pub logical: u64,
+        pub physical: u64,
+        pub length: u64,
+        pub reserved64: [u64; 2],
+        pub flags: u32,
+        pub reserved: [u32; 3],
+    }
+}
+
+/// List the extents of a file, which are the ranges of it that have space allocated on disk, with
+/// the `FIEMAP` ioctl. Unlike `data_ranges`, this tells apart ranges which have been allocated
+/// but not yet written to.
+///
+/// Fails with `EOPNOTSUPP` on filesystems which don't support it.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn extents(file: &File) -> io::Result<Vec<Extent>> {
+    use self::fiemap::*;
+
+    let mut extents = vec![];
+    let mut start = 0;
+    loop {
+        let mut map: Fiemap = unsafe { std::mem::zeroed() };
+        map.start = start;
+        map.length = u64::MAX - start;
+        map.flags = FIEMAP_FLAG_SYNC;
+        map.extent_count = BATCH as u32;
+        if unsafe { libc::ioctl(file.as_raw_fd(), FS_IOC_FIEMAP as _, &mut map) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let mapped = &map.extents[.. map.mapped_extents as usize];
+        for extent in mapped {
+            extents.push(Extent {
+                offset: extent.logical,
+                physical: extent.physical,
+                len: extent.length,
+                unwritten: extent.flags & FIEMAP_EXTENT_UNWRITTEN != 0,
+            });
+        }
+        match mapped.last() {
+            Some(last) if last.flags & FIEMAP_EXTENT_LAST == 0 => {
+                start = last.logical + last.length;
+            }
+            _ => return Ok(extents),
+        }
+    }
+}
+
+#[test]
+fn test_sparse() {
+    use std::fs::OpenOptions;
+
+    const MIB: u64 = 1024 * 1024;
+    let dir = std::env::temp_dir();
+    let open = |name: &str| {
+        let path = dir.join(format!("fuse_mt_sparse_{}_{}", std::process::id(), name));
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(true)
+            .open(&path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        file
+    };
+
+    let from = open("from");
+    from.write_all_at(b"start", 0).unwrap();
+    from.write_all_at(&[0; 4096], MIB).unwrap();
+    from.write_all_at(b"middle", 2 * MIB).unwrap();
+    from.set_len(4 * MIB).unwrap();
+
+    let ranges = data_ranges(&from).unwrap();
+    assert_eq!(0, ranges[0].start);
+    assert!(ranges.iter().any(|range| range.contains(&(2 * MIB))));
+    assert!(ranges.iter().all(|range| range.end <= 4 * MIB));
+
+    let to = open("to");
+    let written = copy_sparse(&from, &to).unwrap();
+    assert!((11 .. MIB).contains(&written), "{}", written);
+    assert_eq!(4 * MIB, to.metadata().unwrap().len());
+    let mut buf = [1; 6];
+    to.read_exact_at(&mut buf, 2 * MIB).unwrap();
+    assert_eq!(b"middle", &buf);
+    to.read_exact_at(&mut buf, MIB).unwrap();
+    assert_eq!([0; 6], buf);
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    match extents(&to) {
+        Ok(extents) => {
+            // The block of zeros wasn't copied.
+            assert!(extents.iter().all(|e| !(e.offset .. e.offset + e.len).contains(&MIB)));
+            assert!(extents.iter().any(|e| (e.offset .. e.offset + e.len).contains(&(2 * MIB))));
+        }
+        Err(e) => assert_eq!(Some(libc::EOPNOTSUPP), e.raw_os_error()),
+    }
+}
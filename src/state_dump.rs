@@ -0,0 +1,145 @@
+// StateDump :: a snapshot of FuseMT's tables, for debugging.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::{fs, io};
+
+use crate::json;
+
+/// Name of the extended attribute on the root of the mount which reads out a `StateDump`, as
+/// JSON.
+///
+/// Like `STATS_XATTR`, FuseMT answers reads of it itself, and leaves it out of `listxattr`
+/// results: `getfattr --only-values -n fusemt.state /mnt`.
+pub const STATE_XATTR: &str = "fusemt.state";
+
+/// A snapshot of FuseMT's internal tables, from `FuseMT::dump_state`, for working out why a mount
+/// is stuck or leaking memory.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct StateDump {
+    /// Every inode the kernel knows about, by inode number.
+    pub inodes: Vec<InodeState>,
+    /// Every open directory.
+    pub directories: Vec<DirectoryState>,
+    /// Number of open files with numeric handles.
+    pub open_files: usize,
+    /// Number of open files with `FileHandle` objects.
+    pub open_file_handles: usize,
+}
+
+/// An entry in the inode table.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct InodeState {
+    pub ino: u64,
+    pub path: PathBuf,
+    /// The number of references the kernel holds.
+    pub lookups: u64,
+    pub generation: u64,
+    /// Whether the path was unlinked or renamed over while the kernel still had references to it.
+    pub unlinked: bool,
+}
+
+/// An open directory.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DirectoryState {
+    /// The file handle FuseMT gave the kernel for it.
+    pub fh: u64,
+    pub ino: u64,
+    /// The number of entries in its cached listing, if it has one.
+    pub entries: Option<usize>,
+    /// Whether it was opened with `opendir_handle`.
+    pub handle: bool,
+    /// Whether its listing will be fetched again.
+    pub stale: bool,
+    /// Whether its listing has been read to the end.
+    pub finished: bool,
+}
+
+impl StateDump {
+    /// Format the dump as a JSON object.
+    pub fn to_json(&self) -> String {
+        let mut out = String::from("{\"inodes\":[");
+        for (i, inode) in self.inodes.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            write!(out, "{{\"ino\":{},\"path\":", inode.ino).unwrap();
+            json::write_str(&mut out, &inode.path.to_string_lossy());
+            write!(out, ",\"lookups\":{},\"generation\":{},\"unlinked\":{}}}",
+                inode.lookups, inode.generation, inode.unlinked).unwrap();
+        }
+        out.push_str("],\"directories\":[");
+        for (i, dir) in self.directories.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            write!(out, "{{\"fh\":{},\"ino\":{},\"entries\":", dir.fh, dir.ino).unwrap();
+            match dir.entries {
+                Some(n) => write!(out, "{}", n).unwrap(),
+                None => out.push_str("null"),
+            }
+            write!(out, ",\"handle\":{},\"stale\":{},\"finished\":{}}}",
+                dir.handle, dir.stale, dir.finished).unwrap();
+        }
+        write!(out, "],\"open_files\":{},\"open_file_handles\":{}}}",
+            self.open_files, self.open_file_handles).unwrap();
+        out
+    }
+
+    /// Write the dump to a file as JSON, replacing it if it exists.
+    pub fn write_to(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_json())
+    }
+}
+
+/// Set when the signal given to `FuseMT::dump_state_on_signal` arrives.
+static SIGNALLED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_signal(_signal: libc::c_int) {
+    SIGNALLED.store(true, Ordering::Relaxed);
+}
+
+/// Make the given signal set a flag for `take_signal` instead of doing what it normally does.
+pub fn install_signal_handler(signal: libc::c_int) -> io::Result<()> {
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        // Don't interrupt the session's read from the FUSE device.
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        if libc::sigaction(signal, &action, std::ptr::null_mut()) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Whether the signal has arrived since this was last called.
+pub fn take_signal() -> bool {
+    SIGNALLED.swap(false, Ordering::Relaxed)
+}
+
+#[test]
+fn test_state_dump_json() {
+    let dump = StateDump {
+        inodes: vec![
+            InodeState { ino: 1, path: "/".into(), lookups: 0, generation: 0, unlinked: false },
+            InodeState { ino: 3, path: "/a\"b".into(), lookups: 2, generation: 1, unlinked: true },
+        ],
+        directories: vec![DirectoryState {
+            fh: 1, ino: 1, entries: Some(4), handle: false, stale: false, finished: true,
+        }],
+        open_files: 2,
+        open_file_handles: 0,
+    };
+    assert_eq!(concat!(
+        r#"{"inodes":[{"ino":1,"path":"/","lookups":0,"generation":0,"unlinked":false},"#,
+        r#"{"ino":3,"path":"/a\"b","lookups":2,"generation":1,"unlinked":true}],"#,
+        r#""directories":[{"fh":1,"ino":1,"entries":4,"handle":false,"stale":false,"finished":true}],"#,
+        r#""open_files":2,"open_file_handles":0}"#),
+        dump.to_json());
+}
@@ -0,0 +1,56 @@
+// Stats :: runtime counters, readable through a virtual extended attribute.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Name of the extended attribute on the root of the mount which reads out the stats.
+///
+/// FuseMT answers reads of this attribute itself, without calling the filesystem, so they work
+/// with any filesystem: `getfattr --only-values -n fusemt.stats /mnt`. It isn't included in
+/// `listxattr` results, so it doesn't get copied around by tools that copy all attributes.
+pub const STATS_XATTR: &str = "fusemt.stats";
+
+/// Counts of each operation handled.
+///
+/// These are only touched from the thread that dispatches FUSE requests, so they don't need to be
+/// atomic.
+#[derive(Debug, Default)]
+pub struct Stats {
+    ops: BTreeMap<&'static str, u64>,
+}
+
+impl Stats {
+    pub fn new() -> Stats {
+        Stats::default()
+    }
+
+    /// Count one call of the given operation.
+    pub fn count(&mut self, op: &'static str) {
+        *self.ops.entry(op).or_insert(0) += 1;
+    }
+
+    /// Format the stats as text, one `name value` pair per line, with the given gauges appended
+    /// after the operation counts.
+    pub fn format(&self, gauges: &[(&str, usize)]) -> String {
+        let mut out = String::new();
+        for (op, count) in &self.ops {
+            writeln!(out, "ops.{} {}", op, count).unwrap();
+        }
+        for (name, value) in gauges {
+            writeln!(out, "{} {}", name, value).unwrap();
+        }
+        out
+    }
+}
+
+#[test]
+fn test_stats() {
+    let mut stats = Stats::new();
+    stats.count("read");
+    stats.count("lookup");
+    stats.count("read");
+    assert_eq!("ops.lookup 1\nops.read 2\ninodes 5\n", stats.format(&[("inodes", 5)]));
+}
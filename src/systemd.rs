@@ -0,0 +1,73 @@
+// Systemd :: service readiness notifications.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+//! Integration with systemd, for filesystems run as `Type=notify` services.
+//!
+//! With the `systemd` feature enabled, FuseMT sends `READY=1` to the service manager once the
+//! kernel has initialized the mount and the filesystem's `init` has succeeded, and `STOPPING=1`
+//! when the filesystem is unmounted, before calling its `destroy`. Nothing is sent unless the
+//! process was started with `NOTIFY_SOCKET` set, so this is harmless outside of systemd.
+//!
+//! Filesystems can send their own status updates with `notify`, for example
+//! `notify("STATUS=Serving 42 files")`.
+//!
+//! Socket activation of the `/dev/fuse` file descriptor isn't supported: the underlying `fuser`
+//! library always opens and mounts the device itself.
+
+use std::env;
+use std::io;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+
+/// Send a notification to the service manager, in the `KEY=VALUE` format described in
+/// sd_notify(3). Multiple assignments can be separated by newlines.
+///
+/// Returns `Ok(false)` without doing anything if the process isn't running under a service manager
+/// which wants notifications.
+pub fn notify(state: &str) -> io::Result<bool> {
+    let socket_path = match env::var_os("NOTIFY_SOCKET") {
+        Some(path) if !path.is_empty() => path,
+        _ => return Ok(false),
+    };
+    let socket = UnixDatagram::unbound()?;
+    let bytes = socket_path.as_bytes();
+    if bytes[0] == b'@' {
+        // An abstract socket.
+        let addr = SocketAddr::from_abstract_name(&bytes[1 ..])?;
+        socket.send_to_addr(state.as_bytes(), &addr)?;
+    } else {
+        socket.send_to(state.as_bytes(), &socket_path)?;
+    }
+    Ok(true)
+}
+
+/// Send a notification, logging instead of returning any error.
+pub(crate) fn notify_or_log(state: &str) {
+    match notify(state) {
+        Ok(true) => debug!("sent {:?} to service manager", state),
+        Ok(false) => (),
+        Err(e) => warn!("failed to send {:?} to service manager: {}", state, e),
+    }
+}
+
+#[test]
+fn test_notify() {
+    let path = env::temp_dir().join(format!("fuse_mt_notify_test.{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixDatagram::bind(&path).unwrap();
+
+    env::set_var("NOTIFY_SOCKET", &path);
+    let result = notify("READY=1");
+    env::remove_var("NOTIFY_SOCKET");
+    assert!(result.unwrap());
+
+    let mut buf = [0u8; 64];
+    let n = listener.recv(&mut buf).unwrap();
+    assert_eq!(b"READY=1", &buf[.. n]);
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(!notify("READY=1").unwrap());
+}
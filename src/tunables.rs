@@ -0,0 +1,79 @@
+// Tunables :: changing FuseMT's settings while it's mounted.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// A handle for changing FuseMT's settings after it has been mounted, without unmounting.
+///
+/// Get one from `FuseMT::tunables` before mounting; it can be cloned and used from any thread, for
+/// example from a thread which waits for SIGHUP and re-reads a configuration file. Changes take
+/// effect the next time FuseMT hands a request off to its thread pool (any read, write, flush,
+/// fsync, or release).
+#[derive(Clone, Debug, Default)]
+pub struct Tunables {
+    inner: Arc<Shared>,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    any: AtomicBool,
+    pending: Mutex<Changes>,
+}
+
+/// Changes taken from a `Tunables`.
+#[derive(Debug, Default)]
+pub(crate) struct Changes {
+    pub threads: Option<usize>,
+    pub prefetch: Option<Option<u32>>,
+}
+
+impl Tunables {
+    pub(crate) fn new() -> Tunables {
+        Tunables::default()
+    }
+
+    /// Change the number of worker threads, as given to `FuseMT::new`. Zero means to handle all
+    /// requests on the thread that reads them from FUSE.
+    ///
+    /// Threads which are busy when the count is lowered finish what they're doing first.
+    pub fn set_threads(&self, num_threads: usize) {
+        self.update(|changes| changes.threads = Some(num_threads));
+    }
+
+    /// Change the prefetch window, as given to `FuseMT::prefetch`, or turn prefetching off with
+    /// `None`. Anything already prefetched is discarded.
+    pub fn set_prefetch(&self, window: Option<u32>) {
+        self.update(|changes| changes.prefetch = Some(window));
+    }
+
+    fn update(&self, f: impl FnOnce(&mut Changes)) {
+        f(&mut self.inner.pending.lock().unwrap());
+        self.inner.any.store(true, Ordering::Release);
+    }
+
+    /// Take all the pending changes, if there are any.
+    pub(crate) fn take(&self) -> Option<Changes> {
+        if !self.inner.any.load(Ordering::Acquire) {
+            return None;
+        }
+        let mut pending = self.inner.pending.lock().unwrap();
+        self.inner.any.store(false, Ordering::Release);
+        Some(std::mem::take(&mut *pending))
+    }
+}
+
+#[test]
+fn test_tunables() {
+    let t = Tunables::new();
+    assert!(t.take().is_none());
+    t.clone().set_threads(3);
+    t.set_threads(5);
+    t.set_prefetch(None);
+    let changes = t.take().unwrap();
+    assert_eq!(Some(5), changes.threads);
+    assert_eq!(Some(None), changes.prefetch);
+    assert!(t.take().is_none());
+}
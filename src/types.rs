@@ -3,12 +3,25 @@
 // Copyright (c) 2016-2022 by William R. Fraser
 //
 
+use std::borrow::Borrow;
+use std::collections::HashMap;
 use std::ffi::{OsStr, OsString};
-use std::path::Path;
+use std::fmt;
+use std::ops::Deref;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::{Duration, SystemTime};
 
+pub use crate::responder::Responder;
+pub use fuser::KernelConfig;
+
 /// Info about a request.
+///
+/// Code outside FuseMT which makes requests of its own builds this with `RequestInfo::new`, so
+/// that fields can be added without breaking it.
 #[derive(Clone, Copy, Debug)]
+#[non_exhaustive]
 pub struct RequestInfo {
     /// The unique ID assigned to this request by FUSE.
     pub unique: u64,
@@ -18,19 +31,212 @@ pub struct RequestInfo {
     pub gid: u32,
     /// The process ID of the process making the request.
     pub pid: u32,
+    /// The file or directory the request is on: for calls on an entry in a directory, like
+    /// `mknod`, `unlink`, and `rename`, the directory; for `link`, the file being linked to; and
+    /// for `copy_file_range`, the file being copied from. `None` for calls which aren't on one,
+    /// like `init`, and for requests which didn't come straight from FuseMT, like those served by
+    /// `remote`.
+    pub node: Option<NodeId>,
+    /// The umask of the process making the request, for `mknod`, `mkdir`, and `create`. The kernel
+    /// applies it to their `mode` itself, unless the filesystem asks it not to with the
+    /// `FUSE_DONT_MASK` capability, so as to apply it in its own way, such as only where there's
+    /// no default ACL. `None` for other calls, and, like `node`, for requests which didn't come
+    /// straight from FuseMT.
+    pub umask: Option<u32>,
+    /// The owner of the locks on the file, for `read` and `write`, if the kernel gave one, which
+    /// it doesn't always. Filesystems which implement `setlk` or `flock` can use it to tell
+    /// whether the caller holds a lock on the range being read or written. `None` for other
+    /// calls, and, like `node`, for requests which didn't come straight from FuseMT.
+    pub lock_owner: Option<LockOwner>,
+}
+
+impl RequestInfo {
+    /// Make the info for a request which didn't come from the kernel, with the given ID and
+    /// caller, and `None` for the rest.
+    pub fn new(unique: u64, uid: u32, gid: u32, pid: u32) -> RequestInfo {
+        RequestInfo { unique, uid, gid, pid, node: None, umask: None, lock_owner: None }
+    }
+}
+
+/// An identifier for a file or directory which, unlike its path, stays the same when it's renamed
+/// or moved, so that filesystems can key state on it which must follow the file, like that of
+/// open handles.
+///
+/// It's made of the inode number FuseMT gave the file and that number's generation, so it's only
+/// good while the kernel remembers the file, which it does at least while the file is open, or
+/// is the current directory of some process. After the kernel forgets it, the file may get a new
+/// one the next time it's looked up, and the old one may be given to another file, but never
+/// with the same generation. Each hard link to a file has a different one, since FuseMT doesn't
+/// know that they're the same file.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct NodeId {
+    ino: u64,
+    generation: u64,
+}
+
+impl NodeId {
+    pub(crate) fn new(ino: u64, generation: u64) -> NodeId {
+        NodeId { ino, generation }
+    }
+}
+
+/// A path in the filesystem, as given to `FilesystemMT` methods.
+///
+/// It derefs to `Path`, so it can be used like one, and it's reference-counted, so it can be
+/// cloned to keep past the call without copying the path. FuseMT keeps the paths of the files the
+/// kernel knows about in this form, so passing one on costs nothing either.
+#[derive(Clone, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct FsPath(Arc<Path>);
+
+impl FsPath {
+    pub fn new(path: impl AsRef<Path>) -> FsPath {
+        FsPath(Arc::from(path.as_ref()))
+    }
+
+    pub fn as_path(&self) -> &Path {
+        &self.0
+    }
+
+    /// Get the path as an `Arc`, without copying it.
+    pub fn to_arc(&self) -> Arc<Path> {
+        Arc::clone(&self.0)
+    }
+}
+
+impl Deref for FsPath {
+    type Target = Path;
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for FsPath {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<OsStr> for FsPath {
+    fn as_ref(&self) -> &OsStr {
+        self.0.as_os_str()
+    }
+}
+
+impl Borrow<Path> for FsPath {
+    fn borrow(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl fmt::Debug for FsPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl From<Arc<Path>> for FsPath {
+    fn from(path: Arc<Path>) -> FsPath {
+        FsPath(path)
+    }
+}
+
+impl From<PathBuf> for FsPath {
+    fn from(path: PathBuf) -> FsPath {
+        FsPath(Arc::from(path))
+    }
+}
+
+impl From<&Path> for FsPath {
+    fn from(path: &Path) -> FsPath {
+        FsPath(Arc::from(path))
+    }
+}
+
+impl From<&str> for FsPath {
+    fn from(path: &str) -> FsPath {
+        FsPath::new(path)
+    }
+}
+
+impl From<FsPath> for Arc<Path> {
+    fn from(path: FsPath) -> Arc<Path> {
+        path.0
+    }
+}
+
+impl From<FsPath> for PathBuf {
+    fn from(path: FsPath) -> PathBuf {
+        path.0.to_path_buf()
+    }
+}
+
+impl PartialEq<Path> for FsPath {
+    fn eq(&self, other: &Path) -> bool {
+        *self.0 == *other
+    }
+}
+
+impl PartialEq<FsPath> for Path {
+    fn eq(&self, other: &FsPath) -> bool {
+        *self == *other.0
+    }
+}
+
+impl PartialEq<PathBuf> for FsPath {
+    fn eq(&self, other: &PathBuf) -> bool {
+        *self.0 == **other
+    }
+}
+
+/// What FuseMT knows about its connection to the kernel, given to `FilesystemMT::init_connection`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ConnectionInfo {
+    /// The version of the FUSE protocol FuseMT speaks, as (major, minor). The kernel uses this
+    /// version, or its own if that's older; every Linux kernel since 4.20 is at least this new.
+    pub protocol_version: (u32, u32),
+    /// The capabilities the kernel offered, as `fuser::consts::FUSE_*` flags, such as
+    /// `FUSE_WRITEBACK_CACHE` or `FUSE_POSIX_ACL`.
+    pub capabilities: u32,
+    /// The options the filesystem was mounted with, if they were given to FuseMT with
+    /// `FuseMT::mount_options`. FuseMT doesn't do the mounting itself, so it can't tell otherwise.
+    pub mount_options: Vec<crate::MountOption>,
+}
+
+impl ConnectionInfo {
+    /// Whether the kernel offered all of the given capabilities.
+    pub fn has_capabilities(&self, capabilities: u32) -> bool {
+        self.capabilities & capabilities == capabilities
+    }
 }
 
 /// A directory entry.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct DirectoryEntry {
     /// Name of the entry
     pub name: OsString,
     /// Kind of file (directory, file, pipe, etc.)
     pub kind: crate::FileType,
+    /// Where listing continues after this entry, like `d_off` in a `dirent`: an opaque, nonzero
+    /// cookie. If every entry in a listing has one, FuseMT gives them to the kernel instead of
+    /// the entries' positions, so that programs which come back to a place in the listing with
+    /// `telldir`/`seekdir`, or keep reading it after it changed, neither skip nor repeat entries.
+    /// Listing resumes after the entry with the cookie asked for or, if that entry is gone, at
+    /// the first one with a greater cookie, so they work best when they increase through the
+    /// listing. Otherwise, use `None`.
+    pub offset: Option<u64>,
+    /// The entry's attributes, and how long they can be cached, as `getattr` would give them, if
+    /// the filesystem got them along with the listing. When FuseMT is asked to list a directory
+    /// with its entries' attributes (see `FuseMT::readdirplus`), it uses these, and calls
+    /// `getattr_deferred` for entries which don't have them. Otherwise, use `None`.
+    pub attr: Option<(Duration, FileAttr)>,
 }
 
 /// Filesystem statistics.
+///
+/// Build this with `Statfs::new`, so that fields can be added without breaking code outside
+/// FuseMT.
 #[derive(Clone, Copy, Debug)]
+#[non_exhaustive]
 pub struct Statfs {
     /// Total data blocks in the filesystem
     pub blocks: u64,
@@ -48,11 +254,61 @@ pub struct Statfs {
     pub namelen: u32,
     /// Fragment size
     pub frsize: u32,
+    /// Filesystem ID. FUSE can't give this to the kernel, which makes up its own; it's passed on by
+    /// `remote` and `ninep`.
+    pub fsid: u64,
+    /// Mount flags (`ST_RDONLY`, `ST_NOSUID`, etc.; see statvfs(3)). FUSE can't give these to the
+    /// kernel, which uses the mount's own; they're passed on by `remote`.
+    pub flags: u64,
+}
+
+impl Statfs {
+    /// Make statistics with the given counts and sizes, and zero for `fsid` and `flags`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(blocks: u64, bfree: u64, bavail: u64, files: u64, ffree: u64, bsize: u32,
+               namelen: u32, frsize: u32) -> Statfs {
+        Statfs { blocks, bfree, bavail, files, ffree, bsize, namelen, frsize, fsid: 0, flags: 0 }
+    }
+
+    /// Limit the statistics to what the given quota allows: the totals become the quota's limits,
+    /// if it has them, and the free counts are reduced to what's left under them.
+    pub fn with_quota(mut self, quota: &Quota) -> Statfs {
+        if let Some(limit) = quota.blocks {
+            let left = limit.saturating_sub(quota.blocks_used);
+            self.blocks = limit;
+            self.bfree = self.bfree.min(left);
+            self.bavail = self.bavail.min(left);
+        }
+        if let Some(limit) = quota.files {
+            self.files = limit;
+            self.ffree = self.ffree.min(limit.saturating_sub(quota.files_used));
+        }
+        self
+    }
+}
+
+/// Limits on the space and number of files in a part of the filesystem, such as a directory with
+/// a project quota.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Quota {
+    /// Most blocks (of `Statfs::frsize` bytes) which may be used, if limited
+    pub blocks: Option<u64>,
+    /// Blocks used
+    pub blocks_used: u64,
+    /// Most files which may exist, if limited
+    pub files: Option<u64>,
+    /// Files which exist
+    pub files_used: u64,
 }
 
 /// File attributes.
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub struct FileAttr {
+    /// The file's inode number in the filesystem's own storage, if it has them, or else 0.
+    /// FuseMT gives the kernel inode numbers of its own; this is used by `ninep`, whose clients
+    /// tell files apart by it, so that files keep their identity when renamed, and hard links are
+    /// seen to be the same file.
+    pub ino: u64,
     /// Size in bytes
     pub size: u64,
     /// Size in blocks
@@ -91,14 +347,205 @@ pub struct CreatedEntry {
     pub flags: u32,
 }
 
+/// The return value for `create_handle`: like `CreatedEntry`, but with a `FileHandle` object in
+/// place of a numeric file handle.
+pub struct CreatedHandle {
+    pub ttl: Duration,
+    pub attr: FileAttr,
+    pub handle: Box<dyn FileHandle>,
+    pub flags: u32,
+}
+
 /// Represents the return value from the `listxattr` and `getxattr` calls, which can be either a
 /// size or contain data, depending on how they are called.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub enum Xattr {
     Size(u32),
     Data(Vec<u8>),
 }
 
+/// Largest extended attribute value, or list of attribute names, the Linux kernel will take from a
+/// filesystem.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub const XATTR_MAX: usize = 64 * 1024;
+
+impl Xattr {
+    /// Answer a `getxattr` or `listxattr` call which asked for up to `size` bytes with the given
+    /// data: with its size if `size` is 0, the data if it fits, or `ERANGE` if it doesn't. On
+    /// Linux, data too big for the kernel to take at all fails with `E2BIG`.
+    pub fn sized(data: Vec<u8>, size: u32) -> ResultXattr {
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        {
+            if data.len() > XATTR_MAX {
+                return Err(libc::E2BIG);
+            }
+        }
+        if size == 0 {
+            Ok(Xattr::Size(data.len() as u32))
+        } else if data.len() > size as usize {
+            Err(libc::ERANGE)
+        } else {
+            Ok(Xattr::Data(data))
+        }
+    }
+
+    /// Answer a `listxattr` call which asked for up to `size` bytes with the given attribute
+    /// names, as `sized` does.
+    pub fn list<S: AsRef<OsStr>>(names: impl IntoIterator<Item = S>, size: u32) -> ResultXattr {
+        let mut data = vec![];
+        for name in names {
+            data.extend_from_slice(name.as_ref().as_bytes());
+            data.push(0);
+        }
+        Xattr::sized(data, size)
+    }
+}
+
+/// Name of the extended attribute macOS stores a file's resource fork in.
+pub const RESOURCE_FORK_XATTR: &str = "com.apple.ResourceFork";
+
+/// Where in an extended attribute's value a `setxattr` call writes.
+///
+/// macOS passes an offset with every `setxattr` call, but it's only meaningful for the resource
+/// fork (`RESOURCE_FORK_XATTR`), which can be written a piece at a time; for every other attribute,
+/// and on every other platform, the whole value is replaced.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum XattrPosition {
+    /// Replace the whole value.
+    Normal,
+    /// Write to the resource fork, starting at the given byte offset.
+    ResourceFork { offset: u32 },
+}
+
+impl XattrPosition {
+    /// Interpret the position the kernel gave for a `setxattr` call on the given attribute.
+    pub fn new(name: &OsStr, position: u32) -> XattrPosition {
+        if name == RESOURCE_FORK_XATTR {
+            XattrPosition::ResourceFork { offset: position }
+        } else {
+            XattrPosition::Normal
+        }
+    }
+
+    /// The position as the kernel gives it, and as macOS's `setxattr` takes it.
+    pub fn offset(self) -> u32 {
+        match self {
+            XattrPosition::Normal => 0,
+            XattrPosition::ResourceFork { offset } => offset,
+        }
+    }
+}
+
+/// Identifies the owner of a lock on an open file.
+///
+/// The kernel assigns these; they have no meaning other than as a key, but every lock taken with
+/// `setlk` belongs to exactly one owner, and when a file is flushed or released, all locks held
+/// by the given owner must be released.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct LockOwner(pub u64);
+
+impl From<u64> for LockOwner {
+    fn from(owner: u64) -> LockOwner {
+        LockOwner(owner)
+    }
+}
+
+/// A POSIX record lock on a range of a file, as taken with `fcntl` (`F_SETLK`, `F_SETLKW`) and
+/// asked about with `F_GETLK`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct FileLock {
+    /// First byte of the range.
+    pub start: u64,
+    /// Last byte of the range, inclusive. `i64::MAX` means the range goes to the end of the file,
+    /// however long it gets.
+    pub end: u64,
+    /// `F_RDLCK` for a shared lock, `F_WRLCK` for an exclusive one, or `F_UNLCK` for none.
+    pub typ: i32,
+    /// The process holding the lock, if it's on the same machine; zero otherwise.
+    pub pid: u32,
+}
+
+/// Which locks the kernel passes on to the filesystem, set with `FuseMT::locks`.
+///
+/// The kernel sends POSIX locks and BSD locks the same way, so FuseMT can only tell them apart
+/// when it asks for just one kind.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum LockMode {
+    /// The kernel keeps track of all locks itself, so they only keep out other processes on the
+    /// same machine. The filesystem's locking calls are never made.
+    #[default]
+    Local,
+    /// POSIX record locks (`fcntl` with `F_GETLK`, `F_SETLK`, and `F_SETLKW`) are passed on to
+    /// `getlk` and `setlk`. The kernel keeps track of BSD locks itself.
+    Posix,
+    /// BSD locks (the `flock` system call) are passed on to `flock`. The kernel keeps track of
+    /// POSIX record locks itself.
+    Flock,
+}
+
+/// A helper for keeping track of lock state on a per-owner basis.
+///
+/// Filesystems which implement locking need to be able to find and drop all the locks held by a
+/// given owner when `flush` or `release` is called. This stores some arbitrary per-lock state
+/// (`T`) grouped by owner to make that easy.
+#[derive(Clone, Debug)]
+pub struct LockOwnerTable<T> {
+    owners: HashMap<LockOwner, Vec<T>>,
+}
+
+impl<T> LockOwnerTable<T> {
+    pub fn new() -> LockOwnerTable<T> {
+        LockOwnerTable {
+            owners: HashMap::new(),
+        }
+    }
+
+    /// Record a lock as being held by the given owner.
+    pub fn insert(&mut self, owner: LockOwner, lock: T) {
+        self.owners.entry(owner).or_default().push(lock);
+    }
+
+    /// Get all the locks currently held by the given owner.
+    pub fn get(&self, owner: LockOwner) -> &[T] {
+        self.owners.get(&owner).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    /// Keep only the locks held by the given owner for which the predicate returns true.
+    pub fn retain(&mut self, owner: LockOwner, f: impl FnMut(&T) -> bool) {
+        if let Some(locks) = self.owners.get_mut(&owner) {
+            locks.retain(f);
+            if locks.is_empty() {
+                self.owners.remove(&owner);
+            }
+        }
+    }
+
+    /// Remove and return all the locks held by the given owner.
+    ///
+    /// This is what should be done on `flush`, and on `release` if a lock owner is given.
+    pub fn remove_owner(&mut self, owner: LockOwner) -> Vec<T> {
+        self.owners.remove(&owner).unwrap_or_default()
+    }
+
+    /// Iterate over all locks held by all owners.
+    pub fn iter(&self) -> impl Iterator<Item = (LockOwner, &T)> {
+        self.owners.iter()
+            .flat_map(|(owner, locks)| locks.iter().map(move |lock| (*owner, lock)))
+    }
+
+    /// Whether any locks are held at all.
+    pub fn is_empty(&self) -> bool {
+        self.owners.is_empty()
+    }
+}
+
+impl<T> Default for LockOwnerTable<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// macOS only: the extended times of a file, returned by `getxtimes`.
 #[cfg(target_os = "macos")]
 #[derive(Clone, Debug)]
 pub struct XTimes {
@@ -106,16 +553,109 @@ pub struct XTimes {
     pub crtime: SystemTime,
 }
 
+/// The calls FuseMT runs on its worker threads, as passed to `FilesystemMT::classify`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Operation {
+    Read,
+    Write,
+    Flush,
+    Fsync,
+    Fallocate,
+    CopyFileRange,
+    /// A read FuseMT makes ahead of the kernel's requests (see `FuseMT::prefetch`), whose data
+    /// may never be used. Classify these into a lane of their own to keep them from holding up
+    /// reads the kernel is waiting for, and report them as `Load::Busy` to stop them while the
+    /// filesystem is under pressure.
+    Prefetch,
+}
+
+/// How busy a filesystem is, as returned by `FilesystemMT::load`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Load {
+    /// Take on the call as usual.
+    Normal,
+    /// Hold off on taking on more work: wait for the worker threads to start on all the calls
+    /// already queued for them before queueing this one. FuseMT also stops prefetching. Since
+    /// FuseMT handles requests one at a time, this holds up new requests of every kind, leaving
+    /// them queued in the kernel instead.
+    Busy,
+    /// Fail the call with `EAGAIN` right away, without queueing it.
+    Overloaded,
+}
+
+/// A group of worker threads which calls can be steered to with `FilesystemMT::classify`. Lanes
+/// other than `Lane::DEFAULT` get their threads from `FuseMT::lane`; calls classified into a lane
+/// which wasn't given any run on the default threads.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Lane(pub u32);
+
+impl Lane {
+    /// The lane of the threads given to `FuseMT::new`.
+    pub const DEFAULT: Lane = Lane(0);
+}
+
+/// The attributes a caller of `FilesystemMT::getattr_mask` wants, as bits of the same values as
+/// the `STATX_*` flags on Linux.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct AttrMask(pub u32);
+
+impl AttrMask {
+    /// `kind`, and `rdev`.
+    pub const TYPE: AttrMask = AttrMask(0x1);
+    /// `perm`.
+    pub const MODE: AttrMask = AttrMask(0x2);
+    pub const NLINK: AttrMask = AttrMask(0x4);
+    pub const UID: AttrMask = AttrMask(0x8);
+    pub const GID: AttrMask = AttrMask(0x10);
+    pub const ATIME: AttrMask = AttrMask(0x20);
+    pub const MTIME: AttrMask = AttrMask(0x40);
+    pub const CTIME: AttrMask = AttrMask(0x80);
+    /// `ino`. FuseMT gives the kernel inode numbers of its own, so only `ninep` asks for this.
+    pub const INO: AttrMask = AttrMask(0x100);
+    pub const SIZE: AttrMask = AttrMask(0x200);
+    pub const BLOCKS: AttrMask = AttrMask(0x400);
+    /// Everything `stat` returns.
+    pub const BASIC: AttrMask = AttrMask(0x7ff);
+    /// `crtime`.
+    pub const BTIME: AttrMask = AttrMask(0x800);
+    /// Every attribute in `FileAttr`.
+    pub const ALL: AttrMask = AttrMask(0xfff);
+
+    /// Whether all the attributes in `other` are wanted.
+    pub fn contains(self, other: AttrMask) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl Default for AttrMask {
+    fn default() -> AttrMask {
+        AttrMask::ALL
+    }
+}
+
+impl std::ops::BitOr for AttrMask {
+    type Output = AttrMask;
+    fn bitor(self, other: AttrMask) -> AttrMask {
+        AttrMask(self.0 | other.0)
+    }
+}
+
 pub type ResultEmpty = Result<(), libc::c_int>;
 pub type ResultEntry = Result<(Duration, FileAttr), libc::c_int>;
-pub type ResultOpen = Result<(u64, u32), libc::c_int>;
+pub type ResultOpen = Result<OpenedFile, libc::c_int>;
 pub type ResultReaddir = Result<Vec<DirectoryEntry>, libc::c_int>;
 pub type ResultData = Result<Vec<u8>, libc::c_int>;
 pub type ResultSlice<'a> = Result<&'a [u8], libc::c_int>;
 pub type ResultWrite = Result<u32, libc::c_int>;
+pub type ResultOffset = Result<u64, libc::c_int>;
+pub type ResultLock = Result<FileLock, libc::c_int>;
 pub type ResultStatfs = Result<Statfs, libc::c_int>;
+pub type ResultQuota = Result<Option<Quota>, libc::c_int>;
 pub type ResultCreate = Result<CreatedEntry, libc::c_int>;
 pub type ResultXattr = Result<Xattr, libc::c_int>;
+pub type ResultOpenHandle = Result<(Box<dyn FileHandle>, u32), libc::c_int>;
+pub type ResultOpenDirHandle = Result<(Box<dyn DirHandle>, u32), libc::c_int>;
+pub type ResultCreateHandle = Result<CreatedHandle, libc::c_int>;
 
 #[cfg(target_os = "macos")]
 pub type ResultXTimes = Result<XTimes, libc::c_int>;
@@ -123,6 +663,92 @@ pub type ResultXTimes = Result<XTimes, libc::c_int>;
 #[deprecated(since = "0.3.0", note = "use ResultEntry instead")]
 pub type ResultGetattr = ResultEntry;
 
+/// Flags which can be returned from `open`, `opendir`, `create`, and their handle variants, to
+/// control how the kernel treats the open file. Kernels too old to know about a flag ignore it.
+pub mod open_flags {
+    /// Bypass the page cache for this file: every read and write goes to the filesystem.
+    pub const FOPEN_DIRECT_IO: u32 = 1 << 0;
+    /// Don't discard the file's cached data when it's opened.
+    pub const FOPEN_KEEP_CACHE: u32 = 1 << 1;
+    /// The file is not seekable.
+    pub const FOPEN_NONSEEKABLE: u32 = 1 << 2;
+    /// Allow the kernel to cache this directory's listing (for `opendir`).
+    pub const FOPEN_CACHE_DIR: u32 = 1 << 3;
+    /// The file is stream-like, with no file position at all.
+    pub const FOPEN_STREAM: u32 = 1 << 4;
+    /// Don't send a flush when the file is closed.
+    pub const FOPEN_NOFLUSH: u32 = 1 << 5;
+    /// Allow multiple writes to this file at once, when it's also opened with `FOPEN_DIRECT_IO`.
+    /// Without this, the kernel sends direct writes to a file one at a time. Only set this if the
+    /// filesystem handles concurrent writes to the same file correctly. (Linux 6.2 and later.)
+    pub const FOPEN_PARALLEL_DIRECT_WRITES: u32 = 1 << 6;
+}
+
+/// An open file or directory, returned by `open` and `opendir`: its handle, and how the kernel
+/// should treat it.
+///
+/// For filesystems written against the old `(fh, flags)` tuple, there's a conversion from it:
+/// `Ok((fh, flags).into())`.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct OpenedFile {
+    /// The file handle, which is passed to the calls made on the open file. It can be any value
+    /// you choose, though it should let the filesystem identify the file even without its path.
+    pub fh: u64,
+    /// Bypass the page cache for this file: every read and write goes to the filesystem.
+    pub direct_io: bool,
+    /// Don't discard the file's cached data when it's opened.
+    pub keep_cache: bool,
+    /// The file is not seekable.
+    pub nonseekable: bool,
+    /// Allow the kernel to cache this directory's listing (for `opendir`).
+    pub cache_dir: bool,
+    /// The file is stream-like, with no file position at all.
+    pub stream: bool,
+    /// Don't send a flush when the file is closed.
+    pub noflush: bool,
+    /// Allow multiple writes to this file at once, when it's also opened with `direct_io`. See
+    /// `open_flags::FOPEN_PARALLEL_DIRECT_WRITES`.
+    pub parallel_direct_writes: bool,
+}
+
+impl OpenedFile {
+    /// An open file with the given handle, which the kernel treats the usual way.
+    pub fn new(fh: u64) -> OpenedFile {
+        OpenedFile { fh, ..OpenedFile::default() }
+    }
+
+    /// The flags to give the kernel, as a combination of those in `open_flags`.
+    pub fn flags(&self) -> u32 {
+        use open_flags::*;
+        [
+            (self.direct_io, FOPEN_DIRECT_IO),
+            (self.keep_cache, FOPEN_KEEP_CACHE),
+            (self.nonseekable, FOPEN_NONSEEKABLE),
+            (self.cache_dir, FOPEN_CACHE_DIR),
+            (self.stream, FOPEN_STREAM),
+            (self.noflush, FOPEN_NOFLUSH),
+            (self.parallel_direct_writes, FOPEN_PARALLEL_DIRECT_WRITES),
+        ].iter().filter(|(set, _)| *set).fold(0, |flags, (_, flag)| flags | flag)
+    }
+}
+
+impl From<(u64, u32)> for OpenedFile {
+    /// From a file handle and a combination of the flags in `open_flags`. Others are ignored.
+    fn from((fh, flags): (u64, u32)) -> OpenedFile {
+        use open_flags::*;
+        OpenedFile {
+            fh,
+            direct_io: flags & FOPEN_DIRECT_IO != 0,
+            keep_cache: flags & FOPEN_KEEP_CACHE != 0,
+            nonseekable: flags & FOPEN_NONSEEKABLE != 0,
+            cache_dir: flags & FOPEN_CACHE_DIR != 0,
+            stream: flags & FOPEN_STREAM != 0,
+            noflush: flags & FOPEN_NOFLUSH != 0,
+            parallel_direct_writes: flags & FOPEN_PARALLEL_DIRECT_WRITES != 0,
+        }
+    }
+}
+
 /// Dummy struct returned by the callback in the `read()` method. Cannot be constructed outside
 /// this crate, `read()` requires you to return it, thus ensuring that you don't forget to call the
 /// callback.
@@ -130,6 +756,159 @@ pub struct CallbackResult {
     pub(crate) _private: std::marker::PhantomData<()>,
 }
 
+/// An open file whose I/O calls are handled by the object itself.
+///
+/// Instead of returning a numeric file handle from `open` (and having to stuff a pointer or file
+/// descriptor into it, and then look it up again on every call), a filesystem can implement
+/// `FilesystemMT::open_handle` and return one of these. FuseMT keeps ownership of it, and calls
+/// to read, write, flush, fsync, and release the file go directly to the object instead of to the
+/// corresponding `FilesystemMT` method, as do truncating and allocating space in the file through
+/// the handle. This makes it the place to keep state which belongs to one open of the file, such
+/// as credentials or decryption state, rather than in a map keyed by file handle.
+///
+/// The handle is dropped after `release` is called on it.
+pub trait FileHandle: Send + Sync {
+    /// Read from the file.
+    ///
+    /// * `offset`: offset into the file to start reading.
+    /// * `size`: number of bytes to read.
+    ///
+    /// See `FilesystemMT::read` for more details.
+    fn read(&self, _req: RequestInfo, _offset: u64, _size: u32) -> ResultData {
+        Err(libc::ENOSYS)
+    }
+
+    /// Like `read`, but the data is sent through `reply`, which can be done later, from any
+    /// thread. The default calls `read` and sends its result right away.
+    ///
+    /// This is for files whose reads wait for data to show up, like the tail of a log or a stream
+    /// of events: the handle can keep the responder until there's something to send, without a
+    /// FuseMT thread waiting along with it, so any number of readers can wait at once. Such files
+    /// should be opened with `FOPEN_DIRECT_IO`, so the kernel passes every read on, and usually
+    /// `FOPEN_STREAM`. A read waiting for data doesn't hold up `Freezer::freeze`.
+    ///
+    /// The kernel can't cancel a read it has sent: `fuser` answers its interrupt requests itself,
+    /// without passing them on, so the reading process waits until the read is answered, even if
+    /// it's killed. Reads which may wait a long time should be answered with what there is, or
+    /// with `EAGAIN` for a file opened with `O_NONBLOCK`, after a while.
+    fn read_deferred(&self, req: RequestInfo, offset: u64, size: u32, reply: Responder<Vec<u8>>) {
+        reply.send(self.read(req, offset, size))
+    }
+
+    /// Write to the file.
+    ///
+    /// * `offset`: offset into the file to start writing.
+    /// * `data`: the data to write
+    /// * `flags`:
+    ///
+    /// Return the number of bytes written.
+    fn write(&self, _req: RequestInfo, _offset: u64, _data: Vec<u8>, _flags: u32) -> ResultWrite {
+        Err(libc::ENOSYS)
+    }
+
+    /// Called each time a program calls `close` on the file.
+    ///
+    /// See `FilesystemMT::flush` for more details.
+    fn flush(&self, _req: RequestInfo, _lock_owner: LockOwner) -> ResultEmpty {
+        Err(libc::ENOSYS)
+    }
+
+    /// Write out any pending changes to the file.
+    ///
+    /// * `datasync`: if `false`, also write metadata, otherwise just write file data.
+    fn fsync(&self, _req: RequestInfo, _datasync: bool) -> ResultEmpty {
+        Err(libc::ENOSYS)
+    }
+
+    /// Change the size of the file, as with `ftruncate`.
+    ///
+    /// If this isn't implemented, `FilesystemMT::truncate` is called instead, without a file
+    /// handle.
+    fn truncate(&self, _req: RequestInfo, _size: u64) -> ResultEmpty {
+        Err(libc::ENOSYS)
+    }
+
+    /// Preallocate or deallocate space in the file.
+    ///
+    /// See `FilesystemMT::fallocate` for more details. If this isn't implemented, the call fails
+    /// with `EOPNOTSUPP`.
+    fn fallocate(&self, _req: RequestInfo, _offset: u64, _length: u64, _mode: i32) -> ResultEmpty {
+        Err(libc::ENOSYS)
+    }
+
+    /// Test for a POSIX record lock on the file.
+    ///
+    /// See `FilesystemMT::getlk` for more details.
+    fn getlk(&self, _req: RequestInfo, _lock_owner: LockOwner, _lock: FileLock) -> ResultLock {
+        Err(libc::ENOSYS)
+    }
+
+    /// Take, change, or release a POSIX record lock on the file.
+    ///
+    /// See `FilesystemMT::setlk` for more details.
+    fn setlk(&self, _req: RequestInfo, _lock_owner: LockOwner, _lock: FileLock, _sleep: bool) -> ResultEmpty {
+        Err(libc::ENOSYS)
+    }
+
+    /// Take, change, or release a BSD lock on the file.
+    ///
+    /// See `FilesystemMT::flock` for more details.
+    fn flock(&self, _req: RequestInfo, _lock_owner: LockOwner, _operation: i32) -> ResultEmpty {
+        Err(libc::ENOSYS)
+    }
+
+    /// Find the next data or hole in the file.
+    ///
+    /// See `FilesystemMT::lseek` for more details. If this isn't implemented, the call fails with
+    /// `EOPNOTSUPP`, rather than the kernel turning it off for the whole mount.
+    fn lseek(&self, _req: RequestInfo, _offset: u64, _whence: i32) -> ResultOffset {
+        Err(libc::ENOSYS)
+    }
+
+    /// Called when the file is closed. The handle is dropped afterwards.
+    ///
+    /// See `FilesystemMT::release` for more details.
+    fn release(&self, _req: RequestInfo, _flags: u32, _lock_owner: Option<LockOwner>, _flush: bool) -> ResultEmpty {
+        Ok(())
+    }
+}
+
+/// An open directory whose calls are handled by the object itself.
+///
+/// This is the directory counterpart to `FileHandle`: a filesystem can implement
+/// `FilesystemMT::opendir_handle` and return one of these, and the readdir, fsyncdir, and
+/// releasedir calls for the directory go directly to the object.
+///
+/// The handle is dropped after `release` is called on it.
+pub trait DirHandle: Send + Sync {
+    /// Get the entries of the directory.
+    ///
+    /// Return all the entries of the directory. This is called at most once per open directory;
+    /// FuseMT caches the result to serve subsequent readdir calls.
+    fn list(&self, _req: RequestInfo) -> ResultReaddir {
+        Err(libc::ENOSYS)
+    }
+
+    /// Get the entries of the directory one page at a time, without FuseMT caching them.
+    ///
+    /// If implemented, this is used instead of `list`. See `FilesystemMT::readdir_at` for details.
+    fn list_at(&self, _req: RequestInfo, _offset: u64, _add: &mut dyn FnMut(&DirectoryEntry, u64) -> bool) -> ResultEmpty {
+        Err(libc::ENOSYS)
+    }
+
+    /// Write out any pending changes to the directory.
+    fn fsync(&self, _req: RequestInfo, _datasync: bool) -> ResultEmpty {
+        Err(libc::ENOSYS)
+    }
+
+    /// Called when the directory is closed. The handle is dropped afterwards.
+    ///
+    /// * `flags`: the file access flags passed to the `opendir_handle` call.
+    fn release(&self, _req: RequestInfo, _flags: u32) -> ResultEmpty {
+        Ok(())
+    }
+}
+
 /// This trait must be implemented to implement a filesystem with FuseMT.
 pub trait FilesystemMT {
     /// Called on mount, before any other function.
@@ -137,6 +916,22 @@ pub trait FilesystemMT {
         Ok(())
     }
 
+    /// Like `init`, with what FuseMT knows about its connection to the kernel, for filesystems
+    /// which adapt to it, such as by leaving out emulation of things the kernel does itself.
+    /// FuseMT calls this instead of `init`; the default calls `init`.
+    ///
+    /// * `conn`: what FuseMT knows about the connection.
+    /// * `config`: the settings FuseMT will reply to the kernel with, such as `max_write`,
+    ///   `max_readahead`, and the capabilities to turn on, like `FUSE_WRITEBACK_CACHE`. They
+    ///   already include those asked for with FuseMT's builder methods, like `FuseMT::max_write`
+    ///   and `FuseMT::locks`, and the filesystem can change them.
+    ///
+    /// Wrappers which do something of their own on `init` call `init` on the filesystem they wrap
+    /// instead of this.
+    fn init_connection(&self, req: RequestInfo, _conn: &ConnectionInfo, _config: &mut KernelConfig) -> ResultEmpty {
+        self.init(req)
+    }
+
     /// Called on filesystem unmount.
     fn destroy(&self) {
         // Nothing.
@@ -145,10 +940,28 @@ pub trait FilesystemMT {
     /// Get the attributes of a filesystem entry.
     ///
     /// * `fh`: a file handle if this is called on an open file.
-    fn getattr(&self, _req: RequestInfo, _path: &Path, _fh: Option<u64>) -> ResultEntry {
+    fn getattr(&self, _req: RequestInfo, _path: &FsPath, _fh: Option<u64>) -> ResultEntry {
         Err(libc::ENOSYS)
     }
 
+    /// Like `getattr`, but the caller only needs the attributes in `mask`, so others which are
+    /// expensive to get, like the exact size of an object in remote storage, can be left out.
+    /// Whatever is returned in their place isn't used. The default calls `getattr`.
+    ///
+    /// The FUSE kernel interface FuseMT speaks has no mask, so FuseMT itself always asks for
+    /// `AttrMask::ALL`, since the kernel keeps what it's given for the TTL; `ninep::NinePServer`
+    /// passes on its clients' masks.
+    fn getattr_mask(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, _mask: AttrMask) -> ResultEntry {
+        self.getattr(req, path, fh)
+    }
+
+    /// Like `getattr`, but the result is sent through `reply`, which can be done later, from any
+    /// thread. FuseMT calls this instead of `getattr`; the default calls `getattr` and sends its
+    /// result right away.
+    fn getattr_deferred(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, reply: Responder<(Duration, FileAttr)>) {
+        reply.send(self.getattr(req, path, fh))
+    }
+
     // The following operations in the FUSE C API are all one kernel call: setattr
     // We split them out to match the C API's behavior.
 
@@ -156,7 +969,7 @@ pub trait FilesystemMT {
     ///
     /// * `fh`: a file handle if this is called on an open file.
     /// * `mode`: the mode to change the file to.
-    fn chmod(&self, _req: RequestInfo, _path: &Path, _fh: Option<u64>, _mode: u32) -> ResultEmpty {
+    fn chmod(&self, _req: RequestInfo, _path: &FsPath, _fh: Option<u64>, _mode: u32) -> ResultEmpty {
         Err(libc::ENOSYS)
     }
 
@@ -165,7 +978,7 @@ pub trait FilesystemMT {
     /// * `fh`: a file handle if this is called on an open file.
     /// * `uid`: user ID to change the file's owner to. If `None`, leave the UID unchanged.
     /// * `gid`: group ID to change the file's group to. If `None`, leave the GID unchanged.
-    fn chown(&self, _req: RequestInfo, _path: &Path, _fh: Option<u64>, _uid: Option<u32>, _gid: Option<u32>) -> ResultEmpty {
+    fn chown(&self, _req: RequestInfo, _path: &FsPath, _fh: Option<u64>, _uid: Option<u32>, _gid: Option<u32>) -> ResultEmpty {
         Err(libc::ENOSYS)
     }
 
@@ -173,7 +986,7 @@ pub trait FilesystemMT {
     ///
     /// * `fh`: a file handle if this is called on an open file.
     /// * `size`: size in bytes to set as the file's length.
-    fn truncate(&self, _req: RequestInfo, _path: &Path, _fh: Option<u64>, _size: u64) -> ResultEmpty {
+    fn truncate(&self, _req: RequestInfo, _path: &FsPath, _fh: Option<u64>, _size: u64) -> ResultEmpty {
         Err(libc::ENOSYS)
     }
 
@@ -182,20 +995,20 @@ pub trait FilesystemMT {
     /// * `fh`: a file handle if this is called on an open file.
     /// * `atime`: the time of last access.
     /// * `mtime`: the time of last modification.
-    fn utimens(&self, _req: RequestInfo, _path: &Path, _fh: Option<u64>, _atime: Option<SystemTime>, _mtime: Option<SystemTime>) -> ResultEmpty {
+    fn utimens(&self, _req: RequestInfo, _path: &FsPath, _fh: Option<u64>, _atime: Option<SystemTime>, _mtime: Option<SystemTime>) -> ResultEmpty {
         Err(libc::ENOSYS)
     }
 
     /// Set timestamps of a filesystem entry (with extra options only used on MacOS).
     #[allow(clippy::too_many_arguments)]
-    fn utimens_macos(&self, _req: RequestInfo, _path: &Path, _fh: Option<u64>, _crtime: Option<SystemTime>, _chgtime: Option<SystemTime>, _bkuptime: Option<SystemTime>, _flags: Option<u32>) -> ResultEmpty {
+    fn utimens_macos(&self, _req: RequestInfo, _path: &FsPath, _fh: Option<u64>, _crtime: Option<SystemTime>, _chgtime: Option<SystemTime>, _bkuptime: Option<SystemTime>, _flags: Option<u32>) -> ResultEmpty {
         Err(libc::ENOSYS)
     }
 
     // END OF SETATTR FUNCTIONS
 
     /// Read a symbolic link.
-    fn readlink(&self, _req: RequestInfo, _path: &Path) -> ResultData {
+    fn readlink(&self, _req: RequestInfo, _path: &FsPath) -> ResultData {
         Err(libc::ENOSYS)
     }
 
@@ -203,9 +1016,9 @@ pub trait FilesystemMT {
     ///
     /// * `parent`: path to the directory to make the entry under.
     /// * `name`: name of the entry.
-    /// * `mode`: mode for the new entry.
+    /// * `mode`: mode for the new entry. The caller's umask is in `req.umask`.
     /// * `rdev`: if mode has the bits `S_IFCHR` or `S_IFBLK` set, this is the major and minor numbers for the device file. Otherwise it should be ignored.
-    fn mknod(&self, _req: RequestInfo, _parent: &Path, _name: &OsStr, _mode: u32, _rdev: u32) -> ResultEntry {
+    fn mknod(&self, _req: RequestInfo, _parent: &FsPath, _name: &OsStr, _mode: u32, _rdev: u32) -> ResultEntry {
         Err(libc::ENOSYS)
     }
 
@@ -213,8 +1026,8 @@ pub trait FilesystemMT {
     ///
     /// * `parent`: path to the directory to make the directory under.
     /// * `name`: name of the directory.
-    /// * `mode`: permissions for the new directory.
-    fn mkdir(&self, _req: RequestInfo, _parent: &Path, _name: &OsStr, _mode: u32) -> ResultEntry {
+    /// * `mode`: permissions for the new directory. The caller's umask is in `req.umask`.
+    fn mkdir(&self, _req: RequestInfo, _parent: &FsPath, _name: &OsStr, _mode: u32) -> ResultEntry {
         Err(libc::ENOSYS)
     }
 
@@ -222,7 +1035,7 @@ pub trait FilesystemMT {
     ///
     /// * `parent`: path to the directory containing the file to delete.
     /// * `name`: name of the file to delete.
-    fn unlink(&self, _req: RequestInfo, _parent: &Path, _name: &OsStr) -> ResultEmpty {
+    fn unlink(&self, _req: RequestInfo, _parent: &FsPath, _name: &OsStr) -> ResultEmpty {
         Err(libc::ENOSYS)
     }
 
@@ -230,7 +1043,7 @@ pub trait FilesystemMT {
     ///
     /// * `parent`: path to the directory containing the directory to delete.
     /// * `name`: name of the directory to delete.
-    fn rmdir(&self, _req: RequestInfo, _parent: &Path, _name: &OsStr) -> ResultEmpty {
+    fn rmdir(&self, _req: RequestInfo, _parent: &FsPath, _name: &OsStr) -> ResultEmpty {
         Err(libc::ENOSYS)
     }
 
@@ -239,7 +1052,7 @@ pub trait FilesystemMT {
     /// * `parent`: path to the directory to make the link in.
     /// * `name`: name of the symbolic link.
     /// * `target`: path (may be relative or absolute) to the target of the link.
-    fn symlink(&self, _req: RequestInfo, _parent: &Path, _name: &OsStr, _target: &Path) -> ResultEntry {
+    fn symlink(&self, _req: RequestInfo, _parent: &FsPath, _name: &OsStr, _target: &Path) -> ResultEntry {
         Err(libc::ENOSYS)
     }
 
@@ -249,7 +1062,7 @@ pub trait FilesystemMT {
     /// * `name`: name of the existing entry.
     /// * `newparent`: path to the directory it should be renamed into (may be the same as `parent`).
     /// * `newname`: name of the new entry.
-    fn rename(&self, _req: RequestInfo, _parent: &Path, _name: &OsStr, _newparent: &Path, _newname: &OsStr) -> ResultEmpty {
+    fn rename(&self, _req: RequestInfo, _parent: &FsPath, _name: &OsStr, _newparent: &FsPath, _newname: &OsStr) -> ResultEmpty {
         Err(libc::ENOSYS)
     }
 
@@ -258,7 +1071,7 @@ pub trait FilesystemMT {
     /// * `path`: path to an existing file.
     /// * `newparent`: path to the directory for the new link.
     /// * `newname`: name for the new link.
-    fn link(&self, _req: RequestInfo, _path: &Path, _newparent: &Path, _newname: &OsStr) -> ResultEntry {
+    fn link(&self, _req: RequestInfo, _path: &FsPath, _newparent: &FsPath, _newname: &OsStr) -> ResultEntry {
         Err(libc::ENOSYS)
     }
 
@@ -267,10 +1080,23 @@ pub trait FilesystemMT {
     /// * `path`: path to the file.
     /// * `flags`: one of `O_RDONLY`, `O_WRONLY`, or `O_RDWR`, plus maybe additional flags.
     ///
-    /// Return a tuple of (file handle, flags). The file handle will be passed to any subsequent
-    /// calls that operate on the file, and can be any value you choose, though it should allow
-    /// your filesystem to identify the file opened even without any path info.
-    fn open(&self, _req: RequestInfo, _path: &Path, _flags: u32) -> ResultOpen {
+    /// Return an `OpenedFile`, with the file handle to pass to any subsequent calls that operate
+    /// on the file, and how the kernel should treat it, such as whether to cache its data.
+    fn open(&self, _req: RequestInfo, _path: &FsPath, _flags: u32) -> ResultOpen {
+        Err(libc::ENOSYS)
+    }
+
+    /// Open a file, returning a `FileHandle` object to handle I/O on it.
+    ///
+    /// This is an alternative to `open`: if implemented, it is called instead, and the returned
+    /// object receives the read, write, flush, fsync, and release calls for the file. If this
+    /// returns `ENOSYS`, FuseMT falls back to calling `open` from then on.
+    ///
+    /// * `path`: path to the file.
+    /// * `flags`: one of `O_RDONLY`, `O_WRONLY`, or `O_RDWR`, plus maybe additional flags.
+    ///
+    /// Return a tuple of (file handle object, flags).
+    fn open_handle(&self, _req: RequestInfo, _path: &FsPath, _flags: u32) -> ResultOpenHandle {
         Err(libc::ENOSYS)
     }
 
@@ -285,10 +1111,12 @@ pub trait FilesystemMT {
     /// * `offset`: offset into the file to start reading.
     /// * `size`: number of bytes to read.
     /// * `callback`: a callback that must be invoked to return the result of the operation: either
-    ///    the result data as a slice, or an error code.
+    ///   the result data as a slice, or an error code.
+    ///
+    /// The caller's lock owner, if the kernel gave it, is in `req.lock_owner`.
     ///
     /// Return the return value from the `callback` function.
-    fn read(&self, _req: RequestInfo, _path: &Path, _fh: u64, _offset: u64, _size: u32, callback: impl FnOnce(ResultSlice<'_>) -> CallbackResult) -> CallbackResult {
+    fn read(&self, _req: RequestInfo, _path: &FsPath, _fh: u64, _offset: u64, _size: u32, callback: impl FnOnce(ResultSlice<'_>) -> CallbackResult) -> CallbackResult {
         callback(Err(libc::ENOSYS))
     }
 
@@ -300,11 +1128,21 @@ pub trait FilesystemMT {
     /// * `data`: the data to write
     /// * `flags`:
     ///
+    /// The caller's lock owner, if the kernel gave it, is in `req.lock_owner`.
+    ///
     /// Return the number of bytes written.
-    fn write(&self, _req: RequestInfo, _path: &Path, _fh: u64, _offset: u64, _data: Vec<u8>, _flags: u32) -> ResultWrite {
+    fn write(&self, _req: RequestInfo, _path: &FsPath, _fh: u64, _offset: u64, _data: Vec<u8>, _flags: u32) -> ResultWrite {
         Err(libc::ENOSYS)
     }
 
+    /// Like `write`, but the result is sent through `reply`, which can be done later, from any
+    /// thread, freeing the worker thread as soon as this returns. FuseMT calls this instead of
+    /// `write`; the default calls `write` and sends its result right away.
+    #[allow(clippy::too_many_arguments)]
+    fn write_deferred(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, data: Vec<u8>, flags: u32, reply: Responder<u32>) {
+        reply.send(self.write(req, path, fh, offset, data, flags))
+    }
+
     /// Called each time a program calls `close` on an open file.
     ///
     /// Note that because file descriptors can be duplicated (by `dup`, `dup2`, `fork`) this may be
@@ -316,10 +1154,16 @@ pub trait FilesystemMT {
     /// * `fh`: file handle returned from the `open` call.
     /// * `lock_owner`: if the filesystem supports locking (`setlk`, `getlk`), remove all locks
     ///   belonging to this lock owner.
-    fn flush(&self, _req: RequestInfo, _path: &Path, _fh: u64, _lock_owner: u64) -> ResultEmpty {
+    fn flush(&self, _req: RequestInfo, _path: &FsPath, _fh: u64, _lock_owner: LockOwner) -> ResultEmpty {
         Err(libc::ENOSYS)
     }
 
+    /// Like `flush`, but the result is sent through `reply`, which can be done later, from any
+    /// thread. The default calls `flush` and sends its result right away.
+    fn flush_deferred(&self, req: RequestInfo, path: &FsPath, fh: u64, lock_owner: LockOwner, reply: Responder<()>) {
+        reply.send(self.flush(req, path, fh, lock_owner))
+    }
+
     /// Called when an open file is closed.
     ///
     /// There will be one of these for each `open` call. After `release`, no more calls will be
@@ -328,13 +1172,25 @@ pub trait FilesystemMT {
     /// * `path`: path to the file.
     /// * `fh`: file handle returned from the `open` call.
     /// * `flags`: the flags passed when the file was opened.
-    /// * `lock_owner`: if the filesystem supports locking (`setlk`, `getlk`), remove all locks
+    /// * `lock_owner`: given if `flock` locks were taken on the file; remove all the locks
     ///   belonging to this lock owner.
     /// * `flush`: whether pending data must be flushed or not.
-    fn release(&self, _req: RequestInfo, _path: &Path, _fh: u64, _flags: u32, _lock_owner: u64, _flush: bool) -> ResultEmpty {
+    fn release(&self, _req: RequestInfo, _path: &FsPath, _fh: u64, _flags: u32, _lock_owner: Option<LockOwner>, _flush: bool) -> ResultEmpty {
         Err(libc::ENOSYS)
     }
 
+    /// Called after `release` when the last open handle to a file which was unlinked (or replaced
+    /// by a rename) while it was open has been closed. Nothing can reach the file's data anymore,
+    /// so this is when its storage can be reclaimed.
+    ///
+    /// This isn't called for files hidden by `FuseMT::silly_rename`; those are deleted with
+    /// `unlink` instead.
+    ///
+    /// * `path`: the path the file had before it was unlinked.
+    /// * `fh`: file handle returned from the `open` call for the last handle, or FuseMT's own
+    ///   handle number if it was opened with `open_handle`.
+    fn last_release_after_unlink(&self, _req: RequestInfo, _path: &FsPath, _fh: u64) {}
+
     /// Write out any pending changes of a file.
     ///
     /// When this returns, data should be written to persistent storage.
@@ -342,7 +1198,22 @@ pub trait FilesystemMT {
     /// * `path`: path to the file.
     /// * `fh`: file handle returned from the `open` call.
     /// * `datasync`: if `false`, also write metadata, otherwise just write file data.
-    fn fsync(&self, _req: RequestInfo, _path: &Path, _fh: u64, _datasync: bool) -> ResultEmpty {
+    fn fsync(&self, _req: RequestInfo, _path: &FsPath, _fh: u64, _datasync: bool) -> ResultEmpty {
+        Err(libc::ENOSYS)
+    }
+
+    /// Like `fsync`, but the result is sent through `reply`, which can be done later, from any
+    /// thread. The default calls `fsync` and sends its result right away.
+    fn fsync_deferred(&self, req: RequestInfo, path: &FsPath, fh: u64, datasync: bool, reply: Responder<()>) {
+        reply.send(self.fsync(req, path, fh, datasync))
+    }
+
+    /// Write out buffered changes to the files whose paths `filter` returns true for. This is
+    /// called from a thread of FuseMT's own, every so often, if `FuseMT::periodic_sync` is used.
+    ///
+    /// If this isn't implemented, FuseMT instead calls `fsync` on each `FileHandle` object (from
+    /// `open_handle` or `create_handle`) which has been written to since it was last synced.
+    fn sync(&self, _filter: &dyn Fn(&Path) -> bool) -> ResultEmpty {
         Err(libc::ENOSYS)
     }
 
@@ -353,10 +1224,23 @@ pub trait FilesystemMT {
     /// * `path`: path to the directory.
     /// * `flags`: file access flags. Will contain `O_DIRECTORY` at least.
     ///
-    /// Return a tuple of (file handle, flags). The file handle will be passed to any subsequent
-    /// calls that operate on the directory, and can be any value you choose, though it should
-    /// allow your filesystem to identify the directory opened even without any path info.
-    fn opendir(&self, _req: RequestInfo, _path: &Path, _flags: u32) -> ResultOpen {
+    /// Return an `OpenedFile`, with the file handle to pass to any subsequent calls that operate
+    /// on the directory, and how the kernel should treat it, such as whether to cache its listing.
+    fn opendir(&self, _req: RequestInfo, _path: &FsPath, _flags: u32) -> ResultOpen {
+        Err(libc::ENOSYS)
+    }
+
+    /// Open a directory, returning a `DirHandle` object to handle calls on it.
+    ///
+    /// This is an alternative to `opendir`: if implemented, it is called instead, and the
+    /// returned object receives the readdir, fsyncdir, and releasedir calls for the directory. If
+    /// this returns `ENOSYS`, FuseMT falls back to calling `opendir` from then on.
+    ///
+    /// * `path`: path to the directory.
+    /// * `flags`: file access flags. Will contain `O_DIRECTORY` at least.
+    ///
+    /// Return a tuple of (directory handle object, flags).
+    fn opendir_handle(&self, _req: RequestInfo, _path: &FsPath, _flags: u32) -> ResultOpenDirHandle {
         Err(libc::ENOSYS)
     }
 
@@ -366,7 +1250,37 @@ pub trait FilesystemMT {
     /// * `fh`: file handle returned from the `opendir` call.
     ///
     /// Return all the entries of the directory.
-    fn readdir(&self, _req: RequestInfo, _path: &Path, _fh: u64) -> ResultReaddir {
+    fn readdir(&self, _req: RequestInfo, _path: &FsPath, _fh: u64) -> ResultReaddir {
+        Err(libc::ENOSYS)
+    }
+
+    /// Like `readdir`, but the entries are sent through `reply`, which can be done later, from any
+    /// thread. FuseMT calls this instead of `readdir`; the default calls `readdir` and sends its
+    /// result right away.
+    ///
+    /// Listings sent later are still cached for the rest of the reads of the directory, but FuseMT
+    /// doesn't prefetch the attributes of their entries.
+    fn readdir_deferred(&self, req: RequestInfo, path: &FsPath, fh: u64, reply: Responder<Vec<DirectoryEntry>>) {
+        reply.send(self.readdir(req, path, fh))
+    }
+
+    /// Get the entries of a directory one page at a time, without FuseMT caching them.
+    ///
+    /// If this is implemented, it is used instead of `readdir`, and FuseMT passes each of the
+    /// kernel's readdir and readdirplus calls straight through to it instead of listing the whole
+    /// directory once and caching it for as long as it's open. This suits directories which are too large to
+    /// hold in memory, or which change too often for a cached listing to be useful.
+    ///
+    /// * `path`: path to the directory.
+    /// * `fh`: file handle returned from the `opendir` call.
+    /// * `offset`: where to continue listing from: 0 to start at the beginning, or one of the
+    ///   offsets previously given to `add`.
+    /// * `add`: call this with each entry, in order, along with the offset of the entry which
+    ///   follows it. It returns `true` if the reply is full, in which case the entry was not added
+    ///   and no more should be given.
+    ///
+    /// Return `Ok` once the reply is full or there are no more entries.
+    fn readdir_at(&self, _req: RequestInfo, _path: &FsPath, _fh: u64, _offset: u64, _add: &mut dyn FnMut(&DirectoryEntry, u64) -> bool) -> ResultEmpty {
         Err(libc::ENOSYS)
     }
 
@@ -377,14 +1291,14 @@ pub trait FilesystemMT {
     /// * `path`: path to the directory.
     /// * `fh`: file handle returned from the `opendir` call.
     /// * `flags`: the file access flags passed to the `opendir` call.
-    fn releasedir(&self, _req: RequestInfo, _path: &Path, _fh: u64, _flags: u32) -> ResultEmpty {
+    fn releasedir(&self, _req: RequestInfo, _path: &FsPath, _fh: u64, _flags: u32) -> ResultEmpty {
         Err(libc::ENOSYS)
     }
 
     /// Write out any pending changes to a directory.
     ///
     /// Analogous to the `fsync` call.
-    fn fsyncdir(&self, _req: RequestInfo, _path: &Path, _fh: u64, _datasync: bool) -> ResultEmpty {
+    fn fsyncdir(&self, _req: RequestInfo, _path: &FsPath, _fh: u64, _datasync: bool) -> ResultEmpty {
         Err(libc::ENOSYS)
     }
 
@@ -393,18 +1307,29 @@ pub trait FilesystemMT {
     /// * `path`: path to some folder in the filesystem.
     ///
     /// See the `Statfs` struct for more details.
-    fn statfs(&self, _req: RequestInfo, _path: &Path) -> ResultStatfs {
+    fn statfs(&self, _req: RequestInfo, _path: &FsPath) -> ResultStatfs {
         Err(libc::ENOSYS)
     }
 
+    /// Get the quota which applies to a directory, if any.
+    ///
+    /// * `path`: path to the directory `statfs` was called on.
+    ///
+    /// If this returns a quota, FuseMT applies it (with `Statfs::with_quota`) to what `statfs`
+    /// returned, so that `df` on a directory with a quota of its own shows that quota's limits.
+    fn quota(&self, _req: RequestInfo, _path: &FsPath) -> ResultQuota {
+        Ok(None)
+    }
+
     /// Set a file extended attribute.
     ///
     /// * `path`: path to the file.
     /// * `name`: attribute name.
     /// * `value`: the data to set the value to.
     /// * `flags`: can be either `XATTR_CREATE` or `XATTR_REPLACE`.
-    /// * `position`: offset into the attribute value to write data.
-    fn setxattr(&self, _req: RequestInfo, _path: &Path, _name: &OsStr, _value: &[u8], _flags: u32, _position: u32) -> ResultEmpty {
+    /// * `position`: where in the value to write; only ever `XattrPosition::ResourceFork` for the
+    ///   macOS resource fork attribute, and `XattrPosition::Normal` otherwise.
+    fn setxattr(&self, _req: RequestInfo, _path: &FsPath, _name: &OsStr, _value: &[u8], _flags: u32, _position: XattrPosition) -> ResultEmpty {
         Err(libc::ENOSYS)
     }
 
@@ -415,8 +1340,9 @@ pub trait FilesystemMT {
     /// * `size`: the maximum number of bytes to read.
     ///
     /// If `size` is 0, return `Xattr::Size(n)` where `n` is the size of the attribute data.
-    /// Otherwise, return `Xattr::Data(data)` with the requested data.
-    fn getxattr(&self, _req: RequestInfo, _path: &Path, _name: &OsStr, _size: u32) -> ResultXattr {
+    /// Otherwise, return `Xattr::Data(data)` with the requested data, or `ERANGE` if it's bigger
+    /// than `size`. `Xattr::sized` does this given the whole value.
+    fn getxattr(&self, _req: RequestInfo, _path: &FsPath, _name: &OsStr, _size: u32) -> ResultXattr {
         Err(libc::ENOSYS)
     }
 
@@ -428,8 +1354,8 @@ pub trait FilesystemMT {
     /// If `size` is 0, return `Xattr::Size(n)` where `n` is the size required for the list of
     /// attribute names.
     /// Otherwise, return `Xattr::Data(data)` where `data` is all the null-terminated attribute
-    /// names.
-    fn listxattr(&self, _req: RequestInfo, _path: &Path, _size: u32) -> ResultXattr {
+    /// names, or `ERANGE` if they don't fit in `size`. `Xattr::list` does this given the names.
+    fn listxattr(&self, _req: RequestInfo, _path: &FsPath, _size: u32) -> ResultXattr {
         Err(libc::ENOSYS)
     }
 
@@ -437,7 +1363,7 @@ pub trait FilesystemMT {
     ///
     /// * `path`: path to the file.
     /// * `name`: name of the attribute to remove.
-    fn removexattr(&self, _req: RequestInfo, _path: &Path, _name: &OsStr) -> ResultEmpty {
+    fn removexattr(&self, _req: RequestInfo, _path: &FsPath, _name: &OsStr) -> ResultEmpty {
         Err(libc::ENOSYS)
     }
 
@@ -448,7 +1374,7 @@ pub trait FilesystemMT {
     ///
     /// Return `Ok(())` if all requested permissions are allowed, otherwise return `Err(EACCES)`
     /// or other error code as appropriate (e.g. `ENOENT` if the file doesn't exist).
-    fn access(&self, _req: RequestInfo, _path: &Path, _mask: u32) -> ResultEmpty {
+    fn access(&self, _req: RequestInfo, _path: &FsPath, _mask: u32) -> ResultEmpty {
         Err(libc::ENOSYS)
     }
 
@@ -456,21 +1382,124 @@ pub trait FilesystemMT {
     ///
     /// * `parent`: path to the directory to create the file in.
     /// * `name`: name of the file to be created.
-    /// * `mode`: the mode to set on the new file.
+    /// * `mode`: the mode to set on the new file. The caller's umask is in `req.umask`.
     /// * `flags`: flags like would be passed to `open`.
     ///
     /// Return a `CreatedEntry` (which contains the new file's attributes as well as a file handle
     /// -- see documentation on `open` for more info on that).
-    fn create(&self, _req: RequestInfo, _parent: &Path, _name: &OsStr, _mode: u32, _flags: u32) -> ResultCreate {
+    fn create(&self, _req: RequestInfo, _parent: &FsPath, _name: &OsStr, _mode: u32, _flags: u32) -> ResultCreate {
         Err(libc::ENOSYS)
     }
 
-    // getlk
+    /// Create and open a new file, returning a `FileHandle` object to handle I/O on it.
+    ///
+    /// This is to `create` what `open_handle` is to `open`. If this returns `ENOSYS`, FuseMT
+    /// falls back to calling `create` from then on.
+    fn create_handle(&self, _req: RequestInfo, _parent: &FsPath, _name: &OsStr, _mode: u32, _flags: u32) -> ResultCreateHandle {
+        Err(libc::ENOSYS)
+    }
+
+    /// Test for a POSIX record lock, for `fcntl(F_GETLK)`. Only called by FuseMT if it was given
+    /// `LockMode::Posix` with `FuseMT::locks`; `ninep` calls it for its clients' locks.
+    ///
+    /// * `path`: path to the file.
+    /// * `fh`: file handle returned from the `open` call.
+    /// * `lock_owner`: the owner asking.
+    /// * `lock`: the lock it would like to take.
+    ///
+    /// Return a lock held by another owner which conflicts with it, or else `lock` with its `typ`
+    /// changed to `F_UNLCK`.
+    fn getlk(&self, _req: RequestInfo, _path: &FsPath, _fh: u64, _lock_owner: LockOwner, _lock: FileLock) -> ResultLock {
+        Err(libc::ENOSYS)
+    }
+
+    /// Take, change, or release a POSIX record lock, for `fcntl(F_SETLK)` and `fcntl(F_SETLKW)`.
+    /// Only called by FuseMT if it was given `LockMode::Posix` with `FuseMT::locks`; `ninep`
+    /// calls it for its clients' locks.
+    ///
+    /// * `path`: path to the file.
+    /// * `fh`: file handle returned from the `open` call.
+    /// * `lock_owner`: the owner of the lock. Its locks must be released when the file is flushed
+    ///   with the same owner (see `LockOwnerTable`).
+    /// * `lock`: the range to lock, and how; a `typ` of `F_UNLCK` releases the range.
+    /// * `sleep`: whether to wait for conflicting locks to be released (`F_SETLKW`), rather than
+    ///   failing with `EAGAIN`. FuseMT makes calls which wait on its worker threads, so the calls
+    ///   which release the locks can come in meanwhile.
+    fn setlk(&self, _req: RequestInfo, _path: &FsPath, _fh: u64, _lock_owner: LockOwner, _lock: FileLock, _sleep: bool) -> ResultEmpty {
+        Err(libc::ENOSYS)
+    }
 
-    // setlk
+    /// Take, change, or release a BSD lock on a whole file, for the `flock` system call.
+    ///
+    /// This is never called unless FuseMT was given `LockMode::Flock` with `FuseMT::locks`;
+    /// otherwise the kernel keeps track of BSD locks itself. The kernel has no call of its own for
+    /// BSD locks, and sends them as POSIX locks of whole files; FuseMT turns those into calls to
+    /// this, which is why it can't pass on both kinds at once.
+    ///
+    /// * `path`: path to the file.
+    /// * `fh`: file handle returned from the `open` call.
+    /// * `lock_owner`: the owner of the lock, which is the same for every descriptor sharing the
+    ///   open file. Its locks must be released when the file is released with the same owner.
+    /// * `operation`: `LOCK_SH` for a shared lock, `LOCK_EX` for an exclusive one, or `LOCK_UN` to
+    ///   release the lock, with `LOCK_NB` added if the caller doesn't want to wait for conflicting
+    ///   locks to be released, in which case fail with `EWOULDBLOCK`. FuseMT makes calls which
+    ///   wait on its worker threads, like `setlk`.
+    fn flock(&self, _req: RequestInfo, _path: &FsPath, _fh: u64, _lock_owner: LockOwner, _operation: i32) -> ResultEmpty {
+        Err(libc::ENOSYS)
+    }
 
     // bmap
 
+    /// Allocate or deallocate space in a file, like the `fallocate` system call.
+    ///
+    /// * `path`: path to the file.
+    /// * `fh`: file handle returned from the `open` call.
+    /// * `offset`: start of the range.
+    /// * `length`: length of the range.
+    /// * `mode`: flags, like `FALLOC_FL_KEEP_SIZE` and `FALLOC_FL_PUNCH_HOLE`. Zero means to
+    ///   allocate the range, extending the file if it goes past the end.
+    ///
+    /// Not called for files opened with `open_handle` or `create_handle`.
+    fn fallocate(&self, _req: RequestInfo, _path: &FsPath, _fh: u64, _offset: u64, _length: u64, _mode: i32) -> ResultEmpty {
+        Err(libc::ENOSYS)
+    }
+
+    /// Find where the next data or hole in a file is, like `lseek` with `SEEK_DATA` or
+    /// `SEEK_HOLE`, so that programs like `cp --sparse` and backup tools can skip over holes
+    /// instead of reading them. The kernel handles the other kinds of seeks itself.
+    ///
+    /// * `path`: path to the file.
+    /// * `fh`: file handle returned from the `open` call.
+    /// * `offset`: where to start looking.
+    /// * `whence`: `SEEK_DATA` or `SEEK_HOLE`.
+    ///
+    /// Return the offset of the start of the first data or hole at or after `offset`. The end of
+    /// the file counts as a hole. If `offset` is at or past the end of the file, fail with
+    /// `ENXIO`. If this returns `ENOSYS`, the kernel stops asking, for the whole mount, and
+    /// treats files as having no holes.
+    ///
+    /// Not called for files opened with `open_handle` or `create_handle`.
+    fn lseek(&self, _req: RequestInfo, _path: &FsPath, _fh: u64, _offset: u64, _whence: i32) -> ResultOffset {
+        Err(libc::ENOSYS)
+    }
+
+    /// Copy a range of data from one file to another, like the `copy_file_range` system call.
+    ///
+    /// This lets the filesystem copy data without it going through the kernel and back. If this
+    /// returns `ENOSYS`, the kernel falls back to reading and writing.
+    ///
+    /// * `path_in`, `fh_in`, `offset_in`: the file to copy from, its handle, and where to start.
+    /// * `path_out`, `fh_out`, `offset_out`: the file to copy to, its handle, and where to start.
+    /// * `len`: the number of bytes to copy.
+    /// * `flags`: currently always zero.
+    ///
+    /// Return the number of bytes copied. Not called for files opened with `open_handle` or
+    /// `create_handle`.
+    #[allow(clippy::too_many_arguments)]
+    fn copy_file_range(&self, _req: RequestInfo, _path_in: &FsPath, _fh_in: u64, _offset_in: u64, _path_out: &FsPath, _fh_out: u64, _offset_out: u64, _len: u64, _flags: u32) -> ResultWrite {
+        Err(libc::ENOSYS)
+    }
+
     /// macOS only: Rename the volume.
     ///
     /// * `name`: new name for the volume
@@ -479,7 +1508,23 @@ pub trait FilesystemMT {
         Err(libc::ENOSYS)
     }
 
-    // exchange (macOS only, undocumented)
+    /// macOS only: Swap two files, atomically, so that each has the other's name. This is how many
+    /// macOS programs save a file safely (with `exchangedata`), by writing the new contents to a
+    /// temporary file and then exchanging it with the original.
+    ///
+    /// * `parent`: path to the directory containing the first file.
+    /// * `name`: name of the first file.
+    /// * `newparent`: path to the directory containing the second file (may be the same as
+    ///   `parent`).
+    /// * `newname`: name of the second file.
+    /// * `options`: options from `exchangedata`, such as `FSOPT_NOFOLLOW`.
+    ///
+    /// FuseMT swaps the two in its inode table afterwards, so each keeps its inode number under
+    /// its new name.
+    #[cfg(target_os = "macos")]
+    fn exchange(&self, _req: RequestInfo, _parent: &FsPath, _name: &OsStr, _newparent: &FsPath, _newname: &OsStr, _options: u64) -> ResultEmpty {
+        Err(libc::ENOSYS)
+    }
 
     /// macOS only: Query extended times (bkuptime and crtime).
     ///
@@ -487,7 +1532,126 @@ pub trait FilesystemMT {
     ///
     /// Return an `XTimes` struct with the times, or other error code as appropriate.
     #[cfg(target_os = "macos")]
-    fn getxtimes(&self, _req: RequestInfo, _path: &Path) -> ResultXTimes {
+    fn getxtimes(&self, _req: RequestInfo, _path: &FsPath) -> ResultXTimes {
         Err(libc::ENOSYS)
     }
+
+    /// macOS only: Set extended times (bkuptime and crtime).
+    ///
+    /// * `path`: path to the file to set the times of.
+    /// * `fh`: a file handle if this is called on an open file.
+    /// * `bkuptime`: the time of the last backup.
+    /// * `crtime`: the time of creation.
+    ///
+    /// By default this calls `utimens_macos` with the same times, so filesystems which already
+    /// implement that don't need to implement this as well.
+    #[cfg(target_os = "macos")]
+    fn setxtimes(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, bkuptime: Option<SystemTime>, crtime: Option<SystemTime>) -> ResultEmpty {
+        self.utimens_macos(req, path, fh, crtime, None, bkuptime, None)
+    }
+
+    /// Choose which lane of worker threads a call should run on. This is called on FuseMT's main
+    /// thread before each call that goes to the worker threads, so it must be quick; it's meant
+    /// for keeping, say, small reads of a metadata database from queueing up behind bulk data
+    /// transfers.
+    ///
+    /// * `op`: the call about to be made. When prefetching is on, this is also called with
+    ///   `Operation::Prefetch` for each read, for the read ahead of it it may lead to.
+    /// * `path`: the path of the file it's for (the source file, for `copy_file_range`).
+    ///
+    /// Return a lane configured with `FuseMT::lane`; the default is `Lane::DEFAULT`.
+    fn classify(&self, _op: Operation, _path: &FsPath) -> Lane {
+        Lane::DEFAULT
+    }
+
+    /// Report how busy the filesystem is, to keep FuseMT from queueing up more calls than it can
+    /// handle. This is called on FuseMT's main thread before each call that goes to the worker
+    /// threads, after `classify`, so it must be quick.
+    ///
+    /// * `op`: the call about to be made.
+    /// * `path`: the path of the file it's for (the source file, for `copy_file_range`).
+    ///
+    /// Return `Load::Overloaded` only for calls whose callers can cope with `EAGAIN`; the default
+    /// is `Load::Normal`.
+    fn load(&self, _op: Operation, _path: &FsPath) -> Load {
+        Load::Normal
+    }
+}
+
+#[test]
+fn test_lock_owner_table() {
+    let mut table = LockOwnerTable::new();
+    let a = LockOwner(1);
+    let b = LockOwner(2);
+
+    table.insert(a, 10);
+    table.insert(a, 20);
+    table.insert(b, 30);
+    assert_eq!(&[10, 20], table.get(a));
+    assert_eq!(3, table.iter().count());
+
+    // Dropping all of an owner's locks removes the owner entirely.
+    table.retain(b, |_| false);
+    assert!(table.get(b).is_empty());
+
+    assert_eq!(vec![10, 20], table.remove_owner(a));
+    assert!(table.is_empty());
+}
+
+#[test]
+fn test_xattr_sized() {
+    assert_eq!(Ok(Xattr::Size(3)), Xattr::sized(b"abc".to_vec(), 0));
+    assert_eq!(Ok(Xattr::Data(b"abc".to_vec())), Xattr::sized(b"abc".to_vec(), 3));
+    assert_eq!(Err(libc::ERANGE), Xattr::sized(b"abc".to_vec(), 2));
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    assert_eq!(Err(libc::E2BIG), Xattr::sized(vec![0; XATTR_MAX + 1], 0));
+
+    assert_eq!(Ok(Xattr::Data(b"user.a\0user.b\0".to_vec())), Xattr::list(["user.a", "user.b"], 100));
+    assert_eq!(Ok(Xattr::Size(0)), Xattr::list(Vec::<&OsStr>::new(), 0));
+}
+
+#[test]
+fn test_xattr_position() {
+    let fork = XattrPosition::new(OsStr::new(RESOURCE_FORK_XATTR), 512);
+    assert_eq!(XattrPosition::ResourceFork { offset: 512 }, fork);
+    assert_eq!(512, fork.offset());
+    assert_eq!(XattrPosition::Normal, XattrPosition::new(OsStr::new("user.a"), 0));
+    assert_eq!(0, XattrPosition::Normal.offset());
+}
+
+#[test]
+fn test_statfs_with_quota() {
+    let statfs = Statfs {
+        blocks: 1000, bfree: 500, bavail: 400, files: 100, ffree: 90,
+        bsize: 4096, namelen: 255, frsize: 4096, fsid: 0, flags: 0,
+    };
+    let quota = Quota { blocks: Some(200), blocks_used: 50, files: None, files_used: 10 };
+    let limited = statfs.with_quota(&quota);
+    assert_eq!((200, 150, 150), (limited.blocks, limited.bfree, limited.bavail));
+    assert_eq!((100, 90), (limited.files, limited.ffree));
+
+    // The quota can't make more space than the filesystem has.
+    let quota = Quota { blocks: Some(2000), blocks_used: 0, files: Some(5), files_used: 9 };
+    let full = statfs.with_quota(&quota);
+    assert_eq!((2000, 500, 400), (full.blocks, full.bfree, full.bavail));
+    assert_eq!((5, 0), (full.files, full.ffree));
+}
+
+#[test]
+fn test_attr_mask() {
+    let mask = AttrMask::TYPE | AttrMask::MODE;
+    assert!(mask.contains(AttrMask::MODE));
+    assert!(!mask.contains(AttrMask::SIZE));
+    assert!(!mask.contains(AttrMask::MODE | AttrMask::SIZE));
+    assert!(AttrMask::ALL.contains(AttrMask::BASIC | AttrMask::BTIME));
+    assert_eq!(AttrMask::ALL, AttrMask::default());
+}
+
+#[test]
+fn test_opened_file_flags() {
+    use open_flags::*;
+    assert_eq!(0, OpenedFile::new(5).flags());
+    let opened = OpenedFile::from((5, FOPEN_DIRECT_IO | FOPEN_KEEP_CACHE | FOPEN_STREAM));
+    assert_eq!(OpenedFile { direct_io: true, keep_cache: true, stream: true, ..OpenedFile::new(5) }, opened);
+    assert_eq!(FOPEN_DIRECT_IO | FOPEN_KEEP_CACHE | FOPEN_STREAM, opened.flags());
 }
@@ -0,0 +1,47 @@
+// Version :: version information for diagnostics.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+use std::fmt;
+
+/// The `fuser` release series FuseMT is built against.
+const FUSER_VERSION: &str = "0.13";
+
+/// The highest FUSE kernel protocol version `fuser` speaks with the features FuseMT enables. The
+/// protocol version used for a mount is the lower of this and the kernel's own version.
+const PROTOCOL_VERSION: (u32, u32) = (7, 8);
+
+/// Versions of FuseMT and the layers under it, from `version()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VersionInfo {
+    /// The version of this crate.
+    pub fuse_mt: &'static str,
+    /// The release series of the `fuser` crate used to talk to the kernel.
+    pub fuser: &'static str,
+    /// The highest FUSE protocol version (major, minor) which can be negotiated with the kernel.
+    pub protocol: (u32, u32),
+}
+
+impl fmt::Display for VersionInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "fuse_mt {} (fuser {}, FUSE protocol {}.{})",
+            self.fuse_mt, self.fuser, self.protocol.0, self.protocol.1)
+    }
+}
+
+/// Get version information about FuseMT, for reporting in diagnostics or `--version` output.
+pub fn version() -> VersionInfo {
+    VersionInfo {
+        fuse_mt: crate::VERSION,
+        fuser: FUSER_VERSION,
+        protocol: PROTOCOL_VERSION,
+    }
+}
+
+#[test]
+fn test_version() {
+    let v = version();
+    assert_eq!(env!("CARGO_PKG_VERSION"), v.fuse_mt);
+    assert!(v.to_string().starts_with(&format!("fuse_mt {} (fuser 0.", v.fuse_mt)));
+}
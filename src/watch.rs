@@ -0,0 +1,236 @@
+// Watch :: watch a directory tree for changes with inotify.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+//! Watching a backing directory tree for changes made behind FUSE's back.
+//!
+//! Passthrough-style filesystems serve files from a directory which other processes can modify
+//! directly. A `Watcher` monitors such a directory tree with inotify and reports every change as a
+//! path relative to the root of the tree, in the same form as the paths FuseMT uses. Feed these to
+//! an `Invalidator` to keep FuseMT's cached directory listings up to date:
+//!
+//! ```ignore
+//! let fuse = FuseMT::new(PassthroughFS::new(&backing_dir), 4);
+//! let invalidator = fuse.invalidator();
+//! let _watcher = Watcher::new(&backing_dir, move |path| invalidator.invalidate(path))?;
+//! fuse_mt::mount(fuse, &mountpoint, &[])?;
+//! ```
+//!
+//! The watch is recursive: new subdirectories are watched as they're created. inotify needs one
+//! watch per directory, so very large trees may need `fs.inotify.max_user_watches` raised.
+
+use std::collections::HashMap;
+use std::ffi::{CString, OsStr};
+use std::io;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::RawFd;
+use std::path::{Path, PathBuf};
+use std::thread::{self, JoinHandle};
+
+const WATCH_MASK: u32 = libc::IN_CREATE | libc::IN_DELETE | libc::IN_MODIFY | libc::IN_ATTRIB
+    | libc::IN_MOVED_FROM | libc::IN_MOVED_TO | libc::IN_DELETE_SELF | libc::IN_MOVE_SELF
+    | libc::IN_ONLYDIR | libc::IN_DONT_FOLLOW;
+
+/// Watches a directory tree for changes on a background thread. The watch stops when this is
+/// dropped.
+#[derive(Debug)]
+pub struct Watcher {
+    stop_fd: RawFd,
+    thread: Option<JoinHandle<()>>,
+}
+
+struct Watches {
+    fd: RawFd,
+    root: PathBuf,
+    /// Watch descriptor to the path of the watched directory, relative to the root.
+    dirs: HashMap<libc::c_int, PathBuf>,
+}
+
+impl Watches {
+    /// Watch the given directory (relative to the root) and all directories under it.
+    fn add_tree(&mut self, rel: &Path) {
+        let full = self.root.join(rel.strip_prefix("/").unwrap_or(rel));
+        let cpath = match CString::new(full.as_os_str().as_bytes()) {
+            Ok(cpath) => cpath,
+            Err(_) => return,
+        };
+        let wd = unsafe { libc::inotify_add_watch(self.fd, cpath.as_ptr(), WATCH_MASK) };
+        if wd == -1 {
+            let e = io::Error::last_os_error();
+            // The directory may have been removed again already, or may not be a directory.
+            if e.raw_os_error() != Some(libc::ENOENT) && e.raw_os_error() != Some(libc::ENOTDIR) {
+                warn!("unable to watch {:?}: {}", full, e);
+            }
+            return;
+        }
+        self.dirs.insert(wd, rel.to_owned());
+
+        if let Ok(entries) = full.read_dir() {
+            for entry in entries.flatten() {
+                if entry.file_type().map(|t| t.is_dir()).unwrap_or(false) {
+                    self.add_tree(&rel.join(entry.file_name()));
+                }
+            }
+        }
+    }
+}
+
+impl Watcher {
+    /// Start watching the directory tree at `root`. The callback is called on a background thread
+    /// with the path (relative to `root`, and starting with a slash) of each entry that is created,
+    /// removed, renamed, or modified.
+    pub fn new(root: impl AsRef<Path>, mut callback: impl FnMut(&Path) + Send + 'static)
+        -> io::Result<Watcher>
+    {
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC | libc::IN_NONBLOCK) };
+        if fd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let stop_fd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC) };
+        if stop_fd == -1 {
+            let e = io::Error::last_os_error();
+            unsafe { libc::close(fd) };
+            return Err(e);
+        }
+
+        let mut watches = Watches {
+            fd,
+            root: root.as_ref().to_owned(),
+            dirs: HashMap::new(),
+        };
+        watches.add_tree(Path::new("/"));
+        if watches.dirs.is_empty() {
+            unsafe {
+                libc::close(fd);
+                libc::close(stop_fd);
+            }
+            return Err(io::Error::new(io::ErrorKind::NotFound,
+                format!("unable to watch {:?}", watches.root)));
+        }
+
+        let thread = thread::Builder::new()
+            .name("fuse_mt watcher".to_owned())
+            .spawn(move || {
+                if let Err(e) = watch_loop(&mut watches, stop_fd, &mut callback) {
+                    error!("directory watcher failed: {}", e);
+                }
+                unsafe { libc::close(watches.fd) };
+            })?;
+
+        Ok(Watcher {
+            stop_fd,
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        let one = 1u64;
+        unsafe {
+            libc::write(self.stop_fd, &one as *const u64 as *const libc::c_void, 8);
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        unsafe { libc::close(self.stop_fd) };
+    }
+}
+
+fn watch_loop(watches: &mut Watches, stop_fd: RawFd, callback: &mut dyn FnMut(&Path))
+    -> io::Result<()>
+{
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let mut fds = [
+            libc::pollfd { fd: watches.fd, events: libc::POLLIN, revents: 0 },
+            libc::pollfd { fd: stop_fd, events: libc::POLLIN, revents: 0 },
+        ];
+        if unsafe { libc::poll(fds.as_mut_ptr(), 2, -1) } == -1 {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(e);
+        }
+        if fds[1].revents != 0 {
+            return Ok(());
+        }
+
+        let n = unsafe { libc::read(watches.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+        if n == -1 {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::Interrupted || e.kind() == io::ErrorKind::WouldBlock {
+                continue;
+            }
+            return Err(e);
+        }
+
+        let mut offset = 0;
+        while offset + mem::size_of::<libc::inotify_event>() <= n as usize {
+            let event: libc::inotify_event = unsafe {
+                std::ptr::read_unaligned(buf[offset ..].as_ptr() as *const libc::inotify_event)
+            };
+            let name_start = offset + mem::size_of::<libc::inotify_event>();
+            let name_bytes = &buf[name_start .. name_start + event.len as usize];
+            let name_len = name_bytes.iter().position(|&b| b == 0).unwrap_or(name_bytes.len());
+            let name = OsStr::from_bytes(&name_bytes[.. name_len]);
+            offset = name_start + event.len as usize;
+
+            if event.mask & libc::IN_Q_OVERFLOW != 0 {
+                // We missed some events, so anything could have changed.
+                warn!("inotify queue overflowed; reporting everything as changed");
+                callback(Path::new("/"));
+                continue;
+            }
+
+            let dir = match watches.dirs.get(&event.wd) {
+                Some(dir) => dir.clone(),
+                None => continue,
+            };
+            if event.mask & libc::IN_IGNORED != 0 {
+                watches.dirs.remove(&event.wd);
+                continue;
+            }
+
+            let path = if name.is_empty() { dir } else { dir.join(name) };
+            if event.mask & libc::IN_ISDIR != 0
+                && event.mask & (libc::IN_CREATE | libc::IN_MOVED_TO) != 0
+            {
+                watches.add_tree(&path);
+            }
+            callback(&path);
+        }
+    }
+}
+
+#[test]
+fn test_watcher() {
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    let root = std::env::temp_dir().join(format!("fuse_mt_watch_test.{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+
+    let (tx, rx) = mpsc::channel();
+    let tx = std::sync::Mutex::new(tx);
+    let watcher = Watcher::new(&root, move |path| tx.lock().unwrap().send(path.to_owned()).unwrap())
+        .unwrap();
+
+    let timeout = Duration::from_secs(5);
+    std::fs::create_dir(root.join("sub")).unwrap();
+    assert_eq!(Path::new("/sub"), rx.recv_timeout(timeout).unwrap());
+
+    // The new directory is watched too.
+    std::fs::write(root.join("sub/file"), b"x").unwrap();
+    loop {
+        if rx.recv_timeout(timeout).unwrap() == Path::new("/sub/file") {
+            break;
+        }
+    }
+
+    drop(watcher);
+    std::fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,601 @@
+// WebDAV :: a FilesystemMT implementation backed by a WebDAV server.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+//! A filesystem which mounts a WebDAV share.
+//!
+//! ```ignore
+//! let fs = WebDavFs::new("http://localhost:8080/dav")?
+//!     .credentials("user", "password")?;
+//! fuse_mt::mount(FuseMT::new(fs, 4), &mountpoint, &[])?;
+//! ```
+//!
+//! Metadata comes from PROPFIND requests and is cached for a configurable time (ten seconds by
+//! default). Files opened read-only are read with Range requests, so nothing is downloaded until
+//! it's actually read. WebDAV has no way to write part of a file, so files opened for writing are
+//! downloaded in full when first written to, and uploaded again when flushed. Responses are
+//! limited to 256 MiB, so files larger than that can be read but not written.
+//!
+//! Both `http://` and `https://` URLs are supported, but credentials are only sent over TLS, or
+//! to a server on the same host; they'd be sent in the clear otherwise. Permissions and ownership
+//! can't be changed, and setting timestamps is silently ignored, since WebDAV has no standard way
+//! to do either.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::ffi::{OsStr, OsString};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::http::{self, HttpClient, Response, Url};
+use crate::types::*;
+use crate::FileType;
+
+const PROPFIND_BODY: &str = "<?xml version=\"1.0\" encoding=\"utf-8\"?>\
+    <D:propfind xmlns:D=\"DAV:\"><D:prop>\
+    <D:resourcetype/><D:getcontentlength/><D:getlastmodified/>\
+    </D:prop></D:propfind>";
+
+/// The properties we care about for each resource.
+#[derive(Clone, Copy, Debug)]
+struct DavAttr {
+    is_dir: bool,
+    size: u64,
+    mtime: SystemTime,
+}
+
+struct Inner {
+    client: HttpClient,
+    auth: Option<String>,
+    cache_ttl: Duration,
+    attr_cache: Mutex<HashMap<PathBuf, (Instant, Option<DavAttr>)>>,
+    /// Sizes of files with changes buffered in open handles, which haven't been uploaded yet.
+    buffered_sizes: Mutex<HashMap<FsPath, u64>>,
+    uid: u32,
+    gid: u32,
+}
+
+/// A `FilesystemMT` over a WebDAV share.
+pub struct WebDavFs {
+    inner: Arc<Inner>,
+}
+
+fn status_errno(status: u16) -> libc::c_int {
+    match status {
+        401 | 403 => libc::EACCES,
+        404 | 409 | 410 => libc::ENOENT,
+        405 | 412 => libc::EEXIST,
+        423 => libc::EBUSY,
+        507 => libc::ENOSPC,
+        _ => libc::EIO,
+    }
+}
+
+fn io_errno(e: io::Error) -> libc::c_int {
+    error!("WebDAV request failed: {}", e);
+    match e.kind() {
+        io::ErrorKind::NotFound => libc::ENOENT,
+        io::ErrorKind::PermissionDenied => libc::EACCES,
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => libc::ETIMEDOUT,
+        _ => crate::errno::io_errno(&e),
+    }
+}
+
+impl Inner {
+    /// The percent-encoded URL path for a filesystem path.
+    fn url_path(&self, path: &Path, dir: bool) -> String {
+        let mut url = self.client.url().path.clone();
+        url += &http::encode(&path.to_string_lossy(), true);
+        if dir && !url.ends_with('/') {
+            url.push('/');
+        }
+        url
+    }
+
+    fn request(&self, method: &str, path: &str, headers: &[(&str, &str)], body: &[u8])
+        -> Result<Response, libc::c_int>
+    {
+        let mut all_headers = headers.to_vec();
+        if let Some(ref auth) = self.auth {
+            all_headers.push(("Authorization", auth));
+        }
+        self.client.request(method, path, &all_headers, body).map_err(io_errno)
+    }
+
+    fn request_ok(&self, method: &str, path: &str, headers: &[(&str, &str)], body: &[u8])
+        -> Result<Response, libc::c_int>
+    {
+        let response = self.request(method, path, headers, body)?;
+        if (200 .. 300).contains(&response.status) {
+            Ok(response)
+        } else {
+            debug!("{} {}: HTTP status {}", method, path, response.status);
+            Err(status_errno(response.status))
+        }
+    }
+
+    /// Get the properties of a resource and, with `depth` 1, its children.
+    fn propfind(&self, path: &Path, depth: u32) -> Result<Vec<(PathBuf, DavAttr)>, libc::c_int> {
+        let depth = depth.to_string();
+        let headers = [("Depth", depth.as_str()), ("Content-Type", "application/xml")];
+        let mut url = self.url_path(path, depth != "0");
+        let mut response = self.request("PROPFIND", &url, &headers, PROPFIND_BODY.as_bytes())?;
+        if response.status == 301 && !url.ends_with('/') {
+            // Collections are usually redirected to the URL with a trailing slash.
+            url.push('/');
+            response = self.request("PROPFIND", &url, &headers, PROPFIND_BODY.as_bytes())?;
+        }
+        if response.status != 207 {
+            return Err(status_errno(response.status));
+        }
+
+        let body = String::from_utf8_lossy(&response.body);
+        let mut results = vec![];
+        for entry in http::xml_elements(&body, "response") {
+            let href = match http::xml_elements(entry, "href").first() {
+                Some(href) => http::decode(&http::xml_unescape(href)),
+                None => continue,
+            };
+            let path = match self.href_to_path(&href) {
+                Some(path) => path,
+                None => continue,
+            };
+            let is_dir = http::xml_elements(entry, "resourcetype").first()
+                .map(|rt| !http::xml_elements(rt, "collection").is_empty())
+                .unwrap_or(false);
+            let size = http::xml_elements(entry, "getcontentlength").first()
+                .and_then(|s| s.trim().parse().ok())
+                .unwrap_or(0);
+            let mtime = http::xml_elements(entry, "getlastmodified").first()
+                .and_then(|t| http::parse_http_date(t.trim()))
+                .unwrap_or(SystemTime::UNIX_EPOCH);
+            results.push((path, DavAttr { is_dir, size, mtime }));
+        }
+        Ok(results)
+    }
+
+    /// Turn an href from a PROPFIND response back into a filesystem path.
+    fn href_to_path(&self, href: &str) -> Option<PathBuf> {
+        // The href may be a full URL or just the path.
+        let href = match href.strip_prefix("http://").or_else(|| href.strip_prefix("https://")) {
+            Some(rest) => &rest[rest.find('/').unwrap_or(rest.len()) ..],
+            None => href,
+        };
+        let base = http::decode(&self.client.url().path);
+        let rel = href.strip_prefix(base.as_str())?;
+        let rel = rel.trim_end_matches('/');
+        Some(PathBuf::from(format!("/{}", rel.trim_start_matches('/'))))
+    }
+
+    fn cached(&self, path: &Path) -> Option<Option<DavAttr>> {
+        let cache = self.attr_cache.lock().unwrap();
+        match cache.get(path) {
+            Some((when, attr)) if when.elapsed() < self.cache_ttl => Some(*attr),
+            _ => None,
+        }
+    }
+
+    fn cache(&self, path: PathBuf, attr: Option<DavAttr>) {
+        self.attr_cache.lock().unwrap().insert(path, (Instant::now(), attr));
+    }
+
+    fn invalidate(&self, path: &Path) {
+        let mut cache = self.attr_cache.lock().unwrap();
+        // Renaming or deleting a directory affects everything under it.
+        cache.retain(|p, _| !p.starts_with(path));
+        if let Some(parent) = path.parent() {
+            cache.remove(parent);
+        }
+    }
+
+    fn lookup(&self, path: &Path) -> Result<DavAttr, libc::c_int> {
+        if let Some(attr) = self.cached(path) {
+            return attr.ok_or(libc::ENOENT);
+        }
+        let attr = match self.propfind(path, 0) {
+            Ok(results) => results.into_iter().next().map(|(_, attr)| attr),
+            Err(libc::ENOENT) => None,
+            Err(e) => return Err(e),
+        };
+        self.cache(path.to_owned(), attr);
+        attr.ok_or(libc::ENOENT)
+    }
+
+    fn file_attr(&self, attr: DavAttr) -> FileAttr {
+        let (kind, perm) = if attr.is_dir {
+            (FileType::Directory, 0o755)
+        } else {
+            (FileType::RegularFile, 0o644)
+        };
+        FileAttr {
+            ino: 0,
+            size: attr.size,
+            blocks: attr.size.div_ceil(512),
+            atime: attr.mtime,
+            mtime: attr.mtime,
+            ctime: attr.mtime,
+            crtime: attr.mtime,
+            kind,
+            perm,
+            nlink: if attr.is_dir { 2 } else { 1 },
+            uid: self.uid,
+            gid: self.gid,
+            rdev: 0,
+            flags: 0,
+        }
+    }
+
+    fn get_all(&self, path: &Path) -> Result<Vec<u8>, libc::c_int> {
+        Ok(self.request_ok("GET", &self.url_path(path, false), &[], &[])?.body)
+    }
+
+    fn put(&self, path: &Path, data: &[u8]) -> ResultEmpty {
+        self.request_ok("PUT", &self.url_path(path, false), &[], data)?;
+        self.invalidate(path);
+        Ok(())
+    }
+}
+
+impl WebDavFs {
+    /// Mount the WebDAV collection at the given URL, e.g. `https://example.com/dav`.
+    pub fn new(url: &str) -> io::Result<WebDavFs> {
+        let url = Url::parse(url)?;
+        Ok(WebDavFs {
+            inner: Arc::new(Inner {
+                client: HttpClient::new(url, Duration::from_secs(30)),
+                auth: None,
+                cache_ttl: Duration::from_secs(10),
+                attr_cache: Mutex::new(HashMap::new()),
+                buffered_sizes: Mutex::new(HashMap::new()),
+                uid: unsafe { libc::geteuid() },
+                gid: unsafe { libc::getegid() },
+            }),
+        })
+    }
+
+    fn inner_mut(&mut self) -> &mut Inner {
+        Arc::get_mut(&mut self.inner).expect("WebDavFs options must be set before mounting")
+    }
+
+    /// Authenticate to the server with HTTP basic authentication.
+    ///
+    /// Basic authentication sends the password as-is, so this fails with `PermissionDenied`
+    /// unless the URL is `https://` or the server is on the loopback interface.
+    pub fn credentials(mut self, user: &str, password: &str) -> io::Result<WebDavFs> {
+        let url = self.inner.client.url();
+        if !url.tls && !http::is_loopback(&url.host) {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied,
+                "refusing to send credentials to a remote host without TLS"));
+        }
+        let token = http::base64(format!("{}:{}", user, password).as_bytes());
+        self.inner_mut().auth = Some(format!("Basic {}", token));
+        Ok(self)
+    }
+
+    /// Set how long metadata is cached for. This is also the TTL given to the kernel.
+    pub fn cache_ttl(mut self, ttl: Duration) -> WebDavFs {
+        self.inner_mut().cache_ttl = ttl;
+        self
+    }
+}
+
+/// An open file. Read-only files are read with Range requests; writable ones are buffered in
+/// memory and uploaded when flushed.
+struct WebDavFile {
+    inner: Arc<Inner>,
+    path: FsPath,
+    writable: bool,
+    buffer: Mutex<WriteBuffer>,
+}
+
+#[derive(Default)]
+struct WriteBuffer {
+    /// The file's full contents, once it has been downloaded or truncated.
+    data: Option<Vec<u8>>,
+    dirty: bool,
+}
+
+impl WebDavFile {
+    fn new(inner: Arc<Inner>, path: FsPath, flags: u32) -> WebDavFile {
+        let accmode = flags as libc::c_int & libc::O_ACCMODE;
+        let mut buffer = WriteBuffer::default();
+        if flags as libc::c_int & libc::O_TRUNC != 0 {
+            buffer = WriteBuffer { data: Some(vec![]), dirty: true };
+            inner.buffered_sizes.lock().unwrap().insert(path.clone(), 0);
+        }
+        WebDavFile {
+            inner,
+            path,
+            writable: accmode != libc::O_RDONLY,
+            buffer: Mutex::new(buffer),
+        }
+    }
+
+    fn upload(&self) -> ResultEmpty {
+        let mut buffer = self.buffer.lock().unwrap();
+        if let (true, Some(data)) = (buffer.dirty, &buffer.data) {
+            self.inner.put(&self.path, data)?;
+            buffer.dirty = false;
+            self.inner.buffered_sizes.lock().unwrap().remove(&self.path);
+        }
+        Ok(())
+    }
+}
+
+impl FileHandle for WebDavFile {
+    fn read(&self, _req: RequestInfo, offset: u64, size: u32) -> ResultData {
+        {
+            let buffer = self.buffer.lock().unwrap();
+            if let Some(ref data) = buffer.data {
+                let start = usize::try_from(offset).unwrap_or(usize::MAX).min(data.len());
+                let end = start.saturating_add(size as usize).min(data.len());
+                return Ok(data[start .. end].to_vec());
+            }
+        }
+        let url = self.inner.url_path(&self.path, false);
+        let auth: Vec<_> = self.inner.auth.iter().map(|auth| ("Authorization", auth.as_str()))
+            .collect();
+        self.inner.client.get_range(&url, &auth, offset, size.into()).map_err(io_errno)
+    }
+
+    fn write(&self, _req: RequestInfo, offset: u64, data: Vec<u8>, _flags: u32) -> ResultWrite {
+        if !self.writable {
+            return Err(libc::EBADF);
+        }
+        let mut buffer = self.buffer.lock().unwrap();
+        if buffer.data.is_none() {
+            buffer.data = Some(self.inner.get_all(&self.path)?);
+        }
+        let contents = buffer.data.as_mut().unwrap();
+        // The whole file is held in memory, so it can't be bigger than the address space.
+        let start = usize::try_from(offset).map_err(|_| libc::EFBIG)?;
+        let end = start.checked_add(data.len()).ok_or(libc::EFBIG)?;
+        if contents.len() < end {
+            contents.resize(end, 0);
+        }
+        contents[start .. end].copy_from_slice(&data);
+        let size = contents.len() as u64;
+        buffer.dirty = true;
+        self.inner.buffered_sizes.lock().unwrap().insert(self.path.clone(), size);
+        Ok(data.len() as u32)
+    }
+
+    fn flush(&self, _req: RequestInfo, _lock_owner: LockOwner) -> ResultEmpty {
+        self.upload()
+    }
+
+    fn fsync(&self, _req: RequestInfo, _datasync: bool) -> ResultEmpty {
+        self.upload()
+    }
+
+    fn release(&self, _req: RequestInfo, _flags: u32, _lock_owner: Option<LockOwner>, _flush: bool) -> ResultEmpty {
+        let result = self.upload();
+        // If the upload failed, the changes are lost now anyway.
+        self.inner.buffered_sizes.lock().unwrap().remove(&self.path);
+        result
+    }
+}
+
+impl FilesystemMT for WebDavFs {
+    fn getattr(&self, _req: RequestInfo, path: &FsPath, _fh: Option<u64>) -> ResultEntry {
+        let mut attr = self.inner.lookup(path)?;
+        // The server doesn't know about writes that are still buffered.
+        if let Some(&size) = self.inner.buffered_sizes.lock().unwrap().get(path) {
+            attr.size = size;
+        }
+        Ok((self.inner.cache_ttl, self.inner.file_attr(attr)))
+    }
+
+    fn truncate(&self, _req: RequestInfo, path: &FsPath, _fh: Option<u64>, size: u64) -> ResultEmpty {
+        let size = usize::try_from(size).map_err(|_| libc::EFBIG)?;
+        let mut data = if size == 0 { vec![] } else { self.inner.get_all(path)? };
+        data.resize(size, 0);
+        self.inner.put(path, &data)
+    }
+
+    fn utimens(&self, _req: RequestInfo, _path: &FsPath, _fh: Option<u64>, _atime: Option<SystemTime>, _mtime: Option<SystemTime>) -> ResultEmpty {
+        Ok(())
+    }
+
+    fn mkdir(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, _mode: u32) -> ResultEntry {
+        let path = FsPath::from(parent.join(name));
+        self.inner.request_ok("MKCOL", &self.inner.url_path(&path, true), &[], &[])?;
+        self.inner.invalidate(&path);
+        self.getattr(req, &path, None)
+    }
+
+    fn unlink(&self, _req: RequestInfo, parent: &FsPath, name: &OsStr) -> ResultEmpty {
+        let path = parent.join(name);
+        if self.inner.lookup(&path)?.is_dir {
+            return Err(libc::EISDIR);
+        }
+        self.inner.request_ok("DELETE", &self.inner.url_path(&path, false), &[], &[])?;
+        self.inner.invalidate(&path);
+        Ok(())
+    }
+
+    fn rmdir(&self, _req: RequestInfo, parent: &FsPath, name: &OsStr) -> ResultEmpty {
+        let path = parent.join(name);
+        // DELETE on a collection deletes everything in it, so check that it's empty first.
+        let mut is_dir = false;
+        let mut empty = true;
+        for (child, attr) in self.inner.propfind(&path, 1)? {
+            if *path == child {
+                is_dir = attr.is_dir;
+            } else {
+                empty = false;
+            }
+        }
+        if !is_dir {
+            return Err(libc::ENOTDIR);
+        }
+        if !empty {
+            return Err(libc::ENOTEMPTY);
+        }
+        self.inner.request_ok("DELETE", &self.inner.url_path(&path, true), &[], &[])?;
+        self.inner.invalidate(&path);
+        Ok(())
+    }
+
+    fn rename(&self, _req: RequestInfo, parent: &FsPath, name: &OsStr, newparent: &FsPath, newname: &OsStr) -> ResultEmpty {
+        let from = parent.join(name);
+        let to = newparent.join(newname);
+        let is_dir = self.inner.lookup(&from)?.is_dir;
+        let destination = self.inner.client.url().origin() + &self.inner.url_path(&to, is_dir);
+        let headers = [("Destination", destination.as_str()), ("Overwrite", "T")];
+        self.inner.request_ok("MOVE", &self.inner.url_path(&from, is_dir), &headers, &[])?;
+        self.inner.invalidate(&from);
+        self.inner.invalidate(&to);
+        Ok(())
+    }
+
+    fn open_handle(&self, _req: RequestInfo, path: &FsPath, flags: u32) -> ResultOpenHandle {
+        if self.inner.lookup(path)?.is_dir {
+            return Err(libc::EISDIR);
+        }
+        let file = WebDavFile::new(Arc::clone(&self.inner), path.clone(), flags);
+        Ok((Box::new(file), 0))
+    }
+
+    fn opendir(&self, _req: RequestInfo, path: &FsPath, _flags: u32) -> ResultOpen {
+        if !self.inner.lookup(path)?.is_dir {
+            return Err(libc::ENOTDIR);
+        }
+        Ok(OpenedFile::new(0))
+    }
+
+    fn readdir(&self, _req: RequestInfo, path: &FsPath, _fh: u64) -> ResultReaddir {
+        let mut entries = vec![
+            DirectoryEntry { name: OsString::from("."), kind: FileType::Directory, offset: None, attr: None },
+            DirectoryEntry { name: OsString::from(".."), kind: FileType::Directory, offset: None, attr: None },
+        ];
+        for (child, attr) in self.inner.propfind(path, 1)? {
+            if *path == child {
+                continue;
+            }
+            if let Some(name) = child.file_name() {
+                let kind = if attr.is_dir { FileType::Directory } else { FileType::RegularFile };
+                entries.push(DirectoryEntry { name: name.to_owned(), kind, offset: None, attr: None });
+            }
+            self.inner.cache(child, Some(attr));
+        }
+        Ok(entries)
+    }
+
+    fn releasedir(&self, _req: RequestInfo, _path: &FsPath, _fh: u64, _flags: u32) -> ResultEmpty {
+        Ok(())
+    }
+
+    fn statfs(&self, _req: RequestInfo, _path: &FsPath) -> ResultStatfs {
+        Ok(Statfs {
+            blocks: 0,
+            bfree: 0,
+            bavail: 0,
+            files: 0,
+            ffree: 0,
+            bsize: 4096,
+            namelen: 255,
+            frsize: 4096,
+            fsid: 0,
+            flags: 0,
+        })
+    }
+
+    fn create_handle(&self, _req: RequestInfo, parent: &FsPath, name: &OsStr, _mode: u32, flags: u32) -> ResultCreateHandle {
+        let path = FsPath::from(parent.join(name));
+        if flags as libc::c_int & libc::O_EXCL != 0 && self.inner.lookup(&path).is_ok() {
+            return Err(libc::EEXIST);
+        }
+        self.inner.put(&path, &[])?;
+        let attr = DavAttr { is_dir: false, size: 0, mtime: SystemTime::now() };
+        self.inner.cache(path.to_path_buf(), Some(attr));
+        let file = WebDavFile::new(Arc::clone(&self.inner), path, flags);
+        *file.buffer.lock().unwrap() = WriteBuffer { data: Some(vec![]), dirty: false };
+        Ok(CreatedHandle {
+            ttl: self.inner.cache_ttl,
+            attr: self.inner.file_attr(attr),
+            handle: Box::new(file),
+            flags: 0,
+        })
+    }
+}
+
+#[test]
+fn test_webdav_readdir() {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut conn = BufReader::new(stream);
+        let mut request = String::new();
+        let mut content_length = 0;
+        loop {
+            let mut line = String::new();
+            conn.read_line(&mut line).unwrap();
+            if let Some(len) = line.strip_prefix("Content-Length: ") {
+                content_length = len.trim().parse().unwrap();
+            }
+            if line == "\r\n" {
+                break;
+            }
+            request += &line;
+        }
+        conn.read_exact(&mut vec![0; content_length]).unwrap();
+        assert!(request.starts_with("PROPFIND /dav/a%20dir/ HTTP/1.1"));
+        assert!(request.contains("Depth: 1"));
+        let body = "<?xml version=\"1.0\"?><d:multistatus xmlns:d=\"DAV:\">\
+            <d:response><d:href>/dav/a%20dir/</d:href><d:propstat><d:prop>\
+            <d:resourcetype><d:collection/></d:resourcetype></d:prop></d:propstat></d:response>\
+            <d:response><d:href>http://127.0.0.1/dav/a%20dir/f.txt</d:href><d:propstat><d:prop>\
+            <d:resourcetype/><d:getcontentlength>12</d:getcontentlength>\
+            <d:getlastmodified>Sun, 06 Nov 1994 08:49:37 GMT</d:getlastmodified>\
+            </d:prop></d:propstat></d:response>\
+            <d:response><d:href>/dav/a%20dir/sub/</d:href><d:propstat><d:prop>\
+            <d:resourcetype><d:collection/></d:resourcetype></d:prop></d:propstat></d:response>\
+            </d:multistatus>";
+        write!(conn.get_mut(), "HTTP/1.1 207 Multi-Status\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(), body).unwrap();
+    });
+
+    let fs = WebDavFs::new(&format!("http://127.0.0.1:{}/dav", port)).unwrap();
+    let req = RequestInfo { unique: 0, uid: 0, gid: 0, pid: 0, node: None, umask: None, lock_owner: None };
+    let names: Vec<OsString> = fs.readdir(req, &FsPath::from("/a dir"), 0).unwrap()
+        .into_iter().map(|e| e.name).collect();
+    assert_eq!(vec![".", "..", "f.txt", "sub"], names);
+    server.join().unwrap();
+
+    // The listing populated the cache, so this doesn't need the server.
+    let (_, attr) = fs.getattr(req, &FsPath::from("/a dir/f.txt"), None).unwrap();
+    assert_eq!(12, attr.size);
+    let (_, attr) = fs.getattr(req, &FsPath::from("/a dir/sub"), None).unwrap();
+    assert_eq!(FileType::Directory, attr.kind);
+}
+
+#[test]
+fn test_webdav_buffered_size() {
+    let fs = WebDavFs::new("http://192.0.2.1/dav").unwrap();
+    assert_eq!(io::ErrorKind::PermissionDenied,
+        fs.credentials("user", "password").err().map(|e| e.kind()).unwrap());
+    assert!(WebDavFs::new("https://192.0.2.1/dav").unwrap().credentials("user", "password")
+        .is_ok());
+    let fs = WebDavFs::new("http://127.0.0.1:1/dav").unwrap().credentials("user", "password")
+        .unwrap();
+
+    // Pretend the file was listed, so getattr doesn't need the server.
+    let path = &FsPath::from("/f");
+    let attr = DavAttr { is_dir: false, size: 3, mtime: SystemTime::UNIX_EPOCH };
+    fs.inner.cache(path.to_path_buf(), Some(attr));
+
+    let req = RequestInfo { unique: 0, uid: 0, gid: 0, pid: 0, node: None, umask: None, lock_owner: None };
+    let flags = (libc::O_WRONLY | libc::O_TRUNC) as u32;
+    let file = WebDavFile::new(Arc::clone(&fs.inner), path.clone(), flags);
+    assert_eq!(0, fs.getattr(req, path, None).unwrap().1.size);
+    file.write(req, 0, b"hello world".to_vec(), 0).unwrap();
+    assert_eq!(11, fs.getattr(req, path, None).unwrap().1.size);
+}
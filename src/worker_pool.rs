@@ -0,0 +1,90 @@
+// Worker Pool :: the threads calls are handed to, which can be waited on to start them.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+use std::sync::{Arc, Condvar, Mutex};
+
+use threadpool::ThreadPool;
+
+/// A pool of worker threads, which keeps count of the calls queued for it, so that they can be
+/// waited on to start without polling.
+#[derive(Clone, Debug)]
+pub struct WorkerPool {
+    threads: ThreadPool,
+    queue: Arc<Queue>,
+}
+
+#[derive(Debug, Default)]
+struct Queue {
+    queued: Mutex<usize>,
+    started: Condvar,
+}
+
+impl WorkerPool {
+    pub fn new(num_threads: usize) -> WorkerPool {
+        WorkerPool {
+            threads: ThreadPool::new(num_threads),
+            queue: Arc::new(Queue::default()),
+        }
+    }
+
+    /// Queue a call to run on one of the threads.
+    pub fn execute<F: FnOnce() + Send + 'static>(&self, f: F) {
+        *self.queue.queued.lock().unwrap() += 1;
+        let queue = self.queue.clone();
+        self.threads.execute(move || {
+            let mut queued = queue.queued.lock().unwrap();
+            *queued -= 1;
+            if *queued == 0 {
+                queue.started.notify_all();
+            }
+            drop(queued);
+            f();
+        });
+    }
+
+    /// Wait until the threads have started on every call queued for them.
+    pub fn wait_for_queue(&self) {
+        let mut queued = self.queue.queued.lock().unwrap();
+        while *queued > 0 {
+            queued = self.queue.started.wait(queued).unwrap();
+        }
+    }
+
+    pub fn set_num_threads(&mut self, num_threads: usize) {
+        self.threads.set_num_threads(num_threads);
+    }
+
+    /// Wait until every call queued has finished.
+    #[cfg(test)]
+    pub fn join(&self) {
+        self.threads.join();
+    }
+}
+
+#[test]
+fn test_wait_for_queue() {
+    use std::sync::mpsc;
+
+    let pool = WorkerPool::new(1);
+    let (release_tx, release) = mpsc::channel::<()>();
+    let (started_tx, started) = mpsc::channel();
+    pool.execute(move || release.recv().unwrap());
+    for i in 0 .. 2 {
+        let started_tx = started_tx.clone();
+        pool.execute(move || started_tx.send(i).unwrap());
+    }
+
+    // Nothing queued starts while the only thread is busy; then everything does.
+    let waiter = {
+        let pool = pool.clone();
+        std::thread::spawn(move || pool.wait_for_queue())
+    };
+    assert!(started.recv_timeout(std::time::Duration::from_millis(50)).is_err());
+    assert!(!waiter.is_finished());
+    release_tx.send(()).unwrap();
+    waiter.join().unwrap();
+    pool.join();
+    assert_eq!(vec![0, 1], started.try_iter().collect::<Vec<_>>());
+}
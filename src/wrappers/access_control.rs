@@ -0,0 +1,467 @@
+// Access Control :: per-user access policies on top of another filesystem.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+use std::ffi::OsStr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use crate::groups::GroupCache;
+use crate::types::*;
+
+/// What a caller is allowed to do.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    /// Every call fails with `EACCES`.
+    Deny,
+    /// Calls which would modify the filesystem fail with `EACCES`.
+    ReadOnly,
+    /// Everything is passed through to the inner filesystem.
+    ReadWrite,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Rule {
+    Uid(u32),
+    Gid(u32),
+    Pid(u32),
+}
+
+/// A list of rules mapping callers to the access they get.
+///
+/// Rules are checked in the order they were added, and the first one that matches the caller's
+/// uid, gid, or pid decides their access. Callers that match no rule get the default access.
+///
+/// ```ignore
+/// // Only uid 1000 may write; everyone else can only read.
+/// let policy = AccessPolicy::new(Access::ReadOnly)
+///     .uid(1000, Access::ReadWrite);
+/// ```
+///
+/// Group rules only check the caller's primary group, unless a `GroupCache` is given with
+/// `supplementary_groups`.
+#[derive(Clone, Debug)]
+pub struct AccessPolicy {
+    rules: Vec<(Rule, Access)>,
+    default: Access,
+    groups: Option<Arc<GroupCache>>,
+}
+
+impl AccessPolicy {
+    /// Make a policy which gives every caller the given access.
+    pub fn new(default: Access) -> AccessPolicy {
+        AccessPolicy {
+            rules: vec![],
+            default,
+            groups: None,
+        }
+    }
+
+    /// Add a rule for callers with the given user ID.
+    pub fn uid(mut self, uid: u32, access: Access) -> AccessPolicy {
+        self.rules.push((Rule::Uid(uid), access));
+        self
+    }
+
+    /// Add a rule for callers with the given group ID.
+    pub fn gid(mut self, gid: u32, access: Access) -> AccessPolicy {
+        self.rules.push((Rule::Gid(gid), access));
+        self
+    }
+
+    /// Add a rule for calls from the given process ID.
+    pub fn pid(mut self, pid: u32, access: Access) -> AccessPolicy {
+        self.rules.push((Rule::Pid(pid), access));
+        self
+    }
+
+    /// Match group rules against all of the caller's groups, as looked up by the given cache,
+    /// rather than just their primary group.
+    pub fn supplementary_groups(mut self, groups: Arc<GroupCache>) -> AccessPolicy {
+        self.groups = Some(groups);
+        self
+    }
+
+    /// Get the access the caller making the given request has.
+    pub fn check(&self, req: &RequestInfo) -> Access {
+        self.rules.iter()
+            .find(|(rule, _)| match *rule {
+                Rule::Uid(uid) => uid == req.uid,
+                Rule::Gid(gid) => match self.groups {
+                    Some(ref groups) => groups.in_group(req, gid),
+                    None => gid == req.gid,
+                },
+                Rule::Pid(pid) => pid == req.pid,
+            })
+            .map(|&(_, access)| access)
+            .unwrap_or(self.default)
+    }
+}
+
+/// A filesystem which enforces an `AccessPolicy` on every call before passing it on to an inner
+/// filesystem.
+///
+/// This is meant for mounts using the `allow_other` option, where users other than the one running
+/// the filesystem can reach it. The policy is applied on top of whatever permission checks the
+/// inner filesystem (or the kernel, with `default_permissions`) does.
+#[derive(Debug)]
+pub struct AccessControlFs<FS> {
+    inner: FS,
+    policy: AccessPolicy,
+}
+
+impl<FS: FilesystemMT> AccessControlFs<FS> {
+    pub fn new(inner: FS, policy: AccessPolicy) -> AccessControlFs<FS> {
+        AccessControlFs { inner, policy }
+    }
+
+    /// Fail unless the caller has at least read-only access.
+    fn check_read(&self, req: &RequestInfo) -> Result<(), libc::c_int> {
+        match self.policy.check(req) {
+            Access::Deny => Err(libc::EACCES),
+            Access::ReadOnly | Access::ReadWrite => Ok(()),
+        }
+    }
+
+    /// Fail unless the caller has read-write access.
+    fn check_write(&self, req: &RequestInfo) -> Result<(), libc::c_int> {
+        match self.policy.check(req) {
+            Access::Deny | Access::ReadOnly => Err(libc::EACCES),
+            Access::ReadWrite => Ok(()),
+        }
+    }
+
+    /// Check access for opening a file with the given flags.
+    fn open_flags(&self, req: &RequestInfo, flags: u32) -> Result<(), libc::c_int> {
+        let flags = flags as libc::c_int;
+        if flags & libc::O_ACCMODE != libc::O_RDONLY || flags & libc::O_TRUNC != 0 {
+            self.check_write(req)
+        } else {
+            self.check_read(req)
+        }
+    }
+}
+
+impl<FS: FilesystemMT> FilesystemMT for AccessControlFs<FS> {
+    // These don't act on behalf of a caller, or just clean up after calls which were allowed.
+    forward_calls!(inner; init, init_connection, destroy, flush, flush_deferred, release,
+        last_release_after_unlink, getlk, setlk, flock, releasedir, classify, load);
+
+    fn getattr(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>) -> ResultEntry {
+        self.check_read(&req)?;
+        self.inner.getattr(req, path, fh)
+    }
+
+    fn getattr_mask(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, mask: AttrMask) -> ResultEntry {
+        self.check_read(&req)?;
+        self.inner.getattr_mask(req, path, fh, mask)
+    }
+
+    fn getattr_deferred(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, reply: Responder<(Duration, FileAttr)>) {
+        if let Err(e) = self.check_read(&req) {
+            return reply.send(Err(e));
+        }
+        self.inner.getattr_deferred(req, path, fh, reply)
+    }
+
+    fn chmod(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, mode: u32) -> ResultEmpty {
+        self.check_write(&req)?;
+        self.inner.chmod(req, path, fh, mode)
+    }
+
+    fn chown(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, uid: Option<u32>, gid: Option<u32>) -> ResultEmpty {
+        self.check_write(&req)?;
+        self.inner.chown(req, path, fh, uid, gid)
+    }
+
+    fn truncate(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, size: u64) -> ResultEmpty {
+        self.check_write(&req)?;
+        self.inner.truncate(req, path, fh, size)
+    }
+
+    fn utimens(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, atime: Option<SystemTime>, mtime: Option<SystemTime>) -> ResultEmpty {
+        self.check_write(&req)?;
+        self.inner.utimens(req, path, fh, atime, mtime)
+    }
+
+    fn utimens_macos(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, crtime: Option<SystemTime>, chgtime: Option<SystemTime>, bkuptime: Option<SystemTime>, flags: Option<u32>) -> ResultEmpty {
+        self.check_write(&req)?;
+        self.inner.utimens_macos(req, path, fh, crtime, chgtime, bkuptime, flags)
+    }
+
+    fn readlink(&self, req: RequestInfo, path: &FsPath) -> ResultData {
+        self.check_read(&req)?;
+        self.inner.readlink(req, path)
+    }
+
+    fn mknod(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, mode: u32, rdev: u32) -> ResultEntry {
+        self.check_write(&req)?;
+        self.inner.mknod(req, parent, name, mode, rdev)
+    }
+
+    fn mkdir(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, mode: u32) -> ResultEntry {
+        self.check_write(&req)?;
+        self.inner.mkdir(req, parent, name, mode)
+    }
+
+    fn unlink(&self, req: RequestInfo, parent: &FsPath, name: &OsStr) -> ResultEmpty {
+        self.check_write(&req)?;
+        self.inner.unlink(req, parent, name)
+    }
+
+    fn rmdir(&self, req: RequestInfo, parent: &FsPath, name: &OsStr) -> ResultEmpty {
+        self.check_write(&req)?;
+        self.inner.rmdir(req, parent, name)
+    }
+
+    fn symlink(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, target: &Path) -> ResultEntry {
+        self.check_write(&req)?;
+        self.inner.symlink(req, parent, name, target)
+    }
+
+    fn rename(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, newparent: &FsPath, newname: &OsStr) -> ResultEmpty {
+        self.check_write(&req)?;
+        self.inner.rename(req, parent, name, newparent, newname)
+    }
+
+    fn link(&self, req: RequestInfo, path: &FsPath, newparent: &FsPath, newname: &OsStr) -> ResultEntry {
+        self.check_write(&req)?;
+        self.inner.link(req, path, newparent, newname)
+    }
+
+    fn open(&self, req: RequestInfo, path: &FsPath, flags: u32) -> ResultOpen {
+        self.open_flags(&req, flags)?;
+        self.inner.open(req, path, flags)
+    }
+
+    fn open_handle(&self, req: RequestInfo, path: &FsPath, flags: u32) -> ResultOpenHandle {
+        self.open_flags(&req, flags)?;
+        self.inner.open_handle(req, path, flags)
+    }
+
+    fn read(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, size: u32, callback: impl FnOnce(ResultSlice<'_>) -> CallbackResult) -> CallbackResult {
+        if let Err(e) = self.check_read(&req) {
+            return callback(Err(e));
+        }
+        self.inner.read(req, path, fh, offset, size, callback)
+    }
+
+    fn write(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, data: Vec<u8>, flags: u32) -> ResultWrite {
+        self.check_write(&req)?;
+        self.inner.write(req, path, fh, offset, data, flags)
+    }
+
+    fn write_deferred(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, data: Vec<u8>, flags: u32, reply: Responder<u32>) {
+        if let Err(e) = self.check_write(&req) {
+            return reply.send(Err(e));
+        }
+        self.inner.write_deferred(req, path, fh, offset, data, flags, reply)
+    }
+
+    fn fallocate(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, length: u64, mode: i32) -> ResultEmpty {
+        self.check_write(&req)?;
+        self.inner.fallocate(req, path, fh, offset, length, mode)
+    }
+
+    fn lseek(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, whence: i32) -> ResultOffset {
+        self.check_read(&req)?;
+        self.inner.lseek(req, path, fh, offset, whence)
+    }
+
+    fn copy_file_range(&self, req: RequestInfo, path_in: &FsPath, fh_in: u64, offset_in: u64, path_out: &FsPath, fh_out: u64, offset_out: u64, len: u64, flags: u32) -> ResultWrite {
+        self.check_write(&req)?;
+        self.inner.copy_file_range(req, path_in, fh_in, offset_in, path_out, fh_out, offset_out, len, flags)
+    }
+
+    fn fsync(&self, req: RequestInfo, path: &FsPath, fh: u64, datasync: bool) -> ResultEmpty {
+        self.check_read(&req)?;
+        self.inner.fsync(req, path, fh, datasync)
+    }
+
+    fn fsync_deferred(&self, req: RequestInfo, path: &FsPath, fh: u64, datasync: bool, reply: Responder<()>) {
+        if let Err(e) = self.check_read(&req) {
+            return reply.send(Err(e));
+        }
+        self.inner.fsync_deferred(req, path, fh, datasync, reply)
+    }
+
+    fn sync(&self, filter: &dyn Fn(&Path) -> bool) -> ResultEmpty {
+        // This isn't made on anyone's behalf, so there's nothing to check.
+        self.inner.sync(filter)
+    }
+
+    fn opendir(&self, req: RequestInfo, path: &FsPath, flags: u32) -> ResultOpen {
+        self.check_read(&req)?;
+        self.inner.opendir(req, path, flags)
+    }
+
+    fn opendir_handle(&self, req: RequestInfo, path: &FsPath, flags: u32) -> ResultOpenDirHandle {
+        self.check_read(&req)?;
+        self.inner.opendir_handle(req, path, flags)
+    }
+
+    fn readdir(&self, req: RequestInfo, path: &FsPath, fh: u64) -> ResultReaddir {
+        self.check_read(&req)?;
+        self.inner.readdir(req, path, fh)
+    }
+
+    fn readdir_deferred(&self, req: RequestInfo, path: &FsPath, fh: u64, reply: Responder<Vec<DirectoryEntry>>) {
+        if let Err(e) = self.check_read(&req) {
+            return reply.send(Err(e));
+        }
+        self.inner.readdir_deferred(req, path, fh, reply)
+    }
+
+    fn readdir_at(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, add: &mut dyn FnMut(&DirectoryEntry, u64) -> bool) -> ResultEmpty {
+        self.check_read(&req)?;
+        self.inner.readdir_at(req, path, fh, offset, add)
+    }
+
+    fn fsyncdir(&self, req: RequestInfo, path: &FsPath, fh: u64, datasync: bool) -> ResultEmpty {
+        self.check_read(&req)?;
+        self.inner.fsyncdir(req, path, fh, datasync)
+    }
+
+    fn statfs(&self, req: RequestInfo, path: &FsPath) -> ResultStatfs {
+        self.check_read(&req)?;
+        self.inner.statfs(req, path)
+    }
+
+    fn quota(&self, req: RequestInfo, path: &FsPath) -> ResultQuota {
+        self.check_read(&req)?;
+        self.inner.quota(req, path)
+    }
+
+    fn setxattr(&self, req: RequestInfo, path: &FsPath, name: &OsStr, value: &[u8], flags: u32, position: XattrPosition) -> ResultEmpty {
+        self.check_write(&req)?;
+        self.inner.setxattr(req, path, name, value, flags, position)
+    }
+
+    fn getxattr(&self, req: RequestInfo, path: &FsPath, name: &OsStr, size: u32) -> ResultXattr {
+        self.check_read(&req)?;
+        self.inner.getxattr(req, path, name, size)
+    }
+
+    fn listxattr(&self, req: RequestInfo, path: &FsPath, size: u32) -> ResultXattr {
+        self.check_read(&req)?;
+        self.inner.listxattr(req, path, size)
+    }
+
+    fn removexattr(&self, req: RequestInfo, path: &FsPath, name: &OsStr) -> ResultEmpty {
+        self.check_write(&req)?;
+        self.inner.removexattr(req, path, name)
+    }
+
+    fn access(&self, req: RequestInfo, path: &FsPath, mask: u32) -> ResultEmpty {
+        if mask as libc::c_int & libc::W_OK != 0 {
+            self.check_write(&req)?;
+        } else {
+            self.check_read(&req)?;
+        }
+        self.inner.access(req, path, mask)
+    }
+
+    fn create(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, mode: u32, flags: u32) -> ResultCreate {
+        self.check_write(&req)?;
+        self.inner.create(req, parent, name, mode, flags)
+    }
+
+    fn create_handle(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, mode: u32, flags: u32) -> ResultCreateHandle {
+        self.check_write(&req)?;
+        self.inner.create_handle(req, parent, name, mode, flags)
+    }
+
+    #[cfg(target_os = "macos")]
+    fn setvolname(&self, req: RequestInfo, name: &OsStr) -> ResultEmpty {
+        self.check_write(&req)?;
+        self.inner.setvolname(req, name)
+    }
+
+    #[cfg(target_os = "macos")]
+    fn exchange(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, newparent: &FsPath, newname: &OsStr, options: u64) -> ResultEmpty {
+        self.check_write(&req)?;
+        self.inner.exchange(req, parent, name, newparent, newname, options)
+    }
+
+    #[cfg(target_os = "macos")]
+    fn getxtimes(&self, req: RequestInfo, path: &FsPath) -> ResultXTimes {
+        self.check_read(&req)?;
+        self.inner.getxtimes(req, path)
+    }
+
+    #[cfg(target_os = "macos")]
+    fn setxtimes(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, bkuptime: Option<SystemTime>, crtime: Option<SystemTime>) -> ResultEmpty {
+        self.check_write(&req)?;
+        self.inner.setxtimes(req, path, fh, bkuptime, crtime)
+    }
+}
+
+#[test]
+fn test_access_policy() {
+    struct Fs;
+    impl FilesystemMT for Fs {
+        fn mkdir(&self, _req: RequestInfo, _parent: &FsPath, _name: &OsStr, _mode: u32) -> ResultEntry {
+            Err(libc::EROFS)
+        }
+        fn readlink(&self, _req: RequestInfo, _path: &FsPath) -> ResultData {
+            Ok(b"target".to_vec())
+        }
+    }
+
+    let fs = AccessControlFs::new(Fs, AccessPolicy::new(Access::ReadOnly)
+        .uid(1000, Access::ReadWrite)
+        .pid(42, Access::Deny)
+        .gid(100, Access::ReadWrite));
+    let req = |uid, gid, pid| RequestInfo { unique: 0, uid, gid, pid, node: None, umask: None, lock_owner: None };
+    let name = OsStr::new("x");
+
+    // Allowed calls reach the inner filesystem.
+    assert_eq!(Err(libc::EROFS), fs.mkdir(req(1000, 1000, 1), &FsPath::from("/"), name, 0o755).map(|_| ()));
+    assert_eq!(Err(libc::EROFS), fs.mkdir(req(1001, 100, 1), &FsPath::from("/"), name, 0o755).map(|_| ()));
+
+    // Read-only callers can read but not write.
+    assert_eq!(Ok(b"target".to_vec()), fs.readlink(req(1001, 1001, 1), &FsPath::from("/l")));
+    assert_eq!(Err(libc::EACCES), fs.mkdir(req(1001, 1001, 1), &FsPath::from("/"), name, 0o755).map(|_| ()));
+    assert_eq!(Err(libc::EACCES), fs.open(req(1001, 1001, 1), &FsPath::from("/f"), libc::O_WRONLY as u32));
+
+    // The first matching rule wins: the pid rule comes before the gid rule.
+    assert_eq!(Err(libc::EACCES), fs.readlink(req(1001, 100, 42), &FsPath::from("/l")));
+    assert_eq!(Ok(b"target".to_vec()), fs.readlink(req(1000, 100, 42), &FsPath::from("/l")));
+
+    // With supplementary groups, a group rule matches callers in any of their groups.
+    let groups = Arc::new(GroupCache::new(std::time::Duration::from_secs(1)));
+    let caller = req(1001, 1001, std::process::id());
+    let policy = AccessPolicy::new(Access::Deny).supplementary_groups(groups.clone());
+    for &gid in groups.groups(&caller).iter() {
+        assert_eq!(Access::ReadOnly, policy.clone().gid(gid, Access::ReadOnly).check(&caller));
+    }
+    assert_eq!(Access::Deny, policy.gid(54321, Access::ReadOnly).check(&caller));
+}
+
+#[test]
+fn test_deferred() {
+    use super::test_fs::DeferredFs;
+
+    let inner = DeferredFs::new();
+    inner.mem.nodes.lock().unwrap().insert("/f".into(), Some(vec![]));
+    let fs = AccessControlFs::new(inner, AccessPolicy::new(Access::ReadOnly)
+        .uid(1000, Access::ReadWrite));
+    let req = |uid| RequestInfo { unique: 0, uid, gid: uid, pid: 1, node: None, umask: None, lock_owner: None };
+    let (root, file) = (&FsPath::from("/"), &FsPath::from("/f"));
+
+    // Allowed calls reach the inner filesystem's deferred methods.
+    let written = Responder::wait(|reply| fs.write_deferred(req(1000), file, 0, 0, b"abc".to_vec(), 0, reply));
+    assert_eq!(Ok(3), written);
+    let attr = Responder::wait(|reply| fs.getattr_deferred(req(1001), file, None, reply));
+    assert_eq!(Ok(3), attr.map(|(_, attr)| attr.size));
+    let entries = Responder::wait(|reply| fs.readdir_deferred(req(1001), root, 0, reply));
+    assert_eq!(vec!["f"], entries.unwrap().iter().map(|entry| entry.name.to_str().unwrap()).collect::<Vec<_>>());
+    assert_eq!(Ok(()), Responder::wait(|reply| fs.fsync_deferred(req(1001), file, 0, false, reply)));
+
+    // Others are refused before getting there.
+    let written = Responder::wait(|reply| fs.write_deferred(req(1001), file, 0, 0, b"x".to_vec(), 0, reply));
+    assert_eq!(Err(libc::EACCES), written);
+    assert_eq!(Some(b"abc".to_vec()), fs.inner.mem.nodes.lock().unwrap()[file.as_path()]);
+}
@@ -0,0 +1,806 @@
+// Dedup :: storing file data as content-addressed chunks.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsStr;
+use std::path::{Component, Path};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
+
+use crate::types::*;
+use super::{read_all, write_all};
+
+/// Name of the directory at the root of the inner filesystem where chunks and the index are kept.
+const META_DIR: &str = ".dedup";
+
+/// Default size of the chunks files are split into.
+const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Number of modified chunks an open file can have in memory before they're stored.
+const MAX_PENDING_CHUNKS: usize = 16;
+
+/// A filesystem which stores the contents of files as chunks in an inner filesystem, keeping only
+/// one copy of each distinct chunk.
+///
+/// Files are split into fixed-size chunks, and each chunk is stored once, under a name derived
+/// from its contents, in a hidden `/.dedup` directory of the inner filesystem. In place of each
+/// regular file, the inner filesystem holds a small manifest listing its size and chunks. A
+/// persistent index in `/.dedup/index` counts the references to each chunk, so chunks are deleted
+/// when the last file using them is deleted or truncated. Chunks which are entirely zero aren't
+/// stored at all.
+///
+/// Everything other than file contents (directories, symlinks, permissions, xattrs, and so on) is
+/// passed through to the inner filesystem as-is. The inner filesystem should start out empty and
+/// be used only through this wrapper, since files in it are expected to be manifests.
+///
+/// Writes are buffered per open file and stored on flush, fsync, and release. Updates are ordered
+/// so that a crash can leave chunks which nothing refers to, but never deletes one which is still
+/// in use. Hard links to files are allowed; chunks are released when the last link is removed.
+pub struct DedupFs<FS> {
+    inner: FS,
+    chunk_size: usize,
+    /// Number of references to each stored chunk, by ID.
+    index: Mutex<HashMap<String, u64>>,
+    /// Open files, by path. Every handle to the same file shares one of these.
+    files: Mutex<HashMap<FsPath, Arc<Mutex<OpenFile>>>>,
+    handles: Mutex<HashMap<u64, Arc<Mutex<OpenFile>>>>,
+    next_fh: AtomicU64,
+}
+
+/// The size and chunk IDs of a file. `None` is a chunk of all zeroes.
+#[derive(Debug, Default, PartialEq)]
+struct Manifest {
+    size: u64,
+    chunks: Vec<Option<String>>,
+}
+
+struct OpenFile {
+    path: FsPath,
+    /// The file as of the last time it was stored, except for its size, which is always current.
+    manifest: Manifest,
+    /// Modified chunks not stored yet, by index.
+    pending: BTreeMap<usize, Vec<u8>>,
+    /// Chunks which were cut off by truncating the file, to be released when it's stored.
+    released: Vec<String>,
+    dirty: bool,
+    /// Number of handles (or other users) of this file.
+    opens: usize,
+    /// The file was deleted while open; its chunks get released when it's closed.
+    unlinked: bool,
+}
+
+impl<FS: FilesystemMT> DedupFs<FS> {
+    pub fn new(inner: FS) -> DedupFs<FS> {
+        DedupFs {
+            inner,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            index: Mutex::new(HashMap::new()),
+            files: Mutex::new(HashMap::new()),
+            handles: Mutex::new(HashMap::new()),
+            next_fh: AtomicU64::new(1),
+        }
+    }
+
+    /// Set the size of chunks new data is split into. The default is 64 KiB. This must not be
+    /// changed for an existing filesystem.
+    pub fn chunk_size(mut self, size: usize) -> DedupFs<FS> {
+        assert!(size > 0, "chunk size must be nonzero");
+        self.chunk_size = size;
+        self
+    }
+
+    /// Number of distinct chunks currently stored.
+    pub fn chunk_count(&self) -> usize {
+        self.index.lock().unwrap().len()
+    }
+
+    fn load_index(&self, req: RequestInfo) -> ResultEmpty {
+        let data = match read_all(&self.inner, req, &meta_path("index")) {
+            Ok(data) => data,
+            Err(libc::ENOENT) => vec![],
+            Err(e) => return Err(e),
+        };
+        let mut index = self.index.lock().unwrap();
+        index.clear();
+        for line in String::from_utf8_lossy(&data).lines() {
+            let mut parts = line.split(' ');
+            match (parts.next(), parts.next().and_then(|n| n.parse().ok())) {
+                (Some(id), Some(refs)) => { index.insert(id.to_owned(), refs); }
+                _ => {
+                    error!("corrupt dedup index line: {:?}", line);
+                    return Err(libc::EIO);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn save_index(&self, req: RequestInfo, index: &HashMap<String, u64>) -> ResultEmpty {
+        let mut data = String::new();
+        for (id, refs) in index {
+            data += &format!("{} {}\n", id, refs);
+        }
+        write_all(&self.inner, req, &meta_path("index"), data.as_bytes())
+    }
+
+    /// Store a chunk, or add a reference to an identical one already stored. Returns its ID.
+    fn put_chunk(&self, req: RequestInfo, index: &mut HashMap<String, u64>, data: &[u8])
+        -> Result<String, libc::c_int>
+    {
+        let hash = fnv1a(data);
+        for n in 0 .. {
+            let id = match n {
+                0 => format!("{:016x}", hash),
+                n => format!("{:016x}-{}", hash, n),
+            };
+            match index.get_mut(&id) {
+                Some(refs) => {
+                    // Hashes can collide, so check it's really the same data.
+                    if read_all(&self.inner, req, &chunk_path(&id))? == data {
+                        *refs += 1;
+                        return Ok(id);
+                    }
+                }
+                None => {
+                    write_all(&self.inner, req, &chunk_path(&id), data)?;
+                    index.insert(id.clone(), 1);
+                    return Ok(id);
+                }
+            }
+        }
+        unreachable!()
+    }
+
+    /// Drop references to chunks, deleting any which are no longer used.
+    fn release_chunks(&self, req: RequestInfo, index: &mut HashMap<String, u64>, ids: Vec<String>)
+        -> ResultEmpty
+    {
+        let mut dead = vec![];
+        for id in ids {
+            match index.get_mut(&id) {
+                Some(refs) if *refs > 1 => *refs -= 1,
+                Some(_) => {
+                    index.remove(&id);
+                    dead.push(id);
+                }
+                None => warn!("releasing unknown chunk {}", id),
+            }
+        }
+        // Save the index first, so a crash can't leave it referring to deleted chunks.
+        self.save_index(req, index)?;
+        let chunks = meta_path("chunks");
+        for id in dead {
+            if let Err(e) = self.inner.unlink(req, &chunks, OsStr::new(&id)) {
+                warn!("failed to delete chunk {}: {}", id, e);
+            }
+        }
+        Ok(())
+    }
+
+    fn load_manifest(&self, req: RequestInfo, path: &FsPath) -> Result<Manifest, libc::c_int> {
+        let data = read_all(&self.inner, req, path)?;
+        Manifest::parse(&data).ok_or_else(|| {
+            error!("{:?} is not a valid dedup manifest", path);
+            libc::EIO
+        })
+    }
+
+    /// Get the open file at a path, loading it if it isn't open already. Each call must be paired
+    /// with a call to `put_file`.
+    fn get_file(&self, req: RequestInfo, path: &FsPath) -> Result<Arc<Mutex<OpenFile>>, libc::c_int> {
+        let mut files = self.files.lock().unwrap();
+        if let Some(file) = files.get(path) {
+            file.lock().unwrap().opens += 1;
+            return Ok(file.clone());
+        }
+        let file = Arc::new(Mutex::new(OpenFile {
+            path: path.clone(),
+            manifest: self.load_manifest(req, path)?,
+            pending: BTreeMap::new(),
+            released: vec![],
+            dirty: false,
+            opens: 1,
+            unlinked: false,
+        }));
+        files.insert(path.clone(), file.clone());
+        Ok(file)
+    }
+
+    /// Store any changes to an open file, and close it if this was its last user.
+    fn put_file(&self, req: RequestInfo, file: &Mutex<OpenFile>) -> ResultEmpty {
+        let result = self.commit(req, &mut file.lock().unwrap());
+        let mut files = self.files.lock().unwrap();
+        let mut file = file.lock().unwrap();
+        file.opens -= 1;
+        if file.opens > 0 {
+            return result;
+        }
+        if !file.unlinked {
+            files.remove(&file.path);
+            return result;
+        }
+        drop(files);
+        let file = &mut *file;
+        let ids = file.manifest.chunks.drain(..).flatten()
+            .chain(file.released.drain(..))
+            .collect();
+        let mut index = self.index.lock().unwrap();
+        result.and(self.release_chunks(req, &mut index, ids))
+    }
+
+    /// Store an open file's modified chunks and manifest.
+    fn commit(&self, req: RequestInfo, file: &mut OpenFile) -> ResultEmpty {
+        if file.unlinked || !file.dirty {
+            return Ok(());
+        }
+        let nchunks = file.manifest.size.div_ceil(self.chunk_size as u64) as usize;
+        file.manifest.chunks.resize(nchunks, None);
+
+        let mut index = self.index.lock().unwrap();
+        let mut old = std::mem::take(&mut file.released);
+        for (&i, data) in &file.pending {
+            let id = if data.iter().all(|&b| b == 0) {
+                None
+            } else {
+                Some(self.put_chunk(req, &mut index, data)?)
+            };
+            old.extend(std::mem::replace(&mut file.manifest.chunks[i], id));
+        }
+        // New chunks are counted in the index before the manifest refers to them, and old ones
+        // are only released after it no longer does.
+        self.save_index(req, &index)?;
+        write_all(&self.inner, req, &file.path, file.manifest.format().as_bytes())?;
+        file.pending.clear();
+        file.dirty = false;
+        self.release_chunks(req, &mut index, old)
+    }
+
+    /// Get a chunk of an open file. Chunks past the end of what's stored are empty.
+    fn chunk<'a>(&self, req: RequestInfo, file: &'a OpenFile, i: usize)
+        -> Result<Cow<'a, [u8]>, libc::c_int>
+    {
+        if let Some(data) = file.pending.get(&i) {
+            return Ok(Cow::Borrowed(data));
+        }
+        match file.manifest.chunks.get(i) {
+            Some(Some(id)) => read_all(&self.inner, req, &chunk_path(id)).map(Cow::Owned),
+            _ => Ok(Cow::Borrowed(&[])),
+        }
+    }
+
+    fn read_file(&self, req: RequestInfo, file: &OpenFile, offset: u64, size: u32) -> ResultData {
+        let file_size = file.manifest.size;
+        if offset >= file_size {
+            return Ok(vec![]);
+        }
+        let chunk_size = self.chunk_size as u64;
+        let end = file_size.min(offset + u64::from(size));
+        let mut out = Vec::with_capacity((end - offset) as usize);
+        let mut pos = offset;
+        while pos < end {
+            let start = (pos % chunk_size) as usize;
+            let len = (chunk_size - start as u64).min(end - pos) as usize;
+            let chunk = self.chunk(req, file, (pos / chunk_size) as usize)?;
+            let have = chunk.len().saturating_sub(start).min(len);
+            if have > 0 {
+                out.extend_from_slice(&chunk[start .. start + have]);
+            }
+            out.resize(out.len() + len - have, 0);
+            pos += len as u64;
+        }
+        Ok(out)
+    }
+
+    fn write_file(&self, req: RequestInfo, file: &mut OpenFile, offset: u64, data: &[u8]) -> ResultEmpty {
+        let chunk_size = self.chunk_size as u64;
+        let mut pos = offset;
+        let mut data = data;
+        while !data.is_empty() {
+            let i = (pos / chunk_size) as usize;
+            let start = (pos % chunk_size) as usize;
+            let len = (self.chunk_size - start).min(data.len());
+            let mut chunk = if len == self.chunk_size {
+                vec![]
+            } else {
+                self.chunk(req, file, i)?.into_owned()
+            };
+            if chunk.len() < start + len {
+                chunk.resize(start + len, 0);
+            }
+            chunk[start .. start + len].copy_from_slice(&data[.. len]);
+            file.pending.insert(i, chunk);
+            data = &data[len ..];
+            pos += len as u64;
+        }
+        file.manifest.size = file.manifest.size.max(pos);
+        file.dirty = true;
+        if file.pending.len() >= MAX_PENDING_CHUNKS {
+            self.commit(req, file)?;
+        }
+        Ok(())
+    }
+
+    fn truncate_file(&self, req: RequestInfo, file: &mut OpenFile, size: u64) -> ResultEmpty {
+        if size < file.manifest.size {
+            let keep = size.div_ceil(self.chunk_size as u64) as usize;
+            file.pending.retain(|&i, _| i < keep);
+            if file.manifest.chunks.len() > keep {
+                let cut = file.manifest.chunks.drain(keep ..).flatten().collect::<Vec<_>>();
+                file.released.extend(cut);
+            }
+            // Trim the new last chunk, so the old data doesn't come back if the file grows.
+            let tail = (size % self.chunk_size as u64) as usize;
+            if tail != 0 {
+                let mut chunk = self.chunk(req, file, keep - 1)?.into_owned();
+                if chunk.len() > tail {
+                    chunk.truncate(tail);
+                    file.pending.insert(keep - 1, chunk);
+                }
+            }
+        }
+        file.manifest.size = size;
+        file.dirty = true;
+        Ok(())
+    }
+
+    fn handle(&self, fh: u64) -> Result<Arc<Mutex<OpenFile>>, libc::c_int> {
+        self.handles.lock().unwrap().get(&fh).cloned().ok_or(libc::EBADF)
+    }
+
+    /// Start using a file for a new handle.
+    fn add_handle(&self, file: Arc<Mutex<OpenFile>>) -> u64 {
+        let fh = self.next_fh.fetch_add(1, Ordering::Relaxed);
+        self.handles.lock().unwrap().insert(fh, file);
+        fh
+    }
+
+    /// Fix up the size of a regular file's attributes from its manifest.
+    fn fix_attr(&self, req: RequestInfo, path: &FsPath, mut attr: FileAttr) -> Result<FileAttr, libc::c_int> {
+        if attr.kind == crate::FileType::RegularFile {
+            let open = self.files.lock().unwrap().get(path).cloned();
+            attr.size = match open {
+                Some(file) => file.lock().unwrap().manifest.size,
+                None => self.load_manifest(req, path)?.size,
+            };
+            attr.blocks = attr.size.div_ceil(512);
+        }
+        Ok(attr)
+    }
+
+    /// Release the chunks of a file which is being deleted or replaced, unless it's still open or
+    /// has other links. Call this before removing the file, and `finish_remove` after.
+    fn start_remove(&self, req: RequestInfo, path: &FsPath) -> Result<Removed, libc::c_int> {
+        let attr = match self.inner.getattr(req, path, None) {
+            Ok((_, attr)) => attr,
+            Err(libc::ENOENT) => return Ok(Removed::Nothing),
+            Err(e) => return Err(e),
+        };
+        if attr.kind != crate::FileType::RegularFile || attr.nlink > 1 {
+            return Ok(Removed::Nothing);
+        }
+        if let Some(file) = self.files.lock().unwrap().get(path) {
+            return Ok(Removed::Open(file.clone()));
+        }
+        let chunks = self.load_manifest(req, path)?.chunks.into_iter().flatten().collect();
+        Ok(Removed::Chunks(chunks))
+    }
+
+    fn finish_remove(&self, req: RequestInfo, removed: Removed) -> ResultEmpty {
+        match removed {
+            Removed::Nothing => Ok(()),
+            Removed::Open(file) => {
+                // Anything opened at the path from now on is a different file.
+                let mut files = self.files.lock().unwrap();
+                let path = file.lock().unwrap().path.clone();
+                if files.get(&path).is_some_and(|open| Arc::ptr_eq(open, &file)) {
+                    files.remove(&path);
+                }
+                file.lock().unwrap().unlinked = true;
+                Ok(())
+            }
+            Removed::Chunks(ids) => {
+                let mut index = self.index.lock().unwrap();
+                self.release_chunks(req, &mut index, ids)
+            }
+        }
+    }
+}
+
+enum Removed {
+    Nothing,
+    Open(Arc<Mutex<OpenFile>>),
+    Chunks(Vec<String>),
+}
+
+impl Manifest {
+    fn parse(data: &[u8]) -> Option<Manifest> {
+        if data.is_empty() {
+            // Files created some other way than through this wrapper.
+            return Some(Manifest::default());
+        }
+        let text = std::str::from_utf8(data).ok()?;
+        let mut lines = text.lines();
+        let size = lines.next()?.strip_prefix("dedup 1 ")?.parse().ok()?;
+        let chunks = lines
+            .map(|line| if line == "-" { None } else { Some(line.to_owned()) })
+            .collect();
+        Some(Manifest { size, chunks })
+    }
+
+    fn format(&self) -> String {
+        let mut text = format!("dedup 1 {}\n", self.size);
+        for chunk in &self.chunks {
+            text += chunk.as_deref().unwrap_or("-");
+            text.push('\n');
+        }
+        text
+    }
+}
+
+fn meta_path(name: &str) -> FsPath {
+    FsPath::from(Path::new("/").join(META_DIR).join(name))
+}
+
+fn chunk_path(id: &str) -> FsPath {
+    FsPath::from(meta_path("chunks").join(id))
+}
+
+/// Whether a path is the metadata directory or something in it.
+fn is_hidden(path: &Path) -> bool {
+    path.components().nth(1) == Some(Component::Normal(OsStr::new(META_DIR)))
+}
+
+fn is_hidden_name(parent: &Path, name: &OsStr) -> bool {
+    parent == Path::new("/") && name == META_DIR
+}
+
+/// 64-bit FNV-1a. Not collision resistant, which is fine because matching chunks are compared.
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| (hash ^ u64::from(b)).wrapping_mul(0x100_0000_01b3))
+}
+
+impl<FS: FilesystemMT> FilesystemMT for DedupFs<FS> {
+    forward_calls!(inner; destroy, readlink, mkdir, rmdir, symlink, releasedir, fsyncdir, statfs,
+        quota, setxattr, getxattr, listxattr, removexattr, access, setvolname, getxtimes, classify, load);
+
+    fn init(&self, req: RequestInfo) -> ResultEmpty {
+        self.inner.init(req)?;
+        let root = FsPath::from("/");
+        let meta = FsPath::from(root.join(META_DIR));
+        for (parent, name) in [(&root, META_DIR), (&meta, "chunks")] {
+            match self.inner.mkdir(req, parent, OsStr::new(name), 0o700) {
+                Ok(_) | Err(libc::EEXIST) => (),
+                Err(e) => return Err(e),
+            }
+        }
+        self.load_index(req)
+    }
+
+    fn getattr(&self, req: RequestInfo, path: &FsPath, _fh: Option<u64>) -> ResultEntry {
+        if is_hidden(path) {
+            return Err(libc::ENOENT);
+        }
+        let (ttl, attr) = self.inner.getattr(req, path, None)?;
+        Ok((ttl, self.fix_attr(req, path, attr)?))
+    }
+
+    fn getattr_mask(&self, req: RequestInfo, path: &FsPath, _fh: Option<u64>, mask: AttrMask) -> ResultEntry {
+        if is_hidden(path) {
+            return Err(libc::ENOENT);
+        }
+        let (ttl, attr) = self.inner.getattr_mask(req, path, None, mask)?;
+        if !mask.contains(AttrMask::SIZE) && !mask.contains(AttrMask::BLOCKS) {
+            // Then there's no need to load the manifest.
+            return Ok((ttl, attr));
+        }
+        Ok((ttl, self.fix_attr(req, path, attr)?))
+    }
+
+    fn getattr_deferred(&self, req: RequestInfo, path: &FsPath, _fh: Option<u64>, reply: Responder<(Duration, FileAttr)>) {
+        if is_hidden(path) {
+            return reply.send(Err(libc::ENOENT));
+        }
+        // Fixing the size needs this wrapper, so wait for the inner filesystem here.
+        let result = Responder::wait(|inner| self.inner.getattr_deferred(req, path, None, inner))
+            .and_then(|(ttl, attr)| Ok((ttl, self.fix_attr(req, path, attr)?)));
+        reply.send(result)
+    }
+
+    // Numeric file handles belong to this wrapper, not the inner filesystem, so they aren't passed
+    // through to it. This goes for the `_deferred` calls on them too: the defaults, which call this
+    // wrapper's own `write`, `flush`, and `fsync`, are what's wanted.
+
+    fn chmod(&self, req: RequestInfo, path: &FsPath, _fh: Option<u64>, mode: u32) -> ResultEmpty {
+        self.inner.chmod(req, path, None, mode)
+    }
+
+    fn chown(&self, req: RequestInfo, path: &FsPath, _fh: Option<u64>, uid: Option<u32>, gid: Option<u32>) -> ResultEmpty {
+        self.inner.chown(req, path, None, uid, gid)
+    }
+
+    fn truncate(&self, req: RequestInfo, path: &FsPath, _fh: Option<u64>, size: u64) -> ResultEmpty {
+        let file = self.get_file(req, path)?;
+        let result = self.truncate_file(req, &mut file.lock().unwrap(), size);
+        result.and(self.put_file(req, &file))
+    }
+
+    fn utimens(&self, req: RequestInfo, path: &FsPath, _fh: Option<u64>, atime: Option<SystemTime>, mtime: Option<SystemTime>) -> ResultEmpty {
+        self.inner.utimens(req, path, None, atime, mtime)
+    }
+
+    fn utimens_macos(&self, req: RequestInfo, path: &FsPath, _fh: Option<u64>, crtime: Option<SystemTime>, chgtime: Option<SystemTime>, bkuptime: Option<SystemTime>, flags: Option<u32>) -> ResultEmpty {
+        self.inner.utimens_macos(req, path, None, crtime, chgtime, bkuptime, flags)
+    }
+
+    #[cfg(target_os = "macos")]
+    fn setxtimes(&self, req: RequestInfo, path: &FsPath, _fh: Option<u64>, bkuptime: Option<SystemTime>, crtime: Option<SystemTime>) -> ResultEmpty {
+        self.inner.setxtimes(req, path, None, bkuptime, crtime)
+    }
+
+    fn mknod(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, mode: u32, rdev: u32) -> ResultEntry {
+        if is_hidden_name(parent, name) {
+            return Err(libc::EEXIST);
+        }
+        // A new regular file is empty, which is also a valid empty manifest.
+        self.inner.mknod(req, parent, name, mode, rdev)
+    }
+
+    fn unlink(&self, req: RequestInfo, parent: &FsPath, name: &OsStr) -> ResultEmpty {
+        if is_hidden_name(parent, name) {
+            return Err(libc::ENOENT);
+        }
+        let removed = self.start_remove(req, &FsPath::from(parent.join(name)))?;
+        self.inner.unlink(req, parent, name)?;
+        self.finish_remove(req, removed)
+    }
+
+    fn rename(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, newparent: &FsPath, newname: &OsStr) -> ResultEmpty {
+        if is_hidden_name(parent, name) || is_hidden_name(newparent, newname) {
+            return Err(libc::EPERM);
+        }
+        let (from, to) = (parent.join(name), FsPath::from(newparent.join(newname)));
+        let removed = self.start_remove(req, &to)?;
+        self.inner.rename(req, parent, name, newparent, newname)?;
+
+        // Move any open files at or under the old path.
+        {
+            let mut files = self.files.lock().unwrap();
+            let moved = files.keys()
+                .filter(|path| path.starts_with(&from))
+                .cloned()
+                .collect::<Vec<_>>();
+            for old in moved {
+                let new = FsPath::from(to.join(old.strip_prefix(&from).unwrap()));
+                let file = files.remove(&old).unwrap();
+                file.lock().unwrap().path = new.clone();
+                // If this replaces an open file, `finish_remove` takes care of it.
+                files.insert(new, file);
+            }
+        }
+
+        self.finish_remove(req, removed)
+    }
+
+    #[cfg(target_os = "macos")]
+    fn exchange(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, newparent: &FsPath, newname: &OsStr, options: u64) -> ResultEmpty {
+        if is_hidden_name(parent, name) || is_hidden_name(newparent, newname) {
+            return Err(libc::EPERM);
+        }
+        self.inner.exchange(req, parent, name, newparent, newname, options)?;
+
+        // Swap the paths of any files open at either path.
+        let (a, b) = (parent.join(name), newparent.join(newname));
+        let mut files = self.files.lock().unwrap();
+        let (file_a, file_b) = (files.remove(&a), files.remove(&b));
+        for (file, path) in [(file_a, b), (file_b, a)] {
+            if let Some(file) = file {
+                file.lock().unwrap().path = path.clone();
+                files.insert(path, file);
+            }
+        }
+        Ok(())
+    }
+
+    fn link(&self, req: RequestInfo, path: &FsPath, newparent: &FsPath, newname: &OsStr) -> ResultEntry {
+        if is_hidden_name(newparent, newname) {
+            return Err(libc::EEXIST);
+        }
+        let (ttl, attr) = self.inner.link(req, path, newparent, newname)?;
+        Ok((ttl, self.fix_attr(req, path, attr)?))
+    }
+
+    fn open(&self, req: RequestInfo, path: &FsPath, flags: u32) -> ResultOpen {
+        if is_hidden(path) {
+            return Err(libc::ENOENT);
+        }
+        let file = self.get_file(req, path)?;
+        if flags & libc::O_TRUNC as u32 != 0 {
+            if let Err(e) = self.truncate_file(req, &mut file.lock().unwrap(), 0) {
+                let _ = self.put_file(req, &file);
+                return Err(e);
+            }
+        }
+        Ok(OpenedFile::new(self.add_handle(file)))
+    }
+
+    fn open_handle(&self, _req: RequestInfo, _path: &FsPath, _flags: u32) -> ResultOpenHandle {
+        // Handle objects from the inner filesystem would bypass the chunking.
+        Err(libc::ENOSYS)
+    }
+
+    fn read(&self, req: RequestInfo, _path: &FsPath, fh: u64, offset: u64, size: u32, callback: impl FnOnce(ResultSlice<'_>) -> CallbackResult) -> CallbackResult {
+        let result = self.handle(fh)
+            .and_then(|file| self.read_file(req, &file.lock().unwrap(), offset, size));
+        match result {
+            Ok(data) => callback(Ok(&data)),
+            Err(e) => callback(Err(e)),
+        }
+    }
+
+    fn write(&self, req: RequestInfo, _path: &FsPath, fh: u64, offset: u64, data: Vec<u8>, _flags: u32) -> ResultWrite {
+        let file = self.handle(fh)?;
+        self.write_file(req, &mut file.lock().unwrap(), offset, &data)?;
+        Ok(data.len() as u32)
+    }
+
+    fn flush(&self, req: RequestInfo, _path: &FsPath, fh: u64, _lock_owner: LockOwner) -> ResultEmpty {
+        let file = self.handle(fh)?;
+        let result = self.commit(req, &mut file.lock().unwrap());
+        result
+    }
+
+    fn release(&self, req: RequestInfo, _path: &FsPath, fh: u64, _flags: u32, _lock_owner: Option<LockOwner>, _flush: bool) -> ResultEmpty {
+        let file = self.handles.lock().unwrap().remove(&fh).ok_or(libc::EBADF)?;
+        self.put_file(req, &file)
+    }
+
+    fn fsync(&self, req: RequestInfo, _path: &FsPath, fh: u64, _datasync: bool) -> ResultEmpty {
+        // This stores the file's changes in the inner filesystem, but doesn't fsync them there:
+        // that would need handles to each of the inner files involved.
+        let file = self.handle(fh)?;
+        let result = self.commit(req, &mut file.lock().unwrap());
+        result
+    }
+
+    fn sync(&self, filter: &dyn Fn(&Path) -> bool) -> ResultEmpty {
+        let files: Vec<_> = self.files.lock().unwrap().iter()
+            .filter(|(path, _)| filter(path))
+            .map(|(_, file)| file.clone())
+            .collect();
+        // Calls the kernel didn't make have no request of their own.
+        let req = RequestInfo { unique: 0, uid: 0, gid: 0, pid: 0, node: None, umask: None, lock_owner: None };
+        for file in files {
+            self.commit(req, &mut file.lock().unwrap())?;
+        }
+        // The chunks and manifests have paths of their own, so sync all of them.
+        match self.inner.sync(&|_| true) {
+            Err(libc::ENOSYS) => Ok(()),
+            result => result,
+        }
+    }
+
+    fn opendir(&self, req: RequestInfo, path: &FsPath, flags: u32) -> ResultOpen {
+        if is_hidden(path) {
+            return Err(libc::ENOENT);
+        }
+        self.inner.opendir(req, path, flags)
+    }
+
+    fn opendir_handle(&self, _req: RequestInfo, _path: &FsPath, _flags: u32) -> ResultOpenDirHandle {
+        // Directory handles from the inner filesystem would list the metadata directory.
+        Err(libc::ENOSYS)
+    }
+
+    fn readdir(&self, req: RequestInfo, path: &FsPath, fh: u64) -> ResultReaddir {
+        let mut entries = self.inner.readdir(req, path, fh)?;
+        if path == Path::new("/") {
+            entries.retain(|entry| entry.name != META_DIR);
+        }
+        // The inner filesystem's attributes are those of the manifests, not of the files.
+        for entry in &mut entries {
+            entry.attr = None;
+        }
+        Ok(entries)
+    }
+
+    fn readdir_deferred(&self, req: RequestInfo, path: &FsPath, fh: u64, reply: Responder<Vec<DirectoryEntry>>) {
+        let root = path == Path::new("/");
+        self.inner.readdir_deferred(req, path, fh, Responder::new(move |result| {
+            reply.send(result.map(|mut entries: Vec<DirectoryEntry>| {
+                if root {
+                    entries.retain(|entry| entry.name != META_DIR);
+                }
+                for entry in &mut entries {
+                    entry.attr = None;
+                }
+                entries
+            }))
+        }))
+    }
+
+    fn readdir_at(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, add: &mut dyn FnMut(&DirectoryEntry, u64) -> bool) -> ResultEmpty {
+        let root = path == Path::new("/");
+        self.inner.readdir_at(req, path, fh, offset, &mut |entry, next| {
+            if root && entry.name == META_DIR {
+                return false;
+            }
+            match entry.attr {
+                Some(_) => add(&DirectoryEntry { attr: None, ..entry.clone() }, next),
+                None => add(entry, next),
+            }
+        })
+    }
+
+    fn create(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, mode: u32, flags: u32) -> ResultCreate {
+        if is_hidden_name(parent, name) {
+            return Err(libc::EEXIST);
+        }
+        let path = FsPath::from(parent.join(name));
+        let created = self.inner.create(req, parent, name, mode, flags)?;
+        self.inner.release(req, &path, created.fh, flags, None, false)?;
+        let fh = self.open(req, &path, flags & !(libc::O_TRUNC as u32))?.fh;
+        Ok(CreatedEntry { fh, flags: 0, ..created })
+    }
+
+    fn create_handle(&self, _req: RequestInfo, _parent: &FsPath, _name: &OsStr, _mode: u32, _flags: u32) -> ResultCreateHandle {
+        Err(libc::ENOSYS)
+    }
+}
+
+#[test]
+fn test_dedup() {
+    use std::ffi::OsString;
+    use super::test_fs::{self, MemFs};
+
+    let req = test_fs::req();
+    let root = &FsPath::from("/");
+    let fs = DedupFs::new(MemFs::new()).chunk_size(4);
+    fs.init(req).unwrap();
+
+    let write = |name: &str, data: &[u8]| {
+        let created = fs.create(req, root, OsStr::new(name), 0o644, libc::O_WRONLY as u32).unwrap();
+        let path = FsPath::from(root.join(name));
+        assert_eq!(data.len() as u32, fs.write(req, &path, created.fh, 0, data.to_vec(), 0).unwrap());
+        fs.release(req, &path, created.fh, 0, None, true).unwrap();
+    };
+    let read = |name: &str| {
+        let path = FsPath::from(root.join(name));
+        let fh = fs.open(req, &path, libc::O_RDONLY as u32).unwrap().fh;
+        let mut data = vec![];
+        fs.read(req, &path, fh, 0, 100, |result| {
+            data = result.unwrap().to_vec();
+            CallbackResult { _private: std::marker::PhantomData }
+        });
+        fs.release(req, &path, fh, 0, None, false).unwrap();
+        data
+    };
+
+    write("a", b"abcdabcd\0\0\0\0xy");
+    write("b", b"abcdxy");
+    assert_eq!(2, fs.chunk_count());
+    assert_eq!(b"abcdabcd\0\0\0\0xy".to_vec(), read("a"));
+    assert_eq!(14, fs.getattr(req, &FsPath::from("/a"), None).unwrap().1.size);
+
+    let names = fs.readdir(req, root, 0).unwrap().into_iter().map(|e| e.name).collect::<Vec<_>>();
+    assert!(!names.contains(&OsString::from(META_DIR)));
+    assert_eq!(Err(libc::ENOENT), fs.getattr(req, &meta_path("index"), None).map(drop));
+
+    // Truncating and deleting release chunks which are no longer used.
+    fs.truncate(req, &FsPath::from("/b"), None, 2).unwrap();
+    assert_eq!(b"ab".to_vec(), read("b"));
+    assert_eq!(3, fs.chunk_count());
+    fs.unlink(req, root, OsStr::new("a")).unwrap();
+    assert_eq!(1, fs.chunk_count());
+
+    // The index is persistent.
+    let fs = DedupFs { inner: fs.inner, ..DedupFs::new(MemFs::new()) }.chunk_size(4);
+    fs.init(req).unwrap();
+    assert_eq!(1, fs.chunk_count());
+    fs.unlink(req, root, OsStr::new("b")).unwrap();
+    assert_eq!(0, fs.chunk_count());
+    assert!(fs.inner.readdir(req, &meta_path("chunks"), 0).unwrap().is_empty());
+}
@@ -0,0 +1,496 @@
+// Dry Run :: recording changes to a filesystem instead of making them.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
+
+use crate::FileType;
+use crate::types::*;
+
+/// A filesystem which answers calls which only read from the filesystem it wraps, but records
+/// calls which would change anything in a journal instead of making them, and pretends they
+/// succeeded. This is for seeing what an installer or sync tool would do to a tree before letting
+/// it.
+///
+/// Since nothing is changed, reads still see the filesystem as it was: a file that was created
+/// can't be looked up afterwards, a file that was written reads back with its old contents, and
+/// so on. Entries returned for things that were created are made up, and aren't cached by the
+/// kernel. Changes are recorded without checking whether they'd have succeeded.
+///
+/// The journal can be read with `journal`, and applied to the filesystem with `replay`, or to
+/// another one with `Change::apply`. It keeps all the data written, so it grows with the amount of
+/// data written through the mount.
+pub struct DryRunFs<FS> {
+    inner: FS,
+    journal: Mutex<Vec<Change>>,
+    handles: Mutex<HashMap<u64, Handle>>,
+    next_fh: AtomicU64,
+}
+
+/// An open file: one which was opened in the filesystem (read-only, whatever it was opened for),
+/// or one created by the dry run, which doesn't exist there.
+#[derive(Clone, Copy)]
+enum Handle {
+    Inner(u64),
+    Created(FileAttr),
+}
+
+/// A change recorded by `DryRunFs`.
+///
+/// Paths are the ones the calls were made with; none of them have been resolved against earlier
+/// changes in the journal.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Change {
+    Chmod { path: FsPath, mode: u32 },
+    Chown { path: FsPath, uid: Option<u32>, gid: Option<u32> },
+    Truncate { path: FsPath, size: u64 },
+    Utimens { path: FsPath, atime: Option<SystemTime>, mtime: Option<SystemTime> },
+    UtimensMacos {
+        path: FsPath,
+        crtime: Option<SystemTime>,
+        chgtime: Option<SystemTime>,
+        bkuptime: Option<SystemTime>,
+        flags: Option<u32>,
+    },
+    Mknod { path: FsPath, mode: u32, rdev: u32 },
+    Mkdir { path: FsPath, mode: u32 },
+    Unlink { path: FsPath },
+    Rmdir { path: FsPath },
+    Symlink { path: FsPath, target: PathBuf },
+    Rename { from: FsPath, to: FsPath },
+    Link { path: FsPath, newpath: FsPath },
+    /// A file was created, with the given mode and open flags.
+    Create { path: FsPath, mode: u32, flags: u32 },
+    Write { path: FsPath, offset: u64, data: Vec<u8> },
+    Fallocate { path: FsPath, offset: u64, length: u64, mode: i32 },
+    Setxattr { path: FsPath, name: OsString, value: Vec<u8>, flags: u32, position: XattrPosition },
+    Removexattr { path: FsPath, name: OsString },
+    #[cfg(target_os = "macos")]
+    Setvolname { name: OsString },
+    #[cfg(target_os = "macos")]
+    Exchange { path: FsPath, other: FsPath, options: u64 },
+}
+
+/// Split a path into its parent and name, for replaying calls which take them separately.
+fn split(path: &Path) -> Result<(FsPath, &OsStr), libc::c_int> {
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => Ok((FsPath::from(parent), name)),
+        _ => Err(libc::EINVAL),
+    }
+}
+
+/// Open a file for writing, call the given function with the handle, and close it.
+fn with_open(fs: &impl FilesystemMT, req: RequestInfo, path: &FsPath, f: impl FnOnce(u64) -> ResultEmpty) -> ResultEmpty {
+    let flags = libc::O_WRONLY as u32;
+    let fh = fs.open(req, path, flags)?.fh;
+    let result = f(fh);
+    let released = fs.release(req, path, fh, flags, None, true);
+    result.and(released)
+}
+
+impl Change {
+    /// Make the change to a filesystem.
+    pub fn apply(&self, fs: &impl FilesystemMT, req: RequestInfo) -> ResultEmpty {
+        match self {
+            Change::Chmod { path, mode } => fs.chmod(req, path, None, *mode),
+            Change::Chown { path, uid, gid } => fs.chown(req, path, None, *uid, *gid),
+            Change::Truncate { path, size } => fs.truncate(req, path, None, *size),
+            Change::Utimens { path, atime, mtime } => fs.utimens(req, path, None, *atime, *mtime),
+            Change::UtimensMacos { path, crtime, chgtime, bkuptime, flags } => {
+                fs.utimens_macos(req, path, None, *crtime, *chgtime, *bkuptime, *flags)
+            }
+            Change::Mknod { path, mode, rdev } => {
+                let (parent, name) = split(path)?;
+                fs.mknod(req, &parent, name, *mode, *rdev).map(drop)
+            }
+            Change::Mkdir { path, mode } => {
+                let (parent, name) = split(path)?;
+                fs.mkdir(req, &parent, name, *mode).map(drop)
+            }
+            Change::Unlink { path } => {
+                let (parent, name) = split(path)?;
+                fs.unlink(req, &parent, name)
+            }
+            Change::Rmdir { path } => {
+                let (parent, name) = split(path)?;
+                fs.rmdir(req, &parent, name)
+            }
+            Change::Symlink { path, target } => {
+                let (parent, name) = split(path)?;
+                fs.symlink(req, &parent, name, target).map(drop)
+            }
+            Change::Rename { from, to } => {
+                let ((parent, name), (newparent, newname)) = (split(from)?, split(to)?);
+                fs.rename(req, &parent, name, &newparent, newname)
+            }
+            Change::Link { path, newpath } => {
+                let (newparent, newname) = split(newpath)?;
+                fs.link(req, path, &newparent, newname).map(drop)
+            }
+            Change::Create { path, mode, flags } => {
+                let (parent, name) = split(path)?;
+                let created = fs.create(req, &parent, name, *mode, *flags)?;
+                fs.release(req, path, created.fh, *flags, None, true)
+            }
+            Change::Write { path, offset, data } => with_open(fs, req, path, |fh| {
+                let mut done = 0;
+                while done < data.len() {
+                    let chunk = data[done ..].to_vec();
+                    match fs.write(req, path, fh, offset + done as u64, chunk, 0)? {
+                        0 => return Err(libc::EIO),
+                        n => done += n as usize,
+                    }
+                }
+                Ok(())
+            }),
+            Change::Fallocate { path, offset, length, mode } => with_open(fs, req, path, |fh| {
+                fs.fallocate(req, path, fh, *offset, *length, *mode)
+            }),
+            Change::Setxattr { path, name, value, flags, position } => {
+                fs.setxattr(req, path, name, value, *flags, *position)
+            }
+            Change::Removexattr { path, name } => fs.removexattr(req, path, name),
+            #[cfg(target_os = "macos")]
+            Change::Setvolname { name } => fs.setvolname(req, name),
+            #[cfg(target_os = "macos")]
+            Change::Exchange { path, other, options } => {
+                let ((parent, name), (newparent, newname)) = (split(path)?, split(other)?);
+                fs.exchange(req, &parent, name, &newparent, newname, *options)
+            }
+        }
+    }
+}
+
+/// Made-up entries aren't cached, so the kernel goes back to the filesystem for them, and finds
+/// them missing like everything else does.
+const TTL: Duration = Duration::from_secs(0);
+
+/// Make up the attributes of an entry being created.
+fn new_attr(req: RequestInfo, kind: FileType, mode: u32, size: u64) -> FileAttr {
+    let now = SystemTime::now();
+    FileAttr {
+        ino: 0,
+        size,
+        blocks: 0,
+        atime: now,
+        mtime: now,
+        ctime: now,
+        crtime: now,
+        kind,
+        perm: (mode & 0o7777) as u16,
+        nlink: if kind == FileType::Directory { 2 } else { 1 },
+        uid: req.uid,
+        gid: req.gid,
+        rdev: 0,
+        flags: 0,
+    }
+}
+
+impl<FS: FilesystemMT> DryRunFs<FS> {
+    pub fn new(inner: FS) -> DryRunFs<FS> {
+        DryRunFs {
+            inner,
+            journal: Mutex::new(vec![]),
+            handles: Mutex::new(HashMap::new()),
+            next_fh: AtomicU64::new(1),
+        }
+    }
+
+    /// The changes recorded so far, oldest first.
+    pub fn journal(&self) -> Vec<Change> {
+        self.journal.lock().unwrap().clone()
+    }
+
+    /// Take the changes recorded so far, leaving the journal empty.
+    pub fn take_journal(&self) -> Vec<Change> {
+        std::mem::take(&mut *self.journal.lock().unwrap())
+    }
+
+    /// Make the recorded changes to the filesystem, in order, and remove them from the journal.
+    ///
+    /// This stops at the first change which fails, and returns its error, leaving it and the ones
+    /// after it in the journal.
+    pub fn replay(&self, req: RequestInfo) -> ResultEmpty {
+        let mut journal = self.journal.lock().unwrap();
+        let mut applied = 0;
+        let result = journal.iter().try_for_each(|change| {
+            change.apply(&self.inner, req)?;
+            applied += 1;
+            Ok(())
+        });
+        journal.drain(.. applied);
+        result
+    }
+
+    fn record(&self, change: Change) {
+        debug!("dry run: {:?}", change);
+        self.journal.lock().unwrap().push(change);
+    }
+
+    fn handle(&self, fh: u64) -> Result<Handle, libc::c_int> {
+        self.handles.lock().unwrap().get(&fh).copied().ok_or(libc::EBADF)
+    }
+
+    fn add_handle(&self, handle: Handle) -> u64 {
+        let fh = self.next_fh.fetch_add(1, Ordering::Relaxed);
+        self.handles.lock().unwrap().insert(fh, handle);
+        fh
+    }
+
+    /// The filesystem's handle for one of ours, if it has one.
+    fn inner_fh(&self, fh: Option<u64>) -> Result<Option<u64>, libc::c_int> {
+        match fh.map(|fh| self.handle(fh)).transpose()? {
+            Some(Handle::Inner(fh)) => Ok(Some(fh)),
+            _ => Ok(None),
+        }
+    }
+}
+
+impl<FS: FilesystemMT> FilesystemMT for DryRunFs<FS> {
+    forward_calls!(inner; init, init_connection, destroy, readlink, opendir, opendir_handle,
+        readdir, readdir_deferred, readdir_at, releasedir, fsyncdir, statfs, quota, getxattr,
+        listxattr, access, getxtimes, classify, load);
+
+    fn getattr(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>) -> ResultEntry {
+        if let Some(Handle::Created(attr)) = fh.map(|fh| self.handle(fh)).transpose()? {
+            return Ok((TTL, attr));
+        }
+        self.inner.getattr(req, path, self.inner_fh(fh)?)
+    }
+
+    fn chmod(&self, _req: RequestInfo, path: &FsPath, _fh: Option<u64>, mode: u32) -> ResultEmpty {
+        self.record(Change::Chmod { path: path.clone(), mode });
+        Ok(())
+    }
+
+    fn chown(&self, _req: RequestInfo, path: &FsPath, _fh: Option<u64>, uid: Option<u32>, gid: Option<u32>) -> ResultEmpty {
+        self.record(Change::Chown { path: path.clone(), uid, gid });
+        Ok(())
+    }
+
+    fn truncate(&self, _req: RequestInfo, path: &FsPath, _fh: Option<u64>, size: u64) -> ResultEmpty {
+        self.record(Change::Truncate { path: path.clone(), size });
+        Ok(())
+    }
+
+    fn utimens(&self, _req: RequestInfo, path: &FsPath, _fh: Option<u64>, atime: Option<SystemTime>, mtime: Option<SystemTime>) -> ResultEmpty {
+        self.record(Change::Utimens { path: path.clone(), atime, mtime });
+        Ok(())
+    }
+
+    fn utimens_macos(&self, _req: RequestInfo, path: &FsPath, _fh: Option<u64>, crtime: Option<SystemTime>, chgtime: Option<SystemTime>, bkuptime: Option<SystemTime>, flags: Option<u32>) -> ResultEmpty {
+        self.record(Change::UtimensMacos { path: path.clone(), crtime, chgtime, bkuptime, flags });
+        Ok(())
+    }
+
+    fn mknod(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, mode: u32, rdev: u32) -> ResultEntry {
+        self.record(Change::Mknod { path: parent.join(name).into(), mode, rdev });
+        let kind = match mode & libc::S_IFMT {
+            libc::S_IFIFO => FileType::NamedPipe,
+            libc::S_IFCHR => FileType::CharDevice,
+            libc::S_IFBLK => FileType::BlockDevice,
+            libc::S_IFSOCK => FileType::Socket,
+            _ => FileType::RegularFile,
+        };
+        let mut attr = new_attr(req, kind, mode, 0);
+        attr.rdev = rdev;
+        Ok((TTL, attr))
+    }
+
+    fn mkdir(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, mode: u32) -> ResultEntry {
+        self.record(Change::Mkdir { path: parent.join(name).into(), mode });
+        Ok((TTL, new_attr(req, FileType::Directory, mode, 0)))
+    }
+
+    fn unlink(&self, _req: RequestInfo, parent: &FsPath, name: &OsStr) -> ResultEmpty {
+        self.record(Change::Unlink { path: parent.join(name).into() });
+        Ok(())
+    }
+
+    fn rmdir(&self, _req: RequestInfo, parent: &FsPath, name: &OsStr) -> ResultEmpty {
+        self.record(Change::Rmdir { path: parent.join(name).into() });
+        Ok(())
+    }
+
+    fn symlink(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, target: &Path) -> ResultEntry {
+        self.record(Change::Symlink { path: parent.join(name).into(), target: target.to_owned() });
+        let size = target.as_os_str().len() as u64;
+        Ok((TTL, new_attr(req, FileType::Symlink, 0o777, size)))
+    }
+
+    fn rename(&self, _req: RequestInfo, parent: &FsPath, name: &OsStr, newparent: &FsPath, newname: &OsStr) -> ResultEmpty {
+        let (from, to) = (parent.join(name).into(), newparent.join(newname).into());
+        self.record(Change::Rename { from, to });
+        Ok(())
+    }
+
+    fn link(&self, req: RequestInfo, path: &FsPath, newparent: &FsPath, newname: &OsStr) -> ResultEntry {
+        let (_, mut attr) = self.inner.getattr(req, path, None)?;
+        self.record(Change::Link { path: path.clone(), newpath: newparent.join(newname).into() });
+        attr.nlink += 1;
+        Ok((TTL, attr))
+    }
+
+    fn open(&self, req: RequestInfo, path: &FsPath, flags: u32) -> ResultOpen {
+        // Whatever the file is opened for, only read it.
+        let read_flags = flags as libc::c_int
+            & !(libc::O_ACCMODE | libc::O_TRUNC | libc::O_APPEND | libc::O_CREAT)
+            | libc::O_RDONLY;
+        let opened = self.inner.open(req, path, read_flags as u32)?;
+        if flags as libc::c_int & libc::O_TRUNC != 0 {
+            self.record(Change::Truncate { path: path.clone(), size: 0 });
+        }
+        Ok(OpenedFile { fh: self.add_handle(Handle::Inner(opened.fh)), ..opened })
+    }
+
+    fn open_handle(&self, _req: RequestInfo, _path: &FsPath, _flags: u32) -> ResultOpenHandle {
+        // Handle objects from the inner filesystem would take writes straight to it.
+        Err(libc::ENOSYS)
+    }
+
+    fn read(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, size: u32, callback: impl FnOnce(ResultSlice<'_>) -> CallbackResult) -> CallbackResult {
+        match self.handle(fh) {
+            Ok(Handle::Inner(fh)) => self.inner.read(req, path, fh, offset, size, callback),
+            Ok(Handle::Created(_)) => callback(Ok(&[])),
+            Err(e) => callback(Err(e)),
+        }
+    }
+
+    fn write(&self, _req: RequestInfo, path: &FsPath, fh: u64, offset: u64, data: Vec<u8>, _flags: u32) -> ResultWrite {
+        self.handle(fh)?;
+        let len = data.len() as u32;
+        self.record(Change::Write { path: path.clone(), offset, data });
+        Ok(len)
+    }
+
+    fn flush(&self, req: RequestInfo, path: &FsPath, fh: u64, lock_owner: LockOwner) -> ResultEmpty {
+        match self.handle(fh)? {
+            Handle::Inner(fh) => self.inner.flush(req, path, fh, lock_owner),
+            Handle::Created(_) => Ok(()),
+        }
+    }
+
+    fn release(&self, req: RequestInfo, path: &FsPath, fh: u64, flags: u32, lock_owner: Option<LockOwner>, flush: bool) -> ResultEmpty {
+        match self.handles.lock().unwrap().remove(&fh).ok_or(libc::EBADF)? {
+            Handle::Inner(fh) => self.inner.release(req, path, fh, flags, lock_owner, flush),
+            Handle::Created(_) => Ok(()),
+        }
+    }
+
+    fn fsync(&self, _req: RequestInfo, _path: &FsPath, fh: u64, _datasync: bool) -> ResultEmpty {
+        // There's nothing written to sync.
+        self.handle(fh).map(drop)
+    }
+
+    fn sync(&self, _filter: &dyn Fn(&Path) -> bool) -> ResultEmpty {
+        Ok(())
+    }
+
+    fn setxattr(&self, _req: RequestInfo, path: &FsPath, name: &OsStr, value: &[u8], flags: u32, position: XattrPosition) -> ResultEmpty {
+        self.record(Change::Setxattr {
+            path: path.clone(),
+            name: name.to_owned(),
+            value: value.to_vec(),
+            flags,
+            position,
+        });
+        Ok(())
+    }
+
+    fn removexattr(&self, _req: RequestInfo, path: &FsPath, name: &OsStr) -> ResultEmpty {
+        self.record(Change::Removexattr { path: path.clone(), name: name.to_owned() });
+        Ok(())
+    }
+
+    fn create(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, mode: u32, flags: u32) -> ResultCreate {
+        self.record(Change::Create { path: parent.join(name).into(), mode, flags });
+        let attr = new_attr(req, FileType::RegularFile, mode, 0);
+        let fh = self.add_handle(Handle::Created(attr));
+        Ok(CreatedEntry { ttl: TTL, attr, fh, flags: 0 })
+    }
+
+    fn create_handle(&self, _req: RequestInfo, _parent: &FsPath, _name: &OsStr, _mode: u32, _flags: u32) -> ResultCreateHandle {
+        Err(libc::ENOSYS)
+    }
+
+    fn fallocate(&self, _req: RequestInfo, path: &FsPath, fh: u64, offset: u64, length: u64, mode: i32) -> ResultEmpty {
+        self.handle(fh)?;
+        self.record(Change::Fallocate { path: path.clone(), offset, length, mode });
+        Ok(())
+    }
+
+    fn copy_file_range(&self, _req: RequestInfo, _path_in: &FsPath, _fh_in: u64, _offset_in: u64, _path_out: &FsPath, _fh_out: u64, _offset_out: u64, _len: u64, _flags: u32) -> ResultWrite {
+        // Have the kernel read and write the data instead, so the journal gets the data copied.
+        Err(libc::ENOSYS)
+    }
+
+    #[cfg(target_os = "macos")]
+    fn setvolname(&self, _req: RequestInfo, name: &OsStr) -> ResultEmpty {
+        self.record(Change::Setvolname { name: name.to_owned() });
+        Ok(())
+    }
+
+    #[cfg(target_os = "macos")]
+    fn exchange(&self, _req: RequestInfo, parent: &FsPath, name: &OsStr, newparent: &FsPath, newname: &OsStr, options: u64) -> ResultEmpty {
+        let (path, other) = (parent.join(name).into(), newparent.join(newname).into());
+        self.record(Change::Exchange { path, other, options });
+        Ok(())
+    }
+}
+
+#[test]
+fn test_dry_run() {
+    use super::test_fs::{self, MemFs};
+    use super::{read_all, write_all};
+
+    let req = test_fs::req();
+    let root = &FsPath::from("/");
+    let fs = DryRunFs::new(MemFs::new());
+    write_all(&fs.inner, req, &FsPath::from("/a"), b"old").unwrap();
+
+    // Changes are recorded, and seem to work, but don't change anything.
+    write_all(&fs, req, &FsPath::from("/a"), b"new").unwrap();
+    fs.mkdir(req, root, OsStr::new("d"), 0o755).unwrap();
+    let created = fs.create(req, &FsPath::from("/d"), OsStr::new("b"), 0o644, libc::O_WRONLY as u32)
+        .unwrap();
+    assert_eq!(3, fs.write(req, &FsPath::from("/d/b"), created.fh, 0, b"bbb".to_vec(), 0).unwrap());
+    assert_eq!(FileType::RegularFile, fs.getattr(req, &FsPath::from("/d/b"), Some(created.fh))
+        .unwrap().1.kind);
+    fs.release(req, &FsPath::from("/d/b"), created.fh, 0, None, true).unwrap();
+    fs.rename(req, root, OsStr::new("a"), root, OsStr::new("c")).unwrap();
+
+    assert_eq!(b"old".to_vec(), read_all(&fs, req, &FsPath::from("/a")).unwrap());
+    assert_eq!(Err(libc::ENOENT), fs.getattr(req, &FsPath::from("/d"), None).map(drop));
+    assert_eq!(Err(libc::ENOENT), fs.getattr(req, &FsPath::from("/c"), None).map(drop));
+
+    let path = FsPath::from;
+    assert_eq!(vec![
+        Change::Truncate { path: path("/a"), size: 0 },
+        Change::Write { path: path("/a"), offset: 0, data: b"new".to_vec() },
+        Change::Truncate { path: path("/a"), size: 3 },
+        Change::Mkdir { path: path("/d"), mode: 0o755 },
+        Change::Create { path: path("/d/b"), mode: 0o644, flags: libc::O_WRONLY as u32 },
+        Change::Write { path: path("/d/b"), offset: 0, data: b"bbb".to_vec() },
+        Change::Rename { from: path("/a"), to: path("/c") },
+    ], fs.journal());
+
+    // Replaying makes them.
+    fs.replay(req).unwrap();
+    assert!(fs.journal().is_empty());
+    assert_eq!(b"new".to_vec(), read_all(&fs, req, &FsPath::from("/c")).unwrap());
+    assert_eq!(b"bbb".to_vec(), read_all(&fs, req, &FsPath::from("/d/b")).unwrap());
+    assert_eq!(Err(libc::ENOENT), fs.getattr(req, &FsPath::from("/a"), None).map(drop));
+
+    // Replaying stops at the first failure, and keeps it and what comes after.
+    fs.unlink(req, root, OsStr::new("missing")).unwrap();
+    fs.unlink(req, root, OsStr::new("c")).unwrap();
+    assert_eq!(Err(libc::ENOENT), fs.replay(req));
+    assert_eq!(2, fs.take_journal().len());
+    assert!(fs.journal().is_empty());
+}
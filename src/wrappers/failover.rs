@@ -0,0 +1,662 @@
+// Failover :: switching to a secondary filesystem when the primary one fails.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::types::*;
+
+/// Errors which indicate that a backend isn't working, as opposed to a problem with the request.
+const BACKEND_ERRORS: &[libc::c_int] = &[
+    libc::EIO, libc::ENOTCONN, libc::ETIMEDOUT, libc::ECONNRESET, libc::ECONNREFUSED,
+    libc::ECONNABORTED, libc::EHOSTDOWN, libc::EHOSTUNREACH, libc::ENETDOWN, libc::ENETUNREACH,
+];
+
+const DEFAULT_MAX_ERRORS: u32 = 3;
+const DEFAULT_PROBE_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Backend {
+    Primary,
+    Secondary,
+}
+
+struct State {
+    active: Backend,
+    /// Consecutive failures of the active backend.
+    errors: u32,
+    /// When the primary was last checked, while the secondary is active.
+    last_probe: Instant,
+}
+
+/// A filesystem which sends calls to a primary filesystem, and switches to a secondary one if the
+/// primary keeps failing.
+///
+/// This is meant for network filesystems with redundant servers, where both backends serve the
+/// same files. Errors like `EIO`, `ETIMEDOUT`, and `ENOTCONN`, and (if a timeout is set) calls
+/// which take too long, count as failures. After `max_errors` of them in a row from the primary,
+/// calls go to the secondary instead, and the call which failed is retried there. Calls can't be
+/// interrupted, so a call which times out still waits for the primary to finish it.
+///
+/// While the secondary is active, the primary is checked every `probe_interval` by getting the
+/// attributes of its root directory, and calls go back to it as soon as that succeeds. Probes are
+/// made by whichever call comes along after the interval is up, not by a separate thread.
+///
+/// Files and directories stay open on the filesystem they were opened on, so handles opened before
+/// a switch go on failing if their backend is down, until they're closed.
+pub struct FailoverFs<P, S> {
+    primary: P,
+    secondary: S,
+    max_errors: u32,
+    timeout: Option<Duration>,
+    probe_interval: Duration,
+    state: Mutex<State>,
+    /// The backend and backend handle of each handle this filesystem returns.
+    handles: Mutex<HashMap<u64, (Backend, u64)>>,
+    next_fh: AtomicU64,
+}
+
+/// Run an expression against the active backend (as `$fs`), recording failures, and retrying on
+/// the secondary if it fails over.
+macro_rules! route {
+    ($self:ident, $req:expr, |$fs:ident| $call:expr) => {{
+        let backend = $self.active($req);
+        let start = Instant::now();
+        let result = match backend {
+            Backend::Primary => { let $fs = &$self.primary; $call }
+            Backend::Secondary => { let $fs = &$self.secondary; $call }
+        };
+        if $self.record(backend, start, result.as_ref().err().copied()) {
+            let $fs = &$self.secondary;
+            $call
+        } else {
+            result
+        }
+    }};
+}
+
+/// Run an expression against the backend a handle belongs to (as `$fs`, with the backend's handle
+/// as `$fh`), recording failures.
+macro_rules! on_handle {
+    ($self:ident, $handle:expr, |$fs:ident, $fh:ident| $call:expr) => {{
+        match $self.handle($handle) {
+            Ok((backend, $fh)) => {
+                let start = Instant::now();
+                let result = match backend {
+                    Backend::Primary => { let $fs = &$self.primary; $call }
+                    Backend::Secondary => { let $fs = &$self.secondary; $call }
+                };
+                $self.record(backend, start, result.as_ref().err().copied());
+                result
+            }
+            Err(e) => Err(e),
+        }
+    }};
+}
+
+impl<P: FilesystemMT, S: FilesystemMT> FailoverFs<P, S> {
+    pub fn new(primary: P, secondary: S) -> FailoverFs<P, S> {
+        FailoverFs {
+            primary,
+            secondary,
+            max_errors: DEFAULT_MAX_ERRORS,
+            timeout: None,
+            probe_interval: DEFAULT_PROBE_INTERVAL,
+            state: Mutex::new(State {
+                active: Backend::Primary,
+                errors: 0,
+                last_probe: Instant::now(),
+            }),
+            handles: Mutex::new(HashMap::new()),
+            next_fh: AtomicU64::new(1),
+        }
+    }
+
+    /// Switch to the secondary after this many failures in a row. The default is 3.
+    pub fn max_errors(mut self, count: u32) -> FailoverFs<P, S> {
+        self.max_errors = count.max(1);
+        self
+    }
+
+    /// Count calls which take longer than this as failures. By default, there's no timeout.
+    pub fn timeout(mut self, timeout: Duration) -> FailoverFs<P, S> {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// How often to check whether the primary is working again, while the secondary is active.
+    /// The default is 10 seconds.
+    pub fn probe_interval(mut self, interval: Duration) -> FailoverFs<P, S> {
+        self.probe_interval = interval;
+        self
+    }
+
+    /// Whether calls are currently going to the secondary.
+    pub fn is_failed_over(&self) -> bool {
+        self.state.lock().unwrap().active == Backend::Secondary
+    }
+
+    /// Get the backend to use for a new call, first checking on the primary if it's time to.
+    fn active(&self, req: RequestInfo) -> Backend {
+        {
+            let mut state = self.state.lock().unwrap();
+            if state.active == Backend::Primary || state.last_probe.elapsed() < self.probe_interval {
+                return state.active;
+            }
+            state.last_probe = Instant::now();
+        }
+
+        let start = Instant::now();
+        let root = FsPath::from("/");
+        let probe = Responder::wait(|reply| self.primary.getattr_deferred(req, &root, None, reply));
+        let healthy = probe.is_ok() && !self.too_slow(start);
+        let mut state = self.state.lock().unwrap();
+        if healthy && state.active == Backend::Secondary {
+            info!("primary filesystem is working again; switching back to it");
+            state.active = Backend::Primary;
+            state.errors = 0;
+        }
+        state.active
+    }
+
+    fn too_slow(&self, start: Instant) -> bool {
+        self.timeout.is_some_and(|timeout| start.elapsed() > timeout)
+    }
+
+    /// Record the outcome of a call to a backend. Returns true if this made the primary fail over
+    /// to the secondary.
+    fn record(&self, backend: Backend, start: Instant, error: Option<libc::c_int>) -> bool {
+        let failed = error.is_some_and(|e| BACKEND_ERRORS.contains(&e)) || self.too_slow(start);
+        let mut state = self.state.lock().unwrap();
+        if backend != state.active {
+            return false;
+        }
+        if !failed {
+            state.errors = 0;
+            return false;
+        }
+        state.errors += 1;
+        if backend == Backend::Primary && state.errors >= self.max_errors {
+            warn!("primary filesystem failed {} times in a row; switching to the secondary",
+                state.errors);
+            state.active = Backend::Secondary;
+            state.errors = 0;
+            state.last_probe = Instant::now();
+            return true;
+        }
+        false
+    }
+
+    fn handle(&self, fh: u64) -> Result<(Backend, u64), libc::c_int> {
+        self.handles.lock().unwrap().get(&fh).copied().ok_or(libc::EBADF)
+    }
+
+    /// Give out a handle for a backend's handle, for a call which just opened one.
+    fn add_handle(&self, backend: Backend, fh: u64) -> u64 {
+        let ours = self.next_fh.fetch_add(1, Ordering::Relaxed);
+        self.handles.lock().unwrap().insert(ours, (backend, fh));
+        ours
+    }
+
+    fn remove_handle(&self, fh: u64) {
+        self.handles.lock().unwrap().remove(&fh);
+    }
+
+    /// Open something on the active backend, returning a handle of ours for it.
+    fn open_with<T>(&self, req: RequestInfo, open_primary: impl Fn(&P) -> Result<T, libc::c_int>,
+        open_secondary: impl Fn(&S) -> Result<T, libc::c_int>, fh: impl Fn(&mut T) -> &mut u64)
+        -> Result<T, libc::c_int>
+    {
+        let mut backend = self.active(req);
+        let start = Instant::now();
+        let mut result = match backend {
+            Backend::Primary => open_primary(&self.primary),
+            Backend::Secondary => open_secondary(&self.secondary),
+        };
+        if self.record(backend, start, result.as_ref().err().copied()) {
+            backend = Backend::Secondary;
+            result = open_secondary(&self.secondary);
+        }
+        let mut opened = result?;
+        let inner = fh(&mut opened);
+        *inner = self.add_handle(backend, *inner);
+        Ok(opened)
+    }
+}
+
+impl<P: FilesystemMT, S: FilesystemMT> FilesystemMT for FailoverFs<P, S> {
+    fn init(&self, req: RequestInfo) -> ResultEmpty {
+        let secondary = self.secondary.init(req);
+        if let Err(e) = self.primary.init(req) {
+            error!("primary filesystem failed to start: {}", e);
+            secondary?;
+            let mut state = self.state.lock().unwrap();
+            state.active = Backend::Secondary;
+            state.last_probe = Instant::now();
+            return Ok(());
+        }
+        if let Err(e) = secondary {
+            warn!("secondary filesystem failed to start: {}", e);
+        }
+        Ok(())
+    }
+
+    fn destroy(&self) {
+        self.primary.destroy();
+        self.secondary.destroy();
+    }
+
+    fn getattr(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>) -> ResultEntry {
+        match fh {
+            Some(fh) => on_handle!(self, fh, |fs, fh| fs.getattr(req, path, Some(fh))),
+            None => route!(self, req, |fs| fs.getattr(req, path, None)),
+        }
+    }
+
+    fn getattr_mask(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, mask: AttrMask) -> ResultEntry {
+        match fh {
+            Some(fh) => on_handle!(self, fh, |fs, fh| fs.getattr_mask(req, path, Some(fh), mask)),
+            None => route!(self, req, |fs| fs.getattr_mask(req, path, None, mask)),
+        }
+    }
+
+    // Failures have to be counted, and maybe retried on the secondary, before the result is passed
+    // on, so the deferred calls wait for their backend's result.
+
+    fn getattr_deferred(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, reply: Responder<(Duration, FileAttr)>) {
+        reply.send(match fh {
+            Some(fh) => on_handle!(self, fh, |fs, fh| {
+                Responder::wait(|inner| fs.getattr_deferred(req, path, Some(fh), inner))
+            }),
+            None => route!(self, req, |fs| {
+                Responder::wait(|inner| fs.getattr_deferred(req, path, None, inner))
+            }),
+        })
+    }
+
+    fn chmod(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, mode: u32) -> ResultEmpty {
+        match fh {
+            Some(fh) => on_handle!(self, fh, |fs, fh| fs.chmod(req, path, Some(fh), mode)),
+            None => route!(self, req, |fs| fs.chmod(req, path, None, mode)),
+        }
+    }
+
+    fn chown(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, uid: Option<u32>, gid: Option<u32>) -> ResultEmpty {
+        match fh {
+            Some(fh) => on_handle!(self, fh, |fs, fh| fs.chown(req, path, Some(fh), uid, gid)),
+            None => route!(self, req, |fs| fs.chown(req, path, None, uid, gid)),
+        }
+    }
+
+    fn truncate(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, size: u64) -> ResultEmpty {
+        match fh {
+            Some(fh) => on_handle!(self, fh, |fs, fh| fs.truncate(req, path, Some(fh), size)),
+            None => route!(self, req, |fs| fs.truncate(req, path, None, size)),
+        }
+    }
+
+    fn utimens(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, atime: Option<SystemTime>, mtime: Option<SystemTime>) -> ResultEmpty {
+        match fh {
+            Some(fh) => on_handle!(self, fh, |fs, fh| fs.utimens(req, path, Some(fh), atime, mtime)),
+            None => route!(self, req, |fs| fs.utimens(req, path, None, atime, mtime)),
+        }
+    }
+
+    fn utimens_macos(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, crtime: Option<SystemTime>, chgtime: Option<SystemTime>, bkuptime: Option<SystemTime>, flags: Option<u32>) -> ResultEmpty {
+        match fh {
+            Some(fh) => on_handle!(self, fh, |fs, fh| fs.utimens_macos(req, path, Some(fh), crtime, chgtime, bkuptime, flags)),
+            None => route!(self, req, |fs| fs.utimens_macos(req, path, None, crtime, chgtime, bkuptime, flags)),
+        }
+    }
+
+    fn readlink(&self, req: RequestInfo, path: &FsPath) -> ResultData {
+        route!(self, req, |fs| fs.readlink(req, path))
+    }
+
+    fn mknod(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, mode: u32, rdev: u32) -> ResultEntry {
+        route!(self, req, |fs| fs.mknod(req, parent, name, mode, rdev))
+    }
+
+    fn mkdir(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, mode: u32) -> ResultEntry {
+        route!(self, req, |fs| fs.mkdir(req, parent, name, mode))
+    }
+
+    fn unlink(&self, req: RequestInfo, parent: &FsPath, name: &OsStr) -> ResultEmpty {
+        route!(self, req, |fs| fs.unlink(req, parent, name))
+    }
+
+    fn rmdir(&self, req: RequestInfo, parent: &FsPath, name: &OsStr) -> ResultEmpty {
+        route!(self, req, |fs| fs.rmdir(req, parent, name))
+    }
+
+    fn symlink(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, target: &Path) -> ResultEntry {
+        route!(self, req, |fs| fs.symlink(req, parent, name, target))
+    }
+
+    fn rename(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, newparent: &FsPath, newname: &OsStr) -> ResultEmpty {
+        route!(self, req, |fs| fs.rename(req, parent, name, newparent, newname))
+    }
+
+    fn link(&self, req: RequestInfo, path: &FsPath, newparent: &FsPath, newname: &OsStr) -> ResultEntry {
+        route!(self, req, |fs| fs.link(req, path, newparent, newname))
+    }
+
+    fn open(&self, req: RequestInfo, path: &FsPath, flags: u32) -> ResultOpen {
+        self.open_with(req,
+            |fs| fs.open(req, path, flags),
+            |fs| fs.open(req, path, flags),
+            |opened| &mut opened.fh)
+    }
+
+    fn open_handle(&self, req: RequestInfo, path: &FsPath, flags: u32) -> ResultOpenHandle {
+        // Handle objects stay with the backend they came from, same as numeric handles.
+        route!(self, req, |fs| fs.open_handle(req, path, flags))
+    }
+
+    fn read(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, size: u32, callback: impl FnOnce(ResultSlice<'_>) -> CallbackResult) -> CallbackResult {
+        let (backend, fh) = match self.handle(fh) {
+            Ok(handle) => handle,
+            Err(e) => return callback(Err(e)),
+        };
+        let start = Instant::now();
+        let mut error = None;
+        let wrapped = |result: ResultSlice<'_>| {
+            error = result.err();
+            callback(result)
+        };
+        let result = match backend {
+            Backend::Primary => self.primary.read(req, path, fh, offset, size, wrapped),
+            Backend::Secondary => self.secondary.read(req, path, fh, offset, size, wrapped),
+        };
+        self.record(backend, start, error);
+        result
+    }
+
+    fn write(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, data: Vec<u8>, flags: u32) -> ResultWrite {
+        on_handle!(self, fh, |fs, fh| fs.write(req, path, fh, offset, data, flags))
+    }
+
+    fn write_deferred(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, data: Vec<u8>, flags: u32, reply: Responder<u32>) {
+        reply.send(on_handle!(self, fh, |fs, fh| {
+            Responder::wait(|inner| fs.write_deferred(req, path, fh, offset, data, flags, inner))
+        }))
+    }
+
+    fn getlk(&self, req: RequestInfo, path: &FsPath, fh: u64, lock_owner: LockOwner, lock: FileLock) -> ResultLock {
+        on_handle!(self, fh, |fs, fh| fs.getlk(req, path, fh, lock_owner, lock))
+    }
+
+    fn setlk(&self, req: RequestInfo, path: &FsPath, fh: u64, lock_owner: LockOwner, lock: FileLock, sleep: bool) -> ResultEmpty {
+        on_handle!(self, fh, |fs, fh| fs.setlk(req, path, fh, lock_owner, lock, sleep))
+    }
+
+    fn flock(&self, req: RequestInfo, path: &FsPath, fh: u64, lock_owner: LockOwner, operation: i32) -> ResultEmpty {
+        on_handle!(self, fh, |fs, fh| fs.flock(req, path, fh, lock_owner, operation))
+    }
+
+    fn fallocate(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, length: u64, mode: i32) -> ResultEmpty {
+        on_handle!(self, fh, |fs, fh| fs.fallocate(req, path, fh, offset, length, mode))
+    }
+
+    fn lseek(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, whence: i32) -> ResultOffset {
+        on_handle!(self, fh, |fs, fh| fs.lseek(req, path, fh, offset, whence))
+    }
+
+    fn copy_file_range(&self, req: RequestInfo, path_in: &FsPath, fh_in: u64, offset_in: u64, path_out: &FsPath, fh_out: u64, offset_out: u64, len: u64, flags: u32) -> ResultWrite {
+        let (backend_in, _) = self.handle(fh_in)?;
+        let (backend_out, fh_out) = self.handle(fh_out)?;
+        if backend_in != backend_out {
+            // The kernel falls back to copying by reading and writing.
+            return Err(libc::EXDEV);
+        }
+        on_handle!(self, fh_in, |fs, fh_in| {
+            fs.copy_file_range(req, path_in, fh_in, offset_in, path_out, fh_out, offset_out, len, flags)
+        })
+    }
+
+    fn flush(&self, req: RequestInfo, path: &FsPath, fh: u64, lock_owner: LockOwner) -> ResultEmpty {
+        on_handle!(self, fh, |fs, fh| fs.flush(req, path, fh, lock_owner))
+    }
+
+    fn flush_deferred(&self, req: RequestInfo, path: &FsPath, fh: u64, lock_owner: LockOwner, reply: Responder<()>) {
+        reply.send(on_handle!(self, fh, |fs, fh| {
+            Responder::wait(|inner| fs.flush_deferred(req, path, fh, lock_owner, inner))
+        }))
+    }
+
+    fn release(&self, req: RequestInfo, path: &FsPath, fh: u64, flags: u32, lock_owner: Option<LockOwner>, flush: bool) -> ResultEmpty {
+        let ours = fh;
+        let result = on_handle!(self, fh, |fs, fh| fs.release(req, path, fh, flags, lock_owner, flush));
+        self.remove_handle(ours);
+        result
+    }
+
+    fn fsync(&self, req: RequestInfo, path: &FsPath, fh: u64, datasync: bool) -> ResultEmpty {
+        on_handle!(self, fh, |fs, fh| fs.fsync(req, path, fh, datasync))
+    }
+
+    fn fsync_deferred(&self, req: RequestInfo, path: &FsPath, fh: u64, datasync: bool, reply: Responder<()>) {
+        reply.send(on_handle!(self, fh, |fs, fh| {
+            Responder::wait(|inner| fs.fsync_deferred(req, path, fh, datasync, inner))
+        }))
+    }
+
+    fn sync(&self, filter: &dyn Fn(&Path) -> bool) -> ResultEmpty {
+        match self.state.lock().unwrap().active {
+            Backend::Primary => self.primary.sync(filter),
+            Backend::Secondary => self.secondary.sync(filter),
+        }
+    }
+
+    fn opendir(&self, req: RequestInfo, path: &FsPath, flags: u32) -> ResultOpen {
+        self.open_with(req,
+            |fs| fs.opendir(req, path, flags),
+            |fs| fs.opendir(req, path, flags),
+            |opened| &mut opened.fh)
+    }
+
+    fn opendir_handle(&self, req: RequestInfo, path: &FsPath, flags: u32) -> ResultOpenDirHandle {
+        route!(self, req, |fs| fs.opendir_handle(req, path, flags))
+    }
+
+    fn readdir(&self, req: RequestInfo, path: &FsPath, fh: u64) -> ResultReaddir {
+        on_handle!(self, fh, |fs, fh| fs.readdir(req, path, fh))
+    }
+
+    fn readdir_deferred(&self, req: RequestInfo, path: &FsPath, fh: u64, reply: Responder<Vec<DirectoryEntry>>) {
+        reply.send(on_handle!(self, fh, |fs, fh| {
+            Responder::wait(|inner| fs.readdir_deferred(req, path, fh, inner))
+        }))
+    }
+
+    fn readdir_at(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, add: &mut dyn FnMut(&DirectoryEntry, u64) -> bool) -> ResultEmpty {
+        on_handle!(self, fh, |fs, fh| fs.readdir_at(req, path, fh, offset, add))
+    }
+
+    fn releasedir(&self, req: RequestInfo, path: &FsPath, fh: u64, flags: u32) -> ResultEmpty {
+        let ours = fh;
+        let result = on_handle!(self, fh, |fs, fh| fs.releasedir(req, path, fh, flags));
+        self.remove_handle(ours);
+        result
+    }
+
+    fn fsyncdir(&self, req: RequestInfo, path: &FsPath, fh: u64, datasync: bool) -> ResultEmpty {
+        on_handle!(self, fh, |fs, fh| fs.fsyncdir(req, path, fh, datasync))
+    }
+
+    fn statfs(&self, req: RequestInfo, path: &FsPath) -> ResultStatfs {
+        route!(self, req, |fs| fs.statfs(req, path))
+    }
+
+    fn quota(&self, req: RequestInfo, path: &FsPath) -> ResultQuota {
+        route!(self, req, |fs| fs.quota(req, path))
+    }
+
+    fn setxattr(&self, req: RequestInfo, path: &FsPath, name: &OsStr, value: &[u8], flags: u32, position: XattrPosition) -> ResultEmpty {
+        route!(self, req, |fs| fs.setxattr(req, path, name, value, flags, position))
+    }
+
+    fn getxattr(&self, req: RequestInfo, path: &FsPath, name: &OsStr, size: u32) -> ResultXattr {
+        route!(self, req, |fs| fs.getxattr(req, path, name, size))
+    }
+
+    fn listxattr(&self, req: RequestInfo, path: &FsPath, size: u32) -> ResultXattr {
+        route!(self, req, |fs| fs.listxattr(req, path, size))
+    }
+
+    fn removexattr(&self, req: RequestInfo, path: &FsPath, name: &OsStr) -> ResultEmpty {
+        route!(self, req, |fs| fs.removexattr(req, path, name))
+    }
+
+    fn access(&self, req: RequestInfo, path: &FsPath, mask: u32) -> ResultEmpty {
+        route!(self, req, |fs| fs.access(req, path, mask))
+    }
+
+    fn create(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, mode: u32, flags: u32) -> ResultCreate {
+        self.open_with(req,
+            |fs| fs.create(req, parent, name, mode, flags),
+            |fs| fs.create(req, parent, name, mode, flags),
+            |created| &mut created.fh)
+    }
+
+    fn create_handle(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, mode: u32, flags: u32) -> ResultCreateHandle {
+        route!(self, req, |fs| fs.create_handle(req, parent, name, mode, flags))
+    }
+
+    #[cfg(target_os = "macos")]
+    fn setvolname(&self, req: RequestInfo, name: &OsStr) -> ResultEmpty {
+        route!(self, req, |fs| fs.setvolname(req, name))
+    }
+
+    #[cfg(target_os = "macos")]
+    fn exchange(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, newparent: &FsPath, newname: &OsStr, options: u64) -> ResultEmpty {
+        route!(self, req, |fs| fs.exchange(req, parent, name, newparent, newname, options))
+    }
+
+    #[cfg(target_os = "macos")]
+    fn getxtimes(&self, req: RequestInfo, path: &FsPath) -> ResultXTimes {
+        route!(self, req, |fs| fs.getxtimes(req, path))
+    }
+
+    #[cfg(target_os = "macos")]
+    fn setxtimes(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, bkuptime: Option<SystemTime>, crtime: Option<SystemTime>) -> ResultEmpty {
+        match fh {
+            Some(fh) => on_handle!(self, fh, |fs, fh| fs.setxtimes(req, path, Some(fh), bkuptime, crtime)),
+            None => route!(self, req, |fs| fs.setxtimes(req, path, None, bkuptime, crtime)),
+        }
+    }
+
+    fn classify(&self, op: Operation, path: &FsPath) -> Lane {
+        // Both backends serve the same files, so they should agree on this.
+        self.primary.classify(op, path)
+    }
+
+    fn load(&self, op: Operation, path: &FsPath) -> Load {
+        match self.state.lock().unwrap().active {
+            Backend::Primary => self.primary.load(op, path),
+            Backend::Secondary => self.secondary.load(op, path),
+        }
+    }
+}
+
+#[test]
+fn test_failover() {
+    use std::sync::atomic::AtomicBool;
+    use super::test_fs;
+
+    struct Fs {
+        name: &'static str,
+        down: AtomicBool,
+    }
+
+    impl FilesystemMT for Fs {
+        fn getattr(&self, _req: RequestInfo, _path: &FsPath, _fh: Option<u64>) -> ResultEntry {
+            if self.down.load(Ordering::SeqCst) {
+                return Err(libc::ETIMEDOUT);
+            }
+            Ok((Duration::from_secs(1), test_fs::attr(crate::FileType::Directory, 0)))
+        }
+        fn readlink(&self, _req: RequestInfo, path: &FsPath) -> ResultData {
+            if self.down.load(Ordering::SeqCst) {
+                return Err(libc::EIO);
+            }
+            if path == Path::new("/missing") {
+                return Err(libc::ENOENT);
+            }
+            Ok(self.name.as_bytes().to_vec())
+        }
+        fn open(&self, _req: RequestInfo, _path: &FsPath, _flags: u32) -> ResultOpen {
+            Ok(OpenedFile::new(7))
+        }
+        fn write(&self, _req: RequestInfo, _path: &FsPath, fh: u64, _offset: u64, _data: Vec<u8>, _flags: u32) -> ResultWrite {
+            if self.down.load(Ordering::SeqCst) {
+                return Err(libc::EIO);
+            }
+            Ok(fh as u32)
+        }
+    }
+
+    let req = test_fs::req();
+    let path = &FsPath::from("/x");
+    let fs = FailoverFs::new(
+        Fs { name: "primary", down: AtomicBool::new(false) },
+        Fs { name: "secondary", down: AtomicBool::new(false) })
+        .max_errors(2)
+        .probe_interval(Duration::from_millis(0));
+
+    let fh = fs.open(req, path, 0).unwrap().fh;
+    assert_eq!(Ok(7), fs.write(req, path, fh, 0, vec![], 0));
+    assert_eq!(Err(libc::EBADF), fs.write(req, path, 7 + fh, 0, vec![], 0));
+
+    // Ordinary errors don't count.
+    for _ in 0 .. 3 {
+        assert_eq!(Err(libc::ENOENT), fs.readlink(req, &FsPath::from("/missing")));
+    }
+    assert!(!fs.is_failed_over());
+
+    // The second failure in a row switches over, and the call is retried.
+    fs.primary.down.store(true, Ordering::SeqCst);
+    assert_eq!(Err(libc::EIO), fs.readlink(req, path));
+    assert_eq!(Ok(b"secondary".to_vec()), fs.readlink(req, path));
+    assert!(fs.is_failed_over());
+    assert_eq!(Ok(b"secondary".to_vec()), fs.readlink(req, path));
+
+    // Handles stay with their backend.
+    assert_eq!(Err(libc::EIO), fs.write(req, path, fh, 0, vec![], 0));
+
+    // It switches back once the primary is healthy again.
+    fs.primary.down.store(false, Ordering::SeqCst);
+    assert_eq!(Ok(b"primary".to_vec()), fs.readlink(req, path));
+    assert!(!fs.is_failed_over());
+}
+
+#[test]
+fn test_failover_deferred() {
+    use super::test_fs::{self, DeferredFs};
+
+    let req = test_fs::req();
+    let fs = FailoverFs::new(DeferredFs::new(), DeferredFs::new());
+    fs.primary.mem.nodes.lock().unwrap().insert("/f".into(), Some(vec![]));
+    let path = &FsPath::from("/f");
+
+    let fh = fs.open(req, path, 0).unwrap().fh;
+    let written = Responder::wait(|reply| fs.write_deferred(req, path, fh, 0, b"abc".to_vec(), 0, reply));
+    assert_eq!(Ok(3), written);
+    let attr = Responder::wait(|reply| fs.getattr_deferred(req, path, None, reply));
+    assert_eq!(Ok(3), attr.map(|(_, attr)| attr.size));
+    let missing = &FsPath::from("/x");
+    let missing = Responder::wait(|reply| fs.getattr_deferred(req, missing, None, reply));
+    assert_eq!(Err(libc::ENOENT), missing);
+    assert_eq!(Ok(()), Responder::wait(|reply| fs.flush_deferred(req, path, fh, LockOwner(0), reply)));
+    assert!(!fs.is_failed_over());
+
+    // Calls on handles this wrapper didn't give out fail as they do without deferring.
+    let written = Responder::wait(|reply| fs.write_deferred(req, path, 99, 0, vec![], 0, reply));
+    assert_eq!(Err(libc::EBADF), written);
+}
@@ -0,0 +1,518 @@
+// Follow Symlinks :: resolving symbolic links instead of showing them to the kernel.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::OsStringExt;
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::FileType;
+use crate::types::*;
+
+/// Most symbolic links to follow while resolving one path, the same as Linux's limit.
+const DEFAULT_MAX_LINKS: usize = 40;
+
+/// A filesystem which resolves the symbolic links of another one itself, for backends which can
+/// store symbolic links (or something like them, such as redirect objects in an object store) but
+/// can't open, list, or change anything through them.
+///
+/// Every path is resolved before it's given to the inner filesystem, by looking up each of its
+/// components in turn and following those which are symbolic links, so the kernel sees each link
+/// as whatever it points to. Targets are taken to be relative to the directory the link is in,
+/// or to the root of the filesystem if absolute, and can't lead out of it. Resolving a path makes
+/// a `getattr` call for each of its components, and a `readlink` call for each link followed.
+///
+/// Following more than `max_links` links for one path fails with `ELOOP`. Links which loop, or
+/// whose targets don't exist, are shown as they are, so they can still be listed and removed.
+///
+/// Unlinking, renaming, and `rmdir` act on links themselves, not what they point to: since the
+/// kernel sees a link to a directory as a directory, `rmdir` on one removes the link.
+pub struct FollowSymlinksFs<FS> {
+    inner: FS,
+    max_links: usize,
+}
+
+impl<FS: FilesystemMT> FollowSymlinksFs<FS> {
+    pub fn new(inner: FS) -> FollowSymlinksFs<FS> {
+        FollowSymlinksFs {
+            inner,
+            max_links: DEFAULT_MAX_LINKS,
+        }
+    }
+
+    /// Set the most symbolic links to follow while resolving one path. The default is 40.
+    pub fn max_links(mut self, max_links: usize) -> FollowSymlinksFs<FS> {
+        self.max_links = max_links;
+        self
+    }
+
+    /// Resolve the symbolic links in a path. The last component is only followed if `follow_last`
+    /// is set; otherwise only the directories it's in are resolved.
+    fn resolve(&self, req: RequestInfo, path: &Path, follow_last: bool) -> Result<FsPath, libc::c_int> {
+        let mut resolved = PathBuf::from("/");
+        // Components left to resolve, last first.
+        let mut pending = components(path);
+        let mut links = 0;
+        while let Some(name) = pending.pop() {
+            if name == ".." {
+                resolved.pop();
+                continue;
+            }
+            let next = FsPath::from(resolved.join(&name));
+            if pending.is_empty() && !follow_last {
+                return Ok(next);
+            }
+            let (_, attr) = self.lookup(req, &next)?;
+            match attr.kind {
+                FileType::Symlink => {
+                    links += 1;
+                    if links > self.max_links {
+                        debug!("too many symbolic links resolving {:?}", path);
+                        return Err(libc::ELOOP);
+                    }
+                    let target = self.inner.readlink(req, &next)?;
+                    let target = PathBuf::from(OsString::from_vec(target));
+                    if target.has_root() {
+                        resolved = PathBuf::from("/");
+                    }
+                    pending.extend(components(&target));
+                }
+                FileType::Directory => resolved = next.into(),
+                _ if pending.is_empty() => resolved = next.into(),
+                _ => return Err(libc::ENOTDIR),
+            }
+        }
+        Ok(FsPath::from(resolved))
+    }
+
+    /// Resolve the path of a call on an open handle. The handle refers to the file no matter what
+    /// the path is, so if it can't be resolved any more, the path is passed on as it is.
+    fn resolve_handle(&self, req: RequestInfo, path: &FsPath) -> FsPath {
+        self.resolve(req, path, true).unwrap_or_else(|_| path.clone())
+    }
+
+    /// Resolve the directory an entry is in, keeping its name.
+    fn resolve_parent(&self, req: RequestInfo, parent: &Path) -> Result<FsPath, libc::c_int> {
+        self.resolve(req, parent, true)
+    }
+
+    /// Get the attributes of a path in the inner filesystem, through `getattr_deferred`, as FuseMT
+    /// would, in case that's all it implements.
+    fn lookup(&self, req: RequestInfo, path: &FsPath) -> ResultEntry {
+        Responder::wait(|reply| self.inner.getattr_deferred(req, path, None, reply))
+    }
+
+    /// Get the attributes to show for a path which couldn't be resolved: those of the link itself,
+    /// if it's a broken link, or else the error.
+    fn broken_link(&self, req: RequestInfo, path: &Path, error: libc::c_int) -> ResultEntry {
+        if error != libc::ENOENT && error != libc::ELOOP {
+            return Err(error);
+        }
+        let link = self.resolve(req, path, false)?;
+        match self.lookup(req, &link)? {
+            (ttl, attr) if attr.kind == FileType::Symlink => Ok((ttl, attr)),
+            _ => Err(error),
+        }
+    }
+
+    /// Show a directory entry which is a symbolic link as the kind of file it points to.
+    fn resolve_kind(&self, req: RequestInfo, dir: &Path, entry: &DirectoryEntry) -> DirectoryEntry {
+        let mut entry = entry.clone();
+        if entry.kind == FileType::Symlink {
+            let target = self.resolve(req, &dir.join(&entry.name), true)
+                .and_then(|target| self.lookup(req, &target));
+            if let Ok((ttl, attr)) = target {
+                entry.kind = attr.kind;
+                entry.attr = Some((ttl, attr));
+            }
+        }
+        entry
+    }
+}
+
+/// Split a path into the names in it, last first, leaving out `.` and the root.
+fn components(path: &Path) -> Vec<OsString> {
+    path.components()
+        .rev()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_owned()),
+            Component::ParentDir => Some(OsString::from("..")),
+            Component::RootDir | Component::CurDir | Component::Prefix(_) => None,
+        })
+        .collect()
+}
+
+impl<FS: FilesystemMT> FilesystemMT for FollowSymlinksFs<FS> {
+    forward_calls!(inner; init, init_connection, destroy, sync, setvolname, classify, load);
+
+    fn getattr(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>) -> ResultEntry {
+        match self.resolve(req, path, true) {
+            Ok(resolved) => self.inner.getattr(req, &resolved, fh),
+            Err(e) => self.broken_link(req, path, e),
+        }
+    }
+
+    fn getattr_mask(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, mask: AttrMask) -> ResultEntry {
+        match self.resolve(req, path, true) {
+            Ok(resolved) => self.inner.getattr_mask(req, &resolved, fh, mask),
+            Err(e) => self.broken_link(req, path, e),
+        }
+    }
+
+    fn getattr_deferred(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, reply: Responder<(Duration, FileAttr)>) {
+        match self.resolve(req, path, true) {
+            Ok(resolved) => self.inner.getattr_deferred(req, &resolved, fh, reply),
+            Err(e) => reply.send(self.broken_link(req, path, e)),
+        }
+    }
+
+    fn chmod(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, mode: u32) -> ResultEmpty {
+        let path = self.resolve(req, path, true)?;
+        self.inner.chmod(req, &path, fh, mode)
+    }
+
+    fn chown(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, uid: Option<u32>, gid: Option<u32>) -> ResultEmpty {
+        let path = self.resolve(req, path, true)?;
+        self.inner.chown(req, &path, fh, uid, gid)
+    }
+
+    fn truncate(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, size: u64) -> ResultEmpty {
+        let path = self.resolve(req, path, true)?;
+        self.inner.truncate(req, &path, fh, size)
+    }
+
+    fn utimens(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, atime: Option<SystemTime>, mtime: Option<SystemTime>) -> ResultEmpty {
+        let path = self.resolve(req, path, true)?;
+        self.inner.utimens(req, &path, fh, atime, mtime)
+    }
+
+    fn utimens_macos(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, crtime: Option<SystemTime>, chgtime: Option<SystemTime>, bkuptime: Option<SystemTime>, flags: Option<u32>) -> ResultEmpty {
+        let path = self.resolve(req, path, true)?;
+        self.inner.utimens_macos(req, &path, fh, crtime, chgtime, bkuptime, flags)
+    }
+
+    fn readlink(&self, req: RequestInfo, path: &FsPath) -> ResultData {
+        // Only called for the broken links the kernel is shown.
+        let path = self.resolve(req, path, false)?;
+        self.inner.readlink(req, &path)
+    }
+
+    fn mknod(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, mode: u32, rdev: u32) -> ResultEntry {
+        let parent = self.resolve_parent(req, parent)?;
+        self.inner.mknod(req, &parent, name, mode, rdev)
+    }
+
+    fn mkdir(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, mode: u32) -> ResultEntry {
+        let parent = self.resolve_parent(req, parent)?;
+        self.inner.mkdir(req, &parent, name, mode)
+    }
+
+    fn unlink(&self, req: RequestInfo, parent: &FsPath, name: &OsStr) -> ResultEmpty {
+        let parent = self.resolve_parent(req, parent)?;
+        self.inner.unlink(req, &parent, name)
+    }
+
+    fn rmdir(&self, req: RequestInfo, parent: &FsPath, name: &OsStr) -> ResultEmpty {
+        let parent = self.resolve_parent(req, parent)?;
+        let (_, attr) = self.lookup(req, &FsPath::from(parent.join(name)))?;
+        if attr.kind == FileType::Symlink {
+            return self.inner.unlink(req, &parent, name);
+        }
+        self.inner.rmdir(req, &parent, name)
+    }
+
+    fn symlink(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, target: &Path) -> ResultEntry {
+        let parent = self.resolve_parent(req, parent)?;
+        self.inner.symlink(req, &parent, name, target)?;
+        self.getattr(req, &FsPath::from(parent.join(name)), None)
+    }
+
+    fn rename(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, newparent: &FsPath, newname: &OsStr) -> ResultEmpty {
+        let parent = self.resolve_parent(req, parent)?;
+        let newparent = self.resolve_parent(req, newparent)?;
+        self.inner.rename(req, &parent, name, &newparent, newname)
+    }
+
+    #[cfg(target_os = "macos")]
+    fn exchange(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, newparent: &FsPath, newname: &OsStr, options: u64) -> ResultEmpty {
+        let parent = self.resolve_parent(req, parent)?;
+        let newparent = self.resolve_parent(req, newparent)?;
+        self.inner.exchange(req, &parent, name, &newparent, newname, options)
+    }
+
+    fn link(&self, req: RequestInfo, path: &FsPath, newparent: &FsPath, newname: &OsStr) -> ResultEntry {
+        let path = self.resolve(req, path, true)?;
+        let newparent = self.resolve_parent(req, newparent)?;
+        self.inner.link(req, &path, &newparent, newname)
+    }
+
+    fn open(&self, req: RequestInfo, path: &FsPath, flags: u32) -> ResultOpen {
+        let path = self.resolve(req, path, true)?;
+        self.inner.open(req, &path, flags)
+    }
+
+    fn open_handle(&self, req: RequestInfo, path: &FsPath, flags: u32) -> ResultOpenHandle {
+        let path = self.resolve(req, path, true)?;
+        self.inner.open_handle(req, &path, flags)
+    }
+
+    fn read(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, size: u32, callback: impl FnOnce(ResultSlice<'_>) -> CallbackResult) -> CallbackResult {
+        let path = self.resolve_handle(req, path);
+        self.inner.read(req, &path, fh, offset, size, callback)
+    }
+
+    fn write(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, data: Vec<u8>, flags: u32) -> ResultWrite {
+        let path = self.resolve_handle(req, path);
+        self.inner.write(req, &path, fh, offset, data, flags)
+    }
+
+    fn write_deferred(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, data: Vec<u8>, flags: u32, reply: Responder<u32>) {
+        let path = self.resolve_handle(req, path);
+        self.inner.write_deferred(req, &path, fh, offset, data, flags, reply)
+    }
+
+    fn flush(&self, req: RequestInfo, path: &FsPath, fh: u64, lock_owner: LockOwner) -> ResultEmpty {
+        let path = self.resolve_handle(req, path);
+        self.inner.flush(req, &path, fh, lock_owner)
+    }
+
+    fn flush_deferred(&self, req: RequestInfo, path: &FsPath, fh: u64, lock_owner: LockOwner, reply: Responder<()>) {
+        let path = self.resolve_handle(req, path);
+        self.inner.flush_deferred(req, &path, fh, lock_owner, reply)
+    }
+
+    fn release(&self, req: RequestInfo, path: &FsPath, fh: u64, flags: u32, lock_owner: Option<LockOwner>, flush: bool) -> ResultEmpty {
+        let path = self.resolve_handle(req, path);
+        self.inner.release(req, &path, fh, flags, lock_owner, flush)
+    }
+
+    fn fsync(&self, req: RequestInfo, path: &FsPath, fh: u64, datasync: bool) -> ResultEmpty {
+        let path = self.resolve_handle(req, path);
+        self.inner.fsync(req, &path, fh, datasync)
+    }
+
+    fn fsync_deferred(&self, req: RequestInfo, path: &FsPath, fh: u64, datasync: bool, reply: Responder<()>) {
+        let path = self.resolve_handle(req, path);
+        self.inner.fsync_deferred(req, &path, fh, datasync, reply)
+    }
+
+    fn opendir(&self, req: RequestInfo, path: &FsPath, flags: u32) -> ResultOpen {
+        let path = self.resolve(req, path, true)?;
+        self.inner.opendir(req, &path, flags)
+    }
+
+    fn opendir_handle(&self, _req: RequestInfo, _path: &FsPath, _flags: u32) -> ResultOpenDirHandle {
+        // Directory handles from the inner filesystem would list links as links.
+        Err(libc::ENOSYS)
+    }
+
+    fn readdir(&self, req: RequestInfo, path: &FsPath, fh: u64) -> ResultReaddir {
+        let path = self.resolve_handle(req, path);
+        let entries = self.inner.readdir(req, &path, fh)?;
+        Ok(entries.iter().map(|entry| self.resolve_kind(req, &path, entry)).collect())
+    }
+
+    fn readdir_deferred(&self, req: RequestInfo, path: &FsPath, fh: u64, reply: Responder<Vec<DirectoryEntry>>) {
+        // Resolving the entries' kinds needs this wrapper, so it can't be done later.
+        let path = self.resolve_handle(req, path);
+        let entries = Responder::wait(|inner| self.inner.readdir_deferred(req, &path, fh, inner));
+        reply.send(entries.map(|entries| {
+            entries.iter().map(|entry| self.resolve_kind(req, &path, entry)).collect()
+        }))
+    }
+
+    fn readdir_at(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, add: &mut dyn FnMut(&DirectoryEntry, u64) -> bool) -> ResultEmpty {
+        let path = self.resolve_handle(req, path);
+        self.inner.readdir_at(req, &path, fh, offset, &mut |entry, next| {
+            add(&self.resolve_kind(req, &path, entry), next)
+        })
+    }
+
+    fn releasedir(&self, req: RequestInfo, path: &FsPath, fh: u64, flags: u32) -> ResultEmpty {
+        let path = self.resolve_handle(req, path);
+        self.inner.releasedir(req, &path, fh, flags)
+    }
+
+    fn fsyncdir(&self, req: RequestInfo, path: &FsPath, fh: u64, datasync: bool) -> ResultEmpty {
+        let path = self.resolve_handle(req, path);
+        self.inner.fsyncdir(req, &path, fh, datasync)
+    }
+
+    fn statfs(&self, req: RequestInfo, path: &FsPath) -> ResultStatfs {
+        let path = self.resolve(req, path, true)?;
+        self.inner.statfs(req, &path)
+    }
+
+    fn quota(&self, req: RequestInfo, path: &FsPath) -> ResultQuota {
+        let path = self.resolve(req, path, true)?;
+        self.inner.quota(req, &path)
+    }
+
+    fn setxattr(&self, req: RequestInfo, path: &FsPath, name: &OsStr, value: &[u8], flags: u32, position: XattrPosition) -> ResultEmpty {
+        let path = self.resolve(req, path, true)?;
+        self.inner.setxattr(req, &path, name, value, flags, position)
+    }
+
+    fn getxattr(&self, req: RequestInfo, path: &FsPath, name: &OsStr, size: u32) -> ResultXattr {
+        let path = self.resolve(req, path, true)?;
+        self.inner.getxattr(req, &path, name, size)
+    }
+
+    fn listxattr(&self, req: RequestInfo, path: &FsPath, size: u32) -> ResultXattr {
+        let path = self.resolve(req, path, true)?;
+        self.inner.listxattr(req, &path, size)
+    }
+
+    fn removexattr(&self, req: RequestInfo, path: &FsPath, name: &OsStr) -> ResultEmpty {
+        let path = self.resolve(req, path, true)?;
+        self.inner.removexattr(req, &path, name)
+    }
+
+    fn access(&self, req: RequestInfo, path: &FsPath, mask: u32) -> ResultEmpty {
+        let path = self.resolve(req, path, true)?;
+        self.inner.access(req, &path, mask)
+    }
+
+    fn create(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, mode: u32, flags: u32) -> ResultCreate {
+        let parent = self.resolve_parent(req, parent)?;
+        self.inner.create(req, &parent, name, mode, flags)
+    }
+
+    fn create_handle(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, mode: u32, flags: u32) -> ResultCreateHandle {
+        let parent = self.resolve_parent(req, parent)?;
+        self.inner.create_handle(req, &parent, name, mode, flags)
+    }
+
+    fn getlk(&self, req: RequestInfo, path: &FsPath, fh: u64, lock_owner: LockOwner, lock: FileLock) -> ResultLock {
+        let path = self.resolve_handle(req, path);
+        self.inner.getlk(req, &path, fh, lock_owner, lock)
+    }
+
+    fn setlk(&self, req: RequestInfo, path: &FsPath, fh: u64, lock_owner: LockOwner, lock: FileLock, sleep: bool) -> ResultEmpty {
+        let path = self.resolve_handle(req, path);
+        self.inner.setlk(req, &path, fh, lock_owner, lock, sleep)
+    }
+
+    fn flock(&self, req: RequestInfo, path: &FsPath, fh: u64, lock_owner: LockOwner, operation: i32) -> ResultEmpty {
+        let path = self.resolve_handle(req, path);
+        self.inner.flock(req, &path, fh, lock_owner, operation)
+    }
+
+    fn fallocate(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, length: u64, mode: i32) -> ResultEmpty {
+        let path = self.resolve_handle(req, path);
+        self.inner.fallocate(req, &path, fh, offset, length, mode)
+    }
+
+    fn lseek(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, whence: i32) -> ResultOffset {
+        let path = self.resolve_handle(req, path);
+        self.inner.lseek(req, &path, fh, offset, whence)
+    }
+
+    fn copy_file_range(&self, req: RequestInfo, path_in: &FsPath, fh_in: u64, offset_in: u64, path_out: &FsPath, fh_out: u64, offset_out: u64, len: u64, flags: u32) -> ResultWrite {
+        let path_in = self.resolve_handle(req, path_in);
+        let path_out = self.resolve_handle(req, path_out);
+        self.inner.copy_file_range(req, &path_in, fh_in, offset_in, &path_out, fh_out, offset_out, len, flags)
+    }
+
+    #[cfg(target_os = "macos")]
+    fn getxtimes(&self, req: RequestInfo, path: &FsPath) -> ResultXTimes {
+        let path = self.resolve(req, path, true)?;
+        self.inner.getxtimes(req, &path)
+    }
+
+    #[cfg(target_os = "macos")]
+    fn setxtimes(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, bkuptime: Option<SystemTime>, crtime: Option<SystemTime>) -> ResultEmpty {
+        let path = self.resolve(req, path, true)?;
+        self.inner.setxtimes(req, &path, fh, bkuptime, crtime)
+    }
+}
+
+#[test]
+fn test_follow_symlinks() {
+    use std::collections::HashMap;
+    use super::test_fs::{self, MemFs};
+    use super::{list_dir, read_all};
+
+    /// The test filesystem, with symbolic links added.
+    struct Fs {
+        inner: MemFs,
+        links: HashMap<PathBuf, &'static str>,
+    }
+
+    impl FilesystemMT for Fs {
+        forward_calls!(inner; open, read, release, opendir, releasedir);
+
+        fn getattr(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>) -> ResultEntry {
+            match self.links.get(path.as_path()) {
+                Some(_) => Ok((Default::default(), test_fs::attr(FileType::Symlink, 0))),
+                None => self.inner.getattr(req, path, fh),
+            }
+        }
+
+        fn readlink(&self, _req: RequestInfo, path: &FsPath) -> ResultData {
+            let target = self.links.get(path.as_path()).ok_or(libc::EINVAL)?;
+            Ok(target.as_bytes().to_vec())
+        }
+
+        fn readdir(&self, req: RequestInfo, path: &FsPath, fh: u64) -> ResultReaddir {
+            let mut entries = self.inner.readdir(req, path, fh)?;
+            entries.extend(self.links.keys().filter(|link| link.parent() == Some(path.as_path()))
+                .map(|link| DirectoryEntry {
+                    name: link.file_name().unwrap().to_owned(),
+                    kind: FileType::Symlink,
+                    offset: None,
+                    attr: None,
+                }));
+            entries.sort_by(|a, b| a.name.cmp(&b.name));
+            Ok(entries)
+        }
+    }
+
+    let req = test_fs::req();
+    let root = &FsPath::from("/");
+    let inner = MemFs::new();
+    inner.mkdir(req, root, OsStr::new("dir"), 0o755).unwrap();
+    inner.nodes.lock().unwrap().insert(PathBuf::from("/dir/file"), Some(b"data".to_vec()));
+    let links = [
+        ("/l1", "dir"),
+        ("/l2", "/l1/file"),
+        ("/dir/up", "../l2"),
+        ("/loop", "./loop"),
+        ("/broken", "missing"),
+    ];
+    let links = links.iter().map(|&(link, target)| (PathBuf::from(link), target)).collect();
+    let fs = FollowSymlinksFs::new(Fs { inner, links });
+    let kind = |fs: &FollowSymlinksFs<Fs>, path: &str| {
+        fs.getattr(req, &FsPath::from(path), None).map(|(_, attr)| attr.kind)
+    };
+
+    // Links look like what they point to.
+    assert_eq!(Ok(FileType::Directory), kind(&fs, "/l1"));
+    assert_eq!(Ok(FileType::RegularFile), kind(&fs, "/l2"));
+    assert_eq!(Ok(b"data".to_vec()), read_all(&fs, req, &FsPath::from("/l1/up")));
+    let kinds = list_dir(&fs, req, root).unwrap().into_iter()
+        .map(|entry| (entry.name.into_string().unwrap(), entry.kind))
+        .collect::<Vec<_>>();
+    assert_eq!(vec![
+        ("broken".to_owned(), FileType::Symlink),
+        ("dir".to_owned(), FileType::Directory),
+        ("l1".to_owned(), FileType::Directory),
+        ("l2".to_owned(), FileType::RegularFile),
+        ("loop".to_owned(), FileType::Symlink),
+    ], kinds);
+
+    // Except broken ones.
+    assert_eq!(Ok(FileType::Symlink), kind(&fs, "/loop"));
+    assert_eq!(Ok(FileType::Symlink), kind(&fs, "/broken"));
+    assert_eq!(Err(libc::ELOOP), kind(&fs, "/loop/file"));
+    assert_eq!(Err(libc::ENOENT), kind(&fs, "/broken/file"));
+    assert_eq!(Err(libc::ENOTDIR), kind(&fs, "/l2/file"));
+
+    // Links count towards the limit as they're followed.
+    let fs = fs.max_links(2);
+    assert_eq!(Ok(FileType::RegularFile), kind(&fs, "/l2"));
+    assert_eq!(Err(libc::ELOOP), fs.resolve(req, Path::new("/dir/up"), true));
+    assert_eq!(Ok(FsPath::from("/dir/up")), fs.resolve(req, Path::new("/l1/up"), false));
+}
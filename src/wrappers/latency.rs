@@ -0,0 +1,435 @@
+// Latency :: making a filesystem slower on purpose, for testing.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use crate::types::*;
+
+/// The kinds of operations which can be given different latencies.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum OpClass {
+    /// Getting and setting attributes and xattrs, reading links, statfs, and access checks.
+    Metadata,
+    /// Reading files and directories.
+    Read,
+    /// Writing and truncating files.
+    Write,
+    /// Creating, removing, renaming, and linking entries.
+    Namespace,
+    /// Opening and closing files and directories.
+    Open,
+    /// Flushing and syncing.
+    Sync,
+}
+
+/// How long to delay an operation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Latency {
+    /// The same delay every time.
+    Fixed(Duration),
+    /// A delay chosen evenly between `min` and `max`.
+    Uniform { min: Duration, max: Duration },
+    /// A delay from a Pareto distribution: usually close to `scale`, but with a long tail of much
+    /// longer ones, the more so the smaller `shape` is. Capped at `max`.
+    Pareto { scale: Duration, shape: f64, max: Duration },
+}
+
+impl Latency {
+    fn sample(&self, rng: &mut Rng) -> Duration {
+        match *self {
+            Latency::Fixed(delay) => delay,
+            Latency::Uniform { min, max } => {
+                let range = max.saturating_sub(min);
+                min + range.mul_f64(rng.next_f64())
+            }
+            Latency::Pareto { scale, shape, max } => {
+                // Inverse transform: u in (0, 1].
+                let u = 1. - rng.next_f64();
+                let factor = u.powf(-1. / shape);
+                if factor.is_finite() {
+                    scale.mul_f64(factor).min(max)
+                } else {
+                    max
+                }
+            }
+        }
+    }
+}
+
+/// SplitMix64: tiny, fast, and good enough for picking delays.
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in [0, 1).
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// A filesystem which adds delays to the calls made to an inner filesystem, to simulate a slow
+/// disk or network on top of a fast backend.
+///
+/// Each class of operation can be given its own latency distribution; operations with none set
+/// aren't delayed. Delays are chosen by a pseudo-random generator with a fixed seed, so the same
+/// sequence of calls gets the same delays every time. (Calls from several threads at once are
+/// still delayed in whatever order they arrive.)
+///
+/// ```ignore
+/// let fs = LatencyFs::new(inner, 42)
+///     .latency(OpClass::Read, Latency::Uniform {
+///         min: Duration::from_millis(5),
+///         max: Duration::from_millis(20),
+///     })
+///     .latency(OpClass::Sync, Latency::Fixed(Duration::from_millis(100)));
+/// ```
+pub struct LatencyFs<FS> {
+    inner: FS,
+    latencies: HashMap<OpClass, Latency>,
+    rng: Mutex<Rng>,
+    sleep: fn(Duration),
+}
+
+impl<FS: FilesystemMT> LatencyFs<FS> {
+    /// Make a new latency wrapper, with the given seed for choosing delays.
+    pub fn new(inner: FS, seed: u64) -> LatencyFs<FS> {
+        LatencyFs {
+            inner,
+            latencies: HashMap::new(),
+            rng: Mutex::new(Rng(seed)),
+            sleep: std::thread::sleep,
+        }
+    }
+
+    /// Set the latency of a class of operations.
+    pub fn latency(mut self, class: OpClass, latency: Latency) -> LatencyFs<FS> {
+        self.latencies.insert(class, latency);
+        self
+    }
+
+    /// Choose a delay for an operation of the given class.
+    fn delay_for(&self, class: OpClass) -> Option<Duration> {
+        let latency = self.latencies.get(&class)?;
+        Some(latency.sample(&mut self.rng.lock().unwrap()))
+    }
+
+    fn delay(&self, class: OpClass) {
+        if let Some(delay) = self.delay_for(class) {
+            if !delay.is_zero() {
+                (self.sleep)(delay);
+            }
+        }
+    }
+}
+
+impl<FS: FilesystemMT> FilesystemMT for LatencyFs<FS> {
+    forward_calls!(inner; init, init_connection, destroy, last_release_after_unlink, getlk, setlk,
+        flock, classify, load);
+
+    fn getattr(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>) -> ResultEntry {
+        self.delay(OpClass::Metadata);
+        self.inner.getattr(req, path, fh)
+    }
+
+    fn getattr_mask(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, mask: AttrMask) -> ResultEntry {
+        self.delay(OpClass::Metadata);
+        self.inner.getattr_mask(req, path, fh, mask)
+    }
+
+    fn getattr_deferred(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, reply: Responder<(Duration, FileAttr)>) {
+        self.delay(OpClass::Metadata);
+        self.inner.getattr_deferred(req, path, fh, reply)
+    }
+
+    fn chmod(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, mode: u32) -> ResultEmpty {
+        self.delay(OpClass::Metadata);
+        self.inner.chmod(req, path, fh, mode)
+    }
+
+    fn chown(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, uid: Option<u32>, gid: Option<u32>) -> ResultEmpty {
+        self.delay(OpClass::Metadata);
+        self.inner.chown(req, path, fh, uid, gid)
+    }
+
+    fn truncate(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, size: u64) -> ResultEmpty {
+        self.delay(OpClass::Write);
+        self.inner.truncate(req, path, fh, size)
+    }
+
+    fn utimens(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, atime: Option<SystemTime>, mtime: Option<SystemTime>) -> ResultEmpty {
+        self.delay(OpClass::Metadata);
+        self.inner.utimens(req, path, fh, atime, mtime)
+    }
+
+    fn utimens_macos(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, crtime: Option<SystemTime>, chgtime: Option<SystemTime>, bkuptime: Option<SystemTime>, flags: Option<u32>) -> ResultEmpty {
+        self.delay(OpClass::Metadata);
+        self.inner.utimens_macos(req, path, fh, crtime, chgtime, bkuptime, flags)
+    }
+
+    fn readlink(&self, req: RequestInfo, path: &FsPath) -> ResultData {
+        self.delay(OpClass::Metadata);
+        self.inner.readlink(req, path)
+    }
+
+    fn mknod(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, mode: u32, rdev: u32) -> ResultEntry {
+        self.delay(OpClass::Namespace);
+        self.inner.mknod(req, parent, name, mode, rdev)
+    }
+
+    fn mkdir(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, mode: u32) -> ResultEntry {
+        self.delay(OpClass::Namespace);
+        self.inner.mkdir(req, parent, name, mode)
+    }
+
+    fn unlink(&self, req: RequestInfo, parent: &FsPath, name: &OsStr) -> ResultEmpty {
+        self.delay(OpClass::Namespace);
+        self.inner.unlink(req, parent, name)
+    }
+
+    fn rmdir(&self, req: RequestInfo, parent: &FsPath, name: &OsStr) -> ResultEmpty {
+        self.delay(OpClass::Namespace);
+        self.inner.rmdir(req, parent, name)
+    }
+
+    fn symlink(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, target: &Path) -> ResultEntry {
+        self.delay(OpClass::Namespace);
+        self.inner.symlink(req, parent, name, target)
+    }
+
+    fn rename(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, newparent: &FsPath, newname: &OsStr) -> ResultEmpty {
+        self.delay(OpClass::Namespace);
+        self.inner.rename(req, parent, name, newparent, newname)
+    }
+
+    fn link(&self, req: RequestInfo, path: &FsPath, newparent: &FsPath, newname: &OsStr) -> ResultEntry {
+        self.delay(OpClass::Namespace);
+        self.inner.link(req, path, newparent, newname)
+    }
+
+    fn open(&self, req: RequestInfo, path: &FsPath, flags: u32) -> ResultOpen {
+        self.delay(OpClass::Open);
+        self.inner.open(req, path, flags)
+    }
+
+    fn open_handle(&self, req: RequestInfo, path: &FsPath, flags: u32) -> ResultOpenHandle {
+        // Calls on the handle object itself go straight to it, so only opening is delayed.
+        self.delay(OpClass::Open);
+        self.inner.open_handle(req, path, flags)
+    }
+
+    fn read(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, size: u32, callback: impl FnOnce(ResultSlice<'_>) -> CallbackResult) -> CallbackResult {
+        self.delay(OpClass::Read);
+        self.inner.read(req, path, fh, offset, size, callback)
+    }
+
+    fn write(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, data: Vec<u8>, flags: u32) -> ResultWrite {
+        self.delay(OpClass::Write);
+        self.inner.write(req, path, fh, offset, data, flags)
+    }
+
+    fn write_deferred(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, data: Vec<u8>, flags: u32, reply: Responder<u32>) {
+        self.delay(OpClass::Write);
+        self.inner.write_deferred(req, path, fh, offset, data, flags, reply)
+    }
+
+    fn fallocate(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, length: u64, mode: i32) -> ResultEmpty {
+        self.delay(OpClass::Write);
+        self.inner.fallocate(req, path, fh, offset, length, mode)
+    }
+
+    fn lseek(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, whence: i32) -> ResultOffset {
+        self.delay(OpClass::Read);
+        self.inner.lseek(req, path, fh, offset, whence)
+    }
+
+    fn copy_file_range(&self, req: RequestInfo, path_in: &FsPath, fh_in: u64, offset_in: u64, path_out: &FsPath, fh_out: u64, offset_out: u64, len: u64, flags: u32) -> ResultWrite {
+        self.delay(OpClass::Write);
+        self.inner.copy_file_range(req, path_in, fh_in, offset_in, path_out, fh_out, offset_out, len, flags)
+    }
+
+    fn flush(&self, req: RequestInfo, path: &FsPath, fh: u64, lock_owner: LockOwner) -> ResultEmpty {
+        self.delay(OpClass::Sync);
+        self.inner.flush(req, path, fh, lock_owner)
+    }
+
+    fn flush_deferred(&self, req: RequestInfo, path: &FsPath, fh: u64, lock_owner: LockOwner, reply: Responder<()>) {
+        self.delay(OpClass::Sync);
+        self.inner.flush_deferred(req, path, fh, lock_owner, reply)
+    }
+
+    fn release(&self, req: RequestInfo, path: &FsPath, fh: u64, flags: u32, lock_owner: Option<LockOwner>, flush: bool) -> ResultEmpty {
+        self.delay(OpClass::Open);
+        self.inner.release(req, path, fh, flags, lock_owner, flush)
+    }
+
+    fn fsync(&self, req: RequestInfo, path: &FsPath, fh: u64, datasync: bool) -> ResultEmpty {
+        self.delay(OpClass::Sync);
+        self.inner.fsync(req, path, fh, datasync)
+    }
+
+    fn fsync_deferred(&self, req: RequestInfo, path: &FsPath, fh: u64, datasync: bool, reply: Responder<()>) {
+        self.delay(OpClass::Sync);
+        self.inner.fsync_deferred(req, path, fh, datasync, reply)
+    }
+
+    fn sync(&self, filter: &dyn Fn(&Path) -> bool) -> ResultEmpty {
+        self.delay(OpClass::Sync);
+        self.inner.sync(filter)
+    }
+
+    fn opendir(&self, req: RequestInfo, path: &FsPath, flags: u32) -> ResultOpen {
+        self.delay(OpClass::Open);
+        self.inner.opendir(req, path, flags)
+    }
+
+    fn opendir_handle(&self, req: RequestInfo, path: &FsPath, flags: u32) -> ResultOpenDirHandle {
+        self.delay(OpClass::Open);
+        self.inner.opendir_handle(req, path, flags)
+    }
+
+    fn readdir(&self, req: RequestInfo, path: &FsPath, fh: u64) -> ResultReaddir {
+        self.delay(OpClass::Read);
+        self.inner.readdir(req, path, fh)
+    }
+
+    fn readdir_deferred(&self, req: RequestInfo, path: &FsPath, fh: u64, reply: Responder<Vec<DirectoryEntry>>) {
+        self.delay(OpClass::Read);
+        self.inner.readdir_deferred(req, path, fh, reply)
+    }
+
+    fn readdir_at(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, add: &mut dyn FnMut(&DirectoryEntry, u64) -> bool) -> ResultEmpty {
+        self.delay(OpClass::Read);
+        self.inner.readdir_at(req, path, fh, offset, add)
+    }
+
+    fn releasedir(&self, req: RequestInfo, path: &FsPath, fh: u64, flags: u32) -> ResultEmpty {
+        self.delay(OpClass::Open);
+        self.inner.releasedir(req, path, fh, flags)
+    }
+
+    fn fsyncdir(&self, req: RequestInfo, path: &FsPath, fh: u64, datasync: bool) -> ResultEmpty {
+        self.delay(OpClass::Sync);
+        self.inner.fsyncdir(req, path, fh, datasync)
+    }
+
+    fn statfs(&self, req: RequestInfo, path: &FsPath) -> ResultStatfs {
+        self.delay(OpClass::Metadata);
+        self.inner.statfs(req, path)
+    }
+
+    fn quota(&self, req: RequestInfo, path: &FsPath) -> ResultQuota {
+        self.delay(OpClass::Metadata);
+        self.inner.quota(req, path)
+    }
+
+    fn setxattr(&self, req: RequestInfo, path: &FsPath, name: &OsStr, value: &[u8], flags: u32, position: XattrPosition) -> ResultEmpty {
+        self.delay(OpClass::Metadata);
+        self.inner.setxattr(req, path, name, value, flags, position)
+    }
+
+    fn getxattr(&self, req: RequestInfo, path: &FsPath, name: &OsStr, size: u32) -> ResultXattr {
+        self.delay(OpClass::Metadata);
+        self.inner.getxattr(req, path, name, size)
+    }
+
+    fn listxattr(&self, req: RequestInfo, path: &FsPath, size: u32) -> ResultXattr {
+        self.delay(OpClass::Metadata);
+        self.inner.listxattr(req, path, size)
+    }
+
+    fn removexattr(&self, req: RequestInfo, path: &FsPath, name: &OsStr) -> ResultEmpty {
+        self.delay(OpClass::Metadata);
+        self.inner.removexattr(req, path, name)
+    }
+
+    fn access(&self, req: RequestInfo, path: &FsPath, mask: u32) -> ResultEmpty {
+        self.delay(OpClass::Metadata);
+        self.inner.access(req, path, mask)
+    }
+
+    fn create(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, mode: u32, flags: u32) -> ResultCreate {
+        self.delay(OpClass::Namespace);
+        self.inner.create(req, parent, name, mode, flags)
+    }
+
+    fn create_handle(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, mode: u32, flags: u32) -> ResultCreateHandle {
+        self.delay(OpClass::Namespace);
+        self.inner.create_handle(req, parent, name, mode, flags)
+    }
+
+    #[cfg(target_os = "macos")]
+    fn setvolname(&self, req: RequestInfo, name: &OsStr) -> ResultEmpty {
+        self.delay(OpClass::Metadata);
+        self.inner.setvolname(req, name)
+    }
+
+    #[cfg(target_os = "macos")]
+    fn exchange(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, newparent: &FsPath, newname: &OsStr, options: u64) -> ResultEmpty {
+        self.delay(OpClass::Namespace);
+        self.inner.exchange(req, parent, name, newparent, newname, options)
+    }
+
+    #[cfg(target_os = "macos")]
+    fn getxtimes(&self, req: RequestInfo, path: &FsPath) -> ResultXTimes {
+        self.delay(OpClass::Metadata);
+        self.inner.getxtimes(req, path)
+    }
+
+    #[cfg(target_os = "macos")]
+    fn setxtimes(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, bkuptime: Option<SystemTime>, crtime: Option<SystemTime>) -> ResultEmpty {
+        self.delay(OpClass::Metadata);
+        self.inner.setxtimes(req, path, fh, bkuptime, crtime)
+    }
+}
+
+#[test]
+fn test_latency() {
+    use super::test_fs::{self, MemFs};
+
+    thread_local! {
+        static SLEPT: std::cell::RefCell<Vec<Duration>> = const { std::cell::RefCell::new(vec![]) };
+    }
+
+    let ms = Duration::from_millis;
+    let make = || {
+        let mut fs = LatencyFs::new(MemFs::new(), 7)
+            .latency(OpClass::Metadata, Latency::Fixed(ms(3)))
+            .latency(OpClass::Read, Latency::Uniform { min: ms(10), max: ms(20) })
+            .latency(OpClass::Namespace, Latency::Pareto { scale: ms(1), shape: 1.5, max: ms(50) });
+        fs.sleep = |delay| SLEPT.with(|slept| slept.borrow_mut().push(delay));
+        fs
+    };
+    let run = |fs: &LatencyFs<MemFs>| {
+        let req = test_fs::req();
+        SLEPT.with(|slept| slept.borrow_mut().clear());
+        for i in 0 .. 20 {
+            fs.getattr(req, &FsPath::from("/"), None).unwrap();
+            fs.readdir(req, &FsPath::from("/"), 0).unwrap();
+            fs.mkdir(req, &FsPath::from("/"), OsStr::new(&format!("d{}", i)), 0o755).unwrap();
+            fs.opendir(req, &FsPath::from("/"), 0).unwrap();
+        }
+        SLEPT.with(|slept| slept.borrow().clone())
+    };
+
+    let delays = run(&make());
+    assert_eq!(60, delays.len());
+    for call in delays.chunks(3) {
+        assert_eq!(ms(3), call[0]);
+        assert!(call[1] >= ms(10) && call[1] <= ms(20));
+        assert!(call[2] >= ms(1) && call[2] <= ms(50));
+    }
+    assert_eq!(delays, run(&make()));
+}
@@ -0,0 +1,426 @@
+// Wrappers :: filesystems which add behavior on top of another filesystem.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+//! Filesystem wrappers.
+//!
+//! Each of these implements `FilesystemMT` by delegating to an inner filesystem, adding some
+//! behavior along the way. They can be stacked, with the outermost one being given to `FuseMT`.
+
+use crate::sparse;
+use crate::types::*;
+
+/// Generate `FilesystemMT` methods which just pass the call through to the filesystem in the
+/// given field, for wrappers which don't need to do anything special for those calls.
+///
+/// ```ignore
+/// impl<FS: FilesystemMT> FilesystemMT for MyWrapper<FS> {
+///     forward_calls!(inner; init, destroy, getattr);
+///     // ... the other methods, implemented by hand.
+/// }
+/// ```
+macro_rules! forward_calls {
+    ($inner:ident; $($method:ident),* $(,)?) => {
+        $( forward_calls!(@ $inner $method); )*
+    };
+
+    (@ $inner:ident init) => {
+        fn init(&self, req: RequestInfo) -> ResultEmpty {
+            self.$inner.init(req)
+        }
+    };
+    (@ $inner:ident init_connection) => {
+        fn init_connection(&self, req: RequestInfo, conn: &ConnectionInfo, config: &mut KernelConfig) -> ResultEmpty {
+            self.$inner.init_connection(req, conn, config)
+        }
+    };
+    (@ $inner:ident destroy) => {
+        fn destroy(&self) {
+            self.$inner.destroy()
+        }
+    };
+    (@ $inner:ident getattr) => {
+        fn getattr(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>) -> ResultEntry {
+            self.$inner.getattr(req, path, fh)
+        }
+    };
+    (@ $inner:ident getattr_mask) => {
+        fn getattr_mask(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, mask: AttrMask) -> ResultEntry {
+            self.$inner.getattr_mask(req, path, fh, mask)
+        }
+    };
+    (@ $inner:ident getattr_deferred) => {
+        fn getattr_deferred(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, reply: Responder<(std::time::Duration, FileAttr)>) {
+            self.$inner.getattr_deferred(req, path, fh, reply)
+        }
+    };
+    (@ $inner:ident chmod) => {
+        fn chmod(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, mode: u32) -> ResultEmpty {
+            self.$inner.chmod(req, path, fh, mode)
+        }
+    };
+    (@ $inner:ident chown) => {
+        fn chown(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, uid: Option<u32>, gid: Option<u32>) -> ResultEmpty {
+            self.$inner.chown(req, path, fh, uid, gid)
+        }
+    };
+    (@ $inner:ident truncate) => {
+        fn truncate(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, size: u64) -> ResultEmpty {
+            self.$inner.truncate(req, path, fh, size)
+        }
+    };
+    (@ $inner:ident utimens) => {
+        fn utimens(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, atime: Option<SystemTime>, mtime: Option<SystemTime>) -> ResultEmpty {
+            self.$inner.utimens(req, path, fh, atime, mtime)
+        }
+    };
+    (@ $inner:ident utimens_macos) => {
+        fn utimens_macos(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, crtime: Option<SystemTime>, chgtime: Option<SystemTime>, bkuptime: Option<SystemTime>, flags: Option<u32>) -> ResultEmpty {
+            self.$inner.utimens_macos(req, path, fh, crtime, chgtime, bkuptime, flags)
+        }
+    };
+    (@ $inner:ident readlink) => {
+        fn readlink(&self, req: RequestInfo, path: &FsPath) -> ResultData {
+            self.$inner.readlink(req, path)
+        }
+    };
+    (@ $inner:ident mknod) => {
+        fn mknod(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, mode: u32, rdev: u32) -> ResultEntry {
+            self.$inner.mknod(req, parent, name, mode, rdev)
+        }
+    };
+    (@ $inner:ident mkdir) => {
+        fn mkdir(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, mode: u32) -> ResultEntry {
+            self.$inner.mkdir(req, parent, name, mode)
+        }
+    };
+    (@ $inner:ident unlink) => {
+        fn unlink(&self, req: RequestInfo, parent: &FsPath, name: &OsStr) -> ResultEmpty {
+            self.$inner.unlink(req, parent, name)
+        }
+    };
+    (@ $inner:ident rmdir) => {
+        fn rmdir(&self, req: RequestInfo, parent: &FsPath, name: &OsStr) -> ResultEmpty {
+            self.$inner.rmdir(req, parent, name)
+        }
+    };
+    (@ $inner:ident symlink) => {
+        fn symlink(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, target: &Path) -> ResultEntry {
+            self.$inner.symlink(req, parent, name, target)
+        }
+    };
+    (@ $inner:ident rename) => {
+        fn rename(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, newparent: &FsPath, newname: &OsStr) -> ResultEmpty {
+            self.$inner.rename(req, parent, name, newparent, newname)
+        }
+    };
+    (@ $inner:ident link) => {
+        fn link(&self, req: RequestInfo, path: &FsPath, newparent: &FsPath, newname: &OsStr) -> ResultEntry {
+            self.$inner.link(req, path, newparent, newname)
+        }
+    };
+    (@ $inner:ident open) => {
+        fn open(&self, req: RequestInfo, path: &FsPath, flags: u32) -> ResultOpen {
+            self.$inner.open(req, path, flags)
+        }
+    };
+    (@ $inner:ident open_handle) => {
+        fn open_handle(&self, req: RequestInfo, path: &FsPath, flags: u32) -> ResultOpenHandle {
+            self.$inner.open_handle(req, path, flags)
+        }
+    };
+    (@ $inner:ident read) => {
+        fn read(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, size: u32, callback: impl FnOnce(ResultSlice<'_>) -> CallbackResult) -> CallbackResult {
+            self.$inner.read(req, path, fh, offset, size, callback)
+        }
+    };
+    (@ $inner:ident write) => {
+        fn write(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, data: Vec<u8>, flags: u32) -> ResultWrite {
+            self.$inner.write(req, path, fh, offset, data, flags)
+        }
+    };
+    (@ $inner:ident write_deferred) => {
+        fn write_deferred(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, data: Vec<u8>, flags: u32, reply: Responder<u32>) {
+            self.$inner.write_deferred(req, path, fh, offset, data, flags, reply)
+        }
+    };
+    (@ $inner:ident flush) => {
+        fn flush(&self, req: RequestInfo, path: &FsPath, fh: u64, lock_owner: LockOwner) -> ResultEmpty {
+            self.$inner.flush(req, path, fh, lock_owner)
+        }
+    };
+    (@ $inner:ident flush_deferred) => {
+        fn flush_deferred(&self, req: RequestInfo, path: &FsPath, fh: u64, lock_owner: LockOwner, reply: Responder<()>) {
+            self.$inner.flush_deferred(req, path, fh, lock_owner, reply)
+        }
+    };
+    (@ $inner:ident release) => {
+        fn release(&self, req: RequestInfo, path: &FsPath, fh: u64, flags: u32, lock_owner: Option<LockOwner>, flush: bool) -> ResultEmpty {
+            self.$inner.release(req, path, fh, flags, lock_owner, flush)
+        }
+    };
+    (@ $inner:ident last_release_after_unlink) => {
+        fn last_release_after_unlink(&self, req: RequestInfo, path: &FsPath, fh: u64) {
+            self.$inner.last_release_after_unlink(req, path, fh)
+        }
+    };
+    (@ $inner:ident fsync) => {
+        fn fsync(&self, req: RequestInfo, path: &FsPath, fh: u64, datasync: bool) -> ResultEmpty {
+            self.$inner.fsync(req, path, fh, datasync)
+        }
+    };
+    (@ $inner:ident fsync_deferred) => {
+        fn fsync_deferred(&self, req: RequestInfo, path: &FsPath, fh: u64, datasync: bool, reply: Responder<()>) {
+            self.$inner.fsync_deferred(req, path, fh, datasync, reply)
+        }
+    };
+    (@ $inner:ident sync) => {
+        fn sync(&self, filter: &dyn Fn(&Path) -> bool) -> ResultEmpty {
+            self.$inner.sync(filter)
+        }
+    };
+    (@ $inner:ident opendir) => {
+        fn opendir(&self, req: RequestInfo, path: &FsPath, flags: u32) -> ResultOpen {
+            self.$inner.opendir(req, path, flags)
+        }
+    };
+    (@ $inner:ident opendir_handle) => {
+        fn opendir_handle(&self, req: RequestInfo, path: &FsPath, flags: u32) -> ResultOpenDirHandle {
+            self.$inner.opendir_handle(req, path, flags)
+        }
+    };
+    (@ $inner:ident readdir) => {
+        fn readdir(&self, req: RequestInfo, path: &FsPath, fh: u64) -> ResultReaddir {
+            self.$inner.readdir(req, path, fh)
+        }
+    };
+    (@ $inner:ident readdir_deferred) => {
+        fn readdir_deferred(&self, req: RequestInfo, path: &FsPath, fh: u64, reply: Responder<Vec<DirectoryEntry>>) {
+            self.$inner.readdir_deferred(req, path, fh, reply)
+        }
+    };
+    (@ $inner:ident readdir_at) => {
+        fn readdir_at(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, add: &mut dyn FnMut(&DirectoryEntry, u64) -> bool) -> ResultEmpty {
+            self.$inner.readdir_at(req, path, fh, offset, add)
+        }
+    };
+    (@ $inner:ident releasedir) => {
+        fn releasedir(&self, req: RequestInfo, path: &FsPath, fh: u64, flags: u32) -> ResultEmpty {
+            self.$inner.releasedir(req, path, fh, flags)
+        }
+    };
+    (@ $inner:ident fsyncdir) => {
+        fn fsyncdir(&self, req: RequestInfo, path: &FsPath, fh: u64, datasync: bool) -> ResultEmpty {
+            self.$inner.fsyncdir(req, path, fh, datasync)
+        }
+    };
+    (@ $inner:ident statfs) => {
+        fn statfs(&self, req: RequestInfo, path: &FsPath) -> ResultStatfs {
+            self.$inner.statfs(req, path)
+        }
+    };
+    (@ $inner:ident quota) => {
+        fn quota(&self, req: RequestInfo, path: &FsPath) -> ResultQuota {
+            self.$inner.quota(req, path)
+        }
+    };
+    (@ $inner:ident setxattr) => {
+        fn setxattr(&self, req: RequestInfo, path: &FsPath, name: &OsStr, value: &[u8], flags: u32, position: XattrPosition) -> ResultEmpty {
+            self.$inner.setxattr(req, path, name, value, flags, position)
+        }
+    };
+    (@ $inner:ident getxattr) => {
+        fn getxattr(&self, req: RequestInfo, path: &FsPath, name: &OsStr, size: u32) -> ResultXattr {
+            self.$inner.getxattr(req, path, name, size)
+        }
+    };
+    (@ $inner:ident listxattr) => {
+        fn listxattr(&self, req: RequestInfo, path: &FsPath, size: u32) -> ResultXattr {
+            self.$inner.listxattr(req, path, size)
+        }
+    };
+    (@ $inner:ident removexattr) => {
+        fn removexattr(&self, req: RequestInfo, path: &FsPath, name: &OsStr) -> ResultEmpty {
+            self.$inner.removexattr(req, path, name)
+        }
+    };
+    (@ $inner:ident access) => {
+        fn access(&self, req: RequestInfo, path: &FsPath, mask: u32) -> ResultEmpty {
+            self.$inner.access(req, path, mask)
+        }
+    };
+    (@ $inner:ident create) => {
+        fn create(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, mode: u32, flags: u32) -> ResultCreate {
+            self.$inner.create(req, parent, name, mode, flags)
+        }
+    };
+    (@ $inner:ident create_handle) => {
+        fn create_handle(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, mode: u32, flags: u32) -> ResultCreateHandle {
+            self.$inner.create_handle(req, parent, name, mode, flags)
+        }
+    };
+    (@ $inner:ident fallocate) => {
+        fn fallocate(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, length: u64, mode: i32) -> ResultEmpty {
+            self.$inner.fallocate(req, path, fh, offset, length, mode)
+        }
+    };
+    (@ $inner:ident getlk) => {
+        fn getlk(&self, req: RequestInfo, path: &FsPath, fh: u64, lock_owner: LockOwner, lock: FileLock) -> ResultLock {
+            self.$inner.getlk(req, path, fh, lock_owner, lock)
+        }
+    };
+    (@ $inner:ident setlk) => {
+        fn setlk(&self, req: RequestInfo, path: &FsPath, fh: u64, lock_owner: LockOwner, lock: FileLock, sleep: bool) -> ResultEmpty {
+            self.$inner.setlk(req, path, fh, lock_owner, lock, sleep)
+        }
+    };
+    (@ $inner:ident flock) => {
+        fn flock(&self, req: RequestInfo, path: &FsPath, fh: u64, lock_owner: LockOwner, operation: i32) -> ResultEmpty {
+            self.$inner.flock(req, path, fh, lock_owner, operation)
+        }
+    };
+    (@ $inner:ident lseek) => {
+        fn lseek(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, whence: i32) -> ResultOffset {
+            self.$inner.lseek(req, path, fh, offset, whence)
+        }
+    };
+    (@ $inner:ident copy_file_range) => {
+        fn copy_file_range(&self, req: RequestInfo, path_in: &FsPath, fh_in: u64, offset_in: u64, path_out: &FsPath, fh_out: u64, offset_out: u64, len: u64, flags: u32) -> ResultWrite {
+            self.$inner.copy_file_range(req, path_in, fh_in, offset_in, path_out, fh_out, offset_out, len, flags)
+        }
+    };
+    (@ $inner:ident setvolname) => {
+        #[cfg(target_os = "macos")]
+        fn setvolname(&self, req: RequestInfo, name: &OsStr) -> ResultEmpty {
+            self.$inner.setvolname(req, name)
+        }
+    };
+    (@ $inner:ident exchange) => {
+        #[cfg(target_os = "macos")]
+        fn exchange(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, newparent: &FsPath, newname: &OsStr, options: u64) -> ResultEmpty {
+            self.$inner.exchange(req, parent, name, newparent, newname, options)
+        }
+    };
+    (@ $inner:ident getxtimes) => {
+        #[cfg(target_os = "macos")]
+        fn getxtimes(&self, req: RequestInfo, path: &FsPath) -> ResultXTimes {
+            self.$inner.getxtimes(req, path)
+        }
+    };
+    (@ $inner:ident setxtimes) => {
+        #[cfg(target_os = "macos")]
+        fn setxtimes(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, bkuptime: Option<SystemTime>, crtime: Option<SystemTime>) -> ResultEmpty {
+            self.$inner.setxtimes(req, path, fh, bkuptime, crtime)
+        }
+    };
+    (@ $inner:ident classify) => {
+        fn classify(&self, op: Operation, path: &FsPath) -> Lane {
+            self.$inner.classify(op, path)
+        }
+    };
+    (@ $inner:ident load) => {
+        fn load(&self, op: Operation, path: &FsPath) -> Load {
+            self.$inner.load(op, path)
+        }
+    };
+}
+
+/// Size of reads done by `read_all`.
+const IO_SIZE: u32 = 128 * 1024;
+
+/// Read a whole file from a filesystem.
+pub(crate) fn read_all(fs: &impl FilesystemMT, req: RequestInfo, path: &FsPath) -> ResultData {
+    let flags = libc::O_RDONLY as u32;
+    let fh = fs.open(req, path, flags)?.fh;
+    let mut data = vec![];
+    let result = loop {
+        let mut chunk = Err(libc::EIO);
+        fs.read(req, path, fh, data.len() as u64, IO_SIZE, |result| {
+            chunk = result.map(<[u8]>::to_vec);
+            CallbackResult { _private: std::marker::PhantomData }
+        });
+        match chunk {
+            Ok(chunk) if chunk.is_empty() => break Ok(()),
+            Ok(chunk) => data.extend(chunk),
+            Err(e) => break Err(e),
+        }
+    };
+    let released = fs.release(req, path, fh, flags, None, false);
+    result.and(released).map(|()| data)
+}
+
+/// Replace the contents of a file in a filesystem, creating it if needed.
+///
+/// Blocks of zeros aren't written, but left as holes for the filesystem to fill in when the file
+/// is extended to its full length, so copies of sparse files stay sparse.
+pub(crate) fn write_all(fs: &impl FilesystemMT, req: RequestInfo, path: &FsPath, data: &[u8]) -> ResultEmpty {
+    let flags = libc::O_WRONLY as u32;
+    let fh = match fs.open(req, path, flags) {
+        Ok(opened) => opened.fh,
+        Err(libc::ENOENT) => {
+            let (parent, name) = (path.parent().unwrap(), path.file_name().unwrap());
+            fs.create(req, &FsPath::from(parent), name, 0o600, flags)?.fh
+        }
+        Err(e) => return Err(e),
+    };
+    let result = fs.truncate(req, path, Some(fh), 0).and_then(|()| {
+        let mut offset = 0;
+        while offset < data.len() {
+            let chunk = &data[offset .. data.len().min(offset + IO_SIZE as usize)];
+            if sparse::is_zeros(chunk) {
+                offset += chunk.len();
+                continue;
+            }
+            match fs.write(req, path, fh, offset as u64, chunk.to_vec(), 0)? {
+                0 => return Err(libc::EIO),
+                n => offset += n as usize,
+            }
+        }
+        fs.truncate(req, path, Some(fh), data.len() as u64)
+    });
+    let released = fs.release(req, path, fh, flags, None, true);
+    result.and(released)
+}
+
+/// List a directory of a filesystem, not including `.` and `..`.
+pub(crate) fn list_dir(fs: &impl FilesystemMT, req: RequestInfo, path: &FsPath) -> ResultReaddir {
+    let flags = libc::O_RDONLY as u32;
+    let fh = fs.opendir(req, path, flags)?.fh;
+    let result = fs.readdir(req, path, fh);
+    let released = fs.releasedir(req, path, fh, flags);
+    let mut entries = result?;
+    released?;
+    entries.retain(|entry| entry.name != "." && entry.name != "..");
+    Ok(entries)
+}
+
+mod access_control;
+mod dedup;
+mod dry_run;
+mod failover;
+mod follow_symlinks;
+mod latency;
+mod mount;
+mod shadow;
+mod snapshot;
+#[cfg(test)]
+mod test_fs;
+mod trash;
+mod user_view;
+mod versioned;
+mod worm;
+
+pub use self::access_control::{Access, AccessControlFs, AccessPolicy};
+pub use self::dedup::DedupFs;
+pub use self::dry_run::{Change, DryRunFs};
+pub use self::failover::FailoverFs;
+pub use self::follow_symlinks::FollowSymlinksFs;
+pub use self::latency::{Latency, LatencyFs, OpClass};
+pub use self::mount::MountFs;
+pub use self::shadow::ShadowFs;
+pub use self::snapshot::SnapshotFs;
+pub use self::trash::{TrashEntry, TrashFs};
+pub use self::user_view::UserViewFs;
+pub use self::versioned::VersionedFs;
+pub use self::worm::WormFs;
@@ -0,0 +1,544 @@
+// Mount :: serving a filesystem under a path prefix of another one.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+use std::ffi::{OsStr, OsString};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::FileType;
+use crate::types::*;
+
+/// Handle given out by `opendir` for directories leading to the mount point which the other
+/// filesystem doesn't have.
+const MADE_UP_FH: u64 = u64::MAX;
+
+const TTL: Duration = Duration::from_secs(1);
+
+/// A filesystem which serves one filesystem under a path prefix, and everything else from
+/// another, the way a mount point does.
+///
+/// Calls on paths under the prefix go to the mounted filesystem, with the prefix taken off: the
+/// prefix itself is its root. Mounting more than one filesystem is done by nesting these:
+///
+/// ```ignore
+/// let fs = MountFs::new(MountFs::new(root, "/live", passthrough), "/snapshots", archive);
+/// ```
+///
+/// Renames, hard links, and `copy_file_range` between the two fail with `EXDEV`, so programs
+/// like `mv` fall back to copying. The mount point can't be removed or renamed (`EBUSY`), or
+/// created over (`EEXIST`). Its name is added to the listing of the directory it's in, and that
+/// directory (and any others leading to the mount point) is made up, as an empty read-only
+/// directory, if the other filesystem doesn't have it, so `root` can be a filesystem with nothing
+/// in it at all.
+///
+/// Handle objects (`open_handle`, `opendir_handle`, and `create_handle`) and `readdir_at` aren't
+/// used, so both filesystems need to implement the plain calls. The targets of symbolic links
+/// are passed through as they are, so absolute ones in the mounted filesystem don't have the
+/// prefix on them.
+pub struct MountFs<R, F> {
+    rest: R,
+    prefix: PathBuf,
+    mounted: F,
+}
+
+/// Make a call on the filesystem the given path belongs to (as `$fs`), with the path as that
+/// filesystem sees it (as `$path`).
+macro_rules! route {
+    ($self:ident, $outer:expr, |$fs:ident, $path:ident| $call:expr) => {
+        match $self.inner_path($outer) {
+            Some(inner) => { let $fs = &$self.mounted; let $path = &inner; $call }
+            None => {
+                let $fs = &$self.rest;
+                #[allow(clippy::redundant_locals)]
+                let $path = $outer;
+                $call
+            }
+        }
+    };
+}
+
+impl<R: FilesystemMT, F: FilesystemMT> MountFs<R, F> {
+    /// Serve `mounted` at `prefix`, which must be an absolute path other than the root, and
+    /// everything else from `rest`.
+    pub fn new(rest: R, prefix: impl Into<PathBuf>, mounted: F) -> MountFs<R, F> {
+        let prefix = prefix.into();
+        assert!(prefix.is_absolute() && prefix.parent().is_some(),
+            "mount point {:?} must be an absolute path other than the root", prefix);
+        MountFs { rest, prefix, mounted }
+    }
+
+    /// The path in the mounted filesystem of the given one, if it's under the mount point.
+    fn inner_path(&self, path: &Path) -> Option<FsPath> {
+        path.strip_prefix(&self.prefix).ok().map(|rest| FsPath::from(Path::new("/").join(rest)))
+    }
+
+    /// The path outside of a path in the mounted filesystem.
+    fn outer_path(&self, path: &Path) -> PathBuf {
+        self.prefix.join(path.strip_prefix("/").unwrap_or(path))
+    }
+
+    fn is_mounted(&self, path: &Path) -> bool {
+        path.starts_with(&self.prefix)
+    }
+
+    fn is_mount_point(&self, parent: &Path, name: &OsStr) -> bool {
+        parent.join(name) == self.prefix
+    }
+
+    /// If the given path is a directory leading to the mount point, the name in it which leads
+    /// there.
+    fn toward_mount_point(&self, path: &Path) -> Option<&OsStr> {
+        match self.prefix.strip_prefix(path).ok()?.components().next()? {
+            Component::Normal(name) => Some(name),
+            _ => None,
+        }
+    }
+
+    /// Replace the error from the other filesystem for a directory leading to the mount point if
+    /// it doesn't have it.
+    fn made_up<T>(&self, path: &Path, result: Result<T, libc::c_int>, value: impl FnOnce() -> T)
+        -> Result<T, libc::c_int>
+    {
+        match result {
+            Err(libc::ENOENT) | Err(libc::ENOSYS) if self.toward_mount_point(path).is_some() => {
+                Ok(value())
+            }
+            result => result,
+        }
+    }
+
+    /// Check that the given paths are on the same filesystem, for calls which involve two.
+    fn same_side(&self, a: &Path, b: &Path) -> ResultEmpty {
+        if self.is_mounted(a) == self.is_mounted(b) {
+            Ok(())
+        } else {
+            Err(libc::EXDEV)
+        }
+    }
+}
+
+fn made_up_dir() -> FileAttr {
+    let t = SystemTime::UNIX_EPOCH;
+    FileAttr { ino: 0, size: 0, blocks: 0, atime: t, mtime: t, ctime: t, crtime: t,
+        kind: FileType::Directory, perm: 0o555, nlink: 2, uid: 0, gid: 0, rdev: 0, flags: 0 }
+}
+
+fn dir_entry(name: &str) -> DirectoryEntry {
+    DirectoryEntry { name: OsString::from(name), kind: FileType::Directory, offset: None, attr: None }
+}
+
+/// Add the mount point to the listing of the directory it's in.
+fn add_mount_point(mut entries: Vec<DirectoryEntry>, name: &OsStr) -> Vec<DirectoryEntry> {
+    // The mount point covers up whatever the other filesystem has there.
+    entries.retain(|entry| entry.name != name);
+    entries.push(DirectoryEntry { name: name.to_owned(), kind: FileType::Directory, offset: None, attr: None });
+    entries
+}
+
+impl<R: FilesystemMT, F: FilesystemMT> FilesystemMT for MountFs<R, F> {
+    fn init(&self, req: RequestInfo) -> ResultEmpty {
+        self.rest.init(req)?;
+        self.mounted.init(req)
+    }
+
+    fn init_connection(&self, req: RequestInfo, conn: &ConnectionInfo, config: &mut KernelConfig) -> ResultEmpty {
+        self.rest.init_connection(req, conn, config)?;
+        self.mounted.init_connection(req, conn, config)
+    }
+
+    fn destroy(&self) {
+        self.mounted.destroy();
+        self.rest.destroy();
+    }
+
+    fn getattr(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>) -> ResultEntry {
+        let result = route!(self, path, |fs, path| fs.getattr(req, path, fh));
+        self.made_up(path, result, || (TTL, made_up_dir()))
+    }
+
+    fn getattr_mask(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, mask: AttrMask) -> ResultEntry {
+        let result = route!(self, path, |fs, path| fs.getattr_mask(req, path, fh, mask));
+        self.made_up(path, result, || (TTL, made_up_dir()))
+    }
+
+    fn getattr_deferred(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, reply: Responder<(Duration, FileAttr)>) {
+        if self.toward_mount_point(path).is_none() {
+            return route!(self, path, |fs, path| fs.getattr_deferred(req, path, fh, reply));
+        }
+        let reply = Responder::new(move |result| reply.send(match result {
+            Err(libc::ENOENT) | Err(libc::ENOSYS) => Ok((TTL, made_up_dir())),
+            result => result,
+        }));
+        route!(self, path, |fs, path| fs.getattr_deferred(req, path, fh, reply))
+    }
+
+    fn chmod(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, mode: u32) -> ResultEmpty {
+        route!(self, path, |fs, path| fs.chmod(req, path, fh, mode))
+    }
+
+    fn chown(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, uid: Option<u32>, gid: Option<u32>) -> ResultEmpty {
+        route!(self, path, |fs, path| fs.chown(req, path, fh, uid, gid))
+    }
+
+    fn truncate(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, size: u64) -> ResultEmpty {
+        route!(self, path, |fs, path| fs.truncate(req, path, fh, size))
+    }
+
+    fn utimens(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, atime: Option<SystemTime>, mtime: Option<SystemTime>) -> ResultEmpty {
+        route!(self, path, |fs, path| fs.utimens(req, path, fh, atime, mtime))
+    }
+
+    fn utimens_macos(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, crtime: Option<SystemTime>, chgtime: Option<SystemTime>, bkuptime: Option<SystemTime>, flags: Option<u32>) -> ResultEmpty {
+        route!(self, path, |fs, path| fs.utimens_macos(req, path, fh, crtime, chgtime, bkuptime, flags))
+    }
+
+    fn readlink(&self, req: RequestInfo, path: &FsPath) -> ResultData {
+        route!(self, path, |fs, path| fs.readlink(req, path))
+    }
+
+    fn mknod(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, mode: u32, rdev: u32) -> ResultEntry {
+        if self.is_mount_point(parent, name) {
+            return Err(libc::EEXIST);
+        }
+        route!(self, parent, |fs, parent| fs.mknod(req, parent, name, mode, rdev))
+    }
+
+    fn mkdir(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, mode: u32) -> ResultEntry {
+        if self.is_mount_point(parent, name) {
+            return Err(libc::EEXIST);
+        }
+        route!(self, parent, |fs, parent| fs.mkdir(req, parent, name, mode))
+    }
+
+    fn unlink(&self, req: RequestInfo, parent: &FsPath, name: &OsStr) -> ResultEmpty {
+        if self.is_mount_point(parent, name) {
+            return Err(libc::EBUSY);
+        }
+        route!(self, parent, |fs, parent| fs.unlink(req, parent, name))
+    }
+
+    fn rmdir(&self, req: RequestInfo, parent: &FsPath, name: &OsStr) -> ResultEmpty {
+        if self.is_mount_point(parent, name) {
+            return Err(libc::EBUSY);
+        }
+        route!(self, parent, |fs, parent| fs.rmdir(req, parent, name))
+    }
+
+    fn symlink(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, target: &Path) -> ResultEntry {
+        if self.is_mount_point(parent, name) {
+            return Err(libc::EEXIST);
+        }
+        route!(self, parent, |fs, parent| fs.symlink(req, parent, name, target))
+    }
+
+    fn rename(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, newparent: &FsPath, newname: &OsStr) -> ResultEmpty {
+        if self.is_mount_point(parent, name) || self.is_mount_point(newparent, newname) {
+            return Err(libc::EBUSY);
+        }
+        self.same_side(parent, newparent)?;
+        match (self.inner_path(parent), self.inner_path(newparent)) {
+            (Some(parent), Some(newparent)) => self.mounted.rename(req, &parent, name, &newparent, newname),
+            _ => self.rest.rename(req, parent, name, newparent, newname),
+        }
+    }
+
+    fn link(&self, req: RequestInfo, path: &FsPath, newparent: &FsPath, newname: &OsStr) -> ResultEntry {
+        if self.is_mount_point(newparent, newname) {
+            return Err(libc::EEXIST);
+        }
+        self.same_side(path, newparent)?;
+        match (self.inner_path(path), self.inner_path(newparent)) {
+            (Some(path), Some(newparent)) => self.mounted.link(req, &path, &newparent, newname),
+            _ => self.rest.link(req, path, newparent, newname),
+        }
+    }
+
+    fn open(&self, req: RequestInfo, path: &FsPath, flags: u32) -> ResultOpen {
+        route!(self, path, |fs, path| fs.open(req, path, flags))
+    }
+
+    fn read(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, size: u32, callback: impl FnOnce(ResultSlice<'_>) -> CallbackResult) -> CallbackResult {
+        route!(self, path, |fs, path| fs.read(req, path, fh, offset, size, callback))
+    }
+
+    fn write(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, data: Vec<u8>, flags: u32) -> ResultWrite {
+        route!(self, path, |fs, path| fs.write(req, path, fh, offset, data, flags))
+    }
+
+    fn write_deferred(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, data: Vec<u8>, flags: u32, reply: Responder<u32>) {
+        route!(self, path, |fs, path| fs.write_deferred(req, path, fh, offset, data, flags, reply))
+    }
+
+    fn flush(&self, req: RequestInfo, path: &FsPath, fh: u64, lock_owner: LockOwner) -> ResultEmpty {
+        route!(self, path, |fs, path| fs.flush(req, path, fh, lock_owner))
+    }
+
+    fn flush_deferred(&self, req: RequestInfo, path: &FsPath, fh: u64, lock_owner: LockOwner, reply: Responder<()>) {
+        route!(self, path, |fs, path| fs.flush_deferred(req, path, fh, lock_owner, reply))
+    }
+
+    fn release(&self, req: RequestInfo, path: &FsPath, fh: u64, flags: u32, lock_owner: Option<LockOwner>, flush: bool) -> ResultEmpty {
+        route!(self, path, |fs, path| fs.release(req, path, fh, flags, lock_owner, flush))
+    }
+
+    fn last_release_after_unlink(&self, req: RequestInfo, path: &FsPath, fh: u64) {
+        route!(self, path, |fs, path| fs.last_release_after_unlink(req, path, fh))
+    }
+
+    fn fsync(&self, req: RequestInfo, path: &FsPath, fh: u64, datasync: bool) -> ResultEmpty {
+        route!(self, path, |fs, path| fs.fsync(req, path, fh, datasync))
+    }
+
+    fn fsync_deferred(&self, req: RequestInfo, path: &FsPath, fh: u64, datasync: bool, reply: Responder<()>) {
+        route!(self, path, |fs, path| fs.fsync_deferred(req, path, fh, datasync, reply))
+    }
+
+    fn sync(&self, filter: &dyn Fn(&Path) -> bool) -> ResultEmpty {
+        let rest = self.rest.sync(&|path| !self.is_mounted(path) && filter(path));
+        let mounted = self.mounted.sync(&|path| filter(&self.outer_path(path)));
+        match (rest, mounted) {
+            (Err(libc::ENOSYS), Err(libc::ENOSYS)) => Err(libc::ENOSYS),
+            (Err(e), _) | (_, Err(e)) if e != libc::ENOSYS => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    fn opendir(&self, req: RequestInfo, path: &FsPath, flags: u32) -> ResultOpen {
+        let result = route!(self, path, |fs, path| fs.opendir(req, path, flags));
+        self.made_up(path, result, || OpenedFile::new(MADE_UP_FH))
+    }
+
+    fn readdir(&self, req: RequestInfo, path: &FsPath, fh: u64) -> ResultReaddir {
+        let name = match self.toward_mount_point(path) {
+            Some(name) => name,
+            None => return route!(self, path, |fs, path| fs.readdir(req, path, fh)),
+        };
+        let entries = if fh == MADE_UP_FH {
+            vec![dir_entry("."), dir_entry("..")]
+        } else {
+            self.rest.readdir(req, path, fh)?
+        };
+        Ok(add_mount_point(entries, name))
+    }
+
+    fn readdir_deferred(&self, req: RequestInfo, path: &FsPath, fh: u64, reply: Responder<Vec<DirectoryEntry>>) {
+        let name = match self.toward_mount_point(path) {
+            Some(name) => name.to_owned(),
+            None => return route!(self, path, |fs, path| fs.readdir_deferred(req, path, fh, reply)),
+        };
+        if fh == MADE_UP_FH {
+            return reply.send(Ok(add_mount_point(vec![dir_entry("."), dir_entry("..")], &name)));
+        }
+        self.rest.readdir_deferred(req, path, fh, Responder::new(move |result| {
+            reply.send(result.map(|entries| add_mount_point(entries, &name)))
+        }))
+    }
+
+    fn releasedir(&self, req: RequestInfo, path: &FsPath, fh: u64, flags: u32) -> ResultEmpty {
+        if fh == MADE_UP_FH && self.toward_mount_point(path).is_some() {
+            return Ok(());
+        }
+        route!(self, path, |fs, path| fs.releasedir(req, path, fh, flags))
+    }
+
+    fn fsyncdir(&self, req: RequestInfo, path: &FsPath, fh: u64, datasync: bool) -> ResultEmpty {
+        if fh == MADE_UP_FH && self.toward_mount_point(path).is_some() {
+            return Ok(());
+        }
+        route!(self, path, |fs, path| fs.fsyncdir(req, path, fh, datasync))
+    }
+
+    fn statfs(&self, req: RequestInfo, path: &FsPath) -> ResultStatfs {
+        route!(self, path, |fs, path| fs.statfs(req, path))
+    }
+
+    fn quota(&self, req: RequestInfo, path: &FsPath) -> ResultQuota {
+        route!(self, path, |fs, path| fs.quota(req, path))
+    }
+
+    fn setxattr(&self, req: RequestInfo, path: &FsPath, name: &OsStr, value: &[u8], flags: u32, position: XattrPosition) -> ResultEmpty {
+        route!(self, path, |fs, path| fs.setxattr(req, path, name, value, flags, position))
+    }
+
+    fn getxattr(&self, req: RequestInfo, path: &FsPath, name: &OsStr, size: u32) -> ResultXattr {
+        route!(self, path, |fs, path| fs.getxattr(req, path, name, size))
+    }
+
+    fn listxattr(&self, req: RequestInfo, path: &FsPath, size: u32) -> ResultXattr {
+        route!(self, path, |fs, path| fs.listxattr(req, path, size))
+    }
+
+    fn removexattr(&self, req: RequestInfo, path: &FsPath, name: &OsStr) -> ResultEmpty {
+        route!(self, path, |fs, path| fs.removexattr(req, path, name))
+    }
+
+    fn access(&self, req: RequestInfo, path: &FsPath, mask: u32) -> ResultEmpty {
+        let result = route!(self, path, |fs, path| fs.access(req, path, mask));
+        match result {
+            Err(libc::ENOENT) | Err(libc::ENOSYS) if self.toward_mount_point(path).is_some() => {
+                // Made-up directories are read-only.
+                if mask & libc::W_OK as u32 != 0 {
+                    Err(libc::EACCES)
+                } else {
+                    Ok(())
+                }
+            }
+            result => result,
+        }
+    }
+
+    fn create(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, mode: u32, flags: u32) -> ResultCreate {
+        if self.is_mount_point(parent, name) {
+            return Err(libc::EEXIST);
+        }
+        route!(self, parent, |fs, parent| fs.create(req, parent, name, mode, flags))
+    }
+
+    fn getlk(&self, req: RequestInfo, path: &FsPath, fh: u64, lock_owner: LockOwner, lock: FileLock) -> ResultLock {
+        route!(self, path, |fs, path| fs.getlk(req, path, fh, lock_owner, lock))
+    }
+
+    fn setlk(&self, req: RequestInfo, path: &FsPath, fh: u64, lock_owner: LockOwner, lock: FileLock, sleep: bool) -> ResultEmpty {
+        route!(self, path, |fs, path| fs.setlk(req, path, fh, lock_owner, lock, sleep))
+    }
+
+    fn flock(&self, req: RequestInfo, path: &FsPath, fh: u64, lock_owner: LockOwner, operation: i32) -> ResultEmpty {
+        route!(self, path, |fs, path| fs.flock(req, path, fh, lock_owner, operation))
+    }
+
+    fn fallocate(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, length: u64, mode: i32) -> ResultEmpty {
+        route!(self, path, |fs, path| fs.fallocate(req, path, fh, offset, length, mode))
+    }
+
+    fn lseek(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, whence: i32) -> ResultOffset {
+        route!(self, path, |fs, path| fs.lseek(req, path, fh, offset, whence))
+    }
+
+    fn copy_file_range(&self, req: RequestInfo, path_in: &FsPath, fh_in: u64, offset_in: u64, path_out: &FsPath, fh_out: u64, offset_out: u64, len: u64, flags: u32) -> ResultWrite {
+        // The kernel falls back to copying by reading and writing.
+        self.same_side(path_in, path_out)?;
+        match (self.inner_path(path_in), self.inner_path(path_out)) {
+            (Some(path_in), Some(path_out)) => self.mounted.copy_file_range(
+                req, &path_in, fh_in, offset_in, &path_out, fh_out, offset_out, len, flags),
+            _ => self.rest.copy_file_range(
+                req, path_in, fh_in, offset_in, path_out, fh_out, offset_out, len, flags),
+        }
+    }
+
+    #[cfg(target_os = "macos")]
+    fn setvolname(&self, req: RequestInfo, name: &OsStr) -> ResultEmpty {
+        self.rest.setvolname(req, name)
+    }
+
+    #[cfg(target_os = "macos")]
+    fn exchange(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, newparent: &FsPath, newname: &OsStr, options: u64) -> ResultEmpty {
+        if self.is_mount_point(parent, name) || self.is_mount_point(newparent, newname) {
+            return Err(libc::EBUSY);
+        }
+        self.same_side(parent, newparent)?;
+        match (self.inner_path(parent), self.inner_path(newparent)) {
+            (Some(parent), Some(newparent)) => self.mounted.exchange(req, &parent, name, &newparent, newname, options),
+            _ => self.rest.exchange(req, parent, name, newparent, newname, options),
+        }
+    }
+
+    #[cfg(target_os = "macos")]
+    fn getxtimes(&self, req: RequestInfo, path: &FsPath) -> ResultXTimes {
+        route!(self, path, |fs, path| fs.getxtimes(req, path))
+    }
+
+    #[cfg(target_os = "macos")]
+    fn setxtimes(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, bkuptime: Option<SystemTime>, crtime: Option<SystemTime>) -> ResultEmpty {
+        route!(self, path, |fs, path| fs.setxtimes(req, path, fh, bkuptime, crtime))
+    }
+
+    fn classify(&self, op: Operation, path: &FsPath) -> Lane {
+        route!(self, path, |fs, path| fs.classify(op, path))
+    }
+
+    fn load(&self, op: Operation, path: &FsPath) -> Load {
+        route!(self, path, |fs, path| fs.load(op, path))
+    }
+}
+
+#[test]
+fn test_mount() {
+    use super::{list_dir, read_all, test_fs};
+
+    let req = test_fs::req();
+    let (rest, live) = (test_fs::MemFs::new(), test_fs::MemFs::new());
+    rest.nodes.lock().unwrap().insert(PathBuf::from("/a"), Some(b"rest".to_vec()));
+    live.nodes.lock().unwrap().insert(PathBuf::from("/a"), Some(b"live".to_vec()));
+    let fs = MountFs::new(MountFs::new(rest, "/live", live), "/x/snapshots", test_fs::MemFs::new());
+    let names = |path: &str| {
+        let mut names = list_dir(&fs, req, &FsPath::from(path)).unwrap().into_iter()
+            .map(|entry| entry.name.into_string().unwrap())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    };
+
+    // Paths under a prefix go to the filesystem mounted there, without the prefix.
+    assert_eq!(b"rest".to_vec(), read_all(&fs, req, &FsPath::from("/a")).unwrap());
+    assert_eq!(b"live".to_vec(), read_all(&fs, req, &FsPath::from("/live/a")).unwrap());
+    fs.mkdir(req, &FsPath::from("/live"), OsStr::new("d"), 0o755).unwrap();
+    assert!(fs.rest.mounted.nodes.lock().unwrap().contains_key(Path::new("/d")));
+
+    // Mount points are listed, and directories leading to them are made up if need be.
+    assert_eq!(vec!["a", "live", "x"], names("/"));
+    assert_eq!(vec!["snapshots"], names("/x"));
+    assert_eq!(FileType::Directory, fs.getattr(req, &FsPath::from("/x"), None).unwrap().1.kind);
+    assert_eq!(Some(libc::ENOENT), fs.getattr(req, &FsPath::from("/y"), None).err());
+    assert_eq!(vec!["a", "d"], names("/live"));
+
+    // Nothing moves between filesystems, and mount points stay put.
+    let root = &FsPath::from("/");
+    assert_eq!(Err(libc::EXDEV), fs.rename(req, root, OsStr::new("a"), &FsPath::from("/live"), OsStr::new("b")));
+    assert_eq!(Err(libc::EBUSY), fs.rename(req, root, OsStr::new("live"), root, OsStr::new("dead")));
+    assert_eq!(Err(libc::EBUSY), fs.rmdir(req, &FsPath::from("/x"), OsStr::new("snapshots")));
+    assert_eq!(Err(libc::EEXIST), fs.mkdir(req, root, OsStr::new("live"), 0o755).map(|_| ()));
+    fs.rename(req, &FsPath::from("/live"), OsStr::new("a"), &FsPath::from("/live/d"), OsStr::new("b")).unwrap();
+    assert_eq!(b"live".to_vec(), read_all(&fs, req, &FsPath::from("/live/d/b")).unwrap());
+}
+
+#[test]
+fn test_mount_deferred() {
+    use super::test_fs::{self, DeferredFs};
+
+    let req = test_fs::req();
+    let fs = MountFs::new(DeferredFs::new(), "/x/live", DeferredFs::new());
+    fs.mounted.mem.nodes.lock().unwrap().insert(PathBuf::from("/a"), Some(vec![]));
+    let names = |path: &str| {
+        let path = &FsPath::from(path);
+        let fh = fs.opendir(req, path, 0).unwrap().fh;
+        let mut names = Responder::wait(|reply| fs.readdir_deferred(req, path, fh, reply)).unwrap()
+            .into_iter()
+            .map(|entry| entry.name.into_string().unwrap())
+            .filter(|name| name != "." && name != "..")
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    };
+
+    // The prefix is taken off paths under it.
+    let written = Responder::wait(|reply| {
+        fs.write_deferred(req, &FsPath::from("/x/live/a"), 0, 0, b"abc".to_vec(), 0, reply)
+    });
+    assert_eq!(Ok(3), written);
+    assert_eq!(Some(b"abc".to_vec()), fs.mounted.mem.nodes.lock().unwrap()[Path::new("/a")]);
+    let attr = Responder::wait(|reply| fs.getattr_deferred(req, &FsPath::from("/x/live/a"), None, reply));
+    assert_eq!(Ok(3), attr.map(|(_, attr)| attr.size));
+    let synced = Responder::wait(|reply| fs.fsync_deferred(req, &FsPath::from("/x/live/a"), 0, false, reply));
+    assert_eq!(Ok(()), synced);
+
+    // Directories leading to the mount point are still made up, and it's still listed.
+    let attr = Responder::wait(|reply| fs.getattr_deferred(req, &FsPath::from("/x"), None, reply));
+    assert_eq!(Ok(FileType::Directory), attr.map(|(_, attr)| attr.kind));
+    let missing = Responder::wait(|reply| fs.getattr_deferred(req, &FsPath::from("/y"), None, reply));
+    assert_eq!(Some(libc::ENOENT), missing.err());
+    assert_eq!(vec!["x"], names("/"));
+    assert_eq!(vec!["live"], names("/x"));
+    assert_eq!(vec!["a"], names("/x/live"));
+}
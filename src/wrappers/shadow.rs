@@ -0,0 +1,540 @@
+// Shadow :: comparing a filesystem against another one serving the same files.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fmt::Debug;
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
+
+use crate::types::*;
+
+/// A filesystem which makes every call on both a primary filesystem and a shadow one, answers
+/// with the primary's result, and logs a warning whenever the shadow's result is different. This
+/// is for trying out a new implementation of a backend against the old one under a real workload,
+/// before switching to it.
+///
+/// Both filesystems should start out with the same contents, since changes are made to both.
+/// Results count as the same if they fail with the same error, or if they succeed and: entries
+/// have the same type, size, and permissions; reads return the same data; directories list the
+/// same entries, in any order; and extended attributes have the same names and values. Times,
+/// TTLs, file handles, and the results of `statfs` aren't compared.
+///
+/// A file or directory which the shadow fails to open is only used on the primary until it's
+/// closed. Directories are listed with `readdir`, and handle objects (`open_handle`,
+/// `opendir_handle`, and `create_handle`) aren't used, so both filesystems need to implement the
+/// plain calls.
+pub struct ShadowFs<P, S> {
+    primary: P,
+    shadow: S,
+    /// The primary's and shadow's handles for each handle this filesystem returns.
+    handles: Mutex<HashMap<u64, (u64, Option<u64>)>>,
+    next_fh: AtomicU64,
+    divergences: AtomicU64,
+}
+
+/// Make a call on the primary and the shadow (as `$fs`), compare the results, and return the
+/// primary's.
+macro_rules! both {
+    ($self:ident, $op:expr, $path:expr, |$fs:ident| $call:expr) => {
+        both!($self, $op, $path, PartialEq::eq, |$fs| $call)
+    };
+    ($self:ident, $op:expr, $path:expr, $same:expr, |$fs:ident| $call:expr) => {{
+        let primary = { let $fs = &$self.primary; $call };
+        let shadow = { let $fs = &$self.shadow; $call };
+        $self.compare($op, $path, &primary, &shadow, $same);
+        primary
+    }};
+}
+
+/// Make a call on an open file or directory (as `$fs`, with that filesystem's handle as `$fh`),
+/// on the primary and, if it opened it too, the shadow, and return the primary's result.
+macro_rules! on_handle {
+    ($self:ident, $handle:expr, $op:expr, $path:expr, |$fs:ident, $fh:ident| $call:expr) => {
+        on_handle!($self, $handle, $op, $path, PartialEq::eq, |$fs, $fh| $call)
+    };
+    ($self:ident, $handle:expr, $op:expr, $path:expr, $same:expr, |$fs:ident, $fh:ident| $call:expr) => {{
+        match $self.handle($handle) {
+            Ok((primary_fh, shadow_fh)) => {
+                let primary = { let $fs = &$self.primary; let $fh = primary_fh; $call };
+                if let Some($fh) = shadow_fh {
+                    let shadow = { let $fs = &$self.shadow; $call };
+                    $self.compare($op, $path, &primary, &shadow, $same);
+                }
+                primary
+            }
+            Err(e) => Err(e),
+        }
+    }};
+}
+
+/// Whether two entries have the same type, size, and permissions.
+fn same_entry(a: &(Duration, FileAttr), b: &(Duration, FileAttr)) -> bool {
+    let (a, b) = (&a.1, &b.1);
+    (a.kind, a.size, a.perm) == (b.kind, b.size, b.perm)
+}
+
+/// For calls whose results are expected to differ, as long as both succeed.
+fn both_ok<T>(_: &T, _: &T) -> bool {
+    true
+}
+
+#[allow(clippy::ptr_arg)] // it's compared with a `Fn(&T, &T)` where T is `Vec<DirectoryEntry>`
+fn same_listing(a: &Vec<DirectoryEntry>, b: &Vec<DirectoryEntry>) -> bool {
+    fn sorted(entries: &[DirectoryEntry]) -> Vec<&DirectoryEntry> {
+        let mut entries = entries.iter()
+            .filter(|entry| entry.name != "." && entry.name != "..")
+            .collect::<Vec<_>>();
+        entries.sort_by(|x, y| x.name.cmp(&y.name));
+        entries
+    }
+    sorted(a) == sorted(b)
+}
+
+fn same_names(a: &Xattr, b: &Xattr) -> bool {
+    fn sorted(data: &[u8]) -> Vec<&[u8]> {
+        let mut names = data.split(|&b| b == 0).collect::<Vec<_>>();
+        names.sort_unstable();
+        names
+    }
+    match (a, b) {
+        (Xattr::Data(a), Xattr::Data(b)) => sorted(a) == sorted(b),
+        _ => a == b,
+    }
+}
+
+impl<P: FilesystemMT, S: FilesystemMT> ShadowFs<P, S> {
+    pub fn new(primary: P, shadow: S) -> ShadowFs<P, S> {
+        ShadowFs {
+            primary,
+            shadow,
+            handles: Mutex::new(HashMap::new()),
+            next_fh: AtomicU64::new(1),
+            divergences: AtomicU64::new(0),
+        }
+    }
+
+    /// Number of calls so far where the shadow's result was different from the primary's.
+    pub fn divergences(&self) -> u64 {
+        self.divergences.load(Ordering::Relaxed)
+    }
+
+    fn diverged(&self, op: &str, path: &Path, primary: &dyn Debug, shadow: &dyn Debug) {
+        self.divergences.fetch_add(1, Ordering::Relaxed);
+        warn!("{} {:?} diverged: primary returned {:?}, shadow returned {:?}",
+            op, path, primary, shadow);
+    }
+
+    fn compare<T: Debug>(&self, op: &str, path: &Path, primary: &Result<T, libc::c_int>,
+                         shadow: &Result<T, libc::c_int>, same: impl Fn(&T, &T) -> bool) {
+        let matched = match (primary, shadow) {
+            (Ok(a), Ok(b)) => same(a, b),
+            (Err(a), Err(b)) => a == b,
+            _ => false,
+        };
+        if !matched {
+            self.diverged(op, path, primary, shadow);
+        }
+    }
+
+    fn handle(&self, fh: u64) -> Result<(u64, Option<u64>), libc::c_int> {
+        self.handles.lock().unwrap().get(&fh).copied().ok_or(libc::EBADF)
+    }
+
+    /// Give out a handle of ours for something opened on both filesystems, if the primary
+    /// succeeded. If only the shadow succeeded, its handle is closed with `close_shadow`.
+    fn opened<T>(&self, primary: Result<T, libc::c_int>, shadow: Result<T, libc::c_int>,
+                 fh: impl Fn(&mut T) -> &mut u64, close_shadow: impl FnOnce(u64))
+        -> Result<T, libc::c_int>
+    {
+        let mut shadow_fh = shadow.ok().map(|mut shadow| *fh(&mut shadow));
+        let mut opened = match primary {
+            Ok(opened) => opened,
+            Err(e) => {
+                if let Some(shadow_fh) = shadow_fh.take() {
+                    close_shadow(shadow_fh);
+                }
+                return Err(e);
+            }
+        };
+        let primary_fh = fh(&mut opened);
+        let ours = self.next_fh.fetch_add(1, Ordering::Relaxed);
+        self.handles.lock().unwrap().insert(ours, (*primary_fh, shadow_fh));
+        *primary_fh = ours;
+        Ok(opened)
+    }
+}
+
+impl<P: FilesystemMT, S: FilesystemMT> FilesystemMT for ShadowFs<P, S> {
+    fn init(&self, req: RequestInfo) -> ResultEmpty {
+        both!(self, "init", Path::new("/"), |fs| fs.init(req))
+    }
+
+    fn destroy(&self) {
+        self.primary.destroy();
+        self.shadow.destroy();
+    }
+
+    fn getattr(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>) -> ResultEntry {
+        match fh {
+            Some(fh) => on_handle!(self, fh, "getattr", path, same_entry, |fs, fh| fs.getattr(req, path, Some(fh))),
+            None => both!(self, "getattr", path, same_entry, |fs| fs.getattr(req, path, None)),
+        }
+    }
+
+    fn getattr_mask(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, mask: AttrMask) -> ResultEntry {
+        // Only what was asked for is compared.
+        let same = |a: &(Duration, FileAttr), b: &(Duration, FileAttr)| {
+            (!mask.contains(AttrMask::TYPE) || a.1.kind == b.1.kind)
+                && (!mask.contains(AttrMask::SIZE) || a.1.size == b.1.size)
+                && (!mask.contains(AttrMask::MODE) || a.1.perm == b.1.perm)
+        };
+        match fh {
+            Some(fh) => on_handle!(self, fh, "getattr", path, same, |fs, fh| fs.getattr_mask(req, path, Some(fh), mask)),
+            None => both!(self, "getattr", path, same, |fs| fs.getattr_mask(req, path, None, mask)),
+        }
+    }
+
+    // Results have to be compared before they're passed on, so the deferred calls wait for both
+    // filesystems' results.
+
+    fn getattr_deferred(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, reply: Responder<(Duration, FileAttr)>) {
+        reply.send(match fh {
+            Some(fh) => on_handle!(self, fh, "getattr", path, same_entry, |fs, fh| {
+                Responder::wait(|inner| fs.getattr_deferred(req, path, Some(fh), inner))
+            }),
+            None => both!(self, "getattr", path, same_entry, |fs| {
+                Responder::wait(|inner| fs.getattr_deferred(req, path, None, inner))
+            }),
+        })
+    }
+
+    fn chmod(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, mode: u32) -> ResultEmpty {
+        match fh {
+            Some(fh) => on_handle!(self, fh, "chmod", path, |fs, fh| fs.chmod(req, path, Some(fh), mode)),
+            None => both!(self, "chmod", path, |fs| fs.chmod(req, path, None, mode)),
+        }
+    }
+
+    fn chown(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, uid: Option<u32>, gid: Option<u32>) -> ResultEmpty {
+        match fh {
+            Some(fh) => on_handle!(self, fh, "chown", path, |fs, fh| fs.chown(req, path, Some(fh), uid, gid)),
+            None => both!(self, "chown", path, |fs| fs.chown(req, path, None, uid, gid)),
+        }
+    }
+
+    fn truncate(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, size: u64) -> ResultEmpty {
+        match fh {
+            Some(fh) => on_handle!(self, fh, "truncate", path, |fs, fh| fs.truncate(req, path, Some(fh), size)),
+            None => both!(self, "truncate", path, |fs| fs.truncate(req, path, None, size)),
+        }
+    }
+
+    fn utimens(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, atime: Option<SystemTime>, mtime: Option<SystemTime>) -> ResultEmpty {
+        match fh {
+            Some(fh) => on_handle!(self, fh, "utimens", path, |fs, fh| fs.utimens(req, path, Some(fh), atime, mtime)),
+            None => both!(self, "utimens", path, |fs| fs.utimens(req, path, None, atime, mtime)),
+        }
+    }
+
+    fn utimens_macos(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, crtime: Option<SystemTime>, chgtime: Option<SystemTime>, bkuptime: Option<SystemTime>, flags: Option<u32>) -> ResultEmpty {
+        match fh {
+            Some(fh) => on_handle!(self, fh, "utimens_macos", path, |fs, fh| fs.utimens_macos(req, path, Some(fh), crtime, chgtime, bkuptime, flags)),
+            None => both!(self, "utimens_macos", path, |fs| fs.utimens_macos(req, path, None, crtime, chgtime, bkuptime, flags)),
+        }
+    }
+
+    fn readlink(&self, req: RequestInfo, path: &FsPath) -> ResultData {
+        both!(self, "readlink", path, |fs| fs.readlink(req, path))
+    }
+
+    fn mknod(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, mode: u32, rdev: u32) -> ResultEntry {
+        both!(self, "mknod", &parent.join(name), same_entry, |fs| fs.mknod(req, parent, name, mode, rdev))
+    }
+
+    fn mkdir(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, mode: u32) -> ResultEntry {
+        both!(self, "mkdir", &parent.join(name), same_entry, |fs| fs.mkdir(req, parent, name, mode))
+    }
+
+    fn unlink(&self, req: RequestInfo, parent: &FsPath, name: &OsStr) -> ResultEmpty {
+        both!(self, "unlink", &parent.join(name), |fs| fs.unlink(req, parent, name))
+    }
+
+    fn rmdir(&self, req: RequestInfo, parent: &FsPath, name: &OsStr) -> ResultEmpty {
+        both!(self, "rmdir", &parent.join(name), |fs| fs.rmdir(req, parent, name))
+    }
+
+    fn symlink(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, target: &Path) -> ResultEntry {
+        both!(self, "symlink", &parent.join(name), same_entry, |fs| fs.symlink(req, parent, name, target))
+    }
+
+    fn rename(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, newparent: &FsPath, newname: &OsStr) -> ResultEmpty {
+        both!(self, "rename", &parent.join(name), |fs| fs.rename(req, parent, name, newparent, newname))
+    }
+
+    fn link(&self, req: RequestInfo, path: &FsPath, newparent: &FsPath, newname: &OsStr) -> ResultEntry {
+        both!(self, "link", path, same_entry, |fs| fs.link(req, path, newparent, newname))
+    }
+
+    fn open(&self, req: RequestInfo, path: &FsPath, flags: u32) -> ResultOpen {
+        let primary = self.primary.open(req, path, flags);
+        let shadow = self.shadow.open(req, path, flags);
+        self.compare("open", path, &primary, &shadow, both_ok);
+        self.opened(primary, shadow, |opened| &mut opened.fh,
+            |fh| { let _ = self.shadow.release(req, path, fh, flags, None, false); })
+    }
+
+    fn open_handle(&self, _req: RequestInfo, _path: &FsPath, _flags: u32) -> ResultOpenHandle {
+        // Calls on handle objects would only go to one of the filesystems.
+        Err(libc::ENOSYS)
+    }
+
+    fn read(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, size: u32, callback: impl FnOnce(ResultSlice<'_>) -> CallbackResult) -> CallbackResult {
+        let (primary_fh, shadow_fh) = match self.handle(fh) {
+            Ok(handle) => handle,
+            Err(e) => return callback(Err(e)),
+        };
+        let mut shadow = None;
+        if let Some(shadow_fh) = shadow_fh {
+            self.shadow.read(req, path, shadow_fh, offset, size, |result| {
+                shadow = Some(result.map(<[u8]>::to_vec));
+                CallbackResult { _private: std::marker::PhantomData }
+            });
+        }
+        self.primary.read(req, path, primary_fh, offset, size, |result| {
+            if let Some(shadow) = shadow {
+                let matched = match (&result, &shadow) {
+                    (Ok(a), Ok(b)) => a == b,
+                    (Err(a), Err(b)) => a == b,
+                    _ => false,
+                };
+                if !matched {
+                    // The data could be large; just show how much there was.
+                    self.diverged("read", path, &result.map(<[u8]>::len),
+                        &shadow.as_ref().map(Vec::len));
+                }
+            }
+            callback(result)
+        })
+    }
+
+    fn write(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, data: Vec<u8>, flags: u32) -> ResultWrite {
+        on_handle!(self, fh, "write", path, |fs, fh| fs.write(req, path, fh, offset, data.clone(), flags))
+    }
+
+    fn write_deferred(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, data: Vec<u8>, flags: u32, reply: Responder<u32>) {
+        reply.send(on_handle!(self, fh, "write", path, |fs, fh| {
+            Responder::wait(|inner| fs.write_deferred(req, path, fh, offset, data.clone(), flags, inner))
+        }))
+    }
+
+    fn flush(&self, req: RequestInfo, path: &FsPath, fh: u64, lock_owner: LockOwner) -> ResultEmpty {
+        on_handle!(self, fh, "flush", path, |fs, fh| fs.flush(req, path, fh, lock_owner))
+    }
+
+    fn flush_deferred(&self, req: RequestInfo, path: &FsPath, fh: u64, lock_owner: LockOwner, reply: Responder<()>) {
+        reply.send(on_handle!(self, fh, "flush", path, |fs, fh| {
+            Responder::wait(|inner| fs.flush_deferred(req, path, fh, lock_owner, inner))
+        }))
+    }
+
+    fn release(&self, req: RequestInfo, path: &FsPath, fh: u64, flags: u32, lock_owner: Option<LockOwner>, flush: bool) -> ResultEmpty {
+        let result = on_handle!(self, fh, "release", path, |fs, fh| fs.release(req, path, fh, flags, lock_owner, flush));
+        self.handles.lock().unwrap().remove(&fh);
+        result
+    }
+
+    fn fsync(&self, req: RequestInfo, path: &FsPath, fh: u64, datasync: bool) -> ResultEmpty {
+        on_handle!(self, fh, "fsync", path, |fs, fh| fs.fsync(req, path, fh, datasync))
+    }
+
+    fn fsync_deferred(&self, req: RequestInfo, path: &FsPath, fh: u64, datasync: bool, reply: Responder<()>) {
+        reply.send(on_handle!(self, fh, "fsync", path, |fs, fh| {
+            Responder::wait(|inner| fs.fsync_deferred(req, path, fh, datasync, inner))
+        }))
+    }
+
+    fn sync(&self, filter: &dyn Fn(&Path) -> bool) -> ResultEmpty {
+        both!(self, "sync", Path::new("/"), |fs| fs.sync(filter))
+    }
+
+    fn opendir(&self, req: RequestInfo, path: &FsPath, flags: u32) -> ResultOpen {
+        let primary = self.primary.opendir(req, path, flags);
+        let shadow = self.shadow.opendir(req, path, flags);
+        self.compare("opendir", path, &primary, &shadow, both_ok);
+        self.opened(primary, shadow, |opened| &mut opened.fh,
+            |fh| { let _ = self.shadow.releasedir(req, path, fh, flags); })
+    }
+
+    fn opendir_handle(&self, _req: RequestInfo, _path: &FsPath, _flags: u32) -> ResultOpenDirHandle {
+        Err(libc::ENOSYS)
+    }
+
+    fn readdir(&self, req: RequestInfo, path: &FsPath, fh: u64) -> ResultReaddir {
+        on_handle!(self, fh, "readdir", path, same_listing, |fs, fh| fs.readdir(req, path, fh))
+    }
+
+    fn readdir_deferred(&self, req: RequestInfo, path: &FsPath, fh: u64, reply: Responder<Vec<DirectoryEntry>>) {
+        reply.send(on_handle!(self, fh, "readdir", path, same_listing, |fs, fh| {
+            Responder::wait(|inner| fs.readdir_deferred(req, path, fh, inner))
+        }))
+    }
+
+    fn readdir_at(&self, _req: RequestInfo, _path: &FsPath, _fh: u64, _offset: u64, _add: &mut dyn FnMut(&DirectoryEntry, u64) -> bool) -> ResultEmpty {
+        // Listings are compared whole, and the filesystems' offsets needn't match anyway.
+        Err(libc::ENOSYS)
+    }
+
+    fn releasedir(&self, req: RequestInfo, path: &FsPath, fh: u64, flags: u32) -> ResultEmpty {
+        let result = on_handle!(self, fh, "releasedir", path, |fs, fh| fs.releasedir(req, path, fh, flags));
+        self.handles.lock().unwrap().remove(&fh);
+        result
+    }
+
+    fn fsyncdir(&self, req: RequestInfo, path: &FsPath, fh: u64, datasync: bool) -> ResultEmpty {
+        on_handle!(self, fh, "fsyncdir", path, |fs, fh| fs.fsyncdir(req, path, fh, datasync))
+    }
+
+    fn statfs(&self, req: RequestInfo, path: &FsPath) -> ResultStatfs {
+        both!(self, "statfs", path, both_ok, |fs| fs.statfs(req, path))
+    }
+
+    fn quota(&self, req: RequestInfo, path: &FsPath) -> ResultQuota {
+        both!(self, "quota", path, both_ok, |fs| fs.quota(req, path))
+    }
+
+    fn setxattr(&self, req: RequestInfo, path: &FsPath, name: &OsStr, value: &[u8], flags: u32, position: XattrPosition) -> ResultEmpty {
+        both!(self, "setxattr", path, |fs| fs.setxattr(req, path, name, value, flags, position))
+    }
+
+    fn getxattr(&self, req: RequestInfo, path: &FsPath, name: &OsStr, size: u32) -> ResultXattr {
+        both!(self, "getxattr", path, |fs| fs.getxattr(req, path, name, size))
+    }
+
+    fn listxattr(&self, req: RequestInfo, path: &FsPath, size: u32) -> ResultXattr {
+        both!(self, "listxattr", path, same_names, |fs| fs.listxattr(req, path, size))
+    }
+
+    fn removexattr(&self, req: RequestInfo, path: &FsPath, name: &OsStr) -> ResultEmpty {
+        both!(self, "removexattr", path, |fs| fs.removexattr(req, path, name))
+    }
+
+    fn access(&self, req: RequestInfo, path: &FsPath, mask: u32) -> ResultEmpty {
+        both!(self, "access", path, |fs| fs.access(req, path, mask))
+    }
+
+    fn create(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, mode: u32, flags: u32) -> ResultCreate {
+        let path = FsPath::from(parent.join(name));
+        let primary = self.primary.create(req, parent, name, mode, flags);
+        let shadow = self.shadow.create(req, parent, name, mode, flags);
+        self.compare("create", &path, &primary, &shadow,
+            |a, b| same_entry(&(a.ttl, a.attr), &(b.ttl, b.attr)));
+        self.opened(primary, shadow, |created| &mut created.fh,
+            |fh| { let _ = self.shadow.release(req, &path, fh, flags, None, false); })
+    }
+
+    fn create_handle(&self, _req: RequestInfo, _parent: &FsPath, _name: &OsStr, _mode: u32, _flags: u32) -> ResultCreateHandle {
+        Err(libc::ENOSYS)
+    }
+
+    fn getlk(&self, req: RequestInfo, path: &FsPath, fh: u64, lock_owner: LockOwner, lock: FileLock) -> ResultLock {
+        on_handle!(self, fh, "getlk", path, |fs, fh| fs.getlk(req, path, fh, lock_owner, lock))
+    }
+
+    fn setlk(&self, req: RequestInfo, path: &FsPath, fh: u64, lock_owner: LockOwner, lock: FileLock, sleep: bool) -> ResultEmpty {
+        on_handle!(self, fh, "setlk", path, |fs, fh| fs.setlk(req, path, fh, lock_owner, lock, sleep))
+    }
+
+    fn flock(&self, req: RequestInfo, path: &FsPath, fh: u64, lock_owner: LockOwner, operation: i32) -> ResultEmpty {
+        on_handle!(self, fh, "flock", path, |fs, fh| fs.flock(req, path, fh, lock_owner, operation))
+    }
+
+    fn fallocate(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, length: u64, mode: i32) -> ResultEmpty {
+        on_handle!(self, fh, "fallocate", path, |fs, fh| fs.fallocate(req, path, fh, offset, length, mode))
+    }
+
+    fn lseek(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, whence: i32) -> ResultOffset {
+        on_handle!(self, fh, "lseek", path, |fs, fh| fs.lseek(req, path, fh, offset, whence))
+    }
+
+    fn copy_file_range(&self, req: RequestInfo, path_in: &FsPath, fh_in: u64, offset_in: u64, path_out: &FsPath, fh_out: u64, offset_out: u64, len: u64, flags: u32) -> ResultWrite {
+        let (primary_in, shadow_in) = self.handle(fh_in)?;
+        let (primary_out, shadow_out) = self.handle(fh_out)?;
+        let primary = self.primary.copy_file_range(req, path_in, primary_in, offset_in, path_out,
+            primary_out, offset_out, len, flags);
+        if let (Some(shadow_in), Some(shadow_out)) = (shadow_in, shadow_out) {
+            let shadow = self.shadow.copy_file_range(req, path_in, shadow_in, offset_in, path_out,
+                shadow_out, offset_out, len, flags);
+            self.compare("copy_file_range", path_out, &primary, &shadow, PartialEq::eq);
+        }
+        primary
+    }
+
+    #[cfg(target_os = "macos")]
+    fn setvolname(&self, req: RequestInfo, name: &OsStr) -> ResultEmpty {
+        both!(self, "setvolname", Path::new("/"), |fs| fs.setvolname(req, name))
+    }
+
+    #[cfg(target_os = "macos")]
+    fn exchange(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, newparent: &FsPath, newname: &OsStr, options: u64) -> ResultEmpty {
+        both!(self, "exchange", &parent.join(name), |fs| fs.exchange(req, parent, name, newparent, newname, options))
+    }
+
+    #[cfg(target_os = "macos")]
+    fn getxtimes(&self, req: RequestInfo, path: &FsPath) -> ResultXTimes {
+        both!(self, "getxtimes", path, both_ok, |fs| fs.getxtimes(req, path))
+    }
+
+    #[cfg(target_os = "macos")]
+    fn setxtimes(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, bkuptime: Option<SystemTime>, crtime: Option<SystemTime>) -> ResultEmpty {
+        match fh {
+            Some(fh) => on_handle!(self, fh, "setxtimes", path, |fs, fh| fs.setxtimes(req, path, Some(fh), bkuptime, crtime)),
+            None => both!(self, "setxtimes", path, |fs| fs.setxtimes(req, path, None, bkuptime, crtime)),
+        }
+    }
+
+    fn classify(&self, op: Operation, path: &FsPath) -> Lane {
+        self.primary.classify(op, path)
+    }
+
+    fn load(&self, op: Operation, path: &FsPath) -> Load {
+        self.primary.load(op, path)
+    }
+}
+
+#[test]
+fn test_shadow() {
+    use super::test_fs::{self, MemFs};
+    use super::{list_dir, read_all, write_all};
+
+    let req = test_fs::req();
+    let root = &FsPath::from("/");
+    let fs = ShadowFs::new(MemFs::new(), MemFs::new());
+
+    // Changes go to both, and matching results aren't divergences.
+    write_all(&fs, req, &FsPath::from("/a"), b"same").unwrap();
+    fs.mkdir(req, root, OsStr::new("d"), 0o755).unwrap();
+    assert_eq!(b"same".to_vec(), read_all(&fs.shadow, req, &FsPath::from("/a")).unwrap());
+    assert_eq!(b"same".to_vec(), read_all(&fs, req, &FsPath::from("/a")).unwrap());
+    assert_eq!(2, list_dir(&fs, req, root).unwrap().len());
+    assert_eq!(0, fs.divergences());
+
+    // Differences are counted, and the primary's results are returned.
+    write_all(&fs.shadow, req, &FsPath::from("/a"), b"diff").unwrap();
+    assert_eq!(b"same".to_vec(), read_all(&fs, req, &FsPath::from("/a")).unwrap());
+    assert_eq!(1, fs.divergences());
+
+    write_all(&fs.shadow, req, &FsPath::from("/b"), b"").unwrap();
+    assert_eq!(2, list_dir(&fs, req, root).unwrap().len());
+    assert_eq!(Err(libc::ENOENT), fs.unlink(req, root, OsStr::new("b")));
+    assert_eq!(3, fs.divergences());
+
+    // Files the shadow can't open are used on the primary alone.
+    write_all(&fs.primary, req, &FsPath::from("/c"), b"primary").unwrap();
+    assert_eq!(b"primary".to_vec(), read_all(&fs, req, &FsPath::from("/c")).unwrap());
+    assert_eq!(4, fs.divergences());
+}
@@ -0,0 +1,266 @@
+// Snapshot :: a read-only view of a filesystem as it was first seen.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use crate::types::*;
+
+/// A read-only view of another filesystem which doesn't change after it's mounted, so that backup
+/// tools reading through it see a consistent tree even while the backend is being changed.
+///
+/// The attributes of each entry, the listing of each directory, and the target of each symbolic
+/// link are kept the first time they're seen, and served from then on. Entries which didn't exist
+/// when first looked up, or weren't in their directory's listing, stay missing.
+///
+/// File contents aren't copied, so opening a file fails with `ESTALE` if its size or modification
+/// time has changed since it was first seen, rather than serving contents which don't match. A
+/// file changed while it's open isn't noticed. Entries which were seen but have since been deleted
+/// also fail with `ESTALE`. Extended attributes aren't kept, and always come from the filesystem.
+///
+/// Calls which would change anything fail with `EROFS`. Everything seen is kept until the
+/// filesystem is unmounted, so the memory used grows with the size of the tree read.
+pub struct SnapshotFs<FS> {
+    inner: FS,
+    /// Attributes by path, or `None` for paths which didn't exist.
+    attrs: Mutex<HashMap<FsPath, Option<(Duration, FileAttr)>>>,
+    listings: Mutex<HashMap<FsPath, Arc<Vec<DirectoryEntry>>>>,
+    links: Mutex<HashMap<FsPath, Arc<Vec<u8>>>>,
+}
+
+impl<FS: FilesystemMT> SnapshotFs<FS> {
+    pub fn new(inner: FS) -> SnapshotFs<FS> {
+        SnapshotFs {
+            inner,
+            attrs: Mutex::new(HashMap::new()),
+            listings: Mutex::new(HashMap::new()),
+            links: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Whether the snapshot has the listing of the directory the path is in, and the path's name
+    /// is in it. `None` if the listing hasn't been read yet.
+    fn listed(&self, path: &Path) -> Option<bool> {
+        let (parent, name) = (path.parent()?, path.file_name()?);
+        let listings = self.listings.lock().unwrap();
+        let entries = listings.get(parent)?;
+        Some(entries.iter().any(|entry| entry.name == name))
+    }
+}
+
+/// Whether a file opened with the given flags could be changed through the handle.
+fn is_writable(flags: u32) -> bool {
+    let flags = flags as libc::c_int;
+    flags & libc::O_ACCMODE != libc::O_RDONLY || flags & libc::O_TRUNC != 0
+}
+
+impl<FS: FilesystemMT> FilesystemMT for SnapshotFs<FS> {
+    forward_calls!(inner; init, init_connection, destroy, read, lseek, flush, flush_deferred,
+        release, getlk, setlk, flock, last_release_after_unlink, opendir, releasedir, statfs, quota,
+        getxattr, listxattr, access, getxtimes, classify, load);
+
+    fn getattr(&self, req: RequestInfo, path: &FsPath, _fh: Option<u64>) -> ResultEntry {
+        if let Some(seen) = self.attrs.lock().unwrap().get(path) {
+            return seen.ok_or(libc::ENOENT);
+        }
+        let listed = self.listed(path);
+        if listed == Some(false) {
+            return Err(libc::ENOENT);
+        }
+        let seen = match self.inner.getattr(req, path, None) {
+            Ok(entry) => Some(entry),
+            Err(libc::ENOENT) if listed == Some(true) => return Err(libc::ESTALE),
+            Err(libc::ENOENT) => None,
+            Err(e) => return Err(e),
+        };
+        // Another thread may have seen it first.
+        self.attrs.lock().unwrap().entry(path.clone()).or_insert(seen).ok_or(libc::ENOENT)
+    }
+
+    fn readlink(&self, req: RequestInfo, path: &FsPath) -> ResultData {
+        if let Some(target) = self.links.lock().unwrap().get(path) {
+            return Ok(target.to_vec());
+        }
+        let target = Arc::new(self.inner.readlink(req, path)?);
+        Ok(self.links.lock().unwrap().entry(path.clone()).or_insert(target).to_vec())
+    }
+
+    fn open(&self, req: RequestInfo, path: &FsPath, flags: u32) -> ResultOpen {
+        if is_writable(flags) {
+            return Err(libc::EROFS);
+        }
+        let (_, seen) = self.getattr(req, path, None)?;
+        let (_, now) = match self.inner.getattr(req, path, None) {
+            Err(libc::ENOENT) => return Err(libc::ESTALE),
+            other => other?,
+        };
+        if (now.size, now.mtime) != (seen.size, seen.mtime) {
+            debug!("{:?} changed since the snapshot was taken", path);
+            return Err(libc::ESTALE);
+        }
+        self.inner.open(req, path, flags)
+    }
+
+    fn open_handle(&self, _req: RequestInfo, _path: &FsPath, _flags: u32) -> ResultOpenHandle {
+        // Handle objects from the inner filesystem could be opened for writing.
+        Err(libc::ENOSYS)
+    }
+
+    fn opendir_handle(&self, _req: RequestInfo, _path: &FsPath, _flags: u32) -> ResultOpenDirHandle {
+        // Directory handles from the inner filesystem would list the directory as it is now.
+        Err(libc::ENOSYS)
+    }
+
+    fn readdir(&self, req: RequestInfo, path: &FsPath, fh: u64) -> ResultReaddir {
+        if let Some(entries) = self.listings.lock().unwrap().get(path) {
+            return Ok(entries.to_vec());
+        }
+        let mut entries = self.inner.readdir(req, path, fh)?;
+        // Attributes are those first seen by `getattr`, which these may not be.
+        for entry in &mut entries {
+            entry.attr = None;
+        }
+        let entries = Arc::new(entries);
+        Ok(self.listings.lock().unwrap().entry(path.clone()).or_insert(entries).to_vec())
+    }
+
+    fn fsync(&self, _req: RequestInfo, _path: &FsPath, _fh: u64, _datasync: bool) -> ResultEmpty {
+        Ok(())
+    }
+
+    fn fsyncdir(&self, _req: RequestInfo, _path: &FsPath, _fh: u64, _datasync: bool) -> ResultEmpty {
+        Ok(())
+    }
+
+    fn chmod(&self, _req: RequestInfo, _path: &FsPath, _fh: Option<u64>, _mode: u32) -> ResultEmpty {
+        Err(libc::EROFS)
+    }
+
+    fn chown(&self, _req: RequestInfo, _path: &FsPath, _fh: Option<u64>, _uid: Option<u32>, _gid: Option<u32>) -> ResultEmpty {
+        Err(libc::EROFS)
+    }
+
+    fn truncate(&self, _req: RequestInfo, _path: &FsPath, _fh: Option<u64>, _size: u64) -> ResultEmpty {
+        Err(libc::EROFS)
+    }
+
+    fn utimens(&self, _req: RequestInfo, _path: &FsPath, _fh: Option<u64>, _atime: Option<SystemTime>, _mtime: Option<SystemTime>) -> ResultEmpty {
+        Err(libc::EROFS)
+    }
+
+    fn utimens_macos(&self, _req: RequestInfo, _path: &FsPath, _fh: Option<u64>, _crtime: Option<SystemTime>, _chgtime: Option<SystemTime>, _bkuptime: Option<SystemTime>, _flags: Option<u32>) -> ResultEmpty {
+        Err(libc::EROFS)
+    }
+
+    fn mknod(&self, _req: RequestInfo, _parent: &FsPath, _name: &OsStr, _mode: u32, _rdev: u32) -> ResultEntry {
+        Err(libc::EROFS)
+    }
+
+    fn mkdir(&self, _req: RequestInfo, _parent: &FsPath, _name: &OsStr, _mode: u32) -> ResultEntry {
+        Err(libc::EROFS)
+    }
+
+    fn unlink(&self, _req: RequestInfo, _parent: &FsPath, _name: &OsStr) -> ResultEmpty {
+        Err(libc::EROFS)
+    }
+
+    fn rmdir(&self, _req: RequestInfo, _parent: &FsPath, _name: &OsStr) -> ResultEmpty {
+        Err(libc::EROFS)
+    }
+
+    fn symlink(&self, _req: RequestInfo, _parent: &FsPath, _name: &OsStr, _target: &Path) -> ResultEntry {
+        Err(libc::EROFS)
+    }
+
+    fn rename(&self, _req: RequestInfo, _parent: &FsPath, _name: &OsStr, _newparent: &FsPath, _newname: &OsStr) -> ResultEmpty {
+        Err(libc::EROFS)
+    }
+
+    fn link(&self, _req: RequestInfo, _path: &FsPath, _newparent: &FsPath, _newname: &OsStr) -> ResultEntry {
+        Err(libc::EROFS)
+    }
+
+    fn write(&self, _req: RequestInfo, _path: &FsPath, _fh: u64, _offset: u64, _data: Vec<u8>, _flags: u32) -> ResultWrite {
+        Err(libc::EROFS)
+    }
+
+    fn setxattr(&self, _req: RequestInfo, _path: &FsPath, _name: &OsStr, _value: &[u8], _flags: u32, _position: XattrPosition) -> ResultEmpty {
+        Err(libc::EROFS)
+    }
+
+    fn removexattr(&self, _req: RequestInfo, _path: &FsPath, _name: &OsStr) -> ResultEmpty {
+        Err(libc::EROFS)
+    }
+
+    fn create(&self, _req: RequestInfo, _parent: &FsPath, _name: &OsStr, _mode: u32, _flags: u32) -> ResultCreate {
+        Err(libc::EROFS)
+    }
+
+    fn create_handle(&self, _req: RequestInfo, _parent: &FsPath, _name: &OsStr, _mode: u32, _flags: u32) -> ResultCreateHandle {
+        Err(libc::EROFS)
+    }
+
+    fn fallocate(&self, _req: RequestInfo, _path: &FsPath, _fh: u64, _offset: u64, _length: u64, _mode: i32) -> ResultEmpty {
+        Err(libc::EROFS)
+    }
+
+    fn copy_file_range(&self, _req: RequestInfo, _path_in: &FsPath, _fh_in: u64, _offset_in: u64, _path_out: &FsPath, _fh_out: u64, _offset_out: u64, _len: u64, _flags: u32) -> ResultWrite {
+        Err(libc::EROFS)
+    }
+
+    #[cfg(target_os = "macos")]
+    fn setvolname(&self, _req: RequestInfo, _name: &OsStr) -> ResultEmpty {
+        Err(libc::EROFS)
+    }
+
+    #[cfg(target_os = "macos")]
+    fn exchange(&self, _req: RequestInfo, _parent: &FsPath, _name: &OsStr, _newparent: &FsPath, _newname: &OsStr, _options: u64) -> ResultEmpty {
+        Err(libc::EROFS)
+    }
+
+    #[cfg(target_os = "macos")]
+    fn setxtimes(&self, _req: RequestInfo, _path: &FsPath, _fh: Option<u64>, _bkuptime: Option<SystemTime>, _crtime: Option<SystemTime>) -> ResultEmpty {
+        Err(libc::EROFS)
+    }
+}
+
+#[test]
+fn test_snapshot() {
+    use std::ffi::OsString;
+    use super::test_fs::{self, MemFs};
+    use super::{list_dir, read_all, write_all};
+
+    let req = test_fs::req();
+    let root = &FsPath::from("/");
+    let fs = SnapshotFs::new(MemFs::new());
+    write_all(&fs.inner, req, &FsPath::from("/a"), b"first").unwrap();
+    write_all(&fs.inner, req, &FsPath::from("/b"), b"second").unwrap();
+    let names = |fs: &SnapshotFs<_>| list_dir(fs, req, root).unwrap().into_iter()
+        .map(|entry| entry.name).collect::<Vec<_>>();
+
+    assert_eq!(vec![OsString::from("a"), OsString::from("b")], names(&fs));
+    assert_eq!(5, fs.getattr(req, &FsPath::from("/a"), None).unwrap().1.size);
+    assert_eq!(Err(libc::ENOENT), fs.getattr(req, &FsPath::from("/c"), None).map(drop));
+
+    // Changes to the backend don't show up.
+    write_all(&fs.inner, req, &FsPath::from("/a"), b"changed").unwrap();
+    write_all(&fs.inner, req, &FsPath::from("/c"), b"new").unwrap();
+    fs.inner.unlink(req, root, OsStr::new("b")).unwrap();
+    assert_eq!(vec![OsString::from("a"), OsString::from("b")], names(&fs));
+    assert_eq!(5, fs.getattr(req, &FsPath::from("/a"), None).unwrap().1.size);
+    assert_eq!(Err(libc::ENOENT), fs.getattr(req, &FsPath::from("/c"), None).map(drop));
+
+    // Files whose contents no longer match the snapshot can't be read.
+    assert_eq!(Err(libc::ESTALE), read_all(&fs, req, &FsPath::from("/a")));
+    assert_eq!(Err(libc::ESTALE), fs.getattr(req, &FsPath::from("/b"), None).map(drop));
+
+    // Nothing can be changed through it.
+    assert_eq!(Err(libc::EROFS), fs.open(req, &FsPath::from("/a"), libc::O_WRONLY as u32));
+    assert_eq!(Err(libc::EROFS), fs.unlink(req, root, OsStr::new("a")));
+    assert_eq!(Err(libc::EROFS), fs.mkdir(req, root, OsStr::new("d"), 0o755).map(drop));
+}
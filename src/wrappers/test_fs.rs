@@ -0,0 +1,238 @@
+// Test FS :: a minimal in-memory filesystem for testing wrappers.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::FileType;
+use crate::types::*;
+
+/// Files (`Some` contents) and directories (`None`) by path. The root always exists. Handles are
+/// all zero: every call works by path.
+pub struct MemFs {
+    pub nodes: Mutex<BTreeMap<PathBuf, Option<Vec<u8>>>>,
+}
+
+impl MemFs {
+    pub fn new() -> MemFs {
+        let mut nodes = BTreeMap::new();
+        nodes.insert(PathBuf::from("/"), None);
+        MemFs { nodes: Mutex::new(nodes) }
+    }
+
+    fn children<'a>(nodes: &'a BTreeMap<PathBuf, Option<Vec<u8>>>, path: &'a Path)
+        -> impl Iterator<Item = &'a PathBuf>
+    {
+        nodes.keys().filter(move |p| p.parent() == Some(path))
+    }
+}
+
+pub fn req() -> RequestInfo {
+    RequestInfo { unique: 0, uid: 0, gid: 0, pid: 0, node: None, umask: None, lock_owner: None }
+}
+
+pub fn attr(kind: FileType, size: u64) -> FileAttr {
+    let t = SystemTime::UNIX_EPOCH;
+    FileAttr { ino: 0, size, blocks: 0, atime: t, mtime: t, ctime: t, crtime: t, kind, perm: 0o644,
+        nlink: 1, uid: 0, gid: 0, rdev: 0, flags: 0 }
+}
+
+const TTL: Duration = Duration::from_secs(1);
+
+impl FilesystemMT for MemFs {
+    fn getattr(&self, _req: RequestInfo, path: &FsPath, _fh: Option<u64>) -> ResultEntry {
+        match self.nodes.lock().unwrap().get(path.as_path()) {
+            Some(Some(data)) => Ok((TTL, attr(FileType::RegularFile, data.len() as u64))),
+            Some(None) => Ok((TTL, attr(FileType::Directory, 0))),
+            None => Err(libc::ENOENT),
+        }
+    }
+
+    fn truncate(&self, _req: RequestInfo, path: &FsPath, _fh: Option<u64>, size: u64) -> ResultEmpty {
+        let mut nodes = self.nodes.lock().unwrap();
+        let data = nodes.get_mut(path.as_path()).ok_or(libc::ENOENT)?.as_mut().ok_or(libc::EISDIR)?;
+        data.resize(size as usize, 0);
+        Ok(())
+    }
+
+    fn mkdir(&self, _req: RequestInfo, parent: &FsPath, name: &OsStr, _mode: u32) -> ResultEntry {
+        let mut nodes = self.nodes.lock().unwrap();
+        let path = parent.join(name);
+        if nodes.contains_key(&path) {
+            return Err(libc::EEXIST);
+        }
+        nodes.insert(path, None);
+        Ok((TTL, attr(FileType::Directory, 0)))
+    }
+
+    fn unlink(&self, _req: RequestInfo, parent: &FsPath, name: &OsStr) -> ResultEmpty {
+        let mut nodes = self.nodes.lock().unwrap();
+        let path = parent.join(name);
+        match nodes.get(&path) {
+            Some(Some(_)) => { nodes.remove(&path); Ok(()) }
+            Some(None) => Err(libc::EISDIR),
+            None => Err(libc::ENOENT),
+        }
+    }
+
+    fn rmdir(&self, _req: RequestInfo, parent: &FsPath, name: &OsStr) -> ResultEmpty {
+        let mut nodes = self.nodes.lock().unwrap();
+        let path = parent.join(name);
+        match nodes.get(&path) {
+            Some(None) if MemFs::children(&nodes, &path).next().is_some() => Err(libc::ENOTEMPTY),
+            Some(None) => { nodes.remove(&path); Ok(()) }
+            Some(Some(_)) => Err(libc::ENOTDIR),
+            None => Err(libc::ENOENT),
+        }
+    }
+
+    fn rename(&self, _req: RequestInfo, parent: &FsPath, name: &OsStr, newparent: &FsPath, newname: &OsStr) -> ResultEmpty {
+        let mut nodes = self.nodes.lock().unwrap();
+        let (from, to) = (parent.join(name), newparent.join(newname));
+        if !nodes.contains_key(&from) {
+            return Err(libc::ENOENT);
+        }
+        if !nodes.contains_key(newparent.as_path()) {
+            return Err(libc::ENOENT);
+        }
+        let moved = nodes.keys().filter(|p| p.starts_with(&from)).cloned().collect::<Vec<_>>();
+        for old in moved {
+            let node = nodes.remove(&old).unwrap();
+            nodes.insert(to.join(old.strip_prefix(&from).unwrap()), node);
+        }
+        Ok(())
+    }
+
+    fn open(&self, _req: RequestInfo, path: &FsPath, _flags: u32) -> ResultOpen {
+        let nodes = self.nodes.lock().unwrap();
+        nodes.get(path.as_path()).map(|_| OpenedFile::new(0)).ok_or(libc::ENOENT)
+    }
+
+    fn read(&self, _req: RequestInfo, path: &FsPath, _fh: u64, offset: u64, size: u32, callback: impl FnOnce(ResultSlice<'_>) -> CallbackResult) -> CallbackResult {
+        let nodes = self.nodes.lock().unwrap();
+        match nodes.get(path.as_path()) {
+            Some(Some(data)) => {
+                let start = data.len().min(offset as usize);
+                let end = data.len().min(start + size as usize);
+                callback(Ok(&data[start .. end]))
+            }
+            Some(None) => callback(Err(libc::EISDIR)),
+            None => callback(Err(libc::ENOENT)),
+        }
+    }
+
+    fn write(&self, _req: RequestInfo, path: &FsPath, _fh: u64, offset: u64, data: Vec<u8>, _flags: u32) -> ResultWrite {
+        let mut nodes = self.nodes.lock().unwrap();
+        let file = nodes.get_mut(path.as_path()).ok_or(libc::ENOENT)?.as_mut().ok_or(libc::EISDIR)?;
+        let end = offset as usize + data.len();
+        if file.len() < end {
+            file.resize(end, 0);
+        }
+        file[offset as usize .. end].copy_from_slice(&data);
+        Ok(data.len() as u32)
+    }
+
+    fn release(&self, _req: RequestInfo, _path: &FsPath, _fh: u64, _flags: u32, _lock_owner: Option<LockOwner>, _flush: bool) -> ResultEmpty {
+        Ok(())
+    }
+
+    fn opendir(&self, _req: RequestInfo, path: &FsPath, _flags: u32) -> ResultOpen {
+        match self.nodes.lock().unwrap().get(path.as_path()) {
+            Some(None) => Ok(OpenedFile::new(0)),
+            Some(Some(_)) => Err(libc::ENOTDIR),
+            None => Err(libc::ENOENT),
+        }
+    }
+
+    fn readdir(&self, _req: RequestInfo, path: &FsPath, _fh: u64) -> ResultReaddir {
+        let nodes = self.nodes.lock().unwrap();
+        Ok(MemFs::children(&nodes, path)
+            .map(|p| DirectoryEntry {
+                name: p.file_name().unwrap().to_owned(),
+                kind: if nodes[p].is_some() { FileType::RegularFile } else { FileType::Directory },
+                offset: None,
+                attr: None,
+            })
+            .collect())
+    }
+
+    fn releasedir(&self, _req: RequestInfo, _path: &FsPath, _fh: u64, _flags: u32) -> ResultEmpty {
+        Ok(())
+    }
+
+    fn create(&self, _req: RequestInfo, parent: &FsPath, name: &OsStr, _mode: u32, _flags: u32) -> ResultCreate {
+        let mut nodes = self.nodes.lock().unwrap();
+        if !nodes.contains_key(parent.as_path()) {
+            return Err(libc::ENOENT);
+        }
+        nodes.insert(parent.join(name), Some(vec![]));
+        Ok(CreatedEntry { ttl: TTL, attr: attr(FileType::RegularFile, 0), fh: 0, flags: 0 })
+    }
+}
+
+/// A `MemFs` which only implements the `_deferred` calls, sending their results from another
+/// thread, as a filesystem driven by an event loop of its own would. The plain calls fail with
+/// `ENOSYS`, so wrappers have to pass the deferred ones on for anything to work.
+pub struct DeferredFs {
+    pub mem: Arc<MemFs>,
+}
+
+impl DeferredFs {
+    pub fn new() -> DeferredFs {
+        DeferredFs { mem: Arc::new(MemFs::new()) }
+    }
+
+    fn later<T: Send + 'static>(&self, reply: Responder<T>,
+        f: impl FnOnce(&MemFs) -> Result<T, libc::c_int> + Send + 'static)
+    {
+        let mem = self.mem.clone();
+        thread::spawn(move || reply.send(f(&mem)));
+    }
+}
+
+impl FilesystemMT for DeferredFs {
+    fn getattr_deferred(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, reply: Responder<(Duration, FileAttr)>) {
+        let path = path.to_owned();
+        self.later(reply, move |mem| mem.getattr(req, &path, fh))
+    }
+
+    fn open(&self, req: RequestInfo, path: &FsPath, flags: u32) -> ResultOpen {
+        self.mem.open(req, path, flags)
+    }
+
+    fn write_deferred(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, data: Vec<u8>, flags: u32, reply: Responder<u32>) {
+        let path = path.to_owned();
+        self.later(reply, move |mem| mem.write(req, &path, fh, offset, data, flags))
+    }
+
+    fn flush_deferred(&self, _req: RequestInfo, _path: &FsPath, _fh: u64, _lock_owner: LockOwner, reply: Responder<()>) {
+        self.later(reply, |_| Ok(()))
+    }
+
+    fn fsync_deferred(&self, _req: RequestInfo, _path: &FsPath, _fh: u64, _datasync: bool, reply: Responder<()>) {
+        self.later(reply, |_| Ok(()))
+    }
+
+    fn release(&self, req: RequestInfo, path: &FsPath, fh: u64, flags: u32, lock_owner: Option<LockOwner>, flush: bool) -> ResultEmpty {
+        self.mem.release(req, path, fh, flags, lock_owner, flush)
+    }
+
+    fn opendir(&self, req: RequestInfo, path: &FsPath, flags: u32) -> ResultOpen {
+        self.mem.opendir(req, path, flags)
+    }
+
+    fn readdir_deferred(&self, req: RequestInfo, path: &FsPath, fh: u64, reply: Responder<Vec<DirectoryEntry>>) {
+        let path = path.to_owned();
+        self.later(reply, move |mem| mem.readdir(req, &path, fh))
+    }
+
+    fn releasedir(&self, req: RequestInfo, path: &FsPath, fh: u64, flags: u32) -> ResultEmpty {
+        self.mem.releasedir(req, path, fh, flags)
+    }
+}
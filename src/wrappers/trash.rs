@@ -0,0 +1,333 @@
+// Trash :: moving deleted files aside instead of deleting them.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::types::*;
+use super::{list_dir, read_all, write_all};
+
+/// Name of the trash directory, at the root of the filesystem.
+const TRASH_DIR: &str = ".Trash";
+
+/// Longest part of the original name kept in the name of a trashed entry.
+const MAX_NAME: usize = 200;
+
+/// A filesystem which moves files and directories into a trash directory when they're deleted,
+/// instead of deleting them.
+///
+/// Deleted entries are moved to `/.Trash/files`, under a name made unique by a timestamp, and a
+/// record of where each one came from and when is kept in `/.Trash/info`. Files replaced by a
+/// rename are moved to the trash too. The trash directory is left out of the listing of the root
+/// directory, but can be looked up and browsed by name. Deleting things inside it deletes them
+/// for real.
+///
+/// Entries stay in the trash until purged, either individually with `purge`, all at once with
+/// `purge_all`, or by age with `purge_expired`, which also runs when the filesystem starts.
+pub struct TrashFs<FS> {
+    inner: FS,
+    expiry: Option<Duration>,
+    counter: AtomicU64,
+}
+
+/// An entry in the trash.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TrashEntry {
+    /// Name of the entry in `/.Trash/files`.
+    pub id: OsString,
+    /// Where the entry was before it was deleted.
+    pub path: PathBuf,
+    /// When the entry was deleted.
+    pub deleted: SystemTime,
+}
+
+impl<FS: FilesystemMT> TrashFs<FS> {
+    pub fn new(inner: FS) -> TrashFs<FS> {
+        TrashFs {
+            inner,
+            expiry: None,
+            counter: AtomicU64::new(0),
+        }
+    }
+
+    /// Make `purge_expired` delete entries which have been in the trash for longer than this.
+    /// Without this, entries never expire.
+    pub fn expire_after(mut self, age: Duration) -> TrashFs<FS> {
+        self.expiry = Some(age);
+        self
+    }
+
+    /// List the entries in the trash.
+    pub fn list(&self, req: RequestInfo) -> Result<Vec<TrashEntry>, libc::c_int> {
+        let mut entries = vec![];
+        for entry in list_dir(&self.inner, req, &trash_path("info"))? {
+            let info_path = FsPath::from(trash_path("info").join(&entry.name));
+            let info = read_all(&self.inner, req, &info_path)?;
+            match parse_info(&info) {
+                Some((path, deleted)) => entries.push(TrashEntry { id: entry.name, path, deleted }),
+                None => warn!("bad trash info for {:?}", entry.name),
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Permanently delete an entry from the trash.
+    pub fn purge(&self, req: RequestInfo, id: &OsStr) -> ResultEmpty {
+        match remove_tree(&self.inner, req, &trash_path("files"), id) {
+            Ok(()) | Err(libc::ENOENT) => (),
+            Err(e) => return Err(e),
+        }
+        self.inner.unlink(req, &trash_path("info"), id)
+    }
+
+    /// Permanently delete everything in the trash. Returns the number of entries deleted.
+    pub fn purge_all(&self, req: RequestInfo) -> Result<usize, libc::c_int> {
+        self.purge_where(req, |_| true)
+    }
+
+    /// Permanently delete entries which have been in the trash for longer than the time given to
+    /// `expire_after`. Returns the number of entries deleted.
+    pub fn purge_expired(&self, req: RequestInfo) -> Result<usize, libc::c_int> {
+        let cutoff = match self.expiry {
+            Some(age) => match SystemTime::now().checked_sub(age) {
+                Some(cutoff) => cutoff,
+                None => return Ok(0),
+            },
+            None => return Ok(0),
+        };
+        self.purge_where(req, |entry| entry.deleted < cutoff)
+    }
+
+    fn purge_where(&self, req: RequestInfo, f: impl Fn(&TrashEntry) -> bool) -> Result<usize, libc::c_int> {
+        let mut count = 0;
+        for entry in self.list(req)?.into_iter().filter(f) {
+            self.purge(req, &entry.id)?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Move an entry into the trash.
+    fn trash(&self, req: RequestInfo, parent: &FsPath, name: &OsStr) -> ResultEmpty {
+        let now = SystemTime::now();
+        let since_epoch = now.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let mut id = OsString::from(format!("{}.{}.", since_epoch.as_nanos(),
+            self.counter.fetch_add(1, Ordering::Relaxed)));
+        id.push(OsStr::from_bytes(&name.as_bytes()[.. name.len().min(MAX_NAME)]));
+
+        // The record goes first, so nothing ends up in the trash without one.
+        let info_path = FsPath::from(trash_path("info").join(&id));
+        write_all(&self.inner, req, &info_path, &format_info(&parent.join(name), since_epoch))?;
+        if let Err(e) = self.inner.rename(req, parent, name, &trash_path("files"), &id) {
+            if let Err(e) = self.inner.unlink(req, &trash_path("info"), &id) {
+                warn!("failed to remove trash info {:?}: {}", info_path, e);
+            }
+            return Err(e);
+        }
+        debug!("trashed {:?} as {:?}", parent.join(name), id);
+        Ok(())
+    }
+
+    /// After deleting something in the trash for real, remove its record if it was a top-level
+    /// entry.
+    fn forget(&self, req: RequestInfo, parent: &FsPath, name: &OsStr) {
+        if *parent == trash_path("files") {
+            if let Err(e) = self.inner.unlink(req, &trash_path("info"), name) {
+                warn!("failed to remove trash info for {:?}: {}", name, e);
+            }
+        }
+    }
+}
+
+fn trash_path(name: &str) -> FsPath {
+    FsPath::from(Path::new("/").join(TRASH_DIR).join(name))
+}
+
+fn in_trash(path: &Path) -> bool {
+    path.starts_with(Path::new("/").join(TRASH_DIR))
+}
+
+fn is_trash(parent: &Path, name: &OsStr) -> bool {
+    parent == Path::new("/") && name == TRASH_DIR
+}
+
+/// The info record of a trashed entry: the deletion time in seconds, then the original path.
+fn format_info(path: &Path, deleted: Duration) -> Vec<u8> {
+    let mut info = format!("{}\n", deleted.as_secs()).into_bytes();
+    info.extend_from_slice(path.as_os_str().as_bytes());
+    info
+}
+
+fn parse_info(info: &[u8]) -> Option<(PathBuf, SystemTime)> {
+    let newline = info.iter().position(|&b| b == b'\n')?;
+    let secs = std::str::from_utf8(&info[.. newline]).ok()?.parse().ok()?;
+    let path = PathBuf::from(OsStr::from_bytes(&info[newline + 1 ..]));
+    Some((path, UNIX_EPOCH + Duration::from_secs(secs)))
+}
+
+/// Delete a file, or a directory and everything in it.
+fn remove_tree(fs: &impl FilesystemMT, req: RequestInfo, parent: &FsPath, name: &OsStr) -> ResultEmpty {
+    let path = FsPath::from(parent.join(name));
+    let (_, attr) = fs.getattr(req, &path, None)?;
+    if attr.kind != crate::FileType::Directory {
+        return fs.unlink(req, parent, name);
+    }
+    for entry in list_dir(fs, req, &path)? {
+        remove_tree(fs, req, &path, &entry.name)?;
+    }
+    fs.rmdir(req, parent, name)
+}
+
+impl<FS: FilesystemMT> FilesystemMT for TrashFs<FS> {
+    forward_calls!(inner; destroy, getattr, getattr_mask, getattr_deferred, chmod, chown, truncate,
+        utimens, utimens_macos, readlink, mknod, mkdir, symlink, link, open, open_handle, read, write,
+        write_deferred, flush, flush_deferred, release, fsync, fsync_deferred, sync, opendir, releasedir, fsyncdir, statfs, quota, setxattr, getxattr,
+        listxattr, removexattr, access, create, create_handle, getlk, setlk, flock, fallocate, lseek, copy_file_range,
+        setvolname, getxtimes, setxtimes, classify, load);
+
+    fn init(&self, req: RequestInfo) -> ResultEmpty {
+        self.inner.init(req)?;
+        let root = FsPath::from("/");
+        let trash = FsPath::from(root.join(TRASH_DIR));
+        for (parent, name) in [(&root, TRASH_DIR), (&trash, "files"), (&trash, "info")] {
+            match self.inner.mkdir(req, parent, OsStr::new(name), 0o755) {
+                Ok(_) | Err(libc::EEXIST) => (),
+                Err(e) => return Err(e),
+            }
+        }
+        match self.purge_expired(req) {
+            Ok(0) => (),
+            Ok(n) => info!("purged {} expired entries from the trash", n),
+            Err(e) => warn!("failed to purge expired trash: {}", e),
+        }
+        Ok(())
+    }
+
+    fn unlink(&self, req: RequestInfo, parent: &FsPath, name: &OsStr) -> ResultEmpty {
+        if in_trash(parent) {
+            self.inner.unlink(req, parent, name)?;
+            self.forget(req, parent, name);
+            return Ok(());
+        }
+        self.trash(req, parent, name)
+    }
+
+    fn rmdir(&self, req: RequestInfo, parent: &FsPath, name: &OsStr) -> ResultEmpty {
+        if is_trash(parent, name) {
+            return Err(libc::EBUSY);
+        }
+        if in_trash(parent) {
+            self.inner.rmdir(req, parent, name)?;
+            self.forget(req, parent, name);
+            return Ok(());
+        }
+        if !list_dir(&self.inner, req, &FsPath::from(parent.join(name)))?.is_empty() {
+            return Err(libc::ENOTEMPTY);
+        }
+        self.trash(req, parent, name)
+    }
+
+    fn rename(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, newparent: &FsPath, newname: &OsStr) -> ResultEmpty {
+        if is_trash(parent, name) || is_trash(newparent, newname) {
+            return Err(libc::EBUSY);
+        }
+        if !in_trash(newparent) {
+            // Save a file which is about to be replaced. (A directory can only be replaced if it's
+            // empty, so there's nothing to save.)
+            match self.inner.getattr(req, &FsPath::from(newparent.join(newname)), None) {
+                Ok((_, attr)) if attr.kind != crate::FileType::Directory => {
+                    self.trash(req, newparent, newname)?;
+                }
+                _ => (),
+            }
+        }
+        self.inner.rename(req, parent, name, newparent, newname)
+    }
+
+    #[cfg(target_os = "macos")]
+    fn exchange(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, newparent: &FsPath, newname: &OsStr, options: u64) -> ResultEmpty {
+        if is_trash(parent, name) || is_trash(newparent, newname) {
+            return Err(libc::EBUSY);
+        }
+        self.inner.exchange(req, parent, name, newparent, newname, options)
+    }
+
+    fn opendir_handle(&self, _req: RequestInfo, _path: &FsPath, _flags: u32) -> ResultOpenDirHandle {
+        // Directory handles from the inner filesystem would list the trash directory.
+        Err(libc::ENOSYS)
+    }
+
+    fn readdir(&self, req: RequestInfo, path: &FsPath, fh: u64) -> ResultReaddir {
+        let mut entries = self.inner.readdir(req, path, fh)?;
+        if path == Path::new("/") {
+            entries.retain(|entry| entry.name != TRASH_DIR);
+        }
+        Ok(entries)
+    }
+
+    fn readdir_deferred(&self, req: RequestInfo, path: &FsPath, fh: u64, reply: Responder<Vec<DirectoryEntry>>) {
+        if path != Path::new("/") {
+            return self.inner.readdir_deferred(req, path, fh, reply);
+        }
+        self.inner.readdir_deferred(req, path, fh, Responder::new(move |result| {
+            reply.send(result.map(|mut entries: Vec<DirectoryEntry>| {
+                entries.retain(|entry| entry.name != TRASH_DIR);
+                entries
+            }))
+        }))
+    }
+
+    fn readdir_at(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, add: &mut dyn FnMut(&DirectoryEntry, u64) -> bool) -> ResultEmpty {
+        if path != Path::new("/") {
+            return self.inner.readdir_at(req, path, fh, offset, add);
+        }
+        self.inner.readdir_at(req, path, fh, offset, &mut |entry, next| {
+            entry.name == TRASH_DIR || add(entry, next)
+        })
+    }
+}
+
+#[test]
+fn test_trash() {
+    use super::test_fs::{self, MemFs};
+
+    let req = test_fs::req();
+    let root = &FsPath::from("/");
+    let fs = TrashFs::new(MemFs::new()).expire_after(Duration::from_secs(3600));
+    fs.init(req).unwrap();
+
+    write_all(&fs, req, &FsPath::from("/a"), b"first").unwrap();
+    write_all(&fs, req, &FsPath::from("/b"), b"second").unwrap();
+    fs.mkdir(req, root, OsStr::new("d"), 0o755).unwrap();
+    write_all(&fs, req, &FsPath::from("/d/c"), b"third").unwrap();
+
+    // Removing and replacing files moves them to the trash, which isn't listed.
+    fs.unlink(req, root, OsStr::new("a")).unwrap();
+    fs.rename(req, &FsPath::from("/d"), OsStr::new("c"), root, OsStr::new("b")).unwrap();
+    assert_eq!(Err(libc::ENOENT), fs.getattr(req, &FsPath::from("/a"), None).map(drop));
+    assert_eq!(b"third".to_vec(), read_all(&fs, req, &FsPath::from("/b")).unwrap());
+    fs.rmdir(req, root, OsStr::new("d")).unwrap();
+    let names = list_dir(&fs, req, root).unwrap().into_iter().map(|e| e.name).collect::<Vec<_>>();
+    assert_eq!(vec![OsString::from("b")], names);
+
+    let mut entries = fs.list(req).unwrap();
+    entries.sort_by_key(|entry| entry.id.clone());
+    let paths = entries.iter().map(|entry| entry.path.clone()).collect::<Vec<_>>();
+    assert_eq!(vec![PathBuf::from("/a"), PathBuf::from("/b"), PathBuf::from("/d")], paths);
+    let trashed = FsPath::from(trash_path("files").join(&entries[1].id));
+    assert_eq!(b"second".to_vec(), read_all(&fs, req, &trashed).unwrap());
+
+    // Purging.
+    assert_eq!(0, fs.purge_expired(req).unwrap());
+    fs.unlink(req, &trash_path("files"), &entries[0].id).unwrap();
+    assert_eq!(2, fs.list(req).unwrap().len());
+    fs.purge(req, &entries[1].id).unwrap();
+    assert_eq!(1, fs.purge_all(req).unwrap());
+    assert!(list_dir(&fs, req, &trash_path("files")).unwrap().is_empty());
+    assert!(fs.list(req).unwrap().is_empty());
+}
@@ -0,0 +1,441 @@
+// User View :: a different subtree of another filesystem for each user.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use crate::types::*;
+use crate::types::open_flags::FOPEN_DIRECT_IO;
+
+type ViewFn = dyn Fn(&RequestInfo) -> Option<PathBuf> + Send + Sync;
+
+/// A filesystem which shows each caller only a subtree of an inner filesystem, chosen by a
+/// function of the caller's credentials.
+///
+/// ```ignore
+/// // Each user sees only their own directory under /home as the root of the mount.
+/// let fs = UserViewFs::new(inner, |req| Some(format!("/home/{}", req.uid).into()));
+/// ```
+///
+/// If the function returns `None`, the caller gets `EACCES` for everything.
+///
+/// The kernel (and FuseMT's inode table) only know about the paths visible in the mount, so the
+/// same visible path is the same inode for every user, even though it's a different file in each
+/// view. To keep users from seeing each other's data through the kernel's caches, this wrapper
+/// returns a zero TTL for all entries and attributes, so the kernel looks every path up again on
+/// each access, and opens files with direct I/O, so file data never goes through the shared page
+/// cache. This makes the mount noticeably slower than the inner filesystem on its own.
+pub struct UserViewFs<FS> {
+    inner: FS,
+    view: Box<ViewFn>,
+    /// The view root of each open numeric file handle, so calls on the handle go to the same file
+    /// no matter which process makes them.
+    files: Mutex<HashMap<u64, PathBuf>>,
+    dirs: Mutex<HashMap<u64, PathBuf>>,
+}
+
+impl<FS: FilesystemMT> UserViewFs<FS> {
+    pub fn new(inner: FS, view: impl Fn(&RequestInfo) -> Option<PathBuf> + Send + Sync + 'static)
+        -> UserViewFs<FS>
+    {
+        UserViewFs {
+            inner,
+            view: Box::new(view),
+            files: Mutex::new(HashMap::new()),
+            dirs: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Get the root of the caller's view.
+    fn root(&self, req: &RequestInfo) -> Result<PathBuf, libc::c_int> {
+        (self.view)(req).ok_or(libc::EACCES)
+    }
+
+    /// Translate a path in the caller's view to a path in the inner filesystem.
+    fn map(&self, req: &RequestInfo, path: &Path) -> Result<FsPath, libc::c_int> {
+        Ok(join(&self.root(req)?, path))
+    }
+
+    /// Translate a path for a call on an open handle.
+    fn map_handle(&self, table: &Mutex<HashMap<u64, PathBuf>>, req: &RequestInfo, path: &Path,
+        fh: u64) -> Result<FsPath, libc::c_int>
+    {
+        match table.lock().unwrap().get(&fh) {
+            Some(root) => Ok(join(root, path)),
+            None => self.map(req, path),
+        }
+    }
+}
+
+fn join(root: &Path, path: &Path) -> FsPath {
+    match path.strip_prefix("/") {
+        Ok(rel) if rel.as_os_str().is_empty() => FsPath::from(root),
+        Ok(rel) => FsPath::from(root.join(rel)),
+        Err(_) => FsPath::from(root.join(path)),
+    }
+}
+
+fn uncached(entry: ResultEntry) -> ResultEntry {
+    entry.map(|(_, attr)| (Duration::from_secs(0), attr))
+}
+
+impl<FS: FilesystemMT> FilesystemMT for UserViewFs<FS> {
+    forward_calls!(inner; init, init_connection, destroy, classify, load);
+
+    fn getattr(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>) -> ResultEntry {
+        let path = match fh {
+            Some(fh) => self.map_handle(&self.files, &req, path, fh)?,
+            None => self.map(&req, path)?,
+        };
+        uncached(self.inner.getattr(req, &path, fh))
+    }
+
+    fn getattr_mask(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, mask: AttrMask) -> ResultEntry {
+        let path = match fh {
+            Some(fh) => self.map_handle(&self.files, &req, path, fh)?,
+            None => self.map(&req, path)?,
+        };
+        uncached(self.inner.getattr_mask(req, &path, fh, mask))
+    }
+
+    fn getattr_deferred(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, reply: Responder<(Duration, FileAttr)>) {
+        let path = match fh {
+            Some(fh) => self.map_handle(&self.files, &req, path, fh),
+            None => self.map(&req, path),
+        };
+        match path {
+            Ok(path) => self.inner.getattr_deferred(req, &path, fh,
+                Responder::new(move |result| reply.send(uncached(result)))),
+            Err(e) => reply.send(Err(e)),
+        }
+    }
+
+    fn chmod(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, mode: u32) -> ResultEmpty {
+        let path = self.map(&req, path)?;
+        self.inner.chmod(req, &path, fh, mode)
+    }
+
+    fn chown(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, uid: Option<u32>, gid: Option<u32>) -> ResultEmpty {
+        let path = self.map(&req, path)?;
+        self.inner.chown(req, &path, fh, uid, gid)
+    }
+
+    fn truncate(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, size: u64) -> ResultEmpty {
+        let path = self.map(&req, path)?;
+        self.inner.truncate(req, &path, fh, size)
+    }
+
+    fn utimens(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, atime: Option<SystemTime>, mtime: Option<SystemTime>) -> ResultEmpty {
+        let path = self.map(&req, path)?;
+        self.inner.utimens(req, &path, fh, atime, mtime)
+    }
+
+    fn utimens_macos(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, crtime: Option<SystemTime>, chgtime: Option<SystemTime>, bkuptime: Option<SystemTime>, flags: Option<u32>) -> ResultEmpty {
+        let path = self.map(&req, path)?;
+        self.inner.utimens_macos(req, &path, fh, crtime, chgtime, bkuptime, flags)
+    }
+
+    fn readlink(&self, req: RequestInfo, path: &FsPath) -> ResultData {
+        let path = self.map(&req, path)?;
+        self.inner.readlink(req, &path)
+    }
+
+    fn mknod(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, mode: u32, rdev: u32) -> ResultEntry {
+        let parent = self.map(&req, parent)?;
+        uncached(self.inner.mknod(req, &parent, name, mode, rdev))
+    }
+
+    fn mkdir(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, mode: u32) -> ResultEntry {
+        let parent = self.map(&req, parent)?;
+        uncached(self.inner.mkdir(req, &parent, name, mode))
+    }
+
+    fn unlink(&self, req: RequestInfo, parent: &FsPath, name: &OsStr) -> ResultEmpty {
+        let parent = self.map(&req, parent)?;
+        self.inner.unlink(req, &parent, name)
+    }
+
+    fn rmdir(&self, req: RequestInfo, parent: &FsPath, name: &OsStr) -> ResultEmpty {
+        let parent = self.map(&req, parent)?;
+        self.inner.rmdir(req, &parent, name)
+    }
+
+    fn symlink(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, target: &Path) -> ResultEntry {
+        // The target is left alone: the kernel resolves it relative to the mount, not the view.
+        let parent = self.map(&req, parent)?;
+        uncached(self.inner.symlink(req, &parent, name, target))
+    }
+
+    fn rename(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, newparent: &FsPath, newname: &OsStr) -> ResultEmpty {
+        let parent = self.map(&req, parent)?;
+        let newparent = self.map(&req, newparent)?;
+        self.inner.rename(req, &parent, name, &newparent, newname)
+    }
+
+    fn link(&self, req: RequestInfo, path: &FsPath, newparent: &FsPath, newname: &OsStr) -> ResultEntry {
+        let path = self.map(&req, path)?;
+        let newparent = self.map(&req, newparent)?;
+        uncached(self.inner.link(req, &path, &newparent, newname))
+    }
+
+    fn open(&self, req: RequestInfo, path: &FsPath, flags: u32) -> ResultOpen {
+        let root = self.root(&req)?;
+        let opened = self.inner.open(req, &join(&root, path), flags)?;
+        self.files.lock().unwrap().insert(opened.fh, root);
+        Ok(OpenedFile { direct_io: true, ..opened })
+    }
+
+    fn open_handle(&self, req: RequestInfo, path: &FsPath, flags: u32) -> ResultOpenHandle {
+        // FuseMT routes calls on handle objects straight to them, so there's nothing to remember.
+        let path = self.map(&req, path)?;
+        let (handle, open_flags) = self.inner.open_handle(req, &path, flags)?;
+        Ok((handle, open_flags | FOPEN_DIRECT_IO))
+    }
+
+    fn read(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, size: u32, callback: impl FnOnce(ResultSlice<'_>) -> CallbackResult) -> CallbackResult {
+        match self.map_handle(&self.files, &req, path, fh) {
+            Ok(path) => self.inner.read(req, &path, fh, offset, size, callback),
+            Err(e) => callback(Err(e)),
+        }
+    }
+
+    fn write(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, data: Vec<u8>, flags: u32) -> ResultWrite {
+        let path = self.map_handle(&self.files, &req, path, fh)?;
+        self.inner.write(req, &path, fh, offset, data, flags)
+    }
+
+    fn write_deferred(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, data: Vec<u8>, flags: u32, reply: Responder<u32>) {
+        match self.map_handle(&self.files, &req, path, fh) {
+            Ok(path) => self.inner.write_deferred(req, &path, fh, offset, data, flags, reply),
+            Err(e) => reply.send(Err(e)),
+        }
+    }
+
+    fn getlk(&self, req: RequestInfo, path: &FsPath, fh: u64, lock_owner: LockOwner, lock: FileLock) -> ResultLock {
+        let path = self.map_handle(&self.files, &req, path, fh)?;
+        self.inner.getlk(req, &path, fh, lock_owner, lock)
+    }
+
+    fn setlk(&self, req: RequestInfo, path: &FsPath, fh: u64, lock_owner: LockOwner, lock: FileLock, sleep: bool) -> ResultEmpty {
+        let path = self.map_handle(&self.files, &req, path, fh)?;
+        self.inner.setlk(req, &path, fh, lock_owner, lock, sleep)
+    }
+
+    fn flock(&self, req: RequestInfo, path: &FsPath, fh: u64, lock_owner: LockOwner, operation: i32) -> ResultEmpty {
+        let path = self.map_handle(&self.files, &req, path, fh)?;
+        self.inner.flock(req, &path, fh, lock_owner, operation)
+    }
+
+    fn fallocate(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, length: u64, mode: i32) -> ResultEmpty {
+        let path = self.map_handle(&self.files, &req, path, fh)?;
+        self.inner.fallocate(req, &path, fh, offset, length, mode)
+    }
+
+    fn lseek(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, whence: i32) -> ResultOffset {
+        let path = self.map_handle(&self.files, &req, path, fh)?;
+        self.inner.lseek(req, &path, fh, offset, whence)
+    }
+
+    fn copy_file_range(&self, req: RequestInfo, path_in: &FsPath, fh_in: u64, offset_in: u64, path_out: &FsPath, fh_out: u64, offset_out: u64, len: u64, flags: u32) -> ResultWrite {
+        let path_in = self.map_handle(&self.files, &req, path_in, fh_in)?;
+        let path_out = self.map_handle(&self.files, &req, path_out, fh_out)?;
+        self.inner.copy_file_range(req, &path_in, fh_in, offset_in, &path_out, fh_out, offset_out, len, flags)
+    }
+
+    fn flush(&self, req: RequestInfo, path: &FsPath, fh: u64, lock_owner: LockOwner) -> ResultEmpty {
+        let path = self.map_handle(&self.files, &req, path, fh)?;
+        self.inner.flush(req, &path, fh, lock_owner)
+    }
+
+    fn flush_deferred(&self, req: RequestInfo, path: &FsPath, fh: u64, lock_owner: LockOwner, reply: Responder<()>) {
+        match self.map_handle(&self.files, &req, path, fh) {
+            Ok(path) => self.inner.flush_deferred(req, &path, fh, lock_owner, reply),
+            Err(e) => reply.send(Err(e)),
+        }
+    }
+
+    fn release(&self, req: RequestInfo, path: &FsPath, fh: u64, flags: u32, lock_owner: Option<LockOwner>, flush: bool) -> ResultEmpty {
+        let root = self.files.lock().unwrap().remove(&fh);
+        let path = match root {
+            Some(root) => join(&root, path),
+            None => self.map(&req, path)?,
+        };
+        self.inner.release(req, &path, fh, flags, lock_owner, flush)
+    }
+
+    fn fsync(&self, req: RequestInfo, path: &FsPath, fh: u64, datasync: bool) -> ResultEmpty {
+        let path = self.map_handle(&self.files, &req, path, fh)?;
+        self.inner.fsync(req, &path, fh, datasync)
+    }
+
+    fn fsync_deferred(&self, req: RequestInfo, path: &FsPath, fh: u64, datasync: bool, reply: Responder<()>) {
+        match self.map_handle(&self.files, &req, path, fh) {
+            Ok(path) => self.inner.fsync_deferred(req, &path, fh, datasync, reply),
+            Err(e) => reply.send(Err(e)),
+        }
+    }
+
+    fn sync(&self, _filter: &dyn Fn(&Path) -> bool) -> ResultEmpty {
+        // Without a user to map them for, the inner filesystem's paths can't be matched against
+        // the filter, so sync all of them.
+        self.inner.sync(&|_| true)
+    }
+
+    fn opendir(&self, req: RequestInfo, path: &FsPath, flags: u32) -> ResultOpen {
+        let root = self.root(&req)?;
+        let opened = self.inner.opendir(req, &join(&root, path), flags)?;
+        self.dirs.lock().unwrap().insert(opened.fh, root);
+        Ok(opened)
+    }
+
+    fn opendir_handle(&self, req: RequestInfo, path: &FsPath, flags: u32) -> ResultOpenDirHandle {
+        let path = self.map(&req, path)?;
+        self.inner.opendir_handle(req, &path, flags)
+    }
+
+    fn readdir(&self, req: RequestInfo, path: &FsPath, fh: u64) -> ResultReaddir {
+        let path = self.map_handle(&self.dirs, &req, path, fh)?;
+        self.inner.readdir(req, &path, fh)
+    }
+
+    fn readdir_deferred(&self, req: RequestInfo, path: &FsPath, fh: u64, reply: Responder<Vec<DirectoryEntry>>) {
+        match self.map_handle(&self.dirs, &req, path, fh) {
+            Ok(path) => self.inner.readdir_deferred(req, &path, fh, reply),
+            Err(e) => reply.send(Err(e)),
+        }
+    }
+
+    fn readdir_at(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, add: &mut dyn FnMut(&DirectoryEntry, u64) -> bool) -> ResultEmpty {
+        let path = self.map_handle(&self.dirs, &req, path, fh)?;
+        self.inner.readdir_at(req, &path, fh, offset, add)
+    }
+
+    fn releasedir(&self, req: RequestInfo, path: &FsPath, fh: u64, flags: u32) -> ResultEmpty {
+        let root = self.dirs.lock().unwrap().remove(&fh);
+        let path = match root {
+            Some(root) => join(&root, path),
+            None => self.map(&req, path)?,
+        };
+        self.inner.releasedir(req, &path, fh, flags)
+    }
+
+    fn fsyncdir(&self, req: RequestInfo, path: &FsPath, fh: u64, datasync: bool) -> ResultEmpty {
+        let path = self.map_handle(&self.dirs, &req, path, fh)?;
+        self.inner.fsyncdir(req, &path, fh, datasync)
+    }
+
+    fn statfs(&self, req: RequestInfo, path: &FsPath) -> ResultStatfs {
+        let path = self.map(&req, path)?;
+        self.inner.statfs(req, &path)
+    }
+
+    fn quota(&self, req: RequestInfo, path: &FsPath) -> ResultQuota {
+        let path = self.map(&req, path)?;
+        self.inner.quota(req, &path)
+    }
+
+    fn setxattr(&self, req: RequestInfo, path: &FsPath, name: &OsStr, value: &[u8], flags: u32, position: XattrPosition) -> ResultEmpty {
+        let path = self.map(&req, path)?;
+        self.inner.setxattr(req, &path, name, value, flags, position)
+    }
+
+    fn getxattr(&self, req: RequestInfo, path: &FsPath, name: &OsStr, size: u32) -> ResultXattr {
+        let path = self.map(&req, path)?;
+        self.inner.getxattr(req, &path, name, size)
+    }
+
+    fn listxattr(&self, req: RequestInfo, path: &FsPath, size: u32) -> ResultXattr {
+        let path = self.map(&req, path)?;
+        self.inner.listxattr(req, &path, size)
+    }
+
+    fn removexattr(&self, req: RequestInfo, path: &FsPath, name: &OsStr) -> ResultEmpty {
+        let path = self.map(&req, path)?;
+        self.inner.removexattr(req, &path, name)
+    }
+
+    fn access(&self, req: RequestInfo, path: &FsPath, mask: u32) -> ResultEmpty {
+        let path = self.map(&req, path)?;
+        self.inner.access(req, &path, mask)
+    }
+
+    fn create(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, mode: u32, flags: u32) -> ResultCreate {
+        let root = self.root(&req)?;
+        let mut created = self.inner.create(req, &join(&root, parent), name, mode, flags)?;
+        self.files.lock().unwrap().insert(created.fh, root);
+        created.ttl = Duration::from_secs(0);
+        created.flags |= FOPEN_DIRECT_IO;
+        Ok(created)
+    }
+
+    fn create_handle(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, mode: u32, flags: u32) -> ResultCreateHandle {
+        let parent = self.map(&req, parent)?;
+        let mut created = self.inner.create_handle(req, &parent, name, mode, flags)?;
+        created.ttl = Duration::from_secs(0);
+        created.flags |= FOPEN_DIRECT_IO;
+        Ok(created)
+    }
+
+    #[cfg(target_os = "macos")]
+    fn setvolname(&self, req: RequestInfo, name: &OsStr) -> ResultEmpty {
+        self.inner.setvolname(req, name)
+    }
+
+    #[cfg(target_os = "macos")]
+    fn exchange(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, newparent: &FsPath, newname: &OsStr, options: u64) -> ResultEmpty {
+        let parent = self.map(&req, parent)?;
+        let newparent = self.map(&req, newparent)?;
+        self.inner.exchange(req, &parent, name, &newparent, newname, options)
+    }
+
+    #[cfg(target_os = "macos")]
+    fn getxtimes(&self, req: RequestInfo, path: &FsPath) -> ResultXTimes {
+        let path = self.map(&req, path)?;
+        self.inner.getxtimes(req, &path)
+    }
+
+    #[cfg(target_os = "macos")]
+    fn setxtimes(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, bkuptime: Option<SystemTime>, crtime: Option<SystemTime>) -> ResultEmpty {
+        let path = self.map(&req, path)?;
+        self.inner.setxtimes(req, &path, fh, bkuptime, crtime)
+    }
+}
+
+#[test]
+fn test_user_view() {
+    struct Fs;
+    impl FilesystemMT for Fs {
+        fn readlink(&self, _req: RequestInfo, path: &FsPath) -> ResultData {
+            Ok(path.as_os_str().to_str().unwrap().as_bytes().to_vec())
+        }
+        fn open(&self, _req: RequestInfo, path: &FsPath, _flags: u32) -> ResultOpen {
+            Ok(OpenedFile::new(if path.starts_with("/home/1000") { 1 } else { 2 }))
+        }
+        fn flush(&self, _req: RequestInfo, path: &FsPath, _fh: u64, _lock_owner: LockOwner) -> ResultEmpty {
+            assert_eq!(Path::new("/home/1000/f"), path);
+            Ok(())
+        }
+    }
+
+    let fs = UserViewFs::new(Fs, |req| {
+        if req.uid == 0 {
+            None
+        } else {
+            Some(format!("/home/{}", req.uid).into())
+        }
+    });
+    let req = |uid| RequestInfo { unique: 0, uid, gid: uid, pid: 1, node: None, umask: None, lock_owner: None };
+
+    assert_eq!(Ok(b"/home/1000/a/b".to_vec()), fs.readlink(req(1000), &FsPath::from("/a/b")));
+    assert_eq!(Ok(b"/home/1001".to_vec()), fs.readlink(req(1001), &FsPath::from("/")));
+    assert_eq!(Err(libc::EACCES), fs.readlink(req(0), &FsPath::from("/a")));
+
+    // Open files stay in the view of whoever opened them.
+    assert_eq!(Ok(OpenedFile { direct_io: true, ..OpenedFile::new(1) }), fs.open(req(1000), &FsPath::from("/f"), 0));
+    assert_eq!(Ok(()), fs.flush(req(0), &FsPath::from("/f"), 1, LockOwner::from(0)));
+}
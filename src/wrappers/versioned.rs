@@ -0,0 +1,377 @@
+// Versioned :: keeping the previous contents of files when they're changed.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+use std::collections::HashSet;
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use crate::types::*;
+use super::{list_dir, read_all, write_all};
+
+/// Name of the versions directory, at the root of the filesystem.
+const VERSIONS_DIR: &str = ".versions";
+
+/// A filesystem which saves a copy of a file's contents before each time it's changed, and makes
+/// the copies available in a read-only `.versions` directory.
+///
+/// The old versions of `/some/dir/file` are `/.versions/some/dir/file;1`, `file;2`, and so on,
+/// numbered from oldest to newest. A copy is made the first time a file is written to or
+/// truncated through each open handle, so a program which opens a file, writes it in many pieces,
+/// and closes it makes only one version. Truncating by path, and opening with `O_TRUNC`, also
+/// make a copy. Empty files aren't copied.
+///
+/// Old versions are kept after a file is renamed or deleted, under its old name. The versions
+/// directory is left out of the listing of the root directory, but can be looked up and browsed by
+/// name.
+pub struct VersionedFs<FS> {
+    inner: FS,
+    keep: Option<usize>,
+    /// Handles which have already had a version made for them.
+    copied: Mutex<HashSet<u64>>,
+    /// Held while making a version, so concurrent ones get different numbers.
+    numbering: Mutex<()>,
+}
+
+impl<FS: FilesystemMT> VersionedFs<FS> {
+    pub fn new(inner: FS) -> VersionedFs<FS> {
+        VersionedFs {
+            inner,
+            keep: None,
+            copied: Mutex::new(HashSet::new()),
+            numbering: Mutex::new(()),
+        }
+    }
+
+    /// Keep at most this many old versions of each file, deleting the oldest ones beyond that.
+    /// By default, all versions are kept.
+    pub fn keep(mut self, count: usize) -> VersionedFs<FS> {
+        self.keep = Some(count);
+        self
+    }
+
+    /// Save the current contents of a file as a new version.
+    fn save_version(&self, req: RequestInfo, path: &FsPath) -> ResultEmpty {
+        let (_, attr) = self.inner.getattr(req, path, None)?;
+        if attr.kind != crate::FileType::RegularFile || attr.size == 0 {
+            return Ok(());
+        }
+        let (parent, name) = match (path.parent(), path.file_name()) {
+            (Some(parent), Some(name)) => (parent, name),
+            _ => return Ok(()),
+        };
+        let data = read_all(&self.inner, req, path)?;
+
+        let _numbering = self.numbering.lock().unwrap();
+        let dir = FsPath::from(versions_path(parent));
+        self.make_dirs(req, &dir)?;
+        let mut versions = list_dir(&self.inner, req, &dir)?
+            .into_iter()
+            .filter_map(|entry| version_number(&entry.name, name))
+            .collect::<Vec<_>>();
+        versions.sort_unstable();
+        let number = versions.last().map_or(1, |n| n + 1);
+        let version = FsPath::from(dir.join(version_name(name, number)));
+        write_all(&self.inner, req, &version, &data)?;
+        debug!("saved {:?} as version {}", path, number);
+
+        if let Some(keep) = self.keep {
+            let excess = (versions.len() + 1).saturating_sub(keep);
+            for &old in &versions[.. excess.min(versions.len())] {
+                if let Err(e) = self.inner.unlink(req, &dir, &version_name(name, old)) {
+                    warn!("failed to delete version {} of {:?}: {}", old, path, e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Save a version of a file for an open handle, unless one was already saved for it.
+    fn save_version_once(&self, req: RequestInfo, path: &FsPath, fh: u64) -> ResultEmpty {
+        if !self.copied.lock().unwrap().insert(fh) {
+            return Ok(());
+        }
+        let result = self.save_version(req, path);
+        if result.is_err() {
+            self.copied.lock().unwrap().remove(&fh);
+        }
+        result
+    }
+
+    /// Create a directory and any missing parents of it.
+    fn make_dirs(&self, req: RequestInfo, dir: &Path) -> ResultEmpty {
+        let mut path = PathBuf::from("/");
+        for component in dir.iter().skip(1) {
+            match self.inner.mkdir(req, &FsPath::from(path.as_path()), component, 0o755) {
+                Ok(_) | Err(libc::EEXIST) => (),
+                Err(e) => return Err(e),
+            }
+            path.push(component);
+        }
+        Ok(())
+    }
+}
+
+/// Where the versions of files in the given directory are kept.
+fn versions_path(dir: &Path) -> PathBuf {
+    let root = Path::new("/").join(VERSIONS_DIR);
+    match dir.strip_prefix("/") {
+        Ok(rel) => root.join(rel),
+        Err(_) => root.join(dir),
+    }
+}
+
+fn in_versions(path: &Path) -> bool {
+    path.starts_with(Path::new("/").join(VERSIONS_DIR))
+}
+
+/// Fail with `EROFS` if the path is in the versions directory.
+fn writable(path: &Path) -> ResultEmpty {
+    if in_versions(path) {
+        Err(libc::EROFS)
+    } else {
+        Ok(())
+    }
+}
+
+fn version_name(name: &OsStr, number: u64) -> OsString {
+    let mut version = name.to_owned();
+    version.push(format!(";{}", number));
+    version
+}
+
+/// If `entry` is a version of the file `name`, get its number.
+fn version_number(entry: &OsStr, name: &OsStr) -> Option<u64> {
+    let suffix = entry.as_bytes().strip_prefix(name.as_bytes())?.strip_prefix(b";")?;
+    std::str::from_utf8(suffix).ok()?.parse().ok()
+}
+
+impl<FS: FilesystemMT> FilesystemMT for VersionedFs<FS> {
+    forward_calls!(inner; destroy, getattr, getattr_mask, getattr_deferred, readlink, read, lseek,
+        flush, getlk, setlk, flock, flush_deferred, fsync, fsync_deferred, sync, opendir, releasedir,
+        fsyncdir, statfs, quota, getxattr, listxattr, access, getxtimes, setvolname, classify, load);
+
+    fn init(&self, req: RequestInfo) -> ResultEmpty {
+        self.inner.init(req)?;
+        match self.inner.mkdir(req, &FsPath::from("/"), OsStr::new(VERSIONS_DIR), 0o755) {
+            Ok(_) | Err(libc::EEXIST) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn chmod(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, mode: u32) -> ResultEmpty {
+        writable(path)?;
+        self.inner.chmod(req, path, fh, mode)
+    }
+
+    fn chown(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, uid: Option<u32>, gid: Option<u32>) -> ResultEmpty {
+        writable(path)?;
+        self.inner.chown(req, path, fh, uid, gid)
+    }
+
+    fn truncate(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, size: u64) -> ResultEmpty {
+        writable(path)?;
+        match fh {
+            Some(fh) => self.save_version_once(req, path, fh)?,
+            None => self.save_version(req, path)?,
+        }
+        self.inner.truncate(req, path, fh, size)
+    }
+
+    fn utimens(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, atime: Option<SystemTime>, mtime: Option<SystemTime>) -> ResultEmpty {
+        writable(path)?;
+        self.inner.utimens(req, path, fh, atime, mtime)
+    }
+
+    fn utimens_macos(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, crtime: Option<SystemTime>, chgtime: Option<SystemTime>, bkuptime: Option<SystemTime>, flags: Option<u32>) -> ResultEmpty {
+        writable(path)?;
+        self.inner.utimens_macos(req, path, fh, crtime, chgtime, bkuptime, flags)
+    }
+
+    #[cfg(target_os = "macos")]
+    fn setxtimes(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, bkuptime: Option<SystemTime>, crtime: Option<SystemTime>) -> ResultEmpty {
+        writable(path)?;
+        self.inner.setxtimes(req, path, fh, bkuptime, crtime)
+    }
+
+    fn mknod(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, mode: u32, rdev: u32) -> ResultEntry {
+        writable(parent)?;
+        self.inner.mknod(req, parent, name, mode, rdev)
+    }
+
+    fn mkdir(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, mode: u32) -> ResultEntry {
+        writable(parent)?;
+        self.inner.mkdir(req, parent, name, mode)
+    }
+
+    fn unlink(&self, req: RequestInfo, parent: &FsPath, name: &OsStr) -> ResultEmpty {
+        writable(parent)?;
+        self.inner.unlink(req, parent, name)
+    }
+
+    fn rmdir(&self, req: RequestInfo, parent: &FsPath, name: &OsStr) -> ResultEmpty {
+        writable(&parent.join(name))?;
+        self.inner.rmdir(req, parent, name)
+    }
+
+    fn symlink(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, target: &Path) -> ResultEntry {
+        writable(parent)?;
+        self.inner.symlink(req, parent, name, target)
+    }
+
+    fn rename(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, newparent: &FsPath, newname: &OsStr) -> ResultEmpty {
+        writable(&parent.join(name))?;
+        writable(&newparent.join(newname))?;
+        self.inner.rename(req, parent, name, newparent, newname)
+    }
+
+    #[cfg(target_os = "macos")]
+    fn exchange(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, newparent: &FsPath, newname: &OsStr, options: u64) -> ResultEmpty {
+        writable(&parent.join(name))?;
+        writable(&newparent.join(newname))?;
+        self.inner.exchange(req, parent, name, newparent, newname, options)
+    }
+
+    fn link(&self, req: RequestInfo, path: &FsPath, newparent: &FsPath, newname: &OsStr) -> ResultEntry {
+        writable(path)?;
+        writable(newparent)?;
+        self.inner.link(req, path, newparent, newname)
+    }
+
+    fn open(&self, req: RequestInfo, path: &FsPath, flags: u32) -> ResultOpen {
+        let write = flags & libc::O_ACCMODE as u32 != libc::O_RDONLY as u32;
+        let trunc = flags & libc::O_TRUNC as u32 != 0;
+        if write || trunc {
+            writable(path)?;
+        }
+        if trunc {
+            self.save_version(req, path)?;
+        }
+        self.inner.open(req, path, flags)
+    }
+
+    fn open_handle(&self, _req: RequestInfo, _path: &FsPath, _flags: u32) -> ResultOpenHandle {
+        // Writes to handle objects from the inner filesystem wouldn't make versions.
+        Err(libc::ENOSYS)
+    }
+
+    fn write(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, data: Vec<u8>, flags: u32) -> ResultWrite {
+        self.save_version_once(req, path, fh)?;
+        self.inner.write(req, path, fh, offset, data, flags)
+    }
+
+    fn write_deferred(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, data: Vec<u8>, flags: u32, reply: Responder<u32>) {
+        if let Err(e) = self.save_version_once(req, path, fh) {
+            return reply.send(Err(e));
+        }
+        self.inner.write_deferred(req, path, fh, offset, data, flags, reply)
+    }
+
+    fn release(&self, req: RequestInfo, path: &FsPath, fh: u64, flags: u32, lock_owner: Option<LockOwner>, flush: bool) -> ResultEmpty {
+        self.copied.lock().unwrap().remove(&fh);
+        self.inner.release(req, path, fh, flags, lock_owner, flush)
+    }
+
+    fn opendir_handle(&self, _req: RequestInfo, _path: &FsPath, _flags: u32) -> ResultOpenDirHandle {
+        // Directory handles from the inner filesystem would list the versions directory.
+        Err(libc::ENOSYS)
+    }
+
+    fn readdir(&self, req: RequestInfo, path: &FsPath, fh: u64) -> ResultReaddir {
+        let mut entries = self.inner.readdir(req, path, fh)?;
+        if path == Path::new("/") {
+            entries.retain(|entry| entry.name != VERSIONS_DIR);
+        }
+        Ok(entries)
+    }
+
+    fn readdir_deferred(&self, req: RequestInfo, path: &FsPath, fh: u64, reply: Responder<Vec<DirectoryEntry>>) {
+        if path != Path::new("/") {
+            return self.inner.readdir_deferred(req, path, fh, reply);
+        }
+        self.inner.readdir_deferred(req, path, fh, Responder::new(move |result| {
+            reply.send(result.map(|mut entries: Vec<DirectoryEntry>| {
+                entries.retain(|entry| entry.name != VERSIONS_DIR);
+                entries
+            }))
+        }))
+    }
+
+    fn readdir_at(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, add: &mut dyn FnMut(&DirectoryEntry, u64) -> bool) -> ResultEmpty {
+        if path != Path::new("/") {
+            return self.inner.readdir_at(req, path, fh, offset, add);
+        }
+        self.inner.readdir_at(req, path, fh, offset, &mut |entry, next| {
+            entry.name == VERSIONS_DIR || add(entry, next)
+        })
+    }
+
+    fn setxattr(&self, req: RequestInfo, path: &FsPath, name: &OsStr, value: &[u8], flags: u32, position: XattrPosition) -> ResultEmpty {
+        writable(path)?;
+        self.inner.setxattr(req, path, name, value, flags, position)
+    }
+
+    fn removexattr(&self, req: RequestInfo, path: &FsPath, name: &OsStr) -> ResultEmpty {
+        writable(path)?;
+        self.inner.removexattr(req, path, name)
+    }
+
+    fn create(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, mode: u32, flags: u32) -> ResultCreate {
+        writable(parent)?;
+        self.inner.create(req, parent, name, mode, flags)
+    }
+
+    fn create_handle(&self, _req: RequestInfo, _parent: &FsPath, _name: &OsStr, _mode: u32, _flags: u32) -> ResultCreateHandle {
+        Err(libc::ENOSYS)
+    }
+}
+
+#[test]
+fn test_versioned() {
+    use super::test_fs::{self, MemFs};
+
+    let req = test_fs::req();
+    let path = &FsPath::from("/d/f");
+    let fs = VersionedFs::new(MemFs::new()).keep(2);
+    fs.init(req).unwrap();
+    fs.mkdir(req, &FsPath::from("/"), OsStr::new("d"), 0o755).unwrap();
+
+    let write = |data: &[u8]| {
+        let fh = fs.open(req, path, libc::O_WRONLY as u32).unwrap().fh;
+        fs.truncate(req, path, Some(fh), 0).unwrap();
+        fs.write(req, path, fh, 0, data[.. 2].to_vec(), 0).unwrap();
+        fs.write(req, path, fh, 2, data[2 ..].to_vec(), 0).unwrap();
+        fs.release(req, path, fh, 0, None, true).unwrap();
+    };
+    let versions = || {
+        list_dir(&fs, req, &FsPath::from("/.versions/d")).unwrap_or_default()
+            .into_iter()
+            .map(|entry| entry.name.into_string().unwrap())
+            .collect::<Vec<_>>()
+    };
+
+    fs.create(req, &FsPath::from("/d"), OsStr::new("f"), 0o644, 0).unwrap();
+    write(b"one");
+    assert!(versions().is_empty());
+    write(b"two");
+    assert_eq!(vec!["f;1"], versions());
+    fs.truncate(req, path, None, 0).unwrap();
+    write(b"three");
+    assert_eq!(vec!["f;1", "f;2"], versions());
+    write(b"four");
+    assert_eq!(vec!["f;2", "f;3"], versions());
+    assert_eq!(b"two".to_vec(), read_all(&fs, req, &FsPath::from("/.versions/d/f;2")).unwrap());
+    assert_eq!(b"three".to_vec(), read_all(&fs, req, &FsPath::from("/.versions/d/f;3")).unwrap());
+    assert_eq!(b"four".to_vec(), read_all(&fs, req, path).unwrap());
+
+    // The history is read-only, and hidden from the root listing.
+    let version = &FsPath::from("/.versions/d/f;2");
+    assert_eq!(Err(libc::EROFS), fs.open(req, version, libc::O_RDWR as u32));
+    assert_eq!(Err(libc::EROFS), fs.unlink(req, &FsPath::from("/.versions/d"), OsStr::new("f;2")));
+    let names = list_dir(&fs, req, &FsPath::from("/")).unwrap().into_iter().map(|e| e.name).collect::<Vec<_>>();
+    assert_eq!(vec![OsString::from("d")], names);
+}
@@ -0,0 +1,303 @@
+// Worm :: files which can't be changed once they've been written.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use crate::types::*;
+
+/// A write-once, read-many filesystem: files can be created and written, but once the handle
+/// they were created with is closed, they're sealed, and can't be changed, truncated, renamed, or
+/// deleted. Attempts to fail with `EPERM`.
+///
+/// Sealing covers a file's metadata and extended attributes as well as its contents. Directories
+/// aren't sealed: they can be created, changed, and removed once they're empty, but not renamed,
+/// since that would move the files in them. Files which already existed when the filesystem was
+/// mounted are sealed from the start.
+///
+/// With `retain_for`, sealed files can be deleted (but still not changed) once the retention
+/// period has passed since they were last modified.
+pub struct WormFs<FS> {
+    inner: FS,
+    retention: Option<Duration>,
+    /// Files which were created and haven't been closed yet, with the number of handles open for
+    /// writing to each.
+    unsealed: Mutex<HashMap<FsPath, usize>>,
+}
+
+impl<FS: FilesystemMT> WormFs<FS> {
+    pub fn new(inner: FS) -> WormFs<FS> {
+        WormFs {
+            inner,
+            retention: None,
+            unsealed: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Let sealed files be deleted once they haven't been modified for this long. Without this,
+    /// they can never be deleted.
+    pub fn retain_for(mut self, retention: Duration) -> WormFs<FS> {
+        self.retention = Some(retention);
+        self
+    }
+
+    fn is_unsealed(&self, path: &Path) -> bool {
+        self.unsealed.lock().unwrap().contains_key(path)
+    }
+
+    /// Check that an entry may be changed: it's a file which hasn't been sealed yet, or a
+    /// directory.
+    fn check_mutable(&self, req: RequestInfo, path: &FsPath) -> ResultEmpty {
+        if self.is_unsealed(path) {
+            return Ok(());
+        }
+        let (_, attr) = self.inner.getattr(req, path, None)?;
+        if attr.kind == crate::FileType::Directory {
+            return Ok(());
+        }
+        debug!("{:?} is sealed", path);
+        Err(libc::EPERM)
+    }
+
+    /// Check that a file may be deleted, or replaced by a rename: it hasn't been sealed yet, or
+    /// its retention period is over.
+    fn check_deletable(&self, req: RequestInfo, path: &FsPath) -> ResultEmpty {
+        if self.is_unsealed(path) {
+            return Ok(());
+        }
+        let (_, attr) = self.inner.getattr(req, path, None)?;
+        let expired = self.retention.is_some_and(|retention| {
+            attr.mtime + retention <= SystemTime::now()
+        });
+        if expired {
+            return Ok(());
+        }
+        debug!("{:?} is sealed and still under retention", path);
+        Err(libc::EPERM)
+    }
+
+    /// Count one more handle open for writing to an unsealed file.
+    fn opened(&self, path: &FsPath) {
+        *self.unsealed.lock().unwrap().entry(path.clone()).or_insert(0) += 1;
+    }
+}
+
+/// Whether a file opened with the given flags can be changed through the handle.
+fn is_writable(flags: u32) -> bool {
+    let flags = flags as libc::c_int;
+    flags & libc::O_ACCMODE != libc::O_RDONLY || flags & libc::O_TRUNC != 0
+}
+
+impl<FS: FilesystemMT> FilesystemMT for WormFs<FS> {
+    forward_calls!(inner; init, init_connection, destroy, getattr, getattr_mask, getattr_deferred,
+        readlink, mknod, mkdir, symlink, link, read, lseek, write, write_deferred, flush,
+        flush_deferred, getlk, setlk, flock, last_release_after_unlink, fsync, fsync_deferred, sync,
+        opendir, opendir_handle, readdir, readdir_deferred, readdir_at, releasedir, fsyncdir, statfs,
+        quota, getxattr, listxattr, access, getxtimes, setvolname, classify, load);
+
+    fn chmod(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, mode: u32) -> ResultEmpty {
+        self.check_mutable(req, path)?;
+        self.inner.chmod(req, path, fh, mode)
+    }
+
+    fn chown(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, uid: Option<u32>, gid: Option<u32>) -> ResultEmpty {
+        self.check_mutable(req, path)?;
+        self.inner.chown(req, path, fh, uid, gid)
+    }
+
+    fn truncate(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, size: u64) -> ResultEmpty {
+        self.check_mutable(req, path)?;
+        self.inner.truncate(req, path, fh, size)
+    }
+
+    fn utimens(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, atime: Option<SystemTime>, mtime: Option<SystemTime>) -> ResultEmpty {
+        self.check_mutable(req, path)?;
+        self.inner.utimens(req, path, fh, atime, mtime)
+    }
+
+    fn utimens_macos(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, crtime: Option<SystemTime>, chgtime: Option<SystemTime>, bkuptime: Option<SystemTime>, flags: Option<u32>) -> ResultEmpty {
+        self.check_mutable(req, path)?;
+        self.inner.utimens_macos(req, path, fh, crtime, chgtime, bkuptime, flags)
+    }
+
+    fn unlink(&self, req: RequestInfo, parent: &FsPath, name: &OsStr) -> ResultEmpty {
+        let path = FsPath::from(parent.join(name));
+        self.check_deletable(req, &path)?;
+        self.inner.unlink(req, parent, name)?;
+        self.unsealed.lock().unwrap().remove(&path);
+        Ok(())
+    }
+
+    fn rmdir(&self, req: RequestInfo, parent: &FsPath, name: &OsStr) -> ResultEmpty {
+        // Directories can only be removed once they're empty, so this can't delete sealed files.
+        self.inner.rmdir(req, parent, name)
+    }
+
+    fn rename(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, newparent: &FsPath, newname: &OsStr) -> ResultEmpty {
+        let (from, to) = (parent.join(name), FsPath::from(newparent.join(newname)));
+        if !self.is_unsealed(&from) {
+            debug!("{:?} is sealed or a directory; not renaming it", from);
+            return Err(libc::EPERM);
+        }
+        match self.check_deletable(req, &to) {
+            Ok(()) | Err(libc::ENOENT) => (),
+            Err(e) => return Err(e),
+        }
+        self.inner.rename(req, parent, name, newparent, newname)?;
+        let mut unsealed = self.unsealed.lock().unwrap();
+        unsealed.remove(&to);
+        if let Some(count) = unsealed.remove(from.as_path()) {
+            unsealed.insert(to, count);
+        }
+        Ok(())
+    }
+
+    #[cfg(target_os = "macos")]
+    fn exchange(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, newparent: &FsPath, newname: &OsStr, options: u64) -> ResultEmpty {
+        // Both files' contents change, so neither can be sealed.
+        let (a, b) = (parent.join(name), newparent.join(newname));
+        for path in [&a, &b] {
+            if !self.is_unsealed(path) {
+                debug!("{:?} is sealed or a directory; not exchanging it", path);
+                return Err(libc::EPERM);
+            }
+        }
+        self.inner.exchange(req, parent, name, newparent, newname, options)?;
+        let mut unsealed = self.unsealed.lock().unwrap();
+        let (count_a, count_b) = (unsealed.remove(&a), unsealed.remove(&b));
+        for (count, path) in [(count_a, b), (count_b, a)] {
+            if let Some(count) = count {
+                unsealed.insert(path, count);
+            }
+        }
+        Ok(())
+    }
+
+    fn open(&self, req: RequestInfo, path: &FsPath, flags: u32) -> ResultOpen {
+        if !is_writable(flags) {
+            return self.inner.open(req, path, flags);
+        }
+        self.check_mutable(req, path)?;
+        let opened = self.inner.open(req, path, flags)?;
+        self.opened(path);
+        Ok(opened)
+    }
+
+    fn open_handle(&self, _req: RequestInfo, _path: &FsPath, _flags: u32) -> ResultOpenHandle {
+        // Handle objects from the inner filesystem would know nothing about sealing.
+        Err(libc::ENOSYS)
+    }
+
+    fn release(&self, req: RequestInfo, path: &FsPath, fh: u64, flags: u32, lock_owner: Option<LockOwner>, flush: bool) -> ResultEmpty {
+        let result = self.inner.release(req, path, fh, flags, lock_owner, flush);
+        if is_writable(flags) {
+            let mut unsealed = self.unsealed.lock().unwrap();
+            if let Some(count) = unsealed.get_mut(path) {
+                *count -= 1;
+                if *count == 0 {
+                    unsealed.remove(path);
+                    debug!("sealed {:?}", path);
+                }
+            }
+        }
+        result
+    }
+
+    fn setxattr(&self, req: RequestInfo, path: &FsPath, name: &OsStr, value: &[u8], flags: u32, position: XattrPosition) -> ResultEmpty {
+        self.check_mutable(req, path)?;
+        self.inner.setxattr(req, path, name, value, flags, position)
+    }
+
+    fn removexattr(&self, req: RequestInfo, path: &FsPath, name: &OsStr) -> ResultEmpty {
+        self.check_mutable(req, path)?;
+        self.inner.removexattr(req, path, name)
+    }
+
+    fn create(&self, req: RequestInfo, parent: &FsPath, name: &OsStr, mode: u32, flags: u32) -> ResultCreate {
+        let path = FsPath::from(parent.join(name));
+        // Creating with O_TRUNC, without O_EXCL, opens an existing file and empties it.
+        if flags as libc::c_int & libc::O_EXCL == 0 {
+            match self.check_mutable(req, &path) {
+                Ok(()) | Err(libc::ENOENT) => (),
+                Err(e) => return Err(e),
+            }
+        }
+        let created = self.inner.create(req, parent, name, mode, flags)?;
+        self.opened(&path);
+        Ok(created)
+    }
+
+    fn create_handle(&self, _req: RequestInfo, _parent: &FsPath, _name: &OsStr, _mode: u32, _flags: u32) -> ResultCreateHandle {
+        Err(libc::ENOSYS)
+    }
+
+    fn fallocate(&self, req: RequestInfo, path: &FsPath, fh: u64, offset: u64, length: u64, mode: i32) -> ResultEmpty {
+        self.check_mutable(req, path)?;
+        self.inner.fallocate(req, path, fh, offset, length, mode)
+    }
+
+    fn copy_file_range(&self, req: RequestInfo, path_in: &FsPath, fh_in: u64, offset_in: u64, path_out: &FsPath, fh_out: u64, offset_out: u64, len: u64, flags: u32) -> ResultWrite {
+        self.check_mutable(req, path_out)?;
+        self.inner.copy_file_range(req, path_in, fh_in, offset_in, path_out, fh_out, offset_out, len, flags)
+    }
+
+    #[cfg(target_os = "macos")]
+    fn setxtimes(&self, req: RequestInfo, path: &FsPath, fh: Option<u64>, bkuptime: Option<SystemTime>, crtime: Option<SystemTime>) -> ResultEmpty {
+        self.check_mutable(req, path)?;
+        self.inner.setxtimes(req, path, fh, bkuptime, crtime)
+    }
+}
+
+#[test]
+fn test_worm() {
+    use super::test_fs::{self, MemFs};
+    use super::{read_all, write_all};
+
+    let req = test_fs::req();
+    let root = &FsPath::from("/");
+    let fs = WormFs::new(MemFs::new());
+    let rw = libc::O_RDWR as u32;
+
+    // A file can be written until the handle it was created with is closed.
+    let fh = fs.create(req, root, OsStr::new("a"), 0o644, rw).unwrap().fh;
+    fs.write(req, &FsPath::from("/a"), fh, 0, b"archived!".to_vec(), 0).unwrap();
+    fs.rename(req, root, OsStr::new("a"), root, OsStr::new("b")).unwrap();
+    let b = &FsPath::from("/b");
+    fs.truncate(req, b, Some(fh), 8).unwrap();
+    fs.release(req, b, fh, rw, None, true).unwrap();
+
+    // Then it's sealed.
+    assert_eq!(b"archived".to_vec(), read_all(&fs, req, b).unwrap());
+    assert_eq!(Err(libc::EPERM), fs.open(req, b, libc::O_WRONLY as u32));
+    assert_eq!(Err(libc::EPERM), fs.open(req, b, (libc::O_RDONLY | libc::O_TRUNC) as u32));
+    assert_eq!(Err(libc::EPERM), fs.truncate(req, b, None, 0));
+    assert_eq!(Err(libc::EPERM), fs.chmod(req, b, None, 0o644));
+    assert_eq!(Err(libc::EPERM), fs.unlink(req, root, OsStr::new("b")));
+    assert_eq!(Err(libc::EPERM), fs.rename(req, root, OsStr::new("b"), root, OsStr::new("c")));
+    assert_eq!(Err(libc::EPERM), write_all(&fs, req, b, b"overwritten"));
+    assert_eq!(Err(libc::EPERM), fs.create(req, root, OsStr::new("b"), 0o644, rw).map(drop));
+
+    // Nor can it be replaced by renaming another file over it.
+    let fh = fs.create(req, root, OsStr::new("c"), 0o644, rw).unwrap().fh;
+    assert_eq!(Err(libc::EPERM), fs.rename(req, root, OsStr::new("c"), root, OsStr::new("b")));
+    fs.release(req, &FsPath::from("/c"), fh, rw, None, true).unwrap();
+    assert_eq!(b"archived".to_vec(), read_all(&fs, req, b).unwrap());
+
+    // Directories aren't sealed, but can't be moved.
+    fs.mkdir(req, root, OsStr::new("d"), 0o755).unwrap();
+    assert_eq!(Ok(()), fs.check_mutable(req, &FsPath::from("/d")));
+    assert_eq!(Err(libc::EPERM), fs.rename(req, root, OsStr::new("d"), root, OsStr::new("e")));
+    fs.rmdir(req, root, OsStr::new("d")).unwrap();
+
+    // Once the retention period is over, sealed files can be deleted, but still not changed. (The
+    // test filesystem's files were all last modified in 1970.)
+    let fs = fs.retain_for(Duration::from_secs(3600));
+    assert_eq!(Err(libc::EPERM), fs.truncate(req, b, None, 0));
+    fs.unlink(req, root, OsStr::new("b")).unwrap();
+    assert_eq!(Err(libc::ENOENT), fs.getattr(req, b, None).map(drop));
+}
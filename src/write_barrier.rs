@@ -0,0 +1,77 @@
+// Write Barrier :: making syncs wait for earlier writes.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+use std::collections::HashMap;
+use std::sync::{Condvar, Mutex};
+
+/// Counts the writes in progress on each file handle, so that a flush or fsync can wait for the
+/// writes which were dispatched before it to finish.
+///
+/// Writes are counted by `begin` on the thread which dispatches requests, before being handed to
+/// the thread pool, so by the time a sync is dispatched, every earlier write is already counted.
+#[derive(Debug, Default)]
+pub struct WriteBarrier {
+    in_flight: Mutex<HashMap<u64, usize>>,
+    done: Condvar,
+}
+
+impl WriteBarrier {
+    pub fn new() -> WriteBarrier {
+        WriteBarrier::default()
+    }
+
+    /// Note that a write on the given handle was dispatched.
+    pub fn begin(&self, fh: u64) {
+        *self.in_flight.lock().unwrap().entry(fh).or_insert(0) += 1;
+    }
+
+    /// Note that a write on the given handle finished.
+    pub fn end(&self, fh: u64) {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        if let Some(count) = in_flight.get_mut(&fh) {
+            *count -= 1;
+            if *count == 0 {
+                in_flight.remove(&fh);
+                self.done.notify_all();
+            }
+        }
+    }
+
+    /// Wait until there are no writes in progress on the given handle.
+    pub fn wait(&self, fh: u64) {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        while in_flight.contains_key(&fh) {
+            debug!("waiting for writes on fh {} to finish", fh);
+            in_flight = self.done.wait(in_flight).unwrap();
+        }
+    }
+}
+
+#[test]
+fn test_write_barrier() {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    let barrier = Arc::new(WriteBarrier::new());
+    let written = Arc::new(AtomicBool::new(false));
+    barrier.begin(1);
+    barrier.begin(1);
+    barrier.end(1);
+
+    let thread = {
+        let barrier = barrier.clone();
+        let written = written.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            written.store(true, Ordering::SeqCst);
+            barrier.end(1);
+        })
+    };
+
+    barrier.wait(2);
+    barrier.wait(1);
+    assert!(written.load(Ordering::SeqCst));
+    thread.join().unwrap();
+}
@@ -0,0 +1,91 @@
+// XattrCache :: short-lived cache of extended attributes fetched for size probes.
+//
+// Copyright (c) 2026 by William R. Fraser
+//
+
+use std::collections::VecDeque;
+use std::ffi::OsString;
+use std::time::{Duration, Instant};
+
+/// Largest extended attribute value, or list of names, Linux allows.
+pub const XATTR_SIZE_MAX: u32 = 64 * 1024;
+
+/// How long a value is kept for the call that follows its probe.
+const TTL: Duration = Duration::from_secs(1);
+
+/// Most values to keep at once.
+const MAX_ENTRIES: usize = 64;
+
+/// Identifies the value a size probe was for: an attribute (or the list of them, if `name` is
+/// `None`) of an inode, as asked for by a process.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct XattrKey {
+    pub ino: u64,
+    pub name: Option<OsString>,
+    pub pid: u32,
+}
+
+/// To find out how big a buffer to use, programs reading extended attributes call `getxattr` or
+/// `listxattr` once with a size of zero, and then again for real. Filesystems usually have to
+/// compute the whole value to answer the first call, so this keeps what they computed around
+/// briefly to answer the second one.
+#[derive(Debug, Default)]
+pub struct XattrCache {
+    entries: VecDeque<(XattrKey, Instant, Vec<u8>)>,
+}
+
+impl XattrCache {
+    pub fn new() -> XattrCache {
+        XattrCache::default()
+    }
+
+    /// Keep a value fetched for a size probe.
+    pub fn insert(&mut self, key: XattrKey, data: Vec<u8>, now: Instant) {
+        self.expire(now);
+        if self.entries.len() >= MAX_ENTRIES {
+            self.entries.pop_front();
+        }
+        self.entries.push_back((key, now, data));
+    }
+
+    /// Take the value kept for the given probe, if it's still there.
+    pub fn take(&mut self, key: &XattrKey, now: Instant) -> Option<Vec<u8>> {
+        self.expire(now);
+        let idx = self.entries.iter().position(|(k, _, _)| k == key)?;
+        self.entries.remove(idx).map(|(_, _, data)| data)
+    }
+
+    /// Drop any values kept for the given inode, because its attributes changed.
+    pub fn invalidate(&mut self, ino: u64) {
+        self.entries.retain(|(key, _, _)| key.ino != ino);
+    }
+
+    fn expire(&mut self, now: Instant) {
+        while self.entries.front().is_some_and(|(_, time, _)| now.duration_since(*time) > TTL) {
+            self.entries.pop_front();
+        }
+    }
+}
+
+#[test]
+fn test_xattr_cache() {
+    let key = |ino, name: Option<&str>| XattrKey { ino, name: name.map(OsString::from), pid: 7 };
+    let mut cache = XattrCache::new();
+    let now = Instant::now();
+    cache.insert(key(2, Some("user.a")), b"a".to_vec(), now);
+    cache.insert(key(2, None), b"user.a\0".to_vec(), now);
+    cache.insert(key(3, Some("user.a")), b"other".to_vec(), now);
+    cache.insert(key(4, None), b"user.b\0".to_vec(), now);
+
+    // Values are only used once.
+    assert_eq!(Some(b"a".to_vec()), cache.take(&key(2, Some("user.a")), now));
+    assert_eq!(None, cache.take(&key(2, Some("user.a")), now));
+    assert_eq!(None, cache.take(&XattrKey { pid: 8, ..key(3, Some("user.a")) }, now));
+
+    cache.invalidate(2);
+    assert_eq!(None, cache.take(&key(2, None), now));
+    assert_eq!(Some(b"other".to_vec()), cache.take(&key(3, Some("user.a")), now));
+
+    // Values are only kept briefly.
+    assert_eq!(None, cache.take(&key(4, None), now + TTL * 2));
+}